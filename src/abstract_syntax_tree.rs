@@ -1,15 +1,22 @@
-use crate::parser::Parse;
-use crate::tokens::{Token, TokenType};
+use crate::operator::compound_name;
+use crate::tokens::{Span, Token, TokenType};
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub items: Vec<Initialization>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Expr(Expr),
+    Function(Function),
     Struct(Struct),
     Enum(Enum),
     Typeclass(Typeclass),
-    Type(TypeAnnotation),
+    Type(Alias),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Domain {
     Const,
     Var,
@@ -21,76 +28,258 @@ pub enum Domain {
     Type,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Struct(pub Vec<Line>);
-pub struct Enum(pub Vec<EnumEntry>);
-pub struct EnumEntry(Field, Option<Vec<TypeExpr>>);
+/// The variants of an enum and the `type T` parameters their payloads use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Enum(pub Vec<EnumEntry>, pub Vec<TypeParam>);
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnumEntry(pub Field, pub Option<Vec<TypeExpr>>, pub Span);
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Typeclass(pub Vec<Line>);
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Name(pub String);
 
-pub struct Signature {
-    args: Vec<Tag<Name, TypeExpr>>,
-    return_type: TypeAnnotation,
+/// The value of a `fn` or `proc`: one clause with its parameter patterns.
+///
+/// A `fn` body is a single expression, a `proc` body is a block of lines.
+/// Declarations such as `fn new = (type T)` have no body at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub type_params: Vec<TypeParam>,
+    pub params: Vec<Param>,
+    pub guard: Option<Box<Expr>>,
+    pub return_type: TypeAnnotation,
+    pub body: Option<Body>,
+    pub span: Span,
+}
+
+/// A `type T` parameter of a generic `fn` or enum, with the typeclass
+/// bound written after a colon, as in `type T: @ord`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeParam {
+    pub name: Name,
+    pub bound: TypeAnnotation,
+    pub span: Span,
+}
+
+/// The right side of a `type` declaration: `type Pair<T> = (T, T)` is a
+/// generic alias, and `type Meters = distinct float` is a new type that
+/// does not mix with `float`. A bodiless `type T` has no target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alias {
+    pub params: Vec<TypeParam>,
+    pub target: TypeAnnotation,
+    pub distinct: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub pattern: Pattern,
+    pub type_annotation: TypeAnnotation,
+    pub span: Span,
 }
 
-pub struct Tag<T: Parse, U: Parse>(pub T, pub Option<U>);
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Binding(Name),
+    Literal(Literal),
+    Tuple(Vec<Pattern>),
+    Constructor(Name, Vec<Pattern>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Body {
+    Expr(Box<Expr>),
+    Block(Vec<Line>),
+}
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag<T, U>(pub T, pub Option<U>);
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
     Null,
     Void,
     Int(i64),
     Float(f64),
     Bool(bool),
+    String(String),
     List(Vec<Expr>),
     Set(Vec<Expr>),
     Map(Vec<(Expr, Expr)>),
     Tuple(Vec<Expr>),
-    StructInitialization(Name, Vec<Expr>),
+    /// `Name { field: value, ..base }`, with its fields in the order they
+    /// are written. The shorthand `{ len }` is parsed as `{ len: len }`.
+    StructInitialization(Name, Vec<FieldInit>, Option<Box<Expr>>),
     Closure(Box<Value>),
 }
 
+/// Binary operators are parsed into calls named after the operator, so
+/// `a + b` becomes `Call(Name("+"), [a, b])`.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
-    Sequence(Vec<Expr>),
-    Call(Name, Vec<Expr>),
-    Literal(Literal),
-    Reference(Name),
-    FieldAccess(Box<Expr>, Field),
-    Grouping(TokenType, Box<Expr>),
+    Sequence(Vec<Expr>, Span),
+    Call(Name, Vec<Expr>, Span),
+    Literal(Literal, Span),
+    Reference(Name, Span),
+    FieldAccess(Box<Expr>, Field, Span),
+    /// `(expr)` keeps its parentheses, and `recv.name(args)` is kept as
+    /// `Grouping(Dot, Call(name, [recv, args..]))`. `impure expr` is
+    /// `Grouping(KwImpure, expr)`: it lets a `fn` call a `proc`, say to
+    /// print while debugging. `@tail call` is `Grouping(KwTail, call)`,
+    /// which asks for the call to be checked to be in tail position.
+    Grouping(TokenType, Box<Expr>, Span),
+    /// `match value { pattern -> result }`, whose arms are tried in order.
+    Match(Box<Expr>, Vec<Arm>, Span),
+}
+
+/// One arm of a `match`: a pattern, an optional `where` guard that must hold
+/// for the arm to be taken, and the arm's result.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+    pub span: Span,
 }
 
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Sequence(_, s)
+            | Expr::Call(_, _, s)
+            | Expr::Literal(_, s)
+            | Expr::Reference(_, s)
+            | Expr::FieldAccess(_, _, s)
+            | Expr::Grouping(_, _, s)
+            | Expr::Match(_, _, s) => *s,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TypeExpr {
     Operator(Token, Vec<TypeExpr>),
     Literal(Name),
     Grouping,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Field {
     pub field_name: String,
 }
+
+/// A `field: value` of a struct literal, spanning the field's name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldInit {
+    pub name: Name,
+    pub value: Expr,
+    pub span: Span,
+}
+#[derive(Clone, Debug, PartialEq)]
 pub struct TypeAnnotation(pub Option<TypeExpr>);
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Initialization {
+    pub memo: Option<Memo>,
     pub domain: Domain,
     pub name: Name,
     pub type_annotation: TypeAnnotation,
-    pub value: Value,
+    pub value: Option<Value>,
+    pub span: Span,
 }
 
+/// The `@memo` or `@memo(capacity)` written before a `fn`, which caches
+/// its results by argument values. `capacity` is how many it keeps when
+/// it is given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Memo {
+    pub capacity: Option<i64>,
+    pub span: Span,
+}
+
+impl Memo {
+    pub const DEFAULT_CAPACITY: u32 = 1024;
+
+    /// How many results the cache keeps. Only checked capacities, which
+    /// are positive and fit a `u32`, are ever asked for.
+    pub fn capacity(&self) -> u32 {
+        self.capacity.map_or(Self::DEFAULT_CAPACITY, |c| c as u32)
+    }
+}
+
+impl Initialization {
+    /// A bodiless `type T`, which declares a type parameter rather than an
+    /// alias. Its bound, as in `type T: @ord`, is its type annotation.
+    pub fn is_type_param(&self) -> bool {
+        self.domain == Domain::Type
+            && matches!(
+                &self.value,
+                None | Some(Value::Type(Alias {
+                    target: TypeAnnotation(None),
+                    ..
+                }))
+            )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Line {
     Initialization(Initialization),
     Statement(Statement),
+    /// `receiver.name(args)` run for its effect. The expression is always
+    /// a method call, a `Grouping` of `Dot` around a `Call`.
+    MethodCall(Expr),
     Return(Expr),
     For(ForStatement),
     While(Conditional),
     If(Conditional),
-    Break,
-    Continue,
+    Assignment(Assignment),
+    Break(Span),
+    Continue(Span),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conditional(pub Expr, pub Vec<Line>, pub Option<Vec<Line>>, pub Span);
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForStatement(pub Vec<Name>, pub Expr, pub Vec<Line>, pub Span);
+
+/// `target = value`, or a compound assignment such as `self.len += 1`
+/// when `operator` is `PlusEq`, `MinusEq` and so on. The target is a
+/// `Reference` or a chain of `FieldAccess`es ending in one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub target: Expr,
+    pub operator: TokenType,
+    pub value: Expr,
+    pub span: Span,
 }
 
-pub struct Conditional(pub Expr, pub Vec<Line>, pub Option<Vec<Line>>);
-pub struct ForStatement(pub Vec<Name>, pub Expr, pub Vec<Line>);
+impl Assignment {
+    /// The binary operator a compound assignment applies, as in `+` for `+=`.
+    pub fn binary_operator(&self) -> Option<&'static str> {
+        compound_name(&self.operator)
+    }
+
+    /// The variable the target writes to, with the span of its reference.
+    pub fn binding(&self) -> Option<(&Name, Span)> {
+        let mut target = &self.target;
+        loop {
+            match target {
+                Expr::Reference(name, span) => return Some((name, *span)),
+                Expr::FieldAccess(receiver, _, _) => target = receiver,
+                _ => return None,
+            }
+        }
+    }
+}
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Statement {
-    proc_name: Name,
-    args: Vec<Expr>,
+    pub proc_name: Name,
+    pub args: Vec<Expr>,
+    pub span: Span,
 }
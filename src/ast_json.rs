@@ -0,0 +1,699 @@
+use crate::abstract_syntax_tree::{
+    Alias, Arm, Assignment, Body, Conditional, Domain, Enum, EnumEntry, Expr, Field, FieldInit,
+    ForStatement, Function, Initialization, Line, Literal, Memo, Module, Name, Param, Pattern,
+    Statement, Struct, TypeAnnotation, TypeExpr, TypeParam, Typeclass, Value,
+};
+use crate::json::Json;
+use crate::tokens::{Position, Span, Token, TokenType};
+
+/// Value of the `format` field of every AST dump.
+pub const AST_FORMAT: &str = "chop-ast";
+/// Bumped whenever the JSON shape of a node changes.
+pub const AST_FORMAT_VERSION: i64 = 9;
+
+/// Copy of a dump with every `span` field removed, for comparing trees by structure only.
+pub fn without_spans(json: &Json) -> Json {
+    match json {
+        Json::Array(items) => Json::Array(items.iter().map(without_spans).collect()),
+        Json::Object(fields) => Json::Object(
+            fields
+                .iter()
+                .filter(|(k, _)| k != "span")
+                .map(|(k, v)| (k.clone(), without_spans(v)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+pub trait FromJson {
+    fn from_json(json: &Json) -> Result<Self, String>
+    where
+        Self: Sized;
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        json.as_array()?.iter().map(T::from_json).collect()
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(t) => t.to_json(),
+            None => Json::Null,
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Null => Ok(None),
+            j => Ok(Some(T::from_json(j)?)),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Box<T> {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Box::new(T::from_json(json)?))
+    }
+}
+
+fn field<T: FromJson>(json: &Json, key: &str) -> Result<T, String> {
+    T::from_json(json.get(key)?).map_err(|e| format!("{}: {}", key, e))
+}
+
+fn unknown(what: &str, kind: &str) -> String {
+    format!("unknown {} kind '{}'", what, kind)
+}
+
+impl ToJson for Span {
+    fn to_json(&self) -> Json {
+        Json::Array(vec![
+            Json::Int(self.start.0 as i64),
+            Json::Int(self.start.1 as i64),
+            Json::Int(self.end.0 as i64),
+            Json::Int(self.end.1 as i64),
+        ])
+    }
+}
+
+impl FromJson for Span {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let parts = json.as_array()?;
+        if parts.len() != 4 {
+            return Err("a span has exactly four numbers".to_string());
+        }
+        let line = |i: usize| -> Result<u32, String> {
+            u32::try_from(parts[i].as_i64()?).map_err(|_| "span line out of range".to_string())
+        };
+        let col = |i: usize| -> Result<u16, String> {
+            u16::try_from(parts[i].as_i64()?).map_err(|_| "span column out of range".to_string())
+        };
+        Ok(Span::new(
+            Position(line(0)?, col(1)?),
+            Position(line(2)?, col(3)?),
+        ))
+    }
+}
+
+impl ToJson for Name {
+    fn to_json(&self) -> Json {
+        Json::String(self.0.clone())
+    }
+}
+
+impl FromJson for Name {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Name(json.as_str()?.to_string()))
+    }
+}
+
+impl ToJson for Module {
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("format".to_string(), Json::String(AST_FORMAT.to_string())),
+            ("version".to_string(), Json::Int(AST_FORMAT_VERSION)),
+            ("items".to_string(), self.items.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Module {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        if json.get("format")?.as_str()? != AST_FORMAT {
+            return Err(format!("not a {} document", AST_FORMAT));
+        }
+        let version = json.get("version")?.as_i64()?;
+        if version != AST_FORMAT_VERSION {
+            return Err(format!(
+                "unsupported AST format version {} (expected {})",
+                version, AST_FORMAT_VERSION
+            ));
+        }
+        Ok(Module {
+            items: field(json, "items")?,
+        })
+    }
+}
+
+impl ToJson for Domain {
+    fn to_json(&self) -> Json {
+        let keyword = match self {
+            Domain::Const => TokenType::KwConst,
+            Domain::Var => TokenType::KwVar,
+            Domain::Proc => TokenType::KwProc,
+            Domain::Fn => TokenType::KwFn,
+            Domain::Struct => TokenType::KwStruct,
+            Domain::Enum => TokenType::KwEnum,
+            Domain::Typeclass => TokenType::KwTypeclass,
+            Domain::Type => TokenType::KwType,
+        };
+        Json::String(keyword.to_string())
+    }
+}
+
+impl FromJson for Domain {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let s = json.as_str()?;
+        s.parse::<TokenType>()
+            .ok()
+            .and_then(|t| Token::new(t, 0, 0, 0).as_domain())
+            .ok_or_else(|| format!("unknown domain '{}'", s))
+    }
+}
+
+impl ToJson for Initialization {
+    fn to_json(&self) -> Json {
+        Json::object("Initialization")
+            .with("span", self.span.to_json())
+            .with("memo", self.memo.to_json())
+            .with("domain", self.domain.to_json())
+            .with("name", self.name.to_json())
+            .with("type", self.type_annotation.to_json())
+            .with("value", self.value.to_json())
+    }
+}
+
+impl FromJson for Initialization {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Initialization {
+            memo: field(json, "memo")?,
+            domain: field(json, "domain")?,
+            name: field(json, "name")?,
+            type_annotation: field(json, "type")?,
+            value: field(json, "value")?,
+            span: field(json, "span")?,
+        })
+    }
+}
+
+impl ToJson for Memo {
+    fn to_json(&self) -> Json {
+        Json::object("Memo")
+            .with("span", self.span.to_json())
+            .with("capacity", self.capacity.map(Json::Int).unwrap_or(Json::Null))
+    }
+}
+
+impl FromJson for Memo {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let capacity = match json.get("capacity")? {
+            Json::Null => None,
+            capacity => Some(capacity.as_i64()?),
+        };
+        Ok(Memo {
+            capacity,
+            span: field(json, "span")?,
+        })
+    }
+}
+
+impl ToJson for TypeAnnotation {
+    fn to_json(&self) -> Json {
+        self.0.to_json()
+    }
+}
+
+impl FromJson for TypeAnnotation {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(TypeAnnotation(Option::from_json(json)?))
+    }
+}
+
+impl ToJson for Value {
+    fn to_json(&self) -> Json {
+        match self {
+            Value::Expr(e) => e.to_json(),
+            Value::Function(f) => f.to_json(),
+            Value::Struct(Struct(lines)) => Json::object("Struct").with("lines", lines.to_json()),
+            Value::Enum(Enum(entries, params)) => Json::object("Enum")
+                .with("entries", entries.to_json())
+                .with("type_params", params.to_json()),
+            Value::Typeclass(Typeclass(lines)) => {
+                Json::object("Typeclass").with("lines", lines.to_json())
+            }
+            Value::Type(alias) => Json::object("Type")
+                .with("params", alias.params.to_json())
+                .with("type", alias.target.to_json())
+                .with("distinct", Json::Bool(alias.distinct)),
+        }
+    }
+}
+
+impl FromJson for Value {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match json.kind()? {
+            "Function" => Value::Function(Function::from_json(json)?),
+            "Struct" => Value::Struct(Struct(field(json, "lines")?)),
+            "Enum" => Value::Enum(Enum(field(json, "entries")?, field(json, "type_params")?)),
+            "Typeclass" => Value::Typeclass(Typeclass(field(json, "lines")?)),
+            "Type" => Value::Type(Alias {
+                params: field(json, "params")?,
+                target: field(json, "type")?,
+                distinct: json.get("distinct")?.as_bool()?,
+            }),
+            _ => Value::Expr(Expr::from_json(json)?),
+        })
+    }
+}
+
+impl ToJson for EnumEntry {
+    fn to_json(&self) -> Json {
+        Json::object("EnumEntry")
+            .with("span", self.2.to_json())
+            .with("name", Json::String(self.0.field_name.clone()))
+            .with("payload", self.1.to_json())
+    }
+}
+
+impl FromJson for EnumEntry {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(EnumEntry(
+            Field {
+                field_name: json.get("name")?.as_str()?.to_string(),
+            },
+            field(json, "payload")?,
+            field(json, "span")?,
+        ))
+    }
+}
+
+impl ToJson for Function {
+    fn to_json(&self) -> Json {
+        Json::object("Function")
+            .with("span", self.span.to_json())
+            .with("type_params", self.type_params.to_json())
+            .with("params", self.params.to_json())
+            .with("guard", self.guard.to_json())
+            .with("return_type", self.return_type.to_json())
+            .with("body", self.body.to_json())
+    }
+}
+
+impl FromJson for Function {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Function {
+            type_params: field(json, "type_params")?,
+            params: field(json, "params")?,
+            guard: field(json, "guard")?,
+            return_type: field(json, "return_type")?,
+            body: field(json, "body")?,
+            span: field(json, "span")?,
+        })
+    }
+}
+
+impl ToJson for TypeParam {
+    fn to_json(&self) -> Json {
+        Json::object("TypeParam")
+            .with("span", self.span.to_json())
+            .with("name", self.name.to_json())
+            .with("bound", self.bound.to_json())
+    }
+}
+
+impl FromJson for TypeParam {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(TypeParam {
+            name: field(json, "name")?,
+            bound: field(json, "bound")?,
+            span: field(json, "span")?,
+        })
+    }
+}
+
+impl ToJson for Param {
+    fn to_json(&self) -> Json {
+        Json::object("Param")
+            .with("span", self.span.to_json())
+            .with("pattern", self.pattern.to_json())
+            .with("type", self.type_annotation.to_json())
+    }
+}
+
+impl FromJson for Param {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Param {
+            pattern: field(json, "pattern")?,
+            type_annotation: field(json, "type")?,
+            span: field(json, "span")?,
+        })
+    }
+}
+
+impl ToJson for Pattern {
+    fn to_json(&self) -> Json {
+        match self {
+            Pattern::Wildcard => Json::object("Wildcard"),
+            Pattern::Binding(n) => Json::object("Binding").with("name", n.to_json()),
+            Pattern::Literal(l) => Json::object("Literal").with("literal", l.to_json()),
+            Pattern::Tuple(p) => Json::object("Tuple").with("elements", p.to_json()),
+            Pattern::Constructor(n, p) => Json::object("Constructor")
+                .with("name", n.to_json())
+                .with("fields", p.to_json()),
+        }
+    }
+}
+
+impl FromJson for Pattern {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match json.kind()? {
+            "Wildcard" => Pattern::Wildcard,
+            "Binding" => Pattern::Binding(field(json, "name")?),
+            "Literal" => Pattern::Literal(field(json, "literal")?),
+            "Tuple" => Pattern::Tuple(field(json, "elements")?),
+            "Constructor" => Pattern::Constructor(field(json, "name")?, field(json, "fields")?),
+            kind => return Err(unknown("pattern", kind)),
+        })
+    }
+}
+
+impl ToJson for Body {
+    fn to_json(&self) -> Json {
+        match self {
+            Body::Expr(e) => Json::object("ExprBody").with("expr", e.to_json()),
+            Body::Block(lines) => Json::object("Block").with("lines", lines.to_json()),
+        }
+    }
+}
+
+impl FromJson for Body {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match json.kind()? {
+            "ExprBody" => Body::Expr(field(json, "expr")?),
+            "Block" => Body::Block(field(json, "lines")?),
+            kind => return Err(unknown("body", kind)),
+        })
+    }
+}
+
+impl ToJson for Literal {
+    fn to_json(&self) -> Json {
+        match self {
+            Literal::Null => Json::object("Null"),
+            Literal::Void => Json::object("Void"),
+            Literal::Int(i) => Json::object("Int").with("value", Json::Int(*i)),
+            Literal::Float(f) => Json::object("Float").with("value", Json::Float(*f)),
+            Literal::Bool(b) => Json::object("Bool").with("value", Json::Bool(*b)),
+            Literal::String(s) => Json::object("String").with("value", Json::String(s.clone())),
+            Literal::List(e) => Json::object("List").with("elements", e.to_json()),
+            Literal::Set(e) => Json::object("Set").with("elements", e.to_json()),
+            Literal::Map(entries) => Json::object("Map").with(
+                "entries",
+                Json::Array(
+                    entries
+                        .iter()
+                        .map(|(k, v)| {
+                            Json::object("Entry")
+                                .with("key", k.to_json())
+                                .with("value", v.to_json())
+                        })
+                        .collect(),
+                ),
+            ),
+            Literal::Tuple(e) => Json::object("Tuple").with("elements", e.to_json()),
+            Literal::StructInitialization(n, f, b) => Json::object("StructInitialization")
+                .with("name", n.to_json())
+                .with("fields", f.to_json())
+                .with("base", b.to_json()),
+            Literal::Closure(v) => Json::object("Closure").with("value", v.to_json()),
+        }
+    }
+}
+
+impl FromJson for Literal {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match json.kind()? {
+            "Null" => Literal::Null,
+            "Void" => Literal::Void,
+            "Int" => Literal::Int(json.get("value")?.as_i64()?),
+            "Float" => Literal::Float(json.get("value")?.as_f64()?),
+            "Bool" => Literal::Bool(json.get("value")?.as_bool()?),
+            "String" => Literal::String(json.get("value")?.as_str()?.to_string()),
+            "List" => Literal::List(field(json, "elements")?),
+            "Set" => Literal::Set(field(json, "elements")?),
+            "Map" => Literal::Map(
+                json.get("entries")?
+                    .as_array()?
+                    .iter()
+                    .map(|e| Ok((field(e, "key")?, field(e, "value")?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            "Tuple" => Literal::Tuple(field(json, "elements")?),
+            "StructInitialization" => Literal::StructInitialization(
+                field(json, "name")?,
+                field(json, "fields")?,
+                field(json, "base")?,
+            ),
+            "Closure" => Literal::Closure(field(json, "value")?),
+            kind => return Err(unknown("literal", kind)),
+        })
+    }
+}
+
+impl ToJson for Expr {
+    fn to_json(&self) -> Json {
+        let json = match self {
+            Expr::Sequence(e, _) => Json::object("Sequence").with("elements", e.to_json()),
+            Expr::Call(n, args, _) => Json::object("Call")
+                .with("name", n.to_json())
+                .with("args", args.to_json()),
+            Expr::Literal(l, _) => Json::object("Literal").with("literal", l.to_json()),
+            Expr::Reference(n, _) => Json::object("Reference").with("name", n.to_json()),
+            Expr::FieldAccess(e, f, _) => Json::object("FieldAccess")
+                .with("receiver", e.to_json())
+                .with("field", Json::String(f.field_name.clone())),
+            Expr::Grouping(t, e, _) => Json::object("Grouping")
+                .with("token", Json::String(t.to_string()))
+                .with("expr", e.to_json()),
+            Expr::Match(value, arms, _) => Json::object("Match")
+                .with("value", value.to_json())
+                .with("arms", arms.to_json()),
+        };
+        json.with("span", self.span().to_json())
+    }
+}
+
+impl FromJson for Expr {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let span = field(json, "span")?;
+        Ok(match json.kind()? {
+            "Sequence" => Expr::Sequence(field(json, "elements")?, span),
+            "Call" => Expr::Call(field(json, "name")?, field(json, "args")?, span),
+            "Literal" => Expr::Literal(field(json, "literal")?, span),
+            "Reference" => Expr::Reference(field(json, "name")?, span),
+            "FieldAccess" => Expr::FieldAccess(
+                field(json, "receiver")?,
+                Field {
+                    field_name: json.get("field")?.as_str()?.to_string(),
+                },
+                span,
+            ),
+            "Grouping" => Expr::Grouping(
+                grouping_token(json.get("token")?.as_str()?)?,
+                field(json, "expr")?,
+                span,
+            ),
+            "Match" => Expr::Match(field(json, "value")?, field(json, "arms")?, span),
+            kind => return Err(unknown("expression", kind)),
+        })
+    }
+}
+
+impl ToJson for FieldInit {
+    fn to_json(&self) -> Json {
+        Json::object("FieldInit")
+            .with("span", self.span.to_json())
+            .with("name", self.name.to_json())
+            .with("value", self.value.to_json())
+    }
+}
+
+impl FromJson for FieldInit {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(FieldInit {
+            name: field(json, "name")?,
+            value: field(json, "value")?,
+            span: field(json, "span")?,
+        })
+    }
+}
+
+impl ToJson for Arm {
+    fn to_json(&self) -> Json {
+        Json::object("Arm")
+            .with("span", self.span.to_json())
+            .with("pattern", self.pattern.to_json())
+            .with("guard", self.guard.to_json())
+            .with("body", self.body.to_json())
+    }
+}
+
+impl FromJson for Arm {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Arm {
+            pattern: field(json, "pattern")?,
+            guard: field(json, "guard")?,
+            body: field(json, "body")?,
+            span: field(json, "span")?,
+        })
+    }
+}
+
+fn assignment_token(symbol: &str) -> Result<TokenType, String> {
+    match symbol {
+        "=" => Ok(TokenType::Equals),
+        "+=" => Ok(TokenType::PlusEq),
+        "-=" => Ok(TokenType::MinusEq),
+        "*=" => Ok(TokenType::StarEq),
+        "/=" => Ok(TokenType::SlashEq),
+        "%=" => Ok(TokenType::PercentEq),
+        _ => Err(format!("unknown assignment operator '{}'", symbol)),
+    }
+}
+
+fn grouping_token(symbol: &str) -> Result<TokenType, String> {
+    match symbol {
+        "(" => Ok(TokenType::LParen),
+        "[" => Ok(TokenType::LBracket),
+        "{" => Ok(TokenType::LBrace),
+        "." => Ok(TokenType::Dot),
+        "impure" => Ok(TokenType::KwImpure),
+        "@tail" => Ok(TokenType::KwTail),
+        "<" => Ok(TokenType::LT),
+        "->" => Ok(TokenType::Arrow),
+        _ => Err(format!("unknown operator token '{}'", symbol)),
+    }
+}
+
+impl ToJson for TypeExpr {
+    fn to_json(&self) -> Json {
+        match self {
+            TypeExpr::Operator(token, operands) => Json::object("Operator")
+                .with("operator", Json::String(token.token_type.to_string()))
+                .with("span", token.span().to_json())
+                .with("operands", operands.to_json()),
+            TypeExpr::Literal(n) => Json::object("Literal").with("name", n.to_json()),
+            TypeExpr::Grouping => Json::object("Grouping"),
+        }
+    }
+}
+
+impl FromJson for TypeExpr {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match json.kind()? {
+            "Operator" => {
+                let span: Span = field(json, "span")?;
+                let token = Token {
+                    token_type: grouping_token(json.get("operator")?.as_str()?)?,
+                    position: span.start,
+                    end: span.end,
+                };
+                TypeExpr::Operator(token, field(json, "operands")?)
+            }
+            "Literal" => TypeExpr::Literal(field(json, "name")?),
+            "Grouping" => TypeExpr::Grouping,
+            kind => return Err(unknown("type", kind)),
+        })
+    }
+}
+
+impl ToJson for Line {
+    fn to_json(&self) -> Json {
+        match self {
+            Line::Initialization(i) => i.to_json(),
+            Line::Statement(s) => Json::object("Statement")
+                .with("span", s.span.to_json())
+                .with("proc", s.proc_name.to_json())
+                .with("args", s.args.to_json()),
+            Line::MethodCall(e) => Json::object("MethodCall").with("expr", e.to_json()),
+            Line::Assignment(a) => Json::object("Assignment")
+                .with("span", a.span.to_json())
+                .with("target", a.target.to_json())
+                .with("operator", Json::String(a.operator.to_string()))
+                .with("value", a.value.to_json()),
+            Line::Return(e) => Json::object("Return").with("expr", e.to_json()),
+            Line::For(ForStatement(names, iter, body, span)) => Json::object("For")
+                .with("span", span.to_json())
+                .with("names", names.to_json())
+                .with("iterable", iter.to_json())
+                .with("body", body.to_json()),
+            Line::While(c) => conditional_json("While", c),
+            Line::If(c) => conditional_json("If", c),
+            Line::Break(span) => Json::object("Break").with("span", span.to_json()),
+            Line::Continue(span) => Json::object("Continue").with("span", span.to_json()),
+        }
+    }
+}
+
+fn conditional_json(kind: &str, conditional: &Conditional) -> Json {
+    let Conditional(condition, body, alternative, span) = conditional;
+    Json::object(kind)
+        .with("span", span.to_json())
+        .with("condition", condition.to_json())
+        .with("body", body.to_json())
+        .with("else", alternative.to_json())
+}
+
+impl FromJson for Conditional {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Conditional(
+            field(json, "condition")?,
+            field(json, "body")?,
+            field(json, "else")?,
+            field(json, "span")?,
+        ))
+    }
+}
+
+impl FromJson for Line {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match json.kind()? {
+            "Initialization" => Line::Initialization(Initialization::from_json(json)?),
+            "Statement" => Line::Statement(Statement {
+                proc_name: field(json, "proc")?,
+                args: field(json, "args")?,
+                span: field(json, "span")?,
+            }),
+            "Assignment" => Line::Assignment(Assignment {
+                target: field(json, "target")?,
+                operator: assignment_token(json.get("operator")?.as_str()?)?,
+                value: field(json, "value")?,
+                span: field(json, "span")?,
+            }),
+            "MethodCall" => Line::MethodCall(field(json, "expr")?),
+            "Return" => Line::Return(field(json, "expr")?),
+            "For" => Line::For(ForStatement(
+                field(json, "names")?,
+                field(json, "iterable")?,
+                field(json, "body")?,
+                field(json, "span")?,
+            )),
+            "While" => Line::While(Conditional::from_json(json)?),
+            "If" => Line::If(Conditional::from_json(json)?),
+            "Break" => Line::Break(field(json, "span")?),
+            "Continue" => Line::Continue(field(json, "span")?),
+            kind => return Err(unknown("line", kind)),
+        })
+    }
+}
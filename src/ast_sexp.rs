@@ -0,0 +1,87 @@
+use crate::json::Json;
+
+/// Forms longer than this are broken over several lines.
+const WIDTH: usize = 80;
+
+/// Renders an AST dump as an S-expression.
+///
+/// The S-expression is derived from the JSON dump, so both formats always
+/// describe the same tree: `{"kind": "Call", "name": "f"}` becomes
+/// `(Call :name "f")`, arrays become lists and `null` becomes `nil`.
+pub fn to_sexp(json: &Json) -> String {
+    let mut out = String::new();
+    write_sexp(json, &mut out, 0);
+    out
+}
+
+fn flat(json: &Json) -> String {
+    match json {
+        Json::Null => "nil".to_string(),
+        Json::Bool(true) => "#t".to_string(),
+        Json::Bool(false) => "#f".to_string(),
+        Json::Int(_) | Json::Float(_) | Json::String(_) => json.to_string(),
+        Json::Array(items) => {
+            let items: Vec<String> = items.iter().map(flat).collect();
+            format!("({})", items.join(" "))
+        }
+        Json::Object(fields) => {
+            let mut parts = vec![head(fields).to_string()];
+            for (key, value) in fields {
+                if key != "kind" {
+                    parts.push(format!(":{} {}", key, flat(value)));
+                }
+            }
+            format!("({})", parts.join(" "))
+        }
+    }
+}
+
+fn head(fields: &[(String, Json)]) -> &str {
+    fields
+        .iter()
+        .find_map(|(k, v)| match (k.as_str(), v) {
+            ("kind", Json::String(kind)) => Some(kind.as_str()),
+            _ => None,
+        })
+        .unwrap_or("module")
+}
+
+fn write_sexp(json: &Json, out: &mut String, depth: usize) {
+    let one_line = flat(json);
+    if one_line.len() + depth * 2 <= WIDTH {
+        out.push_str(&one_line);
+        return;
+    }
+
+    let indent = "  ".repeat(depth + 1);
+    match json {
+        Json::Array(items) => {
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    out.push_str(&indent);
+                }
+                write_sexp(item, out, depth + 1);
+            }
+            out.push(')');
+        }
+        Json::Object(fields) => {
+            out.push('(');
+            out.push_str(head(fields));
+            for (key, value) in fields {
+                if key == "kind" {
+                    continue;
+                }
+                out.push('\n');
+                out.push_str(&indent);
+                out.push(':');
+                out.push_str(key);
+                out.push(' ');
+                write_sexp(value, out, depth + 1);
+            }
+            out.push(')');
+        }
+        _ => out.push_str(&one_line),
+    }
+}
@@ -0,0 +1,22 @@
+/// Procedures every program can call without declaring them.
+pub const BUILTIN_PROCS: &[&str] = &["println", "print"];
+
+/// Pure functions every program can call, including the names binary and
+/// prefix operators are parsed into.
+pub const BUILTIN_FNS: &[&str] = &[
+    "not", "negate", "+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">=", "and", "or",
+];
+
+pub const BUILTIN_TYPES: &[&str] = &[
+    "int", "float", "bool", "string", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32",
+    "f64",
+];
+
+pub const BUILTIN_TYPECLASSES: &[&str] = &["@eq", "@ord", "@num", "@real", "@hash", "@show"];
+
+pub const INTEGER_TYPES: &[&str] = &["int", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64"];
+
+pub const FLOAT_TYPES: &[&str] = &["float", "f32", "f64"];
+
+/// Builtin typeclasses whose instances also need an instance of a superclass.
+pub const BUILTIN_SUPERCLASSES: &[(&str, &str)] = &[("@ord", "@eq"), ("@real", "@num")];
@@ -0,0 +1,252 @@
+use std::hash::{Hash, Hasher};
+
+use crate::tokens::Span;
+
+/// A compiled program: every function, global and type it needs to run.
+///
+/// Functions, globals and structs refer to each other by their index in
+/// these tables, and to names and literals by their index in `constants`.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
+    pub globals: Vec<Global>,
+    pub structs: Vec<StructInfo>,
+    /// Methods by the name of the type they are called on.
+    pub methods: Vec<Method>,
+    /// Each enum variant with the enum it belongs to.
+    pub variants: Vec<(String, String)>,
+    /// The function the program starts in.
+    pub main: u32,
+}
+
+/// A literal or name the code refers to by index.
+#[derive(Clone, Debug)]
+pub enum Constant {
+    Null,
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+/// Constants are deduplicated, so `0.0` and `-0.0` must stay apart: floats
+/// compare by their bits.
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constant::Null, Constant::Null) | (Constant::Unit, Constant::Unit) => true,
+            (Constant::Int(a), Constant::Int(b)) => a == b,
+            (Constant::Float(a), Constant::Float(b)) => a.to_bits() == b.to_bits(),
+            (Constant::Bool(a), Constant::Bool(b)) => a == b,
+            (Constant::String(a), Constant::String(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Constant {}
+
+impl Hash for Constant {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Constant::Int(i) => i.hash(state),
+            Constant::Float(x) => x.to_bits().hash(state),
+            Constant::Bool(b) => b.hash(state),
+            Constant::String(s) => s.hash(state),
+            Constant::Null | Constant::Unit => {}
+        }
+    }
+}
+
+/// A `fn`, `proc` or closure with all of its clauses.
+///
+/// The arguments arrive in the first `arity` slots; the other slots hold the
+/// function's locals. `spans` has the source of each instruction, for
+/// runtime errors.
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: u8,
+    pub slots: u16,
+    /// Where a closure's captured variables come from when it is created.
+    pub captures: Vec<Capture>,
+    pub code: Vec<Op>,
+    pub spans: Vec<Span>,
+    pub patterns: Vec<Pattern>,
+    /// The cache of a `@memo` fn.
+    pub memo: Option<Memo>,
+}
+
+/// How many results a `@memo` fn caches, and where its `@memo` is written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Memo {
+    pub capacity: u32,
+    pub span: Span,
+}
+
+/// A module or struct level `const` or `var`, computed by calling `init`
+/// the first time it is used.
+#[derive(Debug)]
+pub struct Global {
+    pub name: String,
+    pub init: u32,
+}
+
+#[derive(Debug)]
+pub struct StructInfo {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Method {
+    pub owner: String,
+    pub name: String,
+    pub function: u32,
+}
+
+/// A variable a closure captures: a boxed slot of the function creating it,
+/// a slot whose value it copies into a box of its own, or one of that
+/// function's own captures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capture {
+    Local(u16),
+    Value(u16),
+    Enclosing(u16),
+}
+
+/// A pattern a value is matched against. Bindings store into a slot, and
+/// `Cell` bindings box the value first because a closure captures it.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Wildcard,
+    Bind(u16),
+    Cell(u16),
+    Constant(u32),
+    Tuple(Vec<Pattern>),
+    Variant(u32, Vec<Pattern>),
+}
+
+/// One instruction of the stack machine.
+///
+/// Operands are indexes into the tables of the `Program` or the current
+/// `Function`, slot numbers, argument counts and jump targets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Constant(u32),
+    Pop,
+    Dup,
+    /// Reads or writes a slot that holds its value directly.
+    Local(u16),
+    SetLocal(u16),
+    /// Reads or writes through the box in a slot.
+    Cell(u16),
+    SetCell(u16),
+    /// Puts the value on top of the stack into a new box in a slot.
+    NewCell(u16),
+    /// Reads or writes a variable the running closure captured.
+    Capture(u16),
+    SetCapture(u16),
+    Global(u32),
+    SetGlobal(u32),
+    /// A function without captures, as a value.
+    Function(u32),
+    /// A new closure over the variables the function's `captures` name.
+    Closure(u32),
+    /// A builtin named by a constant, as a value.
+    Builtin(u32),
+    /// A struct or enum named by a constant, as a value.
+    Type(u32),
+    /// An enum variant named by a constant, holding that many values.
+    Variant(u32, u8),
+    /// Calls the value below that many arguments.
+    Call(u8),
+    CallFunction(u32, u8),
+    CallBuiltin(u32, u8),
+    /// Calls the method named by a constant on the receiver below the other
+    /// arguments; the count includes the receiver.
+    CallMethod(u32, u8),
+    Return,
+    Jump(u32),
+    JumpIfFalse(u32),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Negate,
+    Not,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    List(u32),
+    Set(u32),
+    /// A map of that many key and value pairs.
+    Map(u32),
+    Tuple(u32),
+    /// An instance of a struct from that many field values, in order.
+    Struct(u32, u32),
+    /// Reads or writes the field named by a constant. `SetField` expects the
+    /// receiver below the value.
+    Field(u32),
+    SetField(u32),
+    /// Turns the iterable on the stack into an iterator producing one value
+    /// per loop variable, or a tuple of them when there are several.
+    Iterate(u8),
+    /// Pushes the next value of the iterator in a slot, or jumps when it is
+    /// exhausted.
+    Next(u16, u32),
+    /// Replaces a tuple with its elements.
+    Unpack(u8),
+    /// Matches the value on the stack against a pattern of the function,
+    /// jumping when it does not match.
+    Match(u32, u32),
+    /// Stops the program because no clause accepts the arguments.
+    NoClause,
+    /// Stops the program because no arm of a `match` accepts the value in a
+    /// slot.
+    NoArm(u16),
+    /// Stops the program with the message in a constant.
+    Fail(u32),
+}
+
+impl Program {
+    pub fn string(&self, index: u32) -> &str {
+        match &self.constants[index as usize] {
+            Constant::String(s) => s,
+            other => panic!("constant {} is {:?}, not a name", index, other),
+        }
+    }
+}
+
+impl Function {
+    /// Whether the function returns the value on top of the stack as soon
+    /// as it reaches `ip`, so that a call just before `ip` is a tail call.
+    /// The value may go through jumps, and through a slot that it is stored
+    /// in and read back from, as optimized code passes on what a `match`
+    /// evaluates to.
+    pub fn returns_at(&self, ip: usize) -> bool {
+        let mut ip = ip;
+        let mut held = None;
+        for _ in 0..self.code.len() {
+            match (self.code.get(ip), held) {
+                (Some(Op::Return), None) => return true,
+                (Some(Op::Jump(target)), _) => {
+                    ip = *target as usize;
+                    continue;
+                }
+                (Some(Op::SetLocal(slot)), None) => held = Some(*slot),
+                (Some(Op::Local(slot)), Some(held_in)) if *slot == held_in => held = None,
+                _ => return false,
+            }
+            ip += 1;
+        }
+        false
+    }
+}
@@ -0,0 +1,1109 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::bytecode::{Capture, Constant, Function, Op, Pattern, Program};
+use crate::interpreter::MAX_CALL_DEPTH;
+
+/// Values, operators, collections and error reporting, shared by every
+/// generated program.
+const RUNTIME: &str = include_str!("c_runtime.c");
+
+/// Lowers a compiled program to a single C99 translation unit that runs it
+/// like the virtual machine does.
+///
+/// Each function becomes a C function whose locals are an array of slots.
+/// The depth of the operand stack is known at every instruction, so the
+/// stack is an array indexed by constants, and jumps become `goto`s. Each
+/// struct becomes a C struct with a member for each field, and each enum a
+/// C enum of its variants' tags and a tagged union of their items. `#line`
+/// directives point C compiler errors and debuggers at `path`. The source
+/// is embedded so that runtime errors can quote it.
+pub fn generate(program: &Program, path: &str, source: &str) -> String {
+    let mut generator = Generator {
+        program,
+        path,
+        out: String::new(),
+        strings: HashMap::new(),
+        tags: HashMap::new(),
+        structs: Vec::new(),
+        variants: HashMap::new(),
+        accessors: HashMap::new(),
+    };
+    generator.generate(source);
+    generator.out
+}
+
+struct Generator<'p> {
+    program: &'p Program,
+    path: &'p str,
+    out: String,
+    /// The constant index of each string, which is also the tag of the
+    /// variant with that name.
+    strings: HashMap<&'p str, u32>,
+    /// The C enumerator naming each variant tag.
+    tags: HashMap<u32, String>,
+    /// The C struct generated for each struct type.
+    structs: Vec<String>,
+    /// The tagged union holding each variant tag, if it has one.
+    variants: HashMap<u32, Variant>,
+    /// The function finding each field name in the structs that have it.
+    accessors: HashMap<&'p str, String>,
+}
+
+/// Where a variant's items live: a member of its enum's tagged union.
+struct Variant {
+    union: String,
+    member: String,
+    count: usize,
+    /// The C function making one from its items.
+    constructor: String,
+}
+
+impl<'p> Generator<'p> {
+    fn generate(&mut self, source: &str) {
+        let program = self.program;
+        let _ = writeln!(
+            self.out,
+            "/* Generated by `chop build` from {}. */",
+            self.path
+        );
+        let _ = writeln!(self.out, "#define CHOP_MAX_DEPTH {}", MAX_CALL_DEPTH);
+        self.out.push_str("#line 1 \"c_runtime.c\"\n");
+        self.out.push_str(RUNTIME);
+        self.out.push('\n');
+
+        for (index, constant) in program.constants.iter().enumerate() {
+            if let Constant::String(s) = constant {
+                self.strings.entry(s).or_insert(index as u32);
+                let _ = writeln!(
+                    self.out,
+                    "static const chop_string str{} = {{{}, {}}};",
+                    index,
+                    s.len(),
+                    c_string(s)
+                );
+            }
+        }
+        self.enums();
+        self.layouts();
+
+        for (index, function) in program.functions.iter().enumerate() {
+            let _ = writeln!(
+                self.out,
+                "static chop_value {}(chop_closure *self, chop_value *args);",
+                code_name(index, function)
+            );
+        }
+        for (index, function) in program.functions.iter().enumerate() {
+            let _ = writeln!(
+                self.out,
+                "static const chop_function fn{} = {{{}, {}, {}}};",
+                index,
+                c_string(&function.name),
+                function.arity,
+                code_name(index, function)
+            );
+            if function.captures.is_empty() {
+                let _ = writeln!(
+                    self.out,
+                    "static chop_closure closure{} = {{&fn{}, NULL}};",
+                    index, index
+                );
+            }
+            if let Some(memo) = function.memo {
+                let _ = writeln!(
+                    self.out,
+                    "static chop_memo memo{} = {{{}, {}, NULL}};",
+                    index, memo.capacity, function.arity
+                );
+            }
+        }
+        self.tables(source);
+        self.constructors();
+
+        for (index, function) in program.functions.iter().enumerate() {
+            self.function(index, function);
+        }
+    }
+
+    /// A C enum for each chop enum, naming the tags of its variants.
+    fn enums(&mut self) {
+        let mut enums: Vec<(&str, Vec<(&str, u32)>)> = Vec::new();
+        for (variant, e) in &self.program.variants {
+            let Some(&tag) = self.strings.get(variant.as_str()) else {
+                continue;
+            };
+            match enums.iter_mut().find(|(name, _)| name == e) {
+                Some((_, variants)) => variants.push((variant, tag)),
+                None => enums.push((e, vec![(variant, tag)])),
+            }
+        }
+        for (e, variants) in enums {
+            let _ = writeln!(self.out, "enum chop_enum_{} {{", ident(e));
+            for (i, (variant, tag)) in variants.iter().enumerate() {
+                let enumerator = format!("chop_{}_{}", ident(e), ident(variant));
+                let comma = if i + 1 < variants.len() { "," } else { "" };
+                let _ = writeln!(self.out, "    {} = {}{}", enumerator, tag, comma);
+                self.tags.insert(*tag, enumerator);
+            }
+            self.out.push_str("};\n");
+        }
+
+        // A variant in several enums belongs to the last, as in the VM.
+        let mut owners = BTreeMap::new();
+        for (variant, e) in &self.program.variants {
+            if let Some(&tag) = self.strings.get(variant.as_str()) {
+                owners.insert(tag, e);
+            }
+        }
+        self.out
+            .push_str("static const char *chop_enum_of(int tag) {\n    switch (tag) {\n");
+        for (tag, e) in owners {
+            let _ = writeln!(self.out, "    case {}: return {};", tag, c_string(e));
+        }
+        self.out.push_str("    default: return NULL;\n    }\n}\n");
+    }
+
+    /// A C struct for each struct type and a tagged union for each enum,
+    /// with the functions that find their fields and items by index.
+    ///
+    /// Fields and items are `chop_value`s because a value's type does not
+    /// fix its representation: `null` belongs to every type, and a `float`
+    /// may hold an integer literal.
+    fn layouts(&mut self) {
+        let program = self.program;
+        for (index, info) in program.structs.iter().enumerate() {
+            let name = format!("struct{}_{}", index, ident(&info.name));
+            let _ = writeln!(
+                self.out,
+                "/* struct {} */\ntypedef struct {{\n    struct chop_instance header;",
+                info.name
+            );
+            for field in &info.fields {
+                let _ = writeln!(self.out, "    chop_value f_{};", ident(field));
+            }
+            let _ = writeln!(
+                self.out,
+                "}} {};\nstatic chop_value *{}_field(void *self, size_t index) {{\n    {} *value = self;\n    switch (index) {{",
+                name, name, name
+            );
+            for (i, field) in info.fields.iter().enumerate() {
+                let _ = writeln!(
+                    self.out,
+                    "    case {}: return &value->f_{};",
+                    i,
+                    ident(field)
+                );
+            }
+            self.out
+                .push_str("    default: (void)value; return NULL;\n    }\n}\n");
+            self.structs.push(name);
+        }
+
+        // The number of items of each variant, which every use must agree on.
+        let mut counts: HashMap<u32, Option<usize>> = HashMap::new();
+        let mut count = |tag: u32, n: usize| {
+            let entry = counts.entry(tag).or_insert(Some(n));
+            if *entry != Some(n) {
+                *entry = None;
+            }
+        };
+        for function in &program.functions {
+            for op in &function.code {
+                if let Op::Variant(name, n) = *op {
+                    count(self.strings[program.string(name)], n as usize);
+                }
+            }
+            let mut patterns: Vec<&Pattern> = function.patterns.iter().collect();
+            while let Some(pattern) = patterns.pop() {
+                match pattern {
+                    Pattern::Variant(name, items) => {
+                        count(self.strings[program.string(*name)], items.len());
+                        patterns.extend(items);
+                    }
+                    Pattern::Tuple(items) => patterns.extend(items),
+                    _ => {}
+                }
+            }
+        }
+
+        // A variant in several enums belongs to the last, as in the VM.
+        let mut enums: Vec<(&str, Vec<(&str, u32)>)> = Vec::new();
+        let mut owners = HashMap::new();
+        for (variant, e) in &program.variants {
+            if let Some(&tag) = self.strings.get(variant.as_str()) {
+                owners.insert(tag, e.as_str());
+            }
+        }
+        for (variant, e) in &program.variants {
+            let Some(&tag) = self.strings.get(variant.as_str()) else {
+                continue;
+            };
+            if owners[&tag] != e || enums.iter().any(|(_, vs)| vs.iter().any(|v| v.1 == tag)) {
+                continue;
+            }
+            match enums.iter_mut().find(|(name, _)| name == e) {
+                Some((_, variants)) => variants.push((variant, tag)),
+                None => enums.push((e, vec![(variant, tag)])),
+            }
+        }
+        for (index, (e, variants)) in enums.into_iter().enumerate() {
+            let union = format!("enum{}_{}", index, ident(e));
+            let variants: Vec<(&str, u32, usize)> = variants
+                .into_iter()
+                .filter_map(|(variant, tag)| Some((variant, tag, counts.get(&tag).copied()??)))
+                .collect();
+            let _ = writeln!(
+                self.out,
+                "/* enum {} */\ntypedef struct {{\n    struct chop_variant header;",
+                e
+            );
+            if variants.iter().any(|v| v.2 > 0) {
+                self.out.push_str("    union {\n");
+                for &(variant, _, n) in variants.iter().filter(|v| v.2 > 0) {
+                    self.out.push_str("        struct {\n");
+                    for i in 0..n {
+                        let _ = writeln!(self.out, "            chop_value _{};", i);
+                    }
+                    let _ = writeln!(self.out, "        }} v_{};", ident(variant));
+                }
+                self.out.push_str("    } as;\n");
+            }
+            let _ = writeln!(
+                self.out,
+                "}} {};\nstatic chop_value *{}_item(void *self, size_t index) {{\n    {} *value = self;\n    switch (value->header.tag) {{",
+                union, union, union
+            );
+            for &(variant, tag, n) in variants.iter().filter(|v| v.2 > 0) {
+                let member = format!("v_{}", ident(variant));
+                let mut item = format!("&value->as.{}._{}", member, n - 1);
+                for i in (0..n - 1).rev() {
+                    item = format!("index == {} ? &value->as.{}._{} : {}", i, member, i, item);
+                }
+                let _ = writeln!(self.out, "    case {}: return {};", self.tags[&tag], item);
+            }
+            self.out
+                .push_str("    default: (void)index; return NULL;\n    }\n}\n");
+
+            for (variant, tag, n) in variants {
+                let member = format!("v_{}", ident(variant));
+                let constructor = format!("{}_{}", union, ident(variant));
+                let _ = writeln!(
+                    self.out,
+                    "static chop_value {}(const chop_value *items) {{
+    {} *value = chop_alloc(sizeof *value);
+    value->header.tag = {};
+    value->header.name = {};
+    value->header.len = {};
+    value->header.size = sizeof *value;
+    value->header.slot = {}_item;",
+                    constructor,
+                    union,
+                    self.tags[&tag],
+                    c_string(variant),
+                    n,
+                    union
+                );
+                for i in 0..n {
+                    let _ = writeln!(self.out, "    value->as.{}._{} = items[{}];", member, i, i);
+                }
+                if n == 0 {
+                    self.out.push_str("    (void)items;\n");
+                }
+                self.out
+                    .push_str("    return chop_variant_value(&value->header);\n}\n");
+                self.variants.insert(
+                    tag,
+                    Variant {
+                        union: union.clone(),
+                        member,
+                        count: n,
+                        constructor,
+                    },
+                );
+            }
+        }
+    }
+
+    /// A constructor for each struct type, and a function for each field
+    /// name the code reads or writes that finds it in the structs that have
+    /// it. They come after the table of structs their values point into.
+    fn constructors(&mut self) {
+        let program = self.program;
+        for (index, info) in program.structs.iter().enumerate() {
+            let name = &self.structs[index];
+            let _ = writeln!(
+                self.out,
+                "static chop_value {}_init(const chop_value *fields) {{
+    {} *value = chop_alloc(sizeof *value);
+    value->header.type = &structs[{}];",
+                name, name, index
+            );
+            for (i, field) in info.fields.iter().enumerate() {
+                let _ = writeln!(self.out, "    value->f_{} = fields[{}];", ident(field), i);
+            }
+            if info.fields.is_empty() {
+                self.out.push_str("    (void)fields;\n");
+            }
+            self.out
+                .push_str("    return chop_instance_value(&value->header);\n}\n");
+        }
+
+        let mut names: Vec<&str> = program
+            .functions
+            .iter()
+            .flat_map(|function| &function.code)
+            .filter_map(|op| match *op {
+                Op::Field(n) | Op::SetField(n) => Some(program.string(n)),
+                _ => None,
+            })
+            .filter(|name| {
+                program
+                    .structs
+                    .iter()
+                    .any(|s| s.fields.iter().any(|f| f == name))
+            })
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            let accessor = format!("field_{}", ident(name));
+            let _ = writeln!(
+                self.out,
+                "static chop_value *{}(chop_value receiver) {{
+    if (receiver.tag != CHOP_STRUCT) {{
+        return NULL;
+    }}
+    switch (receiver.as.instance->type - structs) {{",
+                accessor
+            );
+            for (index, info) in program.structs.iter().enumerate() {
+                if info.fields.iter().any(|f| f == name) {
+                    let _ = writeln!(
+                        self.out,
+                        "    case {}: return &(({} *)receiver.as.instance)->f_{};",
+                        index,
+                        self.structs[index],
+                        ident(name)
+                    );
+                }
+            }
+            self.out.push_str("    default: return NULL;\n    }\n}\n");
+            self.accessors.insert(name, accessor);
+        }
+    }
+
+    /// Structs, methods, globals and the source, then the entry points the
+    /// runtime calls.
+    fn tables(&mut self, source: &str) {
+        let program = self.program;
+        for (index, info) in program.structs.iter().enumerate() {
+            if !info.fields.is_empty() {
+                let fields: Vec<String> = info.fields.iter().map(|f| c_string(f)).collect();
+                let _ = writeln!(
+                    self.out,
+                    "static const char *const fields{}[] = {{{}}};",
+                    index,
+                    fields.join(", ")
+                );
+            }
+        }
+        if !program.structs.is_empty() {
+            self.out
+                .push_str("static const chop_struct structs[] = {\n");
+            for (index, info) in program.structs.iter().enumerate() {
+                let fields = if info.fields.is_empty() {
+                    "NULL".to_string()
+                } else {
+                    format!("fields{}", index)
+                };
+                let _ = writeln!(
+                    self.out,
+                    "    {{{}, {}, {}, sizeof({}), {}_field}},",
+                    c_string(&info.name),
+                    info.fields.len(),
+                    fields,
+                    self.structs[index],
+                    self.structs[index]
+                );
+            }
+            self.out.push_str("};\n");
+        }
+        if !program.methods.is_empty() {
+            self.out
+                .push_str("static const chop_method methods[] = {\n");
+            for method in &program.methods {
+                let _ = writeln!(
+                    self.out,
+                    "    {{{}, {}, &fn{}}},",
+                    c_string(&method.owner),
+                    c_string(&method.name),
+                    method.function
+                );
+            }
+            self.out.push_str("};\n");
+        }
+        if !program.globals.is_empty() {
+            self.out.push_str("static chop_global globals[] = {\n");
+            for global in &program.globals {
+                let _ = writeln!(
+                    self.out,
+                    "    {{{}, &fn{}, CHOP_UNINITIALIZED, {{CHOP_UNIT, {{0}}}}}},",
+                    c_string(&global.name),
+                    global.init
+                );
+            }
+            self.out.push_str("};\n");
+        }
+        self.out.push_str("static const char source[] =");
+        for line in source.split_inclusive('\n') {
+            let _ = write!(self.out, "\n    {}", c_string(line));
+        }
+        self.out.push_str("\n    \"\";\n");
+
+        let table = |present: bool, name: &str| {
+            if present {
+                (
+                    name.to_string(),
+                    format!("sizeof {} / sizeof *{}", name, name),
+                )
+            } else {
+                ("NULL".to_string(), "0".to_string())
+            }
+        };
+        let (structs, struct_count) = table(!program.structs.is_empty(), "structs");
+        let (methods, method_count) = table(!program.methods.is_empty(), "methods");
+        let (globals, _) = table(!program.globals.is_empty(), "globals");
+        let _ = writeln!(
+            self.out,
+            "static void chop_setup(void) {{
+    chop_path = {};
+    chop_source = source;
+    chop_structs = {};
+    chop_struct_count = {};
+    chop_methods = {};
+    chop_method_count = {};
+    chop_globals = {};
+}}
+static chop_value chop_entry(void) {{
+    return chop_finish({}(NULL, NULL));
+}}",
+            c_string(self.path),
+            structs,
+            struct_count,
+            methods,
+            method_count,
+            globals,
+            code_name(
+                program.main as usize,
+                &program.functions[program.main as usize]
+            )
+        );
+    }
+
+    fn function(&mut self, index: usize, function: &Function) {
+        let depths = depths(function);
+        let peak = depths
+            .iter()
+            .zip(&function.code)
+            .filter_map(|(depth, op)| depth.map(|d| d + effect(*op).1))
+            .max()
+            .unwrap_or(0);
+        let targets: HashSet<u32> = function
+            .code
+            .iter()
+            .zip(&depths)
+            .filter(|(_, depth)| depth.is_some())
+            .filter_map(|(op, _)| match *op {
+                Op::Jump(t) | Op::JumpIfFalse(t) | Op::Next(_, t) | Op::Match(_, t) => Some(t),
+                _ => None,
+            })
+            .collect();
+
+        let spans: Vec<String> = function
+            .spans
+            .iter()
+            .map(|s| format!("{{{}, {}, {}, {}}}", s.start.0, s.start.1, s.end.0, s.end.1))
+            .collect();
+        let _ = writeln!(
+            self.out,
+            "static const chop_span at{}[] = {{{}}};",
+            index,
+            spans.join(", ")
+        );
+        // The code of a `@memo` fn runs behind its cache.
+        let name = match function.memo {
+            Some(_) => format!("{}_uncached", code_name(index, function)),
+            None => code_name(index, function),
+        };
+        let _ = writeln!(
+            self.out,
+            "static chop_value {}(chop_closure *self, chop_value *args) {{",
+            name
+        );
+        let _ = writeln!(
+            self.out,
+            "    chop_value l[{}];\n    chop_value s[{}];\n    (void)l;\n    (void)self;\n    (void)args;",
+            function.slots.max(1),
+            peak.max(1)
+        );
+        if function.arity > 0 {
+            let _ = writeln!(
+                self.out,
+                "    memcpy(l, args, {} * sizeof *l);",
+                function.arity
+            );
+        }
+        // A call of the function itself in tail position starts it over.
+        let recursive = function.code.iter().enumerate().any(|(ip, op)| match *op {
+            Op::CallFunction(i, count) => self.self_tail_call(index, function, ip, i, count),
+            _ => false,
+        });
+        if recursive {
+            self.out.push_str("chop_start:;\n");
+        }
+        if function.slots > function.arity as u16 {
+            let _ = writeln!(
+                self.out,
+                "    for (int i = {}; i < {}; i++) {{\n        l[i] = chop_unit;\n    }}",
+                function.arity, function.slots
+            );
+        }
+
+        let mut line = None;
+        for (ip, op) in function.code.iter().enumerate() {
+            let Some(depth) = depths[ip] else {
+                continue;
+            };
+            let at = function.spans[ip].start.0.max(1);
+            if line != Some(at) {
+                line = Some(at);
+                let _ = writeln!(self.out, "#line {} {}", at, c_string(self.path));
+            }
+            if targets.contains(&(ip as u32)) {
+                let _ = writeln!(self.out, "L{}:;", ip);
+            }
+            let statement = self.instruction(index, function, ip, *op, depth);
+            let _ = writeln!(self.out, "    {}", statement);
+        }
+        self.out.push_str("}\n");
+        if function.memo.is_some() {
+            let _ = writeln!(
+                self.out,
+                "static chop_value {}(chop_closure *self, chop_value *args) {{
+    return chop_memoized(&memo{}, {}, self, args);
+}}",
+                code_name(index, function),
+                index,
+                name
+            );
+        }
+    }
+
+    /// Whether the call at `ip` of `function` is one of itself, with the
+    /// right number of arguments, in tail position. A `@memo` fn looks
+    /// every call of itself up in its cache instead.
+    fn self_tail_call(
+        &self,
+        index: usize,
+        function: &Function,
+        ip: usize,
+        callee: u32,
+        count: u8,
+    ) -> bool {
+        callee as usize == index
+            && count == function.arity
+            && function.memo.is_none()
+            && function.returns_at(ip + 1)
+    }
+
+    /// The C statement running one instruction, with `d` values on the
+    /// stack before it. A call in tail position is left for the caller to
+    /// make, or jumps back to the start when it is a call of `function`
+    /// itself.
+    fn instruction(
+        &self,
+        index: usize,
+        function: &Function,
+        ip: usize,
+        op: Op,
+        d: usize,
+    ) -> String {
+        let program = self.program;
+        let at = format!("at{} + {}", index, ip);
+        let top = d.wrapping_sub(1);
+        let args = |n: usize| format!("&s[{}]", d - n);
+        let name = |constant: u32| c_string(program.string(constant));
+        let tail = function.returns_at(ip + 1);
+        match op {
+            Op::Constant(i) => format!("s[{}] = {};", d, self.constant(i)),
+            Op::Pop => ";".to_string(),
+            Op::Dup => format!("s[{}] = s[{}];", d, top),
+            Op::Local(slot) => format!("s[{}] = l[{}];", d, slot),
+            Op::SetLocal(slot) => format!("l[{}] = s[{}];", slot, top),
+            Op::Cell(slot) => format!("s[{}] = *l[{}].as.cell;", d, slot),
+            Op::SetCell(slot) => format!("*l[{}].as.cell = s[{}];", slot, top),
+            Op::NewCell(slot) => format!("l[{}] = chop_new_cell(s[{}]);", slot, top),
+            Op::Capture(i) => format!("s[{}] = *self->captures[{}];", d, i),
+            Op::SetCapture(i) => format!("*self->captures[{}] = s[{}];", i, top),
+            Op::Global(i) => format!("s[{}] = chop_get_global({}, {});", d, i, at),
+            Op::SetGlobal(i) => format!("chop_set_global({}, s[{}]);", i, top),
+            Op::Function(i) => format!("s[{}] = chop_closure_value(&closure{});", d, i),
+            Op::Closure(i) => {
+                let closure = &program.functions[i as usize];
+                let mut code = format!(
+                    "{{\n        chop_value **c = chop_alloc({} * sizeof *c);\n",
+                    closure.captures.len()
+                );
+                for (k, capture) in closure.captures.iter().enumerate() {
+                    let source = match capture {
+                        Capture::Local(slot) => format!("l[{}].as.cell", slot),
+                        Capture::Value(slot) => format!("chop_new_cell(l[{}]).as.cell", slot),
+                        Capture::Enclosing(j) => format!("self->captures[{}]", j),
+                    };
+                    let _ = writeln!(code, "        c[{}] = {};", k, source);
+                }
+                let _ = write!(
+                    code,
+                    "        s[{}] = chop_new_closure(&fn{}, c);\n    }}",
+                    d, i
+                );
+                code
+            }
+            Op::Builtin(n) => format!("s[{}] = chop_named(CHOP_BUILTIN, {});", d, name(n)),
+            Op::Type(n) => format!("s[{}] = chop_named(CHOP_TYPE, {});", d, name(n)),
+            Op::Variant(n, count) => {
+                let count = count as usize;
+                if let Some(variant) = self.variant(n, count) {
+                    return format!(
+                        "s[{}] = {}({});",
+                        d - count,
+                        variant.constructor,
+                        args(count)
+                    );
+                }
+                format!(
+                    "s[{}] = chop_variant({}, {}, {}, {});",
+                    d - count,
+                    self.tag(n),
+                    name(n),
+                    count,
+                    args(count)
+                )
+            }
+            Op::Call(count) => {
+                let count = count as usize;
+                let callee = d - count - 1;
+                format!(
+                    "s[{}] = chop_call(s[{}], {}, {}, {}, {});",
+                    callee,
+                    callee,
+                    count,
+                    args(count),
+                    at,
+                    tail as u8
+                )
+            }
+            Op::CallFunction(i, count) => {
+                let count = count as usize;
+                let callee = &program.functions[i as usize];
+                if count != callee.arity as usize {
+                    return format!(
+                        "chop_no_match(&fn{}, {}, {}, {});",
+                        i,
+                        count,
+                        args(count),
+                        at
+                    );
+                }
+                if self.self_tail_call(index, function, ip, i, count as u8) {
+                    return format!(
+                        "memcpy(l, {}, {} * sizeof *l); chop_replace_call({}); goto chop_start;",
+                        args(count),
+                        count,
+                        at
+                    );
+                }
+                if tail {
+                    return format!(
+                        "return chop_tail(&fn{}, NULL, {}, {}, {});",
+                        i,
+                        count,
+                        args(count),
+                        at
+                    );
+                }
+                format!(
+                    "chop_enter(&fn{}, {}); s[{}] = chop_finish({}(NULL, {})); chop_leave();",
+                    i,
+                    at,
+                    d - count,
+                    code_name(i as usize, callee),
+                    args(count)
+                )
+            }
+            Op::CallBuiltin(n, count) => {
+                let count = count as usize;
+                let call = match program.string(n) {
+                    "print" => format!("chop_print({}, {}, 0)", count, args(count)),
+                    "println" => format!("chop_print({}, {}, 1)", count, args(count)),
+                    _ => format!(
+                        "chop_operator({}, {}, {}, {})",
+                        name(n),
+                        count,
+                        args(count),
+                        at
+                    ),
+                };
+                format!("s[{}] = {};", d - count, call)
+            }
+            Op::CallMethod(n, count) => {
+                let count = count as usize;
+                format!(
+                    "s[{}] = chop_call_method({}, {}, {}, {}, {});",
+                    d - count,
+                    name(n),
+                    count,
+                    args(count),
+                    at,
+                    tail as u8
+                )
+            }
+            Op::Return => format!("return s[{}];", top),
+            Op::Jump(target) => format!("goto L{};", target),
+            Op::JumpIfFalse(target) => {
+                format!("if (!chop_truth(s[{}], {})) goto L{};", top, at, target)
+            }
+            Op::Add | Op::Subtract | Op::Less => {
+                let helper = match op {
+                    Op::Add => "chop_add",
+                    Op::Subtract => "chop_subtract",
+                    _ => "chop_less",
+                };
+                format!(
+                    "s[{}] = {}(s[{}], s[{}], {});",
+                    d - 2,
+                    helper,
+                    d - 2,
+                    top,
+                    at
+                )
+            }
+            Op::Equal => format!("s[{}] = chop_equals(s[{}], s[{}]);", d - 2, d - 2, top),
+            Op::Multiply
+            | Op::Divide
+            | Op::Remainder
+            | Op::NotEqual
+            | Op::LessEqual
+            | Op::Greater
+            | Op::GreaterEqual => format!(
+                "s[{}] = chop_binary(\"{}\", s[{}], s[{}], {});",
+                d - 2,
+                symbol(op),
+                d - 2,
+                top,
+                at
+            ),
+            Op::Negate | Op::Not => format!(
+                "s[{}] = chop_unary(\"{}\", s[{}], {});",
+                top,
+                symbol(op),
+                top,
+                at
+            ),
+            Op::List(count) | Op::Set(count) | Op::Tuple(count) => {
+                let count = count as usize;
+                let helper = match op {
+                    Op::List(_) => "chop_list",
+                    Op::Set(_) => "chop_set",
+                    _ => "chop_tuple",
+                };
+                format!("s[{}] = {}({}, {});", d - count, helper, count, args(count))
+            }
+            Op::Map(count) => {
+                let count = count as usize;
+                format!(
+                    "s[{}] = chop_map({}, {});",
+                    d - 2 * count,
+                    count,
+                    args(2 * count)
+                )
+            }
+            Op::Struct(i, count) => {
+                let count = count as usize;
+                if count == program.structs[i as usize].fields.len() {
+                    return format!(
+                        "s[{}] = {}_init({});",
+                        d - count,
+                        self.structs[i as usize],
+                        args(count)
+                    );
+                }
+                format!(
+                    "s[{}] = chop_struct_value({}, {});",
+                    d - count,
+                    i,
+                    args(count)
+                )
+            }
+            Op::Field(n) => match self.accessors.get(program.string(n)) {
+                Some(accessor) => format!(
+                    "{{ chop_value *f = {}(s[{}]); s[{}] = f ? *f : chop_field(s[{}], {}, {}); }}",
+                    accessor,
+                    top,
+                    top,
+                    top,
+                    name(n),
+                    at
+                ),
+                None => format!("s[{}] = chop_field(s[{}], {}, {});", top, top, name(n), at),
+            },
+            Op::SetField(n) if self.accessors.contains_key(program.string(n)) => format!(
+                "{{ chop_value *f = {}(s[{}]); if (f) *f = s[{}]; else chop_set_field(s[{}], {}, s[{}], {}); }}",
+                self.accessors[program.string(n)],
+                d - 2,
+                top,
+                d - 2,
+                name(n),
+                top,
+                at
+            ),
+            Op::SetField(n) => format!(
+                "chop_set_field(s[{}], {}, s[{}], {});",
+                d - 2,
+                name(n),
+                top,
+                at
+            ),
+            Op::Iterate(count) => {
+                format!("s[{}] = chop_iterate(s[{}], {}, {});", top, top, count, at)
+            }
+            Op::Next(slot, target) => {
+                format!("if (!chop_next(l[{}], &s[{}])) goto L{};", slot, d, target)
+            }
+            Op::Unpack(_) => format!("chop_unpack(s[{}], &s[{}]);", top, top),
+            Op::Match(pattern, target) => format!(
+                "if (!{}) goto L{};",
+                self.pattern(&function.patterns[pattern as usize], &format!("s[{}]", top)),
+                target
+            ),
+            Op::NoClause => format!("chop_no_clause(&fn{}, l, {});", index, at),
+            Op::NoArm(slot) => format!("chop_no_arm(l[{}], {});", slot, at),
+            Op::Fail(message) => format!("chop_error({}, {});", at, name(message)),
+        }
+    }
+
+    fn constant(&self, index: u32) -> String {
+        match &self.program.constants[index as usize] {
+            Constant::Null => "chop_null".to_string(),
+            Constant::Unit => "chop_unit".to_string(),
+            Constant::Int(i64::MIN) => "chop_int(INT64_MIN)".to_string(),
+            Constant::Int(i) => format!("chop_int(INT64_C({}))", i),
+            Constant::Float(x) => format!("chop_float_bits(UINT64_C({:#x}))", x.to_bits()),
+            Constant::Bool(b) => format!("chop_bool({})", *b as u8),
+            Constant::String(_) => format!("chop_string_value(&str{})", index),
+        }
+    }
+
+    /// The tagged union member of the variant whose name is a constant, if
+    /// it has one with `count` items.
+    fn variant(&self, name: u32, count: usize) -> Option<&Variant> {
+        let tag = self.strings[self.program.string(name)];
+        self.variants.get(&tag).filter(|v| v.count == count)
+    }
+
+    /// The tag of the variant whose name is a constant.
+    fn tag(&self, name: u32) -> String {
+        let tag = self.strings[self.program.string(name)];
+        match self.tags.get(&tag) {
+            Some(enumerator) => enumerator.clone(),
+            None => tag.to_string(),
+        }
+    }
+
+    /// A C expression matching `value` against a pattern, storing its
+    /// bindings into the slots as it goes.
+    fn pattern(&self, pattern: &Pattern, value: &str) -> String {
+        let elements = |patterns: &[Pattern]| -> String {
+            patterns
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    format!(
+                        " && {}",
+                        self.pattern(p, &format!("chop_item({}, {})", value, i))
+                    )
+                })
+                .collect()
+        };
+        match pattern {
+            Pattern::Wildcard => "1".to_string(),
+            Pattern::Bind(slot) => format!("(l[{}] = {}, 1)", slot, value),
+            Pattern::Cell(slot) => format!("(l[{}] = chop_new_cell({}), 1)", slot, value),
+            Pattern::Constant(index) => format!("chop_equal({}, {})", self.constant(*index), value),
+            Pattern::Tuple(patterns) => format!(
+                "(chop_is_tuple({}, {}){})",
+                value,
+                patterns.len(),
+                elements(patterns)
+            ),
+            Pattern::Variant(name, patterns) => {
+                let items = match self.variant(*name, patterns.len()) {
+                    Some(variant) => patterns
+                        .iter()
+                        .enumerate()
+                        .map(|(i, p)| {
+                            let item = format!(
+                                "(({} *){}.as.variant)->as.{}._{}",
+                                variant.union, value, variant.member, i
+                            );
+                            format!(" && {}", self.pattern(p, &item))
+                        })
+                        .collect(),
+                    None => elements(patterns),
+                };
+                format!(
+                    "(chop_is_variant({}, {}, {}){})",
+                    value,
+                    self.tag(*name),
+                    patterns.len(),
+                    items
+                )
+            }
+        }
+    }
+}
+
+/// How many values an instruction takes off the stack and puts back.
+/// Jumps of `Next` leave the stack as it was.
+pub fn effect(op: Op) -> (usize, usize) {
+    match op {
+        Op::Constant(_)
+        | Op::Dup
+        | Op::Local(_)
+        | Op::Cell(_)
+        | Op::Capture(_)
+        | Op::Global(_)
+        | Op::Function(_)
+        | Op::Closure(_)
+        | Op::Builtin(_)
+        | Op::Type(_)
+        | Op::Next(..) => (0, 1),
+        Op::Pop
+        | Op::SetLocal(_)
+        | Op::SetCell(_)
+        | Op::NewCell(_)
+        | Op::SetCapture(_)
+        | Op::SetGlobal(_)
+        | Op::Return
+        | Op::JumpIfFalse(_)
+        | Op::Match(..) => (1, 0),
+        Op::Variant(_, n)
+        | Op::CallFunction(_, n)
+        | Op::CallBuiltin(_, n)
+        | Op::CallMethod(_, n) => (n as usize, 1),
+        Op::Call(n) => (n as usize + 1, 1),
+        Op::Add
+        | Op::Subtract
+        | Op::Multiply
+        | Op::Divide
+        | Op::Remainder
+        | Op::Equal
+        | Op::NotEqual
+        | Op::Less
+        | Op::LessEqual
+        | Op::Greater
+        | Op::GreaterEqual => (2, 1),
+        Op::Negate | Op::Not | Op::Field(_) | Op::Iterate(_) => (1, 1),
+        Op::List(n) | Op::Set(n) | Op::Tuple(n) | Op::Struct(_, n) => (n as usize, 1),
+        Op::Map(n) => (2 * n as usize, 1),
+        Op::SetField(_) => (2, 0),
+        Op::Unpack(n) => (1, n as usize),
+        Op::Jump(_) | Op::NoClause | Op::NoArm(_) | Op::Fail(_) => (0, 0),
+    }
+}
+
+/// The depth of the stack before each instruction, or `None` for code that
+/// never runs. The compiler leaves the stack equally deep on every path to
+/// an instruction.
+pub fn depths(function: &Function) -> Vec<Option<usize>> {
+    let mut depths = vec![None; function.code.len()];
+    let mut work = vec![(0, 0)];
+    while let Some((ip, depth)) = work.pop() {
+        match depths[ip] {
+            Some(known) => {
+                assert_eq!(
+                    known, depth,
+                    "unbalanced stack at {} in `{}`",
+                    ip, function.name
+                );
+                continue;
+            }
+            None => depths[ip] = Some(depth),
+        }
+        let op = function.code[ip];
+        let (pops, pushes) = effect(op);
+        let after = depth - pops + pushes;
+        match op {
+            Op::Jump(target) => work.push((target as usize, depth)),
+            Op::JumpIfFalse(target) | Op::Match(_, target) => {
+                work.push((target as usize, after));
+                work.push((ip + 1, after));
+            }
+            Op::Next(_, target) => {
+                work.push((target as usize, depth));
+                work.push((ip + 1, after));
+            }
+            Op::Return | Op::NoClause | Op::NoArm(_) | Op::Fail(_) => {}
+            _ => work.push((ip + 1, after)),
+        }
+    }
+    depths
+}
+
+/// The operator an instruction applies, as `chop_operator` names it.
+fn symbol(op: Op) -> &'static str {
+    match op {
+        Op::Multiply => "*",
+        Op::Divide => "/",
+        Op::Remainder => "%",
+        Op::NotEqual => "!=",
+        Op::LessEqual => "<=",
+        Op::Greater => ">",
+        Op::GreaterEqual => ">=",
+        Op::Negate => "negate",
+        Op::Not => "not",
+        other => unreachable!("{:?} has its own helper", other),
+    }
+}
+
+/// The C function running a chop function, named after it for debuggers.
+fn code_name(index: usize, function: &Function) -> String {
+    format!("f{}_{}", index, ident(&function.name))
+}
+
+fn ident(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// A C string literal with the bytes of `text`. Question marks are escaped
+/// so that no trigraph can form.
+fn c_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            _ => {
+                let _ = write!(literal, "\\{:03o}", byte);
+            }
+        }
+    }
+    literal.push('"');
+    literal
+}
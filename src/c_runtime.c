@@ -0,0 +1,1468 @@
+/* The runtime every C program generated by `chop build --target=c` starts
+ * with. It mirrors the virtual machine in src/vm.rs: values are a tagged
+ * union, collections are shared between copies, and runtime errors are
+ * reported like `chop run` reports them. Values are never freed; a program
+ * only lives as long as one run. */
+
+#define _POSIX_C_SOURCE 200809L
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef enum {
+    CHOP_NULL,
+    CHOP_UNIT,
+    CHOP_INT,
+    CHOP_FLOAT,
+    CHOP_BOOL,
+    CHOP_STRING,
+    CHOP_LIST,
+    CHOP_SET,
+    CHOP_MAP,
+    CHOP_TUPLE,
+    CHOP_STRUCT,
+    CHOP_VARIANT,
+    CHOP_CLOSURE,
+    CHOP_BUILTIN,
+    CHOP_TYPE,
+    /* The box of a local a closure captures; only ever held in a slot. */
+    CHOP_CELL,
+    /* What a `for` loop has left to visit; only ever held in a slot. */
+    CHOP_ITERATOR,
+    /* A tail call left for the caller to make; only ever returned. */
+    CHOP_TAIL
+} chop_tag;
+
+typedef struct chop_value chop_value;
+typedef struct chop_closure chop_closure;
+
+typedef struct {
+    size_t len;
+    const char *data;
+} chop_string;
+
+/* Lists and sets hold values; maps hold keys and values in turn. */
+typedef struct {
+    size_t len, cap;
+    chop_value *items;
+} chop_vec;
+
+/* Where the field or item `index` of a struct or variant lives in the C
+ * type generated for it. */
+typedef struct chop_value *(*chop_slot)(void *self, size_t index);
+
+/* A struct type: its fields, and the size and layout of the C struct
+ * generated for it. */
+typedef struct {
+    const char *name;
+    size_t count;
+    const char *const *fields;
+    size_t size;
+    chop_slot slot;
+} chop_struct;
+
+typedef struct {
+    const char *name;
+    int arity;
+    chop_value (*code)(chop_closure *self, chop_value *args);
+} chop_function;
+
+struct chop_value {
+    chop_tag tag;
+    union {
+        int64_t i;
+        double f;
+        int b;
+        const chop_string *s;
+        chop_vec *vec;
+        struct chop_tuple *tuple;
+        struct chop_instance *instance;
+        struct chop_variant *variant;
+        chop_closure *closure;
+        const char *name;
+        chop_value *cell;
+        struct chop_iterator *iterator;
+    } as;
+};
+
+struct chop_tuple {
+    size_t len;
+    chop_value items[];
+};
+
+/* The start of every struct value; the fields follow it in the C struct
+ * generated for its type. */
+struct chop_instance {
+    const chop_struct *type;
+};
+
+/* The start of every enum variant; its items follow it in the tagged union
+ * generated for its enum. Its tag is the index of its name among the
+ * program's constants, so equal tags mean equal names. */
+struct chop_variant {
+    int tag;
+    const char *name;
+    size_t len;
+    size_t size;
+    chop_slot slot;
+};
+
+/* A variant of no enum the program declares, with its items in an array. */
+struct chop_any_variant {
+    struct chop_variant header;
+    chop_value items[];
+};
+
+static chop_value *chop_field_at(struct chop_instance *instance, size_t index) {
+    return instance->type->slot(instance, index);
+}
+
+static chop_value *chop_item_at(struct chop_variant *variant, size_t index) {
+    return variant->slot(variant, index);
+}
+
+struct chop_closure {
+    const chop_function *function;
+    chop_value **captures;
+};
+
+struct chop_iterator {
+    size_t len, at;
+    chop_value *items;
+};
+
+typedef struct {
+    unsigned line, column, end_line, end_column;
+} chop_span;
+
+typedef struct {
+    const char *owner;
+    const char *name;
+    const chop_function *function;
+} chop_method;
+
+enum { CHOP_UNINITIALIZED, CHOP_INITIALIZING, CHOP_READY };
+
+typedef struct {
+    const char *name;
+    const chop_function *init;
+    int state;
+    chop_value value;
+} chop_global;
+
+/* Filled in by the generated `chop_setup`. */
+static const char *chop_path;
+static const char *chop_source;
+static const chop_struct *chop_structs;
+static size_t chop_struct_count;
+static const chop_method *chop_methods;
+static size_t chop_method_count;
+static chop_global *chop_globals;
+
+/* Defined by the generated code. */
+static void chop_setup(void);
+static chop_value chop_entry(void);
+static const char *chop_enum_of(int tag);
+
+/* The call sites of the running calls, innermost last. The first is that
+ * of `main`, which is only set once `main` makes a tail call. */
+static const chop_span *chop_calls[CHOP_MAX_DEPTH + 1];
+static long chop_depth;
+
+/* The call a function returned as CHOP_TAIL. */
+static const chop_function *chop_tail_function;
+static chop_closure *chop_tail_closure;
+static chop_value chop_tail_args[256];
+
+static void *chop_alloc(size_t size) {
+    void *memory = malloc(size ? size : 1);
+    if (!memory) {
+        fputs("chop: out of memory\n", stderr);
+        exit(1);
+    }
+    return memory;
+}
+
+/* ---- Building text ---------------------------------------------------- */
+
+typedef struct {
+    char *data;
+    size_t len, cap;
+} chop_buf;
+
+static void chop_put(chop_buf *buf, const char *data, size_t len) {
+    if (buf->len + len + 1 > buf->cap) {
+        size_t cap = buf->cap ? buf->cap : 64;
+        while (cap < buf->len + len + 1) {
+            cap *= 2;
+        }
+        char *data2 = chop_alloc(cap);
+        if (buf->len) {
+            memcpy(data2, buf->data, buf->len);
+        }
+        free(buf->data);
+        buf->data = data2;
+        buf->cap = cap;
+    }
+    memcpy(buf->data + buf->len, data, len);
+    buf->len += len;
+    buf->data[buf->len] = '\0';
+}
+
+static void chop_puts(chop_buf *buf, const char *text) {
+    chop_put(buf, text, strlen(text));
+}
+
+static void chop_printf(chop_buf *buf, const char *format, ...) {
+    char small[128];
+    va_list args;
+    va_start(args, format);
+    int len = vsnprintf(small, sizeof small, format, args);
+    va_end(args);
+    if (len < (int)sizeof small) {
+        chop_put(buf, small, (size_t)len);
+        return;
+    }
+    char *large = chop_alloc((size_t)len + 1);
+    va_start(args, format);
+    vsnprintf(large, (size_t)len + 1, format, args);
+    va_end(args);
+    chop_put(buf, large, (size_t)len);
+    free(large);
+}
+
+/* Floats print like Rust's `{:?}`: the shortest digits that read back as
+ * the same number, in exponent notation when very large or small. */
+static void chop_put_float(chop_buf *buf, double x) {
+    if (isnan(x)) {
+        chop_puts(buf, "NaN");
+        return;
+    }
+    if (isinf(x)) {
+        chop_puts(buf, x < 0 ? "-inf" : "inf");
+        return;
+    }
+    if (x == 0) {
+        chop_puts(buf, signbit(x) ? "-0.0" : "0.0");
+        return;
+    }
+    char text[40];
+    for (int precision = 0; precision < 17; precision++) {
+        snprintf(text, sizeof text, "%.*e", precision, x);
+        if (strtod(text, NULL) == x) {
+            break;
+        }
+    }
+    /* `text` is [-]d[.ddd]e[+-]xx; gather the digits and the exponent. */
+    char digits[24];
+    size_t count = 0;
+    const char *p = text;
+    if (*p == '-') {
+        chop_puts(buf, "-");
+        p++;
+    }
+    for (; *p != 'e'; p++) {
+        if (*p != '.') {
+            digits[count++] = *p;
+        }
+    }
+    int exponent = atoi(p + 1);
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+    if (exponent < -4 || exponent >= 16) {
+        chop_put(buf, digits, 1);
+        if (count > 1) {
+            chop_puts(buf, ".");
+            chop_put(buf, digits + 1, count - 1);
+        }
+        chop_printf(buf, "e%d", exponent);
+    } else if (exponent < 0) {
+        chop_puts(buf, "0.");
+        for (int i = -1; i > exponent; i--) {
+            chop_puts(buf, "0");
+        }
+        chop_put(buf, digits, count);
+    } else if ((size_t)exponent + 1 >= count) {
+        chop_put(buf, digits, count);
+        for (size_t i = count; i < (size_t)exponent + 1; i++) {
+            chop_puts(buf, "0");
+        }
+        chop_puts(buf, ".0");
+    } else {
+        chop_put(buf, digits, (size_t)exponent + 1);
+        chop_puts(buf, ".");
+        chop_put(buf, digits + exponent + 1, count - (size_t)exponent - 1);
+    }
+}
+
+/* Strings inside collections print quoted, like Rust's `{:?}`. */
+static void chop_put_quoted(chop_buf *buf, const chop_string *s) {
+    chop_puts(buf, "\"");
+    for (size_t i = 0; i < s->len; i++) {
+        unsigned char c = (unsigned char)s->data[i];
+        switch (c) {
+        case '"': chop_puts(buf, "\\\""); break;
+        case '\\': chop_puts(buf, "\\\\"); break;
+        case '\n': chop_puts(buf, "\\n"); break;
+        case '\r': chop_puts(buf, "\\r"); break;
+        case '\t': chop_puts(buf, "\\t"); break;
+        case '\0': chop_puts(buf, "\\0"); break;
+        default:
+            if (c < 0x20 || c == 0x7f) {
+                chop_printf(buf, "\\u{%x}", c);
+            } else {
+                chop_put(buf, (const char *)&c, 1);
+            }
+        }
+    }
+    chop_puts(buf, "\"");
+}
+
+static void chop_show(chop_buf *buf, chop_value value, int quoted);
+
+static void chop_show_all(chop_buf *buf, const chop_value *items, size_t len) {
+    for (size_t i = 0; i < len; i++) {
+        if (i > 0) {
+            chop_puts(buf, ", ");
+        }
+        chop_show(buf, items[i], 1);
+    }
+}
+
+static void chop_show(chop_buf *buf, chop_value value, int quoted) {
+    switch (value.tag) {
+    case CHOP_NULL: chop_puts(buf, "null"); break;
+    case CHOP_UNIT: chop_puts(buf, "()"); break;
+    case CHOP_INT: chop_printf(buf, "%" PRId64, value.as.i); break;
+    case CHOP_FLOAT: chop_put_float(buf, value.as.f); break;
+    case CHOP_BOOL: chop_puts(buf, value.as.b ? "true" : "false"); break;
+    case CHOP_STRING:
+        if (quoted) {
+            chop_put_quoted(buf, value.as.s);
+        } else {
+            chop_put(buf, value.as.s->data, value.as.s->len);
+        }
+        break;
+    case CHOP_LIST:
+        chop_puts(buf, "[");
+        chop_show_all(buf, value.as.vec->items, value.as.vec->len);
+        chop_puts(buf, "]");
+        break;
+    case CHOP_SET:
+        chop_puts(buf, "{");
+        chop_show_all(buf, value.as.vec->items, value.as.vec->len);
+        chop_puts(buf, "}");
+        break;
+    case CHOP_MAP:
+        chop_puts(buf, "{");
+        for (size_t i = 0; i < value.as.vec->len; i += 2) {
+            if (i > 0) {
+                chop_puts(buf, ", ");
+            }
+            chop_show(buf, value.as.vec->items[i], 1);
+            chop_puts(buf, ": ");
+            chop_show(buf, value.as.vec->items[i + 1], 1);
+        }
+        chop_puts(buf, "}");
+        break;
+    case CHOP_TUPLE:
+        chop_puts(buf, "(");
+        chop_show_all(buf, value.as.tuple->items, value.as.tuple->len);
+        chop_puts(buf, ")");
+        break;
+    case CHOP_STRUCT: {
+        const chop_struct *type = value.as.instance->type;
+        chop_printf(buf, "%s { ", type->name);
+        for (size_t i = 0; i < type->count; i++) {
+            if (i > 0) {
+                chop_puts(buf, ", ");
+            }
+            chop_printf(buf, "%s: ", type->fields[i]);
+            chop_show(buf, *chop_field_at(value.as.instance, i), 1);
+        }
+        chop_puts(buf, " }");
+        break;
+    }
+    case CHOP_VARIANT:
+        chop_puts(buf, value.as.variant->name);
+        if (value.as.variant->len > 0) {
+            chop_puts(buf, "(");
+            for (size_t i = 0; i < value.as.variant->len; i++) {
+                if (i > 0) {
+                    chop_puts(buf, ", ");
+                }
+                chop_show(buf, *chop_item_at(value.as.variant, i), 1);
+            }
+            chop_puts(buf, ")");
+        }
+        break;
+    case CHOP_CLOSURE: chop_printf(buf, "<fn %s>", value.as.closure->function->name); break;
+    case CHOP_BUILTIN: chop_puts(buf, "<builtin>"); break;
+    case CHOP_TYPE: chop_puts(buf, "<type>"); break;
+    case CHOP_CELL: chop_show(buf, *value.as.cell, 1); break;
+    case CHOP_ITERATOR: chop_puts(buf, "<iterator>"); break;
+    case CHOP_TAIL: chop_puts(buf, "<tail call>"); break;
+    }
+}
+
+/* The value as `println` prints it, for error messages. */
+static const char *chop_text(chop_value value) {
+    chop_buf buf = {0};
+    chop_show(&buf, value, 0);
+    chop_puts(&buf, "");
+    return buf.data;
+}
+
+/* ---- Errors ----------------------------------------------------------- */
+
+/* The source line a span starts on, underlined like `Diagnostic::render`. */
+static void chop_snippet(const chop_span *at) {
+    const char *line = chop_source;
+    for (unsigned n = 1; n < at->line; n++) {
+        line = strchr(line, '\n');
+        if (!line) {
+            return;
+        }
+        line++;
+    }
+    if (!*line) {
+        return;
+    }
+    size_t len = strcspn(line, "\n");
+    if (len > 0 && line[len - 1] == '\r') {
+        len--;
+    }
+    size_t start = at->column > 0 ? at->column - 1 : 0;
+    start = start < len ? start : len;
+    size_t end = len > start + 1 ? len : start + 1;
+    if (at->end_line == at->line) {
+        size_t limit = end;
+        end = at->end_column > 0 ? at->end_column - 1 : 0;
+        end = end < start + 1 ? start + 1 : end > limit ? limit : end;
+    }
+    char gutter[16];
+    int width = snprintf(gutter, sizeof gutter, "%u", at->line);
+    fprintf(stderr, "%*s |\n%u | %.*s\n%*s | ", width, "", at->line, (int)len, line, width, "");
+    for (size_t i = 0; i < start; i++) {
+        fputc(' ', stderr);
+    }
+    for (size_t i = start; i < end; i++) {
+        fputc('^', stderr);
+    }
+    fputc('\n', stderr);
+}
+
+/* Stops the program with a runtime error at `at`. */
+static void chop_error(const chop_span *at, const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "%s:%u:%u: error: %s\n", chop_path, at->line, at->column, message);
+    chop_snippet(at);
+    fprintf(stderr, "%s: the program stopped with an error\n", chop_path);
+    exit(1);
+}
+
+static void chop_errorf(const chop_span *at, const char *format, ...) {
+    char small[256];
+    va_list args;
+    va_start(args, format);
+    int len = vsnprintf(small, sizeof small, format, args);
+    va_end(args);
+    if (len < (int)sizeof small) {
+        chop_error(at, small);
+    }
+    char *large = chop_alloc((size_t)len + 1);
+    va_start(args, format);
+    vsnprintf(large, (size_t)len + 1, format, args);
+    va_end(args);
+    chop_error(at, large);
+}
+
+/* "no clause of `f` matches the arguments (a, b)" */
+static void chop_no_match(const chop_function *function, int count, const chop_value *args,
+                          const chop_span *at) {
+    chop_buf buf = {0};
+    chop_printf(&buf, "no clause of `%s` matches the arguments (", function->name);
+    for (int i = 0; i < count; i++) {
+        if (i > 0) {
+            chop_puts(&buf, ", ");
+        }
+        chop_show(&buf, args[i], 0);
+    }
+    chop_puts(&buf, ")");
+    chop_error(at, buf.data);
+}
+
+/* No clause of the running function accepts its arguments; the error
+ * points at the call. */
+static void chop_no_clause(const chop_function *function, const chop_value *args,
+                           const chop_span *at) {
+    const chop_span *call = chop_calls[chop_depth] ? chop_calls[chop_depth] : at;
+    chop_no_match(function, function->arity, args, call);
+}
+
+static void chop_no_arm(chop_value value, const chop_span *at) {
+    chop_errorf(at, "no arm of this `match` matches `%s`", chop_text(value));
+}
+
+/* ---- Values ----------------------------------------------------------- */
+
+static const chop_value chop_null = {CHOP_NULL, {0}};
+static const chop_value chop_unit = {CHOP_UNIT, {0}};
+
+static chop_value chop_int(int64_t i) {
+    chop_value value;
+    value.tag = CHOP_INT;
+    value.as.i = i;
+    return value;
+}
+
+static chop_value chop_float(double f) {
+    chop_value value;
+    value.tag = CHOP_FLOAT;
+    value.as.f = f;
+    return value;
+}
+
+/* A float constant, given by its bits so that it is exact. */
+static chop_value chop_float_bits(uint64_t bits) {
+    double f;
+    memcpy(&f, &bits, sizeof f);
+    return chop_float(f);
+}
+
+static chop_value chop_bool(int b) {
+    chop_value value;
+    value.tag = CHOP_BOOL;
+    value.as.b = b != 0;
+    return value;
+}
+
+static chop_value chop_string_value(const chop_string *s) {
+    chop_value value;
+    value.tag = CHOP_STRING;
+    value.as.s = s;
+    return value;
+}
+
+static chop_value chop_named(chop_tag tag, const char *name) {
+    chop_value value;
+    value.tag = tag;
+    value.as.name = name;
+    return value;
+}
+
+static chop_value chop_closure_value(chop_closure *closure) {
+    chop_value value;
+    value.tag = CHOP_CLOSURE;
+    value.as.closure = closure;
+    return value;
+}
+
+static chop_value chop_new_closure(const chop_function *function, chop_value **captures) {
+    chop_closure *closure = chop_alloc(sizeof *closure);
+    closure->function = function;
+    closure->captures = captures;
+    return chop_closure_value(closure);
+}
+
+static chop_value chop_new_cell(chop_value content) {
+    chop_value value;
+    value.tag = CHOP_CELL;
+    value.as.cell = chop_alloc(sizeof content);
+    *value.as.cell = content;
+    return value;
+}
+
+static chop_value chop_vec_value(chop_tag tag, size_t cap) {
+    chop_value value;
+    value.tag = tag;
+    value.as.vec = chop_alloc(sizeof *value.as.vec);
+    value.as.vec->len = 0;
+    value.as.vec->cap = cap;
+    value.as.vec->items = chop_alloc(cap * sizeof(chop_value));
+    return value;
+}
+
+static chop_value chop_list(size_t count, const chop_value *items) {
+    chop_value value = chop_vec_value(CHOP_LIST, count);
+    memcpy(value.as.vec->items, items, count * sizeof(chop_value));
+    value.as.vec->len = count;
+    return value;
+}
+
+static chop_value chop_tuple(size_t count, const chop_value *items) {
+    chop_value value;
+    value.tag = CHOP_TUPLE;
+    value.as.tuple = chop_alloc(sizeof(struct chop_tuple) + count * sizeof(chop_value));
+    value.as.tuple->len = count;
+    memcpy(value.as.tuple->items, items, count * sizeof(chop_value));
+    return value;
+}
+
+static chop_value chop_variant_value(struct chop_variant *variant) {
+    chop_value value;
+    value.tag = CHOP_VARIANT;
+    value.as.variant = variant;
+    return value;
+}
+
+static chop_value chop_instance_value(struct chop_instance *instance) {
+    chop_value value;
+    value.tag = CHOP_STRUCT;
+    value.as.instance = instance;
+    return value;
+}
+
+static chop_value *chop_any_item(void *self, size_t index) {
+    return &((struct chop_any_variant *)self)->items[index];
+}
+
+/* A variant the generated code has no tagged union for. */
+static chop_value chop_variant(int tag, const char *name, size_t count, const chop_value *items) {
+    size_t size = sizeof(struct chop_any_variant) + count * sizeof(chop_value);
+    struct chop_any_variant *variant = chop_alloc(size);
+    variant->header.tag = tag;
+    variant->header.name = name;
+    variant->header.len = count;
+    variant->header.size = size;
+    variant->header.slot = chop_any_item;
+    memcpy(variant->items, items, count * sizeof(chop_value));
+    return chop_variant_value(&variant->header);
+}
+
+static chop_value chop_instance(const chop_struct *type, const chop_value *fields) {
+    struct chop_instance *instance = chop_alloc(type->size);
+    instance->type = type;
+    for (size_t i = 0; i < type->count; i++) {
+        *chop_field_at(instance, i) = fields[i];
+    }
+    return chop_instance_value(instance);
+}
+
+static chop_value chop_struct_value(size_t index, const chop_value *fields) {
+    return chop_instance(&chop_structs[index], fields);
+}
+
+/* ---- Comparison ------------------------------------------------------- */
+
+static int chop_equal(chop_value a, chop_value b);
+
+static int chop_equal_all(const chop_value *xs, size_t xlen, const chop_value *ys, size_t ylen) {
+    if (xlen != ylen) {
+        return 0;
+    }
+    for (size_t i = 0; i < xlen; i++) {
+        if (!chop_equal(xs[i], ys[i])) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+static int chop_number(chop_value value, double *f) {
+    if (value.tag == CHOP_INT) {
+        *f = (double)value.as.i;
+        return 1;
+    }
+    if (value.tag == CHOP_FLOAT) {
+        *f = value.as.f;
+        return 1;
+    }
+    return 0;
+}
+
+static int chop_same_string(const chop_string *a, const chop_string *b) {
+    return a->len == b->len && memcmp(a->data, b->data, a->len) == 0;
+}
+
+/* Structural equality; functions are never equal. */
+static int chop_equal(chop_value a, chop_value b) {
+    double x, y;
+    if (a.tag == CHOP_INT && b.tag == CHOP_INT) {
+        return a.as.i == b.as.i;
+    }
+    if (chop_number(a, &x) && chop_number(b, &y)) {
+        return x == y;
+    }
+    if (a.tag != b.tag) {
+        return 0;
+    }
+    switch (a.tag) {
+    case CHOP_NULL:
+    case CHOP_UNIT: return 1;
+    case CHOP_BOOL: return a.as.b == b.as.b;
+    case CHOP_STRING: return chop_same_string(a.as.s, b.as.s);
+    case CHOP_LIST:
+        return chop_equal_all(a.as.vec->items, a.as.vec->len, b.as.vec->items, b.as.vec->len);
+    case CHOP_SET:
+        if (a.as.vec->len != b.as.vec->len) {
+            return 0;
+        }
+        for (size_t i = 0; i < a.as.vec->len; i++) {
+            int found = 0;
+            for (size_t j = 0; j < b.as.vec->len && !found; j++) {
+                found = chop_equal(a.as.vec->items[i], b.as.vec->items[j]);
+            }
+            if (!found) {
+                return 0;
+            }
+        }
+        return 1;
+    case CHOP_MAP:
+        if (a.as.vec->len != b.as.vec->len) {
+            return 0;
+        }
+        for (size_t i = 0; i < a.as.vec->len; i += 2) {
+            size_t j = 0;
+            while (j < b.as.vec->len && !chop_equal(a.as.vec->items[i], b.as.vec->items[j])) {
+                j += 2;
+            }
+            if (j == b.as.vec->len || !chop_equal(a.as.vec->items[i + 1], b.as.vec->items[j + 1])) {
+                return 0;
+            }
+        }
+        return 1;
+    case CHOP_TUPLE:
+        return chop_equal_all(a.as.tuple->items, a.as.tuple->len, b.as.tuple->items,
+                              b.as.tuple->len);
+    case CHOP_STRUCT:
+        if (a.as.instance == b.as.instance) {
+            return 1;
+        }
+        if (a.as.instance->type != b.as.instance->type) {
+            return 0;
+        }
+        for (size_t i = 0; i < a.as.instance->type->count; i++) {
+            if (!chop_equal(*chop_field_at(a.as.instance, i), *chop_field_at(b.as.instance, i))) {
+                return 0;
+            }
+        }
+        return 1;
+    case CHOP_VARIANT:
+        if (a.as.variant->tag != b.as.variant->tag || a.as.variant->len != b.as.variant->len) {
+            return 0;
+        }
+        for (size_t i = 0; i < a.as.variant->len; i++) {
+            if (!chop_equal(*chop_item_at(a.as.variant, i), *chop_item_at(b.as.variant, i))) {
+                return 0;
+            }
+        }
+        return 1;
+    case CHOP_TYPE: return strcmp(a.as.name, b.as.name) == 0;
+    default: return 0;
+    }
+}
+
+/* Orders two values, returning 0 when they cannot be compared. */
+static int chop_compare(chop_value a, chop_value b, int *order) {
+    double x, y;
+    if (a.tag == CHOP_INT && b.tag == CHOP_INT) {
+        *order = (a.as.i > b.as.i) - (a.as.i < b.as.i);
+        return 1;
+    }
+    if (a.tag == CHOP_STRING && b.tag == CHOP_STRING) {
+        size_t len = a.as.s->len < b.as.s->len ? a.as.s->len : b.as.s->len;
+        int c = memcmp(a.as.s->data, b.as.s->data, len);
+        size_t x = a.as.s->len, y = b.as.s->len;
+        *order = c != 0 ? (c > 0) - (c < 0) : (x > y) - (x < y);
+        return 1;
+    }
+    if (chop_number(a, &x) && chop_number(b, &y) && !isnan(x) && !isnan(y)) {
+        *order = (x > y) - (x < y);
+        return 1;
+    }
+    return 0;
+}
+
+/* ---- Operators -------------------------------------------------------- */
+
+static int chop_named_is(const char *name, const char *expected) {
+    return strcmp(name, expected) == 0;
+}
+
+static int chop_checked(const char *name, int64_t a, int64_t b, int64_t *result) {
+    switch (name[0]) {
+    case '+':
+        if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+            return 0;
+        }
+        *result = a + b;
+        return 1;
+    case '-':
+        if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) {
+            return 0;
+        }
+        *result = a - b;
+        return 1;
+    case '*':
+        if (a > 0 ? (b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a)
+                  : (b > 0 ? a < INT64_MIN / b : a != 0 && b < INT64_MAX / a)) {
+            return 0;
+        }
+        *result = a * b;
+        return 1;
+    default:
+        if (b == 0 || (a == INT64_MIN && b == -1)) {
+            return 0;
+        }
+        *result = name[0] == '/' ? a / b : a % b;
+        return 1;
+    }
+}
+
+/* The builtin operators, with the same semantics as in the interpreter. */
+static chop_value chop_operator(const char *name, int count, const chop_value *args,
+                                const chop_span *at) {
+    int arithmetic = strlen(name) == 1 && strchr("+-*/%", name[0]) != NULL;
+    if (chop_named_is(name, "not") && count == 1 && args[0].tag == CHOP_BOOL) {
+        return chop_bool(!args[0].as.b);
+    }
+    if (chop_named_is(name, "negate") && count == 1 && args[0].tag == CHOP_INT) {
+        if (args[0].as.i == INT64_MIN) {
+            chop_errorf(at, "negating `%" PRId64 "` overflows an `int`", args[0].as.i);
+        }
+        return chop_int(-args[0].as.i);
+    }
+    if (chop_named_is(name, "negate") && count == 1 && args[0].tag == CHOP_FLOAT) {
+        return chop_float(-args[0].as.f);
+    }
+    if (arithmetic && count == 2 && args[0].tag == CHOP_INT && args[1].tag == CHOP_INT) {
+        int64_t a = args[0].as.i, b = args[1].as.i, result;
+        if (chop_checked(name, a, b, &result)) {
+            return chop_int(result);
+        }
+        if (b == 0 && (name[0] == '/' || name[0] == '%')) {
+            chop_error(at, "division by zero");
+        }
+        chop_errorf(at, "`%" PRId64 " %s %" PRId64 "` overflows an `int`", a, name, b);
+    }
+    if (count == 2 && (chop_named_is(name, "==") || chop_named_is(name, "!="))) {
+        return chop_bool(chop_equal(args[0], args[1]) == (name[0] == '='));
+    }
+    if (count == 2 && (name[0] == '<' || name[0] == '>')) {
+        int order;
+        if (!chop_compare(args[0], args[1], &order)) {
+            chop_errorf(at, "cannot compare `%s` and `%s`", chop_text(args[0]),
+                        chop_text(args[1]));
+        }
+        int equal_too = name[1] == '=';
+        return chop_bool(name[0] == '<' ? order < 0 || (equal_too && order == 0)
+                                        : order > 0 || (equal_too && order == 0));
+    }
+    if (arithmetic && count == 2) {
+        double a, b;
+        if (!chop_number(args[0], &a) || !chop_number(args[1], &b)) {
+            chop_errorf(at, "cannot apply `%s` to `%s` and `%s`", name, chop_text(args[0]),
+                        chop_text(args[1]));
+        }
+        switch (name[0]) {
+        case '+': return chop_float(a + b);
+        case '-': return chop_float(a - b);
+        case '*': return chop_float(a * b);
+        case '/': return chop_float(a / b);
+        default: return chop_float(fmod(a, b));
+        }
+    }
+    chop_errorf(at, "`%s` cannot be applied to these arguments", name);
+    return chop_unit;
+}
+
+static chop_value chop_binary(const char *name, chop_value a, chop_value b, const chop_span *at) {
+    chop_value args[2];
+    args[0] = a;
+    args[1] = b;
+    return chop_operator(name, 2, args, at);
+}
+
+/* Integers take a fast path; anything it cannot handle, overflow included,
+ * goes through `chop_operator`. */
+static chop_value chop_add(chop_value a, chop_value b, const chop_span *at) {
+    if (a.tag == CHOP_INT && b.tag == CHOP_INT
+        && !((b.as.i > 0 && a.as.i > INT64_MAX - b.as.i)
+             || (b.as.i < 0 && a.as.i < INT64_MIN - b.as.i))) {
+        return chop_int(a.as.i + b.as.i);
+    }
+    return chop_binary("+", a, b, at);
+}
+
+static chop_value chop_subtract(chop_value a, chop_value b, const chop_span *at) {
+    if (a.tag == CHOP_INT && b.tag == CHOP_INT
+        && !((b.as.i < 0 && a.as.i > INT64_MAX + b.as.i)
+             || (b.as.i > 0 && a.as.i < INT64_MIN + b.as.i))) {
+        return chop_int(a.as.i - b.as.i);
+    }
+    return chop_binary("-", a, b, at);
+}
+
+static chop_value chop_less(chop_value a, chop_value b, const chop_span *at) {
+    if (a.tag == CHOP_INT && b.tag == CHOP_INT) {
+        return chop_bool(a.as.i < b.as.i);
+    }
+    return chop_binary("<", a, b, at);
+}
+
+static chop_value chop_equals(chop_value a, chop_value b) {
+    return chop_bool(chop_equal(a, b));
+}
+
+static chop_value chop_unary(const char *name, chop_value a, const chop_span *at) {
+    return chop_operator(name, 1, &a, at);
+}
+
+static int chop_truth(chop_value value, const chop_span *at) {
+    if (value.tag != CHOP_BOOL) {
+        chop_errorf(at, "expected `true` or `false`, found `%s`", chop_text(value));
+    }
+    return value.as.b;
+}
+
+/* ---- Collections ------------------------------------------------------ */
+
+static void chop_push(chop_vec *vec, chop_value value) {
+    if (vec->len == vec->cap) {
+        vec->cap = vec->cap ? 2 * vec->cap : 4;
+        chop_value *items = chop_alloc(vec->cap * sizeof(chop_value));
+        if (vec->len) {
+            memcpy(items, vec->items, vec->len * sizeof(chop_value));
+        }
+        free(vec->items);
+        vec->items = items;
+    }
+    vec->items[vec->len++] = value;
+}
+
+static chop_value chop_set(size_t count, const chop_value *items) {
+    chop_value value = chop_vec_value(CHOP_SET, count);
+    for (size_t i = 0; i < count; i++) {
+        size_t j = 0;
+        while (j < value.as.vec->len && !chop_equal(value.as.vec->items[j], items[i])) {
+            j++;
+        }
+        if (j == value.as.vec->len) {
+            chop_push(value.as.vec, items[i]);
+        }
+    }
+    return value;
+}
+
+/* A map from `count` keys, each followed by its value; later keys win. */
+static chop_value chop_map(size_t count, const chop_value *items) {
+    chop_value value = chop_vec_value(CHOP_MAP, 2 * count);
+    for (size_t i = 0; i < 2 * count; i += 2) {
+        size_t j = 0;
+        while (j < value.as.vec->len && !chop_equal(value.as.vec->items[j], items[i])) {
+            j += 2;
+        }
+        if (j == value.as.vec->len) {
+            chop_push(value.as.vec, items[i]);
+            chop_push(value.as.vec, items[i + 1]);
+        } else {
+            value.as.vec->items[j + 1] = items[i + 1];
+        }
+    }
+    return value;
+}
+
+static int chop_field_index(const chop_struct *type, const char *name, const chop_span *at) {
+    for (size_t i = 0; i < type->count; i++) {
+        if (strcmp(type->fields[i], name) == 0) {
+            return (int)i;
+        }
+    }
+    chop_errorf(at, "`%s` has no field `%s`", type->name, name);
+    return -1;
+}
+
+static chop_value chop_field(chop_value receiver, const char *name, const chop_span *at) {
+    if (receiver.tag == CHOP_STRUCT) {
+        return *chop_field_at(receiver.as.instance,
+                              chop_field_index(receiver.as.instance->type, name, at));
+    }
+    if (receiver.tag == CHOP_TUPLE && *name >= '0' && *name <= '9'
+        && strspn(name, "0123456789") == strlen(name)) {
+        char *end;
+        unsigned long long index = strtoull(name, &end, 10);
+        if (index < receiver.as.tuple->len && (name[0] != '0' || name[1] == '\0')) {
+            return receiver.as.tuple->items[index];
+        }
+    }
+    chop_errorf(at, "`%s` has no field `%s`", chop_text(receiver), name);
+    return chop_unit;
+}
+
+static void chop_set_field(chop_value receiver, const char *name, chop_value value,
+                           const chop_span *at) {
+    if (receiver.tag != CHOP_STRUCT) {
+        chop_errorf(at, "`%s` has no field `%s`", chop_text(receiver), name);
+    }
+    *chop_field_at(receiver.as.instance, chop_field_index(receiver.as.instance->type, name, at)) =
+        value;
+}
+
+/* What a `for` loop with `count` names visits: single values, or tuples
+ * with one element per name. */
+static chop_value chop_iterate(chop_value iterable, int count, const chop_span *at) {
+    struct chop_iterator *iterator = chop_alloc(sizeof *iterator);
+    iterator->at = 0;
+    switch (iterable.tag) {
+    case CHOP_LIST:
+    case CHOP_SET:
+        iterator->len = iterable.as.vec->len;
+        iterator->items = chop_alloc(iterator->len * sizeof(chop_value));
+        if (iterator->len) {
+            memcpy(iterator->items, iterable.as.vec->items, iterator->len * sizeof(chop_value));
+        }
+        break;
+    case CHOP_MAP:
+        iterator->len = iterable.as.vec->len / 2;
+        iterator->items = chop_alloc(iterator->len * sizeof(chop_value));
+        for (size_t i = 0; i < iterator->len; i++) {
+            iterator->items[i] = count == 2 ? chop_tuple(2, &iterable.as.vec->items[2 * i])
+                                            : iterable.as.vec->items[2 * i];
+        }
+        break;
+    case CHOP_STRING: {
+        const chop_string *s = iterable.as.s;
+        iterator->len = 0;
+        iterator->items = chop_alloc(s->len * sizeof(chop_value));
+        for (size_t i = 0; i < s->len;) {
+            size_t width = 1;
+            while (i + width < s->len && ((unsigned char)s->data[i + width] & 0xc0) == 0x80) {
+                width++;
+            }
+            chop_string *c = chop_alloc(sizeof *c);
+            c->len = width;
+            c->data = s->data + i;
+            iterator->items[iterator->len++] = chop_string_value(c);
+            i += width;
+        }
+        break;
+    }
+    default:
+        chop_errorf(at, "cannot loop over `%s`", chop_text(iterable));
+    }
+    if (count > 1 && !(iterable.tag == CHOP_MAP && count == 2)) {
+        for (size_t i = 0; i < iterator->len; i++) {
+            chop_value item = iterator->items[i];
+            if (item.tag != CHOP_TUPLE || item.as.tuple->len != (size_t)count) {
+                chop_errorf(at, "cannot split `%s` into %d loop variables", chop_text(item), count);
+            }
+        }
+    }
+    chop_value value;
+    value.tag = CHOP_ITERATOR;
+    value.as.iterator = iterator;
+    return value;
+}
+
+static int chop_next(chop_value iterator, chop_value *next) {
+    if (iterator.as.iterator->at == iterator.as.iterator->len) {
+        return 0;
+    }
+    *next = iterator.as.iterator->items[iterator.as.iterator->at++];
+    return 1;
+}
+
+static void chop_unpack(chop_value tuple, chop_value *into) {
+    memcpy(into, tuple.as.tuple->items, tuple.as.tuple->len * sizeof(chop_value));
+}
+
+static int chop_is_tuple(chop_value value, size_t len) {
+    return value.tag == CHOP_TUPLE && value.as.tuple->len == len;
+}
+
+static int chop_is_variant(chop_value value, int tag, size_t len) {
+    return value.tag == CHOP_VARIANT && value.as.variant->tag == tag
+        && value.as.variant->len == len;
+}
+
+/* An element of a tuple or variant that matched `chop_is_tuple` or
+ * `chop_is_variant`. */
+static chop_value chop_item(chop_value value, size_t index) {
+    return value.tag == CHOP_TUPLE ? value.as.tuple->items[index]
+                                   : *chop_item_at(value.as.variant, index);
+}
+
+/* ---- Calls ------------------------------------------------------------ */
+
+static void chop_enter(const chop_function *function, const chop_span *at) {
+    if (chop_depth >= CHOP_MAX_DEPTH) {
+        chop_errorf(at, "calls to `%s` nest more than %d deep", function->name, CHOP_MAX_DEPTH);
+    }
+    chop_calls[++chop_depth] = at;
+}
+
+static void chop_leave(void) {
+    chop_depth--;
+}
+
+/* A tail call takes the place of the running call, so errors about its
+ * arguments point at it rather than at the call it replaced. */
+static void chop_replace_call(const chop_span *at) {
+    chop_calls[chop_depth] = at;
+}
+
+/* Returns a call to `function` for the caller to make once the running
+ * call has returned, so that tail calls do not grow the C stack. */
+static chop_value chop_tail(const chop_function *function, chop_closure *closure, int count,
+                            const chop_value *args, const chop_span *at) {
+    chop_value value;
+    if (count != function->arity) {
+        chop_no_match(function, count, args, at);
+    }
+    chop_replace_call(at);
+    chop_tail_function = function;
+    chop_tail_closure = closure;
+    memmove(chop_tail_args, args, count * sizeof *args);
+    value.tag = CHOP_TAIL;
+    return value;
+}
+
+/* Makes the tail calls a function returned, until one returns a value.
+ * Each callee copies its arguments into its own slots before anything
+ * else, so they may all share `chop_tail_args`. */
+static chop_value chop_finish(chop_value value) {
+    while (value.tag == CHOP_TAIL) {
+        value = chop_tail_function->code(chop_tail_closure, chop_tail_args);
+    }
+    return value;
+}
+
+static chop_value chop_invoke(const chop_function *function, chop_closure *closure, int count,
+                              chop_value *args, const chop_span *at, int tail) {
+    if (tail) {
+        return chop_tail(function, closure, count, args, at);
+    }
+    if (count != function->arity) {
+        chop_no_match(function, count, args, at);
+    }
+    chop_enter(function, at);
+    chop_value result = chop_finish(function->code(closure, args));
+    chop_leave();
+    return result;
+}
+
+/* ---- Caches ----------------------------------------------------------- */
+
+/* The results a `@memo` fn has cached, in a table of `capacity` slots
+ * that the hash of the arguments picks from, allocated on first use. */
+typedef struct {
+    int used;
+    uint64_t hash;
+    chop_value *args;
+    chop_value result;
+} chop_memo_entry;
+
+typedef struct {
+    uint32_t capacity;
+    int arity;
+    chop_memo_entry *slots;
+} chop_memo;
+
+#define CHOP_HASH_SEED UINT64_C(0xcbf29ce484222325)
+
+/* A step of FNV-1a over words, as in src/memo.rs. */
+static uint64_t chop_mix(uint64_t hash, uint64_t word) {
+    return (hash ^ word) * UINT64_C(0x100000001b3);
+}
+
+static uint64_t chop_hash_bytes(const char *data, size_t len) {
+    uint64_t hash = CHOP_HASH_SEED;
+    for (size_t i = 0; i < len; i++) {
+        hash = chop_mix(hash, (unsigned char)data[i]);
+    }
+    return hash;
+}
+
+static uint64_t chop_hash_all(const chop_value *items, size_t len);
+
+/* The word a value mixes into a hash. Equal values have equal words. */
+static uint64_t chop_word(chop_value value) {
+    uint64_t sum = 0;
+    switch (value.tag) {
+    case CHOP_INT: return (uint64_t)value.as.i;
+    case CHOP_FLOAT:
+        if (floor(value.as.f) == value.as.f && fabs(value.as.f) < 9223372036854775808.0) {
+            return (uint64_t)(int64_t)value.as.f;
+        }
+        memcpy(&sum, &value.as.f, sizeof sum);
+        return sum;
+    case CHOP_BOOL: return (uint64_t)value.as.b;
+    case CHOP_STRING: return chop_hash_bytes(value.as.s->data, value.as.s->len);
+    case CHOP_LIST: return chop_hash_all(value.as.vec->items, value.as.vec->len);
+    case CHOP_SET:
+        for (size_t i = 0; i < value.as.vec->len; i++) {
+            sum += chop_word(value.as.vec->items[i]);
+        }
+        return sum;
+    case CHOP_MAP:
+        for (size_t i = 0; i < value.as.vec->len; i += 2) {
+            sum += chop_mix(chop_word(value.as.vec->items[i]), chop_word(value.as.vec->items[i + 1]));
+        }
+        return sum;
+    case CHOP_TUPLE: return chop_hash_all(value.as.tuple->items, value.as.tuple->len);
+    case CHOP_STRUCT: {
+        const chop_struct *type = value.as.instance->type;
+        uint64_t hash = CHOP_HASH_SEED;
+        for (size_t i = 0; i < type->count; i++) {
+            hash = chop_mix(hash, chop_word(*chop_field_at(value.as.instance, i)));
+        }
+        return chop_mix(chop_hash_bytes(type->name, strlen(type->name)), hash);
+    }
+    case CHOP_VARIANT: {
+        const char *name = value.as.variant->name;
+        uint64_t hash = CHOP_HASH_SEED;
+        for (size_t i = 0; i < value.as.variant->len; i++) {
+            hash = chop_mix(hash, chop_word(*chop_item_at(value.as.variant, i)));
+        }
+        return chop_mix(chop_hash_bytes(name, strlen(name)), hash);
+    }
+    default: return 0;
+    }
+}
+
+static uint64_t chop_hash_all(const chop_value *items, size_t len) {
+    uint64_t hash = CHOP_HASH_SEED;
+    for (size_t i = 0; i < len; i++) {
+        hash = chop_mix(hash, chop_word(items[i]));
+    }
+    return hash;
+}
+
+/* A copy of a value that shares nothing mutable with it, which a cache
+ * keeps so that later changes to the value do not reach it. */
+static chop_value chop_copy(chop_value value) {
+    chop_value copy = value;
+    switch (value.tag) {
+    case CHOP_LIST:
+    case CHOP_SET:
+    case CHOP_MAP:
+        copy = chop_vec_value(value.tag, value.as.vec->len);
+        for (size_t i = 0; i < value.as.vec->len; i++) {
+            copy.as.vec->items[i] = chop_copy(value.as.vec->items[i]);
+        }
+        copy.as.vec->len = value.as.vec->len;
+        return copy;
+    case CHOP_TUPLE:
+        copy = chop_tuple(value.as.tuple->len, value.as.tuple->items);
+        for (size_t i = 0; i < value.as.tuple->len; i++) {
+            copy.as.tuple->items[i] = chop_copy(value.as.tuple->items[i]);
+        }
+        return copy;
+    case CHOP_STRUCT:
+        copy.as.instance = chop_alloc(value.as.instance->type->size);
+        memcpy(copy.as.instance, value.as.instance, value.as.instance->type->size);
+        for (size_t i = 0; i < value.as.instance->type->count; i++) {
+            *chop_field_at(copy.as.instance, i) = chop_copy(*chop_field_at(value.as.instance, i));
+        }
+        return copy;
+    case CHOP_VARIANT:
+        copy.as.variant = chop_alloc(value.as.variant->size);
+        memcpy(copy.as.variant, value.as.variant, value.as.variant->size);
+        for (size_t i = 0; i < value.as.variant->len; i++) {
+            *chop_item_at(copy.as.variant, i) = chop_copy(*chop_item_at(value.as.variant, i));
+        }
+        return copy;
+    default: return copy;
+    }
+}
+
+/* Runs the code of a `@memo` fn unless its cache has a result for the
+ * arguments, and caches the result otherwise. The call it ends with is
+ * made here, as the call is running until its result is known. */
+static chop_value chop_memoized(chop_memo *memo, chop_value (*code)(chop_closure *, chop_value *),
+                                chop_closure *self, chop_value *args) {
+    uint64_t hash = chop_hash_all(args, memo->arity);
+    if (!memo->slots && !(memo->slots = calloc(memo->capacity, sizeof *memo->slots))) {
+        fputs("chop: out of memory\n", stderr);
+        exit(1);
+    }
+    chop_memo_entry *entry = &memo->slots[hash % memo->capacity];
+    if (entry->used && entry->hash == hash
+        && chop_equal_all(entry->args, memo->arity, args, memo->arity)) {
+        return chop_copy(entry->result);
+    }
+    chop_value *key = chop_alloc(memo->arity * sizeof *key);
+    for (int i = 0; i < memo->arity; i++) {
+        key[i] = chop_copy(args[i]);
+    }
+    chop_value result = code(self, args);
+    if (result.tag == CHOP_TAIL) {
+        chop_enter(chop_tail_function, chop_calls[chop_depth]);
+        result = chop_finish(result);
+        chop_leave();
+    }
+    entry = &memo->slots[hash % memo->capacity];
+    entry->used = 1;
+    entry->hash = hash;
+    entry->args = key;
+    entry->result = chop_copy(result);
+    return result;
+}
+
+static chop_value chop_print(int count, const chop_value *args, int newline) {
+    chop_buf buf = {0};
+    for (int i = 0; i < count; i++) {
+        chop_show(&buf, args[i], 0);
+    }
+    if (newline) {
+        chop_puts(&buf, "\n");
+    }
+    if (buf.len) {
+        fwrite(buf.data, 1, buf.len, stdout);
+    }
+    free(buf.data);
+    return chop_unit;
+}
+
+static chop_value chop_builtin(const char *name, int count, const chop_value *args,
+                               const chop_span *at) {
+    if (chop_named_is(name, "print") || chop_named_is(name, "println")) {
+        return chop_print(count, args, name[5] == 'l');
+    }
+    return chop_operator(name, count, args, at);
+}
+
+/* Calls the value below `count` arguments, or returns the call for the
+ * caller to make when it is a `tail` call. */
+static chop_value chop_call(chop_value callee, int count, chop_value *args, const chop_span *at,
+                            int tail) {
+    if (callee.tag == CHOP_CLOSURE) {
+        return chop_invoke(callee.as.closure->function, callee.as.closure, count, args, at, tail);
+    }
+    if (callee.tag == CHOP_BUILTIN) {
+        return chop_builtin(callee.as.name, count, args, at);
+    }
+    chop_errorf(at, "`%s` is not a function", chop_text(callee));
+    return chop_unit;
+}
+
+static chop_value chop_get_global(size_t index, const chop_span *at) {
+    chop_global *global = &chop_globals[index];
+    if (global->state == CHOP_READY) {
+        return global->value;
+    }
+    if (global->state == CHOP_INITIALIZING) {
+        chop_errorf(at, "`%s` is used while it is being initialized", global->name);
+    }
+    global->state = CHOP_INITIALIZING;
+    global->value = chop_invoke(global->init, NULL, 0, NULL, at, 0);
+    global->state = CHOP_READY;
+    return global->value;
+}
+
+static void chop_set_global(size_t index, chop_value value) {
+    chop_globals[index].state = CHOP_READY;
+    chop_globals[index].value = value;
+}
+
+/* The name methods are looked up under for a value. */
+static const char *chop_type_name(chop_value value) {
+    switch (value.tag) {
+    case CHOP_NULL: return "null";
+    case CHOP_UNIT: return "()";
+    case CHOP_INT: return "int";
+    case CHOP_FLOAT: return "float";
+    case CHOP_BOOL: return "bool";
+    case CHOP_STRING: return "string";
+    case CHOP_LIST: return "list";
+    case CHOP_SET: return "set";
+    case CHOP_MAP: return "map";
+    case CHOP_TUPLE: return "tuple";
+    case CHOP_STRUCT: return value.as.instance->type->name;
+    case CHOP_VARIANT: {
+        const char *e = chop_enum_of(value.as.variant->tag);
+        return e ? e : value.as.variant->name;
+    }
+    case CHOP_TYPE: return value.as.name;
+    default: return "function";
+    }
+}
+
+/* The method `name` of a type; later definitions win, as in the VM. */
+static const chop_function *chop_find_method(const char *owner, const char *name) {
+    for (size_t i = chop_method_count; i-- > 0;) {
+        if (strcmp(chop_methods[i].owner, owner) == 0 && strcmp(chop_methods[i].name, name) == 0) {
+            return chop_methods[i].function;
+        }
+    }
+    return NULL;
+}
+
+/* `receiver.name(args)`: a method of the receiver's type, or a function of
+ * a type named directly, as in `ArrayList.new()`. A `tail` call is returned
+ * for the caller to make, like in `chop_call`. */
+static chop_value chop_call_method(const char *name, int count, chop_value *args,
+                                   const chop_span *at, int tail) {
+    chop_value receiver = args[0];
+    if (receiver.tag == CHOP_TYPE) {
+        if (chop_named_is(name, "init")) {
+            for (size_t i = 0; i < chop_struct_count; i++) {
+                const chop_struct *type = &chop_structs[i];
+                if (strcmp(type->name, receiver.as.name) != 0) {
+                    continue;
+                }
+                if ((size_t)(count - 1) != type->count) {
+                    chop_errorf(at, "`%s.init` takes %d fields, but %d were given", type->name,
+                                (int)type->count, count - 1);
+                }
+                return chop_instance(type, args + 1);
+            }
+        }
+        const chop_function *function = chop_find_method(receiver.as.name, name);
+        if (!function) {
+            chop_errorf(at, "`%s` has no function `%s`", receiver.as.name, name);
+        }
+        return chop_invoke(function, NULL, count - 1, args + 1, at, tail);
+    }
+    const char *owner = chop_type_name(receiver);
+    const chop_function *function = chop_find_method(owner, name);
+    if (!function) {
+        chop_errorf(at, "`%s` has no method `%s`", owner, name);
+    }
+    return chop_invoke(function, NULL, count, args, at, tail);
+}
+
+/* ---- Entry ------------------------------------------------------------ */
+
+#if defined(__unix__) || defined(__APPLE__)
+#include <pthread.h>
+
+/* Every call of the program is a C call, so `main` runs on a thread with
+ * room for CHOP_MAX_DEPTH of them. */
+#define CHOP_STACK_SIZE ((size_t)1 << 30)
+
+static void *chop_thread(void *unused) {
+    (void)unused;
+    chop_entry();
+    return NULL;
+}
+
+int main(void) {
+    pthread_attr_t attributes;
+    pthread_t thread;
+    chop_setup();
+    if (pthread_attr_init(&attributes) == 0
+        && pthread_attr_setstacksize(&attributes, CHOP_STACK_SIZE) == 0
+        && pthread_create(&thread, &attributes, chop_thread, NULL) == 0) {
+        pthread_join(thread, NULL);
+    } else {
+        chop_entry();
+    }
+    fflush(stdout);
+    return 0;
+}
+#else
+int main(void) {
+    chop_setup();
+    chop_entry();
+    fflush(stdout);
+    return 0;
+}
+#endif
@@ -0,0 +1,477 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::abstract_syntax_tree::{
+    Body, Conditional, Domain, Expr, ForStatement, Function, Initialization, Line, Literal, Module,
+    Pattern, Statement, Struct, Typeclass, Value,
+};
+use crate::diagnostics::Diagnostic;
+use crate::resolver::{DefId, DefKind, Resolution};
+use crate::tokens::{Span, TokenType};
+use crate::typeck::TypeInfo;
+use crate::types::{Type, TypeVar};
+
+/// How closures and local functions capture the locals around them.
+pub struct Captures {
+    /// The locals captured by reference, should a closure capture them: the
+    /// `var`s that are assigned, so that the closure and the function see
+    /// each other's assignments, and local functions, which may call
+    /// themselves. Every other local is copied into the closure.
+    pub by_reference: HashSet<DefId>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Decides how each captured local is captured, and rejects closures that
+/// outlive a `var` they capture by reference.
+///
+/// A closure outlives the function declaring such a `var` when it is
+/// returned from it, stored in a field, or assigned to a variable declared
+/// outside it, whether directly or inside a list, tuple, or other closure.
+/// A call returns what it is passed when the callee's type lets it, as
+/// `id(() -> x)` does; without `types`, every call is taken to. Otherwise
+/// the callee is trusted not to keep what it is passed.
+pub fn analyse(module: &Module, resolution: &Resolution, types: Option<&TypeInfo>) -> Captures {
+    let mut analysis = Analysis {
+        resolution,
+        types,
+        by_reference: HashSet::new(),
+        depth: 0,
+        depths: HashMap::new(),
+        holding: HashMap::new(),
+        captured: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    for item in &module.items {
+        analysis.assignments(item);
+    }
+    for item in &module.items {
+        analysis.item(item);
+    }
+    analysis.diagnostics.sort_by_key(|d| d.span.start);
+    Captures {
+        by_reference: analysis.by_reference,
+        diagnostics: analysis.diagnostics,
+    }
+}
+
+pub fn check(module: &Module, resolution: &Resolution, types: &TypeInfo) -> Vec<Diagnostic> {
+    analyse(module, resolution, Some(types)).diagnostics
+}
+
+/// The `var`s captured by reference by the closures a value may hold.
+type Held = BTreeSet<DefId>;
+
+struct Analysis<'a> {
+    resolution: &'a Resolution,
+    types: Option<&'a TypeInfo>,
+    by_reference: HashSet<DefId>,
+    /// How many functions deep the code being analysed is.
+    depth: usize,
+    /// How many functions deep each local is declared.
+    depths: HashMap<DefId, usize>,
+    /// What each local holds, for the locals holding closures.
+    holding: HashMap<DefId, Held>,
+    /// The locals captured by each function being analysed, innermost last.
+    captured: Vec<HashSet<DefId>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis<'_> {
+    /// Finds the `var`s that are assigned and the local functions among
+    /// the locals of `item`, which are all captured by reference.
+    fn assignments(&mut self, item: &Initialization) {
+        match &item.value {
+            Some(Value::Struct(Struct(lines))) | Some(Value::Typeclass(Typeclass(lines))) => {
+                self.assigned(lines)
+            }
+            Some(Value::Function(Function {
+                body: Some(Body::Block(lines)),
+                ..
+            })) => self.assigned(lines),
+            _ => {}
+        }
+    }
+
+    fn assigned(&mut self, lines: &[Line]) {
+        for line in lines {
+            match line {
+                Line::Initialization(init) => {
+                    if matches!(init.domain, Domain::Fn | Domain::Proc) {
+                        if let Some(id) = self.resolution.declaration(init.span, &init.name.0) {
+                            self.by_reference.insert(id);
+                        }
+                    }
+                    self.assignments(init);
+                }
+                Line::Assignment(assignment) => {
+                    if let Expr::Reference(_, span) = &assignment.target {
+                        if let Some(&id) = self.resolution.references.get(span) {
+                            if self.resolution.definition(id).kind == DefKind::Var {
+                                self.by_reference.insert(id);
+                            }
+                        }
+                    }
+                }
+                Line::For(ForStatement(_, _, body, _)) => self.assigned(body),
+                Line::While(Conditional(_, then, otherwise, _))
+                | Line::If(Conditional(_, then, otherwise, _)) => {
+                    self.assigned(then);
+                    if let Some(otherwise) = otherwise {
+                        self.assigned(otherwise);
+                    }
+                }
+                Line::Statement(_)
+                | Line::MethodCall(_)
+                | Line::Return(_)
+                | Line::Break(_)
+                | Line::Continue(_) => {}
+            }
+        }
+    }
+
+    fn item(&mut self, item: &Initialization) {
+        match &item.value {
+            Some(Value::Struct(Struct(lines))) | Some(Value::Typeclass(Typeclass(lines))) => {
+                for line in lines {
+                    if let Line::Initialization(member) = line {
+                        self.item(member);
+                    }
+                }
+            }
+            Some(Value::Function(function)) => {
+                self.function(function);
+            }
+            Some(Value::Expr(expr)) => {
+                self.expr(expr);
+            }
+            _ => {}
+        }
+    }
+
+    /// Analyses a function one level deeper, returning what a closure of
+    /// it holds: the `var`s it captures by reference, and what the locals
+    /// it captures hold themselves.
+    fn function(&mut self, function: &Function) -> Held {
+        self.depth += 1;
+        self.captured.push(HashSet::new());
+        for param in &function.params {
+            self.declare_pattern(&param.pattern, param.span);
+        }
+        if let Some(guard) = &function.guard {
+            self.expr(guard);
+        }
+        match &function.body {
+            Some(Body::Expr(body)) => {
+                let held = self.expr(body);
+                self.escapes(&held, self.depth - 1, body.span());
+            }
+            Some(Body::Block(lines)) => self.lines(lines),
+            None => {}
+        }
+        let captured = self.captured.pop().expect("pushed above");
+        self.depth -= 1;
+        let mut held = Held::new();
+        for id in captured {
+            if let Some(inner) = self.holding.get(&id) {
+                held.extend(inner);
+            }
+            if self.resolution.definition(id).kind == DefKind::Var
+                && self.by_reference.contains(&id)
+            {
+                held.insert(id);
+            }
+        }
+        held
+    }
+
+    fn declare(&mut self, span: Span, name: &str) -> Option<DefId> {
+        let id = self.resolution.declaration(span, name)?;
+        self.depths.insert(id, self.depth);
+        Some(id)
+    }
+
+    fn declare_pattern(&mut self, pattern: &Pattern, span: Span) {
+        match pattern {
+            Pattern::Binding(name) => {
+                self.declare(span, &name.0);
+            }
+            Pattern::Tuple(patterns) | Pattern::Constructor(_, patterns) => {
+                for pattern in patterns {
+                    self.declare_pattern(pattern, span);
+                }
+            }
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
+
+    /// Reports the `var`s among `held` that something `depth` functions
+    /// deep outlives, once a value holding them reaches it at `span`.
+    fn escapes(&mut self, held: &Held, depth: usize, span: Span) {
+        for &id in held {
+            if self
+                .depths
+                .get(&id)
+                .is_some_and(|&declared| declared > depth)
+            {
+                let definition = self.resolution.definition(id);
+                let mut error = Diagnostic::error(
+                    format!(
+                        "this closure outlives `{}`, which it captures by reference",
+                        definition.name
+                    ),
+                    span,
+                );
+                if let Some(declared) = definition.span {
+                    error = error
+                        .with_note(declared, format!("`{}` is declared here", definition.name));
+                }
+                self.diagnostics.push(error.with_help(format!(
+                    "a `var` that is assigned is captured by reference; copy `{}` into a \
+                     `const` and capture that instead",
+                    definition.name
+                )));
+            }
+        }
+    }
+
+    fn lines(&mut self, lines: &[Line]) {
+        for line in lines {
+            match line {
+                Line::Initialization(init) => self.local(init),
+                Line::Statement(Statement { args, span, .. }) => {
+                    self.reference(*span);
+                    self.exprs(args);
+                }
+                Line::MethodCall(expr) => {
+                    self.expr(expr);
+                }
+                Line::Assignment(assignment) => {
+                    let held = self.expr(&assignment.value);
+                    match &assignment.target {
+                        Expr::Reference(_, span) => {
+                            let target = self.resolution.references.get(span).copied();
+                            match target.and_then(|id| Some((id, *self.depths.get(&id)?))) {
+                                Some((id, depth)) => {
+                                    self.escapes(&held, depth, assignment.value.span());
+                                    self.holding.entry(id).or_default().extend(held);
+                                }
+                                None => self.escapes(&held, 0, assignment.value.span()),
+                            }
+                        }
+                        target => {
+                            self.expr(target);
+                            self.escapes(&held, 0, assignment.value.span());
+                        }
+                    }
+                }
+                Line::Return(expr) => {
+                    let held = self.expr(expr);
+                    self.escapes(&held, self.depth - 1, expr.span());
+                }
+                Line::For(ForStatement(names, iterable, body, span)) => {
+                    self.expr(iterable);
+                    for name in names {
+                        self.declare(*span, &name.0);
+                    }
+                    self.lines(body);
+                }
+                Line::While(Conditional(condition, then, otherwise, _))
+                | Line::If(Conditional(condition, then, otherwise, _)) => {
+                    self.expr(condition);
+                    self.lines(then);
+                    if let Some(otherwise) = otherwise {
+                        self.lines(otherwise);
+                    }
+                }
+                Line::Break(_) | Line::Continue(_) => {}
+            }
+        }
+    }
+
+    /// A local function is declared before its body, which may call it.
+    fn local(&mut self, init: &Initialization) {
+        match &init.value {
+            Some(Value::Function(function)) => {
+                let id = self.declare(init.span, &init.name.0);
+                let held = self.function(function);
+                if let Some(id) = id {
+                    self.holding.insert(id, held);
+                }
+            }
+            Some(Value::Expr(expr)) => {
+                let held = self.expr(expr);
+                if let Some(id) = self.declare(init.span, &init.name.0) {
+                    self.holding.insert(id, held);
+                }
+            }
+            _ => self.item(init),
+        }
+    }
+
+    /// Notes a use of a local, which every function between its declaration
+    /// and the use captures.
+    fn reference(&mut self, span: Span) -> Held {
+        let Some(&id) = self.resolution.references.get(&span) else {
+            return Held::new();
+        };
+        if let Some(&declared) = self.depths.get(&id) {
+            for captured in self.captured.iter_mut().skip(declared) {
+                captured.insert(id);
+            }
+        }
+        self.holding.get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Analyses an expression, returning what its value holds.
+    fn expr(&mut self, expr: &Expr) -> Held {
+        match expr {
+            Expr::Reference(_, span) => self.reference(*span),
+            Expr::Call(_, args, span) => {
+                self.reference(*span);
+                let callee = self.resolution.references.get(span).copied();
+                self.call(callee, args)
+            }
+            Expr::Grouping(TokenType::Dot, call, _) => match call.as_ref() {
+                Expr::Call(_, args, span) => {
+                    let callee = self
+                        .types
+                        .and_then(|types| types.method_calls.get(span).copied());
+                    self.call(callee, args)
+                }
+                other => self.expr(other),
+            },
+            Expr::Grouping(_, inner, _) => self.expr(inner),
+            Expr::FieldAccess(receiver, _, _) => {
+                self.expr(receiver);
+                Held::new()
+            }
+            Expr::Sequence(elements, _) => self.union(elements),
+            Expr::Literal(literal, _) => match literal {
+                Literal::List(elements) | Literal::Set(elements) | Literal::Tuple(elements) => {
+                    self.union(elements)
+                }
+                Literal::StructInitialization(_, fields, base) => {
+                    let mut held = match base {
+                        Some(base) => self.expr(base),
+                        None => Held::new(),
+                    };
+                    for field in fields {
+                        held.extend(self.expr(&field.value));
+                    }
+                    held
+                }
+                Literal::Map(entries) => {
+                    let mut held = Held::new();
+                    for (key, value) in entries {
+                        held.extend(self.expr(key));
+                        held.extend(self.expr(value));
+                    }
+                    held
+                }
+                Literal::Closure(value) => match value.as_ref() {
+                    Value::Function(function) => self.function(function),
+                    _ => Held::new(),
+                },
+                _ => Held::new(),
+            },
+            Expr::Match(value, arms, _) => {
+                self.expr(value);
+                let mut held = Held::new();
+                for arm in arms {
+                    self.declare_pattern(&arm.pattern, arm.span);
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                    }
+                    held.extend(self.expr(&arm.body));
+                }
+                held
+            }
+        }
+    }
+
+    /// Analyses the arguments of a call to `callee`, returning what its
+    /// result may hold of theirs.
+    fn call(&mut self, callee: Option<DefId>, args: &[Expr]) -> Held {
+        let signature = callee
+            .zip(self.types)
+            .and_then(|(id, types)| types.schemes.get(&id))
+            .and_then(|scheme| match unalias(&scheme.ty) {
+                Type::Function(params, ret) => Some((params.as_slice(), ret.as_ref())),
+                _ => None,
+            });
+        let mut held = Held::new();
+        // The type a static call `Type.name(args)` starts with is not passed.
+        let skipped = match signature {
+            Some((params, _)) if params.len() + 1 == args.len() => 1,
+            _ => 0,
+        };
+        for (i, arg) in args.iter().enumerate() {
+            let arg_held = self.expr(arg);
+            let returned = match signature {
+                Some((params, ret)) if params.len() + skipped == args.len() => {
+                    i < skipped || returns(&params[i - skipped], ret)
+                }
+                _ => true,
+            };
+            if returned {
+                held.extend(arg_held);
+            }
+        }
+        held
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn union(&mut self, exprs: &[Expr]) -> Held {
+        let mut held = Held::new();
+        for expr in exprs {
+            held.extend(self.expr(expr));
+        }
+        held
+    }
+}
+
+fn unalias(ty: &Type) -> &Type {
+    match ty {
+        Type::Alias(_, _, target) => unalias(target),
+        other => other,
+    }
+}
+
+/// Whether a function whose parameter has type `param` and whose result
+/// has type `ret` may return what it is passed: a variable of the result
+/// may stand for the argument or for something inside it, or the result
+/// holds functions, which may close over a function it is passed.
+fn returns(param: &Type, ret: &Type) -> bool {
+    let mut held = Vec::new();
+    let passes_function = holds(param, &mut held);
+    let mut vars = Vec::new();
+    ret.free_vars(&mut vars);
+    held.iter().any(|var| vars.contains(var)) || (passes_function && holds(ret, &mut Vec::new()))
+}
+
+/// Collects the variables of `ty` its values hold themselves, rather than
+/// take or return as functions, and tells whether they hold functions.
+fn holds(ty: &Type, vars: &mut Vec<TypeVar>) -> bool {
+    match ty {
+        Type::Var(var) => {
+            vars.push(*var);
+            false
+        }
+        Type::Named(_, args) | Type::Tuple(args) => {
+            // Every argument is visited for its variables.
+            let mut found = false;
+            for arg in args {
+                found |= holds(arg, vars);
+            }
+            found
+        }
+        Type::List(t) | Type::Set(t) => holds(t, vars),
+        Type::Map(k, v) => holds(k, vars) | holds(v, vars),
+        Type::Function(..) => true,
+        Type::Alias(_, _, target) => holds(target, vars),
+    }
+}
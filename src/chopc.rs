@@ -0,0 +1,907 @@
+use crate::bytecode::{
+    Capture, Constant, Function, Global, Memo, Method, Op, Pattern, Program, StructInfo,
+};
+use crate::tokens::{Position, Span};
+
+const MAGIC: &[u8; 6] = b"chopc\0";
+
+/// Bumped whenever the layout or the instruction set changes.
+pub const FORMAT_VERSION: u16 = 4;
+
+/// Where the checksummed part of a file starts.
+const BODY: usize = MAGIC.len() + 2 + 8;
+
+/// Nesting limit for patterns, so a damaged file can't overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+/// Serializes `program` in the `.chopc` format, recording `source` as the
+/// path it was compiled from.
+///
+/// All integers are little-endian, and strings are a `u32` byte length
+/// followed by UTF-8. A file is laid out as
+///
+/// ```text
+/// magic      b"chopc\0"
+/// version    u16, FORMAT_VERSION
+/// checksum   u64, FNV-1a of every byte after it
+/// source     string, the path of the program's source
+/// main       u32
+/// constants  u32 count, then a tag byte and payload each
+/// structs    u32 count, then a name and a u32 count of field names each
+/// variants   u32 count, then a variant and enum name each
+/// globals    u32 count, then a name and initializing function each
+/// methods    u32 count, then an owner, name and function each
+/// functions  u32 count, then for each: name, arity u8, slots u16, the
+///            u32 capacity of a `@memo` fn's cache and the span of its
+///            `@memo` or 0, captures, patterns, code and the debug line
+///            table
+/// ```
+///
+/// The line table has an entry for every instruction whose source span
+/// differs from the one before it: the instruction's offset followed by the
+/// span. A span is its start and end, each a `u32` line and `u16` column.
+///
+/// Loading checks the checksum, every index the code holds, how each slot
+/// is used and how deep the stack is at each instruction, so that a damaged
+/// file is rejected rather than crashing the virtual machine.
+pub fn write(program: &Program, source: &str) -> Vec<u8> {
+    let mut body = Writer(Vec::new());
+    body.string(source);
+    body.u32(program.main);
+    body.u32(program.constants.len() as u32);
+    for constant in &program.constants {
+        body.constant(constant);
+    }
+    body.u32(program.structs.len() as u32);
+    for info in &program.structs {
+        body.string(&info.name);
+        body.u32(info.fields.len() as u32);
+        for field in &info.fields {
+            body.string(field);
+        }
+    }
+    body.u32(program.variants.len() as u32);
+    for (variant, e) in &program.variants {
+        body.string(variant);
+        body.string(e);
+    }
+    body.u32(program.globals.len() as u32);
+    for global in &program.globals {
+        body.string(&global.name);
+        body.u32(global.init);
+    }
+    body.u32(program.methods.len() as u32);
+    for method in &program.methods {
+        body.string(&method.owner);
+        body.string(&method.name);
+        body.u32(method.function);
+    }
+    body.u32(program.functions.len() as u32);
+    for function in &program.functions {
+        body.function(function);
+    }
+
+    let mut file = MAGIC.to_vec();
+    file.extend(FORMAT_VERSION.to_le_bytes());
+    file.extend(checksum(&body.0).to_le_bytes());
+    file.extend(body.0);
+    file
+}
+
+/// Loads a program, returning it with the path of its source.
+pub fn read(bytes: &[u8]) -> Result<(Program, String), String> {
+    if bytes.len() < BODY || &bytes[..MAGIC.len()] != MAGIC {
+        return Err("not a compiled chop program".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[6], bytes[7]]);
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "compiled for bytecode format {}, but this chop reads format {}",
+            version, FORMAT_VERSION
+        ));
+    }
+    let expected = u64::from_le_bytes(bytes[8..BODY].try_into().expect("eight bytes"));
+    if checksum(&bytes[BODY..]) != expected {
+        return Err("the file is corrupted: its checksum does not match".to_string());
+    }
+
+    let mut reader = Reader {
+        bytes: &bytes[BODY..],
+        at: 0,
+    };
+    let source = reader.string()?;
+    let program = reader.program()?;
+    if reader.at != reader.bytes.len() {
+        return Err(format!(
+            "the file is corrupted: {} bytes follow the program",
+            reader.bytes.len() - reader.at
+        ));
+    }
+    validate(&program).map_err(|e| format!("the file is corrupted: {}", e))?;
+    Ok((program, source))
+}
+
+/// 64-bit FNV-1a.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend(value.as_bytes());
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::Null => self.u8(0),
+            Constant::Unit => self.u8(1),
+            Constant::Int(i) => {
+                self.u8(2);
+                self.0.extend(i.to_le_bytes());
+            }
+            Constant::Float(x) => {
+                self.u8(3);
+                self.0.extend(x.to_bits().to_le_bytes());
+            }
+            Constant::Bool(b) => {
+                self.u8(4);
+                self.u8(*b as u8);
+            }
+            Constant::String(s) => {
+                self.u8(5);
+                self.string(s);
+            }
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.string(&function.name);
+        self.u8(function.arity);
+        self.u16(function.slots);
+        match function.memo {
+            Some(memo) => {
+                self.u32(memo.capacity);
+                self.span(memo.span);
+            }
+            None => self.u32(0),
+        }
+        self.u32(function.captures.len() as u32);
+        for capture in &function.captures {
+            match capture {
+                Capture::Local(slot) => {
+                    self.u8(0);
+                    self.u16(*slot);
+                }
+                Capture::Enclosing(index) => {
+                    self.u8(1);
+                    self.u16(*index);
+                }
+                Capture::Value(slot) => {
+                    self.u8(2);
+                    self.u16(*slot);
+                }
+            }
+        }
+        self.u32(function.patterns.len() as u32);
+        for pattern in &function.patterns {
+            self.pattern(pattern);
+        }
+        self.u32(function.code.len() as u32);
+        for op in &function.code {
+            self.op(*op);
+        }
+
+        let mut lines = Vec::new();
+        for (offset, span) in function.spans.iter().enumerate() {
+            if offset == 0 || function.spans[offset - 1] != *span {
+                lines.push((offset as u32, *span));
+            }
+        }
+        self.u32(lines.len() as u32);
+        for (offset, span) in lines {
+            self.u32(offset);
+            self.span(span);
+        }
+    }
+
+    fn span(&mut self, span: Span) {
+        for position in [span.start, span.end] {
+            self.u32(position.0);
+            self.u16(position.1);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => self.u8(0),
+            Pattern::Bind(slot) => {
+                self.u8(1);
+                self.u16(*slot);
+            }
+            Pattern::Cell(slot) => {
+                self.u8(2);
+                self.u16(*slot);
+            }
+            Pattern::Constant(index) => {
+                self.u8(3);
+                self.u32(*index);
+            }
+            Pattern::Tuple(patterns) => {
+                self.u8(4);
+                self.patterns(patterns);
+            }
+            Pattern::Variant(name, patterns) => {
+                self.u8(5);
+                self.u32(*name);
+                self.patterns(patterns);
+            }
+        }
+    }
+
+    fn patterns(&mut self, patterns: &[Pattern]) {
+        self.u32(patterns.len() as u32);
+        for pattern in patterns {
+            self.pattern(pattern);
+        }
+    }
+
+    /// An opcode byte followed by the operands, each at its own width.
+    fn op(&mut self, op: Op) {
+        let (code, operands): (u8, &[u32]) = match op {
+            Op::Constant(a) => (0, &[a]),
+            Op::Pop => (1, &[]),
+            Op::Dup => (2, &[]),
+            Op::Local(a) => (3, &[a as u32]),
+            Op::SetLocal(a) => (4, &[a as u32]),
+            Op::Cell(a) => (5, &[a as u32]),
+            Op::SetCell(a) => (6, &[a as u32]),
+            Op::NewCell(a) => (7, &[a as u32]),
+            Op::Capture(a) => (8, &[a as u32]),
+            Op::SetCapture(a) => (9, &[a as u32]),
+            Op::Global(a) => (10, &[a]),
+            Op::SetGlobal(a) => (11, &[a]),
+            Op::Function(a) => (12, &[a]),
+            Op::Closure(a) => (13, &[a]),
+            Op::Builtin(a) => (14, &[a]),
+            Op::Type(a) => (15, &[a]),
+            Op::Variant(a, b) => (16, &[a, b as u32]),
+            Op::Call(a) => (17, &[a as u32]),
+            Op::CallFunction(a, b) => (18, &[a, b as u32]),
+            Op::CallBuiltin(a, b) => (19, &[a, b as u32]),
+            Op::CallMethod(a, b) => (20, &[a, b as u32]),
+            Op::Return => (21, &[]),
+            Op::Jump(a) => (22, &[a]),
+            Op::JumpIfFalse(a) => (23, &[a]),
+            Op::Add => (24, &[]),
+            Op::Subtract => (25, &[]),
+            Op::Multiply => (26, &[]),
+            Op::Divide => (27, &[]),
+            Op::Remainder => (28, &[]),
+            Op::Negate => (29, &[]),
+            Op::Not => (30, &[]),
+            Op::Equal => (31, &[]),
+            Op::NotEqual => (32, &[]),
+            Op::Less => (33, &[]),
+            Op::LessEqual => (34, &[]),
+            Op::Greater => (35, &[]),
+            Op::GreaterEqual => (36, &[]),
+            Op::List(a) => (37, &[a]),
+            Op::Set(a) => (38, &[a]),
+            Op::Map(a) => (39, &[a]),
+            Op::Tuple(a) => (40, &[a]),
+            Op::Struct(a, b) => (41, &[a, b]),
+            Op::Field(a) => (42, &[a]),
+            Op::SetField(a) => (43, &[a]),
+            Op::Iterate(a) => (44, &[a as u32]),
+            Op::Next(a, b) => (45, &[a as u32, b]),
+            Op::Unpack(a) => (46, &[a as u32]),
+            Op::Match(a, b) => (47, &[a, b]),
+            Op::NoClause => (48, &[]),
+            Op::NoArm(a) => (49, &[a as u32]),
+            Op::Fail(a) => (50, &[a]),
+        };
+        self.u8(code);
+        for (operand, width) in operands.iter().zip(widths(code)) {
+            match width {
+                Width::U8 => self.u8(*operand as u8),
+                Width::U16 => self.u16(*operand as u16),
+                Width::U32 => self.u32(*operand),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Width {
+    U8,
+    U16,
+    U32,
+}
+
+/// The widths of the operands of each opcode.
+fn widths(code: u8) -> &'static [Width] {
+    use Width::*;
+    match code {
+        1 | 2 | 21 | 24..=36 | 48 => &[],
+        3..=9 | 49 => &[U16],
+        17 | 44 | 46 => &[U8],
+        16 | 18..=20 => &[U32, U8],
+        41 | 47 => &[U32, U32],
+        45 => &[U16, U32],
+        _ => &[U32],
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], String> {
+        let end = self
+            .at
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err("the file is corrupted: it ends too early".to_string());
+        };
+        let bytes = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(
+            self.take(2)?.try_into().expect("two bytes"),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().expect("four bytes"),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(
+            self.take(8)?.try_into().expect("eight bytes"),
+        ))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| "the file is corrupted: a string is not UTF-8".to_string())
+    }
+
+    /// A count of items, each taking at least one byte, so that a damaged
+    /// count cannot make the reader allocate more than the file holds.
+    fn count(&mut self) -> Result<usize, String> {
+        let count = self.u32()? as usize;
+        if count > self.bytes.len() - self.at {
+            return Err("the file is corrupted: it ends too early".to_string());
+        }
+        Ok(count)
+    }
+
+    fn list<T>(&mut self, item: impl Fn(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let count = self.count()?;
+        (0..count).map(|_| item(self)).collect()
+    }
+
+    fn program(&mut self) -> Result<Program, String> {
+        let main = self.u32()?;
+        let constants = self.list(Self::constant)?;
+        let structs = self.list(|r| {
+            Ok(StructInfo {
+                name: r.string()?,
+                fields: r.list(Self::string)?,
+            })
+        })?;
+        let variants = self.list(|r| Ok((r.string()?, r.string()?)))?;
+        let globals = self.list(|r| {
+            Ok(Global {
+                name: r.string()?,
+                init: r.u32()?,
+            })
+        })?;
+        let methods = self.list(|r| {
+            Ok(Method {
+                owner: r.string()?,
+                name: r.string()?,
+                function: r.u32()?,
+            })
+        })?;
+        let functions = self.list(Self::function)?;
+        Ok(Program {
+            constants,
+            functions,
+            globals,
+            structs,
+            methods,
+            variants,
+            main,
+        })
+    }
+
+    fn constant(&mut self) -> Result<Constant, String> {
+        Ok(match self.u8()? {
+            0 => Constant::Null,
+            1 => Constant::Unit,
+            2 => Constant::Int(self.u64()? as i64),
+            3 => Constant::Float(f64::from_bits(self.u64()?)),
+            4 => Constant::Bool(self.u8()? != 0),
+            5 => Constant::String(self.string()?),
+            tag => {
+                return Err(format!(
+                    "the file is corrupted: unknown constant tag {}",
+                    tag
+                ))
+            }
+        })
+    }
+
+    fn function(&mut self) -> Result<Function, String> {
+        let name = self.string()?;
+        let arity = self.u8()?;
+        let slots = self.u16()?;
+        let memo = match self.u32()? {
+            0 => None,
+            capacity => Some(Memo {
+                capacity,
+                span: self.span()?,
+            }),
+        };
+        let captures = self.list(|r| match r.u8()? {
+            0 => Ok(Capture::Local(r.u16()?)),
+            1 => Ok(Capture::Enclosing(r.u16()?)),
+            2 => Ok(Capture::Value(r.u16()?)),
+            tag => Err(format!(
+                "the file is corrupted: unknown capture tag {}",
+                tag
+            )),
+        })?;
+        let patterns = self.list(|r| r.pattern(0))?;
+        let code = self.list(Self::op)?;
+        let lines = self.list(|r| {
+            let offset = r.u32()? as usize;
+            Ok((offset, r.span()?))
+        })?;
+
+        // Every instruction takes the span of the last entry at or before it.
+        let mut spans = Vec::with_capacity(code.len());
+        for (i, (offset, span)) in lines.iter().enumerate() {
+            let next = lines.get(i + 1).map_or(code.len(), |(next, _)| *next);
+            if *offset != spans.len() || next <= *offset || next > code.len() {
+                return Err(format!(
+                    "the file is corrupted: the line table of `{}` is out of order",
+                    name
+                ));
+            }
+            spans.resize(next, *span);
+        }
+        if spans.len() != code.len() {
+            return Err(format!(
+                "the file is corrupted: the line table of `{}` does not cover its code",
+                name
+            ));
+        }
+        Ok(Function {
+            name,
+            arity,
+            slots,
+            captures,
+            code,
+            spans,
+            patterns,
+            memo,
+        })
+    }
+
+    fn span(&mut self) -> Result<Span, String> {
+        let start = Position(self.u32()?, self.u16()?);
+        let end = Position(self.u32()?, self.u16()?);
+        Ok(Span::new(start, end))
+    }
+
+    fn pattern(&mut self, depth: usize) -> Result<Pattern, String> {
+        if depth > MAX_DEPTH {
+            return Err("the file is corrupted: a pattern nests too deeply".to_string());
+        }
+        let nested = |r: &mut Self| r.pattern(depth + 1);
+        Ok(match self.u8()? {
+            0 => Pattern::Wildcard,
+            1 => Pattern::Bind(self.u16()?),
+            2 => Pattern::Cell(self.u16()?),
+            3 => Pattern::Constant(self.u32()?),
+            4 => Pattern::Tuple(self.list(nested)?),
+            5 => Pattern::Variant(self.u32()?, self.list(nested)?),
+            tag => {
+                return Err(format!(
+                    "the file is corrupted: unknown pattern tag {}",
+                    tag
+                ))
+            }
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, String> {
+        let code = self.u8()?;
+        let mut operands = [0u32; 2];
+        for (operand, width) in operands.iter_mut().zip(widths(code)) {
+            *operand = match width {
+                Width::U8 => self.u8()? as u32,
+                Width::U16 => self.u16()? as u32,
+                Width::U32 => self.u32()?,
+            };
+        }
+        let [a, b] = operands;
+        let (a16, a8, b8) = (a as u16, a as u8, b as u8);
+        Ok(match code {
+            0 => Op::Constant(a),
+            1 => Op::Pop,
+            2 => Op::Dup,
+            3 => Op::Local(a16),
+            4 => Op::SetLocal(a16),
+            5 => Op::Cell(a16),
+            6 => Op::SetCell(a16),
+            7 => Op::NewCell(a16),
+            8 => Op::Capture(a16),
+            9 => Op::SetCapture(a16),
+            10 => Op::Global(a),
+            11 => Op::SetGlobal(a),
+            12 => Op::Function(a),
+            13 => Op::Closure(a),
+            14 => Op::Builtin(a),
+            15 => Op::Type(a),
+            16 => Op::Variant(a, b8),
+            17 => Op::Call(a8),
+            18 => Op::CallFunction(a, b8),
+            19 => Op::CallBuiltin(a, b8),
+            20 => Op::CallMethod(a, b8),
+            21 => Op::Return,
+            22 => Op::Jump(a),
+            23 => Op::JumpIfFalse(a),
+            24 => Op::Add,
+            25 => Op::Subtract,
+            26 => Op::Multiply,
+            27 => Op::Divide,
+            28 => Op::Remainder,
+            29 => Op::Negate,
+            30 => Op::Not,
+            31 => Op::Equal,
+            32 => Op::NotEqual,
+            33 => Op::Less,
+            34 => Op::LessEqual,
+            35 => Op::Greater,
+            36 => Op::GreaterEqual,
+            37 => Op::List(a),
+            38 => Op::Set(a),
+            39 => Op::Map(a),
+            40 => Op::Tuple(a),
+            41 => Op::Struct(a, b),
+            42 => Op::Field(a),
+            43 => Op::SetField(a),
+            44 => Op::Iterate(a8),
+            45 => Op::Next(a16, b),
+            46 => Op::Unpack(a8),
+            47 => Op::Match(a, b),
+            48 => Op::NoClause,
+            49 => Op::NoArm(a16),
+            50 => Op::Fail(a),
+            code => return Err(format!("the file is corrupted: unknown opcode {}", code)),
+        })
+    }
+}
+
+/// Checks that every index in the program points at something, so that
+/// running it cannot read past the end of a table.
+fn validate(program: &Program) -> Result<(), String> {
+    let functions = program.functions.len();
+    // Only `Closure` creates a function with captures; anything calling a
+    // function directly leaves it without them.
+    let direct = |index: u32, what: &str| match program.functions.get(index as usize) {
+        None => Err(format!(
+            "{} refers to function {} of {}",
+            what, index, functions
+        )),
+        Some(f) if !f.captures.is_empty() => {
+            Err(format!("{} calls `{}` without its captures", what, f.name))
+        }
+        Some(_) => Ok(()),
+    };
+    direct(program.main, "`main`")?;
+    for global in &program.globals {
+        direct(global.init, &format!("the global `{}`", global.name))?;
+    }
+    for method in &program.methods {
+        direct(method.function, &format!("the method `{}`", method.name))?;
+    }
+    for function in &program.functions {
+        let context = format!("`{}`", function.name);
+        let check = |ok: bool, what: &str| {
+            if ok {
+                Ok(())
+            } else {
+                Err(format!("{} has an out of range {}", context, what))
+            }
+        };
+        let slot = |slot: u16| check(slot < function.slots, "slot");
+        let constant = |index: u32| check((index as usize) < program.constants.len(), "constant");
+        let name = |index: u32| {
+            check(
+                matches!(
+                    program.constants.get(index as usize),
+                    Some(Constant::String(_))
+                ),
+                "name",
+            )
+        };
+        let jump = |target: u32| check((target as usize) < function.code.len(), "jump");
+
+        check(function.arity as u16 <= function.slots, "arity")?;
+        let ends = matches!(
+            function.code.last(),
+            Some(Op::Return | Op::Jump(_) | Op::NoClause | Op::NoArm(_) | Op::Fail(_))
+        );
+        check(ends, "end of code")?;
+        for pattern in &function.patterns {
+            validate_pattern(pattern, &slot, &constant, &name)?;
+        }
+        for op in &function.code {
+            match *op {
+                Op::Constant(index) => constant(index)?,
+                Op::Local(s) | Op::SetLocal(s) | Op::Cell(s) | Op::SetCell(s) | Op::NewCell(s) => {
+                    slot(s)?
+                }
+                Op::NoArm(s) => slot(s)?,
+                Op::Capture(index) | Op::SetCapture(index) => {
+                    check((index as usize) < function.captures.len(), "capture")?
+                }
+                Op::Global(index) | Op::SetGlobal(index) => {
+                    check((index as usize) < program.globals.len(), "global")?
+                }
+                Op::Function(index) | Op::CallFunction(index, _) => direct(index, &context)?,
+                Op::Closure(index) => {
+                    let Some(closure) = program.functions.get(index as usize) else {
+                        return check(false, "function");
+                    };
+                    for capture in &closure.captures {
+                        match *capture {
+                            Capture::Local(s) | Capture::Value(s) => slot(s)?,
+                            Capture::Enclosing(index) => {
+                                check((index as usize) < function.captures.len(), "capture")?
+                            }
+                        }
+                    }
+                }
+                Op::Builtin(index)
+                | Op::Type(index)
+                | Op::Variant(index, _)
+                | Op::CallBuiltin(index, _)
+                | Op::Field(index)
+                | Op::SetField(index)
+                | Op::Fail(index) => name(index)?,
+                Op::CallMethod(index, count) => {
+                    name(index)?;
+                    check(count > 0, "method call")?
+                }
+                Op::Struct(index, count) => check(
+                    program
+                        .structs
+                        .get(index as usize)
+                        .is_some_and(|info| info.fields.len() == count as usize),
+                    "struct",
+                )?,
+                Op::Jump(target) | Op::JumpIfFalse(target) => jump(target)?,
+                Op::Next(s, target) => {
+                    slot(s)?;
+                    jump(target)?
+                }
+                Op::Match(pattern, target) => {
+                    check((pattern as usize) < function.patterns.len(), "pattern")?;
+                    jump(target)?
+                }
+                _ => {}
+            }
+        }
+        validate_slots(program, function)?;
+        validate_stack(function)?;
+    }
+    Ok(())
+}
+
+/// Checks that each slot of `function` either holds the box of a variable
+/// that closures share or holds its value directly, so that a box is never
+/// pushed on the stack and a plain value is never read as a box. The
+/// arguments arrive as plain values.
+fn validate_slots(program: &Program, function: &Function) -> Result<(), String> {
+    fn pattern_slots(pattern: &Pattern, boxed: &mut [bool], direct: &mut [bool]) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Constant(_) => {}
+            Pattern::Bind(s) => direct[*s as usize] = true,
+            Pattern::Cell(s) => boxed[*s as usize] = true,
+            Pattern::Tuple(patterns) | Pattern::Variant(_, patterns) => {
+                for pattern in patterns {
+                    pattern_slots(pattern, boxed, direct);
+                }
+            }
+        }
+    }
+
+    let mut boxed = vec![false; function.slots as usize];
+    let mut direct = vec![false; function.slots as usize];
+    direct[..function.arity as usize].fill(true);
+    for pattern in &function.patterns {
+        pattern_slots(pattern, &mut boxed, &mut direct);
+    }
+    for op in &function.code {
+        match *op {
+            Op::Cell(s) | Op::SetCell(s) | Op::NewCell(s) => boxed[s as usize] = true,
+            Op::Local(s) | Op::SetLocal(s) | Op::Next(s, _) | Op::NoArm(s) => {
+                direct[s as usize] = true
+            }
+            Op::Closure(index) => {
+                for capture in &program.functions[index as usize].captures {
+                    match *capture {
+                        Capture::Local(s) => boxed[s as usize] = true,
+                        Capture::Value(s) => direct[s as usize] = true,
+                        Capture::Enclosing(_) => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    match (0..boxed.len()).find(|&s| boxed[s] && direct[s]) {
+        Some(s) => Err(format!(
+            "`{}` uses slot {} both for a shared variable and a plain one",
+            function.name, s
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Follows every path through `function`, checking that each instruction
+/// finds the values it takes on the stack and that every path reaches an
+/// instruction with as many values as the others, so that running the code
+/// never pops into the function's slots or past the end of its code.
+fn validate_stack(function: &Function) -> Result<(), String> {
+    let mut depths: Vec<Option<usize>> = vec![None; function.code.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((ip, depth)) = pending.pop() {
+        let Some(&op) = function.code.get(ip) else {
+            return Err(format!(
+                "`{}` runs past its last instruction",
+                function.name
+            ));
+        };
+        match depths[ip] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => {
+                return Err(format!(
+                    "`{}` reaches instruction {} with different stack depths",
+                    function.name, ip
+                ))
+            }
+            None => depths[ip] = Some(depth),
+        }
+        let (pops, pushes) = stack_effect(op);
+        let Some(rest) = depth.checked_sub(pops) else {
+            return Err(format!(
+                "instruction {} of `{}` takes more values than the stack holds",
+                ip, function.name
+            ));
+        };
+        match op {
+            Op::Return | Op::NoClause | Op::NoArm(_) | Op::Fail(_) => {}
+            Op::Jump(target) => pending.push((target as usize, rest)),
+            Op::JumpIfFalse(target) | Op::Match(_, target) => {
+                pending.push((target as usize, rest));
+                pending.push((ip + 1, rest));
+            }
+            // Only a value that was found is pushed.
+            Op::Next(_, target) => {
+                pending.push((target as usize, rest));
+                pending.push((ip + 1, rest + pushes));
+            }
+            _ => pending.push((ip + 1, rest + pushes)),
+        }
+    }
+    Ok(())
+}
+
+/// How many values an instruction takes from the stack, and how many it
+/// leaves there.
+fn stack_effect(op: Op) -> (usize, usize) {
+    match op {
+        Op::Constant(_)
+        | Op::Local(_)
+        | Op::Cell(_)
+        | Op::Capture(_)
+        | Op::Global(_)
+        | Op::Function(_)
+        | Op::Closure(_)
+        | Op::Builtin(_)
+        | Op::Type(_)
+        | Op::Next(..) => (0, 1),
+        Op::Dup => (1, 2),
+        Op::Pop
+        | Op::SetLocal(_)
+        | Op::SetCell(_)
+        | Op::NewCell(_)
+        | Op::SetCapture(_)
+        | Op::SetGlobal(_)
+        | Op::JumpIfFalse(_)
+        | Op::Match(..)
+        | Op::Return => (1, 0),
+        Op::Variant(_, count)
+        | Op::CallFunction(_, count)
+        | Op::CallBuiltin(_, count)
+        | Op::CallMethod(_, count) => (count as usize, 1),
+        Op::Call(count) => (count as usize + 1, 1),
+        Op::List(count) | Op::Set(count) | Op::Tuple(count) | Op::Struct(_, count) => {
+            (count as usize, 1)
+        }
+        Op::Map(count) => (2 * count as usize, 1),
+        Op::Add
+        | Op::Subtract
+        | Op::Multiply
+        | Op::Divide
+        | Op::Remainder
+        | Op::Equal
+        | Op::NotEqual
+        | Op::Less
+        | Op::LessEqual
+        | Op::Greater
+        | Op::GreaterEqual => (2, 1),
+        Op::Negate | Op::Not | Op::Field(_) | Op::Iterate(_) => (1, 1),
+        Op::SetField(_) => (2, 0),
+        Op::Unpack(count) => (1, count as usize),
+        Op::Jump(_) | Op::NoClause | Op::NoArm(_) | Op::Fail(_) => (0, 0),
+    }
+}
+
+fn validate_pattern(
+    pattern: &Pattern,
+    slot: &impl Fn(u16) -> Result<(), String>,
+    constant: &impl Fn(u32) -> Result<(), String>,
+    name: &impl Fn(u32) -> Result<(), String>,
+) -> Result<(), String> {
+    match pattern {
+        Pattern::Wildcard => Ok(()),
+        Pattern::Bind(s) | Pattern::Cell(s) => slot(*s),
+        Pattern::Constant(index) => constant(*index),
+        Pattern::Tuple(patterns) => patterns
+            .iter()
+            .try_for_each(|p| validate_pattern(p, slot, constant, name)),
+        Pattern::Variant(index, patterns) => {
+            name(*index)?;
+            patterns
+                .iter()
+                .try_for_each(|p| validate_pattern(p, slot, constant, name))
+        }
+    }
+}
@@ -0,0 +1,1017 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::abstract_syntax_tree::{Initialization, Literal, Module, Name, Pattern as AstPattern};
+use crate::bytecode::{
+    Capture, Constant, Function, Global, Memo, Method, Op, Pattern, Program, StructInfo,
+};
+use crate::captures;
+use crate::hir::{self, is_operator, owner_and_name, Arm, Collection, Expr, Item, MatchSource};
+use crate::mono::Mono;
+use crate::resolver::{inherited_defaults, DefId, DefKind, Resolution};
+use crate::tokens::Span;
+
+/// Compiles a checked module, from its desugared form `hir`, to bytecode
+/// that starts by calling `main`.
+///
+/// Every module level function, method and global gets a function of its
+/// own; closures and local functions are compiled where they are declared.
+/// Locals live in numbered slots, except those a closure captures by
+/// reference, which are boxed so the closure sees later assignments to
+/// them. A closure copies the other locals it captures. The names the
+/// desugarer made up, which start with `$`, always live in slots.
+///
+/// A function with declared type parameters also gets a function for each
+/// instance `mono` found, and the uses that `mono` resolves call those.
+pub fn compile<'a>(
+    module: &Module,
+    hir: &'a hir::Module,
+    resolution: &'a Resolution,
+    mono: &'a Mono,
+    main: &Initialization,
+) -> Program {
+    let mut compiler = Compiler {
+        resolution,
+        mono,
+        instances: HashMap::new(),
+        instance: None,
+        by_reference: captures::analyse(module, resolution, None).by_reference,
+        program: Program::default(),
+        constants: HashMap::new(),
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        structs: HashMap::new(),
+        bodies: Vec::new(),
+        captured: HashSet::new(),
+        scopes: Vec::new(),
+    };
+    compiler.collect(&hir.items);
+    for (owner, name, id) in inherited_defaults(module, resolution) {
+        if let Some(&function) = compiler.functions.get(&id) {
+            compiler.program.methods.push(Method {
+                owner,
+                name,
+                function,
+            });
+        }
+    }
+    compiler.program.main = resolution
+        .declaration(main.span, &main.name.0)
+        .and_then(|id| compiler.functions.get(&id).copied())
+        .expect("`main` is a module level proc");
+
+    // Every instance is compiled from the function it copies.
+    for (i, instance) in mono.instances.iter().enumerate() {
+        let Some(&index) = compiler.functions.get(&instance.def) else {
+            continue;
+        };
+        let Source::Function(_, function, memo, _) = &compiler.bodies[index as usize] else {
+            continue;
+        };
+        let source = Source::Function(instance.name.clone(), function, *memo, Some(i));
+        let copy = compiler.reserve(source);
+        compiler.instances.insert(i, copy);
+    }
+
+    // Bodies are compiled once every module level function has its index,
+    // so that calls between them can be direct.
+    for (index, body) in std::mem::take(&mut compiler.bodies).into_iter().enumerate() {
+        compiler.top_level(index, body);
+    }
+    compiler.program
+}
+
+/// What a module level function is compiled from, with its name.
+enum Source<'a> {
+    /// A function, with its cache when it is a `@memo` fn and the instance
+    /// it is, if it is a copy of a generic function.
+    Function(String, &'a hir::Function, Option<Memo>, Option<usize>),
+    /// The value of a global, declared at the span.
+    Global(String, &'a Expr, Span),
+}
+
+struct Compiler<'a> {
+    resolution: &'a Resolution,
+    mono: &'a Mono,
+    /// The function index of each instance of a generic function.
+    instances: HashMap<usize, u32>,
+    /// The instance whose body is being compiled.
+    instance: Option<usize>,
+    /// Locals a closure captures by reference, should one capture them.
+    by_reference: HashSet<DefId>,
+    program: Program,
+    constants: HashMap<Constant, u32>,
+    /// The function index of each module level `fn`, `proc` and method.
+    functions: HashMap<DefId, u32>,
+    /// The global index of each module and struct level `const` and `var`.
+    globals: HashMap<DefId, u32>,
+    structs: HashMap<String, u32>,
+    /// What the module level functions are compiled from, by function index.
+    bodies: Vec<Source<'a>>,
+    /// Locals some closure captures, which therefore live in boxes.
+    captured: HashSet<DefId>,
+    /// The functions being compiled, innermost last.
+    scopes: Vec<Scope>,
+}
+
+struct Scope {
+    function: Function,
+    slots: HashMap<DefId, u16>,
+    captures: HashMap<DefId, u16>,
+    loops: Vec<Loop>,
+    /// The slots of the names the desugarer made up.
+    temporaries: HashMap<String, u16>,
+    /// The `Iterate` filling each iterator slot, which learns how many
+    /// names its loop has once the loop is compiled.
+    iterates: HashMap<u16, usize>,
+}
+
+struct Loop {
+    continue_to: u32,
+    breaks: Vec<usize>,
+}
+
+/// Where the value of a name is found.
+#[derive(Clone, Copy)]
+enum Place {
+    Local(u16),
+    Cell(u16),
+    Capture(u16),
+    Global(u32),
+    Function(u32),
+    Item(DefKind),
+}
+
+impl<'a> Compiler<'a> {
+    /// Gives every function, global, struct and method its index. Methods
+    /// are named `Owner.name` after the type they belong to.
+    fn collect(&mut self, items: &'a [Item]) {
+        for item in items {
+            match item {
+                Item::Function(function) => {
+                    let (owner, name) = owner_and_name(&function.name);
+                    let Some(id) = self.resolution.declaration(function.span, name) else {
+                        continue;
+                    };
+                    let memo = function.memo.map(|memo| Memo {
+                        capacity: memo.capacity(),
+                        span: memo.span,
+                    });
+                    let source = Source::Function(name.to_string(), function, memo, None);
+                    let index = self.reserve(source);
+                    self.functions.insert(id, index);
+                    if let Some(owner) = owner {
+                        self.program.methods.push(Method {
+                            owner: owner.to_string(),
+                            name: name.to_string(),
+                            function: index,
+                        });
+                    }
+                }
+                Item::Struct { name, fields, .. } => {
+                    let index = self.program.structs.len() as u32;
+                    self.structs.entry(name.clone()).or_insert(index);
+                    self.program.structs.push(StructInfo {
+                        name: name.clone(),
+                        fields: fields.clone(),
+                    });
+                }
+                Item::Enum { name, variants, .. } => {
+                    for (variant, _) in variants {
+                        self.program.variants.push((variant.clone(), name.clone()));
+                    }
+                }
+                Item::Global {
+                    name, value, span, ..
+                } => {
+                    let (_, name) = owner_and_name(name);
+                    let Some(id) = self.resolution.declaration(*span, name) else {
+                        continue;
+                    };
+                    let init = self.reserve(Source::Global(name.to_string(), value, *span));
+                    self.globals.insert(id, self.program.globals.len() as u32);
+                    self.program.globals.push(Global {
+                        name: name.to_string(),
+                        init,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Sets aside a function index for a module level function.
+    fn reserve(&mut self, source: Source<'a>) -> u32 {
+        let index = self.program.functions.len() as u32;
+        self.program.functions.push(Function::default());
+        self.bodies.push(source);
+        index
+    }
+
+    /// Compiles a module level function. Whether a local is boxed is only
+    /// known once the closures after it are compiled, so a function that
+    /// turns out to capture new locals is compiled again.
+    fn top_level(&mut self, index: usize, source: Source<'a>) {
+        loop {
+            let functions = self.program.functions.len();
+            let captured = self.captured.len();
+            let function = match &source {
+                Source::Function(name, function, memo, instance) => {
+                    self.instance = *instance;
+                    let function = self.function(name, function);
+                    self.instance = None;
+                    Function {
+                        memo: *memo,
+                        ..function
+                    }
+                }
+                Source::Global(name, value, span) => {
+                    self.scopes.push(Scope::new(name, 0));
+                    self.expr(value);
+                    self.emit(Op::Return, *span);
+                    self.scopes.pop().expect("scope pushed above").function
+                }
+            };
+            if self.captured.len() == captured {
+                self.program.functions[index] = function;
+                return;
+            }
+            self.program.functions.truncate(functions);
+        }
+    }
+
+    /// Compiles one function. A function made from clauses matches the
+    /// arguments against the patterns and guard of each clause in turn, and
+    /// falls through to the next clause when they do not match.
+    fn function(&mut self, name: &str, function: &'a hir::Function) -> Function {
+        let arity = function.params.len();
+        self.scopes.push(Scope::new(name, arity));
+        match &function.body {
+            Expr::Match(_, arms, MatchSource::Clauses, _) => {
+                for (slot, (param, _)) in function.params.iter().enumerate() {
+                    self.scope().temporaries.insert(param.clone(), slot as u16);
+                }
+                for arm in arms {
+                    self.clause(arm, arity);
+                }
+                let span = arms.last().map_or(function.span, |arm| arm.span);
+                self.emit(Op::NoClause, span);
+            }
+            body => {
+                let mut rejections = Vec::new();
+                for (slot, (param, span)) in function.params.iter().enumerate() {
+                    if param.starts_with('$') {
+                        self.scope().temporaries.insert(param.clone(), slot as u16);
+                        continue;
+                    }
+                    let pattern = AstPattern::Binding(Name(param.clone()));
+                    rejections.extend(self.parameter(slot as u16, &pattern, *span));
+                }
+                self.expr(body);
+                self.emit(Op::Return, body.span());
+                for rejection in rejections {
+                    self.patch(rejection);
+                }
+                self.emit(Op::NoClause, function.span);
+            }
+        }
+        self.scopes.pop().expect("scope pushed above").function
+    }
+
+    /// One clause of a function, from an arm matching its arguments.
+    fn clause(&mut self, arm: &'a Arm, arity: usize) {
+        let patterns: Vec<&AstPattern> = match &arm.pattern {
+            AstPattern::Tuple(patterns) if arity != 1 => patterns.iter().collect(),
+            pattern => vec![pattern],
+        };
+        let mut rejections = Vec::new();
+        for (slot, (pattern, span)) in patterns.into_iter().zip(&arm.params).enumerate() {
+            rejections.extend(self.parameter(slot as u16, pattern, *span));
+        }
+        if let Some(guard) = &arm.guard {
+            self.expr(guard);
+            rejections.push(self.emit(Op::JumpIfFalse(0), guard.span()));
+        }
+        self.expr(&arm.body);
+        self.emit(Op::Return, arm.body.span());
+        for rejection in rejections {
+            self.patch(rejection);
+        }
+    }
+
+    /// Matches the argument in `slot` against the pattern of a parameter,
+    /// returning the jump to patch with where to go when it does not match.
+    fn parameter(&mut self, slot: u16, pattern: &AstPattern, span: Span) -> Option<usize> {
+        match pattern {
+            AstPattern::Wildcard => None,
+            // A plain parameter is used in place.
+            AstPattern::Binding(name) => match self.declaration(span, &name.0) {
+                Some(id) if !self.captured.contains(&id) => {
+                    self.scope().slots.insert(id, slot);
+                    None
+                }
+                _ => {
+                    self.emit(Op::Local(slot), span);
+                    Some(self.match_pattern(pattern, span))
+                }
+            },
+            pattern => {
+                self.emit(Op::Local(slot), span);
+                Some(self.match_pattern(pattern, span))
+            }
+        }
+    }
+
+    /// Compiles a closure or local function and emits the code creating it.
+    fn nested(&mut self, function: &'a hir::Function, span: Span) {
+        let function = self.function(&function.name, function);
+        let index = self.program.functions.len() as u32;
+        let captures = !function.captures.is_empty();
+        self.program.functions.push(function);
+        if captures {
+            self.emit(Op::Closure(index), span);
+        } else {
+            self.emit(Op::Function(index), span);
+        }
+    }
+
+    /// Compiles an expression whose value is not used.
+    fn statement(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Block(exprs, _) => {
+                for expr in exprs {
+                    self.statement(expr);
+                }
+            }
+            Expr::Let {
+                name, value, span, ..
+            } => self.local(name, value, *span),
+            Expr::Assign(target, value, span) => self.assign(target, value, *span),
+            Expr::Return(value, span) => {
+                self.expr(value);
+                self.emit(Op::Return, *span);
+            }
+            Expr::Break(span) => {
+                let jump = self.emit(Op::Jump(0), *span);
+                self.innermost_loop().breaks.push(jump);
+            }
+            Expr::Continue(span) => {
+                let target = self.innermost_loop().continue_to;
+                self.emit(Op::Jump(target), *span);
+            }
+            Expr::If(condition, then, otherwise, span) => {
+                self.expr(condition);
+                // The loop a `while` becomes leaves as soon as its
+                // condition is false.
+                if let Some(Expr::Block(exit, _)) = otherwise.as_deref() {
+                    if let [Expr::Break(_)] = exit.as_slice() {
+                        let exit = self.emit(Op::JumpIfFalse(0), condition.span());
+                        self.innermost_loop().breaks.push(exit);
+                        self.statement(then);
+                        return;
+                    }
+                }
+                let skip = self.emit(Op::JumpIfFalse(0), condition.span());
+                self.statement(then);
+                match otherwise {
+                    Some(otherwise) => {
+                        let end = self.emit(Op::Jump(0), *span);
+                        self.patch(skip);
+                        self.statement(otherwise);
+                        self.patch(end);
+                    }
+                    None => self.patch(skip),
+                }
+            }
+            Expr::Loop(body, span) => {
+                let start = self.here();
+                self.scope().loops.push(Loop {
+                    continue_to: start,
+                    breaks: Vec::new(),
+                });
+                self.statement(body);
+                self.emit(Op::Jump(start), *span);
+                let breaks = self.scope().loops.pop().expect("loop pushed above").breaks;
+                for jump in breaks {
+                    self.patch(jump);
+                }
+            }
+            Expr::Match(next, arms, MatchSource::For, span) => self.step(next, arms, *span),
+            other => {
+                self.expr(other);
+                self.emit(Op::Pop, other.span());
+            }
+        }
+    }
+
+    /// A step of the loop a `for` becomes: the next item of the iterator,
+    /// matched against the names of the loop, or leaving the loop when
+    /// there are no more.
+    fn step(&mut self, next: &'a Expr, arms: &'a [Arm], span: Span) {
+        let (Expr::Call(_, args, _), [_, arm]) = (next, arms) else {
+            return self.fail("this loop has no iterator".to_string(), span);
+        };
+        let Some(Expr::Name(iterator, _)) = args.first() else {
+            return self.fail("this loop has no iterator".to_string(), span);
+        };
+        let iterator = self.scope().temporaries[iterator];
+        let names: Vec<&Name> = match &arm.pattern {
+            AstPattern::Binding(name) => vec![name],
+            AstPattern::Tuple(patterns) => patterns
+                .iter()
+                .filter_map(|pattern| match pattern {
+                    AstPattern::Binding(name) => Some(name),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let Some(at) = self.scope().iterates.remove(&iterator) {
+            self.scope().function.code[at] = Op::Iterate(names.len() as u8);
+        }
+        let exit = self.emit(Op::Next(iterator, 0), span);
+        self.innermost_loop().breaks.push(exit);
+        if names.len() > 1 {
+            self.emit(Op::Unpack(names.len() as u8), span);
+        }
+        for name in names.iter().rev() {
+            match self.declaration(arm.span, &name.0) {
+                Some(id) => self.define(id, span),
+                None => {
+                    self.emit(Op::Pop, span);
+                }
+            }
+        }
+        self.statement(&arm.body);
+    }
+
+    fn innermost_loop(&mut self) -> &mut Loop {
+        self.scope()
+            .loops
+            .last_mut()
+            .expect("`break` and `continue` are only resolved inside loops")
+    }
+
+    /// Declares a local. A local function is in scope inside itself, so
+    /// it can recurse. A name the desugarer made up gets a slot of its
+    /// own, and the iterator of a loop is made for the number of names the
+    /// loop has once that is known.
+    fn local(&mut self, name: &str, value: &'a Expr, span: Span) {
+        if name.starts_with('$') {
+            let iterate = match value {
+                Expr::Call(callee, args, _) if callee == "$iterate" => args.first(),
+                _ => None,
+            };
+            let at = match iterate {
+                Some(iterable) => {
+                    self.expr(iterable);
+                    Some(self.emit(Op::Iterate(1), span))
+                }
+                None => {
+                    self.expr(value);
+                    None
+                }
+            };
+            let slot = self.temporary();
+            self.scope().temporaries.insert(name.to_string(), slot);
+            if let Some(at) = at {
+                self.scope().iterates.insert(slot, at);
+            }
+            self.emit(Op::SetLocal(slot), span);
+            return;
+        }
+        let Some(id) = self.declaration(span, name) else {
+            return;
+        };
+        let kind = self.resolution.definition(id).kind;
+        match value {
+            Expr::Function(function) if matches!(kind, DefKind::Fn | DefKind::Proc) => {
+                self.constant(Constant::Unit, span);
+                self.define(id, span);
+                self.nested(function, span);
+                let place = self.place(id);
+                self.store(place, name, span);
+            }
+            value => {
+                self.expr(value);
+                self.define(id, span);
+            }
+        }
+    }
+
+    /// Stores the value on the stack into a new local.
+    fn define(&mut self, id: DefId, span: Span) {
+        let slot = self.slot(id);
+        if self.captured.contains(&id) {
+            self.emit(Op::NewCell(slot), span);
+        } else {
+            self.emit(Op::SetLocal(slot), span);
+        }
+    }
+
+    fn assign(&mut self, target: &'a Expr, value: &'a Expr, span: Span) {
+        match target {
+            Expr::Field(receiver, field, _) => {
+                self.expr(receiver);
+                let field = self.name(field);
+                self.expr(value);
+                self.emit(Op::SetField(field), span);
+            }
+            Expr::Name(name, _) if name.starts_with('$') => {
+                self.expr(value);
+                let slot = self.scope().temporaries[name];
+                self.emit(Op::SetLocal(slot), span);
+            }
+            Expr::Name(name, name_span) => {
+                let Some(&id) = self.resolution.references.get(name_span) else {
+                    return self.fail(format!("`{}` is not defined", name), *name_span);
+                };
+                let place = self.place(id);
+                self.expr(value);
+                self.store(place, name, span);
+            }
+            other => self.fail(
+                "only variables and their fields can be assigned to".to_string(),
+                other.span(),
+            ),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Literal(literal, span) => {
+                let constant = scalar(literal).unwrap_or(Constant::Null);
+                self.constant(constant, *span);
+            }
+            Expr::Name(name, span) => self.reference(name, *span),
+            Expr::Call(name, args, span) => self.call(name, args, *span),
+            Expr::Method(name, args, span) => {
+                self.exprs(args);
+                let name = self.name(name);
+                self.emit(Op::CallMethod(name, args.len() as u8), *span);
+            }
+            Expr::Field(receiver, field, span) => {
+                self.expr(receiver);
+                let field = self.name(field);
+                self.emit(Op::Field(field), *span);
+            }
+            Expr::Collection(collection, elements, span) => {
+                let count = elements.len() as u32;
+                match collection {
+                    Collection::Tuple => {
+                        self.exprs(elements);
+                        self.emit(Op::Tuple(count), *span);
+                    }
+                    Collection::List => {
+                        self.exprs(elements);
+                        self.emit(Op::List(count), *span);
+                    }
+                    Collection::Set => {
+                        self.exprs(elements);
+                        self.emit(Op::Set(count), *span);
+                    }
+                    // The entries are pushed as keys and values in turn.
+                    Collection::Map => {
+                        for entry in elements {
+                            match entry {
+                                Expr::Collection(Collection::Tuple, pair, _) => self.exprs(pair),
+                                other => self.expr(other),
+                            }
+                        }
+                        self.emit(Op::Map(count), *span);
+                    }
+                }
+            }
+            Expr::Struct(name, inits, base, span) => {
+                self.structure(name, inits, base.as_deref(), *span)
+            }
+            Expr::Function(function) => self.nested(function, function.span),
+            Expr::Match(value, arms, _, span) => self.match_expr(value, arms, *span),
+            // Statements have the value `()`.
+            Expr::If(..)
+            | Expr::Block(..)
+            | Expr::Let { .. }
+            | Expr::Assign(..)
+            | Expr::Loop(..)
+            | Expr::Break(_)
+            | Expr::Continue(_)
+            | Expr::Return(..) => {
+                self.statement(expr);
+                self.constant(Constant::Unit, expr.span());
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &'a [Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    /// A struct literal. The fields go on the stack in the order they are
+    /// declared.
+    fn structure(
+        &mut self,
+        name: &str,
+        inits: &'a [(String, Expr)],
+        base: Option<&'a Expr>,
+        span: Span,
+    ) {
+        let Some(&index) = self.structs.get(name) else {
+            self.constant(Constant::Unit, span);
+            return;
+        };
+        let fields = self.program.structs[index as usize].fields.clone();
+        let in_order = inits.len() == fields.len()
+            && inits.iter().zip(&fields).all(|((init, _), f)| init == f);
+        if base.is_none() && in_order {
+            for (_, value) in inits {
+                self.expr(value);
+            }
+        } else {
+            // The base and the fields are evaluated in the order written
+            // into slots, then pushed in the order declared.
+            let base = base.map(|base| {
+                self.expr(base);
+                let slot = self.temporary();
+                self.emit(Op::SetLocal(slot), base.span());
+                slot
+            });
+            let mut slots = HashMap::new();
+            for (field, value) in inits {
+                self.expr(value);
+                let slot = self.temporary();
+                self.emit(Op::SetLocal(slot), value.span());
+                slots.insert(field.as_str(), slot);
+            }
+            for field in &fields {
+                match (slots.get(field.as_str()), base) {
+                    (Some(&slot), _) => {
+                        self.emit(Op::Local(slot), span);
+                    }
+                    (None, Some(base)) => {
+                        self.emit(Op::Local(base), span);
+                        let name = self.name(field);
+                        self.emit(Op::Field(name), span);
+                    }
+                    (None, None) => self.constant(Constant::Unit, span),
+                }
+            }
+        }
+        self.emit(Op::Struct(index, fields.len() as u32), span);
+    }
+
+    fn reference(&mut self, name: &str, span: Span) {
+        if let Some(&slot) = self.scope().temporaries.get(name) {
+            self.emit(Op::Local(slot), span);
+            return;
+        }
+        let Some(&id) = self.resolution.references.get(&span) else {
+            return self.fail(format!("`{}` is not defined", name), span);
+        };
+        let place = match self.copy(span) {
+            Some(index) => Place::Function(index),
+            None => self.place(id),
+        };
+        self.load(place, name, span);
+    }
+
+    fn call(&mut self, name: &str, args: &'a [Expr], span: Span) {
+        // The operator of a compound assignment is no name the resolver saw.
+        if is_operator(name) {
+            self.exprs(args);
+            return self.operator(name, args.len(), span);
+        }
+        let Some(&id) = self.resolution.references.get(&span) else {
+            return self.fail(format!("`{}` is not defined", name), span);
+        };
+        let count = args.len() as u8;
+        match self.resolution.definition(id).kind {
+            DefKind::BuiltinFn => match (name, args) {
+                ("and", [lhs, rhs]) => self.short_circuit(lhs, rhs, false, span),
+                ("or", [lhs, rhs]) => self.short_circuit(lhs, rhs, true, span),
+                _ => {
+                    self.exprs(args);
+                    self.operator(name, args.len(), span);
+                }
+            },
+            DefKind::BuiltinProc => {
+                self.exprs(args);
+                let name = self.name(name);
+                self.emit(Op::CallBuiltin(name, count), span);
+            }
+            DefKind::Variant | DefKind::Newtype => {
+                self.exprs(args);
+                let name = self.name(name);
+                self.emit(Op::Variant(name, count), span);
+            }
+            _ if self.resolution.class_methods.contains(&id) && !args.is_empty() => {
+                self.exprs(args);
+                let name = self.name(name);
+                self.emit(Op::CallMethod(name, count), span);
+            }
+            _ => match self
+                .copy(span)
+                .map_or_else(|| self.place(id), Place::Function)
+            {
+                Place::Function(index) => {
+                    self.exprs(args);
+                    self.emit(Op::CallFunction(index, count), span);
+                }
+                place => {
+                    self.load(place, name, span);
+                    self.exprs(args);
+                    self.emit(Op::Call(count), span);
+                }
+            },
+        }
+    }
+
+    /// `lhs and rhs` or `lhs or rhs`, which only evaluates `rhs` when `lhs`
+    /// does not already decide the result.
+    fn short_circuit(&mut self, lhs: &'a Expr, rhs: &'a Expr, or: bool, span: Span) {
+        self.expr(lhs);
+        let mut to_false = vec![self.emit(Op::JumpIfFalse(0), lhs.span())];
+        let mut to_true = Vec::new();
+        if or {
+            to_true.push(self.emit(Op::Jump(0), span));
+            self.patch(to_false.pop().expect("pushed above"));
+        }
+        self.expr(rhs);
+        to_false.push(self.emit(Op::JumpIfFalse(0), rhs.span()));
+        for jump in to_true {
+            self.patch(jump);
+        }
+        self.constant(Constant::Bool(true), span);
+        let end = self.emit(Op::Jump(0), span);
+        for jump in to_false {
+            self.patch(jump);
+        }
+        self.constant(Constant::Bool(false), span);
+        self.patch(end);
+    }
+
+    /// A builtin operator applied to the `count` values on the stack.
+    fn operator(&mut self, name: &str, count: usize, span: Span) {
+        let op = match (name, count) {
+            ("+", 2) => Op::Add,
+            ("-", 2) => Op::Subtract,
+            ("*", 2) => Op::Multiply,
+            ("/", 2) => Op::Divide,
+            ("%", 2) => Op::Remainder,
+            ("==", 2) => Op::Equal,
+            ("!=", 2) => Op::NotEqual,
+            ("<", 2) => Op::Less,
+            ("<=", 2) => Op::LessEqual,
+            (">", 2) => Op::Greater,
+            (">=", 2) => Op::GreaterEqual,
+            ("negate", 1) => Op::Negate,
+            ("not", 1) => Op::Not,
+            _ => Op::CallBuiltin(self.name(name), count as u8),
+        };
+        self.emit(op, span);
+    }
+
+    fn match_expr(&mut self, value: &'a Expr, arms: &'a [Arm], span: Span) {
+        self.expr(value);
+        let scrutinee = self.temporary();
+        self.emit(Op::SetLocal(scrutinee), span);
+        let mut ends = Vec::new();
+        for arm in arms {
+            self.emit(Op::Local(scrutinee), arm.span);
+            let mut rejections = vec![self.match_pattern(&arm.pattern, arm.span)];
+            if let Some(guard) = &arm.guard {
+                self.expr(guard);
+                rejections.push(self.emit(Op::JumpIfFalse(0), guard.span()));
+            }
+            self.expr(&arm.body);
+            ends.push(self.emit(Op::Jump(0), arm.span));
+            for rejection in rejections {
+                self.patch(rejection);
+            }
+        }
+        self.emit(Op::NoArm(scrutinee), span);
+        for end in ends {
+            self.patch(end);
+        }
+    }
+
+    /// Emits a `Match` of the value on the stack, returning the instruction
+    /// to patch with where to go when it does not match.
+    fn match_pattern(&mut self, pattern: &AstPattern, span: Span) -> usize {
+        let pattern = self.pattern(pattern, span);
+        let patterns = &mut self.scope().function.patterns;
+        patterns.push(pattern);
+        let index = patterns.len() as u32 - 1;
+        self.emit(Op::Match(index, 0), span)
+    }
+
+    fn pattern(&mut self, pattern: &AstPattern, span: Span) -> Pattern {
+        match pattern {
+            AstPattern::Wildcard => Pattern::Wildcard,
+            AstPattern::Binding(name) => match self.declaration(span, &name.0) {
+                Some(id) if self.captured.contains(&id) => Pattern::Cell(self.slot(id)),
+                Some(id) => Pattern::Bind(self.slot(id)),
+                None => Pattern::Wildcard,
+            },
+            AstPattern::Literal(literal) => {
+                let constant = scalar(literal).expect("patterns only hold scalar literals");
+                Pattern::Constant(self.intern(constant))
+            }
+            AstPattern::Tuple(patterns) => {
+                Pattern::Tuple(patterns.iter().map(|p| self.pattern(p, span)).collect())
+            }
+            AstPattern::Constructor(name, patterns) => {
+                let name = self.name(&name.0);
+                Pattern::Variant(
+                    name,
+                    patterns.iter().map(|p| self.pattern(p, span)).collect(),
+                )
+            }
+        }
+    }
+
+    /// The copy of a generic function the use at `span` calls, if `mono`
+    /// resolved it to one.
+    fn copy(&self, span: Span) -> Option<u32> {
+        let instance = self.mono.calls.get(&(self.instance, span))?;
+        self.instances.get(instance).copied()
+    }
+
+    /// Where the value of a definition is found from the current function.
+    fn place(&mut self, id: DefId) -> Place {
+        if let Some(&slot) = self.scope().slots.get(&id) {
+            return if self.captured.contains(&id) {
+                Place::Cell(slot)
+            } else {
+                Place::Local(slot)
+            };
+        }
+        if let Some(index) = self.capture(self.scopes.len() - 1, id) {
+            return Place::Capture(index);
+        }
+        if let Some(&index) = self.globals.get(&id) {
+            return Place::Global(index);
+        }
+        if let Some(&index) = self.functions.get(&id) {
+            return Place::Function(index);
+        }
+        Place::Item(self.resolution.definition(id).kind)
+    }
+
+    /// The index of a local of an enclosing function among the captures of
+    /// the function at `depth`, capturing it through every function between.
+    fn capture(&mut self, depth: usize, id: DefId) -> Option<u16> {
+        if let Some(&index) = self.scopes[depth].captures.get(&id) {
+            return Some(index);
+        }
+        if depth == 0 {
+            return None;
+        }
+        let source = match self.scopes[depth - 1].slots.get(&id) {
+            Some(&slot) if self.by_reference.contains(&id) => {
+                self.captured.insert(id);
+                Capture::Local(slot)
+            }
+            Some(&slot) => Capture::Value(slot),
+            None => Capture::Enclosing(self.capture(depth - 1, id)?),
+        };
+        let scope = &mut self.scopes[depth];
+        let index = scope.function.captures.len() as u16;
+        scope.function.captures.push(source);
+        scope.captures.insert(id, index);
+        Some(index)
+    }
+
+    fn load(&mut self, place: Place, name: &str, span: Span) {
+        let op = match place {
+            Place::Local(slot) => Op::Local(slot),
+            Place::Cell(slot) => Op::Cell(slot),
+            Place::Capture(index) => Op::Capture(index),
+            Place::Global(index) => Op::Global(index),
+            Place::Function(index) => Op::Function(index),
+            Place::Item(DefKind::Variant) => Op::Variant(self.name(name), 0),
+            Place::Item(DefKind::BuiltinFn | DefKind::BuiltinProc) => Op::Builtin(self.name(name)),
+            Place::Item(DefKind::Struct | DefKind::Enum) => Op::Type(self.name(name)),
+            Place::Item(_) => return self.fail(format!("`{}` has no value here", name), span),
+        };
+        self.emit(op, span);
+    }
+
+    /// Stores the value on the stack into a variable that already exists.
+    fn store(&mut self, place: Place, name: &str, span: Span) {
+        let op = match place {
+            Place::Local(slot) => Op::SetLocal(slot),
+            Place::Cell(slot) => Op::SetCell(slot),
+            Place::Capture(index) => Op::SetCapture(index),
+            Place::Global(index) => Op::SetGlobal(index),
+            Place::Function(_) | Place::Item(_) => {
+                return self.fail(format!("`{}` cannot be assigned to", name), span)
+            }
+        };
+        self.emit(op, span);
+    }
+
+    fn declaration(&self, span: Span, name: &str) -> Option<DefId> {
+        self.resolution.declaration(span, name)
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes
+            .last_mut()
+            .expect("code is compiled inside a function")
+    }
+
+    /// The slot of a local of the current function.
+    fn slot(&mut self, id: DefId) -> u16 {
+        if let Some(&slot) = self.scope().slots.get(&id) {
+            return slot;
+        }
+        let slot = self.temporary();
+        self.scope().slots.insert(id, slot);
+        slot
+    }
+
+    /// A slot no name refers to, for values the generated code keeps around.
+    fn temporary(&mut self) -> u16 {
+        let function = &mut self.scope().function;
+        function.slots += 1;
+        function.slots - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.scope().function.code.len() as u32
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let function = &mut self.scope().function;
+        function.code.push(op);
+        function.spans.push(span);
+        function.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        let code = &mut self.scope().function.code;
+        code[at] = match code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::Match(pattern, _) => Op::Match(pattern, target),
+            Op::Next(slot, _) => Op::Next(slot, target),
+            other => unreachable!("{:?} does not jump", other),
+        };
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) {
+        let index = self.intern(constant);
+        self.emit(Op::Constant(index), span);
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        self.intern(Constant::String(name.to_string()))
+    }
+
+    fn intern(&mut self, constant: Constant) -> u32 {
+        if let Some(&index) = self.constants.get(&constant) {
+            return index;
+        }
+        let index = self.program.constants.len() as u32;
+        self.program.constants.push(constant.clone());
+        self.constants.insert(constant, index);
+        index
+    }
+
+    fn fail(&mut self, message: String, span: Span) {
+        let message = self.intern(Constant::String(message));
+        self.emit(Op::Fail(message), span);
+    }
+}
+
+impl Scope {
+    fn new(name: &str, arity: usize) -> Self {
+        Scope {
+            function: Function {
+                name: name.to_string(),
+                arity: arity as u8,
+                slots: arity as u16,
+                ..Function::default()
+            },
+            slots: HashMap::new(),
+            captures: HashMap::new(),
+            loops: Vec::new(),
+            temporaries: HashMap::new(),
+            iterates: HashMap::new(),
+        }
+    }
+}
+
+fn scalar(literal: &Literal) -> Option<Constant> {
+    Some(match literal {
+        Literal::Null => Constant::Null,
+        Literal::Void => Constant::Unit,
+        Literal::Int(i) => Constant::Int(*i),
+        Literal::Float(x) => Constant::Float(*x),
+        Literal::Bool(b) => Constant::Bool(*b),
+        Literal::String(s) => Constant::String(s.clone()),
+        _ => return None,
+    })
+}
@@ -0,0 +1,748 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::abstract_syntax_tree::{
+    Arm, Body, Conditional, Domain, Expr, ForStatement, Function, Initialization, Line, Literal,
+    Module, Pattern, TypeExpr, Value,
+};
+use crate::diagnostics::Diagnostic;
+use crate::resolver::{DefId, DefKind, Resolution};
+use crate::tokens::{Span, TokenType};
+
+/// How many expressions one `const` may evaluate, including those in the
+/// bodies of the functions it calls.
+const MAX_STEPS: usize = 100_000;
+/// How deeply calls may nest while evaluating a `const`.
+const MAX_DEPTH: usize = 256;
+
+/// A value computed at compile time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstValue {
+    Null,
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    List(Vec<ConstValue>),
+    Set(Vec<ConstValue>),
+    Map(Vec<(ConstValue, ConstValue)>),
+    Tuple(Vec<ConstValue>),
+    Struct(String, Vec<ConstValue>),
+    Variant(String, Vec<ConstValue>),
+}
+
+/// Printed as the chop literal that produces the value.
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, values: &[ConstValue]| -> fmt::Result {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        };
+        match self {
+            ConstValue::Null => write!(f, "null"),
+            ConstValue::Unit => write!(f, "()"),
+            ConstValue::Int(i) => write!(f, "{}", i),
+            ConstValue::Float(x) => write!(f, "{:?}", x),
+            ConstValue::Bool(b) => write!(f, "{}", b),
+            ConstValue::String(s) => write!(f, "{:?}", s),
+            ConstValue::List(values) => {
+                write!(f, "[")?;
+                list(f, values)?;
+                write!(f, "]")
+            }
+            ConstValue::Set(values) => {
+                write!(f, "{{")?;
+                list(f, values)?;
+                write!(f, "}}")
+            }
+            ConstValue::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            ConstValue::Tuple(values) => {
+                write!(f, "(")?;
+                list(f, values)?;
+                write!(f, ")")
+            }
+            ConstValue::Struct(name, values) => {
+                write!(f, "{} {{ ", name)?;
+                list(f, values)?;
+                write!(f, " }}")
+            }
+            ConstValue::Variant(name, values) if values.is_empty() => write!(f, "{}", name),
+            ConstValue::Variant(name, values) => {
+                write!(f, "{}(", name)?;
+                list(f, values)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// The values of every `const` that could be evaluated at compile time.
+pub struct Constants {
+    values: HashMap<DefId, ConstValue>,
+    /// Every `const` in source order, with the name it is printed under.
+    items: Vec<(String, DefId)>,
+}
+
+impl Constants {
+    /// One `const name = value` line per evaluated `const`.
+    pub fn listing(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .filter_map(|(name, id)| {
+                self.values
+                    .get(id)
+                    .map(|value| format!("const {} = {}", name, value))
+            })
+            .collect()
+    }
+}
+
+/// Evaluates every `const` whose value is known at compile time.
+///
+/// Constants may use operators, collection literals, other constants, enum
+/// variants and calls to `fn`s, which are evaluated clause by clause with
+/// their patterns and guards. Each constant is evaluated once, and one that
+/// depends on itself is reported. Integer arithmetic is checked, so overflow
+/// and division by zero are errors here instead of at run time.
+///
+/// A `const` at module or struct level must be computable this way. One
+/// inside a `proc` may depend on parameters and variables, in which case it
+/// is simply left to run time.
+pub fn evaluate(module: &Module, resolution: &Resolution) -> (Constants, Vec<Diagnostic>) {
+    let mut evaluator = Evaluator {
+        resolution,
+        consts: HashMap::new(),
+        functions: HashMap::new(),
+        fields: HashMap::new(),
+        cache: HashMap::new(),
+        evaluating: Vec::new(),
+        frames: Vec::new(),
+        steps: 0,
+        diagnostics: Vec::new(),
+    };
+    let mut items = Vec::new();
+    let top: Vec<&Initialization> = module.items.iter().collect();
+    evaluator.collect(&top, "", false, &mut items);
+
+    let mut values = HashMap::new();
+    for (_, id) in &items {
+        let item = evaluator.consts[id];
+        match evaluator.constant(*id) {
+            Ok(value) => {
+                values.insert(*id, value);
+            }
+            Err(Stop::NotConstant(span, reason)) if !item.local => {
+                let name = &item.init.name.0;
+                evaluator.diagnostics.push(
+                    Diagnostic::error(
+                        format!("`{}` cannot be evaluated at compile time", name),
+                        span,
+                    )
+                    .with_note(item.init.span, format!("`{}` is declared here", name))
+                    .with_help(reason),
+                );
+            }
+            Err(_) => {}
+        }
+    }
+    evaluator.diagnostics.sort_by_key(|d| d.span.start);
+    (Constants { values, items }, evaluator.diagnostics)
+}
+
+#[derive(Clone, Copy)]
+struct ConstItem<'a> {
+    init: &'a Initialization,
+    /// Declared inside a `fn` or `proc` body.
+    local: bool,
+}
+
+/// Why evaluation stopped short of a value.
+#[derive(Clone)]
+enum Stop {
+    /// The expression at the span depends on something only known at run time.
+    NotConstant(Span, String),
+    /// An error that has already been reported.
+    Failed,
+}
+
+type Eval = Result<ConstValue, Stop>;
+
+struct Evaluator<'a> {
+    resolution: &'a Resolution,
+    consts: HashMap<DefId, ConstItem<'a>>,
+    functions: HashMap<DefId, Vec<&'a Function>>,
+    /// Field names of each struct, in the order struct literals list them.
+    fields: HashMap<String, Vec<String>>,
+    cache: HashMap<DefId, Result<ConstValue, Stop>>,
+    /// The constants being evaluated, innermost last, with the reference
+    /// that led to each.
+    evaluating: Vec<(DefId, Option<Span>)>,
+    /// Parameter bindings of the `fn` calls in progress.
+    frames: Vec<HashMap<DefId, ConstValue>>,
+    steps: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Evaluator<'a> {
+    /// Finds every `const` and `fn` in the module, including those nested in
+    /// structs, typeclasses and procedure bodies.
+    fn collect(
+        &mut self,
+        items: &[&'a Initialization],
+        prefix: &str,
+        local: bool,
+        out: &mut Vec<(String, DefId)>,
+    ) {
+        for item in items {
+            let id = self.resolution.declaration(item.span, &item.name.0);
+            match &item.value {
+                Some(Value::Struct(s)) => {
+                    let members = initializations(&s.0);
+                    let fields = members
+                        .iter()
+                        .filter(|m| matches!(m.domain, Domain::Var | Domain::Const))
+                        .filter(|m| m.value.is_none())
+                        .map(|m| m.name.0.clone())
+                        .collect();
+                    self.fields.insert(item.name.0.clone(), fields);
+                    let prefix = format!("{}{}.", prefix, item.name.0);
+                    self.collect(&members, &prefix, local, out);
+                }
+                Some(Value::Typeclass(t)) => {
+                    self.collect(&initializations(&t.0), prefix, local, out);
+                }
+                Some(Value::Function(function)) => {
+                    if let (Some(id), Domain::Fn) = (id, item.domain) {
+                        self.functions.entry(id).or_default().push(function);
+                    }
+                    if let Some(Body::Block(lines)) = &function.body {
+                        let prefix = format!("{}{}.", prefix, item.name.0);
+                        self.collect_lines(lines, &prefix, out);
+                    }
+                }
+                Some(Value::Expr(_)) if item.domain == Domain::Const => {
+                    if let Some(id) = id {
+                        self.consts.insert(id, ConstItem { init: item, local });
+                        out.push((format!("{}{}", prefix, item.name.0), id));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn collect_lines(&mut self, lines: &'a [Line], prefix: &str, out: &mut Vec<(String, DefId)>) {
+        for line in lines {
+            match line {
+                Line::Initialization(init) => self.collect(&[init], prefix, true, out),
+                Line::For(ForStatement(_, _, body, _)) => self.collect_lines(body, prefix, out),
+                Line::While(Conditional(_, then, otherwise, _))
+                | Line::If(Conditional(_, then, otherwise, _)) => {
+                    self.collect_lines(then, prefix, out);
+                    if let Some(otherwise) = otherwise {
+                        self.collect_lines(otherwise, prefix, out);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The value of a `const`, evaluated on first use.
+    fn constant(&mut self, id: DefId) -> Eval {
+        self.constant_from(id, None)
+    }
+
+    fn constant_from(&mut self, id: DefId, reference: Option<Span>) -> Eval {
+        if let Some(result) = self.cache.get(&id) {
+            return result.clone();
+        }
+        if let Some(start) = self.evaluating.iter().position(|(c, _)| *c == id) {
+            self.report_cycle(start, reference);
+            return Err(Stop::Failed);
+        }
+        let item = self.consts[&id];
+        let Some(Value::Expr(expr)) = &item.init.value else {
+            return Err(Stop::Failed);
+        };
+
+        if self.evaluating.is_empty() {
+            self.steps = 0;
+        }
+        self.evaluating.push((id, reference));
+        // A constant sees none of the parameters of the call that uses it.
+        let frames = std::mem::take(&mut self.frames);
+        let mut result = self.expr(expr);
+        self.frames = frames;
+        self.evaluating.pop();
+
+        if let (Ok(value), Some(annotation)) = (&result, &item.init.type_annotation.0) {
+            if let Some(error) = out_of_range(value, annotation) {
+                self.diagnostics
+                    .push(Diagnostic::error(error, item.init.span));
+                result = Err(Stop::Failed);
+            }
+        }
+        // Constants caught up in a cycle are reported once, by the first of them.
+        self.cache.entry(id).or_insert_with(|| result.clone());
+        result
+    }
+
+    fn report_cycle(&mut self, start: usize, reference: Option<Span>) {
+        let cycle: Vec<(DefId, Option<Span>)> = self.evaluating[start..].to_vec();
+        let first = self.consts[&cycle[0].0].init;
+        let mut diagnostic = Diagnostic::error(
+            format!("the value of `{}` depends on itself", first.name.0),
+            first.span,
+        );
+        // Each constant in the cycle refers to the next, and the last to the first.
+        let references = cycle[1..]
+            .iter()
+            .map(|(_, span)| *span)
+            .chain(std::iter::once(reference));
+        for (i, span) in references.enumerate() {
+            if let Some(span) = span {
+                let user = &self.consts[&cycle[i].0].init.name.0;
+                let used = &self.consts[&cycle[(i + 1) % cycle.len()].0].init.name.0;
+                diagnostic = diagnostic.with_note(span, format!("`{}` uses `{}` here", user, used));
+            }
+        }
+        self.diagnostics.push(diagnostic);
+        for (id, _) in cycle {
+            self.cache.insert(id, Err(Stop::Failed));
+        }
+    }
+
+    fn step(&mut self, span: Span) -> Result<(), Stop> {
+        self.steps += 1;
+        if self.steps <= MAX_STEPS {
+            return Ok(());
+        }
+        if self.steps == MAX_STEPS + 1 {
+            let name = self
+                .evaluating
+                .first()
+                .map_or("", |(id, _)| self.consts[id].init.name.0.as_str());
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("evaluating `{}` takes more than {} steps", name, MAX_STEPS),
+                    span,
+                )
+                .with_help("a `const` may only call functions that finish quickly".to_string()),
+            );
+        }
+        Err(Stop::Failed)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Eval {
+        self.step(expr.span())?;
+        match expr {
+            Expr::Literal(literal, span) => self.literal(literal, *span),
+            Expr::Sequence(elements, _) => Ok(ConstValue::Tuple(self.exprs(elements)?)),
+            Expr::Reference(name, span) => {
+                let Some(&id) = self.resolution.references.get(span) else {
+                    return Err(Stop::Failed);
+                };
+                if let Some(value) = self.frames.last().and_then(|frame| frame.get(&id)) {
+                    return Ok(value.clone());
+                }
+                let kind = self.resolution.definition(id).kind;
+                match kind {
+                    DefKind::Const if self.consts.contains_key(&id) => {
+                        self.constant_from(id, Some(*span))
+                    }
+                    DefKind::Variant => Ok(ConstValue::Variant(name.0.clone(), Vec::new())),
+                    _ => Err(not_constant(*span, &name.0, kind)),
+                }
+            }
+            Expr::Call(name, args, span) => {
+                let Some(&id) = self.resolution.references.get(span) else {
+                    return Err(Stop::Failed);
+                };
+                match self.resolution.definition(id).kind {
+                    DefKind::BuiltinFn => self.builtin(&name.0, args, *span),
+                    DefKind::Fn if self.functions.contains_key(&id) => {
+                        let args = self.exprs(args)?;
+                        self.call(id, &name.0, args, *span)
+                    }
+                    DefKind::Variant | DefKind::Newtype => {
+                        Ok(ConstValue::Variant(name.0.clone(), self.exprs(args)?))
+                    }
+                    kind => Err(not_constant(*span, &name.0, kind)),
+                }
+            }
+            Expr::FieldAccess(receiver, field, span) => {
+                let value = self.expr(receiver)?;
+                let index = match &value {
+                    ConstValue::Tuple(_) => field.field_name.parse::<usize>().ok(),
+                    ConstValue::Struct(name, _) => self
+                        .fields
+                        .get(name)
+                        .and_then(|fields| fields.iter().position(|f| *f == field.field_name)),
+                    _ => None,
+                };
+                match (value, index) {
+                    (ConstValue::Tuple(values) | ConstValue::Struct(_, values), Some(i))
+                        if i < values.len() =>
+                    {
+                        Ok(values[i].clone())
+                    }
+                    _ => Err(Stop::NotConstant(
+                        *span,
+                        format!("`{}` cannot be read at compile time", field.field_name),
+                    )),
+                }
+            }
+            Expr::Grouping(TokenType::LParen | TokenType::KwTail, inner, _) => self.expr(inner),
+            Expr::Grouping(TokenType::Dot, _, span) => Err(Stop::NotConstant(
+                *span,
+                "method calls are not evaluated at compile time".to_string(),
+            )),
+            Expr::Grouping(_, _, span) => Err(Stop::NotConstant(
+                *span,
+                "`impure` expressions only run at run time".to_string(),
+            )),
+            Expr::Match(value, arms, span) => {
+                let value = self.expr(value)?;
+                for arm in arms {
+                    // An arm sees the bindings of the call it is in.
+                    let mut frame = self.frames.last().cloned().unwrap_or_default();
+                    if !self.bind(&arm.pattern, &value, arm.span, &mut frame) {
+                        continue;
+                    }
+                    self.frames.push(frame);
+                    let result = self.arm(arm);
+                    self.frames.pop();
+                    if let Some(result) = result? {
+                        return Ok(result);
+                    }
+                }
+                self.diagnostics.push(Diagnostic::error(
+                    format!("no arm of this `match` matches `{}`", value),
+                    *span,
+                ));
+                Err(Stop::Failed)
+            }
+        }
+    }
+
+    /// The arm's result, or `None` when its guard rejects the value.
+    fn arm(&mut self, arm: &Arm) -> Result<Option<ConstValue>, Stop> {
+        if let Some(guard) = &arm.guard {
+            if self.expr(guard)? != ConstValue::Bool(true) {
+                return Ok(None);
+            }
+        }
+        self.expr(&arm.body).map(Some)
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Result<Vec<ConstValue>, Stop> {
+        exprs.iter().map(|e| self.expr(e)).collect()
+    }
+
+    fn literal(&mut self, literal: &Literal, span: Span) -> Eval {
+        Ok(match literal {
+            Literal::List(elements) => ConstValue::List(self.exprs(elements)?),
+            Literal::Set(elements) => {
+                let mut set: Vec<ConstValue> = Vec::new();
+                for value in self.exprs(elements)? {
+                    if !set.contains(&value) {
+                        set.push(value);
+                    }
+                }
+                ConstValue::Set(set)
+            }
+            Literal::Map(entries) => {
+                let mut map: Vec<(ConstValue, ConstValue)> = Vec::new();
+                for (key, value) in entries {
+                    let key = self.expr(key)?;
+                    let value = self.expr(value)?;
+                    match map.iter_mut().find(|(k, _)| *k == key) {
+                        Some(entry) => entry.1 = value,
+                        None => map.push((key, value)),
+                    }
+                }
+                ConstValue::Map(map)
+            }
+            Literal::Tuple(elements) => ConstValue::Tuple(self.exprs(elements)?),
+            Literal::StructInitialization(name, inits, base) => {
+                let base = match base {
+                    Some(base) => Some(self.expr(base)?),
+                    None => None,
+                };
+                let mut values = Vec::new();
+                for init in inits {
+                    values.push(self.expr(&init.value)?);
+                }
+                let declared = self.fields.get(&name.0).cloned().unwrap_or_default();
+                let mut fields = match base {
+                    Some(ConstValue::Struct(_, fields)) => fields,
+                    _ => vec![ConstValue::Null; declared.len()],
+                };
+                for (init, value) in inits.iter().zip(values) {
+                    if let Some(index) = declared.iter().position(|f| *f == init.name.0) {
+                        fields[index] = value;
+                    }
+                }
+                ConstValue::Struct(name.0.clone(), fields)
+            }
+            Literal::Closure(_) => {
+                return Err(Stop::NotConstant(
+                    span,
+                    "closures are not evaluated at compile time".to_string(),
+                ))
+            }
+            scalar => scalar_value(scalar).unwrap_or(ConstValue::Null),
+        })
+    }
+
+    /// Operators and the other builtin functions. Integer arithmetic is
+    /// checked, as its result must be the same as at run time.
+    fn builtin(&mut self, name: &str, args: &[Expr], span: Span) -> Eval {
+        if let ("and" | "or", [lhs, rhs]) = (name, args) {
+            let lhs = self.expr(lhs)?;
+            return match (name, lhs) {
+                ("and", ConstValue::Bool(false)) => Ok(ConstValue::Bool(false)),
+                ("or", ConstValue::Bool(true)) => Ok(ConstValue::Bool(true)),
+                _ => self.expr(rhs),
+            };
+        }
+        let values = self.exprs(args)?;
+        let int = |result: Option<i64>, a: i64, b: i64| match result {
+            Some(i) => Ok(ConstValue::Int(i)),
+            None => Err(format!(
+                "this `{}` overflows: `{} {} {}` does not fit in an `int`",
+                name, a, name, b
+            )),
+        };
+        let result = match (name, values.as_slice()) {
+            ("not", [ConstValue::Bool(b)]) => Ok(ConstValue::Bool(!b)),
+            ("negate", [ConstValue::Int(i)]) => i
+                .checked_neg()
+                .map(ConstValue::Int)
+                .ok_or_else(|| format!("negating `{}` overflows an `int`", i)),
+            ("negate", [ConstValue::Float(x)]) => Ok(ConstValue::Float(-x)),
+            ("/" | "%", [ConstValue::Int(_), ConstValue::Int(0)]) => {
+                Err("division by zero".to_string())
+            }
+            ("+", [ConstValue::Int(a), ConstValue::Int(b)]) => int(a.checked_add(*b), *a, *b),
+            ("-", [ConstValue::Int(a), ConstValue::Int(b)]) => int(a.checked_sub(*b), *a, *b),
+            ("*", [ConstValue::Int(a), ConstValue::Int(b)]) => int(a.checked_mul(*b), *a, *b),
+            ("/", [ConstValue::Int(a), ConstValue::Int(b)]) => int(a.checked_div(*b), *a, *b),
+            ("%", [ConstValue::Int(a), ConstValue::Int(b)]) => int(a.checked_rem(*b), *a, *b),
+            ("+", [ConstValue::Float(a), ConstValue::Float(b)]) => Ok(ConstValue::Float(a + b)),
+            ("-", [ConstValue::Float(a), ConstValue::Float(b)]) => Ok(ConstValue::Float(a - b)),
+            ("*", [ConstValue::Float(a), ConstValue::Float(b)]) => Ok(ConstValue::Float(a * b)),
+            ("/", [ConstValue::Float(a), ConstValue::Float(b)]) => Ok(ConstValue::Float(a / b)),
+            ("%", [ConstValue::Float(a), ConstValue::Float(b)]) => Ok(ConstValue::Float(a % b)),
+            ("==", [a, b]) => Ok(ConstValue::Bool(a == b)),
+            ("!=", [a, b]) => Ok(ConstValue::Bool(a != b)),
+            ("<" | "<=" | ">" | ">=", [a, b]) => match compare(a, b) {
+                Some(ordering) => Ok(ConstValue::Bool(match name {
+                    "<" => ordering.is_lt(),
+                    "<=" => ordering.is_le(),
+                    ">" => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                })),
+                None => {
+                    return Err(Stop::NotConstant(
+                        span,
+                        format!(
+                            "`{}` is not evaluated at compile time for these values",
+                            name
+                        ),
+                    ))
+                }
+            },
+            _ => {
+                return Err(Stop::NotConstant(
+                    span,
+                    format!("`{}` is not evaluated at compile time", name),
+                ))
+            }
+        };
+        result.map_err(|message| {
+            self.diagnostics.push(Diagnostic::error(message, span));
+            Stop::Failed
+        })
+    }
+
+    /// Calls a `fn` by trying its clauses in order.
+    fn call(&mut self, id: DefId, name: &str, args: Vec<ConstValue>, span: Span) -> Eval {
+        if self.frames.len() >= MAX_DEPTH {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "calls to `{}` nest more than {} deep at compile time",
+                    name, MAX_DEPTH
+                ),
+                span,
+            ));
+            return Err(Stop::Failed);
+        }
+        let clauses = self.functions[&id].clone();
+        for function in clauses {
+            if function.params.len() != args.len() {
+                continue;
+            }
+            let mut frame = HashMap::new();
+            let matched = function
+                .params
+                .iter()
+                .zip(&args)
+                .all(|(param, arg)| self.bind(&param.pattern, arg, param.span, &mut frame));
+            if !matched {
+                continue;
+            }
+            self.frames.push(frame);
+            let result = self.clause(function);
+            self.frames.pop();
+            match result? {
+                Some(value) => return Ok(value),
+                None => continue,
+            }
+        }
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        self.diagnostics.push(Diagnostic::error(
+            format!(
+                "no clause of `{}` matches the arguments ({})",
+                name,
+                args.join(", ")
+            ),
+            span,
+        ));
+        Err(Stop::Failed)
+    }
+
+    /// The clause's result, or `None` when its guard rejects the arguments.
+    fn clause(&mut self, function: &Function) -> Result<Option<ConstValue>, Stop> {
+        if let Some(guard) = &function.guard {
+            if self.expr(guard)? != ConstValue::Bool(true) {
+                return Ok(None);
+            }
+        }
+        match &function.body {
+            Some(Body::Expr(body)) => self.expr(body).map(Some),
+            _ => Err(Stop::NotConstant(
+                function.span,
+                "only `fn`s with an expression body are evaluated at compile time".to_string(),
+            )),
+        }
+    }
+
+    fn bind(
+        &self,
+        pattern: &Pattern,
+        value: &ConstValue,
+        span: Span,
+        frame: &mut HashMap<DefId, ConstValue>,
+    ) -> bool {
+        match (pattern, value) {
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Binding(name), _) => {
+                if let Some(id) = self.resolution.declaration(span, &name.0) {
+                    frame.insert(id, value.clone());
+                }
+                true
+            }
+            (Pattern::Literal(literal), _) => scalar_value(literal).as_ref() == Some(value),
+            (Pattern::Tuple(patterns), ConstValue::Tuple(values)) => {
+                patterns.len() == values.len()
+                    && patterns
+                        .iter()
+                        .zip(values)
+                        .all(|(p, v)| self.bind(p, v, span, frame))
+            }
+            (Pattern::Constructor(name, patterns), ConstValue::Variant(variant, values)) => {
+                name.0 == *variant
+                    && patterns.len() == values.len()
+                    && patterns
+                        .iter()
+                        .zip(values)
+                        .all(|(p, v)| self.bind(p, v, span, frame))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn not_constant(span: Span, name: &str, kind: DefKind) -> Stop {
+    let reason = match kind {
+        DefKind::Var => format!("`{}` is a var, whose value is only known at run time", name),
+        DefKind::Proc | DefKind::BuiltinProc => {
+            format!("`{}` is a proc, which only runs at run time", name)
+        }
+        DefKind::Param | DefKind::ForBinding | DefKind::MatchBinding => {
+            format!("`{}` is only known at run time", name)
+        }
+        kind => format!(
+            "`{}` is a {}, which is not evaluated at compile time",
+            name,
+            kind.describe()
+        ),
+    };
+    Stop::NotConstant(span, reason)
+}
+
+fn scalar_value(literal: &Literal) -> Option<ConstValue> {
+    Some(match literal {
+        Literal::Null => ConstValue::Null,
+        Literal::Void => ConstValue::Unit,
+        Literal::Int(i) => ConstValue::Int(*i),
+        Literal::Float(x) => ConstValue::Float(*x),
+        Literal::Bool(b) => ConstValue::Bool(*b),
+        Literal::String(s) => ConstValue::String(s.clone()),
+        _ => return None,
+    })
+}
+
+fn compare(a: &ConstValue, b: &ConstValue) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => Some(a.cmp(b)),
+        (ConstValue::Float(a), ConstValue::Float(b)) => a.partial_cmp(b),
+        (ConstValue::String(a), ConstValue::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// An error when an integer does not fit the sized integer type it is declared with.
+fn out_of_range(value: &ConstValue, annotation: &TypeExpr) -> Option<String> {
+    let (ConstValue::Int(i), TypeExpr::Literal(name)) = (value, annotation) else {
+        return None;
+    };
+    let (min, max) = match name.0.as_str() {
+        "i8" => (i8::MIN as i64, i8::MAX as i64),
+        "i16" => (i16::MIN as i64, i16::MAX as i64),
+        "i32" => (i32::MIN as i64, i32::MAX as i64),
+        "u8" => (0, u8::MAX as i64),
+        "u16" => (0, u16::MAX as i64),
+        "u32" => (0, u32::MAX as i64),
+        "u64" => (0, i64::MAX),
+        _ => return None,
+    };
+    (*i < min || *i > max).then(|| format!("`{}` does not fit in `{}`", i, name.0))
+}
+
+fn initializations(lines: &[Line]) -> Vec<&Initialization> {
+    lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Initialization(init) => Some(init),
+            _ => None,
+        })
+        .collect()
+}
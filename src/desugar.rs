@@ -0,0 +1,496 @@
+use crate::abstract_syntax_tree::{
+    self as ast, Assignment, Body, Conditional, Domain, ForStatement, Initialization, Line,
+    Literal, Module, Name, Pattern, Statement, Struct, TypeExpr, Typeclass, Value,
+};
+use crate::hir::{self, Arm, Collection, Expr, Item, MatchSource};
+use crate::resolver::is_instance;
+use crate::tokens::{Span, TokenType};
+
+/// Lowers a parsed module to HIR.
+///
+/// Methods are named after the type they belong to, the clauses of a `fn`
+/// are gathered into one match over its arguments, `while` and `for` become
+/// `loop`s, and compound assignments spell out their operator. Declarations
+/// without a body, such as those of a typeclass, are left out.
+pub fn desugar(module: &Module) -> hir::Module {
+    let mut desugarer = Desugarer {
+        items: Vec::new(),
+        temporaries: 0,
+    };
+    let items: Vec<&Initialization> = module.items.iter().collect();
+    desugarer.items(&items, None);
+    hir::Module {
+        items: desugarer.items,
+    }
+}
+
+struct Desugarer {
+    items: Vec<Item>,
+    /// How many names of its own the desugarer has made up so far.
+    temporaries: usize,
+}
+
+impl Desugarer {
+    /// Lowers `items`, the members of the type `owner` if there is one.
+    fn items(&mut self, items: &[&Initialization], owner: Option<&str>) {
+        let qualified = |name: &Name| match owner {
+            Some(owner) => format!("{}.{}", owner, name.0),
+            None => name.0.clone(),
+        };
+        // Clauses are gathered by name, at the position of the first one.
+        let mut clauses: Vec<(&Initialization, Vec<&ast::Function>)> = Vec::new();
+        for item in items {
+            if let Some(Value::Function(function)) = &item.value {
+                match clauses
+                    .iter_mut()
+                    .find(|(first, _)| first.name == item.name)
+                {
+                    Some((_, functions)) => functions.push(function),
+                    None => clauses.push((item, vec![function])),
+                }
+            }
+        }
+        for item in items {
+            match &item.value {
+                Some(Value::Function(_)) => {
+                    let Some(index) = clauses
+                        .iter()
+                        .position(|(first, _)| std::ptr::eq(*first, *item))
+                    else {
+                        continue;
+                    };
+                    let proc = item.domain == Domain::Proc;
+                    let function =
+                        self.function(qualified(&item.name), proc, &clauses[index].1, item.span);
+                    if let Some(mut function) = function {
+                        function.memo = item.memo;
+                        self.items.push(Item::Function(function));
+                    }
+                }
+                Some(Value::Expr(value)) => {
+                    let value = self.expr(value);
+                    self.items.push(Item::Global {
+                        name: qualified(&item.name),
+                        mutable: item.domain == Domain::Var,
+                        value,
+                        span: item.span,
+                    });
+                }
+                Some(Value::Struct(Struct(lines))) => {
+                    let members = initializations(lines);
+                    let fields = members
+                        .iter()
+                        .filter(|m| matches!(m.domain, Domain::Var | Domain::Const))
+                        .filter(|m| m.value.is_none())
+                        .map(|m| m.name.0.clone())
+                        .collect();
+                    self.items.push(Item::Struct {
+                        name: item.name.0.clone(),
+                        fields,
+                        span: item.span,
+                    });
+                    self.items(&members, Some(&item.name.0));
+                }
+                Some(Value::Enum(e)) => {
+                    let variants =
+                        e.0.iter()
+                            .map(|entry| {
+                                (
+                                    entry.0.field_name.clone(),
+                                    entry.1.as_ref().map_or(0, Vec::len),
+                                )
+                            })
+                            .collect();
+                    self.items.push(Item::Enum {
+                        name: item.name.0.clone(),
+                        variants,
+                        span: item.span,
+                    });
+                }
+                Some(Value::Typeclass(Typeclass(lines))) => {
+                    let owner = match (owner, &item.type_annotation.0) {
+                        (Some(owner), _) => Some(owner),
+                        (None, Some(TypeExpr::Literal(head))) if is_instance(item, false) => {
+                            Some(head.0.as_str())
+                        }
+                        _ => None,
+                    };
+                    self.items(&initializations(lines), owner);
+                }
+                Some(Value::Type(_)) | None => {}
+            }
+        }
+    }
+
+    /// Gathers the clauses of a function into one body. A single clause
+    /// whose parameters are all plain names keeps them; otherwise the
+    /// arguments are matched against each clause in turn.
+    fn function(
+        &mut self,
+        name: String,
+        proc: bool,
+        clauses: &[&ast::Function],
+        span: Span,
+    ) -> Option<hir::Function> {
+        let clauses: Vec<&ast::Function> = clauses
+            .iter()
+            .copied()
+            .filter(|c| c.body.is_some())
+            .collect();
+        let first = clauses.first()?;
+        if let [clause] = clauses.as_slice() {
+            let names: Option<Vec<(String, Span)>> = clause
+                .params
+                .iter()
+                .enumerate()
+                .map(|(i, param)| match &param.pattern {
+                    Pattern::Binding(name) => Some((name.0.clone(), param.span)),
+                    Pattern::Wildcard => Some((format!("${}", i), param.span)),
+                    _ => None,
+                })
+                .collect();
+            if let (Some(params), None) = (names, &clause.guard) {
+                let body = self.body(clause);
+                return Some(hir::Function {
+                    name,
+                    proc,
+                    params,
+                    body,
+                    memo: None,
+                    span,
+                });
+            }
+        }
+        let params: Vec<(String, Span)> = first
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| (format!("${}", i), param.span))
+            .collect();
+        let mut args: Vec<Expr> = params
+            .iter()
+            .map(|(param, span)| Expr::Name(param.clone(), *span))
+            .collect();
+        let scrutinee = match args.len() {
+            1 => args.pop().expect("one argument"),
+            _ => Expr::Collection(Collection::Tuple, args, span),
+        };
+        let arms = clauses
+            .iter()
+            .map(|clause| {
+                let mut patterns: Vec<Pattern> =
+                    clause.params.iter().map(|p| p.pattern.clone()).collect();
+                let pattern = match patterns.len() {
+                    1 => patterns.pop().expect("one parameter"),
+                    _ => Pattern::Tuple(patterns),
+                };
+                Arm {
+                    pattern,
+                    guard: clause.guard.as_deref().map(|guard| self.expr(guard)),
+                    body: self.body(clause),
+                    params: clause.params.iter().map(|p| p.span).collect(),
+                    span: clause.span,
+                }
+            })
+            .collect();
+        let last = clauses.last().expect("a function has a clause").span;
+        Some(hir::Function {
+            name,
+            proc,
+            params,
+            body: Expr::Match(
+                Box::new(scrutinee),
+                arms,
+                MatchSource::Clauses,
+                Span::new(first.span.start, last.end),
+            ),
+            memo: None,
+            span,
+        })
+    }
+
+    fn body(&mut self, clause: &ast::Function) -> Expr {
+        match &clause.body {
+            Some(Body::Expr(body)) => self.expr(body),
+            Some(Body::Block(lines)) => self.block(lines, clause.span),
+            None => Expr::Block(Vec::new(), clause.span),
+        }
+    }
+
+    fn block(&mut self, lines: &[Line], span: Span) -> Expr {
+        let exprs = lines.iter().filter_map(|line| self.line(line)).collect();
+        Expr::Block(exprs, span)
+    }
+
+    fn line(&mut self, line: &Line) -> Option<Expr> {
+        Some(match line {
+            Line::Initialization(init) => return self.local(init),
+            Line::Statement(Statement {
+                proc_name,
+                args,
+                span,
+            }) => Expr::Call(proc_name.0.clone(), self.exprs(args), *span),
+            Line::MethodCall(expr) => self.expr(expr),
+            Line::Return(value) => Expr::Return(Box::new(self.expr(value)), value.span()),
+            Line::Break(span) => Expr::Break(*span),
+            Line::Continue(span) => Expr::Continue(*span),
+            Line::If(Conditional(condition, then, otherwise, span)) => Expr::If(
+                Box::new(self.expr(condition)),
+                Box::new(self.block(then, *span)),
+                otherwise
+                    .as_ref()
+                    .map(|otherwise| Box::new(self.block(otherwise, *span))),
+                *span,
+            ),
+            Line::While(Conditional(condition, body, otherwise, span)) => {
+                self.while_loop(condition, body, otherwise.as_deref(), *span)
+            }
+            Line::For(ForStatement(names, iterable, body, span)) => {
+                self.for_loop(names, iterable, body, *span)
+            }
+            Line::Assignment(assignment) => self.assign(assignment),
+        })
+    }
+
+    /// `while c { .. }` becomes a loop that breaks once `c` is false. An
+    /// `else` runs after the loop unless a `break` left it, which a flag
+    /// set on the way out tells; a `break` in the `else` itself leaves the
+    /// loop around the `while`.
+    fn while_loop(
+        &mut self,
+        condition: &ast::Expr,
+        body: &[Line],
+        otherwise: Option<&[Line]>,
+        span: Span,
+    ) -> Expr {
+        let condition = Box::new(self.expr(condition));
+        let body = Box::new(self.block(body, span));
+        let Some(otherwise) = otherwise else {
+            let exit = Expr::Block(vec![Expr::Break(span)], span);
+            let step = Expr::If(condition, body, Some(Box::new(exit)), span);
+            return Expr::Loop(Box::new(Expr::Block(vec![step], span)), span);
+        };
+        let ended = format!("$ended{}", self.temporaries);
+        self.temporaries += 1;
+        let flag = |value| Expr::Literal(Literal::Bool(value), span);
+        let start = Expr::Let {
+            name: ended.clone(),
+            mutable: true,
+            value: Box::new(flag(false)),
+            span,
+        };
+        let exit = Expr::Block(
+            vec![
+                Expr::Assign(
+                    Box::new(Expr::Name(ended.clone(), span)),
+                    Box::new(flag(true)),
+                    span,
+                ),
+                Expr::Break(span),
+            ],
+            span,
+        );
+        let step = Expr::If(condition, body, Some(Box::new(exit)), span);
+        let repeat = Expr::Loop(Box::new(Expr::Block(vec![step], span)), span);
+        let after = Expr::If(
+            Box::new(Expr::Name(ended, span)),
+            Box::new(self.block(otherwise, span)),
+            None,
+            span,
+        );
+        Expr::Block(vec![start, repeat, after], span)
+    }
+
+    /// `for x in xs { .. }` becomes a loop over an iterator that matches
+    /// each item against the loop's names, until `$next` gives `$done`.
+    fn for_loop(
+        &mut self,
+        names: &[Name],
+        iterable: &ast::Expr,
+        body: &[Line],
+        span: Span,
+    ) -> Expr {
+        let iterator = format!("$iter{}", self.temporaries);
+        self.temporaries += 1;
+        let mut bindings: Vec<Pattern> = names.iter().cloned().map(Pattern::Binding).collect();
+        let pattern = match bindings.len() {
+            1 => bindings.pop().expect("one name"),
+            _ => Pattern::Tuple(bindings),
+        };
+        let next = Expr::Call(
+            "$next".to_string(),
+            vec![Expr::Name(iterator.clone(), span)],
+            span,
+        );
+        let step = Expr::Match(
+            Box::new(next),
+            vec![
+                Arm {
+                    pattern: Pattern::Constructor(Name("$done".to_string()), Vec::new()),
+                    guard: None,
+                    body: Expr::Break(span),
+                    params: Vec::new(),
+                    span,
+                },
+                Arm {
+                    pattern,
+                    guard: None,
+                    body: self.block(body, span),
+                    params: Vec::new(),
+                    span,
+                },
+            ],
+            MatchSource::For,
+            span,
+        );
+        let start = Expr::Let {
+            name: iterator,
+            mutable: false,
+            value: Box::new(Expr::Call(
+                "$iterate".to_string(),
+                vec![self.expr(iterable)],
+                iterable.span(),
+            )),
+            span,
+        };
+        let repeat = Expr::Loop(Box::new(Expr::Block(vec![step], span)), span);
+        Expr::Block(vec![start, repeat], span)
+    }
+
+    /// Declares a local `const`, `var` or function.
+    fn local(&mut self, init: &Initialization) -> Option<Expr> {
+        let value = match &init.value {
+            Some(Value::Expr(value)) => self.expr(value),
+            Some(Value::Function(function)) => {
+                let proc = init.domain == Domain::Proc;
+                Expr::Function(Box::new(self.function(
+                    init.name.0.clone(),
+                    proc,
+                    &[function],
+                    init.span,
+                )?))
+            }
+            _ => return None,
+        };
+        Some(Expr::Let {
+            name: init.name.0.clone(),
+            mutable: init.domain == Domain::Var,
+            value: Box::new(value),
+            span: init.span,
+        })
+    }
+
+    /// `target op= value` becomes `target = op(target, value)`. Targets are
+    /// names and fields of names, so reading one twice has no effects.
+    fn assign(&mut self, assignment: &Assignment) -> Expr {
+        let target = self.expr(&assignment.target);
+        let mut value = self.expr(&assignment.value);
+        if let Some(operator) = assignment.binary_operator() {
+            value = Expr::Call(
+                operator.to_string(),
+                vec![target.clone(), value],
+                assignment.span,
+            );
+        }
+        Expr::Assign(Box::new(target), Box::new(value), assignment.span)
+    }
+
+    fn expr(&mut self, expr: &ast::Expr) -> Expr {
+        match expr {
+            ast::Expr::Literal(literal, span) => self.literal(literal, *span),
+            ast::Expr::Sequence(elements, span) => {
+                Expr::Collection(Collection::Tuple, self.exprs(elements), *span)
+            }
+            ast::Expr::Call(name, args, span) => {
+                Expr::Call(name.0.clone(), self.exprs(args), *span)
+            }
+            ast::Expr::Reference(name, span) => Expr::Name(name.0.clone(), *span),
+            ast::Expr::FieldAccess(receiver, field, span) => Expr::Field(
+                Box::new(self.expr(receiver)),
+                field.field_name.clone(),
+                *span,
+            ),
+            ast::Expr::Grouping(TokenType::Dot, call, _) => match call.as_ref() {
+                ast::Expr::Call(name, args, span) if !args.is_empty() => {
+                    Expr::Method(name.0.clone(), self.exprs(args), *span)
+                }
+                other => self.expr(other),
+            },
+            ast::Expr::Grouping(_, inner, _) => self.expr(inner),
+            ast::Expr::Match(value, arms, span) => {
+                let value = self.expr(value);
+                let arms = arms
+                    .iter()
+                    .map(|arm| Arm {
+                        pattern: arm.pattern.clone(),
+                        guard: arm.guard.as_ref().map(|guard| self.expr(guard)),
+                        body: self.expr(&arm.body),
+                        params: Vec::new(),
+                        span: arm.span,
+                    })
+                    .collect();
+                Expr::Match(Box::new(value), arms, MatchSource::Match, *span)
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[ast::Expr]) -> Vec<Expr> {
+        exprs.iter().map(|e| self.expr(e)).collect()
+    }
+
+    fn literal(&mut self, literal: &Literal, span: Span) -> Expr {
+        match literal {
+            Literal::List(elements) => {
+                Expr::Collection(Collection::List, self.exprs(elements), span)
+            }
+            Literal::Set(elements) => Expr::Collection(Collection::Set, self.exprs(elements), span),
+            Literal::Tuple(elements) => {
+                Expr::Collection(Collection::Tuple, self.exprs(elements), span)
+            }
+            Literal::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let span = Span::new(key.span().start, value.span().end);
+                        Expr::Collection(
+                            Collection::Tuple,
+                            vec![self.expr(key), self.expr(value)],
+                            span,
+                        )
+                    })
+                    .collect();
+                Expr::Collection(Collection::Map, entries, span)
+            }
+            Literal::StructInitialization(name, fields, base) => Expr::Struct(
+                name.0.clone(),
+                fields
+                    .iter()
+                    .map(|field| (field.name.0.clone(), self.expr(&field.value)))
+                    .collect(),
+                base.as_ref().map(|base| Box::new(self.expr(base))),
+                span,
+            ),
+            Literal::Closure(value) => match value.as_ref() {
+                Value::Function(function) => {
+                    let proc = matches!(function.body, Some(Body::Block(_)));
+                    match self.function("closure".to_string(), proc, &[function], span) {
+                        Some(function) => Expr::Function(Box::new(function)),
+                        None => Expr::Literal(Literal::Void, span),
+                    }
+                }
+                _ => Expr::Literal(Literal::Void, span),
+            },
+            scalar => Expr::Literal(scalar.clone(), span),
+        }
+    }
+}
+
+fn initializations(lines: &[Line]) -> Vec<&Initialization> {
+    lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Initialization(init) => Some(init),
+            _ => None,
+        })
+        .collect()
+}
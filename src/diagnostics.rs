@@ -0,0 +1,98 @@
+use crate::tokens::Span;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found by one of the semantic passes, pointing into the source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<(Span, String)>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            span,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn warning(message: String, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, span)
+        }
+    }
+
+    pub fn with_note(mut self, span: Span, message: String) -> Self {
+        self.notes.push((span, message));
+        self
+    }
+
+    pub fn with_help(mut self, message: String) -> Self {
+        self.help = Some(message);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Renders the diagnostic with the offending source line underlined.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!(
+            "{}:{}: {}: {}\n",
+            path, self.span.start, severity, self.message
+        );
+        out.push_str(&snippet(source, self.span));
+        for (span, note) in &self.notes {
+            out.push_str(&format!("  note: {}:{}: {}\n", path, span.start, note));
+            out.push_str(&snippet(source, *span));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("  help: {}\n", help));
+        }
+        out
+    }
+}
+
+fn snippet(source: &str, span: Span) -> String {
+    let line_number = span.start.0 as usize;
+    let Some(line) = source.lines().nth(line_number.wrapping_sub(1)) else {
+        return String::new();
+    };
+    // Columns count characters, not bytes.
+    let width = line.chars().count();
+    let start = (span.start.1 as usize).saturating_sub(1).min(width);
+    let end = if span.end.0 == span.start.0 {
+        (span.end.1 as usize)
+            .saturating_sub(1)
+            .clamp(start + 1, width.max(start + 1))
+    } else {
+        width.max(start + 1)
+    };
+    let gutter = " ".repeat(line_number.to_string().len());
+    format!(
+        "{} |\n{} | {}\n{} | {}{}\n",
+        gutter,
+        line_number,
+        line,
+        gutter,
+        " ".repeat(start),
+        "^".repeat(end - start)
+    )
+}
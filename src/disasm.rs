@@ -0,0 +1,142 @@
+use std::fmt::Write;
+
+use crate::bytecode::{Capture, Constant, Op, Pattern, Program};
+
+/// Renders a compiled program as annotated bytecode, one function at a time.
+///
+/// Each instruction shows its offset, its operands and what they refer to.
+/// Instructions are grouped under the source line they were compiled from,
+/// which is quoted when `source` is at hand.
+pub fn disassemble(program: &Program, source: Option<&str>) -> String {
+    let lines: Vec<&str> = source.map_or_else(Vec::new, |s| s.lines().collect());
+    let mut out = String::new();
+    for global in &program.globals {
+        let init = &program.functions[global.init as usize];
+        let _ = writeln!(out, "global {} = {}", global.name, init.name);
+    }
+    for method in &program.methods {
+        let function = &program.functions[method.function as usize];
+        let _ = writeln!(
+            out,
+            "method {}.{} = {}",
+            method.owner, method.name, function.name
+        );
+    }
+    let main = &program.functions[program.main as usize];
+    let _ = writeln!(out, "main = {}", main.name);
+
+    for (index, function) in program.functions.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "\nfn #{} {}: {} params, {} slots",
+            index, function.name, function.arity, function.slots
+        );
+        if let Some(memo) = function.memo {
+            let _ = writeln!(out, "  caches {} results", memo.capacity);
+        }
+        if !function.captures.is_empty() {
+            let captures: Vec<String> = function.captures.iter().map(capture).collect();
+            let _ = writeln!(out, "  captures {}", captures.join(", "));
+        }
+        let mut line = None;
+        for (offset, op) in function.code.iter().enumerate() {
+            let at = function.spans[offset].start.0;
+            if line != Some(at) {
+                line = Some(at);
+                match lines.get((at as usize).wrapping_sub(1)) {
+                    Some(text) => {
+                        let _ = writeln!(out, "  {:>4} | {}", at, text.trim_end());
+                    }
+                    None => {
+                        let _ = writeln!(out, "  line {}", at);
+                    }
+                }
+            }
+            let (name, operands) = mnemonic(op);
+            let note = annotation(program, &function.patterns, op);
+            let text = format!("{:>8}  {:<14}{:<10}{}", offset, name, operands, note);
+            let _ = writeln!(out, "{}", text.trim_end());
+        }
+    }
+    out
+}
+
+/// The name of an instruction and its operands, as in `CallFunction 1, 2`.
+fn mnemonic(op: &Op) -> (String, String) {
+    let debug = format!("{:?}", op);
+    match debug.split_once('(') {
+        Some((name, operands)) => (name.to_string(), operands.trim_end_matches(')').to_string()),
+        None => (debug, String::new()),
+    }
+}
+
+/// What the operands of an instruction refer to.
+pub fn annotation(program: &Program, patterns: &[Pattern], op: &Op) -> String {
+    let function_name = |index: u32| program.functions[index as usize].name.clone();
+    match *op {
+        Op::Constant(index) => constant(&program.constants[index as usize]),
+        Op::Builtin(name)
+        | Op::Type(name)
+        | Op::Variant(name, _)
+        | Op::CallBuiltin(name, _)
+        | Op::CallMethod(name, _)
+        | Op::Field(name)
+        | Op::SetField(name) => program.string(name).to_string(),
+        Op::Fail(message) => format!("{:?}", program.string(message)),
+        Op::Global(index) | Op::SetGlobal(index) => program.globals[index as usize].name.clone(),
+        Op::Function(index) | Op::CallFunction(index, _) => function_name(index),
+        Op::Closure(index) => {
+            let closure = &program.functions[index as usize];
+            let captures: Vec<String> = closure.captures.iter().map(capture).collect();
+            format!("{} over {}", closure.name, captures.join(", "))
+        }
+        Op::Struct(index, _) => program.structs[index as usize].name.clone(),
+        Op::Jump(target) | Op::JumpIfFalse(target) => format!("-> {}", target),
+        Op::Next(_, target) => format!("done -> {}", target),
+        Op::Match(index, target) => format!(
+            "{} else -> {}",
+            pattern(program, &patterns[index as usize]),
+            target
+        ),
+        _ => String::new(),
+    }
+}
+
+fn constant(constant: &Constant) -> String {
+    match constant {
+        Constant::Null => "null".to_string(),
+        Constant::Unit => "()".to_string(),
+        Constant::Int(i) => i.to_string(),
+        Constant::Float(x) => format!("{:?}", x),
+        Constant::Bool(b) => b.to_string(),
+        Constant::String(s) => format!("{:?}", s),
+    }
+}
+
+fn capture(capture: &Capture) -> String {
+    match capture {
+        Capture::Local(slot) => format!("slot {}", slot),
+        Capture::Value(slot) => format!("copy of slot {}", slot),
+        Capture::Enclosing(index) => format!("capture {}", index),
+    }
+}
+
+pub fn pattern(program: &Program, pattern: &Pattern) -> String {
+    let all = |patterns: &[Pattern]| {
+        patterns
+            .iter()
+            .map(|p| self::pattern(program, p))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match pattern {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Bind(slot) => format!("slot {}", slot),
+        Pattern::Cell(slot) => format!("cell {}", slot),
+        Pattern::Constant(index) => constant(&program.constants[*index as usize]),
+        Pattern::Tuple(patterns) => format!("({})", all(patterns)),
+        Pattern::Variant(name, patterns) => {
+            format!("{}({})", program.string(*name), all(patterns))
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::abstract_syntax_tree::{
+    Arm, Body, Conditional, Expr, ForStatement, Function, Initialization, Line, Literal, Module,
+    Pattern, Struct, Typeclass, Value,
+};
+use crate::diagnostics::Diagnostic;
+use crate::resolver::{DefId, Resolution};
+use crate::tokens::Span;
+
+/// How many uncovered values a warning lists.
+const MAX_EXAMPLES: usize = 3;
+
+/// Warns about `fn` clauses and `match` arms that leave some values
+/// unmatched, and about ones that can never match because earlier ones
+/// already cover them.
+///
+/// A clause or arm with a `where` guard is assumed to possibly fail, so it never
+/// counts towards covering the arguments. It can still be unreachable.
+pub fn check(module: &Module, resolution: &Resolution) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        resolution,
+        variants: HashMap::new(),
+        functions: Vec::new(),
+        matches: Vec::new(),
+    };
+    let items: Vec<&Initialization> = module.items.iter().collect();
+    checker.collect(&items);
+
+    let mut diagnostics = Vec::new();
+    for (_, clauses) in &checker.functions {
+        diagnostics.extend(checker.function(clauses));
+    }
+    for (arms, span) in &checker.matches {
+        diagnostics.extend(checker.match_expr(arms, *span));
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+/// One clause or arm: its patterns, whether a guard may reject it, and
+/// where it is.
+pub struct Row<'p> {
+    pub patterns: Vec<&'p Pattern>,
+    pub guarded: bool,
+    pub span: Span,
+}
+
+/// What the checker found about a list of rows.
+pub struct Report {
+    /// Indices of the rows that can never match.
+    pub redundant: Vec<usize>,
+    /// Examples of values, one per column, that no row matches.
+    pub missing: Vec<Vec<Witness>>,
+}
+
+/// A value, or a set of values when it contains `_`, printed as a pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Witness {
+    Any,
+    Of(Ctor, Vec<Witness>),
+}
+
+impl fmt::Display for Witness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (ctor, args) = match self {
+            Witness::Any => return write!(f, "_"),
+            Witness::Of(ctor, args) => (ctor, args),
+        };
+        match ctor {
+            Ctor::Variant(name) if args.is_empty() => write!(f, "{}", name),
+            Ctor::Variant(name) => write!(f, "{}({})", name, list(args)),
+            Ctor::Tuple(_) => write!(f, "({})", list(args)),
+            Ctor::Bool(b) => write!(f, "{}", b),
+            Ctor::Int(i) => write!(f, "{}", i),
+            Ctor::Float(bits) => write!(f, "{:?}", f64::from_bits(*bits)),
+            Ctor::String(s) => write!(f, "{:?}", s),
+            Ctor::Unit => write!(f, "()"),
+            Ctor::Null => write!(f, "null"),
+        }
+    }
+}
+
+/// Formats witnesses separated by commas.
+pub fn list(witnesses: &[Witness]) -> String {
+    witnesses
+        .iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The head of a pattern that is not `_` or a binding.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ctor {
+    Variant(String),
+    Tuple(usize),
+    Bool(bool),
+    Int(i64),
+    /// Compared by bits, so that every float pattern is a distinct value.
+    Float(u64),
+    String(String),
+    Unit,
+    Null,
+}
+
+/// A pattern reduced to what matters for coverage.
+#[derive(Clone, Debug)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+impl Pat {
+    fn lower(pattern: &Pattern) -> Pat {
+        let ctor = match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => return Pat::Wild,
+            Pattern::Tuple(patterns) => {
+                let args = patterns.iter().map(Pat::lower).collect();
+                return Pat::Ctor(Ctor::Tuple(patterns.len()), args);
+            }
+            Pattern::Constructor(name, patterns) => {
+                let args = patterns.iter().map(Pat::lower).collect();
+                return Pat::Ctor(Ctor::Variant(name.0.clone()), args);
+            }
+            Pattern::Literal(literal) => match literal {
+                Literal::Bool(b) => Ctor::Bool(*b),
+                Literal::Int(i) => Ctor::Int(*i),
+                Literal::Float(x) => Ctor::Float(x.to_bits()),
+                Literal::String(s) => Ctor::String(s.clone()),
+                Literal::Void => Ctor::Unit,
+                Literal::Null => Ctor::Null,
+                _ => return Pat::Wild,
+            },
+        };
+        Pat::Ctor(ctor, Vec::new())
+    }
+
+    fn witness(self) -> Witness {
+        match self {
+            Pat::Wild => Witness::Any,
+            Pat::Ctor(ctor, args) => {
+                Witness::Of(ctor, args.into_iter().map(Pat::witness).collect())
+            }
+        }
+    }
+}
+
+/// The variants of every enum, by variant name, each with its field count.
+pub type Variants = HashMap<String, Vec<(String, usize)>>;
+
+fn collect_variants(items: &[&Initialization], out: &mut Variants) {
+    for item in items {
+        match &item.value {
+            Some(Value::Enum(e)) => {
+                let siblings: Vec<(String, usize)> =
+                    e.0.iter()
+                        .map(|entry| {
+                            let arity = entry.1.as_ref().map_or(0, |fields| fields.len());
+                            (entry.0.field_name.clone(), arity)
+                        })
+                        .collect();
+                for (name, _) in &siblings {
+                    out.insert(name.clone(), siblings.clone());
+                }
+            }
+            // A distinct type is its own and only variant.
+            Some(Value::Type(alias)) if alias.distinct => {
+                let name = item.name.0.clone();
+                out.insert(name.clone(), vec![(name, 1)]);
+            }
+            Some(Value::Struct(Struct(lines))) => collect_variants(&initializations(lines), out),
+            _ => {}
+        }
+    }
+}
+
+/// Checks rows in order: each is redundant when the unguarded rows before
+/// it already match all it does, and whatever no unguarded row matches is
+/// missing.
+pub fn analyse(rows: &[Row], variants: &Variants) -> Report {
+    let arity = rows.first().map_or(0, |row| row.patterns.len());
+    let matrix = Matrix { variants };
+    let mut covered: Vec<Vec<Pat>> = Vec::new();
+    let mut redundant = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        let pats: Vec<Pat> = row.patterns.iter().map(|p| Pat::lower(p)).collect();
+        if matrix.missing(&covered, &pats, 1).is_empty() {
+            redundant.push(i);
+        }
+        if !row.guarded {
+            covered.push(pats);
+        }
+    }
+    let missing = matrix
+        .missing(&covered, &vec![Pat::Wild; arity], MAX_EXAMPLES)
+        .into_iter()
+        .map(|w| w.into_iter().map(Pat::witness).collect())
+        .collect();
+    Report { redundant, missing }
+}
+
+struct Matrix<'v> {
+    variants: &'v Variants,
+}
+
+impl Matrix<'_> {
+    /// Up to `limit` instances of `q` that no row matches. An empty result
+    /// means the rows cover all of `q`.
+    fn missing(&self, rows: &[Vec<Pat>], q: &[Pat], limit: usize) -> Vec<Vec<Pat>> {
+        let Some((head, rest)) = q.split_first() else {
+            return if rows.is_empty() {
+                vec![Vec::new()]
+            } else {
+                Vec::new()
+            };
+        };
+        match head {
+            Pat::Ctor(ctor, args) => {
+                let mut q = args.clone();
+                q.extend(rest.iter().cloned());
+                self.missing(&specialize(rows, ctor, args.len()), &q, limit)
+                    .into_iter()
+                    .map(|w| rebuild(ctor, args.len(), w))
+                    .collect()
+            }
+            Pat::Wild => {
+                let heads = heads(rows);
+                if let Some(signature) = self.signature(&heads) {
+                    // Every value starts with one of these, so try each.
+                    let mut out = Vec::new();
+                    for (ctor, arity) in signature {
+                        let mut q = vec![Pat::Wild; arity];
+                        q.extend(rest.iter().cloned());
+                        let found =
+                            self.missing(&specialize(rows, &ctor, arity), &q, limit - out.len());
+                        out.extend(found.into_iter().map(|w| rebuild(&ctor, arity, w)));
+                        if out.len() >= limit {
+                            break;
+                        }
+                    }
+                    return out;
+                }
+                // Some value starts with none of the heads, so only the
+                // rows starting with `_` can match it.
+                let defaults: Vec<Vec<Pat>> = rows
+                    .iter()
+                    .filter(|row| matches!(row[0], Pat::Wild))
+                    .map(|row| row[1..].to_vec())
+                    .collect();
+                let found = self.missing(&defaults, rest, limit);
+                let mut out = Vec::new();
+                for example in self.unmatched(&heads) {
+                    for w in &found {
+                        if out.len() == limit {
+                            return out;
+                        }
+                        let mut witness = vec![example.clone()];
+                        witness.extend(w.iter().cloned());
+                        out.push(witness);
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    /// Every constructor of the type, when `heads` includes them all.
+    fn signature(&self, heads: &[(Ctor, usize)]) -> Option<Vec<(Ctor, usize)>> {
+        let all: Vec<(Ctor, usize)> = match &heads.first()?.0 {
+            Ctor::Variant(name) => self
+                .variants
+                .get(name)?
+                .iter()
+                .map(|(name, arity)| (Ctor::Variant(name.clone()), *arity))
+                .collect(),
+            Ctor::Bool(_) => vec![(Ctor::Bool(false), 0), (Ctor::Bool(true), 0)],
+            Ctor::Tuple(n) => vec![(Ctor::Tuple(*n), *n)],
+            Ctor::Unit => vec![(Ctor::Unit, 0)],
+            _ => return None,
+        };
+        all.iter()
+            .all(|(ctor, _)| heads.iter().any(|(head, _)| head == ctor))
+            .then_some(all)
+    }
+
+    /// Examples of values that start with none of `heads`.
+    fn unmatched(&self, heads: &[(Ctor, usize)]) -> Vec<Pat> {
+        let taken = |ctor: &Ctor| heads.iter().any(|(head, _)| head == ctor);
+        let example = match heads.first().map(|(ctor, _)| ctor) {
+            Some(Ctor::Variant(name)) => match self.variants.get(name) {
+                Some(siblings) => {
+                    return siblings
+                        .iter()
+                        .map(|(name, arity)| (Ctor::Variant(name.clone()), *arity))
+                        .filter(|(ctor, _)| !taken(ctor))
+                        .map(|(ctor, arity)| Pat::Ctor(ctor, vec![Pat::Wild; arity]))
+                        .collect()
+                }
+                None => None,
+            },
+            Some(Ctor::Bool(_)) => [false, true]
+                .into_iter()
+                .map(Ctor::Bool)
+                .find(|c| !taken(c)),
+            Some(Ctor::Int(_)) => (0..).map(Ctor::Int).find(|c| !taken(c)),
+            Some(Ctor::Float(_)) => (0..)
+                .map(|i| Ctor::Float((i as f64).to_bits()))
+                .find(|c| !taken(c)),
+            Some(Ctor::String(_)) => (0..)
+                .map(|n| Ctor::String("a".repeat(n)))
+                .find(|c| !taken(c)),
+            _ => None,
+        };
+        vec![example.map_or(Pat::Wild, |ctor| Pat::Ctor(ctor, Vec::new()))]
+    }
+}
+
+/// The distinct constructors that rows start with.
+fn heads(rows: &[Vec<Pat>]) -> Vec<(Ctor, usize)> {
+    let mut heads: Vec<(Ctor, usize)> = Vec::new();
+    for row in rows {
+        if let Pat::Ctor(ctor, args) = &row[0] {
+            if !heads.iter().any(|(head, _)| head == ctor) {
+                heads.push((ctor.clone(), args.len()));
+            }
+        }
+    }
+    heads
+}
+
+/// The rows that can match a value starting with `ctor`, with the head
+/// replaced by its fields.
+fn specialize(rows: &[Vec<Pat>], ctor: &Ctor, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut out = match &row[0] {
+                Pat::Wild => vec![Pat::Wild; arity],
+                Pat::Ctor(head, args) if head == ctor && args.len() == arity => args.clone(),
+                Pat::Ctor(..) => return None,
+            };
+            out.extend(row[1..].iter().cloned());
+            Some(out)
+        })
+        .collect()
+}
+
+/// Undoes `specialize` on a witness: its first `arity` columns become the
+/// fields of `ctor`.
+fn rebuild(ctor: &Ctor, arity: usize, mut witness: Vec<Pat>) -> Vec<Pat> {
+    let rest = witness.split_off(arity);
+    let mut out = vec![Pat::Ctor(ctor.clone(), witness)];
+    out.extend(rest);
+    out
+}
+
+struct Checker<'a> {
+    resolution: &'a Resolution,
+    variants: Variants,
+    /// The clauses of every `fn` and `proc`, in source order.
+    functions: Vec<(DefId, Vec<&'a Initialization>)>,
+    /// The arms of every `match`, with the span of the whole `match`.
+    matches: Vec<(&'a [Arm], Span)>,
+}
+
+impl<'a> Checker<'a> {
+    fn collect(&mut self, items: &[&'a Initialization]) {
+        for item in items {
+            match &item.value {
+                Some(Value::Enum(_) | Value::Type(_)) => {
+                    collect_variants(&[item], &mut self.variants)
+                }
+                Some(Value::Struct(Struct(lines))) | Some(Value::Typeclass(Typeclass(lines))) => {
+                    self.collect(&initializations(lines))
+                }
+                Some(Value::Function(function)) if function.body.is_some() => {
+                    if let Some(id) = self.resolution.declaration(item.span, &item.name.0) {
+                        match self.functions.iter_mut().find(|(f, _)| *f == id) {
+                            Some((_, clauses)) => clauses.push(item),
+                            None => self.functions.push((id, vec![item])),
+                        }
+                    }
+                    self.function_body(function);
+                }
+                Some(Value::Expr(expr)) => self.collect_expr(expr),
+                _ => {}
+            }
+        }
+    }
+
+    fn function_body(&mut self, function: &'a Function) {
+        if let Some(guard) = &function.guard {
+            self.collect_expr(guard);
+        }
+        match &function.body {
+            Some(Body::Expr(expr)) => self.collect_expr(expr),
+            Some(Body::Block(lines)) => self.collect_lines(lines),
+            None => {}
+        }
+    }
+
+    fn collect_lines(&mut self, lines: &'a [Line]) {
+        for line in lines {
+            match line {
+                Line::Initialization(init) => self.collect(&[init]),
+                Line::Assignment(assignment) => {
+                    self.collect_expr(&assignment.target);
+                    self.collect_expr(&assignment.value);
+                }
+                Line::Statement(statement) => self.collect_exprs(&statement.args),
+                Line::MethodCall(expr) | Line::Return(expr) => self.collect_expr(expr),
+                Line::For(ForStatement(_, iterable, body, _)) => {
+                    self.collect_expr(iterable);
+                    self.collect_lines(body);
+                }
+                Line::While(Conditional(condition, then, otherwise, _))
+                | Line::If(Conditional(condition, then, otherwise, _)) => {
+                    self.collect_expr(condition);
+                    self.collect_lines(then);
+                    if let Some(otherwise) = otherwise {
+                        self.collect_lines(otherwise);
+                    }
+                }
+                Line::Break(_) | Line::Continue(_) => {}
+            }
+        }
+    }
+
+    /// Finds the `match` expressions within `expr`, including in closures.
+    fn collect_expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Call(_, args, _) | Expr::Sequence(args, _) => self.collect_exprs(args),
+            Expr::FieldAccess(inner, _, _) | Expr::Grouping(_, inner, _) => {
+                self.collect_expr(inner)
+            }
+            Expr::Match(value, arms, span) => {
+                self.matches.push((arms, *span));
+                self.collect_expr(value);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        self.collect_expr(guard);
+                    }
+                    self.collect_expr(&arm.body);
+                }
+            }
+            Expr::Literal(literal, _) => match literal {
+                Literal::List(elements) | Literal::Set(elements) | Literal::Tuple(elements) => {
+                    self.collect_exprs(elements)
+                }
+                Literal::StructInitialization(_, fields, base) => {
+                    if let Some(base) = base {
+                        self.collect_expr(base);
+                    }
+                    for field in fields {
+                        self.collect_expr(&field.value);
+                    }
+                }
+                Literal::Map(entries) => {
+                    for (key, value) in entries {
+                        self.collect_expr(key);
+                        self.collect_expr(value);
+                    }
+                }
+                Literal::Closure(value) => {
+                    if let Value::Function(function) = value.as_ref() {
+                        self.function_body(function);
+                    }
+                }
+                _ => {}
+            },
+            Expr::Reference(_, _) => {}
+        }
+    }
+
+    fn collect_exprs(&mut self, exprs: &'a [Expr]) {
+        for expr in exprs {
+            self.collect_expr(expr);
+        }
+    }
+
+    fn match_expr(&self, arms: &[Arm], span: Span) -> Vec<Diagnostic> {
+        let rows: Vec<Row> = arms
+            .iter()
+            .map(|arm| Row {
+                patterns: vec![&arm.pattern],
+                guarded: arm.guard.is_some(),
+                span: arm.span,
+            })
+            .collect();
+        let report = analyse(&rows, &self.variants);
+        let mut diagnostics = Vec::new();
+        for &i in &report.redundant {
+            diagnostics.push(
+                Diagnostic::warning("this arm can never match".to_string(), rows[i].span)
+                    .with_help("the arms before it already match all of its values".to_string()),
+            );
+        }
+        if !report.missing.is_empty() {
+            let examples: Vec<String> = report
+                .missing
+                .iter()
+                .map(|values| format!("`{}`", list(values)))
+                .collect();
+            let mut diagnostic =
+                Diagnostic::warning("this `match` does not cover every value".to_string(), span)
+                    .with_help(format!("add an arm for {}", examples.join(" or ")));
+            if let Some(guarded) = rows.iter().find(|row| row.guarded) {
+                diagnostic = diagnostic.with_note(
+                    guarded.span,
+                    "an arm with a `where` guard is not counted, as its guard may be false"
+                        .to_string(),
+                );
+            }
+            diagnostics.push(diagnostic);
+        }
+        diagnostics
+    }
+
+    fn function(&self, clauses: &[&Initialization]) -> Vec<Diagnostic> {
+        let rows: Vec<Row> = clauses
+            .iter()
+            .filter_map(|clause| match &clause.value {
+                Some(Value::Function(function)) => Some(Row {
+                    patterns: function.params.iter().map(|p| &p.pattern).collect(),
+                    guarded: function.guard.is_some(),
+                    span: clause.span,
+                }),
+                _ => None,
+            })
+            .collect();
+        // Clauses that disagree on the parameter count are a type error.
+        if rows
+            .iter()
+            .any(|row| row.patterns.len() != rows[0].patterns.len())
+        {
+            return Vec::new();
+        }
+
+        let first = clauses[0];
+        let name = &first.name.0;
+        let report = analyse(&rows, &self.variants);
+        let mut diagnostics = Vec::new();
+        for &i in &report.redundant {
+            diagnostics.push(
+                Diagnostic::warning(
+                    format!("this clause of `{}` can never match", name),
+                    rows[i].span,
+                )
+                .with_help("the clauses before it already match all of its arguments".to_string()),
+            );
+        }
+        if !report.missing.is_empty() {
+            let examples: Vec<String> = report
+                .missing
+                .iter()
+                .map(|args| format!("`{}({})`", name, list(args)))
+                .collect();
+            let mut diagnostic = Diagnostic::warning(
+                format!("the clauses of `{}` do not cover every argument", name),
+                first.span,
+            )
+            .with_help(format!("add a clause for {}", examples.join(" or ")));
+            if let Some(guarded) = rows.iter().find(|row| row.guarded) {
+                diagnostic = diagnostic.with_note(
+                    guarded.span,
+                    "a clause with a `where` guard is not counted, as its guard may be false"
+                        .to_string(),
+                );
+            }
+            diagnostics.push(diagnostic);
+        }
+        diagnostics
+    }
+}
+
+fn initializations(lines: &[Line]) -> Vec<&Initialization> {
+    lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Initialization(init) => Some(init),
+            _ => None,
+        })
+        .collect()
+}
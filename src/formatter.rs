@@ -0,0 +1,736 @@
+use std::collections::VecDeque;
+
+use crate::abstract_syntax_tree::{
+    Arm, Body, Conditional, Domain, EnumEntry, Expr, ForStatement, Function, Initialization, Line,
+    Literal, Module, Param, Pattern, TypeExpr, TypeParam, Value,
+};
+use crate::operator::infix_token;
+use crate::tokens::{Comment, Span, TokenType};
+
+const INDENT: &str = "    ";
+/// Calls and collections that would run past this column are broken over several lines.
+const MAX_WIDTH: usize = 100;
+
+/// Prints a module in the canonical chop style, re-inserting the comments the lexer set aside.
+pub fn format_module(module: &Module, comments: Vec<Comment>) -> String {
+    let mut f = Formatter {
+        out: String::new(),
+        indent: 0,
+        comments: comments.into(),
+    };
+    let items: Vec<Element> = module.items.iter().map(Element::Item).collect();
+    f.elements(&items, u32::MAX, 0);
+    while f.out.ends_with("\n\n") {
+        f.out.pop();
+    }
+    f.out
+}
+
+struct Formatter {
+    out: String,
+    indent: usize,
+    comments: VecDeque<Comment>,
+}
+
+/// Anything that is printed on its own line inside a block.
+#[derive(Clone, Copy)]
+enum Element<'a> {
+    Item(&'a Initialization),
+    Line(&'a Line),
+    Entry(&'a EnumEntry),
+    Param(&'a TypeParam),
+}
+
+impl Element<'_> {
+    fn span(&self) -> Span {
+        match self {
+            Element::Item(i) => i.span,
+            Element::Line(l) => line_span(l),
+            Element::Entry(e) => e.2,
+            Element::Param(p) => p.span,
+        }
+    }
+
+    /// Struct fields and enum entries that fit on one line are aligned with their neighbours.
+    fn alignable(&self) -> bool {
+        let span = self.span();
+        span.start.0 == span.end.0
+            && match self {
+                Element::Entry(_) => true,
+                Element::Item(i) => is_field(i),
+                Element::Line(Line::Initialization(i)) => is_field(i),
+                Element::Line(_) | Element::Param(_) => false,
+            }
+    }
+}
+
+fn is_field(i: &Initialization) -> bool {
+    matches!(i.domain, Domain::Var | Domain::Const)
+        && i.value.is_none()
+        && i.type_annotation.0.is_some()
+}
+
+fn line_span(line: &Line) -> Span {
+    match line {
+        Line::Initialization(i) => i.span,
+        Line::Statement(s) => s.span,
+        Line::Assignment(a) => a.span,
+        Line::MethodCall(e) | Line::Return(e) => e.span(),
+        Line::For(ForStatement(_, _, _, span)) => *span,
+        Line::While(Conditional(_, _, _, span)) | Line::If(Conditional(_, _, _, span)) => *span,
+        Line::Break(span) | Line::Continue(span) => *span,
+    }
+}
+
+impl Formatter {
+    fn column(&self) -> usize {
+        match self.out.rfind('\n') {
+            Some(i) => self.out.len() - i - 1,
+            None => self.out.len(),
+        }
+    }
+
+    fn start_row(&mut self) {
+        self.out.push_str(&INDENT.repeat(self.indent));
+    }
+
+    fn end_row(&mut self) {
+        self.out.push('\n');
+    }
+
+    fn blank_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn take_trailing(&mut self, line: u32) -> Option<Comment> {
+        match self.comments.front() {
+            Some(c) if c.trailing && c.position.0 == line => self.comments.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Writes the standalone comments that come before source line `line`.
+    fn comments_before(&mut self, line: u32, min_col: u16, last: &mut Option<u32>) {
+        while let Some(c) = self.comments.front() {
+            if c.position.0 >= line || c.position.1 <= min_col {
+                break;
+            }
+            let c = self.comments.pop_front().expect("comment");
+            if last.is_some_and(|l| c.position.0 > l + 1) {
+                self.blank_line();
+            }
+            self.start_row();
+            self.out.push_str("//");
+            self.out.push_str(&c.text);
+            self.end_row();
+            *last = Some(c.position.0);
+        }
+    }
+
+    /// Writes the elements of a block, one per row.
+    ///
+    /// Comments before `limit` that are indented further than `owner_col`
+    /// belong to this block, the rest are left for the enclosing one.
+    fn elements(&mut self, elements: &[Element], limit: u32, owner_col: u16) {
+        let mut last: Option<u32> = None;
+        let mut i = 0;
+        while i < elements.len() {
+            let span = elements[i].span();
+            self.comments_before(span.start.0, 0, &mut last);
+            // Top-level items spanning several lines always get a blank line around them.
+            let multiline = |e: &Element| e.span().start.0 != e.span().end.0;
+            let separated = self.indent == 0
+                && i > 0
+                && (multiline(&elements[i]) || multiline(&elements[i - 1]));
+            if separated || last.is_some_and(|l| span.start.0 > l + 1) {
+                self.blank_line();
+            }
+
+            let mut run = 1;
+            if elements[i].alignable() {
+                while i + run < elements.len()
+                    && elements[i + run].alignable()
+                    && elements[i + run].span().start.0 == elements[i + run - 1].span().end.0 + 1
+                {
+                    run += 1;
+                }
+            }
+
+            if run > 1 {
+                self.aligned(&elements[i..i + run]);
+            } else {
+                let next = elements
+                    .get(i + 1)
+                    .map(|e| e.span().start.0)
+                    .unwrap_or(limit);
+                self.start_row();
+                self.element(elements[i], next, span.start.1);
+                if let Some(c) = self.take_trailing(span.end.0) {
+                    self.out.push_str(" //");
+                    self.out.push_str(&c.text);
+                }
+                self.end_row();
+            }
+            last = Some(elements[i + run - 1].span().end.0);
+            i += run;
+        }
+        self.comments_before(limit, owner_col, &mut last);
+    }
+
+    fn aligned(&mut self, run: &[Element]) {
+        let rows: Vec<(String, Option<String>)> = run
+            .iter()
+            .map(|e| match *e {
+                Element::Item(i) | Element::Line(Line::Initialization(i)) => (
+                    format!("{} {}:", domain_keyword(i.domain), i.name.0),
+                    i.type_annotation.0.as_ref().map(type_expr),
+                ),
+                Element::Entry(entry) => (enum_entry(entry), None),
+                Element::Line(_) | Element::Param(_) => {
+                    unreachable!("only fields and entries are aligned")
+                }
+            })
+            .collect();
+
+        let name_width = rows.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+        let texts: Vec<String> = rows
+            .into_iter()
+            .map(|(left, ty)| match ty {
+                Some(ty) => format!("{:width$} {}", left, ty, width = name_width),
+                None => left,
+            })
+            .collect();
+        let text_width = texts.iter().map(String::len).max().unwrap_or(0);
+
+        for (element, text) in run.iter().zip(texts) {
+            self.start_row();
+            match self.take_trailing(element.span().start.0) {
+                Some(c) => {
+                    self.out
+                        .push_str(&format!("{:width$} //{}", text, c.text, width = text_width));
+                }
+                None => self.out.push_str(&text),
+            }
+            self.end_row();
+        }
+    }
+
+    fn element(&mut self, element: Element, limit: u32, col: u16) {
+        match element {
+            Element::Item(i) => self.initialization(i, limit, col),
+            Element::Line(l) => self.line(l, limit, col),
+            Element::Entry(e) => self.out.push_str(&enum_entry(e)),
+            Element::Param(p) => self.out.push_str(&type_param(p)),
+        }
+    }
+
+    fn block(&mut self, lines: &[Line], limit: u32, owner_col: u16) {
+        let elements: Vec<Element> = lines.iter().map(Element::Line).collect();
+        self.nested(&elements, limit, owner_col);
+    }
+
+    fn nested(&mut self, elements: &[Element], limit: u32, owner_col: u16) {
+        let has_comments = self
+            .comments
+            .front()
+            .is_some_and(|c| c.position.0 < limit && c.position.1 > owner_col);
+        if elements.is_empty() && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.elements(elements, limit, owner_col);
+        self.indent -= 1;
+        self.start_row();
+        self.out.push('}');
+    }
+
+    fn initialization(&mut self, init: &Initialization, limit: u32, col: u16) {
+        if let Some(memo) = &init.memo {
+            match memo.capacity {
+                Some(capacity) => self.out.push_str(&format!("@memo({}) ", capacity)),
+                None => self.out.push_str("@memo "),
+            }
+        }
+        self.out.push_str(domain_keyword(init.domain));
+        self.out.push(' ');
+        self.out.push_str(&init.name.0);
+
+        if let Some(Value::Type(alias)) = &init.value {
+            if !alias.params.is_empty() {
+                let names: Vec<&str> = alias.params.iter().map(|p| p.name.0.as_str()).collect();
+                self.out.push_str(&format!("<{}>", names.join(", ")));
+            }
+            if let Some(bound) = &init.type_annotation.0 {
+                self.out.push_str(": ");
+                self.out.push_str(&type_expr(bound));
+            }
+            if let Some(ty) = &alias.target.0 {
+                self.out.push_str(if alias.distinct {
+                    " = distinct "
+                } else {
+                    " = "
+                });
+                self.out.push_str(&type_expr(ty));
+            }
+            return;
+        }
+
+        if let Some(ty) = &init.type_annotation.0 {
+            self.out.push_str(": ");
+            self.out.push_str(&type_expr(ty));
+        }
+
+        let Some(value) = &init.value else { return };
+        self.out.push_str(" = ");
+        let limit = limit.min(init.span.end.0 + 1);
+        match value {
+            Value::Expr(e) => {
+                let text = self.expr(e);
+                self.out.push_str(&text);
+            }
+            Value::Function(function) => self.function(function, init.domain, limit, col),
+            Value::Struct(s) => self.block(&s.0, limit, col),
+            Value::Typeclass(t) => self.block(&t.0, limit, col),
+            Value::Enum(e) => {
+                let mut elements: Vec<Element> = e.1.iter().map(Element::Param).collect();
+                elements.extend(e.0.iter().map(Element::Entry));
+                elements.sort_by_key(|element| element.span().start);
+                self.nested(&elements, limit, col);
+            }
+            Value::Type(_) => unreachable!("handled above"),
+        }
+    }
+
+    fn function(&mut self, function: &Function, domain: Domain, limit: u32, col: u16) {
+        self.out.push_str(&parameters(function));
+
+        if domain == Domain::Proc {
+            if let Some(ty) = &function.return_type.0 {
+                self.out.push_str(" -> ");
+                self.out.push_str(&type_expr(ty));
+            }
+            match &function.body {
+                Some(Body::Block(lines)) => {
+                    self.out.push(' ');
+                    self.block(lines, limit, col);
+                }
+                Some(Body::Expr(e)) => {
+                    self.out.push_str(" {\n");
+                    self.indent += 1;
+                    self.start_row();
+                    let text = self.expr(e);
+                    self.out.push_str(&text);
+                    self.end_row();
+                    self.indent -= 1;
+                    self.start_row();
+                    self.out.push('}');
+                }
+                None => {}
+            }
+        } else if let Some(body) = &function.body {
+            self.out.push_str(" -> ");
+            match body {
+                Body::Expr(e) => {
+                    let text = self.expr(e);
+                    self.out.push_str(&text);
+                }
+                Body::Block(lines) => self.block(lines, limit, col),
+            }
+        }
+    }
+
+    fn line(&mut self, line: &Line, limit: u32, col: u16) {
+        match line {
+            Line::Initialization(i) => self.initialization(i, limit, col),
+            Line::Statement(s) => {
+                let call = Expr::Call(s.proc_name.clone(), s.args.clone(), s.span);
+                let text = self.expr(&call);
+                self.out.push_str(&text);
+            }
+            Line::MethodCall(e) => {
+                let text = self.expr(e);
+                self.out.push_str(&text);
+            }
+            Line::Assignment(a) => {
+                let target = self.expr(&a.target);
+                self.out.push_str(&format!("{} {} ", target, a.operator));
+                let text = self.expr(&a.value);
+                self.out.push_str(&text);
+            }
+            Line::Return(Expr::Literal(Literal::Void, _)) => self.out.push_str("return"),
+            Line::Return(e) => {
+                self.out.push_str("return ");
+                let text = self.expr(e);
+                self.out.push_str(&text);
+            }
+            Line::For(ForStatement(names, iterable, body, span)) => {
+                let names: Vec<&str> = names.iter().map(|n| n.0.as_str()).collect();
+                self.out.push_str(&format!("for {} in ", names.join(", ")));
+                let text = self.expr(iterable);
+                self.out.push_str(&text);
+                self.out.push(' ');
+                self.block(body, limit.min(span.end.0 + 1), col);
+            }
+            Line::While(c) => self.conditional("while", c, limit, col),
+            Line::If(c) => self.conditional("if", c, limit, col),
+            Line::Break(_) => self.out.push_str("break"),
+            Line::Continue(_) => self.out.push_str("continue"),
+        }
+    }
+
+    fn conditional(&mut self, keyword: &str, c: &Conditional, limit: u32, col: u16) {
+        let Conditional(condition, body, alternative, span) = c;
+        let limit = limit.min(span.end.0 + 1);
+        self.out.push_str(keyword);
+        self.out.push(' ');
+        let text = self.expr(condition);
+        self.out.push_str(&text);
+        self.out.push(' ');
+
+        let body_limit = match alternative.as_deref() {
+            Some([first, ..]) => line_span(first).start.0,
+            _ => limit,
+        };
+        self.block(body, body_limit, col);
+
+        match alternative.as_deref() {
+            None => {}
+            Some([Line::If(inner)]) => {
+                self.out.push_str(" else ");
+                self.conditional("if", inner, limit, col);
+            }
+            Some(lines) => {
+                self.out.push_str(" else ");
+                self.block(lines, limit, col);
+            }
+        }
+    }
+
+    /// Prints an expression starting at the current column, breaking long
+    /// argument lists and collections one element per line.
+    fn expr(&self, e: &Expr) -> String {
+        self.expr_at(e, self.indent, self.column())
+    }
+
+    fn expr_at(&self, e: &Expr, indent: usize, col: usize) -> String {
+        let flat = expr(e);
+        if col + flat.len() <= MAX_WIDTH && !flat.contains('\n') {
+            return flat;
+        }
+
+        match e {
+            Expr::Call(name, args, _) if !is_operator_call(name.0.as_str(), args) => {
+                format!("{}{}", name.0, self.broken("(", args, ")", indent))
+            }
+            Expr::Call(name, args, _) if args.len() == 2 && infix_name_of(&name.0) => {
+                let lhs = self.expr_at(&args[0], indent, col);
+                let op = format!(" {} ", name.0);
+                let rhs_col = last_line_len(&lhs, col) + op.len();
+                let rhs = self.expr_at(&args[1], indent, rhs_col);
+                format!("{}{}{}", lhs, op, rhs)
+            }
+            Expr::Grouping(TokenType::Dot, call, _) => match call.as_ref() {
+                Expr::Call(name, args, _) if !args.is_empty() => {
+                    let receiver = self.expr_at(&args[0], indent, col);
+                    format!(
+                        "{}.{}{}",
+                        receiver,
+                        name.0,
+                        self.broken("(", &args[1..], ")", indent)
+                    )
+                }
+                _ => flat,
+            },
+            Expr::Grouping(TokenType::LParen, inner, _) => {
+                format!("({})", self.expr_at(inner, indent, col + 1))
+            }
+            Expr::Match(value, arms, _) => {
+                let inner = INDENT.repeat(indent + 1);
+                let mut out = format!("match {} {{\n", self.expr_at(value, indent, col + 6));
+                for a in arms {
+                    let head = arm_head(a);
+                    let body_col = inner.len() + head.len() + 1;
+                    let body = self.expr_at(&a.body, indent + 1, body_col);
+                    out.push_str(&format!("{}{} {}\n", inner, head, body));
+                }
+                out.push_str(&INDENT.repeat(indent));
+                out.push('}');
+                out
+            }
+            Expr::Literal(Literal::List(elements), _) => self.broken("[", elements, "]", indent),
+            Expr::Literal(Literal::Set(elements), _) => self.broken("{", elements, "}", indent),
+            Expr::Literal(Literal::Map(entries), _) if !entries.is_empty() => {
+                let inner = INDENT.repeat(indent + 1);
+                let mut out = String::from("{\n");
+                for (k, v) in entries {
+                    let key = self.expr_at(k, indent + 1, inner.len());
+                    let value = self.expr_at(v, indent + 1, last_line_len(&key, inner.len()) + 2);
+                    out.push_str(&format!("{}{}: {},\n", inner, key, value));
+                }
+                out.push_str(&INDENT.repeat(indent));
+                out.push('}');
+                out
+            }
+            _ => flat,
+        }
+    }
+
+    fn broken(&self, open: &str, elements: &[Expr], close: &str, indent: usize) -> String {
+        if elements.is_empty() {
+            return format!("{}{}", open, close);
+        }
+        let inner = INDENT.repeat(indent + 1);
+        let mut out = format!("{}\n", open);
+        for element in elements {
+            out.push_str(&inner);
+            out.push_str(&self.expr_at(element, indent + 1, inner.len()));
+            out.push_str(",\n");
+        }
+        out.push_str(&INDENT.repeat(indent));
+        out.push_str(close);
+        out
+    }
+}
+
+fn last_line_len(text: &str, start_col: usize) -> usize {
+    match text.rfind('\n') {
+        Some(i) => text.len() - i - 1,
+        None => start_col + text.len(),
+    }
+}
+
+fn infix_name_of(name: &str) -> bool {
+    infix_token(name).is_some()
+}
+
+fn is_operator_call(name: &str, args: &[Expr]) -> bool {
+    (args.len() == 2 && infix_name_of(name)) || prefix_operator(name, args).is_some()
+}
+
+/// `not` and `negate` are printed as `!` and `-` unless the operand is itself
+/// an operator expression, which would bind differently without the call.
+fn prefix_operator(name: &str, args: &[Expr]) -> Option<&'static str> {
+    let symbol = match name {
+        "not" => "!",
+        "negate" => "-",
+        _ => return None,
+    };
+    match args {
+        [Expr::Call(inner, inner_args, _)] if inner_args.len() == 2 && infix_name_of(&inner.0) => {
+            None
+        }
+        [Expr::Sequence(..)] => None,
+        [_] => Some(symbol),
+        _ => None,
+    }
+}
+
+fn domain_keyword(domain: Domain) -> &'static str {
+    match domain {
+        Domain::Const => "const",
+        Domain::Var => "var",
+        Domain::Proc => "proc",
+        Domain::Fn => "fn",
+        Domain::Struct => "struct",
+        Domain::Enum => "enum",
+        Domain::Typeclass => "typeclass",
+        Domain::Type => "type",
+    }
+}
+
+fn join(elements: &[Expr]) -> String {
+    elements.iter().map(expr).collect::<Vec<_>>().join(", ")
+}
+
+/// Prints an expression on a single line.
+fn expr(e: &Expr) -> String {
+    match e {
+        Expr::Sequence(elements, _) => join(elements),
+        Expr::Call(name, args, _) => {
+            if args.len() == 2 && infix_name_of(&name.0) {
+                format!("{} {} {}", expr(&args[0]), name.0, expr(&args[1]))
+            } else if let Some(symbol) = prefix_operator(&name.0, args) {
+                format!("{}{}", symbol, expr(&args[0]))
+            } else {
+                format!("{}({})", name.0, join(args))
+            }
+        }
+        Expr::Literal(l, _) => literal(l),
+        Expr::Reference(name, _) => name.0.clone(),
+        Expr::FieldAccess(receiver, field, _) => format!("{}.{}", expr(receiver), field.field_name),
+        Expr::Grouping(TokenType::Dot, call, _) => match call.as_ref() {
+            Expr::Call(name, args, _) if !args.is_empty() => {
+                format!("{}.{}({})", expr(&args[0]), name.0, join(&args[1..]))
+            }
+            other => expr(other),
+        },
+        Expr::Grouping(TokenType::KwImpure, inner, _) => format!("impure {}", expr(inner)),
+        Expr::Grouping(TokenType::KwTail, inner, _) => format!("@tail {}", expr(inner)),
+        Expr::Grouping(_, inner, _) => format!("({})", expr(inner)),
+        // Arms always go on lines of their own; `Formatter::expr_at` indents them.
+        Expr::Match(value, arms, _) => {
+            let arms: String = arms
+                .iter()
+                .map(|a| format!("{}{} {}\n", INDENT, arm_head(a), expr(&a.body)))
+                .collect();
+            format!("match {} {{\n{}}}", expr(value), arms)
+        }
+    }
+}
+
+/// An arm up to and including its arrow.
+fn arm_head(arm: &Arm) -> String {
+    match &arm.guard {
+        Some(guard) => format!("{} where {} ->", pattern(&arm.pattern), expr(guard)),
+        None => format!("{} ->", pattern(&arm.pattern)),
+    }
+}
+
+pub fn literal(l: &Literal) -> String {
+    match l {
+        Literal::Null => "null".to_string(),
+        Literal::Void => "()".to_string(),
+        Literal::Int(i) => i.to_string(),
+        Literal::Float(f) => {
+            let text = f.to_string();
+            if text.contains('.') {
+                text
+            } else {
+                format!("{}.0", text)
+            }
+        }
+        Literal::Bool(b) => b.to_string(),
+        Literal::String(s) => string_literal(s),
+        Literal::List(elements) => format!("[{}]", join(elements)),
+        Literal::Set(elements) => format!("{{{}}}", join(elements)),
+        Literal::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(k, v)| format!("{}: {}", expr(k), expr(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Literal::Tuple(elements) => format!("({})", join(elements)),
+        Literal::StructInitialization(name, fields, base) => {
+            let mut parts: Vec<String> = base.iter().map(|b| format!("..{}", expr(b))).collect();
+            for field in fields {
+                parts.push(match &field.value {
+                    Expr::Reference(value, _) if *value == field.name => field.name.0.clone(),
+                    value => format!("{}: {}", field.name.0, expr(value)),
+                });
+            }
+            if parts.is_empty() {
+                format!("{} {{}}", name.0)
+            } else {
+                format!("{} {{ {} }}", name.0, parts.join(", "))
+            }
+        }
+        Literal::Closure(value) => match value.as_ref() {
+            Value::Function(f) => match &f.body {
+                Some(Body::Expr(body)) => format!("{} -> {}", parameters(f), expr(body)),
+                _ => parameters(f),
+            },
+            Value::Expr(e) => expr(e),
+            _ => "()".to_string(),
+        },
+    }
+}
+
+fn string_literal(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            '\\' => out.push_str("\\\\"),
+            '\"' => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn parameters(function: &Function) -> String {
+    let mut parts: Vec<String> = function.type_params.iter().map(type_param).collect();
+    parts.extend(function.params.iter().map(param));
+    let mut out = format!("({}", parts.join(", "));
+    if let Some(guard) = &function.guard {
+        out.push_str(" where ");
+        out.push_str(&expr(guard));
+    }
+    out.push(')');
+    out
+}
+
+fn type_param(param: &TypeParam) -> String {
+    match &param.bound.0 {
+        Some(bound) => format!("type {}: {}", param.name.0, type_expr(bound)),
+        None => format!("type {}", param.name.0),
+    }
+}
+
+fn param(param: &Param) -> String {
+    match &param.type_annotation.0 {
+        Some(ty) => format!("{}: {}", pattern(&param.pattern), type_expr(ty)),
+        None => pattern(&param.pattern),
+    }
+}
+
+pub fn pattern(p: &Pattern) -> String {
+    match p {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Binding(name) => name.0.clone(),
+        Pattern::Literal(Literal::Int(i)) if *i < 0 => format!("-{}", i.unsigned_abs()),
+        Pattern::Literal(Literal::Float(f)) if f.is_sign_negative() => {
+            format!("-{}", literal(&Literal::Float(-f)))
+        }
+        Pattern::Literal(l) => literal(l),
+        Pattern::Tuple(elements) => {
+            let elements: Vec<String> = elements.iter().map(pattern).collect();
+            format!("({})", elements.join(", "))
+        }
+        Pattern::Constructor(name, fields) => {
+            let fields: Vec<String> = fields.iter().map(pattern).collect();
+            format!("{}({})", name.0, fields.join(", "))
+        }
+    }
+}
+
+fn enum_entry(entry: &EnumEntry) -> String {
+    match &entry.1 {
+        Some(payload) => {
+            let types: Vec<String> = payload.iter().map(type_expr).collect();
+            format!("{}({})", entry.0.field_name, types.join(", "))
+        }
+        None => entry.0.field_name.clone(),
+    }
+}
+
+fn type_expr(t: &TypeExpr) -> String {
+    match t {
+        TypeExpr::Literal(name) => name.0.clone(),
+        TypeExpr::Grouping => "()".to_string(),
+        TypeExpr::Operator(token, operands) => {
+            let all: Vec<String> = operands.iter().map(type_expr).collect();
+            match token.token_type {
+                TokenType::LBracket => format!("[{}]", all.join(", ")),
+                TokenType::LBrace => format!("{{{}}}", all.join(": ")),
+                TokenType::LParen => format!("({})", all.join(", ")),
+                TokenType::LT => format!("{}<{}>", all[0], all[1..].join(", ")),
+                TokenType::Arrow => match &operands[0] {
+                    TypeExpr::Operator(t, _) if t.token_type == TokenType::Arrow => {
+                        format!("({}) -> {}", all[0], all[1])
+                    }
+                    _ => format!("{} -> {}", all[0], all[1]),
+                },
+                ref other => format!("{}{}", other, all.join(", ")),
+            }
+        }
+    }
+}
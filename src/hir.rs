@@ -0,0 +1,320 @@
+use std::fmt::Write;
+
+use crate::abstract_syntax_tree::{Literal, Memo, Pattern};
+use crate::formatter;
+use crate::tokens::Span;
+
+/// A module after desugaring: items are flat, and every function has a
+/// single body.
+///
+/// Compound assignments, sequences, collection literals, `while` and `for`
+/// loops, `fn` clauses and their `where` guards have no node of their own
+/// here. Every node keeps the span of the syntax it came from, so names
+/// are still looked up in the resolver's tables by span.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub items: Vec<Item>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    /// A module or struct level `const` or `var`.
+    Global {
+        name: String,
+        mutable: bool,
+        value: Expr,
+        span: Span,
+    },
+    /// A `fn`, `proc` or method, named `Owner.name` when it is a method.
+    Function(Function),
+    Struct {
+        name: String,
+        fields: Vec<String>,
+        span: Span,
+    },
+    /// An `enum` with the number of values each variant holds.
+    Enum {
+        name: String,
+        variants: Vec<(String, usize)>,
+        span: Span,
+    },
+}
+
+/// A `fn` or `proc` with all its clauses. The parameters are the names of
+/// the arguments, with the spans they are declared at; functions with
+/// patterns among their parameters take `$0`, `$1` and so on and match
+/// them in their body.
+///
+/// The span is that of the declaration, or of the literal for a closure.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub proc: bool,
+    pub params: Vec<(String, Span)>,
+    pub body: Expr,
+    /// The `@memo` of a cached fn.
+    pub memo: Option<Memo>,
+    pub span: Span,
+}
+
+/// Which surface form a `match` was made from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchSource {
+    Match,
+    /// The clauses of a `fn` or `proc`, which fail with "no clause" when
+    /// none applies.
+    Clauses,
+    /// The step of a `for` loop, matching the next item or `$done`.
+    For,
+}
+
+/// Sequences, tuples, lists, sets and maps.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Collection {
+    Tuple,
+    List,
+    Set,
+    /// Holds one two element tuple per entry.
+    Map,
+}
+
+/// Statements are expressions too: a `Block` runs its expressions in order
+/// and its value is `()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// A scalar literal: `null`, `()`, a number, a bool or a string.
+    Literal(Literal, Span),
+    Name(String, Span),
+    /// A call of a named function, including operators and the intrinsics
+    /// `$iterate` and `$next` that `for` loops become.
+    Call(String, Vec<Expr>, Span),
+    /// `receiver.name(args)`, with the receiver as the first argument.
+    Method(String, Vec<Expr>, Span),
+    Field(Box<Expr>, String, Span),
+    Collection(Collection, Vec<Expr>, Span),
+    /// A struct initialization, with its fields in the order they are
+    /// written and the struct the rest are copied from.
+    Struct(String, Vec<(String, Expr)>, Option<Box<Expr>>, Span),
+    /// A closure or local function.
+    Function(Box<Function>),
+    Match(Box<Expr>, Vec<Arm>, MatchSource, Span),
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>, Span),
+    Block(Vec<Expr>, Span),
+    /// A local `const` or `var`; a local function is in scope in its body.
+    Let {
+        name: String,
+        mutable: bool,
+        value: Box<Expr>,
+        span: Span,
+    },
+    /// Assigns to a name or to a field of one.
+    Assign(Box<Expr>, Box<Expr>, Span),
+    /// Runs its body until a `break` or `return`.
+    Loop(Box<Expr>, Span),
+    Break(Span),
+    Continue(Span),
+    Return(Box<Expr>, Span),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal(_, s)
+            | Expr::Name(_, s)
+            | Expr::Call(_, _, s)
+            | Expr::Method(_, _, s)
+            | Expr::Field(_, _, s)
+            | Expr::Collection(_, _, s)
+            | Expr::Struct(_, _, _, s)
+            | Expr::Match(_, _, _, s)
+            | Expr::If(_, _, _, s)
+            | Expr::Block(_, s)
+            | Expr::Let { span: s, .. }
+            | Expr::Assign(_, _, s)
+            | Expr::Loop(_, s)
+            | Expr::Break(s)
+            | Expr::Continue(s)
+            | Expr::Return(_, s) => *s,
+            Expr::Function(function) => function.span,
+        }
+    }
+}
+
+/// The names a pattern binds are declared at the span of its arm, except
+/// in an arm made from a clause: there each parameter declares its own, at
+/// the span in `params`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+    pub params: Vec<Span>,
+    pub span: Span,
+}
+
+/// The type a method belongs to, if any, and its own name.
+pub fn owner_and_name(name: &str) -> (Option<&str>, &str) {
+    match name.rsplit_once('.') {
+        Some((owner, name)) => (Some(owner), name),
+        None => (None, name),
+    }
+}
+
+/// Whether a call is of a builtin operator, written with symbols.
+pub fn is_operator(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| "+-*/%=!<>".contains(c))
+}
+
+/// Prints a module for `chop parse --emit=hir`, one item after another.
+pub fn print(module: &Module) -> String {
+    let mut out = String::new();
+    for item in &module.items {
+        match item {
+            Item::Global {
+                name,
+                mutable,
+                value,
+                ..
+            } => {
+                let keyword = if *mutable { "var" } else { "const" };
+                let _ = writeln!(out, "{} {} = {}", keyword, name, expr(value, 0));
+            }
+            Item::Function(function) => {
+                let _ = writeln!(out, "{}", self::function(function, 0));
+            }
+            Item::Struct { name, fields, .. } => {
+                let _ = writeln!(out, "struct {} {{ {} }}", name, fields.join(", "));
+            }
+            Item::Enum { name, variants, .. } => {
+                let variants: Vec<String> = variants
+                    .iter()
+                    .map(|(variant, count)| format!("{}/{}", variant, count))
+                    .collect();
+                let _ = writeln!(out, "enum {} {{ {} }}", name, variants.join(", "));
+            }
+        }
+    }
+    out
+}
+
+fn function(function: &Function, indent: usize) -> String {
+    format!(
+        "{}{} {}({}) = {}",
+        function
+            .memo
+            .map_or(String::new(), |memo| format!("@memo({}) ", memo.capacity())),
+        if function.proc { "proc" } else { "fn" },
+        function.name,
+        function
+            .params
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+        expr(&function.body, indent)
+    )
+}
+
+fn expr(e: &Expr, indent: usize) -> String {
+    let pad = "    ".repeat(indent + 1);
+    let end = "    ".repeat(indent);
+    match e {
+        Expr::Literal(literal, _) => formatter::literal(literal),
+        Expr::Name(name, _) => name.clone(),
+        Expr::Call(name, args, _) => format!("{}({})", name, exprs(args, indent)),
+        Expr::Method(name, args, _) => match args.split_first() {
+            Some((receiver, args)) => format!(
+                "{}.{}({})",
+                expr(receiver, indent),
+                name,
+                exprs(args, indent)
+            ),
+            None => format!(".{}()", name),
+        },
+        Expr::Field(receiver, field, _) => format!("{}.{}", expr(receiver, indent), field),
+        Expr::Collection(collection, elements, _) => {
+            let elements = exprs(elements, indent);
+            match collection {
+                Collection::Tuple => format!("tuple({})", elements),
+                Collection::List => format!("list({})", elements),
+                Collection::Set => format!("set({})", elements),
+                Collection::Map => format!("map({})", elements),
+            }
+        }
+        Expr::Struct(name, fields, base, _) => {
+            let mut parts: Vec<String> = base
+                .iter()
+                .map(|b| format!("..{}", expr(b, indent)))
+                .collect();
+            for (field, value) in fields {
+                parts.push(format!("{}: {}", field, expr(value, indent)));
+            }
+            format!("struct {}({})", name, parts.join(", "))
+        }
+        Expr::Function(f) => function(f, indent),
+        Expr::Match(value, arms, source, _) => {
+            let keyword = match source {
+                MatchSource::Match => "match",
+                MatchSource::Clauses => "match clauses",
+                MatchSource::For => "match for",
+            };
+            let mut out = format!("{} {} {{\n", keyword, expr(value, indent));
+            for arm in arms {
+                out.push_str(&pad);
+                out.push_str(&formatter::pattern(&arm.pattern));
+                if let Some(guard) = &arm.guard {
+                    out.push_str(" where ");
+                    out.push_str(&expr(guard, indent + 1));
+                }
+                out.push_str(" -> ");
+                out.push_str(&expr(&arm.body, indent + 1));
+                out.push('\n');
+            }
+            out.push_str(&end);
+            out.push('}');
+            out
+        }
+        Expr::If(condition, then, otherwise, _) => {
+            let mut out = format!("if {} {}", expr(condition, indent), expr(then, indent));
+            if let Some(otherwise) = otherwise {
+                out.push_str(" else ");
+                out.push_str(&expr(otherwise, indent));
+            }
+            out
+        }
+        Expr::Block(exprs, _) => {
+            let mut out = String::from("{\n");
+            for e in exprs {
+                out.push_str(&pad);
+                out.push_str(&expr(e, indent + 1));
+                out.push('\n');
+            }
+            out.push_str(&end);
+            out.push('}');
+            out
+        }
+        Expr::Let {
+            name,
+            mutable,
+            value,
+            ..
+        } => format!(
+            "{} {} = {}",
+            if *mutable { "var" } else { "const" },
+            name,
+            expr(value, indent)
+        ),
+        Expr::Assign(target, value, _) => {
+            format!("{} = {}", expr(target, indent), expr(value, indent))
+        }
+        Expr::Loop(body, _) => format!("loop {}", expr(body, indent)),
+        Expr::Break(_) => "break".to_string(),
+        Expr::Continue(_) => "continue".to_string(),
+        Expr::Return(value, _) => format!("return {}", expr(value, indent)),
+    }
+}
+
+fn exprs(exprs: &[Expr], indent: usize) -> String {
+    let exprs: Vec<String> = exprs.iter().map(|e| expr(e, indent)).collect();
+    exprs.join(", ")
+}
@@ -0,0 +1,1298 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::abstract_syntax_tree::{Domain, Initialization, Literal, Module, Pattern};
+use crate::diagnostics::Diagnostic;
+use crate::hir::{
+    self, is_operator, owner_and_name, Arm, Collection, Expr, Function, Item, MatchSource,
+};
+use crate::memo::{self, Cache};
+use crate::resolver::{inherited_defaults, DefId, DefKind, Resolution};
+use crate::tokens::Span;
+
+/// How deeply calls may nest before the program is stopped.
+pub const MAX_CALL_DEPTH: usize = 100_000;
+
+/// A value while the program runs. Structs and collections are shared, so a
+/// `proc` that changes a field of its parameter changes the caller's value.
+#[derive(Clone)]
+pub enum Value<'a> {
+    Null,
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(Rc<str>),
+    List(Rc<RefCell<Vec<Value<'a>>>>),
+    Set(Rc<RefCell<Vec<Value<'a>>>>),
+    Map(Rc<RefCell<Vec<(Value<'a>, Value<'a>)>>>),
+    Tuple(Rc<[Value<'a>]>),
+    Struct(Rc<Instance<'a>>),
+    Variant(Rc<str>, Rc<[Value<'a>]>),
+    Function(Rc<Callable<'a>>),
+    Builtin(DefId),
+    /// A struct or enum named as a value, as the receiver of `ArrayList.init()`.
+    Type(DefId),
+    /// The iterator of a `for` loop, which the program never sees.
+    Iterator(Rc<Iteration<'a>>),
+}
+
+pub struct Instance<'a> {
+    ty: Rc<StructInfo>,
+    fields: RefCell<Vec<Value<'a>>>,
+}
+
+/// A `fn`, `proc` or closure: its body, the variables it captured and,
+/// for a `@memo` fn, the results it has cached.
+pub struct Callable<'a> {
+    name: String,
+    function: &'a Function,
+    env: Env<'a>,
+    memo: Option<RefCell<Cache<Value<'a>>>>,
+}
+
+/// What a `for` loop goes over and, from its first step on, the items it
+/// has left. What an item is depends on how many names the loop binds,
+/// which only the step knows.
+pub struct Iteration<'a> {
+    iterable: Value<'a>,
+    items: RefCell<Option<std::vec::IntoIter<Value<'a>>>>,
+}
+
+/// A variable: a name the program declares, or one the desugarer made up.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Local {
+    Definition(DefId),
+    Temporary(String),
+}
+
+/// The variables visible to running code. Each is a shared cell, so closures
+/// see later assignments to the variables they capture.
+type Env<'a> = HashMap<Local, Rc<RefCell<Value<'a>>>>;
+
+type Eval<'a> = Result<Value<'a>, Diagnostic>;
+
+/// How running a statement ended.
+enum Flow<'a> {
+    Next,
+    Break,
+    Continue,
+    Return(Tail<'a>),
+}
+
+/// The result of an expression in tail position: a value, or a call that
+/// is left for the caller to make once the current call has returned.
+/// Making it there, rather than inside the call it ends, is what keeps
+/// tail recursion from growing the stack.
+enum Tail<'a> {
+    Value(Value<'a>),
+    Call(Rc<Callable<'a>>, Vec<Value<'a>>, Span),
+}
+
+/// The `proc main` a program starts in.
+pub fn main_proc(module: &Module) -> Option<&Initialization> {
+    module
+        .items
+        .iter()
+        .find(|item| item.domain == Domain::Proc && item.name.0 == "main")
+}
+
+/// Runs `main` from the desugared module, writing what the program prints
+/// to stdout. The parsed module is only asked which default methods each
+/// type inherits.
+pub fn run(
+    module: &Module,
+    hir: &hir::Module,
+    resolution: &Resolution,
+    main: &Initialization,
+) -> Result<(), Diagnostic> {
+    let stdout = std::io::stdout();
+    let mut interpreter = Interpreter {
+        resolution,
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        global_items: HashMap::new(),
+        initializing: Vec::new(),
+        structs: HashMap::new(),
+        methods: HashMap::new(),
+        enums: HashMap::new(),
+        frames: Vec::new(),
+        depth: 0,
+        out: std::io::BufWriter::new(stdout.lock()),
+    };
+    interpreter.collect(&hir.items);
+    for (owner, name, id) in inherited_defaults(module, resolution) {
+        interpreter.methods.entry((owner, name)).or_insert(id);
+    }
+
+    let result = match resolution.declaration(main.span, &main.name.0) {
+        Some(id) => interpreter
+            .call_definition(id, Vec::new(), main.span)
+            .and_then(|tail| interpreter.finish(tail)),
+        None => Ok(Value::Unit),
+    };
+    let _ = interpreter.out.flush();
+    result.map(|_| ())
+}
+
+pub struct StructInfo {
+    name: String,
+    fields: Vec<String>,
+}
+
+struct Interpreter<'a, W: Write> {
+    resolution: &'a Resolution,
+    functions: HashMap<DefId, Rc<Callable<'a>>>,
+    globals: HashMap<DefId, Rc<RefCell<Value<'a>>>>,
+    /// Module and struct level `const`s and `var`s, evaluated on first use.
+    global_items: HashMap<DefId, &'a Expr>,
+    initializing: Vec<DefId>,
+    structs: HashMap<DefId, Rc<StructInfo>>,
+    /// Methods by the name of the type they are called on and their own name.
+    methods: HashMap<(String, String), DefId>,
+    /// The enum each variant belongs to.
+    enums: HashMap<String, String>,
+    frames: Vec<Env<'a>>,
+    /// How many calls are running; `frames` also holds the scopes of `match` arms.
+    depth: usize,
+    out: W,
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    /// Registers the functions, globals, structs and methods among `items`.
+    fn collect(&mut self, items: &'a [Item]) {
+        for item in items {
+            match item {
+                Item::Function(function) => {
+                    let (owner, name) = owner_and_name(&function.name);
+                    let Some(id) = self.resolution.declaration(function.span, name) else {
+                        continue;
+                    };
+                    if let Some(owner) = owner {
+                        self.methods
+                            .insert((owner.to_string(), name.to_string()), id);
+                    }
+                    let callable = Callable {
+                        name: name.to_string(),
+                        function,
+                        env: HashMap::new(),
+                        memo: function
+                            .memo
+                            .map(|memo| RefCell::new(Cache::new(memo.capacity()))),
+                    };
+                    self.functions.insert(id, Rc::new(callable));
+                }
+                Item::Struct { name, fields, span } => {
+                    if let Some(id) = self.resolution.declaration(*span, name) {
+                        let name = name.clone();
+                        let fields = fields.clone();
+                        self.structs
+                            .insert(id, Rc::new(StructInfo { name, fields }));
+                    }
+                }
+                Item::Enum { name, variants, .. } => {
+                    for (variant, _) in variants {
+                        self.enums.insert(variant.clone(), name.clone());
+                    }
+                }
+                Item::Global {
+                    name, value, span, ..
+                } => {
+                    let (_, name) = owner_and_name(name);
+                    if let Some(id) = self.resolution.declaration(*span, name) {
+                        self.global_items.insert(id, value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn call_definition(
+        &mut self,
+        id: DefId,
+        args: Vec<Value<'a>>,
+        span: Span,
+    ) -> Result<Tail<'a>, Diagnostic> {
+        match self.functions.get(&id) {
+            Some(callable) => Ok(Tail::Call(callable.clone(), args, span)),
+            None => Err(Diagnostic::error(
+                format!("`{}` cannot be called", self.resolution.definition(id).name),
+                span,
+            )),
+        }
+    }
+
+    /// Makes the call a `Tail` holds, and then every call that ends it in
+    /// tail position, one after the other.
+    fn finish(&mut self, tail: Tail<'a>) -> Eval<'a> {
+        let mut tail = tail;
+        loop {
+            match tail {
+                Tail::Value(value) => return Ok(value),
+                Tail::Call(callable, args, span) => tail = self.call(&callable, args, span)?,
+            }
+        }
+    }
+
+    /// Runs the function up to the call it ends with, if any. A `@memo` fn
+    /// looks the arguments up in its cache first, and otherwise makes that
+    /// last call too, to cache the result.
+    fn call(
+        &mut self,
+        callable: &Callable<'a>,
+        args: Vec<Value<'a>>,
+        span: Span,
+    ) -> Result<Tail<'a>, Diagnostic> {
+        let Some(memo) = &callable.memo else {
+            return self.enter(callable, args, span);
+        };
+        let hash = hash(&args);
+        let cached = memo
+            .borrow()
+            .get(hash, |key| key.iter().zip(&args).all(|(k, a)| equal(k, a)))
+            .map(snapshot);
+        if let Some(result) = cached {
+            return Ok(Tail::Value(result));
+        }
+        let key = args.iter().map(snapshot).collect();
+        let tail = self.enter(callable, args, span)?;
+        // The call is still running until the result is known.
+        self.depth += 1;
+        let result = self.finish(tail);
+        self.depth -= 1;
+        let result = result?;
+        memo.borrow_mut().insert(hash, key, snapshot(&result));
+        Ok(Tail::Value(result))
+    }
+
+    /// Binds the arguments and runs the body. A function made from clauses
+    /// runs the first whose patterns and guard accept the arguments.
+    fn enter(
+        &mut self,
+        callable: &Callable<'a>,
+        args: Vec<Value<'a>>,
+        span: Span,
+    ) -> Result<Tail<'a>, Diagnostic> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(Diagnostic::error(
+                format!(
+                    "calls to `{}` nest more than {} deep",
+                    callable.name, MAX_CALL_DEPTH
+                ),
+                span,
+            ));
+        }
+        let function = callable.function;
+        let Expr::Match(_, arms, MatchSource::Clauses, _) = &function.body else {
+            let mut env = callable.env.clone();
+            for ((param, param_span), arg) in function.params.iter().zip(args) {
+                if let Some(id) = self.resolution.declaration(*param_span, param) {
+                    env.insert(Local::Definition(id), Rc::new(RefCell::new(arg)));
+                }
+            }
+            self.frames.push(env);
+            self.depth += 1;
+            let result = self.body(&function.body);
+            self.depth -= 1;
+            self.frames.pop();
+            return result;
+        };
+        for arm in arms {
+            let patterns: Vec<&Pattern> = match &arm.pattern {
+                Pattern::Tuple(patterns) if args.len() != 1 => patterns.iter().collect(),
+                pattern => vec![pattern],
+            };
+            if patterns.len() != args.len() {
+                continue;
+            }
+            let mut env = callable.env.clone();
+            let matched = patterns
+                .into_iter()
+                .zip(&arm.params)
+                .zip(&args)
+                .all(|((pattern, span), arg)| self.bind(pattern, arg, *span, &mut env));
+            if !matched {
+                continue;
+            }
+            self.frames.push(env);
+            self.depth += 1;
+            let result = self.arm(arm);
+            self.depth -= 1;
+            self.frames.pop();
+            if let Some(tail) = result? {
+                return Ok(tail);
+            }
+        }
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        Err(Diagnostic::error(
+            format!(
+                "no clause of `{}` matches the arguments ({})",
+                callable.name,
+                args.join(", ")
+            ),
+            span,
+        ))
+    }
+
+    /// The result of the body of a function or arm: a block runs until it
+    /// returns, and anything else is the result itself.
+    fn body(&mut self, body: &'a Expr) -> Result<Tail<'a>, Diagnostic> {
+        match body {
+            Expr::Block(..) => match self.statement(body)? {
+                Flow::Return(tail) => Ok(tail),
+                _ => Ok(Tail::Value(Value::Unit)),
+            },
+            body => self.tail(body),
+        }
+    }
+
+    /// Matches `value` against `pattern`, binding the names it declares at `span`.
+    fn bind(&self, pattern: &Pattern, value: &Value<'a>, span: Span, env: &mut Env<'a>) -> bool {
+        match (pattern, value) {
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Binding(name), _) => {
+                if let Some(id) = self.resolution.declaration(span, &name.0) {
+                    env.insert(Local::Definition(id), Rc::new(RefCell::new(value.clone())));
+                }
+                true
+            }
+            (Pattern::Literal(literal), _) => {
+                scalar(literal).is_some_and(|literal| equal(&literal, value))
+            }
+            (Pattern::Tuple(patterns), Value::Tuple(values)) => {
+                patterns.len() == values.len()
+                    && patterns
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(p, v)| self.bind(p, v, span, env))
+            }
+            (Pattern::Constructor(name, patterns), Value::Variant(variant, values)) => {
+                name.0 == **variant
+                    && patterns.len() == values.len()
+                    && patterns
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(p, v)| self.bind(p, v, span, env))
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs an expression whose value is not used.
+    fn statement(&mut self, expr: &'a Expr) -> Result<Flow<'a>, Diagnostic> {
+        match expr {
+            Expr::Block(exprs, _) => {
+                for expr in exprs {
+                    match self.statement(expr)? {
+                        Flow::Next => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Expr::Let {
+                name, value, span, ..
+            } => self.local(name, value, *span)?,
+            Expr::Assign(target, value, span) => self.assign(target, value, *span)?,
+            Expr::Return(value, _) => return Ok(Flow::Return(self.tail(value)?)),
+            Expr::Break(_) => return Ok(Flow::Break),
+            Expr::Continue(_) => return Ok(Flow::Continue),
+            Expr::If(condition, then, otherwise, _) => {
+                if self.condition(condition)? {
+                    return self.statement(then);
+                } else if let Some(otherwise) = otherwise {
+                    return self.statement(otherwise);
+                }
+            }
+            Expr::Loop(body, _) => loop {
+                match self.statement(body)? {
+                    Flow::Break => break,
+                    Flow::Return(tail) => return Ok(Flow::Return(tail)),
+                    Flow::Next | Flow::Continue => {}
+                }
+            },
+            Expr::Match(next, arms, MatchSource::For, span) => return self.step(next, arms, *span),
+            other => {
+                self.expr(other)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// A step of the loop a `for` becomes: the next item of the iterator,
+    /// bound to the names of the loop, or leaving the loop when there are
+    /// no more. The names are bound in the frame of the loop itself.
+    fn step(
+        &mut self,
+        next: &'a Expr,
+        arms: &'a [Arm],
+        span: Span,
+    ) -> Result<Flow<'a>, Diagnostic> {
+        let (Expr::Call(_, args, _), [_, item]) = (next, arms) else {
+            return Err(Diagnostic::error(
+                "this loop has no iterator".to_string(),
+                span,
+            ));
+        };
+        let Some(Expr::Name(iterator, _)) = args.first() else {
+            return Err(Diagnostic::error(
+                "this loop has no iterator".to_string(),
+                span,
+            ));
+        };
+        let count = match &item.pattern {
+            Pattern::Tuple(names) => names.len(),
+            _ => 1,
+        };
+        let value = self.next(iterator, count, span)?;
+        for arm in arms {
+            let mut frame = std::mem::take(self.frame());
+            let matched = self.bind(&arm.pattern, &value, arm.span, &mut frame);
+            *self.frame() = frame;
+            if matched {
+                return self.statement(&arm.body);
+            }
+        }
+        Ok(Flow::Break)
+    }
+
+    /// The next item of the iterator named `iterator`, or `$done`.
+    fn next(&mut self, iterator: &str, count: usize, span: Span) -> Eval<'a> {
+        let value = self.temporary(iterator, span)?.borrow().clone();
+        let Value::Iterator(iteration) = value else {
+            return Err(Diagnostic::error(
+                format!("`{}` is not an iterator", value),
+                span,
+            ));
+        };
+        if iteration.items.borrow().is_none() {
+            let items: Vec<Value<'a>> = self
+                .elements(&iteration.iterable, count, span)?
+                .into_iter()
+                .map(|mut item| match count {
+                    1 => item.pop().unwrap_or(Value::Unit),
+                    _ => Value::Tuple(item.into()),
+                })
+                .collect();
+            *iteration.items.borrow_mut() = Some(items.into_iter());
+        }
+        let item = iteration
+            .items
+            .borrow_mut()
+            .as_mut()
+            .and_then(Iterator::next);
+        Ok(item.unwrap_or_else(|| Value::Variant("$done".into(), Rc::new([]))))
+    }
+
+    /// What a `for` loop with `count` names binds on each iteration.
+    fn elements(
+        &self,
+        iterable: &Value<'a>,
+        count: usize,
+        span: Span,
+    ) -> Result<Vec<Vec<Value<'a>>>, Diagnostic> {
+        let items: Vec<Value<'a>> = match iterable {
+            Value::List(items) | Value::Set(items) => items.borrow().clone(),
+            Value::Map(entries) if count == 2 => {
+                let entries = entries.borrow();
+                return Ok(entries
+                    .iter()
+                    .map(|(k, v)| vec![k.clone(), v.clone()])
+                    .collect());
+            }
+            Value::Map(entries) => entries.borrow().iter().map(|(k, _)| k.clone()).collect(),
+            Value::String(s) => s
+                .chars()
+                .map(|c| Value::String(c.to_string().into()))
+                .collect(),
+            other => {
+                return Err(Diagnostic::error(
+                    format!("cannot loop over `{}`", other),
+                    span,
+                ))
+            }
+        };
+        items
+            .into_iter()
+            .map(|item| match (count, item) {
+                (1, item) => Ok(vec![item]),
+                (n, Value::Tuple(values)) if values.len() == n => Ok(values.to_vec()),
+                (n, item) => Err(Diagnostic::error(
+                    format!("cannot split `{}` into {} loop variables", item, n),
+                    span,
+                )),
+            })
+            .collect()
+    }
+
+    /// Declares a local: functions capture the variables around them,
+    /// including themselves, so they can recurse. A name the desugarer made
+    /// up is a temporary of its own.
+    fn local(&mut self, name: &str, value: &'a Expr, span: Span) -> Result<(), Diagnostic> {
+        if name.starts_with('$') {
+            let value = self.expr(value)?;
+            let local = Local::Temporary(name.to_string());
+            self.frame().insert(local, Rc::new(RefCell::new(value)));
+            return Ok(());
+        }
+        let Some(id) = self.resolution.declaration(span, name) else {
+            return Ok(());
+        };
+        let kind = self.resolution.definition(id).kind;
+        match value {
+            Expr::Function(function) if matches!(kind, DefKind::Fn | DefKind::Proc) => {
+                self.define(id, Value::Unit);
+                let callable = Callable {
+                    name: name.to_string(),
+                    function,
+                    env: self.frame().clone(),
+                    memo: None,
+                };
+                *self.frame()[&Local::Definition(id)].borrow_mut() =
+                    Value::Function(Rc::new(callable));
+            }
+            value => {
+                let value = self.expr(value)?;
+                self.define(id, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self) -> &mut Env<'a> {
+        self.frames.last_mut().expect("code runs inside a call")
+    }
+
+    fn define(&mut self, id: DefId, value: Value<'a>) {
+        self.frame()
+            .insert(Local::Definition(id), Rc::new(RefCell::new(value)));
+    }
+
+    fn assign(&mut self, target: &'a Expr, value: &'a Expr, span: Span) -> Result<(), Diagnostic> {
+        match target {
+            Expr::Field(receiver, field, _) => {
+                let receiver = self.expr(receiver)?;
+                let value = self.expr(value)?;
+                let Value::Struct(instance) = &receiver else {
+                    return Err(Diagnostic::error(
+                        format!("`{}` has no field `{}`", receiver, field),
+                        span,
+                    ));
+                };
+                let index = self.field_index(instance, field, span)?;
+                instance.fields.borrow_mut()[index] = value;
+            }
+            Expr::Name(name, name_span) => {
+                let value = self.expr(value)?;
+                let cell = match name.starts_with('$') {
+                    true => self.temporary(name, *name_span)?,
+                    false => self.variable(name, *name_span)?,
+                };
+                *cell.borrow_mut() = value;
+            }
+            other => {
+                return Err(Diagnostic::error(
+                    "only variables and their fields can be assigned to".to_string(),
+                    other.span(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// The cell of a local or global variable.
+    fn variable(&mut self, name: &str, span: Span) -> Result<Rc<RefCell<Value<'a>>>, Diagnostic> {
+        let Some(&id) = self.resolution.references.get(&span) else {
+            return Err(unknown(name, span));
+        };
+        let local = Local::Definition(id);
+        if let Some(cell) = self.frames.last().and_then(|frame| frame.get(&local)) {
+            return Ok(cell.clone());
+        }
+        self.global(id, span)
+    }
+
+    /// The cell of a name the desugarer made up.
+    fn temporary(&self, name: &str, span: Span) -> Result<Rc<RefCell<Value<'a>>>, Diagnostic> {
+        let local = Local::Temporary(name.to_string());
+        match self.frames.last().and_then(|frame| frame.get(&local)) {
+            Some(cell) => Ok(cell.clone()),
+            None => Err(unknown(name, span)),
+        }
+    }
+
+    /// A module or struct level `const` or `var`, evaluated on first use.
+    fn global(&mut self, id: DefId, span: Span) -> Result<Rc<RefCell<Value<'a>>>, Diagnostic> {
+        if let Some(cell) = self.globals.get(&id) {
+            return Ok(cell.clone());
+        }
+        let name = &self.resolution.definition(id).name;
+        let Some(expr) = self.global_items.get(&id).copied() else {
+            return Err(Diagnostic::error(
+                format!("`{}` has no value here", name),
+                span,
+            ));
+        };
+        if self.initializing.contains(&id) {
+            return Err(Diagnostic::error(
+                format!("`{}` is used while it is being initialized", name),
+                span,
+            ));
+        }
+        self.initializing.push(id);
+        self.frames.push(HashMap::new());
+        let value = self.expr(expr);
+        self.frames.pop();
+        self.initializing.pop();
+        let cell = Rc::new(RefCell::new(value?));
+        self.globals.insert(id, cell.clone());
+        Ok(cell)
+    }
+
+    fn condition(&mut self, expr: &'a Expr) -> Result<bool, Diagnostic> {
+        match self.expr(expr)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(Diagnostic::error(
+                format!("expected `true` or `false`, found `{}`", other),
+                expr.span(),
+            )),
+        }
+    }
+
+    fn exprs(&mut self, exprs: &'a [Expr]) -> Result<Vec<Value<'a>>, Diagnostic> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            values.push(self.expr(expr)?);
+        }
+        Ok(values)
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Eval<'a> {
+        match expr {
+            Expr::Literal(literal, _) => Ok(scalar(literal).unwrap_or(Value::Null)),
+            Expr::Name(name, span) if name.starts_with('$') => {
+                let cell = self.temporary(name, *span)?;
+                let value = cell.borrow().clone();
+                Ok(value)
+            }
+            Expr::Name(name, span) => self.reference(name, *span),
+            Expr::Call(..) | Expr::Method(..) | Expr::Match(..) => {
+                let tail = self.tail(expr)?;
+                self.finish(tail)
+            }
+            Expr::Field(receiver, field, span) => {
+                let receiver = self.expr(receiver)?;
+                match &receiver {
+                    Value::Struct(instance) => {
+                        let index = self.field_index(instance, field, *span)?;
+                        Ok(instance.fields.borrow()[index].clone())
+                    }
+                    Value::Tuple(values) => field
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| values.get(i).cloned())
+                        .ok_or_else(|| no_field(&receiver, field, *span)),
+                    _ => Err(no_field(&receiver, field, *span)),
+                }
+            }
+            Expr::Collection(collection, elements, _) => self.collection(collection, elements),
+            Expr::Struct(name, inits, base, _) => self.structure(name, inits, base.as_deref()),
+            Expr::Function(function) => Ok(Value::Function(Rc::new(Callable {
+                name: function.name.clone(),
+                function,
+                env: self.frames.last().cloned().unwrap_or_default(),
+                memo: None,
+            }))),
+            Expr::If(..)
+            | Expr::Block(..)
+            | Expr::Let { .. }
+            | Expr::Assign(..)
+            | Expr::Loop(..)
+            | Expr::Break(_)
+            | Expr::Continue(_)
+            | Expr::Return(..) => {
+                self.statement(expr)?;
+                Ok(Value::Unit)
+            }
+        }
+    }
+
+    /// Evaluates an expression whose value the current call returns, leaving
+    /// a call it ends with to the caller.
+    fn tail(&mut self, expr: &'a Expr) -> Result<Tail<'a>, Diagnostic> {
+        match expr {
+            Expr::Call(name, args, span) => self.call_expr(name, args, *span),
+            Expr::Method(name, args, span) => self.method_call(name, args, *span),
+            Expr::Match(value, arms, _, span) => self.match_expr(value, arms, *span),
+            _ => self.expr(expr).map(Tail::Value),
+        }
+    }
+
+    fn collection(&mut self, collection: &Collection, elements: &'a [Expr]) -> Eval<'a> {
+        let shared = |values| Rc::new(RefCell::new(values));
+        let values = self.exprs(elements)?;
+        Ok(match collection {
+            Collection::Tuple => Value::Tuple(values.into()),
+            Collection::List => Value::List(shared(values)),
+            Collection::Set => {
+                let mut set: Vec<Value<'a>> = Vec::new();
+                for value in values {
+                    if !set.iter().any(|v| equal(v, &value)) {
+                        set.push(value);
+                    }
+                }
+                Value::Set(shared(set))
+            }
+            Collection::Map => {
+                let mut map: Vec<(Value<'a>, Value<'a>)> = Vec::new();
+                for entry in values {
+                    let Value::Tuple(entry) = entry else { continue };
+                    let [key, value] = &entry[..] else { continue };
+                    match map.iter_mut().find(|(k, _)| equal(k, key)) {
+                        Some(entry) => entry.1 = value.clone(),
+                        None => map.push((key.clone(), value.clone())),
+                    }
+                }
+                Value::Map(Rc::new(RefCell::new(map)))
+            }
+        })
+    }
+
+    /// A struct initialization. The base and the fields are evaluated in
+    /// the order written, then the fields are put in the order they are
+    /// declared.
+    fn structure(
+        &mut self,
+        name: &str,
+        inits: &'a [(String, Expr)],
+        base: Option<&'a Expr>,
+    ) -> Eval<'a> {
+        let base = match base {
+            Some(base) => Some(self.expr(base)?),
+            None => None,
+        };
+        let mut values = Vec::new();
+        for (_, value) in inits {
+            values.push(self.expr(value)?);
+        }
+        let Some(ty) = self.structs.values().find(|info| info.name == name) else {
+            return Ok(Value::Unit);
+        };
+        let mut fields = match &base {
+            Some(Value::Struct(instance)) => instance.fields.borrow().clone(),
+            _ => vec![Value::Unit; ty.fields.len()],
+        };
+        for ((field, _), value) in inits.iter().zip(values) {
+            if let Some(index) = ty.fields.iter().position(|f| f == field) {
+                fields[index] = value;
+            }
+        }
+        Ok(Value::Struct(Rc::new(Instance {
+            ty: ty.clone(),
+            fields: RefCell::new(fields),
+        })))
+    }
+
+    fn reference(&mut self, name: &str, span: Span) -> Eval<'a> {
+        let Some(&id) = self.resolution.references.get(&span) else {
+            return Err(unknown(name, span));
+        };
+        let local = Local::Definition(id);
+        if let Some(cell) = self.frames.last().and_then(|frame| frame.get(&local)) {
+            return Ok(cell.borrow().clone());
+        }
+        match self.resolution.definition(id).kind {
+            DefKind::Fn | DefKind::Proc if self.functions.contains_key(&id) => {
+                Ok(Value::Function(self.functions[&id].clone()))
+            }
+            DefKind::Variant => Ok(Value::Variant(name.into(), Rc::new([]))),
+            DefKind::BuiltinFn | DefKind::BuiltinProc => Ok(Value::Builtin(id)),
+            DefKind::Struct | DefKind::Enum => Ok(Value::Type(id)),
+            _ => {
+                let cell = self.global(id, span)?;
+                let value = cell.borrow().clone();
+                Ok(value)
+            }
+        }
+    }
+
+    /// A call of a named function. Operators that compound assignments
+    /// spelled out and the iterator a `for` loop starts with are made
+    /// directly; they have no definition to look up.
+    fn call_expr(
+        &mut self,
+        name: &str,
+        args: &'a [Expr],
+        span: Span,
+    ) -> Result<Tail<'a>, Diagnostic> {
+        if name == "$iterate" {
+            let iterable = match args.first() {
+                Some(iterable) => self.expr(iterable)?,
+                None => Value::Unit,
+            };
+            return Ok(Tail::Value(Value::Iterator(Rc::new(Iteration {
+                iterable,
+                items: RefCell::new(None),
+            }))));
+        }
+        let Some(&id) = self.resolution.references.get(&span) else {
+            if is_operator(name) {
+                let args = self.exprs(args)?;
+                return operator(name, &args, span).map(Tail::Value);
+            }
+            return Err(unknown(name, span));
+        };
+        if self.resolution.class_methods.contains(&id) && !args.is_empty() {
+            return self.method_call(name, args, span);
+        }
+        let kind = self.resolution.definition(id).kind;
+        if kind == DefKind::BuiltinFn {
+            if let ("and" | "or", [lhs, rhs]) = (name, args) {
+                let value = match (name, self.condition(lhs)?) {
+                    ("and", false) => false,
+                    ("or", true) => true,
+                    _ => self.condition(rhs)?,
+                };
+                return Ok(Tail::Value(Value::Bool(value)));
+            }
+        }
+        let args = self.exprs(args)?;
+        match kind {
+            DefKind::Variant | DefKind::Newtype => {
+                Ok(Tail::Value(Value::Variant(name.into(), args.into())))
+            }
+            _ => {
+                let callee = self.reference(name, span)?;
+                self.apply(callee, args, span)
+            }
+        }
+    }
+
+    /// Calls a value that holds a function.
+    fn apply(
+        &mut self,
+        callee: Value<'a>,
+        args: Vec<Value<'a>>,
+        span: Span,
+    ) -> Result<Tail<'a>, Diagnostic> {
+        match callee {
+            Value::Function(callable) => Ok(Tail::Call(callable, args, span)),
+            Value::Builtin(id) => {
+                let name = self.resolution.definition(id).name.as_str();
+                self.builtin(name, args, span).map(Tail::Value)
+            }
+            other => Err(Diagnostic::error(
+                format!("`{}` is not a function", other),
+                span,
+            )),
+        }
+    }
+
+    fn builtin(&mut self, name: &str, args: Vec<Value<'a>>, span: Span) -> Eval<'a> {
+        match name {
+            "print" | "println" => {
+                for arg in &args {
+                    let _ = write!(self.out, "{}", arg);
+                }
+                if name == "println" {
+                    let _ = writeln!(self.out);
+                }
+                Ok(Value::Unit)
+            }
+            _ => operator(name, &args, span),
+        }
+    }
+
+    /// `receiver.name(args)`: a method of the receiver's type, or a function
+    /// of a struct named directly, as in `ArrayList.init()`.
+    fn method_call(
+        &mut self,
+        name: &str,
+        args: &'a [Expr],
+        span: Span,
+    ) -> Result<Tail<'a>, Diagnostic> {
+        let receiver = self.expr(&args[0])?;
+        let rest = self.exprs(&args[1..])?;
+        if let Value::Type(ty) = receiver {
+            if let Some(info) = self.structs.get(&ty).cloned() {
+                if name == "init" {
+                    if rest.len() != info.fields.len() {
+                        return Err(Diagnostic::error(
+                            format!(
+                                "`{}.init` takes {} fields, but {} were given",
+                                info.name,
+                                info.fields.len(),
+                                rest.len()
+                            ),
+                            span,
+                        ));
+                    }
+                    return Ok(Tail::Value(Value::Struct(Rc::new(Instance {
+                        ty: info,
+                        fields: RefCell::new(rest),
+                    }))));
+                }
+            }
+            let owner = self.resolution.definition(ty).name.clone();
+            return match self.methods.get(&(owner.clone(), name.to_string())) {
+                Some(&method) => self.call_definition(method, rest, span),
+                None => Err(Diagnostic::error(
+                    format!("`{}` has no function `{}`", owner, name),
+                    span,
+                )),
+            };
+        }
+        let owner = self.type_name(&receiver);
+        match self.methods.get(&(owner.clone(), name.to_string())) {
+            Some(&method) => {
+                let mut values = vec![receiver];
+                values.extend(rest);
+                self.call_definition(method, values, span)
+            }
+            None => Err(Diagnostic::error(
+                format!("`{}` has no method `{}`", owner, name),
+                span,
+            )),
+        }
+    }
+
+    /// The name methods are looked up under for a value.
+    fn type_name(&self, value: &Value<'a>) -> String {
+        match value {
+            Value::Null => "null".to_string(),
+            Value::Unit => "()".to_string(),
+            Value::Int(_) => "int".to_string(),
+            Value::Float(_) => "float".to_string(),
+            Value::Bool(_) => "bool".to_string(),
+            Value::String(_) => "string".to_string(),
+            Value::List(_) => "list".to_string(),
+            Value::Set(_) => "set".to_string(),
+            Value::Map(_) => "map".to_string(),
+            Value::Tuple(_) => "tuple".to_string(),
+            Value::Struct(instance) => instance.ty.name.clone(),
+            Value::Variant(name, _) => self
+                .enums
+                .get(name.as_ref())
+                .cloned()
+                .unwrap_or_else(|| name.to_string()),
+            Value::Function(_) | Value::Builtin(_) => "function".to_string(),
+            Value::Type(id) => self.resolution.definition(*id).name.clone(),
+            Value::Iterator(_) => "iterator".to_string(),
+        }
+    }
+
+    fn field_index(
+        &self,
+        instance: &Instance,
+        field: &str,
+        span: Span,
+    ) -> Result<usize, Diagnostic> {
+        let info = &instance.ty;
+        info.fields.iter().position(|f| f == field).ok_or_else(|| {
+            Diagnostic::error(format!("`{}` has no field `{}`", info.name, field), span)
+        })
+    }
+
+    fn match_expr(
+        &mut self,
+        value: &'a Expr,
+        arms: &'a [Arm],
+        span: Span,
+    ) -> Result<Tail<'a>, Diagnostic> {
+        let value = self.expr(value)?;
+        for arm in arms {
+            // Each arm gets its own copy of the frame, so that the names a
+            // rejected arm bound do not leak into the next.
+            let mut env = self.frames.last().cloned().unwrap_or_default();
+            if !self.bind(&arm.pattern, &value, arm.span, &mut env) {
+                continue;
+            }
+            self.frames.push(env);
+            let result = self.arm(arm);
+            self.frames.pop();
+            if let Some(result) = result? {
+                return Ok(result);
+            }
+        }
+        Err(Diagnostic::error(
+            format!("no arm of this `match` matches `{}`", value),
+            span,
+        ))
+    }
+
+    fn arm(&mut self, arm: &'a Arm) -> Result<Option<Tail<'a>>, Diagnostic> {
+        if let Some(guard) = &arm.guard {
+            if !self.condition(guard)? {
+                return Ok(None);
+            }
+        }
+        self.body(&arm.body).map(Some)
+    }
+}
+
+/// The builtin operators. Integer arithmetic is checked, and an integer
+/// meets a float when an integer literal is used as a float.
+fn operator<'a>(name: &str, args: &[Value<'a>], span: Span) -> Eval<'a> {
+    let int = |result: Option<i64>, a: i64, b: i64| {
+        result.map(Value::Int).ok_or_else(|| {
+            let message = if b == 0 && matches!(name, "/" | "%") {
+                "division by zero".to_string()
+            } else {
+                format!("`{} {} {}` overflows an `int`", a, name, b)
+            };
+            Diagnostic::error(message, span)
+        })
+    };
+    match (name, args) {
+        ("not", [Value::Bool(b)]) => Ok(Value::Bool(!b)),
+        ("negate", [Value::Int(i)]) => i
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| Diagnostic::error(format!("negating `{}` overflows an `int`", i), span)),
+        ("negate", [Value::Float(x)]) => Ok(Value::Float(-x)),
+        ("+", [Value::Int(a), Value::Int(b)]) => int(a.checked_add(*b), *a, *b),
+        ("-", [Value::Int(a), Value::Int(b)]) => int(a.checked_sub(*b), *a, *b),
+        ("*", [Value::Int(a), Value::Int(b)]) => int(a.checked_mul(*b), *a, *b),
+        ("/", [Value::Int(a), Value::Int(b)]) => int(a.checked_div(*b), *a, *b),
+        ("%", [Value::Int(a), Value::Int(b)]) => int(a.checked_rem(*b), *a, *b),
+        ("==", [a, b]) => Ok(Value::Bool(equal(a, b))),
+        ("!=", [a, b]) => Ok(Value::Bool(!equal(a, b))),
+        ("<" | "<=" | ">" | ">=", [a, b]) => match compare(a, b) {
+            Some(ordering) => Ok(Value::Bool(match name {
+                "<" => ordering.is_lt(),
+                "<=" => ordering.is_le(),
+                ">" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })),
+            None => Err(Diagnostic::error(
+                format!("cannot compare `{}` and `{}`", a, b),
+                span,
+            )),
+        },
+        ("+" | "-" | "*" | "/" | "%", [a, b]) => match (float(a), float(b)) {
+            (Some(a), Some(b)) => Ok(Value::Float(match name {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                _ => a % b,
+            })),
+            _ => Err(Diagnostic::error(
+                format!("cannot apply `{}` to `{}` and `{}`", name, a, b),
+                span,
+            )),
+        },
+        _ => Err(Diagnostic::error(
+            format!("`{}` cannot be applied to these arguments", name),
+            span,
+        )),
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => float(a)?.partial_cmp(&float(b)?),
+    }
+}
+
+/// Structural equality; functions are never equal.
+pub fn equal<'a>(a: &Value<'a>, b: &Value<'a>) -> bool {
+    let all = |xs: &[Value<'a>], ys: &[Value<'a>]| {
+        xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| equal(x, y))
+    };
+    match (a, b) {
+        (Value::Null, Value::Null) | (Value::Unit, Value::Unit) => true,
+        (Value::Int(x), Value::Int(y)) => x == y,
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => float(a) == float(b),
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::String(x), Value::String(y)) => x == y,
+        (Value::List(x), Value::List(y)) => all(&x.borrow(), &y.borrow()),
+        (Value::Set(x), Value::Set(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len() && x.iter().all(|v| y.iter().any(|w| equal(v, w)))
+        }
+        (Value::Map(x), Value::Map(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len()
+                && x.iter().all(|(k, v)| {
+                    y.iter()
+                        .find(|(l, _)| equal(k, l))
+                        .is_some_and(|(_, w)| equal(v, w))
+                })
+        }
+        (Value::Tuple(x), Value::Tuple(y)) => all(x, y),
+        (Value::Struct(x), Value::Struct(y)) => {
+            Rc::ptr_eq(x, y)
+                || (Rc::ptr_eq(&x.ty, &y.ty) && all(&x.fields.borrow(), &y.fields.borrow()))
+        }
+        (Value::Variant(n, xs), Value::Variant(m, ys)) => n == m && all(xs, ys),
+        (Value::Type(x), Value::Type(y)) => x == y,
+        _ => false,
+    }
+}
+
+/// A hash of values that agrees with `equal`, as `memo::mix` describes:
+/// ints and floats that are equal hash alike.
+fn hash(values: &[Value]) -> u64 {
+    values.iter().fold(memo::SEED, |hash, v| memo::mix(hash, word(v)))
+}
+
+fn word(value: &Value) -> u64 {
+    let sum = |words: &mut dyn Iterator<Item = u64>| words.fold(0u64, u64::wrapping_add);
+    match value {
+        Value::Int(i) => *i as u64,
+        Value::Float(x) if x.fract() == 0.0 && x.abs() < i64::MAX as f64 => *x as i64 as u64,
+        Value::Float(x) => x.to_bits(),
+        Value::Bool(b) => *b as u64,
+        Value::String(s) => memo::string(s.as_bytes()),
+        Value::List(items) => hash(&items.borrow()),
+        Value::Set(items) => sum(&mut items.borrow().iter().map(word)),
+        Value::Map(entries) => sum(
+            &mut entries
+                .borrow()
+                .iter()
+                .map(|(k, v)| memo::mix(word(k), word(v))),
+        ),
+        Value::Tuple(items) => hash(items),
+        Value::Struct(instance) => memo::mix(
+            memo::string(instance.ty.name.as_bytes()),
+            hash(&instance.fields.borrow()),
+        ),
+        Value::Variant(name, items) => memo::mix(memo::string(name.as_bytes()), hash(items)),
+        _ => 0,
+    }
+}
+
+/// A copy of a value that shares nothing mutable with it, which a cache
+/// keeps so that later changes to the value do not reach it.
+fn snapshot<'a>(value: &Value<'a>) -> Value<'a> {
+    let all = |items: &[Value<'a>]| items.iter().map(snapshot).collect::<Vec<_>>();
+    match value {
+        Value::List(items) => Value::List(Rc::new(RefCell::new(all(&items.borrow())))),
+        Value::Set(items) => Value::Set(Rc::new(RefCell::new(all(&items.borrow())))),
+        Value::Map(entries) => Value::Map(Rc::new(RefCell::new(
+            entries
+                .borrow()
+                .iter()
+                .map(|(k, v)| (snapshot(k), snapshot(v)))
+                .collect(),
+        ))),
+        Value::Tuple(items) => Value::Tuple(all(items).into()),
+        Value::Struct(instance) => Value::Struct(Rc::new(Instance {
+            ty: instance.ty.clone(),
+            fields: RefCell::new(all(&instance.fields.borrow())),
+        })),
+        Value::Variant(name, items) => Value::Variant(name.clone(), all(items).into()),
+        other => other.clone(),
+    }
+}
+
+fn scalar<'a>(literal: &Literal) -> Option<Value<'a>> {
+    Some(match literal {
+        Literal::Null => Value::Null,
+        Literal::Void => Value::Unit,
+        Literal::Int(i) => Value::Int(*i),
+        Literal::Float(x) => Value::Float(*x),
+        Literal::Bool(b) => Value::Bool(*b),
+        Literal::String(s) => Value::String(s.as_str().into()),
+        _ => return None,
+    })
+}
+
+fn unknown(name: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(format!("`{}` is not defined", name), span)
+}
+
+fn no_field(receiver: &Value, field: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(format!("`{}` has no field `{}`", receiver, field), span)
+}
+
+/// Strings print as they are at the top level and quoted inside collections.
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl fmt::Debug for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, values: &[Value]) -> fmt::Result {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{:?}", value)?;
+            }
+            Ok(())
+        }
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Unit => write!(f, "()"),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::List(values) => {
+                write!(f, "[")?;
+                list(f, &values.borrow())?;
+                write!(f, "]")
+            }
+            Value::Set(values) => {
+                write!(f, "{{")?;
+                list(f, &values.borrow())?;
+                write!(f, "}}")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {:?}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                list(f, values)?;
+                write!(f, ")")
+            }
+            Value::Struct(instance) => {
+                write!(f, "{} {{ ", instance.ty.name)?;
+                let fields = instance.fields.borrow();
+                for (i, (name, value)) in instance.ty.fields.iter().zip(fields.iter()).enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {:?}", name, value)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Variant(name, values) if values.is_empty() => write!(f, "{}", name),
+            Value::Variant(name, values) => {
+                write!(f, "{}(", name)?;
+                list(f, values)?;
+                write!(f, ")")
+            }
+            Value::Function(callable) => write!(f, "<fn {}>", callable.name),
+            Value::Builtin(_) => write!(f, "<builtin>"),
+            Value::Type(_) => write!(f, "<type>"),
+            Value::Iterator(_) => write!(f, "<iterator>"),
+        }
+    }
+}
@@ -0,0 +1,791 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+use crate::bytecode::{Constant, Op, Pattern, Program};
+use crate::disasm;
+use crate::tokens::Span;
+
+/// A value of the IR. Each one is defined exactly once: by a parameter, a
+/// phi, an instruction or the terminator of the block before its uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+/// What is known about the values a `Value` can hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Type {
+    Any,
+    Null,
+    Unit,
+    Int,
+    Float,
+    Bool,
+    String,
+    List,
+    Set,
+    Map,
+    Tuple,
+    Iterator,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Type::Any => "any",
+            Type::Null => "null",
+            Type::Unit => "unit",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::String => "string",
+            Type::List => "list",
+            Type::Set => "set",
+            Type::Map => "map",
+            Type::Tuple => "tuple",
+            Type::Iterator => "iterator",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A function of the bytecode in SSA form.
+///
+/// The entry block is the first one and nothing jumps back to it. Slots
+/// that hold the boxes closures share stay slots: the instructions reading
+/// and writing them keep their operands, and every other slot and stack
+/// entry of the bytecode becomes values.
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: u8,
+    pub params: Vec<Value>,
+    pub cells: Vec<u16>,
+    pub blocks: Vec<Block>,
+    /// The type of each value, by number.
+    pub types: Vec<Type>,
+    /// The patterns `Match` terminators refer to. Their `Bind` slots are
+    /// replaced by the terminator's values, in order.
+    pub patterns: Vec<Pattern>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub phis: Vec<Phi>,
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+    /// The source of the terminator, for runtime errors.
+    pub span: Span,
+}
+
+/// A value that is `incoming` from whichever predecessor the block was
+/// entered from.
+#[derive(Clone, Debug)]
+pub struct Phi {
+    pub result: Value,
+    pub incoming: Vec<(BlockId, Value)>,
+}
+
+/// A bytecode instruction that neither branches nor touches plain slots,
+/// with the values it pops as `args` and the ones it pushes as `results`.
+#[derive(Clone, Debug)]
+pub struct Inst {
+    pub op: Op,
+    pub args: Vec<Value>,
+    pub results: Vec<Value>,
+    pub span: Span,
+}
+
+/// How a block ends.
+///
+/// `Next` defines `item` and `Match` defines `binds` only on the way to
+/// `more` and `matched`, which therefore have no other predecessor.
+#[derive(Clone, Debug)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Goes to the first block when the condition is `true` and to the
+    /// second when it is `false`.
+    Branch(Value, BlockId, BlockId),
+    Next {
+        iterator: Value,
+        item: Value,
+        more: BlockId,
+        done: BlockId,
+    },
+    Match {
+        value: Value,
+        pattern: u32,
+        binds: Vec<Value>,
+        matched: BlockId,
+        failed: BlockId,
+    },
+    Return(Value),
+    /// Stops the program because no clause accepts these arguments.
+    NoClause(Vec<Value>),
+    NoArm(Value),
+    Fail(u32),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(_, then, otherwise) => vec![*then, *otherwise],
+            Terminator::Next { more, done, .. } => vec![*more, *done],
+            Terminator::Match {
+                matched, failed, ..
+            } => vec![*matched, *failed],
+            Terminator::Return(_)
+            | Terminator::NoClause(_)
+            | Terminator::NoArm(_)
+            | Terminator::Fail(_) => Vec::new(),
+        }
+    }
+
+    /// Points every edge to `from` at `to` instead.
+    pub fn retarget(&mut self, from: BlockId, to: BlockId) {
+        let targets: Vec<&mut BlockId> = match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, then, otherwise) => vec![then, otherwise],
+            Terminator::Next { more, done, .. } => vec![more, done],
+            Terminator::Match {
+                matched, failed, ..
+            } => vec![matched, failed],
+            _ => Vec::new(),
+        };
+        for target in targets {
+            if *target == from {
+                *target = to;
+            }
+        }
+    }
+
+    /// Points the successor at `index` in `successors` at `to`.
+    pub fn set_successor(&mut self, index: usize, to: BlockId) {
+        let target = match (self, index) {
+            (Terminator::Jump(target), 0)
+            | (Terminator::Branch(_, target, _), 0)
+            | (Terminator::Branch(_, _, target), 1)
+            | (Terminator::Next { more: target, .. }, 0)
+            | (Terminator::Next { done: target, .. }, 1)
+            | (
+                Terminator::Match {
+                    matched: target, ..
+                },
+                0,
+            )
+            | (Terminator::Match { failed: target, .. }, 1) => target,
+            (terminator, _) => panic!("{:?} has no successor {}", terminator, index),
+        };
+        *target = to;
+    }
+
+    /// The block that may use the values this terminator defines, and those
+    /// values.
+    pub fn defines(&self) -> Option<(BlockId, &[Value])> {
+        match self {
+            Terminator::Next { item, more, .. } => Some((*more, std::slice::from_ref(item))),
+            Terminator::Match { binds, matched, .. } => Some((*matched, binds)),
+            _ => None,
+        }
+    }
+
+    pub fn uses(&self) -> Vec<Value> {
+        match self {
+            Terminator::Branch(value, ..)
+            | Terminator::Next {
+                iterator: value, ..
+            }
+            | Terminator::Match { value, .. }
+            | Terminator::Return(value)
+            | Terminator::NoArm(value) => vec![*value],
+            Terminator::NoClause(args) => args.clone(),
+            Terminator::Jump(_) | Terminator::Fail(_) => Vec::new(),
+        }
+    }
+
+    fn uses_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Branch(value, ..)
+            | Terminator::Next {
+                iterator: value, ..
+            }
+            | Terminator::Match { value, .. }
+            | Terminator::Return(value)
+            | Terminator::NoArm(value) => vec![value],
+            Terminator::NoClause(args) => args.iter_mut().collect(),
+            Terminator::Jump(_) | Terminator::Fail(_) => Vec::new(),
+        }
+    }
+}
+
+/// The constants of a program, each added at most once.
+pub struct Constants {
+    pub values: Vec<Constant>,
+    indexes: HashMap<Constant, u32>,
+}
+
+impl Constants {
+    pub fn new(values: Vec<Constant>) -> Self {
+        let mut indexes = HashMap::new();
+        for (index, constant) in values.iter().enumerate() {
+            indexes.entry(constant.clone()).or_insert(index as u32);
+        }
+        Constants { values, indexes }
+    }
+
+    pub fn intern(&mut self, constant: Constant) -> u32 {
+        if let Some(&index) = self.indexes.get(&constant) {
+            return index;
+        }
+        let index = self.values.len() as u32;
+        self.values.push(constant.clone());
+        self.indexes.insert(constant, index);
+        index
+    }
+}
+
+/// Where a value is defined.
+#[derive(Clone, Copy)]
+enum Definition {
+    Param,
+    /// At the start of a block, by a phi or by the terminator before it.
+    Start(BlockId),
+    Inst(BlockId, usize),
+}
+
+impl Function {
+    pub fn new_value(&mut self) -> Value {
+        self.types.push(Type::Any);
+        Value(self.types.len() as u32 - 1)
+    }
+
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id.0 as usize]
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = BlockId> {
+        (0..self.blocks.len() as u32).map(BlockId)
+    }
+
+    /// The predecessors of each block, once per edge.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for id in self.ids() {
+            for successor in self.block(id).terminator.successors() {
+                predecessors[successor.0 as usize].push(id);
+            }
+        }
+        predecessors
+    }
+
+    /// The blocks reachable from the entry, each before its successors
+    /// unless a loop leads back to it. Where it can, a block's first
+    /// successor comes right after it, so that lowering falls through.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        let mut stack = vec![(BlockId(0), 0)];
+        visited[0] = true;
+        while let Some((id, next)) = stack.pop() {
+            let successors = self.block(id).terminator.successors();
+            match successors.iter().rev().nth(next) {
+                Some(&successor) => {
+                    stack.push((id, next + 1));
+                    if !visited[successor.0 as usize] {
+                        visited[successor.0 as usize] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => order.push(id),
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Replaces every use of a value in `map` with what it maps to.
+    pub fn substitute(&mut self, map: &HashMap<Value, Value>) {
+        if map.is_empty() {
+            return;
+        }
+        let resolve = |mut value: Value| {
+            while let Some(&next) = map.get(&value) {
+                value = next;
+            }
+            value
+        };
+        for block in &mut self.blocks {
+            for phi in &mut block.phis {
+                for (_, value) in &mut phi.incoming {
+                    *value = resolve(*value);
+                }
+            }
+            for inst in &mut block.insts {
+                for arg in &mut inst.args {
+                    *arg = resolve(*arg);
+                }
+            }
+            for value in block.terminator.uses_mut() {
+                *value = resolve(*value);
+            }
+        }
+    }
+
+    /// Drops the blocks the entry cannot reach and numbers the others in
+    /// reverse postorder.
+    pub fn compact(&mut self) {
+        let order = self.reverse_postorder();
+        let mut numbers = vec![None; self.blocks.len()];
+        for (number, id) in order.iter().enumerate() {
+            numbers[id.0 as usize] = Some(BlockId(number as u32));
+        }
+        let mut old: Vec<Option<Block>> = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(Some)
+            .collect();
+        for id in order {
+            let mut block = old[id.0 as usize]
+                .take()
+                .expect("each block is visited once");
+            for phi in &mut block.phis {
+                phi.incoming
+                    .retain(|(predecessor, _)| numbers[predecessor.0 as usize].is_some());
+                for (predecessor, _) in &mut phi.incoming {
+                    *predecessor = numbers[predecessor.0 as usize].expect("retained above");
+                }
+            }
+            for successor in block.terminator.successors() {
+                let number = numbers[successor.0 as usize].expect("successors are reachable");
+                block
+                    .terminator
+                    .retarget(successor, BlockId(u32::MAX - number.0));
+            }
+            // Targets are renumbered in two steps so that a new number is
+            // never mistaken for an old one.
+            for successor in block.terminator.successors() {
+                block
+                    .terminator
+                    .retarget(successor, BlockId(u32::MAX - successor.0));
+            }
+            self.blocks.push(block);
+        }
+    }
+
+    /// Works out the type of every value from the instructions defining
+    /// them.
+    pub fn retype(&mut self, constants: &Constants) {
+        let mut types: Vec<Option<Type>> = vec![None; self.types.len()];
+        // Types only ever grow towards `Any`, so this reaches a fixed point.
+        fn update(types: &mut [Option<Type>], value: Value, ty: Option<Type>) -> bool {
+            let Some(ty) = ty else {
+                return false;
+            };
+            let slot = &mut types[value.0 as usize];
+            let joined = match *slot {
+                Some(known) if known != ty => Type::Any,
+                _ => ty,
+            };
+            let changed = *slot != Some(joined);
+            *slot = Some(joined);
+            changed
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in &self.blocks {
+                for phi in &block.phis {
+                    for &(_, value) in &phi.incoming {
+                        let ty = types[value.0 as usize];
+                        changed |= update(&mut types, phi.result, ty);
+                    }
+                }
+                for inst in &block.insts {
+                    let args: Vec<Type> = inst
+                        .args
+                        .iter()
+                        .map(|arg| types[arg.0 as usize].unwrap_or(Type::Any))
+                        .collect();
+                    let ty = result_type(inst.op, &args, constants);
+                    for &result in &inst.results {
+                        changed |= update(&mut types, result, Some(ty));
+                    }
+                }
+            }
+        }
+        self.types = types.into_iter().map(|t| t.unwrap_or(Type::Any)).collect();
+    }
+
+    pub fn type_of(&self, value: Value) -> Type {
+        self.types[value.0 as usize]
+    }
+
+    /// Checks that the function is in SSA form: that every value is defined
+    /// once before each of its uses, and that phis and edges agree.
+    pub fn verify(&self) -> Result<(), String> {
+        let predecessors = self.predecessors();
+        if !predecessors[0].is_empty() {
+            return Err("the entry block has predecessors".to_string());
+        }
+        let count = self.blocks.len() as u32;
+        for id in self.ids() {
+            let block = self.block(id);
+            for successor in block.terminator.successors() {
+                if successor.0 >= count {
+                    return Err(format!(
+                        "{} jumps to {}, which does not exist",
+                        id, successor
+                    ));
+                }
+            }
+        }
+
+        let mut definitions: Vec<Option<Definition>> = vec![None; self.types.len()];
+        let mut define = |value: Value, at: Definition| {
+            let slot = definitions
+                .get_mut(value.0 as usize)
+                .ok_or_else(|| format!("{} has no type", value))?;
+            if slot.is_some() {
+                return Err(format!("{} is defined more than once", value));
+            }
+            *slot = Some(at);
+            Ok(())
+        };
+        for &param in &self.params {
+            define(param, Definition::Param)?;
+        }
+        for id in self.ids() {
+            let block = self.block(id);
+            for phi in &block.phis {
+                define(phi.result, Definition::Start(id))?;
+            }
+            for (index, inst) in block.insts.iter().enumerate() {
+                for &result in &inst.results {
+                    define(result, Definition::Inst(id, index))?;
+                }
+            }
+            if let Some((successor, values)) = block.terminator.defines() {
+                let others = &predecessors[successor.0 as usize];
+                if others.len() != 1 || !self.block(successor).phis.is_empty() {
+                    return Err(format!(
+                        "{} defines values on its way to {}, which has other predecessors",
+                        id, successor
+                    ));
+                }
+                for &value in values {
+                    define(value, Definition::Start(successor))?;
+                }
+            }
+        }
+
+        let order = self.reverse_postorder();
+        let dominators = dominators(self, &order, &predecessors);
+        let dominates = |a: BlockId, mut b: BlockId| loop {
+            if a == b {
+                return true;
+            }
+            match dominators[b.0 as usize] {
+                Some(parent) if parent != b => b = parent,
+                _ => return false,
+            }
+        };
+        let available = |value: Value, block: BlockId, index: usize| {
+            let defined = match definitions.get(value.0 as usize).copied().flatten() {
+                Some(Definition::Param) => true,
+                Some(Definition::Start(at)) => dominates(at, block),
+                Some(Definition::Inst(at, position)) => {
+                    if at == block {
+                        position < index
+                    } else {
+                        dominates(at, block)
+                    }
+                }
+                None => false,
+            };
+            if defined {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} is used in {} where it is not defined",
+                    value, block
+                ))
+            }
+        };
+        for &id in &order {
+            let block = self.block(id);
+            let mut expected = predecessors[id.0 as usize].clone();
+            expected.sort();
+            for phi in &block.phis {
+                let mut incoming: Vec<BlockId> = phi.incoming.iter().map(|(b, _)| *b).collect();
+                incoming.sort();
+                if incoming != expected {
+                    return Err(format!(
+                        "the phi defining {} in {} does not have one value per predecessor",
+                        phi.result, id
+                    ));
+                }
+                for &(predecessor, value) in &phi.incoming {
+                    if dominators[predecessor.0 as usize].is_some() {
+                        let end = self.block(predecessor).insts.len();
+                        available(value, predecessor, end)?;
+                    }
+                }
+            }
+            for (index, inst) in block.insts.iter().enumerate() {
+                for &arg in &inst.args {
+                    available(arg, id, index)?;
+                }
+            }
+            for value in block.terminator.uses() {
+                available(value, id, block.insts.len())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the function as text, one instruction per line.
+    pub fn print(&self, index: usize, program: &Program, out: &mut String) {
+        let params: Vec<String> = self.params.iter().map(|p| p.to_string()).collect();
+        let _ = writeln!(out, "\nfn #{} {}({})", index, self.name, params.join(", "));
+        let predecessors = self.predecessors();
+        for id in self.ids() {
+            let block = self.block(id);
+            let from: Vec<String> = predecessors[id.0 as usize]
+                .iter()
+                .map(|b| b.to_string())
+                .collect();
+            if from.is_empty() {
+                let _ = writeln!(out, "{}:", id);
+            } else {
+                let _ = writeln!(out, "{}:  ; from {}", id, from.join(", "));
+            }
+            for phi in &block.phis {
+                let incoming: Vec<String> = phi
+                    .incoming
+                    .iter()
+                    .map(|(b, v)| format!("[{}: {}]", b, v))
+                    .collect();
+                let _ = writeln!(
+                    out,
+                    "    {} = phi {}",
+                    self.typed(phi.result),
+                    incoming.join(", ")
+                );
+            }
+            for inst in &block.insts {
+                let mut line = String::from("    ");
+                if !inst.results.is_empty() {
+                    let results: Vec<String> =
+                        inst.results.iter().map(|&r| self.typed(r)).collect();
+                    let _ = write!(line, "{} = ", results.join(", "));
+                }
+                let _ = write!(line, "{:?}", inst.op);
+                let args: Vec<String> = inst.args.iter().map(|a| a.to_string()).collect();
+                if !args.is_empty() {
+                    let _ = write!(line, " {}", args.join(", "));
+                }
+                let note = disasm::annotation(program, &self.patterns, &inst.op);
+                if !note.is_empty() {
+                    let _ = write!(line, "  ; {}", note);
+                }
+                let _ = writeln!(out, "{}", line);
+            }
+            let _ = writeln!(out, "    {}", self.terminator(&block.terminator, program));
+        }
+    }
+
+    fn typed(&self, value: Value) -> String {
+        format!("{}: {}", value, self.type_of(value))
+    }
+
+    fn terminator(&self, terminator: &Terminator, program: &Program) -> String {
+        let values = |values: &[Value]| {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match terminator {
+            Terminator::Jump(target) => format!("jump {}", target),
+            Terminator::Branch(condition, then, otherwise) => {
+                format!("branch {}, {}, else {}", condition, then, otherwise)
+            }
+            Terminator::Next {
+                iterator,
+                item,
+                more,
+                done,
+            } => format!(
+                "next {} -> {}, {}, done {}",
+                iterator,
+                self.typed(*item),
+                more,
+                done
+            ),
+            Terminator::Match {
+                value,
+                pattern,
+                binds,
+                matched,
+                failed,
+            } => format!(
+                "match {} with {} -> [{}], {}, else {}",
+                value,
+                disasm::pattern(program, &self.patterns[*pattern as usize]),
+                values(binds),
+                matched,
+                failed
+            ),
+            Terminator::Return(value) => format!("return {}", value),
+            Terminator::NoClause(args) => format!("noclause {}", values(args)),
+            Terminator::NoArm(value) => format!("noarm {}", value),
+            Terminator::Fail(message) => format!("fail {:?}", program.string(*message)),
+        }
+    }
+}
+
+/// The immediate dominator of each reachable block; the entry is its own.
+fn dominators(
+    function: &Function,
+    order: &[BlockId],
+    predecessors: &[Vec<BlockId>],
+) -> Vec<Option<BlockId>> {
+    let mut position = vec![usize::MAX; function.blocks.len()];
+    for (index, id) in order.iter().enumerate() {
+        position[id.0 as usize] = index;
+    }
+    let mut dominators: Vec<Option<BlockId>> = vec![None; function.blocks.len()];
+    dominators[0] = Some(BlockId(0));
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in order.iter().skip(1) {
+            let mut new: Option<BlockId> = None;
+            for &predecessor in &predecessors[id.0 as usize] {
+                if dominators[predecessor.0 as usize].is_none() {
+                    continue;
+                }
+                new = Some(match new {
+                    None => predecessor,
+                    Some(mut a) => {
+                        let mut b = predecessor;
+                        while a != b {
+                            while position[a.0 as usize] > position[b.0 as usize] {
+                                a = dominators[a.0 as usize].expect("processed");
+                            }
+                            while position[b.0 as usize] > position[a.0 as usize] {
+                                b = dominators[b.0 as usize].expect("processed");
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if new.is_some() && dominators[id.0 as usize] != new {
+                dominators[id.0 as usize] = new;
+                changed = true;
+            }
+        }
+    }
+    dominators
+}
+
+/// The type of what an instruction pushes, given the types of what it
+/// pops.
+fn result_type(op: Op, args: &[Type], constants: &Constants) -> Type {
+    let numeric = |a: Type, b: Type| match (a, b) {
+        (Type::Int, Type::Int) => Type::Int,
+        (Type::Int | Type::Float, Type::Int | Type::Float) => Type::Float,
+        _ => Type::Any,
+    };
+    match op {
+        Op::Constant(index) => match &constants.values[index as usize] {
+            Constant::Null => Type::Null,
+            Constant::Unit => Type::Unit,
+            Constant::Int(_) => Type::Int,
+            Constant::Float(_) => Type::Float,
+            Constant::Bool(_) => Type::Bool,
+            Constant::String(_) => Type::String,
+        },
+        Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder => {
+            numeric(args[0], args[1])
+        }
+        Op::Negate => match args[0] {
+            Type::Int | Type::Float => args[0],
+            _ => Type::Any,
+        },
+        Op::Not
+        | Op::Equal
+        | Op::NotEqual
+        | Op::Less
+        | Op::LessEqual
+        | Op::Greater
+        | Op::GreaterEqual => Type::Bool,
+        Op::List(_) => Type::List,
+        Op::Set(_) => Type::Set,
+        Op::Map(_) => Type::Map,
+        Op::Tuple(_) => Type::Tuple,
+        Op::Iterate(_) => Type::Iterator,
+        _ => Type::Any,
+    }
+}
+
+/// The slots a pattern binds, in the order it binds them.
+pub fn binds(pattern: &Pattern, slots: &mut Vec<u16>) {
+    match pattern {
+        Pattern::Bind(slot) => slots.push(*slot),
+        Pattern::Tuple(patterns) | Pattern::Variant(_, patterns) => {
+            for pattern in patterns {
+                binds(pattern, slots);
+            }
+        }
+        Pattern::Wildcard | Pattern::Cell(_) | Pattern::Constant(_) => {}
+    }
+}
+
+/// A copy of a pattern binding into the given slots, in order.
+pub fn rebind(pattern: &Pattern, slots: &mut impl Iterator<Item = u16>) -> Pattern {
+    match pattern {
+        Pattern::Bind(_) => Pattern::Bind(slots.next().expect("a slot per binding")),
+        Pattern::Tuple(patterns) => {
+            Pattern::Tuple(patterns.iter().map(|p| rebind(p, slots)).collect())
+        }
+        Pattern::Variant(name, patterns) => {
+            Pattern::Variant(*name, patterns.iter().map(|p| rebind(p, slots)).collect())
+        }
+        other => other.clone(),
+    }
+}
+
+/// Values that some instruction, phi or terminator of the function reads.
+pub fn used(function: &Function) -> HashSet<Value> {
+    let mut used = HashSet::new();
+    for block in &function.blocks {
+        for phi in &block.phis {
+            used.extend(phi.incoming.iter().map(|(_, v)| *v));
+        }
+        for inst in &block.insts {
+            used.extend(inst.args.iter().copied());
+        }
+        used.extend(block.terminator.uses());
+    }
+    used
+}
@@ -0,0 +1,467 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::bytecode::{Capture, Constant, Function, Op, Pattern, Program};
+use crate::c_backend;
+use crate::ir::{self, Block, BlockId, Constants, Inst, Phi, Terminator, Value};
+
+/// The values in the slots and on the stack at some point of a function.
+/// Slots holding boxes have no value.
+#[derive(Clone)]
+struct State {
+    slots: Vec<Option<Value>>,
+    stack: Vec<Value>,
+}
+
+/// Where the code of a block comes from.
+#[derive(Clone, Copy)]
+enum Source {
+    /// The entry, which sets up the slots and jumps to the first
+    /// instruction.
+    Entry,
+    /// The instructions in a range.
+    Code(usize, usize),
+    /// An edge that defines values on its way to a block with other
+    /// predecessors.
+    Edge(BlockId),
+}
+
+/// Translates a function of the bytecode into SSA form, or `None` when it
+/// boxes one of its arguments in place or creates a closure copying one of
+/// its slots, which only keeps its number in the bytecode.
+///
+/// Every slot and stack entry gets a phi at each block with several
+/// predecessors, which the optimizer removes again where they merge the
+/// same value.
+pub fn build(program: &Program, index: usize, constants: &mut Constants) -> Option<ir::Function> {
+    let function = &program.functions[index];
+    let cells = cells(program, function);
+    if cells.iter().any(|&slot| slot < function.arity as u16) || copies(program, function) {
+        return None;
+    }
+    let mut builder = Builder {
+        code: function,
+        depths: c_backend::depths(function),
+        sources: vec![Source::Entry],
+        blocks: HashMap::new(),
+        function: ir::Function {
+            name: function.name.clone(),
+            arity: function.arity,
+            params: Vec::new(),
+            cells: cells.iter().copied().collect(),
+            blocks: Vec::new(),
+            types: Vec::new(),
+            patterns: function.patterns.clone(),
+        },
+        edges: Vec::new(),
+        splits: HashMap::new(),
+    };
+    builder.split();
+    builder.translate(constants)?;
+    builder.function.retype(constants);
+    Some(builder.function)
+}
+
+fn copies(program: &Program, function: &Function) -> bool {
+    function.code.iter().any(|op| match *op {
+        Op::Closure(index) => program.functions[index as usize]
+            .captures
+            .iter()
+            .any(|capture| matches!(capture, Capture::Value(_))),
+        _ => false,
+    })
+}
+
+/// The slots of a function that hold boxes closures share.
+fn cells(program: &Program, function: &Function) -> BTreeSet<u16> {
+    let mut cells = BTreeSet::new();
+    for op in &function.code {
+        match *op {
+            Op::Cell(slot) | Op::SetCell(slot) | Op::NewCell(slot) => {
+                cells.insert(slot);
+            }
+            Op::Closure(index) => {
+                for capture in &program.functions[index as usize].captures {
+                    if let Capture::Local(slot) = capture {
+                        cells.insert(*slot);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    fn boxed(pattern: &Pattern, cells: &mut BTreeSet<u16>) {
+        match pattern {
+            Pattern::Cell(slot) => {
+                cells.insert(*slot);
+            }
+            Pattern::Tuple(patterns) | Pattern::Variant(_, patterns) => {
+                for pattern in patterns {
+                    boxed(pattern, cells);
+                }
+            }
+            _ => {}
+        }
+    }
+    for pattern in &function.patterns {
+        boxed(pattern, &mut cells);
+    }
+    cells
+}
+
+struct Builder<'a> {
+    code: &'a Function,
+    depths: Vec<Option<usize>>,
+    sources: Vec<Source>,
+    /// The block starting at each leader.
+    blocks: HashMap<usize, BlockId>,
+    function: ir::Function,
+    /// The state leaving each block towards a block with phis.
+    edges: Vec<(BlockId, BlockId, State)>,
+    /// The edge blocks taking the place of an edge, by its block and
+    /// position among the block's successors.
+    splits: HashMap<(BlockId, usize), BlockId>,
+}
+
+impl Builder<'_> {
+    fn is_cell(&self, slot: usize) -> bool {
+        self.function.cells.contains(&(slot as u16))
+    }
+
+    /// Splits the reachable code into blocks at every jump and jump target.
+    fn split(&mut self) {
+        let code = &self.code.code;
+        let mut leaders = vec![false; code.len() + 1];
+        leaders[0] = true;
+        for (ip, op) in code.iter().enumerate() {
+            if self.depths[ip].is_none() {
+                continue;
+            }
+            match *op {
+                Op::Jump(target)
+                | Op::JumpIfFalse(target)
+                | Op::Next(_, target)
+                | Op::Match(_, target) => leaders[target as usize] = true,
+                Op::Return | Op::NoClause | Op::NoArm(_) | Op::Fail(_) => {}
+                _ => continue,
+            }
+            leaders[ip + 1] = true;
+        }
+        let mut start = None;
+        for (ip, &leader) in leaders.iter().enumerate() {
+            if !leader && ip < code.len() {
+                continue;
+            }
+            if let Some(start) = start.take() {
+                self.blocks
+                    .insert(start, BlockId(self.sources.len() as u32));
+                self.sources.push(Source::Code(start, ip));
+            }
+            if ip < code.len() && self.depths[ip].is_some() {
+                start = Some(ip);
+            }
+        }
+    }
+
+    /// The successors of each block, before edge blocks are added.
+    fn successors(&self, source: Source) -> Vec<BlockId> {
+        match source {
+            Source::Entry => vec![self.blocks[&0]],
+            Source::Code(_, end) => {
+                let at = |ip: u32| self.blocks[&(ip as usize)];
+                match self.code.code[end - 1] {
+                    Op::Jump(target) => vec![at(target)],
+                    Op::JumpIfFalse(target) | Op::Next(_, target) | Op::Match(_, target) => {
+                        vec![at(end as u32), at(target)]
+                    }
+                    Op::Return | Op::NoClause | Op::NoArm(_) | Op::Fail(_) => Vec::new(),
+                    _ => vec![at(end as u32)],
+                }
+            }
+            Source::Edge(target) => vec![target],
+        }
+    }
+
+    fn translate(&mut self, constants: &mut Constants) -> Option<()> {
+        // Blocks entered from several places get a phi per slot and stack
+        // entry, so that their state is known before their predecessors.
+        let mut predecessors = vec![0; self.sources.len()];
+        let successors: Vec<Vec<BlockId>> = self
+            .sources
+            .iter()
+            .map(|&source| self.successors(source))
+            .collect();
+        for successor in successors.iter().flatten() {
+            predecessors[successor.0 as usize] += 1;
+        }
+        // Values defined on an edge into a block with other predecessors,
+        // and a second edge to the same block, go through a block of their
+        // own.
+        for (index, successors) in successors.iter().enumerate() {
+            let defining = match self.sources[index] {
+                Source::Code(_, end) => {
+                    matches!(self.code.code[end - 1], Op::Next(..) | Op::Match(..))
+                }
+                _ => false,
+            };
+            let edge = match successors.as_slice() {
+                [first, _] if defining && predecessors[first.0 as usize] > 1 => 0,
+                [first, second] if first == second => 1,
+                _ => continue,
+            };
+            let id = BlockId(self.sources.len() as u32);
+            self.sources.push(Source::Edge(successors[edge]));
+            self.splits.insert((BlockId(index as u32), edge), id);
+            predecessors.push(1);
+        }
+        let span = self.code.spans[0];
+        let count = self.sources.len();
+        for index in 0..count {
+            let span = match self.sources[index] {
+                Source::Code(_, end) => self.code.spans[end - 1],
+                _ => span,
+            };
+            self.function.blocks.push(Block {
+                phis: Vec::new(),
+                insts: Vec::new(),
+                terminator: Terminator::Jump(BlockId(0)),
+                span,
+            });
+        }
+        let mut states: Vec<Option<State>> = vec![None; count];
+        for index in 1..count {
+            if predecessors[index] > 1 {
+                let Source::Code(start, _) = self.sources[index] else {
+                    unreachable!("only code blocks have several predecessors")
+                };
+                states[index] = Some(self.phis(BlockId(index as u32), start));
+            }
+        }
+
+        let params: Vec<Value> = (0..self.code.arity)
+            .map(|_| self.function.new_value())
+            .collect();
+        self.function.params = params.clone();
+        let unit = self.function.new_value();
+        let mut entry = State {
+            slots: Vec::new(),
+            stack: Vec::new(),
+        };
+        for slot in 0..self.code.slots as usize {
+            entry.slots.push(match params.get(slot) {
+                _ if self.is_cell(slot) => None,
+                Some(&param) => Some(param),
+                None => Some(unit),
+            });
+        }
+        self.function.blocks[0].insts.push(Inst {
+            op: Op::Constant(constants.intern(Constant::Unit)),
+            args: Vec::new(),
+            results: vec![unit],
+            span,
+        });
+        states[0] = Some(entry);
+
+        let mut work = vec![BlockId(0)];
+        let mut done = vec![false; count];
+        while let Some(id) = work.pop() {
+            if std::mem::replace(&mut done[id.0 as usize], true) {
+                continue;
+            }
+            let state = states[id.0 as usize]
+                .clone()
+                .expect("a block's state is known before it");
+            for (target, state) in self.block(id, state)? {
+                if predecessors[target.0 as usize] > 1 {
+                    self.edges.push((id, target, state));
+                } else {
+                    states[target.0 as usize] = Some(state);
+                }
+                work.push(target);
+            }
+        }
+
+        for (from, to, state) in std::mem::take(&mut self.edges) {
+            let values: Vec<Value> = state
+                .slots
+                .iter()
+                .flatten()
+                .chain(&state.stack)
+                .copied()
+                .collect();
+            let phis = &mut self.function.blocks[to.0 as usize].phis;
+            assert_eq!(
+                phis.len(),
+                values.len(),
+                "the stack is equally deep on every edge"
+            );
+            for (phi, value) in phis.iter_mut().zip(values) {
+                phi.incoming.push((from, value));
+            }
+        }
+        Some(())
+    }
+
+    fn phis(&mut self, id: BlockId, start: usize) -> State {
+        let mut state = State {
+            slots: Vec::new(),
+            stack: Vec::new(),
+        };
+        let mut phis = Vec::new();
+        for slot in 0..self.code.slots as usize {
+            if self.is_cell(slot) {
+                state.slots.push(None);
+            } else {
+                let result = self.function.new_value();
+                phis.push(result);
+                state.slots.push(Some(result));
+            }
+        }
+        for _ in 0..self.depths[start].expect("blocks are reachable") {
+            let result = self.function.new_value();
+            phis.push(result);
+            state.stack.push(result);
+        }
+        self.function.blocks[id.0 as usize].phis = phis
+            .into_iter()
+            .map(|result| Phi {
+                result,
+                incoming: Vec::new(),
+            })
+            .collect();
+        state
+    }
+
+    /// Translates the code of a block, returning the state on each edge
+    /// leaving it.
+    fn block(&mut self, id: BlockId, mut state: State) -> Option<Vec<(BlockId, State)>> {
+        let (start, end) = match self.sources[id.0 as usize] {
+            Source::Entry => {
+                let first = self.blocks[&0];
+                self.function.blocks[0].terminator = Terminator::Jump(first);
+                return Some(vec![(first, state)]);
+            }
+            Source::Edge(target) => {
+                let block = &mut self.function.blocks[id.0 as usize];
+                block.terminator = Terminator::Jump(target);
+                return Some(vec![(target, state)]);
+            }
+            Source::Code(start, end) => (start, end),
+        };
+        let mut insts = Vec::new();
+        let mut terminator = None;
+        let mut edges = Vec::new();
+        for ip in start..end {
+            let op = self.code.code[ip];
+            let span = self.code.spans[ip];
+            let next = |ip: usize| self.blocks[&ip];
+            match op {
+                Op::Pop => {
+                    state.stack.pop();
+                }
+                Op::Dup => {
+                    let top = *state.stack.last()?;
+                    state.stack.push(top);
+                }
+                Op::Local(slot) => {
+                    let value = state.slots[slot as usize]?;
+                    state.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = state.stack.pop()?;
+                    if self.is_cell(slot as usize) {
+                        return None;
+                    }
+                    state.slots[slot as usize] = Some(value);
+                }
+                Op::Jump(target) => {
+                    let target = next(target as usize);
+                    terminator = Some(Terminator::Jump(target));
+                    edges.push((target, state.clone()));
+                }
+                Op::JumpIfFalse(target) => {
+                    let condition = state.stack.pop()?;
+                    let (then, otherwise) = (next(ip + 1), next(target as usize));
+                    terminator = Some(Terminator::Branch(condition, then, otherwise));
+                    edges.push((then, state.clone()));
+                    edges.push((otherwise, state.clone()));
+                }
+                Op::Next(slot, target) => {
+                    let iterator = state.slots[slot as usize]?;
+                    let item = self.function.new_value();
+                    let mut more = state.clone();
+                    more.stack.push(item);
+                    let (more_block, done) = (next(ip + 1), next(target as usize));
+                    edges.push((more_block, more));
+                    edges.push((done, state.clone()));
+                    terminator = Some(Terminator::Next {
+                        iterator,
+                        item,
+                        more: more_block,
+                        done,
+                    });
+                }
+                Op::Match(pattern, target) => {
+                    let value = state.stack.pop()?;
+                    let mut slots = Vec::new();
+                    ir::binds(&self.code.patterns[pattern as usize], &mut slots);
+                    let mut matched = state.clone();
+                    let binds: Vec<Value> = slots
+                        .iter()
+                        .map(|&slot| {
+                            let bind = self.function.new_value();
+                            matched.slots[slot as usize] = Some(bind);
+                            bind
+                        })
+                        .collect();
+                    let (matched_block, failed) = (next(ip + 1), next(target as usize));
+                    edges.push((matched_block, matched));
+                    edges.push((failed, state.clone()));
+                    terminator = Some(Terminator::Match {
+                        value,
+                        pattern,
+                        binds,
+                        matched: matched_block,
+                        failed,
+                    });
+                }
+                Op::Return => terminator = Some(Terminator::Return(state.stack.pop()?)),
+                Op::NoClause => {
+                    let args = state.slots[..self.code.arity as usize].iter().copied();
+                    terminator = Some(Terminator::NoClause(args.collect::<Option<_>>()?));
+                }
+                Op::NoArm(slot) => {
+                    terminator = Some(Terminator::NoArm(state.slots[slot as usize]?))
+                }
+                Op::Fail(message) => terminator = Some(Terminator::Fail(message)),
+                _ => {
+                    let (pops, pushes) = c_backend::effect(op);
+                    let args = state.stack.split_off(state.stack.len().checked_sub(pops)?);
+                    let results: Vec<Value> =
+                        (0..pushes).map(|_| self.function.new_value()).collect();
+                    state.stack.extend(&results);
+                    insts.push(Inst {
+                        op,
+                        args,
+                        results,
+                        span,
+                    });
+                }
+            }
+        }
+        let terminator = terminator.unwrap_or_else(|| {
+            let target = self.blocks[&end];
+            edges.push((target, state));
+            Terminator::Jump(target)
+        });
+        let block = &mut self.function.blocks[id.0 as usize];
+        block.insts = insts;
+        block.terminator = terminator;
+        for (index, (target, _)) in edges.iter_mut().enumerate() {
+            if let Some(&edge) = self.splits.get(&(id, index)) {
+                block.terminator.set_successor(index, edge);
+                *target = edge;
+            }
+        }
+        Some(edges)
+    }
+}
@@ -0,0 +1,633 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::bytecode::{Function, Op, Pattern};
+use crate::ir::{self, BlockId, Terminator, Value};
+use crate::tokens::Span;
+
+/// Turns a function in SSA form back into bytecode for `original`.
+///
+/// Constants and other loads without operands are emitted again where they
+/// are used. A value used once, right where the stack has it on top, stays
+/// on the stack; every other value gets a slot, shared with values that are
+/// never needed at the same time. Phis become copies at the end of each
+/// predecessor, through the stack so that they happen all at once.
+pub fn lower(function: &ir::Function, original: &Function) -> Function {
+    let order = function.reverse_postorder();
+    let loads = loads(function);
+    let stacked = stacked(function, &order, &loads);
+    let slots = allocate(function, &order, &stacked, &loads);
+    let mut lowering = Lowering {
+        function,
+        loads,
+        slots,
+        code: Vec::new(),
+        spans: Vec::new(),
+        patterns: Vec::new(),
+        fixups: Vec::new(),
+        scratch: None,
+    };
+    let mut starts = HashMap::new();
+    for (index, &id) in order.iter().enumerate() {
+        starts.insert(id, lowering.code.len() as u32);
+        lowering.block(id, order.get(index + 1).copied(), &stacked);
+    }
+    for (at, target) in std::mem::take(&mut lowering.fixups) {
+        let target = match target {
+            Target::Block(id) => starts[&id],
+            Target::Code(offset) => offset,
+        };
+        lowering.code[at] = match lowering.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::Next(slot, _) => Op::Next(slot, target),
+            Op::Match(pattern, _) => Op::Match(pattern, target),
+            other => unreachable!("{:?} does not jump", other),
+        };
+    }
+    Function {
+        name: original.name.clone(),
+        arity: original.arity,
+        slots: lowering.slot_count(),
+        captures: original.captures.clone(),
+        code: lowering.code,
+        spans: lowering.spans,
+        patterns: lowering.patterns,
+        memo: original.memo,
+    }
+}
+
+/// Where a jump goes, before the offsets of blocks are known.
+enum Target {
+    Block(BlockId),
+    Code(u32),
+}
+
+/// Values loaded by an instruction without operands or effects, which is
+/// cheaper to repeat at each use than to keep in a slot.
+fn loads(function: &ir::Function) -> HashMap<Value, Op> {
+    let mut loads = HashMap::new();
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let (Op::Constant(_) | Op::Function(_) | Op::Builtin(_) | Op::Type(_), [result]) =
+                (inst.op, inst.results.as_slice())
+            {
+                loads.insert(*result, inst.op);
+            }
+        }
+    }
+    // `Next` and `NoArm` read their operand from a slot.
+    for block in &function.blocks {
+        if let Terminator::Next { iterator: v, .. } | Terminator::NoArm(v) = &block.terminator {
+            loads.remove(v);
+        }
+    }
+    loads
+}
+
+/// How many times each value is used.
+fn uses(function: &ir::Function) -> HashMap<Value, usize> {
+    let mut uses: HashMap<Value, usize> = HashMap::new();
+    let mut count = |value: Value| *uses.entry(value).or_insert(0) += 1;
+    for block in &function.blocks {
+        for phi in &block.phis {
+            for &(_, value) in &phi.incoming {
+                count(value);
+            }
+        }
+        for inst in &block.insts {
+            inst.args.iter().for_each(|&arg| count(arg));
+        }
+        block.terminator.uses().into_iter().for_each(&mut count);
+    }
+    uses
+}
+
+/// The values an instruction of a block finds on top of the stack rather
+/// than in slots.
+///
+/// A value qualifies when its only use is an instruction or terminator of
+/// the block defining it that pops it, and whatever is pushed in between is
+/// popped again first. Candidates are simulated until none breaks that.
+fn stacked(
+    function: &ir::Function,
+    order: &[BlockId],
+    loads: &HashMap<Value, Op>,
+) -> HashSet<Value> {
+    let uses = uses(function);
+    let mut stacked = HashSet::new();
+    for &id in order {
+        let block = function.block(id);
+        let mut defined = HashSet::new();
+        for inst in &block.insts {
+            for &arg in &inst.args {
+                if defined.contains(&arg) && uses.get(&arg) == Some(&1) {
+                    stacked.insert(arg);
+                }
+            }
+            if let [result] = inst.results.as_slice() {
+                if !loads.contains_key(result) {
+                    defined.insert(*result);
+                }
+            }
+        }
+        if let Some(value) = popped(&block.terminator) {
+            if defined.contains(&value) && uses.get(&value) == Some(&1) {
+                stacked.insert(value);
+            }
+        }
+    }
+    loop {
+        let mut broken = Vec::new();
+        for &id in order {
+            let block = function.block(id);
+            let mut stack: Vec<Value> = Vec::new();
+            let mut take = |args: &[Value], stack: &mut Vec<Value>| {
+                let taken = on_top(stack, args);
+                stack.truncate(stack.len() - taken);
+                broken.extend(args[taken..].iter().filter(|a| stack.contains(a)));
+            };
+            for inst in &block.insts {
+                take(&inst.args, &mut stack);
+                if let [result] = inst.results.as_slice() {
+                    if stacked.contains(result) {
+                        stack.push(*result);
+                    }
+                }
+            }
+            if let Some(value) = popped(&block.terminator) {
+                take(&[value], &mut stack);
+            }
+            broken.extend(stack);
+        }
+        if broken.is_empty() {
+            return stacked;
+        }
+        for value in broken {
+            stacked.remove(&value);
+        }
+    }
+}
+
+/// The value a terminator pops off the stack.
+fn popped(terminator: &Terminator) -> Option<Value> {
+    match terminator {
+        Terminator::Branch(value, ..)
+        | Terminator::Match { value, .. }
+        | Terminator::Return(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// How many of `args`, from the first, are the values on top of `stack`.
+fn on_top(stack: &[Value], args: &[Value]) -> usize {
+    (0..=args.len().min(stack.len()))
+        .rev()
+        .find(|&n| stack[stack.len() - n..] == args[..n])
+        .unwrap_or(0)
+}
+
+/// Gives a slot to every value that is used and neither kept on the stack
+/// nor loaded again where it is used.
+///
+/// Each value lives from its first to its last appearance in the order the
+/// blocks are emitted in, stretched over the blocks it is live into or out
+/// of. Arguments keep the slots they arrive in, the slots of boxes are left
+/// alone, and a phi shares its slot with the values it merges where that
+/// saves a copy.
+fn allocate(
+    function: &ir::Function,
+    order: &[BlockId],
+    stacked: &HashSet<Value>,
+    loads: &HashMap<Value, Op>,
+) -> HashMap<Value, u16> {
+    let count = function.blocks.len();
+    let mut start = vec![0; count];
+    let mut end = vec![0; count];
+    let mut points: HashMap<Value, (usize, usize)> = HashMap::new();
+    let mut touch = |value: Value, at: usize| {
+        let range = points.entry(value).or_insert((at, at));
+        range.0 = range.0.min(at);
+        range.1 = range.1.max(at);
+    };
+    for &param in &function.params {
+        touch(param, 0);
+    }
+    let mut position = 0;
+    for &id in order {
+        let block = function.block(id);
+        start[id.0 as usize] = position;
+        for phi in &block.phis {
+            touch(phi.result, position);
+        }
+        position += 1;
+        for inst in &block.insts {
+            for &value in inst.args.iter().chain(&inst.results) {
+                touch(value, position);
+            }
+            position += 1;
+        }
+        for value in block.terminator.uses() {
+            touch(value, position);
+        }
+        if let Some((_, values)) = block.terminator.defines() {
+            for &value in values {
+                touch(value, position);
+            }
+        }
+        // Phi copies happen at the end of each predecessor.
+        for successor in block.terminator.successors() {
+            for phi in &function.block(successor).phis {
+                for &(from, value) in &phi.incoming {
+                    if from == id {
+                        touch(value, position);
+                        touch(phi.result, position);
+                    }
+                }
+            }
+        }
+        end[id.0 as usize] = position;
+        position += 1;
+    }
+
+    let liveness = liveness(function);
+    for (id, (live_in, live_out)) in liveness.iter().enumerate() {
+        for &value in live_in {
+            touch(value, start[id]);
+        }
+        for &value in live_out {
+            touch(value, end[id]);
+        }
+    }
+    let merged = coalescable(function, &liveness);
+
+    let used = ir::used(function);
+    let mut intervals: Vec<(usize, usize, Value)> = points
+        .into_iter()
+        .filter(|(value, _)| !stacked.contains(value) && !loads.contains_key(value))
+        .filter(|(value, _)| used.contains(value) || function.params.contains(value))
+        .map(|(value, (first, last))| (first, last, value))
+        .collect();
+    intervals.sort();
+    let mut slots: HashMap<Value, u16> = HashMap::new();
+    // The last position each slot is needed at, and the value needing it.
+    let mut busy: Vec<Option<(usize, Value)>> = Vec::new();
+    for (index, &param) in function.params.iter().enumerate() {
+        slots.insert(param, index as u16);
+    }
+    for (first, last, value) in intervals {
+        let free = |slot: usize, busy: &[Option<(usize, Value)>]| {
+            !function.cells.contains(&(slot as u16))
+                && busy
+                    .get(slot)
+                    .copied()
+                    .flatten()
+                    .is_none_or(|(until, _)| until < first)
+        };
+        // A value merged by a phi that already has a slot goes into that
+        // slot, and a phi takes over the slot of a value it merges that
+        // dies where the phi starts.
+        let shared = merged.iter().find_map(|&(phi, merged)| {
+            if merged == value {
+                slots.get(&phi).map(|&slot| slot as usize)
+            } else if phi == value {
+                let slot = *slots.get(&merged)? as usize;
+                let holder = busy.get(slot).copied().flatten();
+                let handed_over =
+                    holder.is_some_and(|(until, holder)| holder == merged && until <= first);
+                (handed_over || free(slot, &busy)).then_some(slot)
+            } else {
+                None
+            }
+        });
+        let slot = match slots.get(&value) {
+            Some(&slot) => slot as usize,
+            None => shared.unwrap_or_else(|| {
+                (0..)
+                    .find(|&slot| free(slot, &busy))
+                    .expect("there is always a free slot")
+            }),
+        };
+        if busy.len() <= slot {
+            busy.resize(slot + 1, None);
+        }
+        let until = busy[slot].map_or(last, |(until, _)| until.max(last));
+        busy[slot] = Some((until, value));
+        slots.insert(value, slot as u16);
+    }
+    slots
+}
+
+/// Pairs of a phi and a value it merges that may share a slot: the value
+/// is defined by an instruction of the predecessor it comes from and used
+/// by nothing else, and the phi is not needed in that block after it.
+fn coalescable(
+    function: &ir::Function,
+    liveness: &[(HashSet<Value>, HashSet<Value>)],
+) -> Vec<(Value, Value)> {
+    let uses = uses(function);
+    let mut pairs = Vec::new();
+    for id in function.ids() {
+        let block = function.block(id);
+        let phis: Vec<(Value, Value)> = block
+            .terminator
+            .successors()
+            .into_iter()
+            .flat_map(|successor| &function.block(successor).phis)
+            .filter_map(|phi| {
+                let &(_, value) = phi.incoming.iter().find(|(from, _)| *from == id)?;
+                Some((phi.result, value))
+            })
+            .collect();
+        for &(phi, value) in &phis {
+            let Some(defined) = block
+                .insts
+                .iter()
+                .position(|inst| inst.results.contains(&value))
+            else {
+                continue;
+            };
+            let needed_later = block.insts[defined + 1..]
+                .iter()
+                .any(|inst| inst.args.contains(&phi))
+                || block.terminator.uses().contains(&phi)
+                || phis.iter().any(|&(_, other)| other == phi)
+                || liveness[id.0 as usize].1.contains(&phi);
+            if uses.get(&value) == Some(&1) && !needed_later {
+                pairs.push((phi, value));
+            }
+        }
+    }
+    pairs
+}
+
+/// The values live into and out of each block.
+fn liveness(function: &ir::Function) -> Vec<(HashSet<Value>, HashSet<Value>)> {
+    let count = function.blocks.len();
+    let mut defined: Vec<HashSet<Value>> = vec![HashSet::new(); count];
+    let mut used: Vec<HashSet<Value>> = vec![HashSet::new(); count];
+    for block in &function.blocks {
+        if let Some((successor, values)) = block.terminator.defines() {
+            defined[successor.0 as usize].extend(values);
+        }
+    }
+    for id in function.ids() {
+        let block = function.block(id);
+        let index = id.0 as usize;
+        defined[index].extend(block.phis.iter().map(|phi| phi.result));
+        for inst in &block.insts {
+            for arg in &inst.args {
+                if !defined[index].contains(arg) {
+                    used[index].insert(*arg);
+                }
+            }
+            defined[index].extend(&inst.results);
+        }
+        for value in block.terminator.uses() {
+            if !defined[index].contains(&value) {
+                used[index].insert(value);
+            }
+        }
+    }
+    let mut live_in: Vec<HashSet<Value>> = used.clone();
+    let mut live_out: Vec<HashSet<Value>> = vec![HashSet::new(); count];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in (0..count as u32).rev().map(BlockId) {
+            let index = id.0 as usize;
+            let mut out = HashSet::new();
+            for successor in function.block(id).terminator.successors() {
+                let next = function.block(successor);
+                for phi in &next.phis {
+                    for &(from, value) in &phi.incoming {
+                        if from == id {
+                            out.insert(value);
+                        }
+                    }
+                }
+                for &value in &live_in[successor.0 as usize] {
+                    if !next.phis.iter().any(|phi| phi.result == value) {
+                        out.insert(value);
+                    }
+                }
+            }
+            let mut inside = used[index].clone();
+            inside.extend(out.iter().filter(|v| !defined[index].contains(v)));
+            if out != live_out[index] || inside != live_in[index] {
+                live_out[index] = out;
+                live_in[index] = inside;
+                changed = true;
+            }
+        }
+    }
+    live_in.into_iter().zip(live_out).collect()
+}
+
+struct Lowering<'a> {
+    function: &'a ir::Function,
+    loads: HashMap<Value, Op>,
+    slots: HashMap<Value, u16>,
+    code: Vec<Op>,
+    spans: Vec<Span>,
+    patterns: Vec<Pattern>,
+    fixups: Vec<(usize, Target)>,
+    /// A slot for pattern bindings that nothing reads.
+    scratch: Option<u16>,
+}
+
+impl Lowering<'_> {
+    fn emit(&mut self, op: Op, span: Span) {
+        self.code.push(op);
+        self.spans.push(span);
+    }
+
+    fn jump(&mut self, op: Op, target: Target, span: Span) {
+        self.fixups.push((self.code.len(), target));
+        self.emit(op, span);
+    }
+
+    fn slot(&self, value: Value) -> u16 {
+        self.slots[&value]
+    }
+
+    /// Pushes a value from its slot, or by loading it again.
+    fn push(&mut self, value: Value, span: Span) {
+        match self.loads.get(&value) {
+            Some(&op) => self.emit(op, span),
+            None => self.emit(Op::Local(self.slot(value)), span),
+        }
+    }
+
+    fn slot_count(&self) -> u16 {
+        self.slots
+            .values()
+            .copied()
+            .chain(self.function.cells.iter().copied())
+            .chain(self.scratch)
+            .map(|slot| slot + 1)
+            .max()
+            .unwrap_or(0)
+            .max(self.function.arity as u16)
+    }
+
+    fn block(&mut self, id: BlockId, next: Option<BlockId>, stacked: &HashSet<Value>) {
+        let function = self.function;
+        let block = function.block(id);
+        let mut stack: Vec<Value> = Vec::new();
+        for inst in &block.insts {
+            if let [result] = inst.results.as_slice() {
+                if self.loads.contains_key(result) {
+                    continue;
+                }
+            }
+            let taken = on_top(&stack, &inst.args);
+            stack.truncate(stack.len() - taken);
+            for &arg in &inst.args[taken..] {
+                self.push(arg, inst.span);
+            }
+            self.emit(inst.op, inst.span);
+            match inst.results.as_slice() {
+                [result] if stacked.contains(result) => stack.push(*result),
+                results => {
+                    for result in results.iter().rev() {
+                        match self.slots.get(result) {
+                            Some(&slot) => self.emit(Op::SetLocal(slot), inst.span),
+                            None => self.emit(Op::Pop, inst.span),
+                        }
+                    }
+                }
+            }
+        }
+        let span = block.span;
+        if let Some(value) = popped(&block.terminator) {
+            if stack.pop() != Some(value) {
+                self.push(value, span);
+            }
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => self.follow(id, *target, next, span),
+            Terminator::Branch(_, then, otherwise) => {
+                let at = self.code.len();
+                self.emit(Op::JumpIfFalse(0), span);
+                self.branch(id, at, *otherwise, *then, next, span);
+            }
+            Terminator::Next {
+                iterator,
+                item,
+                more,
+                done,
+            } => {
+                let at = self.code.len();
+                self.emit(Op::Next(self.slot(*iterator), 0), span);
+                match self.slots.get(item) {
+                    Some(&slot) => self.emit(Op::SetLocal(slot), span),
+                    None => self.emit(Op::Pop, span),
+                }
+                self.branch(id, at, *done, *more, next, span);
+            }
+            Terminator::Match {
+                pattern,
+                binds,
+                matched,
+                failed,
+                ..
+            } => {
+                let mut slots = Vec::new();
+                for bind in binds {
+                    let slot = match self.slots.get(bind) {
+                        Some(&slot) => slot,
+                        None => self.scratch(),
+                    };
+                    slots.push(slot);
+                }
+                let pattern = ir::rebind(
+                    &function.patterns[*pattern as usize],
+                    &mut slots.into_iter(),
+                );
+                let index = self.patterns.len() as u32;
+                self.patterns.push(pattern);
+                let at = self.code.len();
+                self.emit(Op::Match(index, 0), span);
+                self.branch(id, at, *failed, *matched, next, span);
+            }
+            Terminator::Return(_) => self.emit(Op::Return, span),
+            Terminator::NoClause(args) => {
+                let copies: Vec<(Value, u16)> = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &v)| (v, i as u16))
+                    .collect();
+                self.copy(&copies, span);
+                self.emit(Op::NoClause, span);
+            }
+            Terminator::NoArm(value) => self.emit(Op::NoArm(self.slot(*value)), span),
+            Terminator::Fail(message) => self.emit(Op::Fail(*message), span),
+        }
+    }
+
+    fn scratch(&mut self) -> u16 {
+        let count = self.slot_count();
+        *self.scratch.get_or_insert(count)
+    }
+
+    /// Finishes a terminator whose instruction at `at` jumps to `jumped`,
+    /// going on to `fallen` otherwise.
+    fn branch(
+        &mut self,
+        id: BlockId,
+        at: usize,
+        jumped: BlockId,
+        fallen: BlockId,
+        next: Option<BlockId>,
+        span: Span,
+    ) {
+        if self.copies(id, jumped).is_empty() {
+            self.fixups.push((at, Target::Block(jumped)));
+            self.follow(id, fallen, next, span);
+        } else {
+            self.follow(id, fallen, None, span);
+            self.fixups.push((at, Target::Code(self.code.len() as u32)));
+            self.follow(id, jumped, next, span);
+        }
+    }
+
+    /// Copies the values the phis of `target` take from `id`, and jumps
+    /// there unless it comes next.
+    fn follow(&mut self, id: BlockId, target: BlockId, next: Option<BlockId>, span: Span) {
+        let copies = self.copies(id, target);
+        self.copy(&copies, span);
+        if next != Some(target) {
+            self.jump(Op::Jump(0), Target::Block(target), span);
+        }
+    }
+
+    /// The values the phis of `to` take from `from`, with their slots.
+    fn copies(&self, from: BlockId, to: BlockId) -> Vec<(Value, u16)> {
+        self.function
+            .block(to)
+            .phis
+            .iter()
+            .filter_map(|phi| {
+                let &(_, value) = phi.incoming.iter().find(|(b, _)| *b == from)?;
+                Some((value, *self.slots.get(&phi.result)?))
+            })
+            .filter(|&(value, slot)| self.slots.get(&value) != Some(&slot))
+            .collect()
+    }
+
+    /// Copies values into slots all at once: every value is pushed before
+    /// any slot is written. Values already in their slot stay put.
+    fn copy(&mut self, copies: &[(Value, u16)], span: Span) {
+        let copies: Vec<(Value, u16)> = copies
+            .iter()
+            .copied()
+            .filter(|&(value, slot)| self.slots.get(&value) != Some(&slot))
+            .collect();
+        for &(value, _) in &copies {
+            self.push(value, span);
+        }
+        for &(_, slot) in copies.iter().rev() {
+            self.emit(Op::SetLocal(slot), span);
+        }
+    }
+}
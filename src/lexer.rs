@@ -1,31 +1,32 @@
-use crate::tokens::{Token, TokenType};
+use crate::tokens::{Comment, Position, Token, TokenType};
 use std::collections::VecDeque;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::str::FromStr;
 
 pub struct Lexer {
-    pub stream: Vec<Vec<u8>>,
+    pub stream: Vec<Vec<char>>,
     line: usize,
     column: usize,
+    comments: Vec<Comment>,
 }
 
 impl Lexer {
-    pub(crate) fn new(file: File) -> Lexer {
-        let lines = BufReader::new(file)
+    pub(crate) fn new<R: Read>(source: R) -> Lexer {
+        let lines = BufReader::new(source)
             .lines()
-            .map(|line| line.expect("Line failed").into_bytes())
+            .map(|line| line.expect("Line failed").chars().collect())
             .collect();
 
         Lexer {
             stream: lines,
             line: 0,
             column: 0,
+            comments: Vec::new(),
         }
     }
 
     fn next(&mut self) -> Option<char> {
-        if self.stream.len() == self.line as usize {
+        if self.stream.len() == self.line {
             return None;
         }
 
@@ -35,228 +36,240 @@ impl Lexer {
             return Some('\n');
         }
 
-        let c: char = self.stream[self.line][self.column] as char;
-        // println!("Char:  {} , Line:  {} , Column:  {} , Depth:  {} ", c, self.line, self.column, self.depth);
+        let c = self.stream[self.line][self.column];
         self.column += 1;
         Some(c)
     }
 
     fn peek(&self) -> Option<char> {
-        if self.stream.len() == self.line as usize || self.stream[self.line].len() == self.column {
-            None
-        } else {
-            Some(self.stream[self.line][self.column] as char)
-        }
+        self.peek_nth(0)
     }
 
-    pub(crate) fn lex(mut self) -> (VecDeque<Token>, Vec<String>) {
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.stream
+            .get(self.line)
+            .and_then(|line| line.get(self.column + n))
+            .copied()
+    }
+
+    /// Returns the tokens, the lexing errors and the comments that were skipped.
+    pub(crate) fn lex(mut self) -> (VecDeque<Token>, Vec<String>, Vec<Comment>) {
         let mut error_list: Vec<String> = Vec::new();
         let mut token_list: Vec<Token> = Vec::new();
 
-        while let Some(c) = self.next() {
-            let col = self.column as u16;
-            let line = self.line as u32;
-            match self.match_chars(c) {
-                Ok(t) => {
-                    if t != TokenType::Whitespace {
-                        token_list.push(Token::new(t, line, col))
+        loop {
+            let line = self.line as u32 + 1;
+            let col = column(self.column + 1);
+            let Some(c) = self.next() else { break };
+
+            let comment_count = self.comments.len();
+            let result = self.match_chars(c);
+            if self.comments.len() > comment_count {
+                let trailing = token_list
+                    .last()
+                    .is_some_and(|t| t.position.0 == line && t.token_type != TokenType::Newline);
+                if let Some(comment) = self.comments.last_mut() {
+                    comment.trailing = trailing;
+                }
+            }
+
+            match result {
+                Ok(TokenType::Whitespace) => {}
+                Ok(mut t) => {
+                    // A minus sign is unary unless it follows something that ends an operand.
+                    if t == TokenType::Minus && !token_list.last().is_some_and(ends_operand) {
+                        t = TokenType::Negate;
                     }
+                    let end = if self.line as u32 + 1 == line {
+                        column(self.column + 1)
+                    } else {
+                        col.saturating_add(1)
+                    };
+                    token_list.push(Token::new(t, line, col, end));
                 }
-                Err(e) => error_list.push(e.to_owned()),
+                Err(e) => error_list.push(format!("{}:{}: {}", line, col, e)),
             }
         }
-        token_list.push(Token::new(
-            TokenType::EOF,
-            self.line as u32,
-            self.column as u16,
-        ));
 
-        return (token_list.into(), error_list);
+        let line = self.stream.len() as u32 + 1;
+        token_list.push(Token::new(TokenType::EOF, line, 1, 1));
+
+        (token_list.into(), error_list, self.comments)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.column += 1;
+            true
+        } else {
+            false
+        }
     }
 
     fn match_chars(&mut self, c: char) -> Result<TokenType, String> {
         match c {
-            '\n' => return Ok(TokenType::Newline),
-            '\'' => return Ok(TokenType::SingleQuote),
+            '\n' => Ok(TokenType::Newline),
+            '\'' => Ok(TokenType::SingleQuote),
             '\"' => {
-                let mut s = c.to_string();
-                while let Some(new_char) = self.next() {
-                    match new_char {
-                        '\"' => break,
-                        '\n' => return Err("Multiline strings not supported".to_string()),
-                        _ => s.push(new_char),
+                let mut s = String::new();
+                loop {
+                    match self.peek() {
+                        None => return Err("Multiline strings not supported".to_string()),
+                        Some('\"') => {
+                            self.column += 1;
+                            break;
+                        }
+                        Some('\\') => {
+                            self.column += 1;
+                            match self.next() {
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some('0') => s.push('\0'),
+                                Some('\\') => s.push('\\'),
+                                Some('\"') => s.push('\"'),
+                                Some(other) => {
+                                    return Err(format!("Unknown escape sequence '\\{}'", other))
+                                }
+                                None => return Err("Unterminated string".to_string()),
+                            }
+                        }
+                        Some(new_char) => {
+                            self.column += 1;
+                            s.push(new_char);
+                        }
                     }
                 }
 
-                return Ok(TokenType::StringLit(s));
-            }
-            '\\' => return Ok(TokenType::Delim),
-            '(' => return Ok(TokenType::LParen),
-            ')' => return Ok(TokenType::RParen),
-            '[' => return Ok(TokenType::RBracket),
-            ']' => return Ok(TokenType::LBracket),
-            ':' => return Ok(TokenType::Colon),
-            ';' => return Ok(TokenType::Newline),
-            ',' => return Ok(TokenType::Comma),
-            '.' => return Ok(TokenType::Dot),
-            '<' => return Ok(TokenType::LT),
-            '>' => return Ok(TokenType::GT),
-            '@' => return Ok(TokenType::At),
-            '_' => return Ok(TokenType::Underscore),
-            '?' => return Ok(TokenType::Question),
-            '{' => return Ok(TokenType::LBrace),
-            '}' => return Ok(TokenType::RBrace),
-            '!' => {
-                if let Some('=') = self.peek() {
-                    self.column += 1;
-                    Ok(TokenType::BangEq)
-                } else {
-                    Ok(TokenType::Bang)
-                }
-            }
-            '%' => {
-                if let Some('=') = self.peek() {
-                    self.column += 1;
-                    Ok(TokenType::PercentEq)
-                } else {
-                    Ok(TokenType::Percent)
-                }
-            }
-            '+' => {
-                if let Some('=') = self.peek() {
-                    self.column += 1;
-                    Ok(TokenType::PlusEq)
-                } else {
-                    Ok(TokenType::Plus)
-                }
+                Ok(TokenType::StringLit(s))
             }
-            '*' => {
-                if let Some('=') = self.peek() {
-                    self.column += 1;
-                    Ok(TokenType::StarEq)
-                } else {
-                    Ok(TokenType::Star)
-                }
+            '\\' => Ok(TokenType::Delim),
+            '(' => Ok(TokenType::LParen),
+            ')' => Ok(TokenType::RParen),
+            '[' => Ok(TokenType::LBracket),
+            ']' => Ok(TokenType::RBracket),
+            ':' => Ok(TokenType::Colon),
+            ';' => Ok(TokenType::Newline),
+            ',' => Ok(TokenType::Comma),
+            '.' if self.eat('.') => Ok(TokenType::DotDot),
+            '.' => Ok(TokenType::Dot),
+            '?' => Ok(TokenType::Question),
+            '{' => Ok(TokenType::LBrace),
+            '}' => Ok(TokenType::RBrace),
+            '<' if self.eat('=') => Ok(TokenType::LTEq),
+            '<' => Ok(TokenType::LT),
+            '>' if self.eat('=') => Ok(TokenType::GTEq),
+            '>' => Ok(TokenType::GT),
+            '!' if self.eat('=') => Ok(TokenType::BangEq),
+            '!' => Ok(TokenType::Bang),
+            '%' if self.eat('=') => Ok(TokenType::PercentEq),
+            '%' => Ok(TokenType::Percent),
+            '+' if self.eat('=') => Ok(TokenType::PlusEq),
+            '+' => Ok(TokenType::Plus),
+            '*' if self.eat('=') => Ok(TokenType::StarEq),
+            '*' => Ok(TokenType::Star),
+            '=' if self.eat('=') => Ok(TokenType::EqualsEq),
+            '=' => Ok(TokenType::Equals),
+            '/' if self.peek() == Some('/') => {
+                // Comments run to the end of the line, which still ends the current line.
+                let text = &self.stream[self.line][self.column + 1..];
+                self.comments.push(Comment {
+                    text: text.iter().collect::<String>().trim_end().to_string(),
+                    position: Position(self.line as u32 + 1, column(self.column)),
+                    trailing: false,
+                });
+                self.column = self.stream[self.line].len();
+                self.next();
+                Ok(TokenType::Newline)
             }
-            '=' => {
-                if let Some('=') = self.peek() {
-                    self.column += 1;
-                    Ok(TokenType::EqualsEq)
-                } else {
-                    Ok(TokenType::Equals)
-                }
-            }
-            '/' => match self.peek() {
-                Some('/') => {
-                    self.line += 1;
-                    self.column = 0;
-                    Ok(TokenType::Newline)
-                }
-                Some('=') => {
-                    self.next();
-                    Ok(TokenType::SlashEq)
-                }
-                _ => Ok(TokenType::Slash),
-            },
-            '-' => match self.peek().expect("Early end of File") {
-                'a'..='z' | 'A'..='Z' | '0'..='9' => Ok(TokenType::Negate),
-                '>' => {
-                    self.next();
-                    Ok(TokenType::Arrow)
-                }
-                '=' => {
-                    self.next();
-                    Ok(TokenType::MinusEq)
-                }
-                ' ' => Ok(TokenType::Minus),
-                _ => Err(format!(
-                    "Unexpected character {}:{}",
-                    self.line, self.column
-                )),
-            },
-            'a'..='z' | 'A'..='Z' => {
+            '/' if self.eat('=') => Ok(TokenType::SlashEq),
+            '/' => Ok(TokenType::Slash),
+            '-' if self.eat('>') => Ok(TokenType::Arrow),
+            '-' if self.eat('=') => Ok(TokenType::MinusEq),
+            '-' => Ok(TokenType::Minus),
+            '_' if !self.peek().is_some_and(is_ident_char) => Ok(TokenType::Underscore),
+            '@' if !self.peek().is_some_and(|c| c.is_ascii_alphabetic()) => Ok(TokenType::At),
+            'a'..='z' | 'A'..='Z' | '_' | '@' => {
                 let mut ident = c.to_string();
 
-                while let Some(new_char) = self.next() {
-                    match new_char {
-                        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => ident.push(new_char),
-                        ' ' | '\n' | '(' | ')' | '.' | ',' | '{' | '}' | ':' | '<' | '>' | '['
-                        | ']' | '@' => {
-                            if self.column != 0 {
-                                self.column -= 1;
-                            }
-                            break;
-                        }
-                        _ => {
-                            return Err(format!(
-                                "Illegal Identifier character: \'{}\'    {}:{}",
-                                &new_char, self.line, self.column
-                            ));
-                        }
+                while let Some(new_char) = self.peek() {
+                    let kebab = new_char == '-'
+                        && self.peek_nth(1).is_some_and(|c| c.is_ascii_alphabetic());
+                    if is_ident_char(new_char) || kebab {
+                        ident.push(new_char);
+                        self.column += 1;
+                    } else {
+                        break;
                     }
                 }
 
-                Ok(TokenType::from_str(&ident).unwrap())
+                Ok(TokenType::from_str(&ident).unwrap_or(TokenType::Ident(ident)))
             }
             '0'..='9' => {
                 let mut num_lit = c.to_string();
                 let mut floating = false;
 
-                loop {
-                    if let Some(new_char) = self.next() {
-                        match new_char {
-                            '0'..='9' => num_lit.push(new_char),
-                            '_' => {}
-                            '.' => {
-                                num_lit.push(new_char);
-                                floating = true;
-                                match self.peek().unwrap() {
-                                    '0'..='9' => {}
-                                    _ => {
-                                        return Err(format!(
-                                            "Float cannot end in '.' {}:{}",
-                                            self.line, self.column
-                                        ))
-                                    }
+                while let Some(new_char) = self.peek() {
+                    match new_char {
+                        '0'..='9' => num_lit.push(new_char),
+                        '_' => {}
+                        '.' if !floating => {
+                            if !self.peek_nth(1).is_some_and(|c| c.is_ascii_digit()) {
+                                // `1.` is an error, but `x.0.y`-style access stops at the dot.
+                                if self.peek_nth(1).is_some_and(|c| c.is_ascii_alphabetic()) {
+                                    break;
                                 }
+                                return Err("Float cannot end in '.'".to_string());
                             }
-                            _ => break,
+                            num_lit.push(new_char);
+                            floating = true;
                         }
+                        _ => break,
                     }
-                }
-                if self.column != 0 {
-                    self.column -= 1;
+                    self.column += 1;
                 }
 
                 if floating {
-                    if let Ok(n) = num_lit.parse::<f64>() {
-                        Ok(TokenType::FloatLit(*Box::new(n)))
-                    } else {
-                        Err(format!(
-                            "Cannot parse Float Literal {}:{}",
-                            self.line, self.column
-                        ))
-                    }
+                    num_lit
+                        .parse::<f64>()
+                        .map(TokenType::FloatLit)
+                        .map_err(|_| "Cannot parse Float Literal".to_string())
                 } else {
-                    if let Ok(n) = num_lit.parse::<i64>() {
-                        Ok(TokenType::IntLit(*Box::new(n)))
-                    } else {
-                        Err(format!(
-                            "Cannot parse Int Literal {}:{}",
-                            self.line, self.column
-                        ))
-                    }
+                    num_lit
+                        .parse::<i64>()
+                        .map(TokenType::IntLit)
+                        .map_err(|_| "Cannot parse Int Literal".to_string())
                 }
             }
 
             c if c.is_ascii_whitespace() => Ok(TokenType::Whitespace),
 
-            _ => {
-                return Err(format!(
-                    "Unexpected atom: {} {}:{}",
-                    c, self.line, self.column
-                ))
-            }
+            _ => Err(format!("Unexpected atom: {}", c)),
         }
     }
 }
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token.token_type,
+        TokenType::Ident(_)
+            | TokenType::IntLit(_)
+            | TokenType::FloatLit(_)
+            | TokenType::StringLit(_)
+            | TokenType::RParen
+            | TokenType::RBracket
+            | TokenType::RBrace
+            | TokenType::KwTrue
+            | TokenType::KwFalse
+            | TokenType::KwNull
+    )
+}
+
+/// A column as a `Position` holds it. Columns past the last one it can hold
+/// are reported at that one.
+fn column(column: usize) -> u16 {
+    u16::try_from(column).unwrap_or(u16::MAX)
+}
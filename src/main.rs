@@ -1,26 +1,717 @@
-use crate::parser::{Parse, Parser};
+use std::io::{Read, Write};
+use std::process::ExitCode;
+
+use crate::abstract_syntax_tree::Module;
+use crate::ast_json::{FromJson, ToJson};
+use crate::json::Json;
+use crate::parser::Parser;
+use crate::tokens::Comment;
 
 mod tokens;
 mod lexer;
 mod parser;
 mod abstract_syntax_tree;
 mod operator;
+mod json;
+mod ast_json;
+mod ast_sexp;
+mod formatter;
+mod diagnostics;
+mod builtins;
+mod resolver;
+mod purity;
+mod mutability;
+mod consteval;
+mod exhaustiveness;
+mod captures;
+mod tailcalls;
+mod memo;
+mod mono;
+mod interpreter;
+mod bytecode;
+mod compiler;
+mod vm;
+mod chopc;
+mod disasm;
+mod c_backend;
+mod wasm;
+mod wasm_backend;
+mod wasm_validate;
+mod x86_64_backend;
+mod ir;
+mod ir_build;
+mod ir_lower;
+mod optimize;
+mod hir;
+mod desugar;
+mod wasm_interpreter;
+mod types;
+mod typeclasses;
+mod typeck;
 
-fn main() {
+/// Stack for the thread `chop run` interprets the program on, with room
+/// for `interpreter::MAX_CALL_DEPTH` calls. A call takes about 16 KiB of it
+/// in an unoptimized build, and a fraction of that in an optimized one.
+const INTERPRETER_STACK_SIZE: usize = if cfg!(debug_assertions) {
+    4 << 30
+} else {
+    1 << 30
+};
 
+const USAGE: &str = "usage: chop <command> [options] <file>
 
-    let file = std::fs::File::open("./examples/main.chop").expect("File Open Error");
+commands:
+  parse    parse a file and print its syntax tree
+           --emit=ast-json   versioned JSON dump (default)
+           --emit=ast-sexp   S-expression dump
+           --emit=hir        the desugared form, for reading
+           --from=ast-json   read a JSON dump instead of chop source
+  fmt      rewrite files in the canonical chop style (directories are searched for .chop files)
+           --check           only list unformatted files, failing if there are any
+  run      check a file, then run its `proc main` (.chopc and .wasm files run as they are)
+           --engine=vm       compile it to bytecode for the virtual machine (default)
+           --engine=ast      interpret the syntax tree directly
+           -O1, -O2          optimize the bytecode first (run, compile, disasm and build)
+  compile  check a file and save its bytecode next to it as a .chopc file
+           --output=<path>   write the bytecode to <path> instead
+  disasm   print the bytecode of a .chop or .chopc file with its source lines
+  build    check a file and compile it to a native executable next to it
+           --target=c        generate C99 and compile it with `cc`, or with $CC (default)
+           --target=wasm     generate a WebAssembly module next to it as a .wasm file
+           --target=x86-64   generate x86-64 assembly and link it with `cc`
+           --emit=c          print the generated C instead of compiling it
+           --emit=asm        print the generated assembly (--target=x86-64)
+           --emit=ir         print the optimized SSA form of every function instead
+           --output=<path>   write the executable to <path> instead
+  check    report semantic errors such as undefined names and type mismatches
+           --emit=names      also print the definition each name refers to
+           --emit=types      also print the inferred type of every fn, proc and const
+           --emit=consts     also print the value of every const known at compile time
+           --emit=mono       also print every copy of a generic function the program needs
 
-    let lexer = lexer::Lexer::new(file);
-    let (token_stream, error_list) = lexer.lex();
+Use '-' as the file to read from standard input.";
 
-    if !error_list.is_empty() {
-        for x in error_list {
-            dbg!("{}", x);
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let result = match command.as_str() {
+        "parse" => parse_command(rest),
+        "fmt" => fmt_command(rest),
+        "check" => check_command(rest),
+        "run" => run_command(rest),
+        "compile" => compile_command(rest),
+        "disasm" => disasm_command(rest),
+        "build" => build_command(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(vec![format!("unknown command '{}'\n\n{}", other, USAGE)]),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Command line options shared by every command: `--key=value` flags and input paths.
+struct Options {
+    flags: Vec<(String, String)>,
+    paths: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, Vec<String>> {
+        let mut flags = Vec::new();
+        let mut paths = Vec::new();
+        for arg in args {
+            if let Some(flag) = arg.strip_prefix("--") {
+                let (key, value) = flag.split_once('=').unwrap_or((flag, ""));
+                flags.push((key.to_string(), value.to_string()));
+            } else if let Some(level) = arg.strip_prefix("-O") {
+                flags.push(("O".to_string(), level.to_string()));
+            } else {
+                paths.push(arg.clone());
+            }
+        }
+        if paths.is_empty() {
+            return Err(vec![format!("missing input file\n\n{}", USAGE)]);
+        }
+        Ok(Options { flags, paths })
+    }
+
+    /// The input of commands that take exactly one file.
+    fn path(&self) -> Result<&str, Vec<String>> {
+        match self.paths.as_slice() {
+            [path] => Ok(path),
+            [_, extra, ..] => Err(vec![format!("unexpected argument '{}'", extra)]),
+            [] => unreachable!("Options::parse requires a path"),
+        }
+    }
+
+    fn has_flag(&self, key: &str) -> bool {
+        self.flags.iter().any(|(k, _)| k == key)
+    }
+
+    fn flag(&self, key: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The optimization level `-O<n>` asks for; `-O0` by default.
+    fn level(&self) -> Result<u8, Vec<String>> {
+        match self.flag("O") {
+            None | Some("0") => Ok(0),
+            Some("1") => Ok(1),
+            Some("2") => Ok(2),
+            Some(other) => Err(vec![format!("unknown optimization level '{}'", other)]),
         }
     }
+}
+
+fn read_input(path: &str) -> Result<String, Vec<String>> {
+    let mut source = String::new();
+    let result = if path == "-" {
+        std::io::stdin().read_to_string(&mut source).map(|_| ())
+    } else {
+        std::fs::File::open(path).and_then(|mut f| f.read_to_string(&mut source).map(|_| ()))
+    };
+    result
+        .map(|_| source)
+        .map_err(|e| vec![format!("{}: {}", path, e)])
+}
+
+fn read_bytes(path: &str) -> Result<Vec<u8>, Vec<String>> {
+    let mut bytes = Vec::new();
+    let result = if path == "-" {
+        std::io::stdin().read_to_end(&mut bytes).map(|_| ())
+    } else {
+        std::fs::File::open(path).and_then(|mut f| f.read_to_end(&mut bytes).map(|_| ()))
+    };
+    result
+        .map(|_| bytes)
+        .map_err(|e| vec![format!("{}: {}", path, e)])
+}
+
+fn parse_source(path: &str, source: &str) -> Result<Module, Vec<String>> {
+    parse_with_comments(path, source).map(|(module, _)| module)
+}
+
+fn parse_with_comments(path: &str, source: &str) -> Result<(Module, Vec<Comment>), Vec<String>> {
+    let lexer = lexer::Lexer::new(source.as_bytes());
+    let (token_stream, error_list, comments) = lexer.lex();
+
+    if !error_list.is_empty() {
+        return Err(error_list
+            .into_iter()
+            .map(|e| format!("{}:{}", path, e))
+            .collect());
+    }
 
-    dbg!(&token_stream);
     let mut parser = Parser::new(token_stream);
+    parser
+        .ast_build()
+        .map(|module| (module, comments))
+        .map_err(|errors| errors.iter().map(|e| format!("{}:{}", path, e)).collect())
+}
+
+fn load_module(options: &Options) -> Result<Module, Vec<String>> {
+    let path = options.path()?;
+    let source = read_input(path)?;
+    match options.flag("from") {
+        None | Some("chop") => parse_source(path, &source),
+        Some("ast-json") => Json::parse(&source)
+            .and_then(|json| Module::from_json(&json))
+            .map_err(|e| vec![format!("{}: invalid AST dump: {}", path, e)]),
+        Some(other) => Err(vec![format!("unknown input format '{}'", other)]),
+    }
+}
+
+fn parse_command(args: &[String]) -> Result<(), Vec<String>> {
+    let options = Options::parse(args)?;
+    let module = load_module(&options)?;
+
+    match options.flag("emit").unwrap_or("ast-json") {
+        "ast-json" => println!("{}", module.to_json().pretty()),
+        "ast-sexp" => println!("{}", ast_sexp::to_sexp(&module.to_json())),
+        "hir" => print!("{}", hir::print(&desugar::desugar(&module))),
+        other => return Err(vec![format!("unknown emit format '{}'", other)]),
+    }
+    Ok(())
+}
+
+/// Expands directories into the `.chop` files they contain, in a stable order.
+fn collect_sources(paths: &[String]) -> Result<Vec<String>, Vec<String>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let meta = std::fs::metadata(path).map_err(|e| vec![format!("{}: {}", path, e)])?;
+        if !meta.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let entries = std::fs::read_dir(path).map_err(|e| vec![format!("{}: {}", path, e)])?;
+        let mut nested: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|p| p.is_dir() || p.extension().is_some_and(|ext| ext == "chop"))
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        nested.sort();
+        files.extend(collect_sources(&nested)?);
+    }
+    Ok(files)
+}
+
+fn format_source(path: &str, source: &str) -> Result<String, Vec<String>> {
+    let (module, comments) = parse_with_comments(path, source)?;
+    let formatted = formatter::format_module(&module, comments);
+
+    // Formatting must never change what the program means.
+    let reparsed = parse_source(path, &formatted)
+        .map_err(|e| vec![format!("{}: formatter produced invalid code: {}", path, e.join("; "))])?;
+    if ast_json::without_spans(&reparsed.to_json()) != ast_json::without_spans(&module.to_json()) {
+        return Err(vec![format!("{}: formatter changed the syntax tree", path)]);
+    }
+    Ok(formatted)
+}
+
+fn fmt_command(args: &[String]) -> Result<(), Vec<String>> {
+    let options = Options::parse(args)?;
+    let check = options.has_flag("check");
+
+    if options.paths == ["-"] {
+        let source = read_input("-")?;
+        let formatted = format_source("-", &source)?;
+        if check {
+            return if formatted == source {
+                Ok(())
+            } else {
+                Err(vec!["-: not formatted".to_string()])
+            };
+        }
+        print!("{}", formatted);
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    for path in collect_sources(&options.paths)? {
+        let source = read_input(&path)?;
+        let formatted = match format_source(&path, &source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                errors.extend(e);
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            errors.push(format!("{}: not formatted", path));
+        } else if let Err(e) = std::fs::write(&path, formatted) {
+            errors.push(format!("{}: {}", path, e));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn check_command(args: &[String]) -> Result<(), Vec<String>> {
+    let options = Options::parse(args)?;
+    let path = options.path()?;
+    let source = read_input(path)?;
+    let mut module = parse_source(path, &source)?;
+
+    let emit = options.flag("emit");
+    if let Some(other) = emit.filter(|e| !matches!(*e, "names" | "types" | "consts" | "mono")) {
+        return Err(vec![format!("unknown emit format '{}'", other)]);
+    }
 
+    let (_, _, diagnostics) = analyse(&mut module, emit);
+    for diagnostic in &diagnostics {
+        eprint!("{}", diagnostic.render(path, &source));
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        Err(vec![format!("{}: could not check due to previous errors", path)])
+    } else {
+        Ok(())
+    }
+}
+
+fn run_command(args: &[String]) -> Result<(), Vec<String>> {
+    let options = Options::parse(args)?;
+    let path = options.path()?;
+    let level = options.level()?;
+    let engine = options.flag("engine").unwrap_or("vm");
+    if !matches!(engine, "vm" | "ast") {
+        return Err(vec![format!("unknown engine '{}'", engine)]);
+    }
+    if is_compiled(path) {
+        if engine != "vm" {
+            return Err(vec![format!("{}: compiled programs only run on the vm engine", path)]);
+        }
+        let (program, source_path, source) = load_compiled(path)?;
+        return vm::run(&program).map_err(|diagnostic| {
+            eprint!("{}", diagnostic.render(&source_path, &source));
+            vec![format!("{}: the program stopped with an error", path)]
+        });
+    }
+    if path.ends_with(".wasm") {
+        if engine != "vm" {
+            return Err(vec![format!("{}: wasm modules only run on the vm engine", path)]);
+        }
+        return run_wasm(path);
+    }
+    let source = read_input(path)?;
+    let (module, resolution, mono) = check_program(path, &source, "run")?;
+    let main = main_proc(path, &module)?;
+    let hir = desugar::desugar(&module);
+
+    let result = match engine {
+        "vm" => {
+            let mut program = compiler::compile(&module, &hir, &resolution, &mono, main);
+            optimize::optimize(&mut program, level);
+            vm::run(&program)
+        }
+        // Every call in the program nests a few calls of the interpreter,
+        // which needs more stack than the main thread has.
+        _ => std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(INTERPRETER_STACK_SIZE)
+                .spawn_scoped(scope, || interpreter::run(&module, &hir, &resolution, main))
+                .map_err(|e| vec![format!("could not start the interpreter: {}", e)])?
+                .join()
+                .map_err(|_| vec![format!("{}: the interpreter crashed", path)])
+        })?,
+    };
+    result.map_err(|diagnostic| {
+        eprint!("{}", diagnostic.render(path, &source));
+        vec![format!("{}: the program stopped with an error", path)]
+    })
+}
+
+/// Loads, validates and runs a `.wasm` module built by `chop build`.
+fn run_wasm(path: &str) -> Result<(), Vec<String>> {
+    let bytes = read_bytes(path)?;
+    let module = wasm::Module::decode(&bytes).map_err(|e| vec![format!("{}: {}", path, e)])?;
+    let targets = wasm_validate::validate(&module)
+        .map_err(|e| vec![format!("{}: invalid module: {}", path, e)])?;
+    let result = wasm_interpreter::run(&module, &targets, &mut std::io::stdout().lock());
+    match result {
+        Ok(()) => Ok(()),
+        Err(wasm_interpreter::Stop::Failed(diagnostic)) => {
+            let source_path = module
+                .custom("chop.source")
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+                .unwrap_or_default();
+            let source = std::fs::read_to_string(&source_path).unwrap_or_default();
+            eprint!("{}", diagnostic.render(&source_path, &source));
+            Err(vec![format!("{}: the program stopped with an error", path)])
+        }
+        Err(wasm_interpreter::Stop::Trap(trap)) => {
+            Err(vec![format!("{}: the module trapped: {}", path, trap)])
+        }
+    }
+}
+
+fn compile_command(args: &[String]) -> Result<(), Vec<String>> {
+    let options = Options::parse(args)?;
+    let path = options.path()?;
+    let output = match options.flag("output") {
+        Some(output) => output.to_string(),
+        None if path == "-" => {
+            return Err(vec!["--output is required when compiling standard input".to_string()])
+        }
+        None => format!("{}c", path.strip_suffix(".chopc").unwrap_or(path)),
+    };
+    let source = read_input(path)?;
+    let program = compile_source(path, &source, options.level()?)?;
+    std::fs::write(&output, chopc::write(&program, path))
+        .map_err(|e| vec![format!("{}: {}", output, e)])
+}
+
+fn disasm_command(args: &[String]) -> Result<(), Vec<String>> {
+    let options = Options::parse(args)?;
+    let path = options.path()?;
+    let (program, source) = if is_compiled(path) {
+        let (program, _, source) = load_compiled(path)?;
+        (program, source)
+    } else {
+        let source = read_input(path)?;
+        (compile_source(path, &source, options.level()?)?, source)
+    };
+    print!("{}", disasm::disassemble(&program, Some(&source)));
+    Ok(())
+}
+
+fn build_command(args: &[String]) -> Result<(), Vec<String>> {
+    let options = Options::parse(args)?;
+    let path = options.path()?;
+    let target = options.flag("target").unwrap_or("c");
+    if !matches!(target, "c" | "wasm" | "x86-64") {
+        return Err(vec![format!("unknown target '{}'", target)]);
+    }
+    let emit = options.flag("emit");
+    let emits = match target {
+        "c" => Some("c"),
+        "x86-64" => Some("asm"),
+        _ => None,
+    };
+    if let Some(other) = emit.filter(|e| Some(*e) != emits && *e != "ir") {
+        return Err(vec![format!("unknown emit format '{}'", other)]);
+    }
+    let output = match options.flag("output") {
+        Some(output) => output.to_string(),
+        None if emit.is_some() => String::new(),
+        None if path == "-" => {
+            return Err(vec!["--output is required when building standard input".to_string()])
+        }
+        None => match (path.strip_suffix(".chop"), target) {
+            (Some(stem), "wasm") => format!("{}.wasm", stem),
+            (None, "wasm") => format!("{}.wasm", path),
+            (Some(stem), _) => stem.to_string(),
+            (None, _) => format!("{}.out", path),
+        },
+    };
+    let level = options.level()?;
+    let source = read_input(path)?;
+    if emit == Some("ir") {
+        let (module, resolution, mono) = check_program(path, &source, "compile")?;
+        let hir = desugar::desugar(&module);
+        let main = main_proc(path, &module)?;
+        let program = compiler::compile(&module, &hir, &resolution, &mono, main);
+        print!("{}", optimize::emit(program, level));
+        return Ok(());
+    }
+    let program = compile_source(path, &source, level)?;
+    if target == "wasm" {
+        let module = wasm_backend::generate(&program, path).map_err(|diagnostics| {
+            for diagnostic in &diagnostics {
+                eprint!("{}", diagnostic.render(path, &source));
+            }
+            vec![format!("{}: could not build due to previous errors", path)]
+        })?;
+        let bytes = module.encode();
+        // The module is checked as a runtime would load it before it is
+        // written, so that a backend bug cannot ship an invalid file.
+        wasm::Module::decode(&bytes)
+            .and_then(|module| wasm_validate::validate(&module))
+            .map_err(|e| vec![format!("{}: generated an invalid module: {}", path, e)])?;
+        return std::fs::write(&output, bytes).map_err(|e| vec![format!("{}: {}", output, e)]);
+    }
+    if target == "x86-64" {
+        let assembly = x86_64_backend::generate(&program, path, &source).map_err(|diagnostics| {
+            for diagnostic in &diagnostics {
+                eprint!("{}", diagnostic.render(path, &source));
+            }
+            vec![format!("{}: could not build due to previous errors", path)]
+        })?;
+        if emit.is_some() {
+            print!("{}", assembly);
+            return Ok(());
+        }
+        return assemble(&assembly, &output).map_err(|e| vec![format!("{}: {}", path, e)]);
+    }
+    let c = c_backend::generate(&program, path, &source);
+    if emit.is_some() {
+        print!("{}", c);
+        return Ok(());
+    }
+    compile_c(&c, &output).map_err(|e| vec![format!("{}: {}", path, e)])
+}
+
+/// Compiles a generated C program with the system C compiler, or the one
+/// `CC` names.
+fn compile_c(c: &str, output: &str) -> Result<(), String> {
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let mut child = std::process::Command::new(&compiler)
+        .args(["-std=c99", "-O2", "-g", "-o", output, "-x", "c", "-", "-lm", "-pthread"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run the C compiler `{}`: {}", compiler, e))?;
+    let written = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(c.as_bytes());
+    let status = child
+        .wait()
+        .map_err(|e| format!("the C compiler `{}` failed: {}", compiler, e))?;
+    match written {
+        Ok(()) if status.success() => Ok(()),
+        _ => Err(format!("the C compiler `{}` failed", compiler)),
+    }
+}
+
+/// Assembles and links generated x86-64 assembly with the system C
+/// compiler driver, which also links in the C library the runtime uses.
+fn assemble(assembly: &str, output: &str) -> Result<(), String> {
+    let mut child = std::process::Command::new("cc")
+        .args(["-o", output, "-x", "assembler", "-", "-lm"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run the assembler `cc`: {}", e))?;
+    let written = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(assembly.as_bytes());
+    let status = child
+        .wait()
+        .map_err(|e| format!("the assembler `cc` failed: {}", e))?;
+    match written {
+        Ok(()) if status.success() => Ok(()),
+        _ => Err("the assembler `cc` failed".to_string()),
+    }
+}
+
+/// Whether a path names a compiled program rather than chop source.
+fn is_compiled(path: &str) -> bool {
+    path.ends_with(".chopc")
+}
+
+/// Loads a `.chopc` file, with the path and text of the source it was
+/// compiled from. The text is empty when the source can no longer be read.
+fn load_compiled(path: &str) -> Result<(bytecode::Program, String, String), Vec<String>> {
+    let bytes = read_bytes(path)?;
+    let (program, source_path) = chopc::read(&bytes).map_err(|e| vec![format!("{}: {}", path, e)])?;
+    let source = if source_path == "-" {
+        String::new()
+    } else {
+        std::fs::read_to_string(&source_path).unwrap_or_default()
+    };
+    Ok((program, source_path, source))
+}
+
+/// Checks a program and compiles it to bytecode for the virtual machine,
+/// optimized at `level`.
+fn compile_source(path: &str, source: &str, level: u8) -> Result<bytecode::Program, Vec<String>> {
+    let (module, resolution, mono) = check_program(path, source, "compile")?;
+    let main = main_proc(path, &module)?;
+    let hir = desugar::desugar(&module);
+    let mut program = compiler::compile(&module, &hir, &resolution, &mono, main);
+    optimize::optimize(&mut program, level);
+    Ok(program)
+}
+
+/// Parses and analyses a program that is about to be run or compiled,
+/// printing its diagnostics.
+fn check_program(
+    path: &str,
+    source: &str,
+    action: &str,
+) -> Result<(Module, resolver::Resolution, mono::Mono), Vec<String>> {
+    let mut module = parse_source(path, source)?;
+    let (resolution, mono, diagnostics) = analyse(&mut module, None);
+    for diagnostic in &diagnostics {
+        eprint!("{}", diagnostic.render(path, source));
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return Err(vec![format!("{}: could not {} due to previous errors", path, action)]);
+    }
+    Ok((module, resolution, mono))
+}
+
+fn main_proc<'m>(
+    path: &str,
+    module: &'m Module,
+) -> Result<&'m abstract_syntax_tree::Initialization, Vec<String>> {
+    interpreter::main_proc(module)
+        .ok_or_else(|| vec![format!("{}: there is no `proc main` to run", path)])
+}
+
+/// Runs every semantic pass, printing what `--emit` asks for along the way.
+/// Name resolution settles what some patterns mean, so it may rewrite them.
+fn analyse(
+    module: &mut Module,
+    emit: Option<&str>,
+) -> (resolver::Resolution, mono::Mono, Vec<diagnostics::Diagnostic>) {
+    let (resolution, mut diagnostics) = resolver::resolve(module);
+    if emit == Some("names") {
+        print_names(&resolution);
+    }
+
+    // Types, purity, mutability, coverage, captures, tail calls and caches
+    // are only checked once every name is known.
+    let mut instances = mono::Mono::default();
+    if diagnostics.is_empty() {
+        let (types, type_diagnostics) = typeck::check(module, &resolution);
+        if emit == Some("types") {
+            for signature in types.signatures(&resolution) {
+                println!("{}", signature);
+            }
+        }
+        diagnostics = type_diagnostics;
+        // Generic functions are only copied for types that check.
+        if !diagnostics.iter().any(|d| d.is_error()) {
+            let (mono, mono_diagnostics) = mono::instantiate(&types, &resolution);
+            if emit == Some("mono") {
+                for line in mono.listing(&resolution) {
+                    println!("{}", line);
+                }
+            }
+            diagnostics.extend(mono_diagnostics);
+            instances = mono;
+        }
+        diagnostics.extend(purity::check(module, &resolution, &types));
+        diagnostics.extend(mutability::check(module, &resolution, &types));
+        diagnostics.extend(exhaustiveness::check(module, &resolution));
+        diagnostics.extend(captures::check(module, &resolution, &types));
+        diagnostics.extend(tailcalls::check(module));
+        diagnostics.extend(memo::check(module));
+        diagnostics.sort_by_key(|d| d.span.start);
+    }
+
+    // Constants are evaluated only in a program that is otherwise sound.
+    if !diagnostics.iter().any(|d| d.is_error()) {
+        let (constants, const_diagnostics) = consteval::evaluate(module, &resolution);
+        if emit == Some("consts") {
+            for line in constants.listing() {
+                println!("{}", line);
+            }
+        }
+        diagnostics.extend(const_diagnostics);
+        diagnostics.sort_by_key(|d| d.span.start);
+    }
+    (resolution, instances, diagnostics)
+}
+
+fn print_names(resolution: &resolver::Resolution) {
+    let mut references: Vec<_> = resolution.references.iter().collect();
+    references.sort_by_key(|(span, _)| (span.start, span.end));
+    for (span, id) in references {
+        let definition = resolution.definition(*id);
+        match definition.span {
+            Some(defined) => println!(
+                "{}: {} -> {} at {}",
+                span.start,
+                definition.name,
+                definition.kind.describe(),
+                defined.start
+            ),
+            None => println!(
+                "{}: {} -> {}",
+                span.start,
+                definition.name,
+                definition.kind.describe()
+            ),
+        }
+    }
 }
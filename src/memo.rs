@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use crate::abstract_syntax_tree::{
+    Body, Conditional, Domain, Expr, ForStatement, Function, Initialization, Line, Literal, Module,
+    Statement, Struct, Typeclass, Value,
+};
+use crate::diagnostics::Diagnostic;
+use crate::tokens::Span;
+
+/// Checks where `@memo` is written.
+///
+/// It applies to a module level or member `fn` with a body, written on its
+/// first clause, whose cache has room for at least one result. A `proc`
+/// has effects that a cached call would skip, and a local `fn` may depend
+/// on the variables it captures as well as on its arguments. That a `fn`
+/// has no effects is the purity check's job, and that its arguments can be
+/// compared and hashed is the type checker's.
+pub fn check(module: &Module) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        diagnostics: Vec::new(),
+    };
+    let items: Vec<&Initialization> = module.items.iter().collect();
+    checker.items(&items, false);
+    checker.diagnostics.sort_by_key(|d| d.span.start);
+    checker.diagnostics
+}
+
+struct Checker {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    /// Checks `items`, which are declared in a body when `local` is set.
+    fn items(&mut self, items: &[&Initialization], local: bool) {
+        let mut first: HashMap<&str, Span> = HashMap::new();
+        for item in items {
+            let is_fn = matches!(item.value, Some(Value::Function(_)));
+            let first_clause = match first.get(item.name.0.as_str()) {
+                Some(span) if is_fn => Some(*span),
+                _ => None,
+            };
+            if is_fn {
+                first.entry(&item.name.0).or_insert(item.span);
+            }
+            if let Some(memo) = &item.memo {
+                if let Some(error) = self.misplaced(item, local, first_clause) {
+                    self.diagnostics.push(error);
+                } else if let Some(capacity) =
+                    memo.capacity.filter(|c| !(1..=u32::MAX as i64).contains(c))
+                {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "the cache of `{}` cannot hold {} results",
+                                item.name.0, capacity
+                            ),
+                            memo.span,
+                        )
+                        .with_help(format!("give it room for 1 to {} results", u32::MAX)),
+                    );
+                }
+            }
+            self.item(item);
+        }
+    }
+
+    /// Why `@memo` does not apply to `item`, if it does not.
+    fn misplaced(
+        &self,
+        item: &Initialization,
+        local: bool,
+        first_clause: Option<Span>,
+    ) -> Option<Diagnostic> {
+        let span = item.memo.as_ref().map_or(item.span, |memo| memo.span);
+        if item.domain != Domain::Fn {
+            let error = Diagnostic::error("`@memo` only applies to `fn`s".to_string(), span);
+            return Some(match item.domain {
+                Domain::Proc => {
+                    error.with_help("a cached call would skip the effects of a `proc`".to_string())
+                }
+                _ => error,
+            });
+        }
+        if local {
+            return Some(
+                Diagnostic::error(
+                    "`@memo` only applies to module level and member `fn`s".to_string(),
+                    span,
+                )
+                .with_help(
+                    "a local `fn` may depend on the variables it captures as well as on its \
+                     arguments"
+                        .to_string(),
+                ),
+            );
+        }
+        if let Some(first) = first_clause {
+            return Some(
+                Diagnostic::error(
+                    format!("`@memo` goes on the first clause of `{}`", item.name.0),
+                    span,
+                )
+                .with_note(first, "the first clause is here".to_string()),
+            );
+        }
+        match &item.value {
+            Some(Value::Function(Function { body: Some(_), .. })) => None,
+            _ => Some(Diagnostic::error(
+                format!(
+                    "`{}` has no body whose results could be cached",
+                    item.name.0
+                ),
+                span,
+            )),
+        }
+    }
+
+    fn item(&mut self, item: &Initialization) {
+        match &item.value {
+            Some(Value::Struct(Struct(lines))) | Some(Value::Typeclass(Typeclass(lines))) => {
+                let members: Vec<&Initialization> = lines
+                    .iter()
+                    .filter_map(|line| match line {
+                        Line::Initialization(init) => Some(init),
+                        _ => None,
+                    })
+                    .collect();
+                self.items(&members, false);
+            }
+            Some(Value::Function(function)) => self.function(function),
+            Some(Value::Expr(expr)) => self.expr(expr),
+            _ => {}
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        if let Some(guard) = &function.guard {
+            self.expr(guard);
+        }
+        match &function.body {
+            Some(Body::Expr(expr)) => self.expr(expr),
+            Some(Body::Block(lines)) => self.lines(lines),
+            None => {}
+        }
+    }
+
+    fn lines(&mut self, lines: &[Line]) {
+        let locals: Vec<&Initialization> = lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Initialization(init) => Some(init),
+                _ => None,
+            })
+            .collect();
+        self.items(&locals, true);
+        for line in lines {
+            match line {
+                Line::Initialization(_) | Line::Break(_) | Line::Continue(_) => {}
+                Line::Statement(Statement { args, .. }) => self.exprs(args),
+                Line::Assignment(assignment) => {
+                    self.expr(&assignment.target);
+                    self.expr(&assignment.value);
+                }
+                Line::MethodCall(expr) | Line::Return(expr) => self.expr(expr),
+                Line::For(ForStatement(_, iterable, body, _)) => {
+                    self.expr(iterable);
+                    self.lines(body);
+                }
+                Line::While(Conditional(condition, then, otherwise, _))
+                | Line::If(Conditional(condition, then, otherwise, _)) => {
+                    self.expr(condition);
+                    self.lines(then);
+                    if let Some(otherwise) = otherwise {
+                        self.lines(otherwise);
+                    }
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Grouping(_, inner, _) | Expr::FieldAccess(inner, _, _) => self.expr(inner),
+            Expr::Call(_, args, _) | Expr::Sequence(args, _) => self.exprs(args),
+            Expr::Literal(literal, _) => match literal {
+                Literal::List(elements) | Literal::Set(elements) | Literal::Tuple(elements) => {
+                    self.exprs(elements)
+                }
+                Literal::StructInitialization(_, fields, base) => {
+                    if let Some(base) = base {
+                        self.expr(base);
+                    }
+                    for field in fields {
+                        self.expr(&field.value);
+                    }
+                }
+                Literal::Map(entries) => {
+                    for (key, value) in entries {
+                        self.expr(key);
+                        self.expr(value);
+                    }
+                }
+                Literal::Closure(value) => {
+                    if let Value::Function(function) = value.as_ref() {
+                        self.function(function);
+                    }
+                }
+                _ => {}
+            },
+            Expr::Match(value, arms, _) => {
+                self.expr(value);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                    }
+                    self.expr(&arm.body);
+                }
+            }
+            Expr::Reference(_, _) => {}
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+}
+
+/// Where the hash of a call's arguments starts.
+pub const SEED: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0100_0000_01b3;
+
+/// Mixes a word into a hash, a step of FNV-1a over words.
+///
+/// Every engine hashes arguments alike, so that they all evict the same
+/// results: the arguments are mixed into `SEED` in order, an int or bool
+/// being its own word and a string the hash of its bytes. Other values,
+/// which only some engines cache, mix in their parts the same way; sets
+/// and maps add up the words of their elements, so order does not matter.
+pub fn mix(hash: u64, word: u64) -> u64 {
+    (hash ^ word).wrapping_mul(PRIME)
+}
+
+pub fn string(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(SEED, |hash, &b| mix(hash, b as u64))
+}
+
+/// The results a `@memo` fn has cached, in a table of `capacity` slots.
+///
+/// The hash of the arguments picks their slot, so a new result evicts the
+/// one in its slot. Slots are only allocated once they are used. Engines
+/// store copies of mutable arguments and results, so that changing a list
+/// after the call changes neither what it is looked up by nor what a later
+/// call gets back.
+pub struct Cache<V> {
+    capacity: u32,
+    slots: HashMap<u32, Entry<V>>,
+}
+
+struct Entry<V> {
+    hash: u64,
+    args: Vec<V>,
+    result: V,
+}
+
+impl<V> Cache<V> {
+    pub fn new(capacity: u32) -> Self {
+        Cache {
+            capacity,
+            slots: HashMap::new(),
+        }
+    }
+
+    fn slot(&self, hash: u64) -> u32 {
+        (hash % self.capacity as u64) as u32
+    }
+
+    /// The result cached for arguments with this hash, for which `same`
+    /// holds.
+    pub fn get(&self, hash: u64, same: impl Fn(&[V]) -> bool) -> Option<&V> {
+        self.slots
+            .get(&self.slot(hash))
+            .filter(|entry| entry.hash == hash && same(&entry.args))
+            .map(|entry| &entry.result)
+    }
+
+    pub fn insert(&mut self, hash: u64, args: Vec<V>, result: V) {
+        let slot = self.slot(hash);
+        self.slots.insert(slot, Entry { hash, args, result });
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostics::Diagnostic;
+use crate::resolver::{DefId, Resolution};
+use crate::tokens::Span;
+use crate::typeck::TypeInfo;
+use crate::types::{Type, TypeVar};
+
+/// One copy of a generic function, for one choice of its type parameters.
+pub struct Instance {
+    pub def: DefId,
+    pub args: Vec<Type>,
+    /// The function's name with its type arguments, as in `max<int>`.
+    pub name: String,
+    /// The function's type with its parameters replaced by the arguments.
+    ty: Type,
+}
+
+/// The copies of generic functions a program is compiled to.
+#[derive(Default)]
+pub struct Mono {
+    pub instances: Vec<Instance>,
+    /// The instance each use of a generic function calls, keyed by the
+    /// instance whose body the use is in, or by `None` in a body that is
+    /// compiled only once.
+    pub calls: HashMap<(Option<usize>, Span), usize>,
+}
+
+impl Mono {
+    /// One `fn name<args>: type` line per instance, in the order they were found.
+    pub fn listing(&self, resolution: &Resolution) -> Vec<String> {
+        self.instances
+            .iter()
+            .map(|instance| {
+                let kind = resolution.definition(instance.def).kind.describe();
+                format!("{} {}: {}", kind, instance.name, instance.ty)
+            })
+            .collect()
+    }
+}
+
+/// Decides which copies of the functions with declared `type T`
+/// parameters a program needs.
+///
+/// A use whose type arguments are known types calls the copy for them. One
+/// in a generic function whose arguments mention that function's own
+/// parameters calls a different copy in each copy of the caller, so copies
+/// are found from one another until no new one turns up. A use whose
+/// arguments are still variables the caller's type quantifies over calls
+/// the one shared copy, which works for every type; a variable nothing
+/// quantifies over is a parameter no part of the program decides.
+pub fn instantiate(types: &TypeInfo, resolution: &Resolution) -> (Mono, Vec<Diagnostic>) {
+    let quantified: HashSet<TypeVar> = types
+        .schemes
+        .values()
+        .flat_map(|scheme| scheme.vars.iter().copied())
+        .collect();
+    let mut mono = Mono::default();
+    let mut diagnostics = Vec::new();
+
+    for site in &types.sites {
+        let mut vars = Vec::new();
+        for arg in &site.args {
+            arg.free_vars(&mut vars);
+        }
+        if vars.is_empty() {
+            let instance = intern(&mut mono, types, resolution, site.callee, &site.args);
+            mono.calls.insert((None, site.span), instance);
+            continue;
+        }
+        let undecided = site.args.iter().position(|arg| {
+            let mut vars = Vec::new();
+            arg.free_vars(&mut vars);
+            vars.iter().any(|v| !quantified.contains(v))
+        });
+        if let Some(index) = undecided {
+            let name = &resolution.definition(site.callee).name;
+            let generic = types.generics[&site.callee]
+                .get(index)
+                .map_or("?", |(generic, _)| generic.as_str());
+            diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "the type parameter `{}` of `{}` cannot be inferred here",
+                        generic, name
+                    ),
+                    site.span,
+                )
+                .with_help(format!(
+                    "annotate a value it is used with so that `{}` is known",
+                    generic
+                )),
+            );
+        }
+    }
+
+    // Instances are appended while the list is walked, which visits the
+    // uses in each new copy exactly once.
+    let mut next = 0;
+    while next < mono.instances.len() {
+        let def = mono.instances[next].def;
+        let map: HashMap<TypeVar, Type> = types.generics[&def]
+            .iter()
+            .map(|(_, var)| *var)
+            .zip(mono.instances[next].args.iter().cloned())
+            .collect();
+        for site in types.sites.iter().filter(|site| site.caller == Some(def)) {
+            let args: Vec<Type> = site.args.iter().map(|a| a.substitute(&map)).collect();
+            let mut vars = Vec::new();
+            for arg in &args {
+                arg.free_vars(&mut vars);
+            }
+            if vars.is_empty() {
+                let instance = intern(&mut mono, types, resolution, site.callee, &args);
+                mono.calls.insert((Some(next), site.span), instance);
+            }
+        }
+        next += 1;
+    }
+    (mono, diagnostics)
+}
+
+/// The index of the instance of `def` for `args`, adding it if it is new.
+fn intern(
+    mono: &mut Mono,
+    types: &TypeInfo,
+    resolution: &Resolution,
+    def: DefId,
+    args: &[Type],
+) -> usize {
+    if let Some(index) = mono
+        .instances
+        .iter()
+        .position(|i| i.def == def && i.args == args)
+    {
+        return index;
+    }
+    let base = types
+        .name(def)
+        .unwrap_or(&resolution.definition(def).name)
+        .to_string();
+    let list: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    let map: HashMap<TypeVar, Type> = types.generics[&def]
+        .iter()
+        .map(|(_, var)| *var)
+        .zip(args.iter().cloned())
+        .collect();
+    let ty = types
+        .schemes
+        .get(&def)
+        .map_or(Type::unit(), |scheme| scheme.ty.substitute(&map));
+    mono.instances.push(Instance {
+        def,
+        args: args.to_vec(),
+        name: format!("{}<{}>", base, list.join(", ")),
+        ty,
+    });
+    mono.instances.len() - 1
+}
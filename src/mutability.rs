@@ -0,0 +1,423 @@
+use crate::abstract_syntax_tree::{
+    Assignment, Body, Conditional, Domain, Expr, ForStatement, Function, Initialization, Line,
+    Literal, Module, Name, Pattern, Struct, Typeclass, Value,
+};
+use crate::diagnostics::Diagnostic;
+use crate::resolver::{DefId, DefKind, Resolution};
+use crate::tokens::{Span, TokenType};
+use crate::typeck::TypeInfo;
+use crate::types::Type;
+
+/// Rejects writes through immutable bindings.
+///
+/// Only a `var` may be assigned to. Fields may be written through a `var`,
+/// or through a parameter of a `proc`, which is how a method like
+/// `proc push = (self, x)` changes its receiver. Calling a `proc` method
+/// counts as writing the fields of its receiver. Parameters themselves,
+/// `const`s and loop variables are never reassigned.
+///
+/// Structs are shared rather than copied, so a `const` and a `var` may not
+/// be bound to the same one: writing its fields through the `var` would
+/// change the `const`. Neither may a `const` share a struct with a
+/// parameter of a `proc`.
+pub fn check(module: &Module, resolution: &Resolution, types: &TypeInfo) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        resolution,
+        types,
+        proc_params: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    for item in &module.items {
+        checker.item(item);
+    }
+    checker.diagnostics.sort_by_key(|d| d.span.start);
+    checker.diagnostics
+}
+
+struct Checker<'a> {
+    resolution: &'a Resolution,
+    types: &'a TypeInfo,
+    /// Parameters of the enclosing `proc`s, whose fields may be written.
+    proc_params: Vec<DefId>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn item(&mut self, item: &Initialization) {
+        match &item.value {
+            Some(Value::Struct(Struct(lines))) | Some(Value::Typeclass(Typeclass(lines))) => {
+                self.lines(lines)
+            }
+            Some(Value::Function(function)) => self.function(function, item.domain == Domain::Proc),
+            Some(Value::Expr(expr)) => {
+                if let Some(id) = self.resolution.declaration(item.span, &item.name.0) {
+                    self.binding(id, expr);
+                }
+                self.expr(expr)
+            }
+            _ => {}
+        }
+    }
+
+    fn function(&mut self, function: &Function, is_proc: bool) {
+        let depth = self.proc_params.len();
+        if is_proc {
+            for param in &function.params {
+                if let Pattern::Binding(name) = &param.pattern {
+                    if let Some(id) = self.resolution.declaration(param.span, &name.0) {
+                        self.proc_params.push(id);
+                    }
+                }
+            }
+        }
+        match &function.body {
+            Some(Body::Expr(expr)) => self.expr(expr),
+            Some(Body::Block(lines)) => self.lines(lines),
+            None => {}
+        }
+        self.proc_params.truncate(depth);
+    }
+
+    fn lines(&mut self, lines: &[Line]) {
+        for line in lines {
+            match line {
+                Line::Initialization(init) => self.item(init),
+                Line::Assignment(assignment) => {
+                    self.assignment(assignment);
+                    if let Some((_, span)) = assignment.binding() {
+                        if let Some(&id) = self.resolution.references.get(&span) {
+                            self.binding(id, &assignment.value);
+                        }
+                    }
+                    self.expr(&assignment.value);
+                }
+                Line::Statement(statement) => {
+                    for arg in &statement.args {
+                        self.expr(arg);
+                    }
+                }
+                Line::MethodCall(expr) | Line::Return(expr) => self.expr(expr),
+                Line::For(ForStatement(_, iterable, body, _)) => {
+                    self.expr(iterable);
+                    self.lines(body);
+                }
+                Line::While(Conditional(condition, then, otherwise, _))
+                | Line::If(Conditional(condition, then, otherwise, _)) => {
+                    self.expr(condition);
+                    self.lines(then);
+                    if let Some(otherwise) = otherwise {
+                        self.lines(otherwise);
+                    }
+                }
+                Line::Break(_) | Line::Continue(_) => {}
+            }
+        }
+    }
+
+    /// Only closures can hold assignments inside an expression.
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call(_, args, _) | Expr::Sequence(args, _) => {
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Grouping(TokenType::Dot, inner, _) => {
+                if let Expr::Call(name, args, span) = inner.as_ref() {
+                    self.method_call(name, args, *span);
+                }
+                self.expr(inner);
+            }
+            Expr::FieldAccess(inner, _, _) | Expr::Grouping(_, inner, _) => self.expr(inner),
+            Expr::Literal(literal, _) => match literal {
+                Literal::List(elements) | Literal::Set(elements) | Literal::Tuple(elements) => {
+                    for element in elements {
+                        self.expr(element);
+                    }
+                }
+                Literal::StructInitialization(_, fields, base) => {
+                    if let Some(base) = base {
+                        self.expr(base);
+                    }
+                    for field in fields {
+                        self.expr(&field.value);
+                    }
+                }
+                Literal::Map(entries) => {
+                    for (key, value) in entries {
+                        self.expr(key);
+                        self.expr(value);
+                    }
+                }
+                Literal::Closure(value) => {
+                    if let Value::Function(function) = value.as_ref() {
+                        let is_proc = matches!(function.body, Some(Body::Block(_)));
+                        self.function(function, is_proc);
+                    }
+                }
+                _ => {}
+            },
+            Expr::Match(value, arms, _) => {
+                self.expr(value);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                    }
+                    self.expr(&arm.body);
+                }
+            }
+            Expr::Reference(_, _) => {}
+        }
+    }
+
+    /// A `proc` method may change the fields of its receiver, which must
+    /// allow that as an assignment to one of them would.
+    fn method_call(&mut self, method: &Name, args: &[Expr], span: Span) {
+        let Some(&def) = self.types.method_calls.get(&span) else {
+            return;
+        };
+        if self.resolution.definition(def).kind != DefKind::Proc {
+            return;
+        }
+        // `Type.name(receiver, ..)` passes the receiver after the type.
+        let receiver = match args {
+            [Expr::Reference(_, span), receiver, ..] if self.names_type(*span) => receiver,
+            [receiver, ..] => receiver,
+            [] => return,
+        };
+        let mut root = receiver;
+        while let Expr::FieldAccess(inner, _, _) = root {
+            root = inner;
+        }
+        let Expr::Reference(name, reference) = root else {
+            return;
+        };
+        let Some(&id) = self.resolution.references.get(reference) else {
+            return;
+        };
+        if self.fields_writable(id) {
+            return;
+        }
+        let definition = self.resolution.definition(id);
+        let mut diagnostic = Diagnostic::error(
+            format!(
+                "cannot call the `proc` `{}` on `{}`, which is a {}",
+                method.0,
+                name.0,
+                definition.kind.describe()
+            ),
+            span,
+        );
+        if let Some(declared) = definition.span {
+            diagnostic = diagnostic.with_note(declared, format!("`{}` is declared here", name.0));
+        }
+        if let Some(help) = field_help(definition.kind) {
+            diagnostic = diagnostic.with_help(help.to_string());
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn names_type(&self, span: Span) -> bool {
+        self.resolution.references.get(&span).is_some_and(|id| {
+            matches!(
+                self.resolution.definition(*id).kind,
+                DefKind::Struct | DefKind::Enum
+            )
+        })
+    }
+
+    /// Whether the fields of the value bound to `id` may be written.
+    fn fields_writable(&self, id: DefId) -> bool {
+        match self.resolution.definition(id).kind {
+            DefKind::Var => true,
+            DefKind::Param => self.proc_params.contains(&id),
+            _ => false,
+        }
+    }
+
+    /// Rejects binding `id`, or a field of it, to a struct that a binding
+    /// of the other kind holds: a `const` to one a `var` or a parameter of
+    /// a `proc` may change, or a `var` to one a `const` holds.
+    fn binding(&mut self, id: DefId, value: &Expr) {
+        let kind = self.resolution.definition(id).kind;
+        let Some((source, shared)) = self.shared(value, kind) else {
+            return;
+        };
+        let target = self.resolution.definition(id);
+        let definition = self.resolution.definition(source);
+        let mut diagnostic = Diagnostic::error(
+            format!(
+                "the {} `{}` cannot share a struct with the {} `{}`",
+                target.kind.describe(),
+                target.name,
+                definition.kind.describe(),
+                definition.name
+            ),
+            shared.span(),
+        );
+        if let Some(declared) = definition.span {
+            diagnostic =
+                diagnostic.with_note(declared, format!("`{}` is declared here", definition.name));
+        }
+        let help = match self.type_of(shared) {
+            Some(Type::Named(name, _)) if self.types.fields.contains_key(&name) => {
+                format!("copy it instead, as in `{} {{ ..{} }}`", name, path(shared))
+            }
+            _ => "copy the structs instead, with `..` in struct literals".to_string(),
+        };
+        self.diagnostics.push(diagnostic.with_help(help));
+    }
+
+    /// The binding whose struct the value of `expr` may be, or hold, when
+    /// it is bound to a `target` binding: one that binding may not share a
+    /// struct with, and the reference or field access it is used through.
+    fn shared<'e>(&self, expr: &'e Expr, target: DefKind) -> Option<(DefId, &'e Expr)> {
+        match expr {
+            Expr::Reference(..) | Expr::FieldAccess(..) => {
+                let mut root = expr;
+                while let Expr::FieldAccess(inner, _, _) = root {
+                    root = inner;
+                }
+                let Expr::Reference(_, reference) = root else {
+                    return None;
+                };
+                let id = *self.resolution.references.get(reference)?;
+                let conflicts = match target {
+                    DefKind::Const => self.fields_writable(id),
+                    DefKind::Var => self.resolution.definition(id).kind == DefKind::Const,
+                    _ => false,
+                };
+                let holds = self.type_of(expr).is_some_and(|ty| self.holds_struct(&ty));
+                (conflicts && holds).then_some((id, expr))
+            }
+            Expr::Grouping(TokenType::Dot, _, _) => None,
+            Expr::Grouping(_, inner, _) => self.shared(inner, target),
+            Expr::Sequence(elements, _) => elements.iter().find_map(|e| self.shared(e, target)),
+            Expr::Literal(literal, _) => match literal {
+                Literal::List(elements) | Literal::Set(elements) | Literal::Tuple(elements) => {
+                    elements.iter().find_map(|e| self.shared(e, target))
+                }
+                // The struct copied from is not shared.
+                Literal::StructInitialization(_, fields, _) => fields
+                    .iter()
+                    .find_map(|field| self.shared(&field.value, target)),
+                Literal::Map(entries) => entries.iter().find_map(|(key, value)| {
+                    self.shared(key, target)
+                        .or_else(|| self.shared(value, target))
+                }),
+                _ => None,
+            },
+            Expr::Match(_, arms, _) => arms.iter().find_map(|arm| self.shared(&arm.body, target)),
+            Expr::Call(..) => None,
+        }
+    }
+
+    /// The type of a reference or a chain of field accesses, as far as the
+    /// schemes of the bindings and the types of struct fields tell.
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        match expr {
+            Expr::Reference(_, span) => {
+                let id = self.resolution.references.get(span)?;
+                Some(self.types.schemes.get(id)?.ty.clone())
+            }
+            Expr::FieldAccess(receiver, field, _) => match self.type_of(receiver)? {
+                Type::Named(name, _) => self
+                    .types
+                    .fields
+                    .get(&name)?
+                    .iter()
+                    .find(|(f, _)| *f == field.field_name)
+                    .map(|(_, ty)| ty.clone()),
+                Type::Tuple(mut items) => {
+                    let index = field.field_name.parse::<usize>().ok()?;
+                    (index < items.len()).then(|| items.swap_remove(index))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether values of type `ty` are, or hold, structs.
+    fn holds_struct(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Named(name, args) => {
+                self.types.fields.contains_key(name)
+                    || args.iter().any(|arg| self.holds_struct(arg))
+            }
+            Type::List(t) | Type::Set(t) => self.holds_struct(t),
+            Type::Map(k, v) => self.holds_struct(k) || self.holds_struct(v),
+            Type::Tuple(items) => items.iter().any(|item| self.holds_struct(item)),
+            Type::Alias(_, _, target) => self.holds_struct(target),
+            Type::Var(_) | Type::Function(..) => false,
+        }
+    }
+
+    fn assignment(&mut self, assignment: &Assignment) {
+        let Some((name, span)) = assignment.binding() else {
+            return;
+        };
+        let Some(&id) = self.resolution.references.get(&span) else {
+            return;
+        };
+        let definition = self.resolution.definition(id);
+        let through_field = !matches!(assignment.target, Expr::Reference(..));
+        let allowed = match definition.kind {
+            DefKind::Var => true,
+            _ => through_field && self.fields_writable(id),
+        };
+        if allowed {
+            return;
+        }
+
+        let kind = definition.kind.describe();
+        let (message, help) = if through_field {
+            let help = field_help(definition.kind);
+            (
+                format!(
+                    "cannot assign to a field of `{}`, which is a {}",
+                    name.0, kind
+                ),
+                help,
+            )
+        } else {
+            let help = match definition.kind {
+                DefKind::Const => Some("declare it with `var` to allow assigning to it"),
+                DefKind::Param | DefKind::ForBinding | DefKind::MatchBinding => {
+                    Some("copy it into a `var` first")
+                }
+                _ => None,
+            };
+            (
+                format!("cannot assign to `{}`, which is a {}", name.0, kind),
+                help,
+            )
+        };
+        let mut diagnostic = Diagnostic::error(message, assignment.span);
+        if let Some(declared) = definition.span {
+            diagnostic = diagnostic.with_note(declared, format!("`{}` is declared here", name.0));
+        }
+        if let Some(help) = help {
+            diagnostic = diagnostic.with_help(help.to_string());
+        }
+        self.diagnostics.push(diagnostic);
+    }
+}
+
+/// How to make the fields of a binding of this kind writable.
+fn field_help(kind: DefKind) -> Option<&'static str> {
+    match kind {
+        DefKind::Const => Some("declare it with `var` to allow changing its fields"),
+        DefKind::Param => Some("only a `proc` may change the fields of its parameters"),
+        _ => None,
+    }
+}
+
+/// How a reference or a chain of field accesses is written, as in `b.p`.
+fn path(expr: &Expr) -> String {
+    match expr {
+        Expr::Reference(name, _) => name.0.clone(),
+        Expr::FieldAccess(receiver, field, _) => format!("{}.{}", path(receiver), field.field_name),
+        _ => String::new(),
+    }
+}
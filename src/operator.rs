@@ -3,15 +3,26 @@ use crate::tokens::TokenType;
 pub struct TypeOperator;
 impl BP for TypeOperator {
     fn prefix_bp(token_type: &TokenType) -> Option<u8> {
-        todo!()
+        match token_type {
+            TokenType::LParen => Some(9),
+            TokenType::LBracket => Some(9),
+            TokenType::LBrace => Some(9),
+            _ => None,
+        }
     }
 
     fn infix_bp(token_type: &TokenType) -> Option<(u8, u8)> {
-        todo!()
+        match token_type {
+            TokenType::Arrow => Some((2, 1)),
+            _ => None,
+        }
     }
 
     fn postfix_bp(token_type: &TokenType) -> Option<u8> {
-        todo!()
+        match token_type {
+            TokenType::LT => Some(7),
+            _ => None,
+        }
     }
 }
 
@@ -19,55 +30,94 @@ pub struct ExprOperator;
 impl BP for ExprOperator {
     fn prefix_bp(token_type: &TokenType) -> Option<u8> {
         match token_type {
-            TokenType::LBrace => Some(18),
-            TokenType::LBracket => Some(18),
-            TokenType::LParen => Some(18),
-            TokenType::Bang => Some(15),
-            TokenType::Negate => Some(15),
+            TokenType::LBrace => Some(21),
+            TokenType::LBracket => Some(21),
+            TokenType::LParen => Some(21),
+            TokenType::Bang => Some(17),
+            TokenType::Negate => Some(17),
+            TokenType::KwImpure => Some(17),
+            TokenType::KwTail => Some(17),
             _ => None,
         }
     }
 
     fn infix_bp(token_type: &TokenType) -> Option<(u8, u8)> {
         match token_type {
-            TokenType::Dot => Some((17, 16)),
-            TokenType::Percent => Some((13, 14)),
-            TokenType::Slash => Some((13, 14)),
-            TokenType::Star => Some((13, 14)),
-            TokenType::Plus => Some((11, 12)),
-            TokenType::Minus => Some((11, 12)),
-            TokenType::KwAnd => Some((9, 10)),
-            TokenType::KwOr => Some((9, 10)),
-            TokenType::EqualsEq => Some((7, 8)),
-            TokenType::BangEq => Some((7, 8)),
-            TokenType::KwIf => Some((6, 5)),
-            TokenType::KwFor => Some((5, 6)),
-            TokenType::Arrow => Some((4, 3)),
+            TokenType::Percent => Some((15, 16)),
+            TokenType::Slash => Some((15, 16)),
+            TokenType::Star => Some((15, 16)),
+            TokenType::Plus => Some((13, 14)),
+            TokenType::Minus => Some((13, 14)),
+            TokenType::LT => Some((11, 12)),
+            TokenType::LTEq => Some((11, 12)),
+            TokenType::GT => Some((11, 12)),
+            TokenType::GTEq => Some((11, 12)),
+            TokenType::EqualsEq => Some((9, 10)),
+            TokenType::BangEq => Some((9, 10)),
+            TokenType::KwAnd => Some((7, 8)),
+            TokenType::KwOr => Some((5, 6)),
             _ => None,
         }
     }
 
     fn postfix_bp(token_type: &TokenType) -> Option<u8> {
         match token_type {
-            TokenType::RBrace | TokenType::RBracket | TokenType::RParen => Some(17),
+            TokenType::LParen | TokenType::Dot => Some(19),
             TokenType::Comma => Some(1),
             _ => None,
         }
     }
 }
 
-pub struct LineOperator;
-impl BP for LineOperator {
-    fn prefix_bp(token_type: &TokenType) -> Option<u8> {
-        todo!();
+/// Name of the call a binary operator token is parsed into.
+pub fn infix_name(token_type: &TokenType) -> Option<&'static str> {
+    match token_type {
+        TokenType::Percent => Some("%"),
+        TokenType::Slash => Some("/"),
+        TokenType::Star => Some("*"),
+        TokenType::Plus => Some("+"),
+        TokenType::Minus => Some("-"),
+        TokenType::LT => Some("<"),
+        TokenType::LTEq => Some("<="),
+        TokenType::GT => Some(">"),
+        TokenType::GTEq => Some(">="),
+        TokenType::EqualsEq => Some("=="),
+        TokenType::BangEq => Some("!="),
+        TokenType::KwAnd => Some("and"),
+        TokenType::KwOr => Some("or"),
+        _ => None,
     }
+}
 
-    fn infix_bp(token_type: &TokenType) -> Option<(u8, u8)> {
-        todo!();
+/// Name of the binary operator a compound assignment token applies.
+pub fn compound_name(token_type: &TokenType) -> Option<&'static str> {
+    match token_type {
+        TokenType::PlusEq => Some("+"),
+        TokenType::MinusEq => Some("-"),
+        TokenType::StarEq => Some("*"),
+        TokenType::SlashEq => Some("/"),
+        TokenType::PercentEq => Some("%"),
+        _ => None,
     }
+}
 
-    fn postfix_bp(token_type: &TokenType) -> Option<u8> {
-        todo!();
+/// Inverse of `infix_name`, used when printing calls back as operators.
+pub fn infix_token(name: &str) -> Option<TokenType> {
+    match name {
+        "%" => Some(TokenType::Percent),
+        "/" => Some(TokenType::Slash),
+        "*" => Some(TokenType::Star),
+        "+" => Some(TokenType::Plus),
+        "-" => Some(TokenType::Minus),
+        "<" => Some(TokenType::LT),
+        "<=" => Some(TokenType::LTEq),
+        ">" => Some(TokenType::GT),
+        ">=" => Some(TokenType::GTEq),
+        "==" => Some(TokenType::EqualsEq),
+        "!=" => Some(TokenType::BangEq),
+        "and" => Some(TokenType::KwAnd),
+        "or" => Some(TokenType::KwOr),
+        _ => None,
     }
 }
 
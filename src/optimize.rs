@@ -0,0 +1,719 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::bytecode::{Constant, Op, Pattern, Program};
+use crate::ir::{self, BlockId, Constants, Inst, Phi, Terminator, Type, Value};
+use crate::ir_build;
+use crate::ir_lower;
+use crate::vm;
+
+/// The most instructions a function may have to be inlined at `-O2`.
+const INLINE_LIMIT: usize = 16;
+
+/// How often the passes of a level run before giving up on reaching a
+/// fixed point.
+const MAX_ROUNDS: usize = 8;
+
+/// A transformation of one function, which says whether it changed
+/// anything.
+struct Pass {
+    name: &'static str,
+    run: fn(&mut ir::Function, &mut Constants) -> bool,
+}
+
+/// What `-O1` runs on every function until nothing changes.
+const PASSES: &[Pass] = &[
+    Pass {
+        name: "simplify-cfg",
+        run: simplify_cfg,
+    },
+    Pass {
+        name: "fold-constants",
+        run: fold_constants,
+    },
+    Pass {
+        name: "propagate-copies",
+        run: propagate_copies,
+    },
+    Pass {
+        name: "eliminate-dead-code",
+        run: eliminate_dead_code,
+    },
+];
+
+/// Optimizes every function of a program at an optimization level: `-O1`
+/// folds constants, `const` globals included, and cleans up after itself,
+/// and `-O2` also inlines small functions. Level 0 leaves the program as
+/// the compiler emitted it.
+pub fn optimize(program: &mut Program, level: u8) {
+    if level == 0 {
+        return;
+    }
+    let (functions, constants) = pipeline(program, level);
+    for (index, function) in functions.into_iter().enumerate() {
+        if let Some(function) = function {
+            let original = &mut program.functions[index];
+            *original = ir_lower::lower(&function, original);
+        }
+    }
+    program.constants = constants.values;
+}
+
+/// The IR of every function after the passes of a level, as text.
+pub fn emit(mut program: Program, level: u8) -> String {
+    let (functions, constants) = pipeline(&program, level);
+    program.constants = constants.values;
+    let mut out = String::new();
+    for (index, function) in functions.iter().enumerate() {
+        match function {
+            Some(function) => function.print(index, &program, &mut out),
+            None => {
+                let name = &program.functions[index].name;
+                out.push_str(&format!(
+                    "\nfn #{} {}: boxes an argument, kept as bytecode\n",
+                    index, name
+                ));
+            }
+        }
+    }
+    out
+}
+
+fn pipeline(program: &Program, level: u8) -> (Vec<Option<ir::Function>>, Constants) {
+    let mut constants = Constants::new(program.constants.clone());
+    let mut functions: Vec<Option<ir::Function>> = (0..program.functions.len())
+        .map(|index| ir_build::build(program, index, &mut constants))
+        .collect();
+    for function in functions.iter_mut().flatten() {
+        check(function, "ir-build");
+    }
+    if level == 0 {
+        return (functions, constants);
+    }
+    run(&mut functions, &mut constants);
+    if fold_globals(program, &mut functions) {
+        for function in functions.iter_mut().flatten() {
+            check(function, "fold-globals");
+        }
+        run(&mut functions, &mut constants);
+    }
+    if level >= 2 && inline(program, &mut functions) {
+        for function in functions.iter_mut().flatten() {
+            check(function, "inline");
+        }
+        run(&mut functions, &mut constants);
+    }
+    (functions, constants)
+}
+
+/// Runs the passes on every function until they reach a fixed point.
+fn run(functions: &mut [Option<ir::Function>], constants: &mut Constants) {
+    for function in functions.iter_mut().flatten() {
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for pass in PASSES {
+                if (pass.run)(function, constants) {
+                    changed = true;
+                    check(function, pass.name);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        function.compact();
+        function.retype(constants);
+    }
+}
+
+/// Verifies a function after a pass in debug builds, so that a broken pass
+/// is caught where it went wrong rather than in the code it produces.
+fn check(function: &ir::Function, pass: &str) {
+    if cfg!(debug_assertions) {
+        if let Err(error) = function.verify() {
+            panic!("{} left invalid IR in `{}`: {}", pass, function.name, error);
+        }
+    }
+}
+
+/// Removes unreachable blocks, threads jumps through empty blocks and
+/// merges blocks with the single predecessor jumping to them.
+fn simplify_cfg(function: &mut ir::Function, _: &mut Constants) -> bool {
+    let mut changed = false;
+    let before = function.blocks.len();
+    function.compact();
+    changed |= function.blocks.len() != before;
+
+    // Jumps through a block that does nothing go straight to its target.
+    let ids: Vec<BlockId> = function.ids().skip(1).collect();
+    for id in ids {
+        let block = function.block(id);
+        let Terminator::Jump(target) = block.terminator else {
+            continue;
+        };
+        if target == id || !block.phis.is_empty() || !block.insts.is_empty() {
+            continue;
+        }
+        let predecessors = function.predecessors();
+        let into_target = &predecessors[target.0 as usize];
+        let has_phis = !function.block(target).phis.is_empty();
+        for &predecessor in &predecessors[id.0 as usize] {
+            let terminator = &function.block(predecessor).terminator;
+            let defining = terminator.defines().is_some_and(|(to, _)| to == id);
+            let alone = predecessors[id.0 as usize].len() == 1 && into_target.len() == 1;
+            if terminator.successors().contains(&target) || (defining && (has_phis || !alone)) {
+                continue;
+            }
+            let phis = &mut function.block_mut(target).phis;
+            for phi in phis.iter_mut() {
+                let value = phi
+                    .incoming
+                    .iter()
+                    .find(|(from, _)| *from == id)
+                    .map(|(_, value)| *value)
+                    .expect("a phi has a value per predecessor");
+                phi.incoming.push((predecessor, value));
+            }
+            function
+                .block_mut(predecessor)
+                .terminator
+                .retarget(id, target);
+            changed = true;
+        }
+    }
+
+    // A block whose only predecessor jumps to it is part of that
+    // predecessor.
+    let mut merged = true;
+    while merged {
+        merged = false;
+        let predecessors = function.predecessors();
+        let ids: Vec<BlockId> = function.ids().collect();
+        for id in ids {
+            let Terminator::Jump(target) = function.block(id).terminator else {
+                continue;
+            };
+            if target == id || target.0 == 0 || predecessors[target.0 as usize] != [id] {
+                continue;
+            }
+            let span = function.block(id).span;
+            let next = std::mem::replace(
+                function.block_mut(target),
+                ir::Block {
+                    phis: Vec::new(),
+                    insts: Vec::new(),
+                    terminator: Terminator::Fail(0),
+                    span,
+                },
+            );
+            let copies: HashMap<Value, Value> = next
+                .phis
+                .iter()
+                .map(|phi| (phi.result, phi.incoming[0].1))
+                .collect();
+            for successor in next.terminator.successors() {
+                for phi in &mut function.block_mut(successor).phis {
+                    for (from, _) in &mut phi.incoming {
+                        if *from == target {
+                            *from = id;
+                        }
+                    }
+                }
+            }
+            let block = function.block_mut(id);
+            block.insts.extend(next.insts);
+            block.terminator = next.terminator;
+            block.span = next.span;
+            // The emptied block is unreachable: it keeps a terminator that
+            // jumps nowhere until the next compaction drops it.
+            function.substitute(&copies);
+            merged = true;
+            changed = true;
+            break;
+        }
+    }
+    function.compact();
+    changed
+}
+
+/// The constant each value is known to be, from the instructions loading
+/// constants.
+fn known(function: &ir::Function) -> HashMap<Value, u32> {
+    let mut known = HashMap::new();
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let (Op::Constant(index), [result]) = (inst.op, inst.results.as_slice()) {
+                known.insert(*result, index);
+            }
+        }
+    }
+    known
+}
+
+/// Evaluates operators whose operands are constants, and takes the only
+/// branch a constant condition or pattern can take.
+fn fold_constants(function: &mut ir::Function, constants: &mut Constants) -> bool {
+    let mut changed = false;
+    let mut known = known(function);
+    for block in &mut function.blocks {
+        for inst in &mut block.insts {
+            let operator = matches!(
+                inst.op,
+                Op::Add
+                    | Op::Subtract
+                    | Op::Multiply
+                    | Op::Divide
+                    | Op::Remainder
+                    | Op::Negate
+                    | Op::Not
+                    | Op::Equal
+                    | Op::NotEqual
+                    | Op::Less
+                    | Op::LessEqual
+                    | Op::Greater
+                    | Op::GreaterEqual
+            );
+            if !operator {
+                continue;
+            }
+            let Some(operands) = inst
+                .args
+                .iter()
+                .map(|arg| known.get(arg).map(|&i| &constants.values[i as usize]))
+                .collect::<Option<Vec<&Constant>>>()
+            else {
+                continue;
+            };
+            // Operations that stop the program are left for it to run.
+            if let Some(result) = vm::evaluate(inst.op, &operands) {
+                let index = constants.intern(result);
+                inst.op = Op::Constant(index);
+                inst.args.clear();
+                known.insert(inst.results[0], index);
+                changed = true;
+            }
+        }
+    }
+
+    let mut copies = HashMap::new();
+    for id in function.ids() {
+        let block = function.block(id);
+        let (taken, skipped) = match &block.terminator {
+            Terminator::Branch(condition, then, otherwise) => {
+                match known.get(condition).map(|&i| &constants.values[i as usize]) {
+                    Some(Constant::Bool(true)) => (*then, *otherwise),
+                    Some(Constant::Bool(false)) => (*otherwise, *then),
+                    _ => continue,
+                }
+            }
+            Terminator::Match {
+                value,
+                pattern,
+                binds,
+                matched,
+                failed,
+            } => {
+                let matches = match &function.patterns[*pattern as usize] {
+                    Pattern::Wildcard => true,
+                    Pattern::Bind(_) => {
+                        copies.insert(binds[0], *value);
+                        true
+                    }
+                    Pattern::Constant(expected) => match known.get(value) {
+                        Some(&actual) => {
+                            let operands = [
+                                &constants.values[*expected as usize],
+                                &constants.values[actual as usize],
+                            ];
+                            matches!(
+                                vm::evaluate(Op::Equal, &operands),
+                                Some(Constant::Bool(true))
+                            )
+                        }
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                if matches {
+                    (*matched, *failed)
+                } else {
+                    (*failed, *matched)
+                }
+            }
+            _ => continue,
+        };
+        function.block_mut(id).terminator = Terminator::Jump(taken);
+        if taken != skipped {
+            for phi in &mut function.block_mut(skipped).phis {
+                phi.incoming.retain(|(from, _)| *from != id);
+            }
+        }
+        changed = true;
+    }
+    function.substitute(&copies);
+    changed
+}
+
+/// Replaces phis that merge a single value with that value.
+fn propagate_copies(function: &mut ir::Function, _: &mut Constants) -> bool {
+    let mut copies = HashMap::new();
+    loop {
+        let mut found = false;
+        for block in &mut function.blocks {
+            block.phis.retain(|phi| {
+                let mut values = phi
+                    .incoming
+                    .iter()
+                    .map(|(_, value)| *value)
+                    .filter(|&value| value != phi.result);
+                let Some(first) = values.next() else {
+                    return true;
+                };
+                if values.all(|value| value == first) {
+                    copies.insert(phi.result, first);
+                    found = true;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        if !found {
+            break;
+        }
+        function.substitute(&copies);
+    }
+    !copies.is_empty()
+}
+
+/// Removes instructions and phis whose values nothing needs, unless
+/// running them could have an effect or stop the program.
+fn eliminate_dead_code(function: &mut ir::Function, constants: &mut Constants) -> bool {
+    function.retype(constants);
+    let mut definitions: HashMap<Value, Vec<Value>> = HashMap::new();
+    let mut live: HashSet<Value> = HashSet::new();
+    let mut work: Vec<Value> = Vec::new();
+    for block in &function.blocks {
+        for phi in &block.phis {
+            let inputs = phi.incoming.iter().map(|(_, v)| *v).collect();
+            definitions.insert(phi.result, inputs);
+        }
+        for inst in &block.insts {
+            if removable(function, inst) {
+                for &result in &inst.results {
+                    definitions.insert(result, inst.args.clone());
+                }
+            } else {
+                work.extend(&inst.args);
+            }
+        }
+        work.extend(block.terminator.uses());
+    }
+    while let Some(value) = work.pop() {
+        if live.insert(value) {
+            if let Some(inputs) = definitions.get(&value) {
+                work.extend(inputs);
+            }
+        }
+    }
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let (phis, insts) = (block.phis.len(), block.insts.len());
+        block.phis.retain(|phi| live.contains(&phi.result));
+        block.insts.retain(|inst| match inst.results.first() {
+            Some(result) if definitions.contains_key(result) => {
+                inst.results.iter().any(|r| live.contains(r))
+            }
+            _ => true,
+        });
+        changed |= phis != block.phis.len() || insts != block.insts.len();
+    }
+    changed
+}
+
+/// Whether an instruction can be dropped when nothing uses what it pushes:
+/// it has no effect and cannot stop the program with the operands it gets.
+fn removable(function: &ir::Function, inst: &Inst) -> bool {
+    if inst.results.is_empty() {
+        return false;
+    }
+    let types: Vec<Type> = inst.args.iter().map(|&a| function.type_of(a)).collect();
+    match inst.op {
+        Op::Constant(_)
+        | Op::Function(_)
+        | Op::Closure(_)
+        | Op::Builtin(_)
+        | Op::Type(_)
+        | Op::Variant(..)
+        | Op::List(_)
+        | Op::Set(_)
+        | Op::Map(_)
+        | Op::Tuple(_)
+        | Op::Struct(..)
+        | Op::Equal
+        | Op::NotEqual
+        | Op::Cell(_)
+        | Op::Capture(_)
+        | Op::Unpack(_) => true,
+        Op::Not => types == [Type::Bool],
+        Op::Negate => types == [Type::Float],
+        // Floats never overflow, so only integers on both sides can fail.
+        Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder => matches!(
+            types.as_slice(),
+            [Type::Float, Type::Int | Type::Float] | [Type::Int, Type::Float]
+        ),
+        Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => {
+            matches!(
+                types.as_slice(),
+                [Type::Int, Type::Int] | [Type::String, Type::String]
+            )
+        }
+        _ => false,
+    }
+}
+
+/// Replaces reads of globals that are never assigned and whose initializer
+/// just returns a constant with that constant.
+fn fold_globals(program: &Program, functions: &mut [Option<ir::Function>]) -> bool {
+    let assigned: HashSet<u32> = program
+        .functions
+        .iter()
+        .flat_map(|f| &f.code)
+        .filter_map(|op| match op {
+            Op::SetGlobal(index) => Some(*index),
+            _ => None,
+        })
+        .collect();
+    let mut values = HashMap::new();
+    for (index, global) in program.globals.iter().enumerate() {
+        let index = index as u32;
+        if assigned.contains(&index) {
+            continue;
+        }
+        let Some(init) = &functions[global.init as usize] else {
+            continue;
+        };
+        if let Some(constant) = returned_constant(init) {
+            values.insert(index, constant);
+        }
+    }
+    let mut changed = false;
+    for function in functions.iter_mut().flatten() {
+        for block in &mut function.blocks {
+            for inst in &mut block.insts {
+                if let Op::Global(index) = inst.op {
+                    if let Some(&constant) = values.get(&index) {
+                        inst.op = Op::Constant(constant);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    changed
+}
+
+/// The constant a function returns when all it does is load and return it.
+fn returned_constant(function: &ir::Function) -> Option<u32> {
+    let [block] = function.blocks.as_slice() else {
+        return None;
+    };
+    let Terminator::Return(value) = block.terminator else {
+        return None;
+    };
+    let mut result = None;
+    for inst in &block.insts {
+        match (inst.op, inst.results.as_slice()) {
+            (Op::Constant(index), [defined]) => {
+                if *defined == value {
+                    result = Some(index);
+                }
+            }
+            _ => return None,
+        }
+    }
+    result
+}
+
+/// Inlines calls of small functions that neither recurse, nor capture,
+/// nor can reject their arguments, nor cache their results.
+fn inline(program: &Program, functions: &mut [Option<ir::Function>]) -> bool {
+    let candidates: HashMap<u32, ir::Function> = functions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, function)| {
+            let function = function.as_ref()?;
+            let size: usize = function
+                .blocks
+                .iter()
+                .map(|b| b.insts.len() + b.phis.len() + 1)
+                .sum();
+            let calls_itself = function.blocks.iter().flat_map(|b| &b.insts).any(
+                |inst| matches!(inst.op, Op::CallFunction(callee, _) if callee as usize == index),
+            );
+            let simple = function.blocks.iter().all(|b| {
+                !matches!(b.terminator, Terminator::NoClause(_) | Terminator::NoArm(_))
+                    && b.insts
+                        .iter()
+                        .all(|i| !matches!(i.op, Op::Capture(_) | Op::SetCapture(_)))
+            });
+            let inlinable = size <= INLINE_LIMIT
+                && !calls_itself
+                && simple
+                && function.cells.is_empty()
+                && program.functions[index].captures.is_empty()
+                && program.functions[index].memo.is_none();
+            inlinable.then(|| (index as u32, function.clone()))
+        })
+        .collect();
+    let mut changed = false;
+    for (index, function) in functions.iter_mut().enumerate() {
+        let Some(function) = function else {
+            continue;
+        };
+        // Calls in the inlined bodies stay calls, so that functions calling
+        // each other are not inlined forever.
+        let mut inlined = HashSet::new();
+        let mut id = 0;
+        while id < function.blocks.len() {
+            if inlined.contains(&id) {
+                id += 1;
+                continue;
+            }
+            let position = function.blocks[id].insts.iter().position(|inst| {
+                matches!(inst.op, Op::CallFunction(callee, count)
+                    if callee as usize != index
+                        && candidates.get(&callee).is_some_and(|c| c.arity == count))
+            });
+            match position {
+                Some(position) => {
+                    let Op::CallFunction(callee, _) = function.blocks[id].insts[position].op else {
+                        unreachable!("found above")
+                    };
+                    let callee = &candidates[&callee];
+                    let base = function.blocks.len() + 1;
+                    inlined.extend(base..base + callee.blocks.len());
+                    splice(function, BlockId(id as u32), position, callee);
+                    changed = true;
+                }
+                None => id += 1,
+            }
+        }
+    }
+    changed
+}
+
+/// Replaces the call at `position` in a block with the body of `callee`.
+fn splice(function: &mut ir::Function, id: BlockId, position: usize, callee: &ir::Function) {
+    let block = function.block_mut(id);
+    let rest = block.insts.split_off(position + 1);
+    let call = block.insts.pop().expect("the call is in the block");
+    let continuation = BlockId(function.blocks.len() as u32);
+    let base = continuation.0 + 1;
+    let terminator = std::mem::replace(
+        &mut function.block_mut(id).terminator,
+        Terminator::Jump(BlockId(base)),
+    );
+    for successor in terminator.successors() {
+        for phi in &mut function.block_mut(successor).phis {
+            for (from, _) in &mut phi.incoming {
+                if *from == id {
+                    *from = continuation;
+                }
+            }
+        }
+    }
+    let span = function.block(id).span;
+    function.blocks.push(ir::Block {
+        phis: Vec::new(),
+        insts: rest,
+        terminator,
+        span,
+    });
+
+    let mut values: HashMap<Value, Value> = callee.params.iter().copied().zip(call.args).collect();
+    let mut value = |function: &mut ir::Function, v: Value| {
+        *values.entry(v).or_insert_with(|| function.new_value())
+    };
+    let block = |b: BlockId| BlockId(base + b.0);
+    let patterns = function.patterns.len() as u32;
+    function.patterns.extend(callee.patterns.iter().cloned());
+    let mut returns = Vec::new();
+    for (index, body) in callee.blocks.iter().enumerate() {
+        let phis = body
+            .phis
+            .iter()
+            .map(|phi| Phi {
+                result: value(function, phi.result),
+                incoming: phi
+                    .incoming
+                    .iter()
+                    .map(|&(from, v)| (block(from), value(function, v)))
+                    .collect(),
+            })
+            .collect();
+        let insts = body
+            .insts
+            .iter()
+            .map(|inst| Inst {
+                op: inst.op,
+                args: inst.args.iter().map(|&v| value(function, v)).collect(),
+                results: inst.results.iter().map(|&v| value(function, v)).collect(),
+                span: inst.span,
+            })
+            .collect();
+        let terminator = match &body.terminator {
+            Terminator::Jump(target) => Terminator::Jump(block(*target)),
+            Terminator::Branch(condition, then, otherwise) => {
+                Terminator::Branch(value(function, *condition), block(*then), block(*otherwise))
+            }
+            Terminator::Next {
+                iterator,
+                item,
+                more,
+                done,
+            } => Terminator::Next {
+                iterator: value(function, *iterator),
+                item: value(function, *item),
+                more: block(*more),
+                done: block(*done),
+            },
+            Terminator::Match {
+                value: scrutinee,
+                pattern,
+                binds,
+                matched,
+                failed,
+            } => Terminator::Match {
+                value: value(function, *scrutinee),
+                pattern: pattern + patterns,
+                binds: binds.iter().map(|&v| value(function, v)).collect(),
+                matched: block(*matched),
+                failed: block(*failed),
+            },
+            Terminator::Return(result) => {
+                returns.push((BlockId(base + index as u32), value(function, *result)));
+                Terminator::Jump(continuation)
+            }
+            Terminator::Fail(message) => Terminator::Fail(*message),
+            Terminator::NoClause(_) | Terminator::NoArm(_) => {
+                unreachable!("functions that can reject their arguments are not inlined")
+            }
+        };
+        function.blocks.push(ir::Block {
+            phis,
+            insts,
+            terminator,
+            span: body.span,
+        });
+    }
+    let result = call.results[0];
+    match returns.as_slice() {
+        [(_, returned)] => {
+            function.substitute(&HashMap::from([(result, *returned)]));
+        }
+        _ => function.block_mut(continuation).phis.push(Phi {
+            result,
+            incoming: returns,
+        }),
+    }
+}
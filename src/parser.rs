@@ -1,50 +1,48 @@
-use std::slice::Iter;
 use std::collections::VecDeque;
 
 use crate::abstract_syntax_tree::{
-    Conditional, Domain, Enum, EnumEntry, Expr, ForStatement, Initialization, Line, Literal, Name,
-    Statement, Struct, Tag, TypeAnnotation, TypeExpr, Typeclass, Value,
+    Alias, Arm, Assignment, Body, Conditional, Domain, Enum, EnumEntry, Expr, Field, FieldInit,
+    ForStatement, Function, Initialization, Line, Literal, Memo, Module, Name, Param, Pattern,
+    Statement, Struct, Tag, TypeAnnotation, TypeExpr, TypeParam, Typeclass, Value,
 };
-use crate::operator::{ExprOperator, BP};
-use crate::tokens::{Position, Token, TokenType};
+use crate::operator::{compound_name, infix_name, ExprOperator, TypeOperator, BP};
+use crate::tokens::{Position, Span, Token, TokenType};
 
 #[derive(Debug)]
 pub struct Parser {
     token_stream: VecDeque<Token>,
-    error_stream: VecDeque<Token>,
+    error_stream: Vec<ParseError>,
     expected_domain: Option<Domain>,
-    indent: Position,
+    /// Whether the guard of a `match` arm is being parsed, where `(..) ->`
+    /// ends the guard rather than starting a closure.
+    in_guard: bool,
+    /// Whether the head of an `if`, `while`, `for` or `match` is being
+    /// parsed, where `name {` starts its block rather than a struct literal.
+    in_condition: bool,
+    last_end: Position,
 }
 
 impl Parser {
     fn next(&mut self) -> Option<Token> {
-        return match self
-            .indent
-            .includes(self.token_stream.front().expect("Early End of file"))
-        {
-            Ok(_) => self.token_stream.pop_front(),
-            Err(_) => {
-                self.expected_domain = None;
-                Some(Token::new(TokenType::Newline, self.indent.0, 0))
-            }
+        let token = match self.token_stream.front() {
+            Some(t) if t.token_type == TokenType::EOF => Some(t.clone()),
+            _ => self.token_stream.pop_front(),
         };
+        if let Some(t) = &token {
+            self.last_end = t.end;
+        }
+        token
     }
 
-    fn peek(&mut self) -> Token {
-        return match self
-            .indent
-            .includes(self.token_stream.front().expect("Early End of file"))
-        {
-            Ok(_) => self
-                .token_stream
-                .front()
-                .expect("Early end of File")
-                .clone(),
-            Err(_) => {
-                self.expected_domain = None;
-                Token::new(TokenType::Newline, self.indent.0, 0)
-            }
-        };
+    fn peek(&self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        self.token_stream
+            .get(n)
+            .or(self.token_stream.back())
+            .expect("Early end of File")
     }
 
     fn yank(&mut self) -> Token {
@@ -54,58 +52,110 @@ impl Parser {
     pub fn new(token_stream: VecDeque<Token>) -> Self {
         Parser {
             token_stream,
-            error_stream: VecDeque::new(),
+            error_stream: Vec::new(),
             expected_domain: None,
-            indent: Position(0, 0),
+            in_guard: false,
+            in_condition: false,
+            last_end: Position(1, 1),
         }
     }
 
-    fn skip(&mut self, n: u8) {
-        for _ in 0..n {
+    fn skip_newlines(&mut self) {
+        while self.peek().token_type == TokenType::Newline {
             self.next();
         }
     }
 
+    fn expect(&mut self, expected: TokenType) -> Result<Token, ParseError> {
+        let tok = self.yank();
+        if tok.token_type == expected {
+            Ok(tok)
+        } else {
+            Err(ParseError::new(
+                format!("Expected '{}', found '{}'", expected, tok.token_type),
+                tok.position,
+            ))
+        }
+    }
+
+    /// Whether the `(` just taken closes with a `)` followed by `->`, which
+    /// makes it the parameters of a closure.
+    fn closure_ahead(&self) -> bool {
+        if self.in_guard {
+            return false;
+        }
+        let mut depth = 1;
+        for (index, token) in self.token_stream.iter().enumerate() {
+            match token.token_type {
+                TokenType::LParen => depth += 1,
+                TokenType::RParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.peek_nth(index + 1).token_type == TokenType::Arrow;
+                    }
+                }
+                TokenType::EOF => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn span_from(&self, start: Position) -> Span {
+        Span::new(start, self.last_end.max(start))
+    }
+
     fn parse_list<T: Parse>(
         &mut self,
         separator: TokenType,
         left: TokenType,
         right: TokenType,
     ) -> Result<Vec<T>, ParseError> {
-        let first = self.yank();
-        if left != first.token_type && left != TokenType::KwFor {
-            return Err(ParseError::new(
-                format!(
-                    "Unexpected first Grouping '{:?}', expected {:?}",
-                    &first, &left
-                ),
-                first.position
-            ));
-        }
+        let in_guard = std::mem::replace(&mut self.in_guard, false);
+        let in_condition = std::mem::replace(&mut self.in_condition, false);
+        let list = self.parse_items(separator, left, right);
+        self.in_guard = in_guard;
+        self.in_condition = in_condition;
+        list
+    }
+
+    fn parse_condition(&mut self) -> Result<Expr, ParseError> {
+        let in_condition = std::mem::replace(&mut self.in_condition, true);
+        let condition = Expr::parse(self);
+        self.in_condition = in_condition;
+        condition
+    }
+
+    fn parse_items<T: Parse>(
+        &mut self,
+        separator: TokenType,
+        left: TokenType,
+        right: TokenType,
+    ) -> Result<Vec<T>, ParseError> {
+        self.expect(left)?;
 
         let mut list = Vec::new();
         loop {
-            let element = self.yank();
-            if element.token_type == right {
+            self.skip_newlines();
+            if self.peek().token_type == right {
+                self.next();
                 return Ok(list);
             }
             list.push(T::parse(self)?);
-            if separator == TokenType::Newline {
-                self.indent = Position(self.indent.0 + 1, 0);
+            if separator != TokenType::Newline {
+                self.skip_newlines();
             }
-            let next = self.yank();
-            match next.token_type {
-                separator => {}
-                right => return Ok(list),
-                _ => {
-                    return Err(ParseError::new(
-                        format!(
-                            "Unexpected token '{:?}', expected '{:?}' | '{:?}'",
-                            &first, &separator, &right
-                        ),
-                        element.position
-                    ))
-                }
+            let next = self.peek().clone();
+            if next.token_type == separator {
+                self.next();
+            } else if next.token_type != right {
+                return Err(ParseError::new(
+                    format!(
+                        "Unexpected token '{}', expected '{}' or '{}'",
+                        next.token_type, separator, right
+                    ),
+                    next.position,
+                ));
             }
         }
     }
@@ -115,25 +165,49 @@ impl Parser {
         separator: TokenType,
     ) -> Result<(T, Option<U>), ParseError> {
         let first = T::parse(self)?;
-        let next = self.yank();
-        if next.token_type == separator {
-            return Ok((first, Some(U::parse(self)?)));
+        if self.peek().token_type == separator {
+            self.next();
+            Ok((first, Some(U::parse(self)?)))
         } else {
-            return Ok((first, None));
+            Ok((first, None))
         }
     }
 
-    fn ast_build(&mut self) -> Vec<Statement> {
-        todo!()
-    }
-}
+    /// Parses every top-level item, skipping to the next item after an error.
+    pub fn ast_build(&mut self) -> Result<Module, Vec<ParseError>> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek().token_type == TokenType::EOF {
+                break;
+            }
+            match Initialization::parse(self) {
+                Ok(item) => items.push(item),
+                Err(e) => {
+                    self.error_stream.push(e);
+                    self.recover();
+                }
+            }
+        }
 
-fn yank(next_token: Option<&Token>) -> &Token {
-    next_token.expect("Early end of file")
-}
+        if self.error_stream.is_empty() {
+            Ok(Module { items })
+        } else {
+            Err(std::mem::take(&mut self.error_stream))
+        }
+    }
 
-fn yank_owned(next_token: Option<Token>) -> Token {
-    next_token.expect("Early end of file")
+    fn recover(&mut self) {
+        self.next();
+        loop {
+            let tok = self.peek();
+            let item = tok.is_domain() || tok.token_type == TokenType::KwMemo;
+            if tok.token_type == TokenType::EOF || (tok.position.1 == 1 && item) {
+                return;
+            }
+            self.next();
+        }
+    }
 }
 
 pub trait Parse {
@@ -148,6 +222,7 @@ pub trait ParseBP {
         Self: Sized;
 }
 
+#[derive(Debug)]
 pub struct ParseError {
     message: String,
     position: Position,
@@ -159,58 +234,163 @@ impl ParseError {
     }
 }
 
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
 //
 //  Parse implementations for AST types
 //
 
 impl Parse for Line {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        let tok = yank(p.token_stream.front());
+        let tok = p.peek().clone();
 
-        if tok.is_domain() {
+        if tok.is_domain() || tok.token_type == TokenType::KwMemo {
             return Ok(Line::Initialization(Initialization::parse(p)?));
         }
 
-        return match tok.token_type {
+        match tok.token_type {
             TokenType::KwFor => Ok(Line::For(ForStatement::parse(p)?)),
             TokenType::KwIf => Ok(Line::If(Conditional::parse(p)?)),
             TokenType::KwWhile => Ok(Line::While(Conditional::parse(p)?)),
-            TokenType::KwReturn => {p.yank(); Ok(Line::Return(Expr::parse(p)?))},
-            TokenType::KwBreak => {p.yank(); Ok(Line::Break)},
-            TokenType::KwContinue => {p.yank(); Ok(Line::Continue)},
-            _ => Ok(Line::parse(p)?),
-        };
+            TokenType::KwReturn => {
+                p.yank();
+                match p.peek().token_type {
+                    TokenType::Newline | TokenType::RBrace => Ok(Line::Return(Expr::Literal(
+                        Literal::Void,
+                        tok.span(),
+                    ))),
+                    _ => Ok(Line::Return(Expr::parse_bp(p, 0)?)),
+                }
+            }
+            TokenType::KwBreak => {
+                p.yank();
+                Ok(Line::Break(tok.span()))
+            }
+            TokenType::KwContinue => {
+                p.yank();
+                Ok(Line::Continue(tok.span()))
+            }
+            _ => {
+                let expr = Expr::parse(p)?;
+                let operator = p.peek().token_type.clone();
+                if operator == TokenType::Equals || compound_name(&operator).is_some() {
+                    Ok(Line::Assignment(assignment(p, expr)?))
+                } else if is_method_call(&expr) {
+                    Ok(Line::MethodCall(expr))
+                } else {
+                    Ok(Line::Statement(statement(expr)?))
+                }
+            }
+        }
+    }
+}
+
+/// Parses the rest of an assignment whose target has already been read.
+fn assignment(p: &mut Parser, target: Expr) -> Result<Assignment, ParseError> {
+    if !is_assignable(&target) {
+        return Err(ParseError::new(
+            "Only variables and their fields can be assigned to".to_string(),
+            target.span().start,
+        ));
+    }
+    let operator = p.yank().token_type;
+    let value = Expr::parse(p)?;
+    let span = Span::new(target.span().start, value.span().end);
+    Ok(Assignment {
+        target,
+        operator,
+        value,
+        span,
+    })
+}
+
+fn is_assignable(target: &Expr) -> bool {
+    match target {
+        Expr::Reference(..) => true,
+        Expr::FieldAccess(receiver, _, _) => is_assignable(receiver),
+        _ => false,
+    }
+}
+
+fn is_method_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Grouping(TokenType::Dot, call, _) => matches!(call.as_ref(), Expr::Call(..)),
+        _ => false,
+    }
+}
+
+fn statement(expr: Expr) -> Result<Statement, ParseError> {
+    match expr {
+        Expr::Call(proc_name, args, span) => Ok(Statement {
+            proc_name,
+            args,
+            span,
+        }),
+        other => Err(ParseError::new(
+            "Expected a statement, found an expression".to_string(),
+            other.span().start,
+        )),
     }
 }
 
 impl Parse for ForStatement {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        Ok(ForStatement(
-            p.parse_list(TokenType::Comma, TokenType::KwFor, TokenType::KwIn)?,
-            Expr::parse(p)?,
-            p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?,
-        ))
+        let start = p.expect(TokenType::KwFor)?.position;
+        let mut names = vec![Name::parse(p)?];
+        while p.peek().token_type == TokenType::Comma {
+            p.next();
+            names.push(Name::parse(p)?);
+        }
+        p.expect(TokenType::KwIn)?;
+
+        let iterable = p.parse_condition()?;
+        let body = p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?;
+
+        Ok(ForStatement(names, iterable, body, p.span_from(start)))
     }
 }
 
+/// Parses `if`/`while` and everything up to the closing brace of its last block.
 impl Parse for Conditional {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        Ok(Conditional(
-            Expr::parse(p)?,
-            p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?,
-            if p.peek().token_type == TokenType::KwElse {
-                p.next();
-                Some(p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?)
+        let start = p.yank().position;
+        let condition = p.parse_condition()?;
+        let body = p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?;
+
+        if p.peek().token_type == TokenType::Newline
+            && p.peek_nth(1).token_type == TokenType::KwElse
+        {
+            p.next();
+        }
+        let alternative = if p.peek().token_type == TokenType::KwElse {
+            p.next();
+            if p.peek().token_type == TokenType::KwIf {
+                Some(vec![Line::If(Conditional::parse(p)?)])
             } else {
-                None
-            },
-        ))
+                Some(p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?)
+            }
+        } else {
+            None
+        };
+
+        Ok(Conditional(condition, body, alternative, p.span_from(start)))
     }
 }
 
 impl Parse for Initialization {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        p.indent = p.peek().position;
+        let memo = if p.peek().token_type == TokenType::KwMemo {
+            let memo = Memo::parse(p)?;
+            p.skip_newlines();
+            Some(memo)
+        } else {
+            None
+        };
+        let start = p.peek().position;
 
         let domain = Domain::parse(p)?;
 
@@ -218,59 +398,135 @@ impl Parse for Initialization {
 
         let name = Name::parse(p)?;
 
+        // `type T = U` is an alias, while `type T` and `type T: @bound`
+        // declare a type parameter.
         if let Domain::Type = domain {
+            let params = if p.peek().token_type == TokenType::LT {
+                alias_params(p)?
+            } else {
+                Vec::new()
+            };
+            let mut bound = TypeAnnotation(None);
+            if p.peek().token_type == TokenType::Colon {
+                p.next();
+                bound = TypeAnnotation(Some(TypeExpr::parse(p)?));
+            }
+            let mut distinct = false;
+            let target = match p.peek().token_type {
+                TokenType::Equals => {
+                    p.next();
+                    // `distinct` is only a keyword right after the `=`.
+                    if p.peek().token_type == TokenType::Ident("distinct".to_string()) {
+                        p.next();
+                        distinct = true;
+                    }
+                    TypeAnnotation(Some(TypeExpr::parse(p)?))
+                }
+                _ => TypeAnnotation(None),
+            };
+            if !params.is_empty() && target.0.is_none() {
+                return Err(ParseError::new(
+                    "Expected '=' after the parameters of a type alias".to_string(),
+                    p.peek().position,
+                ));
+            }
             return Ok(Initialization {
+                memo,
                 domain,
                 name,
-                type_annotation: TypeAnnotation(None),
-                value: Value::Type(TypeAnnotation::parse(p)?),
+                type_annotation: bound,
+                value: Some(Value::Type(Alias {
+                    params,
+                    target,
+                    distinct,
+                })),
+                span: p.span_from(start),
             });
         }
 
         let type_annotation = TypeAnnotation::parse(p)?;
 
-        let equals = p.yank();
-
-        if let TokenType::Equals = equals.token_type {
+        let value = if p.peek().token_type == TokenType::Equals {
+            p.next();
+            p.expected_domain = Some(domain);
+            Some(Value::parse(p)?)
+        } else if matches!(domain, Domain::Var | Domain::Const) || type_annotation.0.is_some() {
+            // A `fn` or `proc` with only a type is a signature, as in typeclass declarations.
+            None
         } else {
+            let equals = p.yank();
             return Err(ParseError::new(
-                format!("Expected '=', found {:?}", equals.token_type),
-                equals.position
+                format!("Expected '=', found '{}'", equals.token_type),
+                equals.position,
             ));
-        }
+        };
 
         Ok(Initialization {
+            memo,
             domain,
             name,
             type_annotation,
-            value: Value::parse(p)?,
+            value,
+            span: p.span_from(start),
+        })
+    }
+}
+
+/// Parses `@memo` and `@memo(capacity)`.
+impl Parse for Memo {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        let start = p.expect(TokenType::KwMemo)?.position;
+        let mut capacity = None;
+        if p.peek().token_type == TokenType::LParen {
+            p.next();
+            let tok = p.yank();
+            match tok.token_type {
+                TokenType::IntLit(i) => capacity = Some(i),
+                other => {
+                    return Err(ParseError::new(
+                        format!("Expected the capacity of the cache, found '{}'", other),
+                        tok.position,
+                    ))
+                }
+            }
+            p.expect(TokenType::RParen)?;
+        }
+        Ok(Memo {
+            capacity,
+            span: p.span_from(start),
         })
     }
 }
 
 impl Parse for Statement {
     fn parse(p: &mut Parser) -> Result<Statement, ParseError> {
-        todo!()
+        statement(Expr::parse(p)?)
     }
 }
 
 impl Parse for Domain {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        Ok(p.yank().as_domain().unwrap())
+        let tok = p.yank();
+        tok.as_domain().ok_or_else(|| {
+            ParseError::new(
+                format!("Expected a declaration, found '{}'", tok.token_type),
+                tok.position,
+            )
+        })
     }
 }
 
 impl Parse for Name {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        let tok = yank(p.token_stream.front());
+        let tok = p.yank();
 
-        return match tok.as_name() {
+        match tok.as_name() {
             Some(n) => Ok(n),
             None => Err(ParseError::new(
-                format!("Expected Identifier, found '{:?}\'", tok.token_type),
+                format!("Expected Identifier, found '{}'", tok.token_type),
                 tok.position,
             )),
-        };
+        }
     }
 }
 
@@ -283,22 +539,12 @@ impl<T: Parse, U: Parse> Parse for Tag<T, U> {
 
 impl Parse for TypeAnnotation {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        let first = yank(p.token_stream.front());
-        let second = yank(p.token_stream.get(1));
-
-        return match first.token_type {
-            TokenType::Equals | TokenType::LBrace | TokenType::Comma | TokenType::Newline => {
-                Ok(TypeAnnotation(None))
-            }
-            TokenType::Colon => {
-                p.skip(1);
-                Ok(TypeAnnotation(Some(TypeExpr::parse(p)?)))
-            }
-            _ => Err(ParseError::new(
-                format!("Unexpected token '{:?}'", second.token_type),
-                second.position
-            )),
-        };
+        if p.peek().token_type == TokenType::Colon {
+            p.next();
+            Ok(TypeAnnotation(Some(TypeExpr::parse(p)?)))
+        } else {
+            Ok(TypeAnnotation(None))
+        }
     }
 }
 
@@ -310,114 +556,446 @@ impl Parse for Value {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
         if let Some(d) = &p.expected_domain {
             match d {
-                &Domain::Struct => return Ok(Value::Struct(Struct::parse(p)?)),
-                &Domain::Enum => return Ok(Value::Enum(Enum::parse(p)?)),
-                &Domain::Typeclass => return Ok(Value::Typeclass(Typeclass::parse(p)?)),
+                Domain::Struct => return Ok(Value::Struct(Struct::parse(p)?)),
+                Domain::Enum => return Ok(Value::Enum(Enum::parse(p)?)),
+                Domain::Typeclass => return Ok(Value::Typeclass(Typeclass::parse(p)?)),
+                Domain::Fn | Domain::Proc => return Ok(Value::Function(Function::parse(p)?)),
                 _ => {}
             }
         }
 
-        return Ok(Value::Expr(Expr::parse(p)?));
+        Ok(Value::Expr(Expr::parse_bp(p, 0)?))
+    }
+}
+
+impl Parse for Function {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        let is_proc = p.expected_domain == Some(Domain::Proc);
+        let start = p.peek().position;
+
+        let mut type_params = Vec::new();
+        let mut params = Vec::new();
+        let mut guard = None;
+
+        if p.peek().token_type == TokenType::LParen {
+            p.next();
+            loop {
+                p.skip_newlines();
+                match p.peek().token_type {
+                    TokenType::RParen => {
+                        p.next();
+                        break;
+                    }
+                    TokenType::KwWhere => {
+                        p.next();
+                        guard = Some(Box::new(Expr::parse(p)?));
+                        p.skip_newlines();
+                        p.expect(TokenType::RParen)?;
+                        break;
+                    }
+                    TokenType::KwType => type_params.push(TypeParam::parse(p)?),
+                    _ => params.push(Param::parse(p)?),
+                }
+                p.skip_newlines();
+                match p.peek().token_type {
+                    TokenType::Comma => {
+                        p.next();
+                    }
+                    TokenType::RParen | TokenType::KwWhere => {}
+                    _ => {
+                        let tok = p.yank();
+                        return Err(ParseError::new(
+                            format!("Unexpected token '{}' in parameter list", tok.token_type),
+                            tok.position,
+                        ));
+                    }
+                }
+            }
+        } else {
+            params.push(Param::parse(p)?);
+        }
+
+        let mut return_type = TypeAnnotation(None);
+        let body = if is_proc {
+            if p.peek().token_type == TokenType::Arrow {
+                p.next();
+                return_type = TypeAnnotation(Some(TypeExpr::parse(p)?));
+            }
+            if p.peek().token_type == TokenType::LBrace {
+                Some(Body::Block(p.parse_list(
+                    TokenType::Newline,
+                    TokenType::LBrace,
+                    TokenType::RBrace,
+                )?))
+            } else {
+                None
+            }
+        } else if p.peek().token_type == TokenType::Arrow {
+            p.next();
+            Some(Body::Expr(Box::new(Expr::parse(p)?)))
+        } else {
+            None
+        };
+
+        Ok(Function {
+            type_params,
+            params,
+            guard,
+            return_type,
+            body,
+            span: p.span_from(start),
+        })
+    }
+}
+
+impl Parse for Param {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        let start = p.peek().position;
+        let pattern = Pattern::parse(p)?;
+        let type_annotation = TypeAnnotation::parse(p)?;
+        Ok(Param {
+            pattern,
+            type_annotation,
+            span: p.span_from(start),
+        })
+    }
+}
+
+impl Parse for Pattern {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        let tok = p.yank();
+        match tok.token_type {
+            TokenType::Underscore => Ok(Pattern::Wildcard),
+            TokenType::Ident(name) => {
+                if p.peek().token_type == TokenType::LParen {
+                    let fields =
+                        p.parse_list(TokenType::Comma, TokenType::LParen, TokenType::RParen)?;
+                    Ok(Pattern::Constructor(Name(name), fields))
+                } else {
+                    Ok(Pattern::Binding(Name(name)))
+                }
+            }
+            TokenType::LParen => {
+                p.token_stream.push_front(tok);
+                let mut elements: Vec<Pattern> =
+                    p.parse_list(TokenType::Comma, TokenType::LParen, TokenType::RParen)?;
+                match elements.len() {
+                    0 => Ok(Pattern::Literal(Literal::Void)),
+                    1 => Ok(elements.remove(0)),
+                    _ => Ok(Pattern::Tuple(elements)),
+                }
+            }
+            TokenType::Negate => match p.yank().token_type {
+                TokenType::IntLit(i) => Ok(Pattern::Literal(Literal::Int(-i))),
+                TokenType::FloatLit(f) => Ok(Pattern::Literal(Literal::Float(-f))),
+                other => Err(ParseError::new(
+                    format!("Expected a number after '-', found '{}'", other),
+                    tok.position,
+                )),
+            },
+            TokenType::IntLit(i) => Ok(Pattern::Literal(Literal::Int(i))),
+            TokenType::FloatLit(f) => Ok(Pattern::Literal(Literal::Float(f))),
+            TokenType::StringLit(s) => Ok(Pattern::Literal(Literal::String(s))),
+            TokenType::KwTrue => Ok(Pattern::Literal(Literal::Bool(true))),
+            TokenType::KwFalse => Ok(Pattern::Literal(Literal::Bool(false))),
+            TokenType::KwNull => Ok(Pattern::Literal(Literal::Null)),
+            other => Err(ParseError::new(
+                format!("Expected a pattern, found '{}'", other),
+                tok.position,
+            )),
+        }
     }
 }
 
+/// Expressions in lists, arguments and conditions stop at commas; a bare
+/// comma separated sequence is only parsed where `parse_bp(p, 0)` is used.
 impl Parse for Expr {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        todo!();
+        Expr::parse_bp(p, 2)
     }
 }
 
 impl ParseBP for Expr {
     fn parse_bp(p: &mut Parser, min_bp: u8) -> Result<Self, ParseError> {
         let first = p.yank();
+        let start = first.position;
 
-        let lhs: Expr = if let Some(bp) = ExprOperator::prefix_bp(&first.token_type) {
+        let mut lhs: Expr = if let Some(bp) = ExprOperator::prefix_bp(&first.token_type) {
             match first.token_type {
+                TokenType::LParen if p.closure_ahead() => {
+                    p.token_stream.push_front(first);
+                    let domain = p.expected_domain.replace(Domain::Fn);
+                    let function = Function::parse(p);
+                    p.expected_domain = domain;
+                    let closure = Box::new(Value::Function(function?));
+                    Expr::Literal(Literal::Closure(closure), p.span_from(start))
+                }
+
                 TokenType::LParen => {
+                    p.skip_newlines();
                     if p.peek().token_type == TokenType::RParen {
                         p.next();
-                        Expr::Literal(Literal::Void)
+                        Expr::Literal(Literal::Void, p.span_from(start))
                     } else {
-                        let inside = Expr::parse_bp(p, bp)?;
+                        let in_guard = std::mem::replace(&mut p.in_guard, false);
+                        let in_condition = std::mem::replace(&mut p.in_condition, false);
+                        let inside = Expr::parse_bp(p, 0);
+                        p.in_guard = in_guard;
+                        p.in_condition = in_condition;
+                        let inside = inside?;
+                        p.skip_newlines();
+                        p.expect(TokenType::RParen)?;
                         match inside {
-                            Expr::Sequence(s) => Expr::Literal(Literal::Tuple(s)),
-                            e => e,
+                            Expr::Sequence(s, _) => {
+                                Expr::Literal(Literal::Tuple(s), p.span_from(start))
+                            }
+                            e => Expr::Grouping(
+                                TokenType::LParen,
+                                Box::new(e),
+                                p.span_from(start),
+                            ),
                         }
                     }
                 }
 
                 TokenType::LBrace => {
+                    p.token_stream.push_front(first);
                     let elements = p.parse_list::<Tag<Expr, Expr>>(
                         TokenType::Comma,
                         TokenType::LBrace,
                         TokenType::RBrace,
                     )?;
-                    let mut err = "";
-                    if elements[0].1.is_some() {
+                    let span = p.span_from(start);
+                    if elements.is_empty() || elements[0].1.is_some() {
                         let mut map = Vec::new();
                         for e in elements {
-                            if let Some(v) = e.1 {
-                                map.push((e.0, v))
-                            } else {
-                                err = "Expected map entry, found set entry";
-                                break;
+                            match e.1 {
+                                Some(v) => map.push((e.0, v)),
+                                None => {
+                                    return Err(ParseError::new(
+                                        "Expected map entry, found set entry".to_string(),
+                                        e.0.span().start,
+                                    ))
+                                }
                             }
                         }
-
-                        if err != "" {
-                            return Err(ParseError::new(err.to_string(), first.position));
-                        }
-
-                        Expr::Literal(Literal::Map(map))
+                        Expr::Literal(Literal::Map(map), span)
                     } else {
                         let mut set = Vec::new();
                         for e in elements {
-                            if e.1.is_none() {
-                                set.push(e.0);
-                            } else {
-                                err = "Expected set entry, found map entry";
-                                break;
+                            if e.1.is_some() {
+                                return Err(ParseError::new(
+                                    "Expected set entry, found map entry".to_string(),
+                                    e.0.span().start,
+                                ));
                             }
+                            set.push(e.0);
                         }
-
-                        if err != "" {
-                            return Err(ParseError::new(err.to_string(), first.position));
-                        }
-
-                        Expr::Literal(Literal::Set(set))
+                        Expr::Literal(Literal::Set(set), span)
                     }
                 }
 
                 TokenType::LBracket => {
+                    p.token_stream.push_front(first);
                     let list =
                         p.parse_list(TokenType::Comma, TokenType::LBracket, TokenType::RBracket)?;
-                    Expr::Literal(Literal::List(list))
+                    Expr::Literal(Literal::List(list), p.span_from(start))
                 }
 
                 TokenType::Bang => {
-                    Expr::Call(Name(String::from("not")), vec![Expr::parse_bp(p, bp)?])
+                    let operand = Expr::parse_bp(p, bp)?;
+                    Expr::Call(Name(String::from("not")), vec![operand], p.span_from(start))
+                }
+
+                TokenType::KwImpure => {
+                    let operand = Expr::parse_bp(p, bp)?;
+                    Expr::Grouping(TokenType::KwImpure, Box::new(operand), p.span_from(start))
+                }
+
+                TokenType::KwTail => {
+                    let operand = Expr::parse_bp(p, bp)?;
+                    Expr::Grouping(TokenType::KwTail, Box::new(operand), p.span_from(start))
                 }
 
-                _ => Expr::Call(Name(String::from("negate")), vec![Expr::parse_bp(p, bp)?]),
+                _ => {
+                    let operand = Expr::parse_bp(p, bp)?;
+                    Expr::Call(Name(String::from("negate")), vec![operand], p.span_from(start))
+                }
             }
         } else {
+            let span = first.span();
             match &first.token_type {
-                TokenType::Ident(s) => Expr::Reference(Name(s.to_string())),
-                TokenType::KwNull => Expr::Literal(Literal::Null),
-                TokenType::KwTrue => Expr::Literal(Literal::Bool(true)),
-                TokenType::KwFalse => Expr::Literal(Literal::Bool(false)),
-                TokenType::IntLit(i) => Expr::Literal(Literal::Int(*i)),
-                TokenType::FloatLit(f) => Expr::Literal(Literal::Float(*f)),
-                _ => {
+                TokenType::Ident(s)
+                    if p.peek().token_type == TokenType::LBrace && !p.in_condition =>
+                {
+                    struct_literal(p, Name(s.to_string()), start)?
+                }
+                TokenType::Ident(s) => Expr::Reference(Name(s.to_string()), span),
+                TokenType::KwNull => Expr::Literal(Literal::Null, span),
+                TokenType::KwTrue => Expr::Literal(Literal::Bool(true), span),
+                TokenType::KwFalse => Expr::Literal(Literal::Bool(false), span),
+                TokenType::IntLit(i) => Expr::Literal(Literal::Int(*i), span),
+                TokenType::FloatLit(f) => Expr::Literal(Literal::Float(*f), span),
+                TokenType::StringLit(s) => Expr::Literal(Literal::String(s.clone()), span),
+                TokenType::KwMatch => {
+                    let value = p.parse_condition()?;
+                    let arms =
+                        p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?;
+                    Expr::Match(Box::new(value), arms, p.span_from(start))
+                }
+                other => {
                     return Err(ParseError::new(
-                        format!("Unexpected token {:?}, unsure what happened", first),
-                        first.position
+                        format!("Unexpected token '{}' in expression", other),
+                        first.position,
                     ))
                 }
             }
         };
 
-        loop {}
+        loop {
+            let op = p.peek().clone();
+
+            if let Some(bp) = ExprOperator::postfix_bp(&op.token_type) {
+                if bp < min_bp {
+                    break;
+                }
+
+                lhs = match op.token_type {
+                    TokenType::Comma => {
+                        let mut elements = vec![lhs];
+                        while p.peek().token_type == TokenType::Comma {
+                            p.next();
+                            elements.push(Expr::parse_bp(p, bp + 1)?);
+                        }
+                        Expr::Sequence(elements, p.span_from(start))
+                    }
+                    TokenType::Dot => {
+                        p.next();
+                        let field = Name::parse(p)?;
+                        Expr::FieldAccess(
+                            Box::new(lhs),
+                            Field {
+                                field_name: field.0,
+                            },
+                            p.span_from(start),
+                        )
+                    }
+                    _ => {
+                        let args: Vec<Expr> =
+                            p.parse_list(TokenType::Comma, TokenType::LParen, TokenType::RParen)?;
+                        let span = p.span_from(start);
+                        match lhs {
+                            Expr::Reference(name, _) => Expr::Call(name, args, span),
+                            Expr::FieldAccess(receiver, field, _) => {
+                                let mut call_args = vec![*receiver];
+                                call_args.extend(args);
+                                Expr::Grouping(
+                                    TokenType::Dot,
+                                    Box::new(Expr::Call(Name(field.field_name), call_args, span)),
+                                    span,
+                                )
+                            }
+                            other => {
+                                return Err(ParseError::new(
+                                    "Only named functions can be called".to_string(),
+                                    other.span().start,
+                                ))
+                            }
+                        }
+                    }
+                };
+                continue;
+            }
+
+            if let Some((l_bp, r_bp)) = ExprOperator::infix_bp(&op.token_type) {
+                if l_bp < min_bp {
+                    break;
+                }
+                p.next();
+                let rhs = Expr::parse_bp(p, r_bp)?;
+                let name = infix_name(&op.token_type).expect("infix operator without a name");
+                lhs = Expr::Call(Name(name.to_string()), vec![lhs, rhs], p.span_from(start));
+                continue;
+            }
+
+            break;
+        }
+
+        Ok(lhs)
+    }
+}
+
+/// An entry between the braces of a struct literal.
+enum StructEntry {
+    Field(FieldInit),
+    Base(Expr, Position),
+}
+
+/// `field: value`, the shorthand `field`, or `..base`.
+impl Parse for StructEntry {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        let start = p.peek().position;
+        if p.peek().token_type == TokenType::DotDot {
+            p.next();
+            return Ok(StructEntry::Base(Expr::parse(p)?, start));
+        }
+        let name = Name::parse(p)?;
+        let span = p.span_from(start);
+        let value = if p.peek().token_type == TokenType::Colon {
+            p.next();
+            Expr::parse(p)?
+        } else {
+            Expr::Reference(name.clone(), span)
+        };
+        Ok(StructEntry::Field(FieldInit { name, value, span }))
+    }
+}
+
+/// Parses `{ ..base, field: value }` after the name of a struct; `..base`
+/// may only come first.
+fn struct_literal(p: &mut Parser, name: Name, start: Position) -> Result<Expr, ParseError> {
+    let entries = p.parse_list(TokenType::Comma, TokenType::LBrace, TokenType::RBrace)?;
+    let mut fields = Vec::new();
+    let mut base = None;
+    for (i, entry) in entries.into_iter().enumerate() {
+        match entry {
+            StructEntry::Field(field) => fields.push(field),
+            StructEntry::Base(expr, _) if i == 0 => base = Some(Box::new(expr)),
+            StructEntry::Base(_, position) => {
+                return Err(ParseError::new(
+                    "A struct literal's `..base` goes before its fields".to_string(),
+                    position,
+                ))
+            }
+        }
+    }
+    let literal = Literal::StructInitialization(name, fields, base);
+    Ok(Expr::Literal(literal, p.span_from(start)))
+}
+
+/// `pattern where guard -> result`, with the guard optional. A guard ending
+/// in parentheses is not taken for the parameters of a closure.
+impl Parse for Arm {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        let start = p.peek().position;
+        let pattern = Pattern::parse(p)?;
+        let guard = if p.peek().token_type == TokenType::KwWhere {
+            p.next();
+            p.in_guard = true;
+            let guard = Expr::parse(p);
+            p.in_guard = false;
+            Some(guard?)
+        } else {
+            None
+        };
+        p.expect(TokenType::Arrow)?;
+        let body = Expr::parse(p)?;
+        Ok(Arm {
+            pattern,
+            guard,
+            body,
+            span: p.span_from(start),
+        })
     }
 }
 
@@ -433,17 +1011,94 @@ impl Parse for Struct {
 
 impl Parse for Enum {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        Ok(Enum(p.parse_list(
-            TokenType::Newline,
-            TokenType::LBrace,
-            TokenType::RBrace,
-        )?))
+        let lines = p.parse_list(TokenType::Newline, TokenType::LBrace, TokenType::RBrace)?;
+        let mut entries = Vec::new();
+        let mut params = Vec::new();
+        for line in lines {
+            match line {
+                EnumLine::Entry(entry) => entries.push(entry),
+                EnumLine::Param(param) => params.push(param),
+            }
+        }
+        Ok(Enum(entries, params))
+    }
+}
+
+/// A line of an enum body: a variant or a `type T` parameter.
+enum EnumLine {
+    Entry(EnumEntry),
+    Param(TypeParam),
+}
+
+impl Parse for EnumLine {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        if p.peek().token_type == TokenType::KwType {
+            Ok(EnumLine::Param(TypeParam::parse(p)?))
+        } else {
+            Ok(EnumLine::Entry(EnumEntry::parse(p)?))
+        }
+    }
+}
+
+/// Parses `type T` or `type T: @bound`.
+impl Parse for TypeParam {
+    fn parse(p: &mut Parser) -> Result<Self, ParseError> {
+        let start = p.expect(TokenType::KwType)?.position;
+        let name = Name::parse(p)?;
+        let bound = if p.peek().token_type == TokenType::Colon {
+            p.next();
+            TypeAnnotation(Some(TypeExpr::parse(p)?))
+        } else {
+            TypeAnnotation(None)
+        };
+        Ok(TypeParam {
+            name,
+            bound,
+            span: p.span_from(start),
+        })
+    }
+}
+
+/// The `<A, B>` after the name of a generic alias.
+fn alias_params(p: &mut Parser) -> Result<Vec<TypeParam>, ParseError> {
+    p.expect(TokenType::LT)?;
+    let mut params = Vec::new();
+    loop {
+        let start = p.peek().position;
+        let name = Name::parse(p)?;
+        params.push(TypeParam {
+            name,
+            bound: TypeAnnotation(None),
+            span: p.span_from(start),
+        });
+        let tok = p.yank();
+        match tok.token_type {
+            TokenType::Comma => {}
+            TokenType::GT => return Ok(params),
+            other => {
+                return Err(ParseError::new(
+                    format!("Expected '>', found '{}'", other),
+                    tok.position,
+                ))
+            }
+        }
     }
 }
 
 impl Parse for EnumEntry {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        todo!()
+        let start = p.peek().position;
+        let name = Name::parse(p)?;
+        let payload = if p.peek().token_type == TokenType::LParen {
+            Some(p.parse_list(TokenType::Comma, TokenType::LParen, TokenType::RParen)?)
+        } else {
+            None
+        };
+        Ok(EnumEntry(
+            Field { field_name: name.0 },
+            payload,
+            p.span_from(start),
+        ))
     }
 }
 
@@ -459,6 +1114,91 @@ impl Parse for Typeclass {
 
 impl Parse for TypeExpr {
     fn parse(p: &mut Parser) -> Result<Self, ParseError> {
-        todo!()
+        TypeExpr::parse_bp(p, 0)
+    }
+}
+
+impl ParseBP for TypeExpr {
+    fn parse_bp(p: &mut Parser, min_bp: u8) -> Result<Self, ParseError> {
+        let first = p.yank();
+
+        let mut lhs = match first.token_type {
+            TokenType::Ident(ref s) => TypeExpr::Literal(Name(s.to_string())),
+            TokenType::LParen if p.peek().token_type == TokenType::RParen => {
+                p.next();
+                TypeExpr::Grouping
+            }
+            TokenType::LParen => {
+                p.token_stream.push_front(first.clone());
+                let mut elements: Vec<TypeExpr> =
+                    p.parse_list(TokenType::Comma, TokenType::LParen, TokenType::RParen)?;
+                if elements.len() == 1 {
+                    elements.remove(0)
+                } else {
+                    TypeExpr::Operator(first, elements)
+                }
+            }
+            TokenType::LBracket => {
+                let element = TypeExpr::parse(p)?;
+                p.expect(TokenType::RBracket)?;
+                TypeExpr::Operator(first, vec![element])
+            }
+            TokenType::LBrace => {
+                let mut elements = vec![TypeExpr::parse(p)?];
+                if p.peek().token_type == TokenType::Colon {
+                    p.next();
+                    elements.push(TypeExpr::parse(p)?);
+                }
+                p.expect(TokenType::RBrace)?;
+                TypeExpr::Operator(first, elements)
+            }
+            other => {
+                return Err(ParseError::new(
+                    format!("Unexpected token '{}' in type", other),
+                    first.position,
+                ))
+            }
+        };
+
+        loop {
+            let op = p.peek().clone();
+
+            if let Some(bp) = TypeOperator::postfix_bp(&op.token_type) {
+                if bp < min_bp {
+                    break;
+                }
+                p.next();
+                let mut elements = vec![lhs];
+                loop {
+                    elements.push(TypeExpr::parse(p)?);
+                    match p.yank().token_type {
+                        TokenType::Comma => {}
+                        TokenType::GT => break,
+                        other => {
+                            return Err(ParseError::new(
+                                format!("Expected ',' or '>', found '{}'", other),
+                                op.position,
+                            ))
+                        }
+                    }
+                }
+                lhs = TypeExpr::Operator(op, elements);
+                continue;
+            }
+
+            if let Some((l_bp, r_bp)) = TypeOperator::infix_bp(&op.token_type) {
+                if l_bp < min_bp {
+                    break;
+                }
+                p.next();
+                let rhs = TypeExpr::parse_bp(p, r_bp)?;
+                lhs = TypeExpr::Operator(op, vec![lhs, rhs]);
+                continue;
+            }
+
+            break;
+        }
+
+        Ok(lhs)
     }
 }
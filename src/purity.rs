@@ -0,0 +1,369 @@
+use std::collections::HashSet;
+
+use crate::abstract_syntax_tree::{
+    Assignment, Body, Conditional, Domain, Expr, ForStatement, Function, Initialization, Line,
+    Literal, Module, Name, Statement, Struct, Typeclass, Value,
+};
+use crate::diagnostics::Diagnostic;
+use crate::resolver::{DefId, DefKind, Resolution};
+use crate::tokens::{Span, TokenType};
+use crate::typeck::TypeInfo;
+
+/// Rejects side effects in `fn`s.
+///
+/// A `fn` may not call a `proc`, neither a builtin one such as `println` nor
+/// one declared in the program, and may not use one as a value that could
+/// be called later. Closures written inside a `fn` are held to the same
+/// rule, since calling them runs their body. Method calls are held to it
+/// through the function the type checker resolved them to. Nor may a `fn`
+/// be passed a `proc`, a local holding one, or a closure with side effects,
+/// which it could call. Anything wrapped in `impure` is exempt, which is
+/// meant for debug printing.
+pub fn check(module: &Module, resolution: &Resolution, types: &TypeInfo) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        resolution,
+        types,
+        procs: HashSet::new(),
+        diagnostics: Vec::new(),
+    };
+    for item in &module.items {
+        checker.item(item, None);
+    }
+    checker.diagnostics.sort_by_key(|d| d.span.start);
+    checker.diagnostics
+}
+
+/// The `fn` whose body is being checked.
+#[derive(Clone, Copy)]
+struct Enclosing<'n> {
+    name: &'n str,
+    span: Span,
+}
+
+/// What makes a value one a `fn` may not be passed.
+enum Effect {
+    /// A `proc`, by name.
+    Proc(String),
+    /// A local holding a `proc`.
+    Holds(String),
+    /// A closure whose body would be rejected in a `fn`.
+    Closure,
+}
+
+struct Checker<'a> {
+    resolution: &'a Resolution,
+    types: &'a TypeInfo,
+    /// The locals a `proc` is stored in.
+    procs: HashSet<DefId>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn item(&mut self, item: &Initialization, enclosing: Option<Enclosing>) {
+        match &item.value {
+            Some(Value::Struct(Struct(lines))) | Some(Value::Typeclass(Typeclass(lines))) => {
+                self.lines(lines, None)
+            }
+            Some(Value::Function(function)) => {
+                let enclosing = match item.domain {
+                    Domain::Fn => Some(Enclosing {
+                        name: &item.name.0,
+                        span: item.span,
+                    }),
+                    _ => None,
+                };
+                self.function(function, enclosing);
+            }
+            Some(Value::Expr(expr)) => {
+                self.expr(expr, enclosing);
+                if self.proc_value(expr).is_some() {
+                    if let Some(id) = self.resolution.declaration(item.span, &item.name.0) {
+                        self.procs.insert(id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn function(&mut self, function: &Function, enclosing: Option<Enclosing>) {
+        if let Some(guard) = &function.guard {
+            self.expr(guard, enclosing);
+        }
+        match &function.body {
+            Some(Body::Expr(expr)) => self.expr(expr, enclosing),
+            Some(Body::Block(lines)) => self.lines(lines, enclosing),
+            None => {}
+        }
+    }
+
+    fn lines(&mut self, lines: &[Line], enclosing: Option<Enclosing>) {
+        for line in lines {
+            match line {
+                Line::Initialization(init) => self.item(init, enclosing),
+                Line::Statement(Statement {
+                    proc_name,
+                    args,
+                    span,
+                }) => self.call(proc_name, args, *span, enclosing),
+                Line::Assignment(assignment) => {
+                    if let Some(enclosing) = enclosing {
+                        self.assignment(assignment, enclosing);
+                    }
+                    self.expr(&assignment.target, enclosing);
+                    self.expr(&assignment.value, enclosing);
+                    if self.proc_value(&assignment.value).is_some() {
+                        if let Some((_, span)) = assignment.binding() {
+                            if let Some(&id) = self.resolution.references.get(&span) {
+                                self.procs.insert(id);
+                            }
+                        }
+                    }
+                }
+                Line::MethodCall(expr) | Line::Return(expr) => self.expr(expr, enclosing),
+                Line::For(ForStatement(_, iterable, body, _)) => {
+                    self.expr(iterable, enclosing);
+                    self.lines(body, enclosing);
+                }
+                Line::While(Conditional(condition, then, otherwise, _))
+                | Line::If(Conditional(condition, then, otherwise, _)) => {
+                    self.expr(condition, enclosing);
+                    self.lines(then, enclosing);
+                    if let Some(otherwise) = otherwise {
+                        self.lines(otherwise, enclosing);
+                    }
+                }
+                Line::Break(_) | Line::Continue(_) => {}
+            }
+        }
+    }
+
+    /// A closure inside a `fn` may assign to its own locals, but not to
+    /// anything declared outside the `fn`.
+    fn assignment(&mut self, assignment: &Assignment, enclosing: Enclosing) {
+        let Some((name, span)) = assignment.binding() else {
+            return;
+        };
+        let Some(id) = self.resolution.references.get(&span) else {
+            return;
+        };
+        let declared = self.resolution.definition(*id).span;
+        if declared.is_some_and(|d| d.start >= enclosing.span.start && d.end <= enclosing.span.end)
+        {
+            return;
+        }
+        self.diagnostics.push(
+            Diagnostic::error(
+                format!(
+                    "the `fn` `{}` cannot assign to `{}`",
+                    enclosing.name, name.0
+                ),
+                assignment.span,
+            )
+            .with_note(
+                enclosing.span,
+                format!(
+                    "`{}` is a `fn`, so it cannot have side effects",
+                    enclosing.name
+                ),
+            ),
+        );
+    }
+
+    fn expr(&mut self, expr: &Expr, enclosing: Option<Enclosing>) {
+        match expr {
+            Expr::Call(name, args, span) => self.call(name, args, *span, enclosing),
+            Expr::Grouping(TokenType::KwImpure, inner, _) => self.expr(inner, None),
+            Expr::Grouping(_, inner, _) => self.expr(inner, enclosing),
+            Expr::FieldAccess(receiver, _, _) => self.expr(receiver, enclosing),
+            Expr::Sequence(elements, _) => self.exprs(elements, enclosing),
+            Expr::Literal(literal, _) => match literal {
+                Literal::List(elements) | Literal::Set(elements) | Literal::Tuple(elements) => {
+                    self.exprs(elements, enclosing)
+                }
+                Literal::StructInitialization(_, fields, base) => {
+                    if let Some(base) = base {
+                        self.expr(base, enclosing);
+                    }
+                    for field in fields {
+                        self.expr(&field.value, enclosing);
+                    }
+                }
+                Literal::Map(entries) => {
+                    for (key, value) in entries {
+                        self.expr(key, enclosing);
+                        self.expr(value, enclosing);
+                    }
+                }
+                Literal::Closure(value) => {
+                    if let Value::Function(function) = value.as_ref() {
+                        self.function(function, enclosing);
+                    }
+                }
+                _ => {}
+            },
+            Expr::Match(value, arms, _) => {
+                self.expr(value, enclosing);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard, enclosing);
+                    }
+                    self.expr(&arm.body, enclosing);
+                }
+            }
+            Expr::Reference(name, span) => {
+                if let Some(enclosing) = enclosing {
+                    self.reference(name, *span, enclosing);
+                }
+            }
+        }
+    }
+
+    /// A `proc` used as a value could be called through it, so a `fn` may
+    /// not use one at all.
+    fn reference(&mut self, name: &Name, span: Span, enclosing: Enclosing) {
+        let Some(id) = self.resolution.references.get(&span) else {
+            return;
+        };
+        if !matches!(
+            self.resolution.definition(*id).kind,
+            DefKind::Proc | DefKind::BuiltinProc
+        ) {
+            return;
+        }
+        self.diagnostics.push(
+            Diagnostic::error(
+                format!(
+                    "the `fn` `{}` cannot use the `proc` `{}` as a value",
+                    enclosing.name, name.0
+                ),
+                span,
+            )
+            .with_note(
+                enclosing.span,
+                format!(
+                    "`{}` is a `fn`, so it cannot have side effects",
+                    enclosing.name
+                ),
+            )
+            .with_help(format!(
+                "make `{}` a `proc`, or write `impure {}` to allow this one use",
+                enclosing.name, name.0
+            )),
+        );
+    }
+
+    fn exprs(&mut self, exprs: &[Expr], enclosing: Option<Enclosing>) {
+        for expr in exprs {
+            self.expr(expr, enclosing);
+        }
+    }
+
+    fn call(&mut self, name: &Name, args: &[Expr], span: Span, enclosing: Option<Enclosing>) {
+        let callee = self
+            .resolution
+            .references
+            .get(&span)
+            .or_else(|| self.types.method_calls.get(&span))
+            .map(|id| self.resolution.definition(*id));
+        // Inside a `fn`, the arguments are held to the rule themselves.
+        if let (None, Some(definition)) = (enclosing, callee) {
+            if definition.kind == DefKind::Fn {
+                for arg in args {
+                    self.passed(arg, &definition.name, definition.span);
+                }
+            }
+        }
+        let callee = callee.map(|definition| definition.kind);
+        if let (Some(enclosing), Some(DefKind::Proc | DefKind::BuiltinProc)) = (enclosing, callee) {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "the `fn` `{}` cannot call the `proc` `{}`",
+                        enclosing.name, name.0
+                    ),
+                    span,
+                )
+                .with_note(
+                    enclosing.span,
+                    format!(
+                        "`{}` is a `fn`, so it cannot have side effects",
+                        enclosing.name
+                    ),
+                )
+                .with_help(format!(
+                    "make `{}` a `proc`, or write `impure {}(..)` to allow this one call",
+                    enclosing.name, name.0
+                )),
+            );
+        }
+        self.exprs(args, enclosing);
+    }
+
+    /// Rejects an argument of the `fn` `callee` that it could call to have
+    /// side effects.
+    fn passed(&mut self, arg: &Expr, callee: &str, declared: Option<Span>) {
+        let Some(value) = self.proc_value(arg) else {
+            return;
+        };
+        let (what, exempt) = match value {
+            Effect::Proc(name) => (
+                format!("the `proc` `{}`", name),
+                format!("`impure {}`", name),
+            ),
+            Effect::Holds(name) => (
+                format!("`{}`, which holds a `proc`", name),
+                format!("`impure {}`", name),
+            ),
+            Effect::Closure => (
+                "a closure with side effects".to_string(),
+                "`impure` before the closure".to_string(),
+            ),
+        };
+        let mut error = Diagnostic::error(
+            format!("the `fn` `{}` cannot be passed {}", callee, what),
+            arg.span(),
+        );
+        if let Some(declared) = declared {
+            error = error.with_note(
+                declared,
+                format!("`{}` is a `fn`, so it cannot have side effects", callee),
+            );
+        }
+        self.diagnostics.push(error.with_help(format!(
+            "make `{}` a `proc`, or write {} to allow this one use",
+            callee, exempt
+        )));
+    }
+
+    /// Why calling `expr` may have side effects, if it may.
+    fn proc_value(&mut self, expr: &Expr) -> Option<Effect> {
+        match expr {
+            Expr::Reference(name, span) => {
+                let id = *self.resolution.references.get(span)?;
+                match self.resolution.definition(id).kind {
+                    DefKind::Proc | DefKind::BuiltinProc => Some(Effect::Proc(name.0.clone())),
+                    _ if self.procs.contains(&id) => Some(Effect::Holds(name.0.clone())),
+                    _ => None,
+                }
+            }
+            Expr::Literal(Literal::Closure(value), span) => {
+                let Value::Function(function) = value.as_ref() else {
+                    return None;
+                };
+                let reported = std::mem::take(&mut self.diagnostics);
+                let closure = Enclosing {
+                    name: "closure",
+                    span: *span,
+                };
+                self.function(function, Some(closure));
+                let effects = !self.diagnostics.is_empty();
+                self.diagnostics = reported;
+                effects.then_some(Effect::Closure)
+            }
+            Expr::Grouping(TokenType::KwImpure, _, _) => None,
+            Expr::Grouping(_, inner, _) => self.proc_value(inner),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,841 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::abstract_syntax_tree::{
+    Body, Conditional, Domain, Enum, Expr, ForStatement, Function, Initialization, Line, Literal,
+    Module, Name, Pattern, Statement, TypeAnnotation, TypeExpr, TypeParam, Value,
+};
+use crate::builtins::{BUILTIN_FNS, BUILTIN_PROCS, BUILTIN_TYPECLASSES, BUILTIN_TYPES};
+use crate::diagnostics::Diagnostic;
+use crate::tokens::{Span, TokenType};
+
+pub type DefId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefKind {
+    BuiltinFn,
+    BuiltinProc,
+    BuiltinType,
+    BuiltinTypeclass,
+    Const,
+    Var,
+    Fn,
+    Proc,
+    Struct,
+    Enum,
+    Variant,
+    Typeclass,
+    TypeAlias,
+    /// A `type X = distinct T`, which is also the function that makes an
+    /// `X` out of a `T`.
+    Newtype,
+    TypeParam,
+    Param,
+    ForBinding,
+    MatchBinding,
+}
+
+impl DefKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            DefKind::BuiltinFn | DefKind::BuiltinProc => "builtin",
+            DefKind::BuiltinType => "builtin type",
+            DefKind::BuiltinTypeclass => "builtin typeclass",
+            DefKind::Const => "const",
+            DefKind::Var => "var",
+            DefKind::Fn => "fn",
+            DefKind::Proc => "proc",
+            DefKind::Struct => "struct",
+            DefKind::Enum => "enum",
+            DefKind::Variant => "enum variant",
+            DefKind::Typeclass => "typeclass",
+            DefKind::TypeAlias => "type",
+            DefKind::Newtype => "distinct type",
+            DefKind::TypeParam => "type parameter",
+            DefKind::Param => "parameter",
+            DefKind::ForBinding => "loop variable",
+            DefKind::MatchBinding => "match binding",
+        }
+    }
+
+    fn from_domain(domain: Domain) -> DefKind {
+        match domain {
+            Domain::Const => DefKind::Const,
+            Domain::Var => DefKind::Var,
+            Domain::Fn => DefKind::Fn,
+            Domain::Proc => DefKind::Proc,
+            Domain::Struct => DefKind::Struct,
+            Domain::Enum => DefKind::Enum,
+            Domain::Typeclass => DefKind::Typeclass,
+            Domain::Type => DefKind::TypeAlias,
+        }
+    }
+}
+
+/// Something a name can refer to. Builtins have no span.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefKind,
+    pub span: Option<Span>,
+}
+
+/// The result of name resolution, shared by the later passes.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub definitions: Vec<Definition>,
+    /// The definition each `Expr::Reference`, `Expr::Call` and statement refers
+    /// to, keyed by the span of the referring node.
+    pub references: HashMap<Span, DefId>,
+    /// The definition introduced by a declaring node, keyed by that node's
+    /// span and the declared name. Patterns and `for` loops can declare
+    /// several names, so the span alone is not enough.
+    pub declarations: HashMap<(Span, String), DefId>,
+    /// The methods typeclass declarations declare. A call naming one runs
+    /// the method of its first argument's instance, as a method call would.
+    pub class_methods: HashSet<DefId>,
+}
+
+impl Resolution {
+    pub fn definition(&self, id: DefId) -> &Definition {
+        &self.definitions[id]
+    }
+
+    pub fn declaration(&self, span: Span, name: &str) -> Option<DefId> {
+        self.declarations.get(&(span, name.to_string())).copied()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ScopeKind {
+    Builtin,
+    Module,
+    Struct,
+    Typeclass,
+    Function,
+    Block,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Namespace {
+    Value,
+    Type,
+}
+
+struct Scope {
+    kind: ScopeKind,
+    values: HashMap<String, DefId>,
+    types: HashMap<String, DefId>,
+}
+
+impl Scope {
+    fn new(kind: ScopeKind) -> Self {
+        Scope {
+            kind,
+            values: HashMap::new(),
+            types: HashMap::new(),
+        }
+    }
+
+    fn names(&self, namespace: Namespace) -> &HashMap<String, DefId> {
+        match namespace {
+            Namespace::Value => &self.values,
+            Namespace::Type => &self.types,
+        }
+    }
+
+    fn names_mut(&mut self, namespace: Namespace) -> &mut HashMap<String, DefId> {
+        match namespace {
+            Namespace::Value => &mut self.values,
+            Namespace::Type => &mut self.types,
+        }
+    }
+}
+
+/// Resolves every name in the module against nested scopes.
+///
+/// Module, struct and typeclass members are visible throughout their scope,
+/// while locals in a `proc` block are only visible after their declaration.
+/// Redeclaring a local that is still visible from an enclosing scope of the
+/// same function is reported as illegal shadowing.
+pub fn resolve(module: &mut Module) -> (Resolution, Vec<Diagnostic>) {
+    let mut resolver = Resolver {
+        resolution: Resolution::default(),
+        diagnostics: Vec::new(),
+        scopes: Vec::new(),
+        nullary: HashSet::new(),
+    };
+    resolver.push(ScopeKind::Builtin);
+    for name in BUILTIN_FNS {
+        resolver.builtin(name, DefKind::BuiltinFn, Namespace::Value);
+    }
+    for name in BUILTIN_PROCS {
+        resolver.builtin(name, DefKind::BuiltinProc, Namespace::Value);
+    }
+    for name in BUILTIN_TYPES {
+        resolver.builtin(name, DefKind::BuiltinType, Namespace::Type);
+    }
+    for name in BUILTIN_TYPECLASSES {
+        resolver.builtin(name, DefKind::BuiltinTypeclass, Namespace::Type);
+    }
+
+    resolver.push(ScopeKind::Module);
+    resolver.members(&mut module.items.iter_mut().collect::<Vec<_>>());
+    resolver.pop();
+
+    resolver.diagnostics.sort_by_key(|d| d.span.start);
+    (resolver.resolution, resolver.diagnostics)
+}
+
+struct Resolver {
+    resolution: Resolution,
+    diagnostics: Vec<Diagnostic>,
+    scopes: Vec<Scope>,
+    /// The variants that have no fields, which a bare name in a pattern
+    /// stands for rather than binding a new name.
+    nullary: HashSet<DefId>,
+}
+
+impl Resolver {
+    fn push(&mut self, kind: ScopeKind) {
+        self.scopes.push(Scope::new(kind));
+    }
+
+    fn pop(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn builtin(&mut self, name: &str, kind: DefKind, namespace: Namespace) {
+        let id = self.define(name, kind, None);
+        self.current()
+            .names_mut(namespace)
+            .insert(name.to_string(), id);
+    }
+
+    fn current(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("resolver always has a scope")
+    }
+
+    fn define(&mut self, name: &str, kind: DefKind, span: Option<Span>) -> DefId {
+        self.resolution.definitions.push(Definition {
+            name: name.to_string(),
+            kind,
+            span,
+        });
+        self.resolution.definitions.len() - 1
+    }
+
+    /// Declares `name` in the innermost scope, reporting duplicates and
+    /// illegal shadowing of locals from the same function.
+    fn declare(
+        &mut self,
+        name: &str,
+        kind: DefKind,
+        span: Span,
+        namespaces: &[Namespace],
+    ) -> DefId {
+        let id = self.define(name, kind, Some(span));
+        self.resolution
+            .declarations
+            .insert((span, name.to_string()), id);
+
+        // A struct or enum clashes in both of its namespaces, but is only
+        // reported once.
+        let mut reported = false;
+        for &namespace in namespaces {
+            if let Some(&existing) = self.current().names(namespace).get(name) {
+                if std::mem::replace(&mut reported, true) {
+                    continue;
+                }
+                let previous = self.resolution.definitions[existing].clone();
+                let mut diagnostic =
+                    Diagnostic::error(format!("`{}` is defined more than once", name), span);
+                if let Some(previous_span) = previous.span {
+                    diagnostic = diagnostic
+                        .with_note(previous_span, format!("`{}` was first defined here", name));
+                }
+                self.diagnostics.push(diagnostic);
+                continue;
+            }
+
+            if let Some(shadowed) = self.shadowed_local(name, namespace) {
+                let previous = self.resolution.definitions[shadowed].clone();
+                let mut diagnostic = Diagnostic::error(
+                    format!(
+                        "`{}` shadows the {} of the same name",
+                        name,
+                        previous.kind.describe()
+                    ),
+                    span,
+                );
+                if let Some(previous_span) = previous.span {
+                    diagnostic =
+                        diagnostic.with_note(previous_span, format!("`{}` is defined here", name));
+                }
+                self.diagnostics
+                    .push(diagnostic.with_help("rename one of the two".to_string()));
+            }
+            self.current()
+                .names_mut(namespace)
+                .insert(name.to_string(), id);
+        }
+        id
+    }
+
+    /// A definition with this name in an enclosing scope of the current
+    /// function, which a new local declaration would hide.
+    fn shadowed_local(&self, name: &str, namespace: Namespace) -> Option<DefId> {
+        let innermost = self.scopes.last()?;
+        if !matches!(innermost.kind, ScopeKind::Function | ScopeKind::Block) {
+            return None;
+        }
+        for scope in self.scopes.iter().rev().skip(1) {
+            if !matches!(scope.kind, ScopeKind::Function | ScopeKind::Block) {
+                break;
+            }
+            if let Some(&id) = scope.names(namespace).get(name) {
+                return Some(id);
+            }
+            if scope.kind == ScopeKind::Function {
+                break;
+            }
+        }
+        None
+    }
+
+    fn lookup(&self, name: &str, namespace: Namespace) -> Option<DefId> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.names(namespace).get(name).copied())
+    }
+
+    fn use_name(&mut self, name: &Name, namespace: Namespace, span: Span) -> Option<DefId> {
+        match self.lookup(&name.0, namespace) {
+            Some(id) => Some(id),
+            None => {
+                let what = match namespace {
+                    Namespace::Value => "name",
+                    Namespace::Type => "type",
+                };
+                let mut diagnostic =
+                    Diagnostic::error(format!("undefined {} `{}`", what, name.0), span);
+                if let Some(suggestion) = self.suggest(&name.0, namespace) {
+                    diagnostic = diagnostic.with_help(format!("did you mean `{}`?", suggestion));
+                }
+                self.diagnostics.push(diagnostic);
+                None
+            }
+        }
+    }
+
+    fn reference(&mut self, name: &Name, span: Span) {
+        if let Some(id) = self.use_name(name, Namespace::Value, span) {
+            self.resolution.references.insert(span, id);
+        }
+    }
+
+    /// The visible name closest to `name`, if any is close enough to be a typo.
+    fn suggest(&self, name: &str, namespace: Namespace) -> Option<String> {
+        let limit = (name.chars().count() + 1) / 3;
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.names(namespace).keys())
+            .filter(|candidate| candidate.chars().any(|c| c.is_alphabetic()))
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= limit)
+            .min()
+            .map(|(_, candidate)| candidate.clone())
+    }
+
+    /// Declares a group of items that can all see each other, then resolves
+    /// their contents. All `fn` clauses sharing a name form one function, and
+    /// the methods of a typeclass declaration are declared alongside it.
+    fn members(&mut self, items: &mut [&mut Initialization]) {
+        let in_struct = self
+            .scopes
+            .last()
+            .is_some_and(|s| s.kind == ScopeKind::Struct);
+        let mut functions: HashMap<String, DefId> = HashMap::new();
+        for item in items.iter() {
+            if is_instance(item, in_struct) {
+                continue;
+            }
+            self.declare_member(item, &mut functions);
+            match &item.value {
+                Some(Value::Enum(e)) => self.declare_variants(e),
+                Some(Value::Typeclass(t)) => {
+                    for method in t.0.iter().filter_map(as_initialization) {
+                        self.declare_member(method, &mut functions);
+                        if let Some(id) = self.resolution.declaration(method.span, &method.name.0) {
+                            self.resolution.class_methods.insert(id);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for item in items.iter_mut() {
+            if is_instance(item, in_struct) {
+                self.instance(item);
+            } else {
+                self.item(item);
+            }
+        }
+    }
+
+    fn declare_member(&mut self, item: &Initialization, functions: &mut HashMap<String, DefId>) {
+        if item.domain == Domain::Fn {
+            if let Some(&id) = functions.get(&item.name.0) {
+                self.resolution
+                    .declarations
+                    .insert((item.span, item.name.0.clone()), id);
+                return;
+            }
+        }
+        let id = self.declare_item(item);
+        if item.domain == Domain::Fn {
+            functions.insert(item.name.0.clone(), id);
+        }
+    }
+
+    /// An instance refers to a typeclass declared elsewhere. Its methods and
+    /// `type` parameters live in a scope of their own, where `Self` is the
+    /// instance type.
+    fn instance(&mut self, item: &mut Initialization) {
+        self.use_name(&item.name, Namespace::Type, item.span);
+        self.push(ScopeKind::Typeclass);
+        self.declare("Self", DefKind::TypeParam, item.span, &[Namespace::Type]);
+        let Some(Value::Typeclass(t)) = &mut item.value else {
+            unreachable!()
+        };
+        let mut methods: Vec<&mut Initialization> =
+            t.0.iter_mut()
+                .filter_map(|line| match line {
+                    Line::Initialization(init) => Some(init),
+                    _ => None,
+                })
+                .collect();
+        let mut functions = HashMap::new();
+        for method in &methods {
+            self.declare_member(method, &mut functions);
+        }
+        self.annotation(&item.type_annotation, item.span);
+        for method in &mut methods {
+            self.item(method);
+        }
+        self.pop();
+    }
+
+    fn declare_item(&mut self, item: &Initialization) -> DefId {
+        let distinct = matches!(&item.value, Some(Value::Type(alias)) if alias.distinct);
+        let namespaces: &[Namespace] = match item.domain {
+            Domain::Struct | Domain::Enum => &[Namespace::Type, Namespace::Value],
+            Domain::Type if distinct => &[Namespace::Type, Namespace::Value],
+            Domain::Typeclass | Domain::Type => &[Namespace::Type],
+            _ => &[Namespace::Value],
+        };
+        let kind = if item.is_type_param() {
+            DefKind::TypeParam
+        } else if distinct {
+            DefKind::Newtype
+        } else {
+            DefKind::from_domain(item.domain)
+        };
+        self.declare(&item.name.0, kind, item.span, namespaces)
+    }
+
+    fn declare_variants(&mut self, e: &Enum) {
+        for entry in &e.0 {
+            let id = self.declare(
+                &entry.0.field_name,
+                DefKind::Variant,
+                entry.2,
+                &[Namespace::Value],
+            );
+            if entry.1.is_none() {
+                self.nullary.insert(id);
+            }
+        }
+    }
+
+    fn item(&mut self, item: &mut Initialization) {
+        self.annotation(&item.type_annotation, item.span);
+        match &mut item.value {
+            None => {}
+            Some(Value::Expr(expr)) => self.expr(expr),
+            Some(Value::Function(function)) => self.function(function),
+            Some(Value::Struct(s)) => self.struct_members(&mut s.0, item.span),
+            Some(Value::Typeclass(t)) => {
+                // The methods were declared in the enclosing scope.
+                self.push(ScopeKind::Typeclass);
+                self.declare("Self", DefKind::TypeParam, item.span, &[Namespace::Type]);
+                for line in &mut t.0 {
+                    match line {
+                        Line::Initialization(method) => self.item(method),
+                        _ => self.diagnostics.push(Diagnostic::error(
+                            "only declarations are allowed here".to_string(),
+                            item.span,
+                        )),
+                    }
+                }
+                self.pop();
+            }
+            Some(Value::Enum(e)) => {
+                // Like the `type T` lines of a struct, an enum's parameters
+                // are only seen by its own payloads.
+                self.push(ScopeKind::Struct);
+                self.type_params(&e.1);
+                for entry in &e.0 {
+                    for payload in entry.1.iter().flatten() {
+                        self.type_expr(payload, entry.2);
+                    }
+                }
+                self.pop();
+            }
+            Some(Value::Type(alias)) => {
+                self.push(ScopeKind::Struct);
+                self.type_params(&alias.params);
+                self.annotation(&alias.target, item.span);
+                self.pop();
+            }
+        }
+    }
+
+    fn type_params(&mut self, params: &[TypeParam]) {
+        for param in params {
+            self.declare(
+                &param.name.0,
+                DefKind::TypeParam,
+                param.span,
+                &[Namespace::Type],
+            );
+        }
+        for param in params {
+            self.annotation(&param.bound, param.span);
+        }
+    }
+
+    fn struct_members(&mut self, lines: &mut [Line], span: Span) {
+        self.push(ScopeKind::Struct);
+        let mut items = Vec::new();
+        for line in lines {
+            match line {
+                Line::Initialization(init) => items.push(init),
+                _ => self.diagnostics.push(Diagnostic::error(
+                    "only declarations are allowed here".to_string(),
+                    span,
+                )),
+            }
+        }
+        self.members(&mut items);
+        self.pop();
+    }
+
+    fn function(&mut self, function: &mut Function) {
+        self.push(ScopeKind::Function);
+        self.type_params(&function.type_params);
+        for param in &mut function.params {
+            self.annotation(&param.type_annotation, param.span);
+            self.pattern(&mut param.pattern, param.span, DefKind::Param);
+        }
+        if let Some(guard) = &mut function.guard {
+            self.expr(guard);
+        }
+        self.annotation(&function.return_type, function.span);
+        match &mut function.body {
+            None => {}
+            Some(Body::Expr(expr)) => self.expr(expr),
+            Some(Body::Block(lines)) => self.block(lines),
+        }
+        self.pop();
+    }
+
+    /// Declares the names bound by a pattern as `kind`, keyed by `span`.
+    /// A bare name that is a variant without fields matches that variant
+    /// rather than binding anything, and becomes a constructor pattern.
+    fn pattern(&mut self, pattern: &mut Pattern, span: Span, kind: DefKind) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+            Pattern::Binding(name) => {
+                let variant = self
+                    .lookup(&name.0, Namespace::Value)
+                    .filter(|id| self.nullary.contains(id));
+                if variant.is_some() {
+                    *pattern = Pattern::Constructor(name.clone(), Vec::new());
+                } else {
+                    self.declare(&name.0, kind, span, &[Namespace::Value]);
+                }
+            }
+            Pattern::Tuple(patterns) => {
+                for p in patterns {
+                    self.pattern(p, span, kind);
+                }
+            }
+            Pattern::Constructor(name, patterns) => {
+                self.use_name(name, Namespace::Value, span);
+                for p in patterns {
+                    self.pattern(p, span, kind);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, lines: &mut [Line]) {
+        self.push(ScopeKind::Block);
+        for line in lines {
+            self.line(line);
+        }
+        self.pop();
+    }
+
+    fn line(&mut self, line: &mut Line) {
+        match line {
+            Line::Initialization(init) => {
+                // Local functions may call themselves; other locals only
+                // become visible once their initializer has been resolved.
+                if matches!(init.domain, Domain::Fn | Domain::Proc) {
+                    self.declare_item(init);
+                    self.item(init);
+                } else {
+                    self.item(init);
+                    self.declare_item(init);
+                    if let Some(Value::Enum(e)) = &init.value {
+                        self.declare_variants(e);
+                    }
+                }
+            }
+            Line::Statement(Statement {
+                proc_name,
+                args,
+                span,
+            }) => {
+                self.reference(proc_name, *span);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Line::Assignment(assignment) => {
+                self.expr(&mut assignment.target);
+                self.expr(&mut assignment.value);
+            }
+            Line::MethodCall(expr) | Line::Return(expr) => self.expr(expr),
+            Line::For(ForStatement(names, iterable, body, span)) => {
+                self.expr(iterable);
+                self.push(ScopeKind::Block);
+                for name in names {
+                    self.declare(&name.0, DefKind::ForBinding, *span, &[Namespace::Value]);
+                }
+                self.block(body);
+                self.pop();
+            }
+            Line::While(conditional) | Line::If(conditional) => self.conditional(conditional),
+            Line::Break(_) | Line::Continue(_) => {}
+        }
+    }
+
+    fn conditional(&mut self, Conditional(condition, then, otherwise, _): &mut Conditional) {
+        self.expr(condition);
+        self.block(then);
+        if let Some(otherwise) = otherwise {
+            self.block(otherwise);
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Sequence(exprs, _) => {
+                for e in exprs {
+                    self.expr(e);
+                }
+            }
+            Expr::Call(name, args, span) => {
+                self.reference(name, *span);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Literal(literal, span) => self.literal(literal, *span),
+            Expr::Reference(name, span) => self.reference(name, *span),
+            Expr::FieldAccess(receiver, _, _) => self.expr(receiver),
+            Expr::Grouping(TokenType::Dot, inner, _) => match inner.as_mut() {
+                // Method names depend on the receiver's type, so only the
+                // receiver and arguments are resolved here.
+                Expr::Call(_, args, _) => {
+                    for arg in args {
+                        self.expr(arg);
+                    }
+                }
+                other => self.expr(other),
+            },
+            Expr::Grouping(_, inner, _) => self.expr(inner),
+            Expr::Match(value, arms, _) => {
+                self.expr(value);
+                for arm in arms {
+                    self.push(ScopeKind::Block);
+                    self.pattern(&mut arm.pattern, arm.span, DefKind::MatchBinding);
+                    if let Some(guard) = &mut arm.guard {
+                        self.expr(guard);
+                    }
+                    self.expr(&mut arm.body);
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    fn literal(&mut self, literal: &mut Literal, span: Span) {
+        match literal {
+            Literal::Null
+            | Literal::Void
+            | Literal::Int(_)
+            | Literal::Float(_)
+            | Literal::Bool(_)
+            | Literal::String(_) => {}
+            Literal::List(exprs) | Literal::Set(exprs) | Literal::Tuple(exprs) => {
+                for e in exprs {
+                    self.expr(e);
+                }
+            }
+            Literal::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Literal::StructInitialization(name, fields, base) => {
+                self.use_name(name, Namespace::Type, span);
+                if let Some(base) = base {
+                    self.expr(base);
+                }
+                for field in fields {
+                    self.expr(&mut field.value);
+                }
+            }
+            Literal::Closure(value) => {
+                if let Value::Function(function) = value.as_mut() {
+                    self.function(function);
+                }
+            }
+        }
+    }
+
+    fn annotation(&mut self, annotation: &TypeAnnotation, span: Span) {
+        if let Some(type_expr) = &annotation.0 {
+            self.type_expr(type_expr, span);
+        }
+    }
+
+    /// Type expressions carry no spans of their own, so errors point at the
+    /// declaration that contains them.
+    fn type_expr(&mut self, type_expr: &TypeExpr, span: Span) {
+        match type_expr {
+            TypeExpr::Literal(name) => {
+                self.use_name(name, Namespace::Type, span);
+            }
+            TypeExpr::Operator(_, operands) => {
+                for operand in operands {
+                    self.type_expr(operand, span);
+                }
+            }
+            TypeExpr::Grouping => {}
+        }
+    }
+}
+
+/// Whether a `typeclass` item implements a typeclass rather than declaring
+/// one: every typeclass inside a struct implements it for the struct, and
+/// `typeclass @show: int = { .. }` implements it for the annotated type.
+pub fn is_instance(item: &Initialization, in_struct: bool) -> bool {
+    matches!(item.value, Some(Value::Typeclass(_)))
+        && (in_struct || item.type_annotation.0.is_some())
+}
+
+/// The default methods of typeclasses that method calls reach on a type
+/// because its instance does not define them, as the name of the type, the
+/// name of the method and the default's definition.
+pub fn inherited_defaults(
+    module: &Module,
+    resolution: &Resolution,
+) -> Vec<(String, String, DefId)> {
+    let mut defaults: HashMap<&str, Vec<(&str, DefId)>> = HashMap::new();
+    let mut instances = Vec::new();
+    for item in &module.items {
+        match &item.value {
+            Some(Value::Typeclass(t)) if !is_instance(item, false) => {
+                for member in t.0.iter().filter_map(as_initialization) {
+                    if !matches!(&member.value, Some(Value::Function(f)) if f.body.is_some()) {
+                        continue;
+                    }
+                    if let Some(id) = resolution.declaration(member.span, &member.name.0) {
+                        defaults
+                            .entry(&item.name.0)
+                            .or_default()
+                            .push((&member.name.0, id));
+                    }
+                }
+            }
+            Some(Value::Typeclass(_)) => {
+                if let Some(TypeExpr::Literal(head)) = &item.type_annotation.0 {
+                    instances.push((head.0.as_str(), item));
+                }
+            }
+            Some(Value::Struct(s)) => {
+                for member in s.0.iter().filter_map(as_initialization) {
+                    if is_instance(member, true) {
+                        instances.push((item.name.0.as_str(), member));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut inherited = Vec::new();
+    for (owner, instance) in instances {
+        let Some(Value::Typeclass(t)) = &instance.value else {
+            continue;
+        };
+        for &(name, id) in defaults.get(instance.name.0.as_str()).into_iter().flatten() {
+            if !t
+                .0
+                .iter()
+                .filter_map(as_initialization)
+                .any(|m| m.name.0 == name)
+            {
+                inherited.push((owner.to_string(), name.to_string(), id));
+            }
+        }
+    }
+    inherited
+}
+
+fn as_initialization(line: &Line) -> Option<&Initialization> {
+    match line {
+        Line::Initialization(init) => Some(init),
+        _ => None,
+    }
+}
+
+/// Edit distance between two names where swapping two adjacent characters
+/// counts as one edit. Case is ignored so that `Arraylist` still suggests
+/// `ArrayList`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
@@ -0,0 +1,170 @@
+use crate::abstract_syntax_tree::{
+    Body, Conditional, Expr, ForStatement, Function, Initialization, Line, Literal, Module,
+    Statement, Struct, Typeclass, Value,
+};
+use crate::diagnostics::Diagnostic;
+use crate::tokens::{Span, TokenType};
+
+/// Checks that every call marked `@tail` is a tail call.
+///
+/// A call is in tail position when its result is returned as it is: it is
+/// the body of a `fn`, the value of a `return`, or one of those through
+/// parentheses, `impure` and the arms of a `match`. Such calls never grow
+/// the stack, whether they are marked or not; the mark only turns a call
+/// that is not one into an error.
+pub fn check(module: &Module) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        diagnostics: Vec::new(),
+    };
+    for item in &module.items {
+        checker.item(item);
+    }
+    checker.diagnostics.sort_by_key(|d| d.span.start);
+    checker.diagnostics
+}
+
+struct Checker {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn item(&mut self, item: &Initialization) {
+        match &item.value {
+            Some(Value::Struct(Struct(lines))) | Some(Value::Typeclass(Typeclass(lines))) => {
+                self.lines(lines)
+            }
+            Some(Value::Function(function)) => self.function(function),
+            Some(Value::Expr(expr)) => self.expr(expr, false),
+            _ => {}
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        if let Some(guard) = &function.guard {
+            self.expr(guard, false);
+        }
+        match &function.body {
+            Some(Body::Expr(expr)) => self.expr(expr, true),
+            Some(Body::Block(lines)) => self.lines(lines),
+            None => {}
+        }
+    }
+
+    /// The last line of a `proc` is not in tail position: the `proc`
+    /// returns `()` after it, whatever it evaluates to.
+    fn lines(&mut self, lines: &[Line]) {
+        for line in lines {
+            match line {
+                Line::Initialization(init) => self.item(init),
+                Line::Statement(Statement { args, .. }) => self.exprs(args),
+                Line::MethodCall(expr) => self.expr(expr, false),
+                Line::Assignment(assignment) => {
+                    self.expr(&assignment.target, false);
+                    self.expr(&assignment.value, false);
+                }
+                Line::Return(expr) => self.expr(expr, true),
+                Line::For(ForStatement(_, iterable, body, _)) => {
+                    self.expr(iterable, false);
+                    self.lines(body);
+                }
+                Line::While(Conditional(condition, then, otherwise, _))
+                | Line::If(Conditional(condition, then, otherwise, _)) => {
+                    self.expr(condition, false);
+                    self.lines(then);
+                    if let Some(otherwise) = otherwise {
+                        self.lines(otherwise);
+                    }
+                }
+                Line::Break(_) | Line::Continue(_) => {}
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, tail: bool) {
+        match expr {
+            Expr::Grouping(TokenType::KwTail, inner, span) => {
+                self.marked(inner, tail, *span);
+                self.expr(inner, tail);
+            }
+            Expr::Grouping(TokenType::Dot, call, _) => match call.as_ref() {
+                Expr::Call(_, args, _) => self.exprs(args),
+                other => self.expr(other, false),
+            },
+            Expr::Grouping(_, inner, _) => self.expr(inner, tail),
+            Expr::Call(_, args, _) => self.exprs(args),
+            Expr::FieldAccess(receiver, _, _) => self.expr(receiver, false),
+            Expr::Sequence(elements, _) => self.exprs(elements),
+            Expr::Literal(literal, _) => match literal {
+                Literal::List(elements) | Literal::Set(elements) | Literal::Tuple(elements) => {
+                    self.exprs(elements)
+                }
+                Literal::StructInitialization(_, fields, base) => {
+                    if let Some(base) = base {
+                        self.expr(base, false);
+                    }
+                    for field in fields {
+                        self.expr(&field.value, false);
+                    }
+                }
+                Literal::Map(entries) => {
+                    for (key, value) in entries {
+                        self.expr(key, false);
+                        self.expr(value, false);
+                    }
+                }
+                Literal::Closure(value) => {
+                    if let Value::Function(function) = value.as_ref() {
+                        self.function(function);
+                    }
+                }
+                _ => {}
+            },
+            Expr::Match(value, arms, _) => {
+                self.expr(value, false);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard, false);
+                    }
+                    self.expr(&arm.body, tail);
+                }
+            }
+            Expr::Reference(_, _) => {}
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            self.expr(expr, false);
+        }
+    }
+
+    fn marked(&mut self, inner: &Expr, tail: bool, span: Span) {
+        let call = match inner {
+            Expr::Call(name, _, _) => Some(name.0.as_str()),
+            Expr::Grouping(TokenType::Dot, call, _) => match call.as_ref() {
+                Expr::Call(name, _, _) => Some(name.0.as_str()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(name) = call else {
+            self.diagnostics.push(
+                Diagnostic::error("`@tail` only applies to calls".to_string(), span)
+                    .with_help("write `@tail` right before a call, as in `@tail f(x)`".to_string()),
+            );
+            return;
+        };
+        if !tail {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("this call to `{}` is not in tail position", name),
+                    span,
+                )
+                .with_help(
+                    "a tail call's result must be returned as it is, without being used first"
+                        .to_string(),
+                ),
+            );
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::abstract_syntax_tree::{Domain, Name};
 use std::str::FromStr;
 
 #[repr(u8)]
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     KwConst,
@@ -15,6 +16,7 @@ pub enum TokenType {
     KwIf,
     KwElse,
     //KwIs,
+    KwWhere,
     KwIn,
     KwFor,
     KwReturn,
@@ -26,12 +28,19 @@ pub enum TokenType {
     KwOr,
     KwTrue,
     KwFalse,
+    KwImpure,
+    KwTail,
+    KwMemo,
+    KwMatch,
 
     Newline,
     Comma,
     Dot,
+    DotDot,
     LT,
+    LTEq,
     GT,
+    GTEq,
     At,
     LBrace,
     RBrace,
@@ -70,56 +79,75 @@ pub enum TokenType {
     StringLit(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub position: Position,
+    pub end: Position,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// A `//` comment, kept aside by the lexer for the formatter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub position: Position,
+    /// Whether code precedes the comment on its line.
+    pub trailing: bool,
+}
+
+/// Line and column of a character, both counted from 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Position(pub u32, pub u16);
 
-impl Position {
-    pub fn includes(&self, t: &Token) -> Result<(), ()> {
-        return match t.token_type {
-            TokenType::RParen | TokenType::RBracket | TokenType::RBrace => {
-                if self.1 <= t.position.1 {
-                    Ok(())
-                } else {
-                    Err(())
-                }
-            }
-            _ => {
-                if self.1 < t.position.1 {
-                    Ok(())
-                } else {
-                    Err(())
-                }
-            }
-        };
+/// Source range from `start` up to, but not including, `end`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+
+    /// Whether `other` lies within this span.
+    pub fn contains(&self, other: Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.0, self.1)
     }
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, line: u32, col: u16) -> Self {
-        return Token {
+    pub fn new(token_type: TokenType, line: u32, col: u16, end_col: u16) -> Self {
+        Token {
             token_type,
             position: Position(line, col),
-        };
+            end: Position(line, end_col),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        Span::new(self.position, self.end)
     }
 
     pub fn is_domain(&self) -> bool {
-        match self.token_type {
+        matches!(
+            self.token_type,
             TokenType::KwConst
-            | TokenType::KwVar
-            | TokenType::KwFn
-            | TokenType::KwProc
-            | TokenType::KwStruct
-            | TokenType::KwEnum
-            | TokenType::KwType
-            | TokenType::KwTypeclass => true,
-            _ => false,
-        }
+                | TokenType::KwVar
+                | TokenType::KwFn
+                | TokenType::KwProc
+                | TokenType::KwStruct
+                | TokenType::KwEnum
+                | TokenType::KwType
+                | TokenType::KwTypeclass
+        )
     }
 
     pub fn as_domain(&self) -> Option<Domain> {
@@ -172,12 +200,6 @@ impl Token {
     */
 }
 
-pub enum ExprType {
-    TypeExpr,
-    PureExpr,
-    LineExpr,
-}
-
 impl FromStr for TokenType {
     type Err = ();
 
@@ -194,17 +216,99 @@ impl FromStr for TokenType {
             "if" => Ok(TokenType::KwIf),
             "else" => Ok(TokenType::KwElse),
             //"is" => Ok(TokenType::KwIs),
+            "where" => Ok(TokenType::KwWhere),
             "in" => Ok(TokenType::KwIn),
             "for" => Ok(TokenType::KwFor),
             "return" => Ok(TokenType::KwReturn),
             "break" => Ok(TokenType::KwBreak),
+            "continue" => Ok(TokenType::KwContinue),
             "while" => Ok(TokenType::KwWhile),
             "null" => Ok(TokenType::KwNull),
             "and" => Ok(TokenType::KwAnd),
             "or" => Ok(TokenType::KwOr),
             "true" => Ok(TokenType::KwTrue),
             "false" => Ok(TokenType::KwFalse),
+            "impure" => Ok(TokenType::KwImpure),
+            "@tail" => Ok(TokenType::KwTail),
+            "@memo" => Ok(TokenType::KwMemo),
+            "match" => Ok(TokenType::KwMatch),
             _ => Err(()),
         }
     }
 }
+
+impl std::fmt::Display for TokenType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            TokenType::KwConst => "const",
+            TokenType::KwVar => "var",
+            TokenType::KwFn => "fn",
+            TokenType::KwProc => "proc",
+            TokenType::KwStruct => "struct",
+            TokenType::KwEnum => "enum",
+            TokenType::KwType => "type",
+            TokenType::KwTypeclass => "typeclass",
+            TokenType::KwIf => "if",
+            TokenType::KwElse => "else",
+            TokenType::KwWhere => "where",
+            TokenType::KwIn => "in",
+            TokenType::KwFor => "for",
+            TokenType::KwReturn => "return",
+            TokenType::KwBreak => "break",
+            TokenType::KwContinue => "continue",
+            TokenType::KwWhile => "while",
+            TokenType::KwNull => "null",
+            TokenType::KwAnd => "and",
+            TokenType::KwOr => "or",
+            TokenType::KwTrue => "true",
+            TokenType::KwFalse => "false",
+            TokenType::KwImpure => "impure",
+            TokenType::KwTail => "@tail",
+            TokenType::KwMemo => "@memo",
+            TokenType::KwMatch => "match",
+            TokenType::Newline => "newline",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::DotDot => "..",
+            TokenType::LT => "<",
+            TokenType::LTEq => "<=",
+            TokenType::GT => ">",
+            TokenType::GTEq => ">=",
+            TokenType::At => "@",
+            TokenType::LBrace => "{",
+            TokenType::RBrace => "}",
+            TokenType::LBracket => "[",
+            TokenType::RBracket => "]",
+            TokenType::LParen => "(",
+            TokenType::RParen => ")",
+            TokenType::Colon => ":",
+            TokenType::Underscore => "_",
+            TokenType::Question => "?",
+            TokenType::SingleQuote => "'",
+            TokenType::Delim => "\\",
+            TokenType::Equals => "=",
+            TokenType::EqualsEq => "==",
+            TokenType::Bang => "!",
+            TokenType::BangEq => "!=",
+            TokenType::Percent => "%",
+            TokenType::PercentEq => "%=",
+            TokenType::Star => "*",
+            TokenType::StarEq => "*=",
+            TokenType::Slash => "/",
+            TokenType::SlashEq => "/=",
+            TokenType::Plus => "+",
+            TokenType::PlusEq => "+=",
+            TokenType::Minus => "-",
+            TokenType::MinusEq => "-=",
+            TokenType::Negate => "-",
+            TokenType::Arrow => "->",
+            TokenType::EOF => "end of file",
+            TokenType::Whitespace => "whitespace",
+            TokenType::Ident(s) => return write!(f, "{}", s),
+            TokenType::IntLit(i) => return write!(f, "{}", i),
+            TokenType::FloatLit(x) => return write!(f, "{:?}", x),
+            TokenType::StringLit(s) => return write!(f, "{:?}", s),
+        };
+        write!(f, "{}", text)
+    }
+}
@@ -0,0 +1,3141 @@
+use std::collections::HashMap;
+
+use crate::abstract_syntax_tree::{
+    Body, Conditional, Domain, Expr, FieldInit, ForStatement, Function, Initialization, Line,
+    Literal, Module, Name, Pattern, Statement, TypeAnnotation, TypeExpr, TypeParam, Value,
+};
+use crate::builtins::{BUILTIN_TYPECLASSES, BUILTIN_TYPES};
+use crate::diagnostics::Diagnostic;
+use crate::resolver::{is_instance, DefId, DefKind, Resolution};
+use crate::tokens::{Span, TokenType};
+use crate::typeclasses::{builtin_instance, match_type, minimize, overlap, superclasses};
+use crate::types::{Constraint, Scheme, Type, TypeVar};
+
+/// The outcome of type checking a module.
+pub struct TypeInfo {
+    /// The principal type of every value definition.
+    pub schemes: HashMap<DefId, Scheme>,
+    /// Module-level and struct member values in source order, with the
+    /// name they are printed under.
+    items: Vec<(String, DefId)>,
+    /// The names and variables of the declared `type T` parameters of
+    /// every generic `fn` or `proc`, in the order they are declared.
+    pub generics: HashMap<DefId, Vec<(String, TypeVar)>>,
+    /// Every use of a generic function, with its type arguments.
+    pub sites: Vec<Site>,
+    /// The function each `receiver.name(args)` or `Type.name(args)` calls,
+    /// keyed by the span of the call.
+    pub method_calls: HashMap<Span, DefId>,
+    /// The fields of every struct and their types, keyed by struct name.
+    pub fields: HashMap<String, Vec<(String, Type)>>,
+}
+
+/// A use of a function with declared type parameters, as in `max(1, 2)`.
+pub struct Site {
+    pub span: Span,
+    /// The value item whose body the use is in.
+    pub caller: Option<DefId>,
+    pub callee: DefId,
+    /// What the callee's type parameters are at this use. Variables in
+    /// them are those of the caller's scheme.
+    pub args: Vec<Type>,
+}
+
+impl TypeInfo {
+    /// The name a module-level or member value is printed under.
+    pub fn name(&self, id: DefId) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|(_, item)| *item == id)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// One `kind name: type` line per module-level or member value.
+    pub fn signatures(&self, resolution: &Resolution) -> Vec<String> {
+        self.items
+            .iter()
+            .filter_map(|(name, id)| {
+                let kind = resolution.definition(*id).kind.describe();
+                self.schemes
+                    .get(id)
+                    .map(|scheme| format!("{} {}: {}", kind, name, scheme))
+            })
+            .collect()
+    }
+}
+
+/// Infers principal types with Hindley–Milner inference.
+///
+/// Module-level values are grouped into strongly connected components of
+/// their references, so mutually recursive functions are inferred together
+/// and everything else is generalized before it is used. Every clause of a
+/// multi-clause `fn` is unified against one shared function type.
+///
+/// Typeclass constraints are collected while inferring, become part of a
+/// scheme when they mention its quantified variables, and are otherwise
+/// solved against the instances once the whole module has been seen.
+/// Numeric literals are overloaded: an integer literal has any type with an
+/// `@num` instance and defaults to `int`, a float literal defaults to `float`.
+pub fn check(module: &Module, resolution: &Resolution) -> (TypeInfo, Vec<Diagnostic>) {
+    let mut checker = Checker {
+        resolution,
+        bindings: Vec::new(),
+        env: HashMap::new(),
+        scoped: Vec::new(),
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        bounds: HashMap::new(),
+        generics: HashMap::new(),
+        sites: Vec::new(),
+        method_calls: HashMap::new(),
+        current: None,
+        aliases: HashMap::new(),
+        expanding: Vec::new(),
+        variants: HashMap::new(),
+        type_scopes: Vec::new(),
+        returns: Vec::new(),
+        deferred: Vec::new(),
+        classes: HashMap::new(),
+        signatures: HashMap::new(),
+        instances: Vec::new(),
+        methods: Vec::new(),
+        wanted: Vec::new(),
+        unsolved: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    let mut items = Vec::new();
+    let mut names = Vec::new();
+    let top: Vec<&Initialization> = module.items.iter().collect();
+    checker.declare_types(&top);
+    checker.define_types(&top);
+    checker.collect(&top, None, "", &mut items, &mut names);
+    checker.inherit_defaults(&top);
+    checker.check_instance_heads();
+
+    for component in components(&items, &checker.methods, resolution) {
+        checker.infer_component(&items, &component);
+    }
+    for item in &items {
+        if let Context::Instance(index) = item.context {
+            checker.check_instance_method(index, item);
+        }
+    }
+    checker.check_instance_methods_present();
+    checker.solve();
+
+    let schemes = checker
+        .env
+        .iter()
+        .map(|(id, scheme)| {
+            let scheme = Scheme {
+                vars: scheme.vars.clone(),
+                constraints: scheme
+                    .constraints
+                    .iter()
+                    .map(|c| Constraint::new(&c.class, checker.zonk(&c.ty)))
+                    .collect(),
+                ty: checker.written(&scheme.ty),
+            };
+            (*id, scheme)
+        })
+        .collect();
+    let generics = checker
+        .generics
+        .iter()
+        .map(|(id, generics)| {
+            let vars = generics
+                .iter()
+                .filter_map(|g| match checker.zonk(&g.ty) {
+                    Type::Var(v) => Some((g.name.clone(), v)),
+                    _ => None,
+                })
+                .collect();
+            (*id, vars)
+        })
+        .collect();
+    let sites = std::mem::take(&mut checker.sites)
+        .into_iter()
+        .map(|site| Site {
+            args: site.args.iter().map(|a| checker.zonk(a)).collect(),
+            ..site
+        })
+        .collect();
+    let fields = checker
+        .structs
+        .iter()
+        .map(|(name, info)| {
+            let fields = info
+                .fields
+                .iter()
+                .map(|(field, ty)| (field.clone(), checker.written(ty)))
+                .collect();
+            (name.clone(), fields)
+        })
+        .collect();
+    checker.diagnostics.sort_by_key(|d| d.span.start);
+    (
+        TypeInfo {
+            schemes,
+            items: names,
+            generics,
+            sites,
+            method_calls: checker.method_calls,
+            fields,
+        },
+        checker.diagnostics,
+    )
+}
+
+/// A `fn`, `proc`, `const` or `var` whose type is inferred as one unit.
+struct ValueItem<'a> {
+    def: DefId,
+    domain: Domain,
+    clauses: Vec<&'a Initialization>,
+    context: Context,
+}
+
+/// What an item is a member of, which decides what `Self` and an
+/// unannotated `self` parameter stand for.
+#[derive(Clone, PartialEq)]
+enum Context {
+    Module,
+    Struct(String),
+    /// A default method of a typeclass declaration.
+    Class(String),
+    /// A method of the instance with this index.
+    Instance(usize),
+}
+
+struct ClassInfo {
+    span: Span,
+    /// Every method with its definition, span and whether it has a default.
+    methods: Vec<(String, DefId, Span, bool)>,
+}
+
+struct InstanceInfo {
+    class: String,
+    /// The type the instance is for, over the variables in `params`.
+    head: Type,
+    params: Vec<(String, TypeVar)>,
+    /// Constraints on `params` that the instance's methods need.
+    context: Vec<Constraint>,
+    methods: Vec<(String, DefId, Span)>,
+    /// Whether method calls find it by the name of its type.
+    dispatched: bool,
+    span: Span,
+}
+
+/// A function `receiver.name(args)` can call: a member of a struct, or a
+/// method of an instance for a type named in its annotation. These are the
+/// ones the engines look up by the name of the receiver's type.
+struct MethodInfo {
+    name: String,
+    def: DefId,
+    /// The type whose values it is called on.
+    head: Type,
+    /// The typeclass it is a method of, if it is an instance method.
+    class: Option<String>,
+    /// Whether its first parameter is `self`, which makes it a method
+    /// rather than a function only called as `Type.name(args)`.
+    takes_self: bool,
+    span: Span,
+}
+
+/// A constraint that still has to be satisfied, with the expression that needs it.
+struct Wanted {
+    constraint: Constraint,
+    span: Span,
+}
+
+/// A declared `type T` parameter of a function.
+#[derive(Clone)]
+struct Generic {
+    name: String,
+    ty: Type,
+    bounds: Vec<String>,
+    span: Span,
+}
+
+struct StructInfo {
+    params: Vec<(String, TypeVar)>,
+    fields: Vec<(String, Type)>,
+    span: Span,
+}
+
+/// A transparent `type` alias, which stands for its target with its
+/// parameters replaced by the arguments it is used with.
+#[derive(Clone)]
+struct AliasInfo {
+    params: Vec<String>,
+    target: TypeExpr,
+    span: Span,
+}
+
+/// A field access or method call whose receiver type was not yet known
+/// when it was seen.
+struct Deferred {
+    receiver: Type,
+    member: Member,
+    result: Type,
+    span: Span,
+}
+
+enum Member {
+    Field(String),
+    /// A method with the types and spans of its arguments after `self`.
+    Method(String, Vec<(Type, Span)>),
+}
+
+/// The return type of the enclosing `proc` body, which `return` unifies with.
+struct Return {
+    ty: Type,
+    origin: Option<Span>,
+    seen: bool,
+}
+
+enum UnifyError {
+    Mismatch,
+    Infinite,
+}
+
+struct Checker<'a> {
+    resolution: &'a Resolution,
+    /// What each type variable has been unified with so far.
+    bindings: Vec<Option<Type>>,
+    env: HashMap<DefId, Scheme>,
+    /// Definitions whose types are still monomorphic and in scope, which
+    /// generalization must leave alone.
+    scoped: Vec<DefId>,
+    structs: HashMap<String, StructInfo>,
+    /// The type parameters of every enum and distinct type.
+    enums: HashMap<String, Vec<(String, TypeVar)>>,
+    /// The typeclasses each struct or enum type parameter is bounded by.
+    bounds: HashMap<TypeVar, Vec<String>>,
+    /// The declared type parameters of every generic value item.
+    generics: HashMap<DefId, Vec<Generic>>,
+    sites: Vec<Site>,
+    method_calls: HashMap<Span, DefId>,
+    /// The value item being inferred.
+    current: Option<DefId>,
+    aliases: HashMap<String, AliasInfo>,
+    /// The aliases being expanded, which guards against cyclic ones.
+    expanding: Vec<String>,
+    variants: HashMap<String, Scheme>,
+    type_scopes: Vec<HashMap<String, Type>>,
+    returns: Vec<Return>,
+    deferred: Vec<Deferred>,
+    classes: HashMap<String, ClassInfo>,
+    /// Declared types of typeclass methods, which win over anything inferred.
+    signatures: HashMap<DefId, Scheme>,
+    instances: Vec<InstanceInfo>,
+    methods: Vec<MethodInfo>,
+    wanted: Vec<Wanted>,
+    /// The constraints reported as unsolved and where. An operator and the
+    /// literals it applies to want the same constraint, which is reported
+    /// once for all of them.
+    unsolved: Vec<Wanted>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn fresh_var(&mut self) -> TypeVar {
+        self.bindings.push(None);
+        self.bindings.len() as TypeVar - 1
+    }
+
+    fn fresh(&mut self) -> Type {
+        Type::Var(self.fresh_var())
+    }
+
+    /// Registers the names of struct, enum and alias types so that field and
+    /// variant types may refer to each other in any order.
+    fn declare_types(&mut self, items: &[&Initialization]) {
+        for item in items {
+            match &item.value {
+                Some(Value::Struct(s)) => {
+                    let mut params = Vec::new();
+                    for init in initializations(&s.0) {
+                        if init.is_type_param() {
+                            let var = self.fresh_var();
+                            let bounds =
+                                self.bounds(&init.type_annotation, &init.name.0, init.span);
+                            self.bounds.insert(var, bounds);
+                            params.push((init.name.0.clone(), var));
+                        }
+                    }
+                    self.structs.insert(
+                        item.name.0.clone(),
+                        StructInfo {
+                            params,
+                            fields: Vec::new(),
+                            span: item.span,
+                        },
+                    );
+                }
+                Some(Value::Enum(e)) => {
+                    let mut params = Vec::new();
+                    for param in &e.1 {
+                        let var = self.fresh_var();
+                        let bounds = self.bounds(&param.bound, &param.name.0, param.span);
+                        self.bounds.insert(var, bounds);
+                        params.push((param.name.0.clone(), var));
+                    }
+                    self.enums.insert(item.name.0.clone(), params);
+                }
+                Some(Value::Typeclass(t)) if !is_instance(item, false) => {
+                    let methods = initializations(&t.0)
+                        .into_iter()
+                        .filter_map(|m| {
+                            let def = self.resolution.declaration(m.span, &m.name.0)?;
+                            let default =
+                                matches!(&m.value, Some(Value::Function(f)) if f.body.is_some());
+                            Some((m.name.0.clone(), def, m.span, default))
+                        })
+                        .collect();
+                    self.classes.insert(
+                        item.name.0.clone(),
+                        ClassInfo {
+                            span: item.span,
+                            methods,
+                        },
+                    );
+                }
+                // A distinct type has parameters like an enum, while an
+                // alias is expanded wherever it is used.
+                Some(Value::Type(alias)) if alias.distinct => {
+                    let mut params = Vec::new();
+                    for param in &alias.params {
+                        let var = self.fresh_var();
+                        self.bounds.insert(var, Vec::new());
+                        params.push((param.name.0.clone(), var));
+                    }
+                    self.enums.insert(item.name.0.clone(), params);
+                }
+                Some(Value::Type(alias)) => {
+                    if let Some(t) = &alias.target.0 {
+                        let info = AliasInfo {
+                            params: alias.params.iter().map(|p| p.name.0.clone()).collect(),
+                            target: t.clone(),
+                            span: item.span,
+                        };
+                        self.aliases.insert(item.name.0.clone(), info);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.check_alias_cycles(items);
+    }
+
+    /// Reports every alias that stands for itself, directly or through
+    /// other aliases, since it could never be expanded. A distinct type
+    /// ends the chain, as it is a type of its own.
+    fn check_alias_cycles(&mut self, items: &[&Initialization]) {
+        let mut reported: Vec<String> = Vec::new();
+        for item in items {
+            let name = &item.name.0;
+            if !self.aliases.contains_key(name) || reported.contains(name) {
+                continue;
+            }
+            let Some(path) = self.alias_path(name, name, &mut Vec::new()) else {
+                continue;
+            };
+            let through: Vec<String> = path[1..].iter().map(|n| format!("`{}`", n)).collect();
+            let diagnostic = match path.last() {
+                Some(last) if path.len() > 1 => Diagnostic::error(
+                    format!(
+                        "the type alias `{}` refers to itself through {}",
+                        name,
+                        through.join(" and ")
+                    ),
+                    item.span,
+                )
+                .with_note(
+                    self.aliases[last].span,
+                    format!("`{}` refers back to `{}` here", last, name),
+                ),
+                _ => Diagnostic::error(
+                    format!("the type alias `{}` refers to itself", name),
+                    item.span,
+                ),
+            };
+            self.diagnostics.push(diagnostic);
+            reported.extend(path);
+        }
+    }
+
+    /// The aliases leading from `from` back to `to`, starting with `from`.
+    fn alias_path(&self, from: &str, to: &str, seen: &mut Vec<String>) -> Option<Vec<String>> {
+        let alias = &self.aliases[from];
+        let mut names = Vec::new();
+        type_names(&alias.target, &mut names);
+        names.retain(|n| !alias.params.contains(n) && self.aliases.contains_key(n));
+        for next in names {
+            if next == to {
+                return Some(vec![from.to_string()]);
+            }
+            if seen.contains(&next) {
+                continue;
+            }
+            seen.push(next.clone());
+            if let Some(mut path) = self.alias_path(&next, to, seen) {
+                path.insert(0, from.to_string());
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    /// Fills in struct field types, enum variant constructors and the
+    /// declared types of typeclass methods.
+    fn define_types(&mut self, items: &[&Initialization]) {
+        for item in items {
+            match &item.value {
+                Some(Value::Typeclass(t)) if !is_instance(item, false) => {
+                    for method in initializations(&t.0) {
+                        let Some(annotation) = &method.type_annotation.0 else {
+                            continue;
+                        };
+                        let Some(def) = self.resolution.declaration(method.span, &method.name.0)
+                        else {
+                            continue;
+                        };
+                        let self_type = self.fresh();
+                        self.type_scopes
+                            .push(HashMap::from([("Self".to_string(), self_type.clone())]));
+                        let ty = self.convert(annotation, method.span);
+                        self.type_scopes.pop();
+                        let mut scheme = self.generalize(&ty, false);
+                        scheme
+                            .constraints
+                            .insert(0, Constraint::new(&item.name.0, self_type));
+                        self.signatures.insert(def, scheme.clone());
+                        self.env.insert(def, scheme);
+                    }
+                }
+                Some(Value::Struct(s)) => {
+                    let scope = self.structs[&item.name.0]
+                        .params
+                        .iter()
+                        .map(|(name, var)| (name.clone(), Type::Var(*var)))
+                        .collect();
+                    self.type_scopes.push(scope);
+                    let mut fields = Vec::new();
+                    for line in &s.0 {
+                        let Line::Initialization(init) = line else {
+                            continue;
+                        };
+                        if !matches!(init.domain, Domain::Var | Domain::Const)
+                            || init.value.is_some()
+                        {
+                            continue;
+                        }
+                        let ty = match &init.type_annotation.0 {
+                            Some(t) => self.convert(t, init.span),
+                            None => self.fresh(),
+                        };
+                        fields.push((init.name.0.clone(), ty));
+                    }
+                    self.type_scopes.pop();
+                    if let Some(info) = self.structs.get_mut(&item.name.0) {
+                        info.fields = fields;
+                    }
+                }
+                // Building a variant needs the bounds of the enum's type
+                // parameters, while matching against one does not.
+                Some(Value::Enum(e)) => {
+                    let params = self.enums[&item.name.0].clone();
+                    let scope = params
+                        .iter()
+                        .map(|(name, var)| (name.clone(), Type::Var(*var)))
+                        .collect();
+                    self.type_scopes.push(scope);
+                    let args = params.iter().map(|(_, var)| Type::Var(*var)).collect();
+                    let result = Type::Named(item.name.0.clone(), args);
+                    for entry in &e.0 {
+                        let ty = match &entry.1 {
+                            None => result.clone(),
+                            Some(payload) => {
+                                let params =
+                                    payload.iter().map(|t| self.convert(t, entry.2)).collect();
+                                Type::Function(params, Box::new(result.clone()))
+                            }
+                        };
+                        let mut bounds = Vec::new();
+                        for (_, var) in &params {
+                            for class in &self.bounds[var] {
+                                bounds.push(Constraint::new(class, Type::Var(*var)));
+                            }
+                        }
+                        for bound in &bounds {
+                            self.want(&bound.class, bound.ty.clone(), entry.2);
+                        }
+                        let scheme = self.generalize(&ty, false);
+                        let mut pattern = scheme.clone();
+                        pattern.constraints.retain(|c| !bounds.contains(c));
+                        if let Some(id) = self.resolution.declaration(entry.2, &entry.0.field_name)
+                        {
+                            self.env.insert(id, scheme);
+                        }
+                        self.variants.insert(entry.0.field_name.clone(), pattern);
+                    }
+                    self.type_scopes.pop();
+                }
+                // A distinct type is made and taken apart like an enum
+                // variant of the same name with a single field.
+                Some(Value::Type(alias)) if alias.distinct => {
+                    let params = self.enums[&item.name.0].clone();
+                    let scope = params
+                        .iter()
+                        .map(|(name, var)| (name.clone(), Type::Var(*var)))
+                        .collect();
+                    self.type_scopes.push(scope);
+                    let args = params.iter().map(|(_, var)| Type::Var(*var)).collect();
+                    let result = Type::Named(item.name.0.clone(), args);
+                    let target = match &alias.target.0 {
+                        Some(t) => self.convert(t, item.span),
+                        None => self.fresh(),
+                    };
+                    let scheme =
+                        self.generalize(&Type::Function(vec![target], Box::new(result)), false);
+                    if let Some(id) = self.resolution.declaration(item.span, &item.name.0) {
+                        self.env.insert(id, scheme.clone());
+                    }
+                    self.variants.insert(item.name.0.clone(), scheme);
+                    self.type_scopes.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Gathers the value items of a module or struct body, registering the
+    /// typeclass instances among them. `owner` is the enclosing struct.
+    fn collect(
+        &mut self,
+        items: &[&'a Initialization],
+        owner: Option<&str>,
+        prefix: &str,
+        out: &mut Vec<ValueItem<'a>>,
+        names: &mut Vec<(String, DefId)>,
+    ) {
+        let context = match owner {
+            Some(owner) => Context::Struct(owner.to_string()),
+            None => Context::Module,
+        };
+        for item in items {
+            match (&item.domain, &item.value) {
+                (_, Some(Value::Struct(s))) => {
+                    let members = initializations(&s.0);
+                    let prefix = format!("{}{}.", prefix, item.name.0);
+                    self.collect(&members, Some(&item.name.0), &prefix, out, names);
+                }
+                (_, Some(Value::Typeclass(t))) if is_instance(item, owner.is_some()) => {
+                    let index = self.instance(item, owner);
+                    // Only instances for a named type are found by method calls.
+                    let dispatched = owner.is_some()
+                        || matches!(item.type_annotation.0, Some(TypeExpr::Literal(_)));
+                    self.instances[index].dispatched = dispatched;
+                    let prefix = match owner {
+                        Some(_) => prefix.to_string(),
+                        None => format!("{}{}.", prefix, self.instances[index].head),
+                    };
+                    for member in initializations(&t.0) {
+                        if !matches!(member.domain, Domain::Fn | Domain::Proc) {
+                            continue;
+                        }
+                        let Some(def) =
+                            self.value_item(member, Context::Instance(index), &prefix, out, names)
+                        else {
+                            continue;
+                        };
+                        if !self.instances[index].methods.iter().any(|m| m.1 == def) {
+                            self.instances[index].methods.push((
+                                member.name.0.clone(),
+                                def,
+                                member.span,
+                            ));
+                            if dispatched {
+                                let head = self.instances[index].head.clone();
+                                let class = Some(self.instances[index].class.clone());
+                                self.method(member, def, head, class);
+                            }
+                        }
+                    }
+                }
+                (_, Some(Value::Typeclass(t))) => {
+                    let context = Context::Class(item.name.0.clone());
+                    for member in initializations(&t.0) {
+                        match &member.value {
+                            Some(Value::Function(f)) if f.body.is_some() => {
+                                self.value_item(member, context.clone(), prefix, out, names);
+                            }
+                            _ => {
+                                if let Some(def) =
+                                    self.resolution.declaration(member.span, &member.name.0)
+                                {
+                                    if !names.iter().any(|(_, id)| *id == def) {
+                                        names.push((format!("{}{}", prefix, member.name.0), def));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                // Struct fields are part of the struct's type, not values.
+                (Domain::Var | Domain::Const, None) if owner.is_some() => {}
+                (Domain::Fn | Domain::Proc | Domain::Const | Domain::Var, _) => {
+                    let def = self.value_item(item, context.clone(), prefix, out, names);
+                    if let (Some(def), Some(owner), Domain::Fn | Domain::Proc) =
+                        (def, owner, item.domain)
+                    {
+                        let head = match self.structs.get(owner) {
+                            Some(info) => Type::Named(
+                                owner.to_string(),
+                                info.params.iter().map(|(_, var)| Type::Var(*var)).collect(),
+                            ),
+                            None => Type::named(owner),
+                        };
+                        self.method(item, def, head, None);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Lets method calls reach the default methods of a typeclass on the
+    /// types whose instances do not define them.
+    fn inherit_defaults(&mut self, items: &[&Initialization]) {
+        for item in items {
+            let Some(Value::Typeclass(t)) = &item.value else {
+                continue;
+            };
+            if is_instance(item, false) {
+                continue;
+            }
+            for member in initializations(&t.0) {
+                if !matches!(&member.value, Some(Value::Function(f)) if f.body.is_some()) {
+                    continue;
+                }
+                let Some(def) = self.resolution.declaration(member.span, &member.name.0) else {
+                    continue;
+                };
+                let heads: Vec<Type> = self
+                    .instances
+                    .iter()
+                    .filter(|i| i.dispatched && i.class == item.name.0)
+                    .filter(|i| !i.methods.iter().any(|m| m.0 == member.name.0))
+                    .map(|i| i.head.clone())
+                    .collect();
+                for head in heads {
+                    self.method(member, def, head, Some(item.name.0.clone()));
+                }
+            }
+        }
+    }
+
+    /// Registers `item` as a function method calls can reach, once for all
+    /// of its clauses.
+    fn method(&mut self, item: &Initialization, def: DefId, head: Type, class: Option<String>) {
+        if self.methods.iter().any(|m| m.def == def && m.head == head) {
+            return;
+        }
+        let takes_self = match &item.value {
+            Some(Value::Function(function)) => matches!(
+                function.params.first().map(|p| &p.pattern),
+                Some(Pattern::Binding(Name(name))) if name == "self"
+            ),
+            _ => false,
+        };
+        self.methods.push(MethodInfo {
+            name: item.name.0.clone(),
+            def,
+            head,
+            class,
+            takes_self,
+            span: item.span,
+        });
+    }
+
+    /// Adds `item` as a value item or as another clause of one, returning its definition.
+    fn value_item(
+        &mut self,
+        item: &'a Initialization,
+        context: Context,
+        prefix: &str,
+        out: &mut Vec<ValueItem<'a>>,
+        names: &mut Vec<(String, DefId)>,
+    ) -> Option<DefId> {
+        let def = self.resolution.declaration(item.span, &item.name.0)?;
+        let bodiless = match &item.value {
+            Some(Value::Function(function)) => function.body.is_none(),
+            None => matches!(item.domain, Domain::Fn | Domain::Proc),
+            _ => false,
+        };
+        if bodiless {
+            self.diagnostics.push(
+                Diagnostic::error(format!("`{}` has no body", item.name.0), item.span).with_help(
+                    "only the methods of a typeclass declaration may be signatures".to_string(),
+                ),
+            );
+        }
+        if let Some(existing) = out.iter_mut().find(|v| v.def == def) {
+            existing.clauses.push(item);
+            return Some(def);
+        }
+        names.push((format!("{}{}", prefix, item.name.0), def));
+        out.push(ValueItem {
+            def,
+            domain: item.domain,
+            clauses: vec![item],
+            context,
+        });
+        Some(def)
+    }
+
+    /// Registers an instance: one written inside a struct is for the struct's
+    /// type, any other is for the type in its annotation. The head's type
+    /// parameters are the struct's, the instance's own `type T` lines, and any
+    /// arguments the annotation leaves out.
+    fn instance(&mut self, item: &Initialization, owner: Option<&str>) -> usize {
+        let mut params: Vec<(String, TypeVar)> = Vec::new();
+        if let Some(Value::Typeclass(t)) = &item.value {
+            for init in initializations(&t.0) {
+                if init.is_type_param() {
+                    params.push((init.name.0.clone(), self.fresh_var()));
+                }
+            }
+        }
+        let head = match (owner, &item.type_annotation.0) {
+            (Some(owner), _) => {
+                let names: Vec<String> = self
+                    .structs
+                    .get(owner)
+                    .map(|info| info.params.iter().map(|(name, _)| name.clone()).collect())
+                    .unwrap_or_default();
+                let args = names
+                    .into_iter()
+                    .map(|name| {
+                        let var = self.fresh_var();
+                        params.push((name, var));
+                        Type::Var(var)
+                    })
+                    .collect();
+                Type::Named(owner.to_string(), args)
+            }
+            (None, Some(annotation)) => {
+                let scope = params
+                    .iter()
+                    .map(|(name, var)| (name.clone(), Type::Var(*var)))
+                    .collect();
+                self.type_scopes.push(scope);
+                let head = self.convert(annotation, item.span);
+                self.type_scopes.pop();
+                self.zonk(&head)
+            }
+            (None, None) => Type::unit(),
+        };
+        let mut vars = Vec::new();
+        head.free_vars(&mut vars);
+        for var in vars {
+            if !params.iter().any(|(_, p)| *p == var) {
+                params.push((String::new(), var));
+            }
+        }
+        self.instances.push(InstanceInfo {
+            class: item.name.0.clone(),
+            head,
+            params,
+            context: Vec::new(),
+            methods: Vec::new(),
+            dispatched: false,
+            span: item.span,
+        });
+        self.instances.len() - 1
+    }
+
+    fn infer_component(&mut self, items: &[ValueItem], component: &[usize]) {
+        for &i in component {
+            let item = &items[i];
+            let template = match self.signatures.get(&item.def).cloned() {
+                Some(signature) => self.instantiate(&signature, item.clauses[0].span),
+                None => self.template(item),
+            };
+            self.env.insert(item.def, Scheme::mono(template));
+            self.scoped.push(item.def);
+            let declared = item.clauses.iter().find_map(|clause| match &clause.value {
+                Some(Value::Function(f)) if !f.type_params.is_empty() => Some(&f.type_params),
+                _ => None,
+            });
+            if let Some(declared) = declared {
+                let generics = self.declare_generics(declared);
+                self.generics.insert(item.def, generics);
+            }
+        }
+
+        for &i in component {
+            let item = &items[i];
+            let expected = self.env[&item.def].ty.clone();
+            self.current = Some(item.def);
+            self.infer_item(item, &expected);
+        }
+        self.current = None;
+        self.solve_deferred(true);
+
+        self.scoped.clear();
+        for &i in component {
+            let item = &items[i];
+            let ty = self.env[&item.def].ty.clone();
+            let scheme = if item.domain == Domain::Var {
+                Scheme::mono(self.zonk(&ty))
+            } else {
+                // The monomorphism restriction: a `const` bound to an
+                // expression is computed once, so it must not be overloaded.
+                let restricted = item
+                    .clauses
+                    .iter()
+                    .any(|c| matches!(c.value, Some(Value::Expr(_))));
+                self.generalize(&ty, restricted)
+            };
+            // A declared signature wins over what the default method allows.
+            let scheme = self.signatures.get(&item.def).cloned().unwrap_or(scheme);
+            self.env.insert(item.def, scheme);
+        }
+        for &i in component {
+            self.check_generics(items[i].def);
+        }
+        self.default_constraints();
+    }
+
+    /// The shape every clause of an item must agree with.
+    fn template(&mut self, item: &ValueItem) -> Type {
+        let mut arity = None;
+        for clause in &item.clauses {
+            let Some(Value::Function(function)) = &clause.value else {
+                continue;
+            };
+            match arity {
+                None => arity = Some((function.params.len(), clause.span)),
+                Some((n, first)) if n != function.params.len() => self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "this clause of `{}` takes {} parameters, but the first takes {}",
+                            clause.name.0,
+                            function.params.len(),
+                            n
+                        ),
+                        clause.span,
+                    )
+                    .with_note(first, "first clause is here".to_string()),
+                ),
+                _ => {}
+            }
+        }
+        match arity {
+            Some((n, _)) => {
+                let params = (0..n).map(|_| self.fresh()).collect();
+                Type::Function(params, Box::new(self.fresh()))
+            }
+            None => self.fresh(),
+        }
+    }
+
+    /// Gives every `type T` parameter of a function a variable, which must
+    /// satisfy the parameter's bounds.
+    fn declare_generics(&mut self, params: &[TypeParam]) -> Vec<Generic> {
+        let mut generics = Vec::new();
+        for param in params {
+            let ty = self.fresh();
+            let bounds = self.bounds(&param.bound, &param.name.0, param.span);
+            for class in &bounds {
+                self.want(class, ty.clone(), param.span);
+            }
+            generics.push(Generic {
+                name: param.name.0.clone(),
+                ty,
+                bounds,
+                span: param.span,
+            });
+        }
+        generics
+    }
+
+    /// The typeclasses a bound names: one, as in `type T: @ord`, or several,
+    /// as in `type T: (@ord, @show)`.
+    fn bounds(&mut self, bound: &TypeAnnotation, name: &str, span: Span) -> Vec<String> {
+        let classes = match &bound.0 {
+            None => return Vec::new(),
+            Some(TypeExpr::Operator(token, elements)) if token.token_type == TokenType::LParen => {
+                elements.iter().collect()
+            }
+            Some(single) => vec![single],
+        };
+        let mut bounds = Vec::new();
+        for class in classes {
+            match class {
+                TypeExpr::Literal(Name(class)) if class.starts_with('@') => {
+                    bounds.push(class.clone())
+                }
+                _ => self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "the bound of the type parameter `{}` is not a typeclass",
+                            name
+                        ),
+                        span,
+                    )
+                    .with_help(format!(
+                        "bound it by typeclasses, as in `type {}: @ord`",
+                        name
+                    )),
+                ),
+            }
+        }
+        bounds
+    }
+
+    /// Checks that every declared type parameter of `def` still stands for
+    /// any type its bounds allow: that the body did not fix it to one type
+    /// or to another parameter, needs no typeclass its bounds leave out, and
+    /// that a call can tell what it is.
+    fn check_generics(&mut self, def: DefId) {
+        let Some(generics) = self.generics.get(&def).cloned() else {
+            return;
+        };
+        let scheme = self.env[&def].clone();
+        let name = self.resolution.definition(def).name.clone();
+        let mut seen: Vec<(TypeVar, &str)> = Vec::new();
+        for generic in &generics {
+            let var = match self.zonk(&generic.ty) {
+                Type::Var(var) => var,
+                other => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "`{}` only works when its type parameter `{}` is `{}`",
+                                name, generic.name, other
+                            ),
+                            generic.span,
+                        )
+                        .with_help(format!("write `{}` in place of `{}`", other, generic.name)),
+                    );
+                    continue;
+                }
+            };
+            if let Some((_, first)) = seen.iter().find(|(v, _)| *v == var) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "`{}` only works when its type parameters `{}` and `{}` are the same type",
+                            name, first, generic.name
+                        ),
+                        generic.span,
+                    )
+                    .with_help(format!("write `{}` in place of `{}`", first, generic.name)),
+                );
+                continue;
+            }
+            seen.push((var, &generic.name));
+            if !scheme.vars.contains(&var) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "the type parameter `{}` of `{}` is in none of its parameters or its result",
+                            generic.name, name
+                        ),
+                        generic.span,
+                    )
+                    .with_help("no call could tell what it is, so remove it".to_string()),
+                );
+                let bounds: Vec<Wanted> = std::mem::take(&mut self.wanted);
+                for wanted in bounds {
+                    if self.zonk(&wanted.constraint.ty) != Type::Var(var) {
+                        self.wanted.push(wanted);
+                    }
+                }
+                continue;
+            }
+            for constraint in &scheme.constraints {
+                let allowed = generic.bounds.iter().any(|b| implies(b, &constraint.class));
+                if constraint.ty == Type::Var(var) && !allowed {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "`{}` needs `{}` of its type parameter `{}`, which its bounds do not provide",
+                                name, constraint.class, generic.name
+                            ),
+                            generic.span,
+                        )
+                        .with_help(format!(
+                            "add the bound: `type {}: {}`",
+                            generic.name, constraint.class
+                        )),
+                    );
+                }
+            }
+        }
+    }
+
+    fn infer_item(&mut self, item: &ValueItem, expected: &Type) {
+        let generics = self.generics.get(&item.def).cloned().unwrap_or_default();
+        let mut first: Option<&Function> = None;
+        for clause in &item.clauses {
+            if let Some(t) = &clause.type_annotation.0 {
+                let annotated = self.convert(t, clause.span);
+                self.expect(&annotated, expected, clause.span, None);
+            }
+            match &clause.value {
+                Some(Value::Function(function)) => {
+                    if function.params.len()
+                        == first.map_or(function.params.len(), |f| f.params.len())
+                    {
+                        self.function(function, expected, &item.context, first, &generics);
+                    }
+                    first = first.or(Some(function));
+                }
+                Some(Value::Expr(expr)) => {
+                    let ty = self.infer(expr);
+                    let origin = clause.type_annotation.0.as_ref().map(|_| clause.span);
+                    self.expect(expected, &ty, expr.span(), origin);
+                }
+                _ => {}
+            }
+        }
+        // A `@memo` fn looks its arguments up in its cache.
+        if let (Domain::Fn, Some(memo)) = (item.domain, item.clauses[0].memo) {
+            if let Type::Function(params, _) = self.zonk(expected) {
+                for param in params {
+                    self.want("@eq", param.clone(), memo.span);
+                    self.want("@hash", param, memo.span);
+                }
+            }
+        }
+    }
+
+    /// Checks one clause against the shared function type `expected`.
+    /// `first` is the first clause, which mismatches in later clauses point
+    /// back to, and `generics` are the type parameters all clauses share.
+    fn function(
+        &mut self,
+        function: &Function,
+        expected: &Type,
+        context: &Context,
+        first: Option<&Function>,
+        generics: &[Generic],
+    ) {
+        let (params, ret) = match self.unfold(expected) {
+            Type::Function(params, ret) if params.len() == function.params.len() => (params, *ret),
+            other => {
+                let params: Vec<Type> = function.params.iter().map(|_| self.fresh()).collect();
+                let ret = self.fresh();
+                let ty = Type::Function(params.clone(), Box::new(ret.clone()));
+                self.expect(&other, &ty, function.span, None);
+                (params, ret)
+            }
+        };
+
+        let scope_depth = self.scoped.len();
+        let mut scope = HashMap::new();
+        // Each member sees the type parameters of its struct or instance
+        // afresh, so one method cannot pin them down for the others.
+        let self_type = match context {
+            Context::Module => None,
+            Context::Struct(owner) => {
+                let names: Vec<String> = self.structs.get(owner).map_or(Vec::new(), |info| {
+                    info.params.iter().map(|(name, _)| name.clone()).collect()
+                });
+                let args = names
+                    .into_iter()
+                    .map(|name| {
+                        let var = self.fresh();
+                        scope.insert(name, var.clone());
+                        var
+                    })
+                    .collect();
+                Some(Type::Named(owner.clone(), args))
+            }
+            Context::Class(_) => Some(self.fresh()),
+            Context::Instance(index) => {
+                let params = self.instances[*index].params.clone();
+                let mut copies = HashMap::new();
+                for (name, var) in params {
+                    let copy = self.fresh();
+                    if !name.is_empty() {
+                        scope.insert(name, copy.clone());
+                    }
+                    copies.insert(var, copy);
+                }
+                Some(self.instances[*index].head.substitute(&copies))
+            }
+        };
+        if let Some(self_type) = &self_type {
+            scope.insert("Self".to_string(), self_type.clone());
+        }
+        for generic in generics {
+            scope.insert(generic.name.clone(), generic.ty.clone());
+        }
+        self.type_scopes.push(scope);
+
+        for (i, param) in function.params.iter().enumerate() {
+            let ty = self.pattern(&param.pattern, param.span);
+            if let Some(t) = &param.type_annotation.0 {
+                let annotated = self.convert(t, param.span);
+                self.expect(&annotated, &ty, param.span, None);
+            } else if let (Pattern::Binding(Name(name)), Some(self_type)) =
+                (&param.pattern, &self_type)
+            {
+                if name == "self" {
+                    self.expect(self_type, &ty, param.span, None);
+                    if let Context::Class(class) = context {
+                        self.want(class, self_type.clone(), param.span);
+                    }
+                }
+            }
+            let origin = first.map(|f| f.params[i].span);
+            self.expect(&params[i], &ty, param.span, origin);
+        }
+
+        if let Some(guard) = &function.guard {
+            let ty = self.infer(guard);
+            self.expect(&Type::bool(), &ty, guard.span(), None);
+        }
+
+        let annotated = function.return_type.0.is_some();
+        if let Some(t) = &function.return_type.0 {
+            let ty = self.convert(t, function.span);
+            let origin = first.map(|f| f.span);
+            self.expect(&ret, &ty, function.span, origin);
+        }
+
+        match &function.body {
+            None => {}
+            Some(Body::Expr(expr)) => {
+                let ty = self.infer(expr);
+                let origin = match first.and_then(|f| f.body.as_ref()) {
+                    Some(Body::Expr(e)) => Some(e.span()),
+                    _ if annotated => Some(function.span),
+                    _ => None,
+                };
+                self.expect(&ret, &ty, expr.span(), origin);
+            }
+            Some(Body::Block(lines)) => {
+                self.returns.push(Return {
+                    ty: ret.clone(),
+                    origin: annotated.then_some(function.span),
+                    seen: false,
+                });
+                self.block(lines);
+                let returned = self.returns.pop().is_some_and(|r| r.seen);
+                if !returned {
+                    let ret = self.zonk(&ret);
+                    if !annotated {
+                        self.expect(&ret, &Type::unit(), function.span, None);
+                    } else if ret != Type::unit() && !matches!(ret, Type::Var(_)) {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!(
+                                "this `proc` declares the return type `{}` but never returns",
+                                ret
+                            ),
+                            function.span,
+                        ));
+                    }
+                }
+            }
+        }
+
+        self.type_scopes.pop();
+        self.scoped.truncate(scope_depth);
+    }
+
+    fn block(&mut self, lines: &[Line]) {
+        let scope_depth = self.scoped.len();
+        for line in lines {
+            self.line(line);
+        }
+        self.scoped.truncate(scope_depth);
+    }
+
+    fn line(&mut self, line: &Line) {
+        match line {
+            Line::Initialization(init) => self.local(init),
+            Line::Statement(Statement {
+                proc_name,
+                args,
+                span,
+            }) => {
+                self.call(proc_name, args, *span);
+            }
+            Line::MethodCall(expr) => {
+                self.infer(expr);
+            }
+            Line::Assignment(assignment) => {
+                let target = self.infer(&assignment.target);
+                let value = self.infer(&assignment.value);
+                if let Some(class) = assignment.binary_operator().and_then(builtin_constraint) {
+                    self.want(class, target.clone(), assignment.span);
+                }
+                let origin = Some(assignment.target.span());
+                self.expect(&target, &value, assignment.value.span(), origin);
+            }
+            Line::Return(expr) => {
+                let ty = self.infer(expr);
+                let Some(ret) = self.returns.last_mut() else {
+                    return;
+                };
+                ret.seen = true;
+                let (expected, origin) = (ret.ty.clone(), ret.origin);
+                self.expect(&expected, &ty, expr.span(), origin);
+            }
+            Line::For(ForStatement(names, iterable, body, span)) => {
+                let ty = self.infer(iterable);
+                let element = self.element_type(&ty, iterable.span());
+                let bound = match (names.len(), self.zonk(&ty), self.zonk(&element)) {
+                    (1, _, element) => vec![element],
+                    (2, Type::Map(k, v), _) => vec![*k, *v],
+                    (n, _, Type::Tuple(elements)) if elements.len() == n => elements,
+                    (n, _, element) => {
+                        let elements: Vec<Type> = (0..n).map(|_| self.fresh()).collect();
+                        self.expect(
+                            &Type::Tuple(elements.clone()),
+                            &element,
+                            iterable.span(),
+                            None,
+                        );
+                        elements
+                    }
+                };
+                let scope_depth = self.scoped.len();
+                for (name, ty) in names.iter().zip(bound) {
+                    self.bind(*span, &name.0, ty);
+                }
+                self.block(body);
+                self.scoped.truncate(scope_depth);
+            }
+            Line::While(conditional) | Line::If(conditional) => {
+                let Conditional(condition, then, otherwise, _) = conditional;
+                let ty = self.infer(condition);
+                self.expect(&Type::bool(), &ty, condition.span(), None);
+                self.block(then);
+                if let Some(otherwise) = otherwise {
+                    self.block(otherwise);
+                }
+            }
+            Line::Break(_) | Line::Continue(_) => {}
+        }
+    }
+
+    /// The type of the elements a `for` loop visits in a value of type `ty`.
+    fn element_type(&mut self, ty: &Type, span: Span) -> Type {
+        match self.zonk(ty) {
+            Type::List(element) | Type::Set(element) => *element,
+            Type::Map(key, value) => Type::Tuple(vec![*key, *value]),
+            Type::Named(name, _) if name == "string" => Type::string(),
+            Type::Var(_) => {
+                let element = self.fresh();
+                self.expect(ty, &Type::List(Box::new(element.clone())), span, None);
+                element
+            }
+            other => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("cannot iterate over a value of type `{}`", other),
+                    span,
+                ));
+                self.fresh()
+            }
+        }
+    }
+
+    /// A declaration inside a `proc` block. Local functions and `const`s are
+    /// generalized like module-level ones; a `var` stays monomorphic.
+    fn local(&mut self, init: &Initialization) {
+        let annotated = init
+            .type_annotation
+            .0
+            .as_ref()
+            .map(|t| self.convert(t, init.span));
+        let ty = match &init.value {
+            Some(Value::Function(function)) => {
+                let params = function.params.iter().map(|_| self.fresh()).collect();
+                let ty = Type::Function(params, Box::new(self.fresh()));
+                self.bind(init.span, &init.name.0, ty.clone());
+                let generics = self.declare_generics(&function.type_params);
+                self.function(function, &ty, &Context::Module, None, &generics);
+                ty
+            }
+            Some(Value::Expr(expr)) => {
+                let ty = self.infer(expr);
+                if let Some(annotated) = &annotated {
+                    self.expect(annotated, &ty, expr.span(), Some(init.span));
+                }
+                ty
+            }
+            None if matches!(init.domain, Domain::Var | Domain::Const) => {
+                annotated.clone().unwrap_or_else(|| self.fresh())
+            }
+            _ => return,
+        };
+        if let (Some(annotated), Some(Value::Function(_))) = (&annotated, &init.value) {
+            self.expect(annotated, &ty, init.span, None);
+        }
+
+        let Some(id) = self.resolution.declaration(init.span, &init.name.0) else {
+            return;
+        };
+        if init.domain == Domain::Var {
+            self.bind(init.span, &init.name.0, ty);
+        } else {
+            self.scoped.retain(|scoped| *scoped != id);
+            let restricted = matches!(init.value, Some(Value::Expr(_)));
+            let scheme = self.generalize(&ty, restricted);
+            self.env.insert(id, scheme);
+        }
+    }
+
+    /// Gives the name declared at `span` a monomorphic type.
+    fn bind(&mut self, span: Span, name: &str, ty: Type) {
+        if let Some(id) = self.resolution.declaration(span, name) {
+            self.env.insert(id, Scheme::mono(ty));
+            self.scoped.push(id);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, span: Span) -> Type {
+        match pattern {
+            Pattern::Wildcard => self.fresh(),
+            Pattern::Binding(name) => {
+                let ty = self.fresh();
+                self.bind(span, &name.0, ty.clone());
+                ty
+            }
+            Pattern::Literal(literal) => {
+                let ty = self.literal(literal, span);
+                if matches!(literal, Literal::Int(_) | Literal::Float(_)) {
+                    self.want("@eq", ty.clone(), span);
+                }
+                ty
+            }
+            Pattern::Tuple(patterns) => {
+                Type::Tuple(patterns.iter().map(|p| self.pattern(p, span)).collect())
+            }
+            Pattern::Constructor(name, patterns) => {
+                let Some(scheme) = self.variants.get(&name.0).cloned() else {
+                    return self.fresh();
+                };
+                let ty = self.instantiate(&scheme, span);
+                let (params, result) = match ty {
+                    Type::Function(params, result) => (params, *result),
+                    other => (Vec::new(), other),
+                };
+                if params.len() != patterns.len() {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}` has {} fields, but the pattern has {}",
+                            name.0,
+                            params.len(),
+                            patterns.len()
+                        ),
+                        span,
+                    ));
+                }
+                for (pattern, param) in patterns.iter().zip(&params) {
+                    let ty = self.pattern(pattern, span);
+                    self.expect(param, &ty, span, None);
+                }
+                result
+            }
+        }
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Sequence(exprs, _) => Type::Tuple(exprs.iter().map(|e| self.infer(e)).collect()),
+            Expr::Call(name, args, span) => self.call(name, args, *span),
+            Expr::Literal(literal, span) => self.literal(literal, *span),
+            Expr::Reference(name, span) => match self.value_definition(name, *span) {
+                Some(id) => self.instantiate_definition(id, *span),
+                None => self.fresh(),
+            },
+            Expr::FieldAccess(receiver, field, span) => {
+                let receiver = self.infer(receiver);
+                self.field(&receiver, &field.field_name, *span)
+            }
+            Expr::Grouping(TokenType::Dot, inner, _) => match inner.as_ref() {
+                Expr::Call(name, args, span) if !args.is_empty() => {
+                    self.method_call(name, args, *span)
+                }
+                other => self.infer(other),
+            },
+            Expr::Grouping(_, inner, _) => self.infer(inner),
+            Expr::Match(value, arms, _) => {
+                let scrutinee = self.infer(value);
+                let result = self.fresh();
+                for (i, arm) in arms.iter().enumerate() {
+                    let ty = self.pattern(&arm.pattern, arm.span);
+                    self.expect(&scrutinee, &ty, arm.span, Some(value.span()));
+                    if let Some(guard) = &arm.guard {
+                        let ty = self.infer(guard);
+                        self.expect(&Type::bool(), &ty, guard.span(), None);
+                    }
+                    let ty = self.infer(&arm.body);
+                    let origin = (i > 0).then(|| arms[0].body.span());
+                    self.expect(&result, &ty, arm.body.span(), origin);
+                }
+                result
+            }
+        }
+    }
+
+    /// Whether `expr` is a bare reference to a struct or enum, as in `ArrayList.init()`.
+    fn names_type(&self, expr: &Expr) -> bool {
+        let Expr::Reference(_, span) = expr else {
+            return false;
+        };
+        self.resolution
+            .references
+            .get(span)
+            .is_some_and(|id| is_type_kind(self.resolution.definition(*id).kind))
+    }
+
+    /// The definition a value reference resolved to, reporting uses of type names as values.
+    fn value_definition(&mut self, name: &Name, span: Span) -> Option<DefId> {
+        let id = *self.resolution.references.get(&span)?;
+        let definition = self.resolution.definition(id);
+        if is_type_kind(definition.kind) {
+            let mut diagnostic = Diagnostic::error(
+                format!(
+                    "`{}` is a {}, not a value",
+                    name.0,
+                    definition.kind.describe()
+                ),
+                span,
+            );
+            if let Some(defined) = definition.span {
+                diagnostic = diagnostic.with_note(defined, format!("`{}` is defined here", name.0));
+            }
+            self.diagnostics.push(diagnostic);
+            return None;
+        }
+        Some(id)
+    }
+
+    /// A typeclass method called by name runs the method of its first
+    /// argument's instance, so that argument has to be the `Self` of it.
+    fn class_call(&mut self, name: &Name, def: DefId, span: Span) {
+        let Some(scheme) = self.signatures.get(&def) else {
+            return;
+        };
+        let first = match &scheme.ty {
+            Type::Function(params, _) => params.first(),
+            _ => None,
+        };
+        if first.is_some_and(|first| scheme.constraints.first().is_some_and(|c| c.ty == *first)) {
+            return;
+        }
+        self.diagnostics.push(
+            Diagnostic::error(
+                format!(
+                    "`{}` cannot be called by name, as its first parameter is not `Self`",
+                    name.0
+                ),
+                span,
+            )
+            .with_help(format!("call it on the type, as in `Type.{}(..)`", name.0)),
+        );
+    }
+
+    /// A fresh instance of a definition's type. A use of a function with
+    /// declared type parameters is recorded with what they are here.
+    fn instantiate_definition(&mut self, id: DefId, span: Span) -> Type {
+        if let Some(scheme) = self.env.get(&id).cloned() {
+            let (ty, map) = self.instantiate_with(&scheme, span);
+            if let Some(generics) = self.generics.get(&id) {
+                let args = generics
+                    .iter()
+                    .map(|g| self.zonk(&g.ty).substitute(&map))
+                    .collect();
+                self.sites.push(Site {
+                    span,
+                    caller: self.current,
+                    callee: id,
+                    args,
+                });
+            }
+            return ty;
+        }
+        let definition = self.resolution.definition(id);
+        if matches!(definition.kind, DefKind::BuiltinFn | DefKind::BuiltinProc) {
+            let a = self.fresh_var();
+            let ty = builtin_type(&definition.name, Type::Var(a));
+            let constraints = builtin_constraint(&definition.name)
+                .map(|class| Constraint::new(class, Type::Var(a)))
+                .into_iter()
+                .collect();
+            let scheme = Scheme {
+                vars: vec![a],
+                constraints,
+                ty,
+            };
+            self.env.insert(id, scheme.clone());
+            return self.instantiate(&scheme, span);
+        }
+        self.fresh()
+    }
+
+    fn call(&mut self, name: &Name, args: &[Expr], span: Span) -> Type {
+        let callee = match self.value_definition(name, span) {
+            Some(id) => {
+                if self.resolution.class_methods.contains(&id) {
+                    self.class_call(name, id, span);
+                }
+                self.instantiate_definition(id, span)
+            }
+            None => self.fresh(),
+        };
+        // The declared type parameters of a generic callee, by the variable
+        // they were instantiated to here.
+        let declared: Vec<(String, Type)> = match self.sites.last() {
+            Some(site) if site.span == span => self.generics[&site.callee]
+                .iter()
+                .map(|g| g.name.clone())
+                .zip(site.args.iter().cloned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let arg_types: Vec<Type> = args.iter().map(|arg| self.infer(arg)).collect();
+
+        match self.unfold(&callee) {
+            Type::Function(params, ret) => {
+                if params.len() != args.len() {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}` takes {} arguments, but {} were given",
+                            name.0,
+                            params.len(),
+                            args.len()
+                        ),
+                        span,
+                    ));
+                } else {
+                    let mut decided: Vec<(&str, Span)> = Vec::new();
+                    for ((param, arg), ty) in params.iter().zip(args).zip(&arg_types) {
+                        let Some((generic, _)) = declared.iter().find(|(_, t)| t == param) else {
+                            self.expect(param, ty, arg.span(), None);
+                            continue;
+                        };
+                        let before = self.zonk(param);
+                        if self.unify(param, ty).is_ok() {
+                            decided.push((generic, arg.span()));
+                            continue;
+                        }
+                        let mut diagnostic = Diagnostic::error(
+                            format!(
+                                "the type parameter `{}` of `{}` cannot be both `{}` and `{}`",
+                                generic,
+                                name.0,
+                                before,
+                                self.zonk(ty)
+                            ),
+                            arg.span(),
+                        );
+                        if let Some((_, first)) = decided.iter().find(|(g, _)| g == generic) {
+                            diagnostic = diagnostic.with_note(
+                                *first,
+                                format!("`{}` is `{}` because of this", generic, before),
+                            );
+                        }
+                        self.diagnostics.push(diagnostic);
+                    }
+                }
+                *ret
+            }
+            Type::Var(_) => {
+                let ret = self.fresh();
+                let ty = Type::Function(arg_types, Box::new(ret.clone()));
+                self.expect(&callee, &ty, span, None);
+                ret
+            }
+            other => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("`{}` has type `{}` and cannot be called", name.0, other),
+                    span,
+                ));
+                self.fresh()
+            }
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal, span: Span) -> Type {
+        match literal {
+            Literal::Null => self.fresh(),
+            Literal::Void => Type::unit(),
+            Literal::Int(_) => {
+                let ty = self.fresh();
+                self.want("@num", ty.clone(), span);
+                ty
+            }
+            Literal::Float(_) => {
+                let ty = self.fresh();
+                self.want("@real", ty.clone(), span);
+                ty
+            }
+            Literal::Bool(_) => Type::bool(),
+            Literal::String(_) => Type::string(),
+            Literal::List(elements) => Type::List(Box::new(self.elements(elements))),
+            Literal::Set(elements) => Type::Set(Box::new(self.elements(elements))),
+            Literal::Map(entries) => {
+                let keys: Vec<Expr> = entries.iter().map(|(k, _)| k.clone()).collect();
+                let values: Vec<Expr> = entries.iter().map(|(_, v)| v.clone()).collect();
+                Type::Map(
+                    Box::new(self.elements(&keys)),
+                    Box::new(self.elements(&values)),
+                )
+            }
+            Literal::Tuple(elements) => {
+                Type::Tuple(elements.iter().map(|e| self.infer(e)).collect())
+            }
+            Literal::StructInitialization(name, inits, base) => {
+                self.struct_literal(name, inits, base.as_deref(), span)
+            }
+            Literal::Closure(value) => match value.as_ref() {
+                Value::Function(function) => {
+                    let params = function.params.iter().map(|_| self.fresh()).collect();
+                    let ty = Type::Function(params, Box::new(self.fresh()));
+                    let generics = self.declare_generics(&function.type_params);
+                    self.function(function, &ty, &Context::Module, None, &generics);
+                    ty
+                }
+                _ => {
+                    self.diagnostics.push(Diagnostic::error(
+                        "only functions can be closures".to_string(),
+                        span,
+                    ));
+                    self.fresh()
+                }
+            },
+        }
+    }
+
+    /// The common type of the elements of a collection literal.
+    fn elements(&mut self, elements: &[Expr]) -> Type {
+        let element = self.fresh();
+        let mut origin = None;
+        for e in elements {
+            let ty = self.infer(e);
+            self.expect(&element, &ty, e.span(), origin);
+            origin = origin.or(Some(e.span()));
+        }
+        element
+    }
+
+    fn field(&mut self, receiver: &Type, field: &str, span: Span) -> Type {
+        match self.field_type(receiver, field, span) {
+            Some(ty) => ty,
+            None => {
+                let result = self.fresh();
+                self.deferred.push(Deferred {
+                    receiver: receiver.clone(),
+                    member: Member::Field(field.to_string()),
+                    result: result.clone(),
+                    span,
+                });
+                result
+            }
+        }
+    }
+
+    /// `receiver.name(args)`, or `Type.name(args)` when the receiver names
+    /// a struct or enum: a function of the type, called with `self` passed
+    /// explicitly if it takes one, or `init`, which builds a struct from
+    /// its fields in order.
+    fn method_call(&mut self, name: &Name, args: &[Expr], span: Span) -> Type {
+        if let Some(Expr::Reference(owner, _)) = args.first().filter(|a| self.names_type(a)) {
+            return self.static_call(&owner.0, &name.0, &args[1..], span);
+        }
+        let receiver = self.infer(&args[0]);
+        let rest = args[1..]
+            .iter()
+            .map(|arg| (self.infer(arg), arg.span()))
+            .collect();
+        let member = Member::Method(name.0.clone(), rest);
+        match self.method_type(&receiver, &member, span) {
+            Some(ty) => ty,
+            None => {
+                let result = self.fresh();
+                self.deferred.push(Deferred {
+                    receiver,
+                    member,
+                    result: result.clone(),
+                    span,
+                });
+                result
+            }
+        }
+    }
+
+    /// A struct's type over fresh type variables, with its fields' types.
+    /// Building one at `span` needs the bounds of its type parameters.
+    fn instantiate_struct(&mut self, owner: &str, span: Span) -> (Type, Vec<(String, Type)>) {
+        let params: Vec<TypeVar> = self.structs[owner]
+            .params
+            .iter()
+            .map(|(_, var)| *var)
+            .collect();
+        let copies: HashMap<TypeVar, Type> =
+            params.iter().map(|var| (*var, self.fresh())).collect();
+        let info = &self.structs[owner];
+        let fields = info
+            .fields
+            .iter()
+            .map(|(name, ty)| (name.clone(), ty.substitute(&copies)))
+            .collect();
+        for var in &params {
+            for class in self.bounds.get(var).cloned().unwrap_or_default() {
+                self.want(&class, copies[var].clone(), span);
+            }
+        }
+        let args = params.iter().map(|var| copies[var].clone()).collect();
+        (Type::Named(owner.to_string(), args), fields)
+    }
+
+    /// `Name { field: value, ..base }`: every field of the struct is given
+    /// once, or copied from `base`, and no other field is.
+    fn struct_literal(
+        &mut self,
+        name: &Name,
+        inits: &[FieldInit],
+        base: Option<&Expr>,
+        span: Span,
+    ) -> Type {
+        let base_type = base.map(|base| (self.infer(base), base.span()));
+        let values: Vec<Type> = inits.iter().map(|init| self.infer(&init.value)).collect();
+        if !self.structs.contains_key(&name.0) {
+            self.diagnostics.push(Diagnostic::error(
+                format!("`{}` is not a struct", name.0),
+                span,
+            ));
+            return self.fresh();
+        }
+        let (ty, fields) = self.instantiate_struct(&name.0, span);
+        if let Some((base_type, base_span)) = &base_type {
+            self.expect(&ty, base_type, *base_span, None);
+        }
+        let mut given: HashMap<&str, Span> = HashMap::new();
+        for (init, value) in inits.iter().zip(&values) {
+            if let Some(&first) = given.get(init.name.0.as_str()) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("the field `{}` is given more than once", init.name.0),
+                        init.span,
+                    )
+                    .with_note(first, "it is first given here".to_string()),
+                );
+                continue;
+            }
+            given.insert(&init.name.0, init.span);
+            match fields.iter().find(|(field, _)| *field == init.name.0) {
+                Some((_, field)) => self.expect(field, value, init.value.span(), None),
+                None => {
+                    let mut error = Diagnostic::error(
+                        format!("`{}` has no field `{}`", name.0, init.name.0),
+                        init.span,
+                    );
+                    if let Some(declared) = self.structs.get(&name.0).map(|info| info.span) {
+                        error = error.with_note(declared, format!("`{}` is declared here", name.0));
+                    }
+                    self.diagnostics.push(error);
+                }
+            }
+        }
+        let missing: Vec<String> = fields
+            .iter()
+            .filter(|(field, _)| !given.contains_key(field.as_str()))
+            .map(|(field, _)| format!("`{}`", field))
+            .collect();
+        if base.is_none() && !missing.is_empty() {
+            let message = match missing.as_slice() {
+                [field] => format!("this `{}` is missing its field {}", name.0, field),
+                _ => format!(
+                    "this `{}` is missing its fields {}",
+                    name.0,
+                    missing.join(", ")
+                ),
+            };
+            self.diagnostics
+                .push(Diagnostic::error(message, span).with_help(format!(
+                    "give every field a value, or copy the rest from another `{}` with `..other`",
+                    name.0
+                )));
+        }
+        ty
+    }
+
+    fn static_call(&mut self, owner: &str, name: &str, args: &[Expr], span: Span) -> Type {
+        let arg_types: Vec<(Type, Span)> = args
+            .iter()
+            .map(|arg| (self.infer(arg), arg.span()))
+            .collect();
+        let candidates: Vec<usize> = (0..self.methods.len())
+            .filter(|&i| self.methods[i].name == name)
+            .filter(|&i| matches!(&self.methods[i].head, Type::Named(n, _) if n == owner))
+            .collect();
+        match candidates.as_slice() {
+            [] if name == "init" && self.structs.contains_key(owner) => {
+                let (ty, fields) = self.instantiate_struct(owner, span);
+                if fields.len() != args.len() {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}.init` takes {} fields, but {} were given",
+                            owner,
+                            fields.len(),
+                            args.len()
+                        ),
+                        span,
+                    ));
+                } else {
+                    for ((_, field), (arg, arg_span)) in fields.iter().zip(&arg_types) {
+                        self.expect(field, arg, *arg_span, None);
+                    }
+                }
+                ty
+            }
+            [] => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("`{}` has no function `{}`", owner, name),
+                    span,
+                ));
+                self.fresh()
+            }
+            [index] => {
+                let def = self.methods[*index].def;
+                let what = format!("`{}.{}`", owner, name);
+                self.apply(def, &what, arg_types, span)
+            }
+            _ => {
+                let what = format!("`{}` has more than one function `{}`", owner, name);
+                self.ambiguous_method(what, &candidates, span);
+                self.fresh()
+            }
+        }
+    }
+
+    /// The result of calling a method on `receiver`, or `None` while the
+    /// receiver's type is unknown.
+    fn method_type(&mut self, receiver: &Type, member: &Member, span: Span) -> Option<Type> {
+        let Member::Method(name, args) = member else {
+            return None;
+        };
+        let receiver = self.zonk(receiver);
+        if let Type::Var(_) = receiver {
+            return self.class_method(&receiver, name, args, span);
+        }
+        let candidates: Vec<usize> = (0..self.methods.len())
+            .filter(|&i| &self.methods[i].name == name)
+            .filter(|&i| overlap(&self.methods[i].head, &receiver))
+            .collect();
+        match candidates.as_slice() {
+            [] => {
+                let mut error = Diagnostic::error(
+                    format!("a value of type `{}` has no method `{}`", receiver, name),
+                    span,
+                );
+                if let Type::Named(owner, _) = &receiver {
+                    let is_field = self
+                        .structs
+                        .get(owner)
+                        .is_some_and(|info| info.fields.iter().any(|(f, _)| f == name));
+                    if is_field {
+                        error = error.with_help(format!(
+                            "`{}` is a field; bind it to a name to call the function it holds",
+                            name
+                        ));
+                    }
+                }
+                self.diagnostics.push(error);
+                Some(self.fresh())
+            }
+            [index] if !self.methods[*index].takes_self => {
+                let method = &self.methods[*index];
+                let owner = match &method.head {
+                    Type::Named(owner, _) => owner.clone(),
+                    other => other.to_string(),
+                };
+                let error = Diagnostic::error(
+                    format!("`{}` takes no `self`, so it is not a method", name),
+                    span,
+                )
+                .with_note(method.span, format!("`{}` is defined here", name))
+                .with_help(format!("call it as `{}.{}(..)`", owner, name));
+                self.diagnostics.push(error);
+                Some(self.fresh())
+            }
+            [index] => {
+                let def = self.methods[*index].def;
+                let mut arg_types = vec![(receiver, span)];
+                arg_types.extend(args.iter().cloned());
+                let what = format!("`{}`", name);
+                Some(self.apply(def, &what, arg_types, span))
+            }
+            _ => {
+                let what = format!("`{}` has more than one method `{}`", receiver, name);
+                self.ambiguous_method(what, &candidates, span);
+                Some(self.fresh())
+            }
+        }
+    }
+
+    /// Types `self.name(args)` in a default method of a typeclass, where
+    /// `self` is the class's `Self` and `name` one of the class's methods.
+    fn class_method(
+        &mut self,
+        receiver: &Type,
+        name: &str,
+        args: &[(Type, Span)],
+        span: Span,
+    ) -> Option<Type> {
+        let this = self
+            .type_scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get("Self"))
+            .cloned()?;
+        if self.zonk(&this) != *receiver {
+            return None;
+        }
+        let mut declared = self
+            .classes
+            .values()
+            .flat_map(|class| &class.methods)
+            .filter(|(method, ..)| method == name);
+        let (_, def, ..) = declared.next()?;
+        let def = *def;
+        if declared.next().is_some() {
+            return None;
+        }
+        let mut arg_types = vec![(receiver.clone(), span)];
+        arg_types.extend(args.iter().cloned());
+        let what = format!("`{}`", name);
+        Some(self.apply(def, &what, arg_types, span))
+    }
+
+    /// Calls the method `def` with arguments of these types.
+    fn apply(&mut self, def: DefId, what: &str, args: Vec<(Type, Span)>, span: Span) -> Type {
+        self.method_calls.insert(span, def);
+        let callee = self.instantiate_definition(def, span);
+        match self.unfold(&callee) {
+            Type::Function(params, ret) => {
+                if params.len() != args.len() {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "{} takes {} arguments, but {} were given",
+                            what,
+                            params.len(),
+                            args.len()
+                        ),
+                        span,
+                    ));
+                } else {
+                    for (param, (arg, arg_span)) in params.iter().zip(&args) {
+                        self.expect(param, arg, *arg_span, None);
+                    }
+                }
+                *ret
+            }
+            _ => {
+                let ret = self.fresh();
+                let params = args.into_iter().map(|(ty, _)| ty).collect();
+                let ty = Type::Function(params, Box::new(ret.clone()));
+                self.expect(&callee, &ty, span, None);
+                ret
+            }
+        }
+    }
+
+    fn ambiguous_method(&mut self, message: String, candidates: &[usize], span: Span) {
+        let mut error = Diagnostic::error(message, span);
+        for &index in candidates {
+            let method = &self.methods[index];
+            let owner = match (&method.class, &method.head) {
+                (Some(class), _) => format!("`{}`", class),
+                (None, head) => format!("`{}`", head),
+            };
+            error = error.with_note(method.span, format!("{} defines one here", owner));
+        }
+        self.diagnostics
+            .push(error.with_help("rename all but one of them".to_string()));
+    }
+
+    /// The type of `receiver.field`, or `None` while the receiver's type is unknown.
+    fn field_type(&mut self, receiver: &Type, field: &str, span: Span) -> Option<Type> {
+        let receiver = self.zonk(receiver);
+        match &receiver {
+            Type::Var(_) => None,
+            Type::Named(name, args) if self.structs.contains_key(name) => {
+                let info = &self.structs[name];
+                let Some((_, ty)) = info.fields.iter().find(|(f, _)| f == field) else {
+                    let defined = info.span;
+                    self.diagnostics.push(
+                        Diagnostic::error(format!("`{}` has no field `{}`", name, field), span)
+                            .with_note(defined, format!("`{}` is defined here", name)),
+                    );
+                    return Some(self.fresh());
+                };
+                let map = info
+                    .params
+                    .iter()
+                    .map(|(_, var)| *var)
+                    .zip(args.iter().cloned())
+                    .collect();
+                Some(ty.substitute(&map))
+            }
+            Type::Tuple(elements) if field.parse::<usize>().is_ok_and(|i| i < elements.len()) => {
+                Some(elements[field.parse::<usize>().unwrap_or(0)].clone())
+            }
+            other => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("a value of type `{}` has no field `{}`", other, field),
+                    span,
+                ));
+                Some(self.fresh())
+            }
+        }
+    }
+
+    /// Retries field accesses and method calls whose receivers were
+    /// unknown. With `last` set, any that are still unknown are reported.
+    fn solve_deferred(&mut self, last: bool) {
+        loop {
+            let pending = std::mem::take(&mut self.deferred);
+            let count = pending.len();
+            for d in pending {
+                let ty = match &d.member {
+                    Member::Field(field) => self.field_type(&d.receiver, field, d.span),
+                    method => self.method_type(&d.receiver, method, d.span),
+                };
+                match ty {
+                    Some(ty) => self.expect(&d.result, &ty, d.span, None),
+                    None => self.deferred.push(d),
+                }
+            }
+            if self.deferred.len() == count {
+                break;
+            }
+        }
+        if last {
+            for d in std::mem::take(&mut self.deferred) {
+                let message = match &d.member {
+                    Member::Field(field) => format!(
+                        "the type of this value must be known to access its field `{}`",
+                        field
+                    ),
+                    Member::Method(name, _) => format!(
+                        "the type of this value must be known to call its method `{}`",
+                        name
+                    ),
+                };
+                self.diagnostics.push(
+                    Diagnostic::error(message, d.span)
+                        .with_help("add a type annotation".to_string()),
+                );
+            }
+        }
+    }
+
+    /// Turns a written type into a checker type. Type expressions have no
+    /// spans of their own, so errors point at the enclosing declaration.
+    fn convert(&mut self, type_expr: &TypeExpr, span: Span) -> Type {
+        match type_expr {
+            TypeExpr::Literal(name) => self.named_type(&name.0, Vec::new(), span),
+            TypeExpr::Grouping => Type::unit(),
+            TypeExpr::Operator(token, operands) => match (&token.token_type, operands.as_slice()) {
+                (TokenType::LBracket, [element]) => {
+                    Type::List(Box::new(self.convert(element, span)))
+                }
+                (TokenType::LBrace, [element]) => Type::Set(Box::new(self.convert(element, span))),
+                (TokenType::LBrace, [key, value]) => Type::Map(
+                    Box::new(self.convert(key, span)),
+                    Box::new(self.convert(value, span)),
+                ),
+                (TokenType::LParen, elements) => {
+                    Type::Tuple(elements.iter().map(|e| self.convert(e, span)).collect())
+                }
+                (TokenType::LT, [TypeExpr::Literal(base), args @ ..]) => {
+                    let args = args.iter().map(|a| self.convert(a, span)).collect();
+                    self.named_type(&base.0, args, span)
+                }
+                (TokenType::Arrow, [params, ret]) => {
+                    let params = match params {
+                        TypeExpr::Grouping => Vec::new(),
+                        TypeExpr::Operator(token, elements)
+                            if token.token_type == TokenType::LParen =>
+                        {
+                            elements.iter().map(|e| self.convert(e, span)).collect()
+                        }
+                        single => vec![self.convert(single, span)],
+                    };
+                    Type::Function(params, Box::new(self.convert(ret, span)))
+                }
+                _ => {
+                    self.diagnostics.push(Diagnostic::error(
+                        "this type cannot be understood".to_string(),
+                        span,
+                    ));
+                    self.fresh()
+                }
+            },
+        }
+    }
+
+    fn named_type(&mut self, name: &str, args: Vec<Type>, span: Span) -> Type {
+        if let Some(ty) = self
+            .type_scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+        {
+            return ty.clone();
+        }
+        // A typeclass used as a type stands for some type with an instance of it.
+        if name.starts_with('@') {
+            let ty = self.fresh();
+            self.want(name, ty.clone(), span);
+            return ty;
+        }
+        let alias = self.aliases.get(name).cloned();
+        let expected = match (self.structs.get(name), self.enums.get(name), &alias) {
+            (Some(info), _, _) => info.params.len(),
+            (None, Some(params), _) => params.len(),
+            (None, None, Some(alias)) => alias.params.len(),
+            (None, None, None) if BUILTIN_TYPES.contains(&name) => 0,
+            (None, None, None) => return self.fresh(),
+        };
+        if args.is_empty() {
+            let args = (0..expected).map(|_| self.fresh()).collect();
+            return match alias {
+                Some(alias) => self.expand(name, alias, args, span),
+                None => Type::Named(name.to_string(), args),
+            };
+        }
+        if args.len() != expected {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "`{}` takes {} type arguments, but {} were given",
+                    name,
+                    expected,
+                    args.len()
+                ),
+                span,
+            ));
+            return self.fresh();
+        }
+        match alias {
+            Some(alias) => self.expand(name, alias, args, span),
+            None => Type::Named(name.to_string(), args),
+        }
+    }
+
+    /// The type an alias stands for with `args`, remembering the alias so
+    /// that it is shown as written. Only the alias's own parameters are in
+    /// scope in its target.
+    fn expand(&mut self, name: &str, alias: AliasInfo, args: Vec<Type>, span: Span) -> Type {
+        // A cyclic alias was reported when it was declared.
+        if self.expanding.iter().any(|n| n == name) {
+            return self.fresh();
+        }
+        let scope = alias.params.into_iter().zip(args.iter().cloned()).collect();
+        let outer = std::mem::replace(&mut self.type_scopes, vec![scope]);
+        self.expanding.push(name.to_string());
+        let target = self.convert(&alias.target, span);
+        self.expanding.pop();
+        self.type_scopes = outer;
+        Type::Alias(name.to_string(), args, Box::new(target))
+    }
+
+    /// Unifies `actual` with `expected`, reporting a mismatch at `span`.
+    /// `origin` is where the expectation came from, if it was written down.
+    fn expect(&mut self, expected: &Type, actual: &Type, span: Span, origin: Option<Span>) {
+        let Err(error) = self.unify(expected, actual) else {
+            return;
+        };
+        let expected = self.written(expected);
+        let actual = self.written(actual);
+        let message = match error {
+            UnifyError::Mismatch => {
+                format!(
+                    "mismatched types: expected `{}`, found `{}`",
+                    expected, actual
+                )
+            }
+            UnifyError::Infinite => {
+                format!(
+                    "`{}` would have to contain itself as `{}`",
+                    expected, actual
+                )
+            }
+        };
+        let mut diagnostic = Diagnostic::error(message, span);
+        if let Some(origin) = origin {
+            diagnostic =
+                diagnostic.with_note(origin, format!("expected `{}` because of this", expected));
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), UnifyError> {
+        let (written_a, written_b) = (a, b);
+        let a = self.shallow(a);
+        let b = self.shallow(b);
+        match (&a, &b) {
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Var(v), _) | (_, Type::Var(v)) => {
+                // Bind to the type as written, aliases and all.
+                let other = match a {
+                    Type::Var(x) if x == *v => written_b,
+                    _ => written_a,
+                };
+                let other = self.written(other);
+                let mut vars = Vec::new();
+                other.free_vars(&mut vars);
+                if vars.contains(v) {
+                    return Err(UnifyError::Infinite);
+                }
+                self.bindings[*v as usize] = Some(other);
+                Ok(())
+            }
+            (Type::Named(n1, a1), Type::Named(n2, a2)) if n1 == n2 && a1.len() == a2.len() => {
+                a1.iter().zip(a2).try_for_each(|(x, y)| self.unify(x, y))
+            }
+            (Type::List(x), Type::List(y)) | (Type::Set(x), Type::Set(y)) => self.unify(x, y),
+            (Type::Map(k1, v1), Type::Map(k2, v2)) => {
+                self.unify(k1, k2)?;
+                self.unify(v1, v2)
+            }
+            (Type::Tuple(x), Type::Tuple(y)) if x.len() == y.len() => {
+                x.iter().zip(y).try_for_each(|(x, y)| self.unify(x, y))
+            }
+            (Type::Function(p1, r1), Type::Function(p2, r2)) if p1.len() == p2.len() => {
+                p1.iter().zip(p2).try_for_each(|(x, y)| self.unify(x, y))?;
+                self.unify(r1, r2)
+            }
+            _ => Err(UnifyError::Mismatch),
+        }
+    }
+
+    /// Follows variable bindings and aliases until reaching a type
+    /// constructor or an unbound variable.
+    fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        loop {
+            ty = match ty {
+                Type::Var(v) => match &self.bindings[v as usize] {
+                    Some(bound) => bound.clone(),
+                    None => return Type::Var(v),
+                },
+                Type::Alias(_, _, target) => *target,
+                other => return other,
+            };
+        }
+    }
+
+    /// Applies every binding made so far, all the way down, and expands
+    /// every alias.
+    fn zonk(&self, ty: &Type) -> Type {
+        self.resolve(ty, false)
+    }
+
+    /// Like `zonk`, but keeps the aliases a type was written with, for
+    /// showing it.
+    fn written(&self, ty: &Type) -> Type {
+        self.resolve(ty, true)
+    }
+
+    /// The outermost type constructor of `ty`, with its parts as written.
+    fn unfold(&self, ty: &Type) -> Type {
+        self.shallow(&self.written(ty))
+    }
+
+    fn resolve(&self, ty: &Type, aliases: bool) -> Type {
+        let ty = match ty {
+            Type::Var(v) => match &self.bindings[*v as usize] {
+                Some(bound) => return self.resolve(bound, aliases),
+                None => return Type::Var(*v),
+            },
+            Type::Alias(name, args, target) if aliases => {
+                return Type::Alias(
+                    name.clone(),
+                    args.iter().map(|a| self.resolve(a, aliases)).collect(),
+                    Box::new(self.resolve(target, aliases)),
+                )
+            }
+            other => self.shallow(other),
+        };
+        match ty {
+            Type::Var(v) => Type::Var(v),
+            Type::Named(name, args) => Type::Named(
+                name,
+                args.iter().map(|a| self.resolve(a, aliases)).collect(),
+            ),
+            Type::List(t) => Type::List(Box::new(self.resolve(&t, aliases))),
+            Type::Set(t) => Type::Set(Box::new(self.resolve(&t, aliases))),
+            Type::Map(k, v) => Type::Map(
+                Box::new(self.resolve(&k, aliases)),
+                Box::new(self.resolve(&v, aliases)),
+            ),
+            Type::Tuple(elements) => {
+                Type::Tuple(elements.iter().map(|e| self.resolve(e, aliases)).collect())
+            }
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| self.resolve(p, aliases)).collect(),
+                Box::new(self.resolve(&ret, aliases)),
+            ),
+            Type::Alias(..) => unreachable!("shallow looks through aliases"),
+        }
+    }
+
+    /// Quantifies over the variables of `ty` that no monomorphic binding in
+    /// scope mentions, taking along the wanted constraints on them. With
+    /// `restricted`, constrained variables stay monomorphic instead.
+    fn generalize(&mut self, ty: &Type, restricted: bool) -> Scheme {
+        let ty = self.written(ty);
+        let mut fixed = Vec::new();
+        for id in &self.scoped {
+            if let Some(scheme) = self.env.get(id) {
+                self.zonk(&scheme.ty).free_vars(&mut fixed);
+            }
+        }
+        let mut vars = Vec::new();
+        ty.free_vars(&mut vars);
+        vars.retain(|v| !fixed.contains(v));
+
+        let wanted: Vec<Wanted> = std::mem::take(&mut self.wanted)
+            .into_iter()
+            .flat_map(|w| self.simplify(w))
+            .collect();
+        if restricted {
+            for w in &wanted {
+                let mut constrained = Vec::new();
+                w.constraint.ty.free_vars(&mut constrained);
+                vars.retain(|v| !constrained.contains(v));
+            }
+        }
+        let mut constraints = Vec::new();
+        for w in wanted {
+            let mut mentioned = Vec::new();
+            w.constraint.ty.free_vars(&mut mentioned);
+            if !mentioned.is_empty() && mentioned.iter().all(|v| vars.contains(v)) {
+                constraints.push(w.constraint);
+            } else {
+                self.wanted.push(w);
+            }
+        }
+        Scheme {
+            vars,
+            constraints: minimize(constraints),
+            ty,
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme, span: Span) -> Type {
+        self.instantiate_with(scheme, span).0
+    }
+
+    /// Instantiates `scheme`, also returning what each of its variables became.
+    fn instantiate_with(&mut self, scheme: &Scheme, span: Span) -> (Type, HashMap<TypeVar, Type>) {
+        let map = scheme.vars.iter().map(|v| (*v, self.fresh())).collect();
+        for constraint in &scheme.constraints {
+            let ty = self.zonk(&constraint.ty).substitute(&map);
+            self.want(&constraint.class, ty, span);
+        }
+        (self.written(&scheme.ty).substitute(&map), map)
+    }
+
+    fn want(&mut self, class: &str, ty: Type, span: Span) {
+        self.wanted.push(Wanted {
+            constraint: Constraint::new(class, ty),
+            span,
+        });
+    }
+
+    /// Replaces a constraint on a compound type by what its builtin instance
+    /// needs, as in `@eq [a]` becoming `@eq a`. A constraint that no instance
+    /// could ever satisfy is reported right away.
+    fn simplify(&mut self, wanted: Wanted) -> Vec<Wanted> {
+        let constraint =
+            Constraint::new(&wanted.constraint.class, self.zonk(&wanted.constraint.ty));
+        if matches!(constraint.ty, Type::Var(_)) {
+            return vec![Wanted {
+                constraint,
+                span: wanted.span,
+            }];
+        }
+        if let Some(needed) = builtin_instance(&constraint.class, &constraint.ty) {
+            return needed
+                .into_iter()
+                .flat_map(|c| {
+                    self.simplify(Wanted {
+                        constraint: c,
+                        span: wanted.span,
+                    })
+                })
+                .collect();
+        }
+        let declared = self
+            .instances
+            .iter()
+            .any(|i| i.class == constraint.class && overlap(&i.head, &constraint.ty));
+        if !declared {
+            let diagnostic = no_instance(&constraint, wanted.span);
+            self.unsolved(diagnostic, constraint, wanted.span);
+            return Vec::new();
+        }
+        vec![Wanted {
+            constraint,
+            span: wanted.span,
+        }]
+    }
+
+    /// Picks a type for variables that are constrained but were not
+    /// generalized: `float` when `@real` is wanted, otherwise `int` when only
+    /// builtin classes including `@num` are. Anything else is ambiguous.
+    fn default_constraints(&mut self) {
+        let wanted: Vec<Wanted> = std::mem::take(&mut self.wanted)
+            .into_iter()
+            .flat_map(|w| self.simplify(w))
+            .collect();
+        let mut groups: Vec<(TypeVar, Vec<Wanted>)> = Vec::new();
+        for w in wanted {
+            match w.constraint.ty {
+                Type::Var(v) => match groups.iter_mut().find(|(g, _)| *g == v) {
+                    Some((_, group)) => group.push(w),
+                    None => groups.push((v, vec![w])),
+                },
+                _ => self.wanted.push(w),
+            }
+        }
+        for (var, group) in groups {
+            let has = |class: &str| group.iter().any(|w| w.constraint.class == class);
+            let builtin = group
+                .iter()
+                .all(|w| BUILTIN_TYPECLASSES.contains(&w.constraint.class.as_str()));
+            let default = if !builtin {
+                None
+            } else if has("@real") {
+                Some(Type::float())
+            } else if has("@num") {
+                Some(Type::int())
+            } else {
+                None
+            };
+            match default {
+                Some(ty) => {
+                    self.bindings[var as usize] = Some(ty);
+                    self.wanted.extend(group);
+                }
+                None => self
+                    .diagnostics
+                    .push(ambiguous(&group[0].constraint, group[0].span)),
+            }
+        }
+    }
+
+    /// Checks that instance heads do not overlap each other or a builtin
+    /// instance, and that an instance of a class with a superclass comes
+    /// with an instance of the superclass.
+    fn check_instance_heads(&mut self) {
+        for (i, instance) in self.instances.iter().enumerate() {
+            if builtin_instance(&instance.class, &instance.head).is_some() {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "this instance of `{}` for `{}` overlaps the builtin one",
+                        instance.class, instance.head
+                    ),
+                    instance.span,
+                ));
+                continue;
+            }
+            if let Some(other) = self.instances[..i]
+                .iter()
+                .find(|o| o.class == instance.class && overlap(&o.head, &instance.head))
+            {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "overlapping instances of `{}` for `{}` and `{}`",
+                            instance.class, instance.head, other.head
+                        ),
+                        instance.span,
+                    )
+                    .with_note(other.span, "the other instance is here".to_string()),
+                );
+            }
+            for superclass in superclasses(&instance.class) {
+                let provided = builtin_instance(superclass, &instance.head).is_some()
+                    || self.instances.iter().any(|o| {
+                        o.class == superclass
+                            && match_type(&o.head, &instance.head, &params(o), &mut HashMap::new())
+                    });
+                if !provided {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}` for `{}` needs an instance of its superclass `{}`",
+                            instance.class, instance.head, superclass
+                        ),
+                        instance.span,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Checks an instance method against its class's declared signature with
+    /// `Self` replaced by the instance head. Whatever the method needs of the
+    /// head's type parameters becomes the instance's context.
+    fn check_instance_method(&mut self, index: usize, item: &ValueItem) {
+        let span = item.clauses[0].span;
+        let name = self.resolution.definition(item.def).name.clone();
+        let class_name = self.instances[index].class.clone();
+        let Some(class) = self.classes.get(&class_name) else {
+            // Builtin classes have no declared methods to check against; like
+            // the builtin instances for collections, a generic instance needs
+            // the class of its type parameters.
+            let instance = &mut self.instances[index];
+            instance.context = instance
+                .params
+                .iter()
+                .map(|(_, var)| Constraint::new(&class_name, Type::Var(*var)))
+                .collect();
+            return;
+        };
+        let Some(&(_, class_def, class_span, _)) = class.methods.iter().find(|m| m.0 == name)
+        else {
+            return;
+        };
+        let (Some(signature), Some(method)) = (
+            self.signatures.get(&class_def).cloned(),
+            self.env.get(&item.def).cloned(),
+        ) else {
+            return;
+        };
+        let Some(Type::Var(self_var)) = signature.constraints.first().map(|c| c.ty.clone()) else {
+            return;
+        };
+
+        let params = self.instances[index].params.clone();
+        let copies: Vec<(TypeVar, Type)> =
+            params.iter().map(|(_, var)| (*var, self.fresh())).collect();
+        let head = self.instances[index]
+            .head
+            .substitute(&copies.iter().cloned().collect());
+        let mut map: HashMap<TypeVar, Type> =
+            signature.vars.iter().map(|v| (*v, self.fresh())).collect();
+        map.insert(self_var, head);
+        let expected = signature.ty.substitute(&map);
+        let given: Vec<Constraint> = signature.constraints[1..]
+            .iter()
+            .map(|c| c.substitute(&map))
+            .collect();
+        let fresh: HashMap<TypeVar, Type> =
+            method.vars.iter().map(|v| (*v, self.fresh())).collect();
+        let actual = method.ty.substitute(&fresh);
+        let errors = self.diagnostics.len();
+        self.expect(&expected, &actual, span, Some(class_span));
+        if self.diagnostics.len() > errors {
+            return;
+        }
+
+        // Each type parameter of the head must still be a distinct variable,
+        // or the method only works for some of the types the instance is for.
+        let mut back = HashMap::new();
+        for (var, copy) in &copies {
+            match self.zonk(copy) {
+                Type::Var(v) if !back.contains_key(&v) => {
+                    back.insert(v, Type::Var(*var));
+                }
+                _ => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "`{}` is less general than the instance of `{}` for `{}`",
+                                name, class_name, self.instances[index].head
+                            ),
+                            span,
+                        )
+                        .with_note(class_span, format!("`{}` is declared here", name)),
+                    );
+                    return;
+                }
+            }
+        }
+        let given: Vec<Constraint> = given
+            .iter()
+            .map(|c| Constraint::new(&c.class, self.zonk(&c.ty)))
+            .collect();
+        let mut context = Vec::new();
+        for constraint in &method.constraints {
+            let constraint = Constraint::new(
+                &constraint.class,
+                self.zonk(&constraint.ty.substitute(&fresh)),
+            );
+            let mut mentioned = Vec::new();
+            constraint.ty.free_vars(&mut mentioned);
+            if mentioned.is_empty() {
+                self.want(&constraint.class, constraint.ty, span);
+            } else if mentioned.iter().all(|v| back.contains_key(v)) {
+                context.push(constraint.substitute(&back));
+            } else if !given.contains(&constraint) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "`{}` needs `{}`, which its signature does not provide",
+                            name, constraint
+                        ),
+                        span,
+                    )
+                    .with_note(class_span, format!("`{}` is declared here", name)),
+                );
+            }
+        }
+        let instance = &mut self.instances[index];
+        instance.context.extend(context);
+        instance.context = minimize(std::mem::take(&mut instance.context));
+    }
+
+    /// Reports class methods an instance leaves out without a default, and
+    /// instance methods the class does not declare.
+    fn check_instance_methods_present(&mut self) {
+        for instance in &self.instances {
+            let Some(class) = self.classes.get(&instance.class) else {
+                continue;
+            };
+            for (name, _, span, default) in &class.methods {
+                if !default && !instance.methods.iter().any(|m| m.0 == *name) {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "the instance of `{}` for `{}` does not implement `{}`",
+                                instance.class, instance.head, name
+                            ),
+                            instance.span,
+                        )
+                        .with_note(*span, format!("`{}` is declared here", name)),
+                    );
+                }
+            }
+            for (name, _, span) in &instance.methods {
+                if !class.methods.iter().any(|m| m.0 == *name) {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!("`{}` is not a method of `{}`", name, instance.class),
+                            *span,
+                        )
+                        .with_note(class.span, format!("`{}` is declared here", instance.class)),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks every remaining constraint against the builtin and declared instances.
+    fn solve(&mut self) {
+        for wanted in std::mem::take(&mut self.wanted) {
+            let constraint =
+                Constraint::new(&wanted.constraint.class, self.zonk(&wanted.constraint.ty));
+            let Err(failed) = self.entails(&constraint, 0) else {
+                if let Some(instance) = self.undispatched(&constraint) {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "the instance of `{}` for `{}` cannot be found when the program runs",
+                                constraint.class, instance.head
+                            ),
+                            wanted.span,
+                        )
+                        .with_note(instance.span, "the instance is here".to_string())
+                        .with_help(
+                            "only instances for a type named by itself, such as a struct or \
+                             `int`, are found by its values"
+                                .to_string(),
+                        ),
+                    );
+                }
+                continue;
+            };
+            let mut diagnostic = if matches!(failed.ty, Type::Var(_)) {
+                ambiguous(&failed, wanted.span)
+            } else {
+                no_instance(&failed, wanted.span)
+            };
+            if failed != constraint {
+                diagnostic =
+                    diagnostic.with_help(format!("`{}` is needed for `{}`", failed, constraint));
+            }
+            self.unsolved(diagnostic, failed, wanted.span);
+        }
+    }
+
+    /// Reports `constraint` as unsolved at `span`, unless it already is at a
+    /// span that contains this one, or is contained by it.
+    fn unsolved(&mut self, diagnostic: Diagnostic, constraint: Constraint, span: Span) {
+        let reported = self.unsolved.iter().any(|w| {
+            w.constraint == constraint && (w.span.contains(span) || span.contains(w.span))
+        });
+        if !reported {
+            self.diagnostics.push(diagnostic);
+            self.unsolved.push(Wanted { constraint, span });
+        }
+    }
+
+    /// The declared instance providing `constraint` when it is not found by
+    /// the type of a value, as method calls find the instances they run.
+    fn undispatched(&self, constraint: &Constraint) -> Option<&InstanceInfo> {
+        self.instances
+            .iter()
+            .find(|instance| {
+                instance.class == constraint.class
+                    && match_type(
+                        &instance.head,
+                        &constraint.ty,
+                        &params(instance),
+                        &mut HashMap::new(),
+                    )
+            })
+            .filter(|instance| !instance.dispatched)
+    }
+
+    /// Whether the instances provide `constraint`, or the first constraint
+    /// that they do not.
+    fn entails(&self, constraint: &Constraint, depth: usize) -> Result<(), Constraint> {
+        if depth > MAX_INSTANCE_DEPTH || matches!(constraint.ty, Type::Var(_)) {
+            return Err(constraint.clone());
+        }
+        if let Some(needed) = builtin_instance(&constraint.class, &constraint.ty) {
+            return needed.iter().try_for_each(|c| self.entails(c, depth + 1));
+        }
+        for instance in &self.instances {
+            let mut map = HashMap::new();
+            if instance.class == constraint.class
+                && match_type(&instance.head, &constraint.ty, &params(instance), &mut map)
+            {
+                return instance
+                    .context
+                    .iter()
+                    .try_for_each(|c| self.entails(&c.substitute(&map), depth + 1));
+            }
+        }
+        Err(constraint.clone())
+    }
+}
+
+/// How deeply instance contexts are followed before giving up on a constraint.
+const MAX_INSTANCE_DEPTH: usize = 32;
+
+fn params(instance: &InstanceInfo) -> Vec<TypeVar> {
+    instance.params.iter().map(|(_, var)| *var).collect()
+}
+
+fn no_instance(constraint: &Constraint, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        format!(
+            "no instance of `{}` for `{}`",
+            constraint.class, constraint.ty
+        ),
+        span,
+    )
+}
+
+fn ambiguous(constraint: &Constraint, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        format!(
+            "the type of this value is ambiguous: it needs an instance of `{}`",
+            constraint.class
+        ),
+        span,
+    )
+    .with_help("add a type annotation".to_string())
+}
+
+/// The class a builtin function needs of its type parameter, if any.
+/// Whether an instance of `bound` implies one of `class`, directly or
+/// through its superclasses.
+fn implies(bound: &str, class: &str) -> bool {
+    bound == class || superclasses(bound).any(|superclass| implies(superclass, class))
+}
+
+fn builtin_constraint(name: &str) -> Option<&'static str> {
+    match name {
+        "+" | "-" | "*" | "/" | "%" | "negate" => Some("@num"),
+        "<" | "<=" | ">" | ">=" => Some("@ord"),
+        "==" | "!=" => Some("@eq"),
+        _ => None,
+    }
+}
+
+/// The type of a builtin function, polymorphic in `a`.
+fn builtin_type(name: &str, a: Type) -> Type {
+    let function = |params: Vec<Type>, ret: Type| Type::Function(params, Box::new(ret));
+    match name {
+        "+" | "-" | "*" | "/" | "%" => function(vec![a.clone(), a.clone()], a),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => function(vec![a.clone(), a], Type::bool()),
+        "and" | "or" => function(vec![Type::bool(), Type::bool()], Type::bool()),
+        "not" => function(vec![Type::bool()], Type::bool()),
+        "negate" => function(vec![a.clone()], a),
+        "println" | "print" => function(vec![a], Type::unit()),
+        _ => a,
+    }
+}
+
+fn is_type_kind(kind: DefKind) -> bool {
+    matches!(
+        kind,
+        DefKind::Struct
+            | DefKind::Enum
+            | DefKind::Typeclass
+            | DefKind::TypeAlias
+            | DefKind::TypeParam
+            | DefKind::BuiltinType
+            | DefKind::BuiltinTypeclass
+    )
+}
+
+/// The names a type expression mentions, in order.
+fn type_names(type_expr: &TypeExpr, out: &mut Vec<String>) {
+    match type_expr {
+        TypeExpr::Literal(name) => out.push(name.0.clone()),
+        TypeExpr::Grouping => {}
+        TypeExpr::Operator(_, operands) => {
+            for operand in operands {
+                type_names(operand, out);
+            }
+        }
+    }
+}
+
+fn initializations(lines: &[Line]) -> Vec<&Initialization> {
+    lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Initialization(init) => Some(init),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Groups items into strongly connected components of the references
+/// between them, dependencies first (Tarjan's algorithm). A method call
+/// counts as a reference to every method of that name, since which one it
+/// calls is only known once its receiver's type is.
+fn components(
+    items: &[ValueItem],
+    methods: &[MethodInfo],
+    resolution: &Resolution,
+) -> Vec<Vec<usize>> {
+    let index: HashMap<DefId, usize> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (item.def, i))
+        .collect();
+    let edges: Vec<Vec<usize>> = items
+        .iter()
+        .map(|item| {
+            let mut uses = Uses::default();
+            for clause in &item.clauses {
+                uses_in_item(clause, &mut uses);
+            }
+            let methods = methods
+                .iter()
+                .filter(|m| uses.methods.contains(&m.name.as_str()))
+                .map(|m| &m.def);
+            let mut targets: Vec<usize> = uses
+                .spans
+                .iter()
+                .filter_map(|span| resolution.references.get(span))
+                .chain(methods)
+                .filter_map(|id| index.get(id).copied())
+                .collect();
+            targets.sort();
+            targets.dedup();
+            targets
+        })
+        .collect();
+
+    struct Tarjan<'e> {
+        edges: &'e [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        counter: usize,
+        components: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.counter);
+            self.low[v] = self.counter;
+            self.counter += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for &w in &self.edges[v] {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    Some(_) => {}
+                }
+            }
+            if Some(self.low[v]) == self.index[v] {
+                let mut component = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                component.sort();
+                self.components.push(component);
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        edges: &edges,
+        index: vec![None; items.len()],
+        low: vec![0; items.len()],
+        on_stack: vec![false; items.len()],
+        stack: Vec::new(),
+        counter: 0,
+        components: Vec::new(),
+    };
+    for v in 0..items.len() {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.components
+}
+
+/// What the code inside an item refers to.
+#[derive(Default)]
+struct Uses<'a> {
+    /// Spans of every reference, call and statement, which are the keys of
+    /// `Resolution::references`.
+    spans: Vec<Span>,
+    /// Names of the methods it calls.
+    methods: Vec<&'a str>,
+}
+
+fn uses_in_item<'a>(init: &'a Initialization, out: &mut Uses<'a>) {
+    match &init.value {
+        Some(Value::Expr(expr)) => uses(expr, out),
+        Some(Value::Function(function)) => uses_in_function(function, out),
+        _ => {}
+    }
+}
+
+fn uses_in_function<'a>(function: &'a Function, out: &mut Uses<'a>) {
+    if let Some(guard) = &function.guard {
+        uses(guard, out);
+    }
+    match &function.body {
+        Some(Body::Expr(expr)) => uses(expr, out),
+        Some(Body::Block(lines)) => uses_in_lines(lines, out),
+        None => {}
+    }
+}
+
+fn uses_in_lines<'a>(lines: &'a [Line], out: &mut Uses<'a>) {
+    for line in lines {
+        match line {
+            Line::Initialization(init) => uses_in_item(init, out),
+            Line::Statement(statement) => {
+                out.spans.push(statement.span);
+                for arg in &statement.args {
+                    uses(arg, out);
+                }
+            }
+            Line::Assignment(assignment) => {
+                uses(&assignment.target, out);
+                uses(&assignment.value, out);
+            }
+            Line::MethodCall(expr) | Line::Return(expr) => uses(expr, out),
+            Line::For(ForStatement(_, iterable, body, _)) => {
+                uses(iterable, out);
+                uses_in_lines(body, out);
+            }
+            Line::While(Conditional(condition, then, otherwise, _))
+            | Line::If(Conditional(condition, then, otherwise, _)) => {
+                uses(condition, out);
+                uses_in_lines(then, out);
+                if let Some(otherwise) = otherwise {
+                    uses_in_lines(otherwise, out);
+                }
+            }
+            Line::Break(_) | Line::Continue(_) => {}
+        }
+    }
+}
+
+fn uses<'a>(expr: &'a Expr, out: &mut Uses<'a>) {
+    match expr {
+        Expr::Reference(_, span) => out.spans.push(*span),
+        Expr::Call(_, args, span) => {
+            out.spans.push(*span);
+            for arg in args {
+                uses(arg, out);
+            }
+        }
+        Expr::Sequence(exprs, _) => {
+            for e in exprs {
+                uses(e, out);
+            }
+        }
+        Expr::FieldAccess(receiver, _, _) => uses(receiver, out),
+        Expr::Grouping(TokenType::Dot, inner, _) => {
+            if let Expr::Call(name, _, _) = inner.as_ref() {
+                out.methods.push(&name.0);
+            }
+            uses(inner, out);
+        }
+        Expr::Grouping(_, inner, _) => uses(inner, out),
+        Expr::Match(value, arms, _) => {
+            uses(value, out);
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    uses(guard, out);
+                }
+                uses(&arm.body, out);
+            }
+        }
+        Expr::Literal(literal, _) => match literal {
+            Literal::List(exprs) | Literal::Set(exprs) | Literal::Tuple(exprs) => {
+                for e in exprs {
+                    uses(e, out);
+                }
+            }
+            Literal::StructInitialization(_, fields, base) => {
+                if let Some(base) = base {
+                    uses(base, out);
+                }
+                for field in fields {
+                    uses(&field.value, out);
+                }
+            }
+            Literal::Map(entries) => {
+                for (key, value) in entries {
+                    uses(key, out);
+                    uses(value, out);
+                }
+            }
+            Literal::Closure(value) => {
+                if let Value::Function(function) = value.as_ref() {
+                    uses_in_function(function, out);
+                }
+            }
+            _ => {}
+        },
+    }
+}
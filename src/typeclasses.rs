@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use crate::builtins::{BUILTIN_SUPERCLASSES, BUILTIN_TYPES, FLOAT_TYPES, INTEGER_TYPES};
+use crate::types::{Constraint, Type, TypeVar};
+
+/// The constraints a builtin instance of `class` for `ty` depends on, or
+/// `None` when the builtins provide no such instance.
+///
+/// Primitive types have the instances their values support. Collections and
+/// tuples have an instance when their elements do.
+pub fn builtin_instance(class: &str, ty: &Type) -> Option<Vec<Constraint>> {
+    let each = |types: Vec<&Type>| {
+        Some(
+            types
+                .into_iter()
+                .map(|t| Constraint::new(class, t.clone()))
+                .collect(),
+        )
+    };
+    match ty {
+        Type::Named(name, args) if args.is_empty() && BUILTIN_TYPES.contains(&name.as_str()) => {
+            let integer = INTEGER_TYPES.contains(&name.as_str());
+            let float = FLOAT_TYPES.contains(&name.as_str());
+            let provided = match class {
+                "@eq" | "@show" => true,
+                "@hash" => !float,
+                "@ord" => integer || float || name == "string",
+                "@num" => integer || float,
+                "@real" => float,
+                _ => false,
+            };
+            provided.then(Vec::new)
+        }
+        Type::Tuple(elements) if matches!(class, "@eq" | "@ord" | "@hash" | "@show") => {
+            each(elements.iter().collect())
+        }
+        Type::List(element) if matches!(class, "@eq" | "@ord" | "@hash" | "@show") => {
+            each(vec![element])
+        }
+        Type::Set(element) if matches!(class, "@eq" | "@hash" | "@show") => each(vec![element]),
+        Type::Map(key, value) if matches!(class, "@eq" | "@show") => each(vec![key, value]),
+        _ => None,
+    }
+}
+
+pub fn superclasses(class: &str) -> impl Iterator<Item = &'static str> + '_ {
+    BUILTIN_SUPERCLASSES
+        .iter()
+        .filter(move |(sub, _)| *sub == class)
+        .map(|(_, sup)| *sup)
+}
+
+/// Drops duplicate constraints and those implied by a superclass relation,
+/// so `@ord a, @eq a` becomes `@ord a`.
+pub fn minimize(constraints: Vec<Constraint>) -> Vec<Constraint> {
+    let mut out: Vec<Constraint> = Vec::new();
+    for c in &constraints {
+        let implied = constraints
+            .iter()
+            .any(|other| other.ty == c.ty && superclasses(&other.class).any(|sup| sup == c.class));
+        if !implied && !out.contains(c) {
+            out.push(c.clone());
+        }
+    }
+    out
+}
+
+/// One-way matching of an instance head against a type: binds the head's
+/// `params` so that `pattern` becomes equal to `target`.
+pub fn match_type(
+    pattern: &Type,
+    target: &Type,
+    params: &[TypeVar],
+    map: &mut HashMap<TypeVar, Type>,
+) -> bool {
+    match (pattern, target) {
+        (Type::Var(v), _) if params.contains(v) => match map.get(v) {
+            Some(bound) => bound == target,
+            None => {
+                map.insert(*v, target.clone());
+                true
+            }
+        },
+        (Type::Var(a), Type::Var(b)) => a == b,
+        (Type::Named(n1, a1), Type::Named(n2, a2)) => n1 == n2 && all_match(a1, a2, params, map),
+        (Type::List(a), Type::List(b)) | (Type::Set(a), Type::Set(b)) => {
+            match_type(a, b, params, map)
+        }
+        (Type::Map(k1, v1), Type::Map(k2, v2)) => {
+            match_type(k1, k2, params, map) && match_type(v1, v2, params, map)
+        }
+        (Type::Tuple(a), Type::Tuple(b)) => all_match(a, b, params, map),
+        (Type::Function(p1, r1), Type::Function(p2, r2)) => {
+            all_match(p1, p2, params, map) && match_type(r1, r2, params, map)
+        }
+        _ => false,
+    }
+}
+
+fn all_match(
+    patterns: &[Type],
+    targets: &[Type],
+    params: &[TypeVar],
+    map: &mut HashMap<TypeVar, Type>,
+) -> bool {
+    patterns.len() == targets.len()
+        && patterns
+            .iter()
+            .zip(targets)
+            .all(|(p, t)| match_type(p, t, params, map))
+}
+
+/// Whether some type is described by both instance heads. The heads'
+/// variables are independent of each other.
+pub fn overlap(a: &Type, b: &Type) -> bool {
+    // Keep the variables of `b` apart from those of `a`.
+    let mut vars = Vec::new();
+    b.free_vars(&mut vars);
+    let offset = TypeVar::MAX / 2;
+    let renamed: HashMap<TypeVar, Type> =
+        vars.iter().map(|v| (*v, Type::Var(v + offset))).collect();
+    unifiable(a, &b.substitute(&renamed), &mut HashMap::new())
+}
+
+fn unifiable(a: &Type, b: &Type, subst: &mut HashMap<TypeVar, Type>) -> bool {
+    let resolve = |t: &Type, subst: &HashMap<TypeVar, Type>| {
+        let mut t = t.clone();
+        while let Type::Var(v) = t {
+            match subst.get(&v) {
+                Some(bound) => t = bound.clone(),
+                None => break,
+            }
+        }
+        t
+    };
+    let (a, b) = (resolve(a, subst), resolve(b, subst));
+    match (&a, &b) {
+        (Type::Var(x), Type::Var(y)) if x == y => true,
+        (Type::Var(v), other) | (other, Type::Var(v)) => {
+            subst.insert(*v, other.clone());
+            true
+        }
+        (Type::Named(n1, a1), Type::Named(n2, a2)) => {
+            n1 == n2
+                && a1.len() == a2.len()
+                && a1.iter().zip(a2).all(|(x, y)| unifiable(x, y, subst))
+        }
+        (Type::List(x), Type::List(y)) | (Type::Set(x), Type::Set(y)) => unifiable(x, y, subst),
+        (Type::Map(k1, v1), Type::Map(k2, v2)) => {
+            unifiable(k1, k2, subst) && unifiable(v1, v2, subst)
+        }
+        (Type::Tuple(x), Type::Tuple(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| unifiable(x, y, subst))
+        }
+        (Type::Function(p1, r1), Type::Function(p2, r2)) => {
+            p1.len() == p2.len()
+                && p1.iter().zip(p2).all(|(x, y)| unifiable(x, y, subst))
+                && unifiable(r1, r2, subst)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fmt;
+
+pub type TypeVar = u32;
+
+/// A type as seen by the checker. Unlike `TypeExpr` it has no syntax left in
+/// it: names are resolved and function parameters are a plain list.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Var(TypeVar),
+    /// Primitive, struct and enum types applied to their type arguments.
+    Named(String, Vec<Type>),
+    List(Box<Type>),
+    Set(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    /// The empty tuple is the unit type `()`.
+    Tuple(Vec<Type>),
+    Function(Vec<Type>, Box<Type>),
+    /// A use of a `type` alias, applied to its arguments, together with
+    /// what it stands for. It is kept only so that types are shown the way
+    /// they were written; the checker looks through it.
+    Alias(String, Vec<Type>, Box<Type>),
+}
+
+impl Type {
+    pub fn named(name: &str) -> Type {
+        Type::Named(name.to_string(), Vec::new())
+    }
+
+    pub fn int() -> Type {
+        Type::named("int")
+    }
+
+    pub fn float() -> Type {
+        Type::named("float")
+    }
+
+    pub fn bool() -> Type {
+        Type::named("bool")
+    }
+
+    pub fn string() -> Type {
+        Type::named("string")
+    }
+
+    pub fn unit() -> Type {
+        Type::Tuple(Vec::new())
+    }
+
+    /// Type variables in order of first appearance, without duplicates.
+    pub fn free_vars(&self, out: &mut Vec<TypeVar>) {
+        match self {
+            Type::Var(v) => {
+                if !out.contains(v) {
+                    out.push(*v);
+                }
+            }
+            Type::Named(_, args) | Type::Tuple(args) => {
+                for arg in args {
+                    arg.free_vars(out);
+                }
+            }
+            Type::List(t) | Type::Set(t) => t.free_vars(out),
+            Type::Map(k, v) => {
+                k.free_vars(out);
+                v.free_vars(out);
+            }
+            Type::Function(params, ret) => {
+                for param in params {
+                    param.free_vars(out);
+                }
+                ret.free_vars(out);
+            }
+            Type::Alias(_, args, target) => {
+                for arg in args {
+                    arg.free_vars(out);
+                }
+                target.free_vars(out);
+            }
+        }
+    }
+
+    pub fn substitute(&self, map: &HashMap<TypeVar, Type>) -> Type {
+        match self {
+            Type::Var(v) => map.get(v).cloned().unwrap_or(Type::Var(*v)),
+            Type::Named(name, args) => Type::Named(
+                name.clone(),
+                args.iter().map(|a| a.substitute(map)).collect(),
+            ),
+            Type::List(t) => Type::List(Box::new(t.substitute(map))),
+            Type::Set(t) => Type::Set(Box::new(t.substitute(map))),
+            Type::Map(k, v) => Type::Map(Box::new(k.substitute(map)), Box::new(v.substitute(map))),
+            Type::Tuple(elements) => {
+                Type::Tuple(elements.iter().map(|e| e.substitute(map)).collect())
+            }
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| p.substitute(map)).collect(),
+                Box::new(ret.substitute(map)),
+            ),
+            Type::Alias(name, args, target) => Type::Alias(
+                name.clone(),
+                args.iter().map(|a| a.substitute(map)).collect(),
+                Box::new(target.substitute(map)),
+            ),
+        }
+    }
+
+    /// Writes the type, naming the variables by their position in `names`.
+    pub fn write(&self, f: &mut fmt::Formatter<'_>, names: &[TypeVar]) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, types: &[Type]| -> fmt::Result {
+            for (i, t) in types.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                t.write(f, names)?;
+            }
+            Ok(())
+        };
+        match self {
+            Type::Var(v) => match names.iter().position(|n| n == v) {
+                Some(i) => write!(f, "{}", var_name(i)),
+                None => write!(f, "?{}", v),
+            },
+            Type::Named(name, args) | Type::Alias(name, args, _) if args.is_empty() => {
+                write!(f, "{}", name)
+            }
+            Type::Named(name, args) | Type::Alias(name, args, _) => {
+                write!(f, "{}<", name)?;
+                list(f, args)?;
+                write!(f, ">")
+            }
+            Type::List(t) => {
+                write!(f, "[")?;
+                t.write(f, names)?;
+                write!(f, "]")
+            }
+            Type::Set(t) => {
+                write!(f, "{{")?;
+                t.write(f, names)?;
+                write!(f, "}}")
+            }
+            Type::Map(k, v) => {
+                write!(f, "{{")?;
+                k.write(f, names)?;
+                write!(f, ": ")?;
+                v.write(f, names)?;
+                write!(f, "}}")
+            }
+            Type::Tuple(elements) => {
+                write!(f, "(")?;
+                list(f, elements)?;
+                write!(f, ")")
+            }
+            Type::Function(params, ret) => {
+                write!(f, "(")?;
+                list(f, params)?;
+                write!(f, ") -> ")?;
+                ret.write(f, names)
+            }
+        }
+    }
+}
+
+/// Type variables are printed as `a`, `b`, ... in order of appearance.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Vec::new();
+        self.free_vars(&mut names);
+        self.write(f, &names)
+    }
+}
+
+fn var_name(i: usize) -> String {
+    let letter = (b'a' + (i % 26) as u8) as char;
+    if i < 26 {
+        letter.to_string()
+    } else {
+        format!("{}{}", letter, i / 26)
+    }
+}
+
+/// A requirement that `ty` has an instance of the typeclass `class`, as in `@num a`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constraint {
+    pub class: String,
+    pub ty: Type,
+}
+
+impl Constraint {
+    pub fn new(class: &str, ty: Type) -> Constraint {
+        Constraint {
+            class: class.to_string(),
+            ty,
+        }
+    }
+
+    pub fn substitute(&self, map: &HashMap<TypeVar, Type>) -> Constraint {
+        Constraint {
+            class: self.class.clone(),
+            ty: self.ty.substitute(map),
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Vec::new();
+        self.ty.free_vars(&mut names);
+        write!(f, "{} ", self.class)?;
+        self.ty.write(f, &names)
+    }
+}
+
+/// A possibly polymorphic type: `vars` may be instantiated freshly at every
+/// use, provided the instantiation satisfies `constraints`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scheme {
+    pub vars: Vec<TypeVar>,
+    pub constraints: Vec<Constraint>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    pub fn mono(ty: Type) -> Scheme {
+        Scheme {
+            vars: Vec::new(),
+            constraints: Vec::new(),
+            ty,
+        }
+    }
+}
+
+/// Printed in chop's own syntax, with constraints in a `where` clause:
+/// `(a) -> bool where @num a, @eq a`.
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Vec::new();
+        self.ty.free_vars(&mut names);
+        for constraint in &self.constraints {
+            constraint.ty.free_vars(&mut names);
+        }
+        self.ty.write(f, &names)?;
+        for (i, constraint) in self.constraints.iter().enumerate() {
+            write!(
+                f,
+                "{}{} ",
+                if i == 0 { " where " } else { ", " },
+                constraint.class
+            )?;
+            constraint.ty.write(f, &names)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1051 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::bytecode::{Capture, Constant, Function, Memo, Op, Pattern, Program, StructInfo};
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::MAX_CALL_DEPTH;
+use crate::memo::{self, Cache};
+use crate::tokens::Span;
+
+/// A value on the stack of the virtual machine. Like the values of the
+/// interpreter, structs and collections are shared between copies.
+#[derive(Clone)]
+pub enum Value<'p> {
+    Null,
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(Rc<str>),
+    List(Rc<RefCell<Vec<Value<'p>>>>),
+    Set(Rc<RefCell<Vec<Value<'p>>>>),
+    Map(Rc<RefCell<Vec<(Value<'p>, Value<'p>)>>>),
+    Tuple(Rc<[Value<'p>]>),
+    Struct(Rc<Instance<'p>>),
+    Variant(Rc<str>, Rc<[Value<'p>]>),
+    Closure(Rc<Closure<'p>>),
+    Builtin(&'p str),
+    Type(&'p str),
+    /// The box of a local that a closure captures. It only ever lives in a
+    /// slot, never in a value the program sees.
+    Cell(Rc<RefCell<Value<'p>>>),
+    /// The values a `for` loop has left to visit.
+    Iterator(Rc<RefCell<std::vec::IntoIter<Value<'p>>>>),
+}
+
+pub struct Instance<'p> {
+    ty: &'p StructInfo,
+    fields: RefCell<Vec<Value<'p>>>,
+}
+
+pub struct Closure<'p> {
+    function: &'p Function,
+    captures: Vec<Rc<RefCell<Value<'p>>>>,
+}
+
+type Run<T> = Result<T, Diagnostic>;
+
+/// Runs the program's `main`, writing what it prints to stdout.
+pub fn run(program: &Program) -> Result<(), Diagnostic> {
+    let stdout = std::io::stdout();
+    let mut vm = Vm::new(program, std::io::BufWriter::new(stdout.lock()));
+    let result = vm.run();
+    let _ = vm.out.flush();
+    result.map(|_| ())
+}
+
+/// A function call in progress. Its slots start at `base` on the stack, and
+/// returning truncates the stack to `bottom`, which also removes the callee
+/// when it was called as a value.
+struct Frame<'p> {
+    function: &'p Function,
+    closure: Option<Rc<Closure<'p>>>,
+    ip: usize,
+    base: usize,
+    bottom: usize,
+    /// The global whose value the call computes, if it initializes one.
+    global: Option<u32>,
+    /// The tail call that made this frame, if it took over its caller's.
+    site: Option<Span>,
+    /// The hash and a copy of the arguments of a call to a `@memo` fn,
+    /// which its result is cached under when it returns.
+    memo: Option<(u64, Vec<Value<'p>>)>,
+}
+
+enum GlobalState<'p> {
+    Uninitialized,
+    Initializing,
+    Ready(Value<'p>),
+}
+
+struct Vm<'p, W: Write> {
+    program: &'p Program,
+    constants: Vec<Value<'p>>,
+    globals: Vec<GlobalState<'p>>,
+    structs: HashMap<&'p str, &'p StructInfo>,
+    /// Methods by the name of the type they are called on and their own name.
+    methods: HashMap<&'p str, HashMap<&'p str, &'p Function>>,
+    /// The enum each variant belongs to.
+    enums: HashMap<&'p str, &'p str>,
+    stack: Vec<Value<'p>>,
+    /// The callers of the running function.
+    frames: Vec<Frame<'p>>,
+    /// The caches of the `@memo` fns called so far, by function.
+    memos: HashMap<*const Function, Cache<Value<'p>>>,
+    out: W,
+}
+
+impl<'p, W: Write> Vm<'p, W> {
+    fn new(program: &'p Program, out: W) -> Self {
+        let constants = program
+            .constants
+            .iter()
+            .map(value)
+            .collect();
+        let mut structs = HashMap::new();
+        for info in &program.structs {
+            structs.entry(info.name.as_str()).or_insert(info);
+        }
+        let mut methods: HashMap<&str, HashMap<&str, &Function>> = HashMap::new();
+        for method in &program.methods {
+            let function = &program.functions[method.function as usize];
+            methods
+                .entry(method.owner.as_str())
+                .or_default()
+                .insert(method.name.as_str(), function);
+        }
+        let enums = program
+            .variants
+            .iter()
+            .map(|(variant, e)| (variant.as_str(), e.as_str()))
+            .collect();
+        Vm {
+            program,
+            constants,
+            globals: program
+                .globals
+                .iter()
+                .map(|_| GlobalState::Uninitialized)
+                .collect(),
+            structs,
+            methods,
+            enums,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            memos: HashMap::new(),
+            out,
+        }
+    }
+
+    fn run(&mut self) -> Run<Value<'p>> {
+        let main = &self.program.functions[self.program.main as usize];
+        let mut frame = Frame {
+            function: main,
+            closure: None,
+            ip: 0,
+            base: 0,
+            bottom: 0,
+            global: None,
+            site: None,
+            memo: None,
+        };
+        self.stack.resize(main.slots as usize, Value::Unit);
+        self.execute(&mut frame)
+    }
+
+    /// The interpreter loop. The running frame is kept out of `frames`, so
+    /// that its instruction pointer and slots are at hand.
+    fn execute(&mut self, frame: &mut Frame<'p>) -> Run<Value<'p>> {
+        let program = self.program;
+        loop {
+            let op = frame.function.code[frame.ip];
+            frame.ip += 1;
+            match op {
+                Op::Constant(index) => self.stack.push(self.constants[index as usize].clone()),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => {
+                    let value = self.stack.last().expect("a value to copy").clone();
+                    self.stack.push(value);
+                }
+                Op::Local(slot) => {
+                    let value = self.stack[frame.base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.pop();
+                    self.stack[frame.base + slot as usize] = value;
+                }
+                Op::Cell(slot) => {
+                    let value = self.cell(frame, slot)?.borrow().clone();
+                    self.stack.push(value);
+                }
+                Op::SetCell(slot) => {
+                    let value = self.pop();
+                    *self.cell(frame, slot)?.borrow_mut() = value;
+                }
+                Op::NewCell(slot) => {
+                    let value = self.pop();
+                    self.stack[frame.base + slot as usize] =
+                        Value::Cell(Rc::new(RefCell::new(value)));
+                }
+                Op::Capture(index) => {
+                    let value = captured(frame, index).borrow().clone();
+                    self.stack.push(value);
+                }
+                Op::SetCapture(index) => {
+                    let value = self.pop();
+                    *captured(frame, index).borrow_mut() = value;
+                }
+                Op::Global(index) => match &self.globals[index as usize] {
+                    GlobalState::Ready(value) => self.stack.push(value.clone()),
+                    GlobalState::Initializing => {
+                        let name = &program.globals[index as usize].name;
+                        return Err(error(
+                            frame,
+                            format!("`{}` is used while it is being initialized", name),
+                        ));
+                    }
+                    GlobalState::Uninitialized => {
+                        self.globals[index as usize] = GlobalState::Initializing;
+                        let init = program.globals[index as usize].init;
+                        let function = &program.functions[init as usize];
+                        let bottom = self.stack.len();
+                        self.enter(frame, function, None, 0, bottom, Some(index))?;
+                    }
+                },
+                Op::SetGlobal(index) => {
+                    let value = self.pop();
+                    self.globals[index as usize] = GlobalState::Ready(value);
+                }
+                Op::Function(index) => {
+                    let function = &program.functions[index as usize];
+                    self.stack.push(Value::Closure(Rc::new(Closure {
+                        function,
+                        captures: Vec::new(),
+                    })));
+                }
+                Op::Closure(index) => {
+                    let function = &program.functions[index as usize];
+                    let captures = function
+                        .captures
+                        .iter()
+                        .map(|capture| match *capture {
+                            Capture::Local(slot) => self.cell(frame, slot).cloned(),
+                            Capture::Value(slot) => Ok(Rc::new(RefCell::new(
+                                self.stack[frame.base + slot as usize].clone(),
+                            ))),
+                            Capture::Enclosing(index) => Ok(captured(frame, index).clone()),
+                        })
+                        .collect::<Run<_>>()?;
+                    self.stack
+                        .push(Value::Closure(Rc::new(Closure { function, captures })));
+                }
+                Op::Builtin(name) => self.stack.push(Value::Builtin(program.string(name))),
+                Op::Type(name) => self.stack.push(Value::Type(program.string(name))),
+                Op::Variant(name, count) => {
+                    let values = self.popn(count as usize);
+                    let name = program.string(name);
+                    self.stack.push(Value::Variant(name.into(), values.into()));
+                }
+                Op::Call(count) => {
+                    let at = self.stack.len() - count as usize - 1;
+                    match self.stack[at].clone() {
+                        Value::Closure(closure) => {
+                            let function = closure.function;
+                            self.enter(frame, function, Some(closure), count as usize, at, None)?;
+                        }
+                        Value::Builtin(name) => {
+                            let args = self.popn(count as usize);
+                            self.pop();
+                            let value = self.builtin(name, args).map_err(|e| error(frame, e))?;
+                            self.stack.push(value);
+                        }
+                        other => {
+                            return Err(error(frame, format!("`{}` is not a function", other)))
+                        }
+                    }
+                }
+                Op::CallFunction(index, count) => {
+                    let function = &program.functions[index as usize];
+                    let bottom = self.stack.len() - count as usize;
+                    self.enter(frame, function, None, count as usize, bottom, None)?;
+                }
+                Op::CallBuiltin(name, count) => {
+                    let args = self.popn(count as usize);
+                    let value = self
+                        .builtin(program.string(name), args)
+                        .map_err(|e| error(frame, e))?;
+                    self.stack.push(value);
+                }
+                Op::CallMethod(name, count) => {
+                    self.method_call(frame, program.string(name), count as usize)?
+                }
+                Op::Return => {
+                    let result = self.pop();
+                    self.stack.truncate(frame.bottom);
+                    if let Some(global) = frame.global {
+                        self.globals[global as usize] = GlobalState::Ready(result.clone());
+                    }
+                    if let Some((hash, args)) = frame.memo.take() {
+                        if let Some(cache) = self.memos.get_mut(&(frame.function as *const _)) {
+                            cache.insert(hash, args, snapshot(&result));
+                        }
+                    }
+                    match self.frames.pop() {
+                        Some(caller) => *frame = caller,
+                        None => return Ok(result),
+                    }
+                    self.stack.push(result);
+                }
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => match self.pop() {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => frame.ip = target as usize,
+                    other => {
+                        return Err(error(
+                            frame,
+                            format!("expected `true` or `false`, found `{}`", other),
+                        ))
+                    }
+                },
+                Op::Add
+                | Op::Subtract
+                | Op::Multiply
+                | Op::Divide
+                | Op::Remainder
+                | Op::Equal
+                | Op::NotEqual
+                | Op::Less
+                | Op::LessEqual
+                | Op::Greater
+                | Op::GreaterEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    // Integers take a fast path; anything it cannot handle,
+                    // overflow included, goes through `operator`.
+                    let fast = match (op, &a, &b) {
+                        (Op::Add, Value::Int(x), Value::Int(y)) => {
+                            x.checked_add(*y).map(Value::Int)
+                        }
+                        (Op::Subtract, Value::Int(x), Value::Int(y)) => {
+                            x.checked_sub(*y).map(Value::Int)
+                        }
+                        (Op::Multiply, Value::Int(x), Value::Int(y)) => {
+                            x.checked_mul(*y).map(Value::Int)
+                        }
+                        (Op::Less, Value::Int(x), Value::Int(y)) => Some(Value::Bool(x < y)),
+                        (Op::LessEqual, Value::Int(x), Value::Int(y)) => Some(Value::Bool(x <= y)),
+                        (Op::Greater, Value::Int(x), Value::Int(y)) => Some(Value::Bool(x > y)),
+                        (Op::GreaterEqual, Value::Int(x), Value::Int(y)) => {
+                            Some(Value::Bool(x >= y))
+                        }
+                        (Op::Equal, Value::Int(x), Value::Int(y)) => Some(Value::Bool(x == y)),
+                        _ => None,
+                    };
+                    let value = match fast {
+                        Some(value) => value,
+                        None => operator(symbol(op), &[a, b]).map_err(|e| error(frame, e))?,
+                    };
+                    self.stack.push(value);
+                }
+                Op::Negate | Op::Not => {
+                    let value = self.pop();
+                    let value = operator(symbol(op), &[value]).map_err(|e| error(frame, e))?;
+                    self.stack.push(value);
+                }
+                Op::List(count) => {
+                    let values = self.popn(count as usize);
+                    self.stack.push(Value::List(Rc::new(RefCell::new(values))));
+                }
+                Op::Set(count) => {
+                    let mut set: Vec<Value<'p>> = Vec::new();
+                    for value in self.popn(count as usize) {
+                        if !set.iter().any(|v| equal(v, &value)) {
+                            set.push(value);
+                        }
+                    }
+                    self.stack.push(Value::Set(Rc::new(RefCell::new(set))));
+                }
+                Op::Map(count) => {
+                    let values = self.popn(2 * count as usize);
+                    let mut map: Vec<(Value<'p>, Value<'p>)> = Vec::new();
+                    let mut values = values.into_iter();
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        match map.iter_mut().find(|(k, _)| equal(k, &key)) {
+                            Some(entry) => entry.1 = value,
+                            None => map.push((key, value)),
+                        }
+                    }
+                    self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+                Op::Tuple(count) => {
+                    let values = self.popn(count as usize);
+                    self.stack.push(Value::Tuple(values.into()));
+                }
+                Op::Struct(index, count) => {
+                    let fields = self.popn(count as usize);
+                    let ty = &program.structs[index as usize];
+                    self.stack.push(Value::Struct(Rc::new(Instance {
+                        ty,
+                        fields: RefCell::new(fields),
+                    })));
+                }
+                Op::Field(name) => {
+                    let receiver = self.pop();
+                    let value =
+                        field(&receiver, program.string(name)).map_err(|e| error(frame, e))?;
+                    self.stack.push(value);
+                }
+                Op::SetField(name) => {
+                    let value = self.pop();
+                    let receiver = self.pop();
+                    let name = program.string(name);
+                    let Value::Struct(instance) = &receiver else {
+                        return Err(error(frame, no_field(&receiver, name)));
+                    };
+                    let index = field_index(instance, name).map_err(|e| error(frame, e))?;
+                    instance.fields.borrow_mut()[index] = value;
+                }
+                Op::Iterate(count) => {
+                    let iterable = self.pop();
+                    let items = elements(&iterable, count as usize).map_err(|e| error(frame, e))?;
+                    self.stack
+                        .push(Value::Iterator(Rc::new(RefCell::new(items.into_iter()))));
+                }
+                Op::Next(slot, target) => {
+                    let next = match &self.stack[frame.base + slot as usize] {
+                        Value::Iterator(items) => items.borrow_mut().next(),
+                        _ => return Err(damaged(frame, "a loop reads a slot with no iterator")),
+                    };
+                    match next {
+                        Some(value) => self.stack.push(value),
+                        None => frame.ip = target as usize,
+                    }
+                }
+                // The loader counted on exactly `count` values.
+                Op::Unpack(count) => match self.pop() {
+                    Value::Tuple(values) if values.len() == count as usize => {
+                        self.stack.extend(values.iter().cloned())
+                    }
+                    _ => return Err(damaged(frame, "a loop unpacks something else")),
+                },
+                Op::Match(pattern, target) => {
+                    let value = self.pop();
+                    let pattern = &frame.function.patterns[pattern as usize];
+                    if !self.bind(pattern, &value, frame.base) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::NoClause => {
+                    let start = frame.base;
+                    let args: Vec<String> = self.stack
+                        [start..start + frame.function.arity as usize]
+                        .iter()
+                        .map(|a| a.to_string())
+                        .collect();
+                    // The error points at the call rather than the clauses.
+                    let message = format!(
+                        "no clause of `{}` matches the arguments ({})",
+                        frame.function.name,
+                        args.join(", ")
+                    );
+                    return Err(match frame.site {
+                        Some(site) => Diagnostic::error(message, site),
+                        None => error(self.frames.last().unwrap_or(frame), message),
+                    });
+                }
+                Op::NoArm(slot) => {
+                    let value = &self.stack[frame.base + slot as usize];
+                    return Err(error(
+                        frame,
+                        format!("no arm of this `match` matches `{}`", value),
+                    ));
+                }
+                Op::Fail(message) => {
+                    return Err(error(frame, program.string(message).to_string()));
+                }
+            }
+        }
+    }
+
+    /// Starts running `function` with the arguments on top of the stack,
+    /// suspending the running frame. A tail call replaces the running frame
+    /// instead, so tail recursion runs in constant space, unless the running
+    /// frame still has a result to cache. A `@memo` fn that has cached a
+    /// result for the arguments does not run at all.
+    fn enter(
+        &mut self,
+        frame: &mut Frame<'p>,
+        function: &'p Function,
+        closure: Option<Rc<Closure<'p>>>,
+        count: usize,
+        bottom: usize,
+        global: Option<u32>,
+    ) -> Run<()> {
+        if count != function.arity as usize {
+            let args: Vec<String> = self.stack[self.stack.len() - count..]
+                .iter()
+                .map(|a| a.to_string())
+                .collect();
+            return Err(error(
+                frame,
+                format!(
+                    "no clause of `{}` matches the arguments ({})",
+                    function.name,
+                    args.join(", ")
+                ),
+            ));
+        }
+        let memo = match function.memo {
+            Some(Memo { capacity, .. }) => {
+                let args = &self.stack[self.stack.len() - count..];
+                let hash = hash(args);
+                let cache = self
+                    .memos
+                    .entry(function as *const _)
+                    .or_insert_with(|| Cache::new(capacity));
+                let cached = cache.get(hash, |key| key.iter().zip(args).all(|(k, a)| equal(k, a)));
+                if let Some(result) = cached.map(snapshot) {
+                    self.stack.truncate(bottom);
+                    self.stack.push(result);
+                    return Ok(());
+                }
+                Some((hash, args.iter().map(snapshot).collect()))
+            }
+            None => None,
+        };
+        if frame.memo.is_none() && frame.function.returns_at(frame.ip) {
+            let site = frame.function.spans[frame.ip - 1];
+            self.stack.drain(frame.bottom..bottom);
+            let base = self.stack.len() - function.arity as usize;
+            self.stack
+                .resize(base + function.slots as usize, Value::Unit);
+            *frame = Frame {
+                function,
+                closure,
+                ip: 0,
+                base,
+                bottom: frame.bottom,
+                global: frame.global,
+                site: Some(site),
+                memo,
+            };
+            return Ok(());
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(error(
+                frame,
+                format!(
+                    "calls to `{}` nest more than {} deep",
+                    function.name, MAX_CALL_DEPTH
+                ),
+            ));
+        }
+        let base = self.stack.len() - function.arity as usize;
+        self.stack
+            .resize(base + function.slots as usize, Value::Unit);
+        let callee = Frame {
+            function,
+            closure,
+            ip: 0,
+            base,
+            bottom,
+            global,
+            site: None,
+            memo,
+        };
+        self.frames.push(std::mem::replace(frame, callee));
+        Ok(())
+    }
+
+    /// `receiver.name(args)` with the receiver and arguments on the stack:
+    /// a method of the receiver's type, or a function of a type named
+    /// directly, as in `ArrayList.new()`.
+    fn method_call(&mut self, frame: &mut Frame<'p>, name: &str, count: usize) -> Run<()> {
+        let at = self.stack.len() - count;
+        let receiver = self.stack[at].clone();
+        if let Value::Type(ty) = receiver {
+            if let Some(&info) = self.structs.get(ty).filter(|_| name == "init") {
+                let fields = self.popn(count - 1);
+                self.pop();
+                if fields.len() != info.fields.len() {
+                    return Err(error(
+                        frame,
+                        format!(
+                            "`{}.init` takes {} fields, but {} were given",
+                            info.name,
+                            info.fields.len(),
+                            fields.len()
+                        ),
+                    ));
+                }
+                self.stack.push(Value::Struct(Rc::new(Instance {
+                    ty: info,
+                    fields: RefCell::new(fields),
+                })));
+                return Ok(());
+            }
+            let Some(&function) = self.methods.get(ty).and_then(|m| m.get(name)) else {
+                return Err(error(frame, format!("`{}` has no function `{}`", ty, name)));
+            };
+            self.stack.remove(at);
+            return self.enter(frame, function, None, count - 1, at, None);
+        }
+        let owner = self.type_name(&receiver);
+        let Some(&function) = self.methods.get(owner).and_then(|m| m.get(name)) else {
+            return Err(error(
+                frame,
+                format!("`{}` has no method `{}`", owner, name),
+            ));
+        };
+        self.enter(frame, function, None, count, at, None)
+    }
+
+    /// The name methods are looked up under for a value.
+    fn type_name<'v>(&self, value: &'v Value<'p>) -> &'v str {
+        match value {
+            Value::Null => "null",
+            Value::Unit => "()",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::Map(_) => "map",
+            Value::Tuple(_) => "tuple",
+            Value::Struct(instance) => &instance.ty.name,
+            Value::Variant(name, _) => self.enums.get(name.as_ref()).copied().unwrap_or(name),
+            Value::Closure(_) | Value::Builtin(_) => "function",
+            Value::Type(name) => name,
+            Value::Iterator(_) => "iterator",
+            Value::Cell(_) => unreachable!("the loader keeps boxes off the stack"),
+        }
+    }
+
+    fn builtin(&mut self, name: &str, args: Vec<Value<'p>>) -> Result<Value<'p>, String> {
+        match name {
+            "print" | "println" => {
+                for arg in &args {
+                    let _ = write!(self.out, "{}", arg);
+                }
+                if name == "println" {
+                    let _ = writeln!(self.out);
+                }
+                Ok(Value::Unit)
+            }
+            _ => operator(name, &args),
+        }
+    }
+
+    /// Matches `value` against `pattern`, storing the bindings into the
+    /// slots from `base`.
+    fn bind(&mut self, pattern: &Pattern, value: &Value<'p>, base: usize) -> bool {
+        match (pattern, value) {
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Bind(slot), _) => {
+                self.stack[base + *slot as usize] = value.clone();
+                true
+            }
+            (Pattern::Cell(slot), _) => {
+                self.stack[base + *slot as usize] =
+                    Value::Cell(Rc::new(RefCell::new(value.clone())));
+                true
+            }
+            (Pattern::Constant(index), _) => equal(&self.constants[*index as usize], value),
+            (Pattern::Tuple(patterns), Value::Tuple(values)) => {
+                patterns.len() == values.len()
+                    && patterns
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(p, v)| self.bind(p, v, base))
+            }
+            (Pattern::Variant(name, patterns), Value::Variant(variant, values)) => {
+                self.program.string(*name) == variant.as_ref()
+                    && patterns.len() == values.len()
+                    && patterns
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(p, v)| self.bind(p, v, base))
+            }
+            _ => false,
+        }
+    }
+
+    /// The box in a slot. The loader has checked that only boxes are
+    /// stored there, but the slot may still be read before it is set.
+    fn cell(&self, frame: &Frame<'p>, slot: u16) -> Run<&Rc<RefCell<Value<'p>>>> {
+        match &self.stack[frame.base + slot as usize] {
+            Value::Cell(cell) => Ok(cell),
+            _ => Err(damaged(frame, "a shared variable is read before it is made")),
+        }
+    }
+
+    fn pop(&mut self) -> Value<'p> {
+        self.stack.pop().expect("the compiler balances the stack")
+    }
+
+    fn popn(&mut self, count: usize) -> Vec<Value<'p>> {
+        let at = self.stack.len() - count;
+        self.stack.split_off(at)
+    }
+}
+
+fn captured<'f, 'p>(frame: &'f Frame<'p>, index: u16) -> &'f Rc<RefCell<Value<'p>>> {
+    let closure = frame.closure.as_ref().expect("only closures have captures");
+    &closure.captures[index as usize]
+}
+
+fn value<'p>(constant: &Constant) -> Value<'p> {
+    match constant {
+        Constant::Null => Value::Null,
+        Constant::Unit => Value::Unit,
+        Constant::Int(i) => Value::Int(*i),
+        Constant::Float(x) => Value::Float(*x),
+        Constant::Bool(b) => Value::Bool(*b),
+        Constant::String(s) => Value::String(s.as_str().into()),
+    }
+}
+
+/// What an operator instruction computes from constant operands, or `None`
+/// when it would stop the program, so that the error still happens when the
+/// program runs.
+pub fn evaluate(op: Op, operands: &[&Constant]) -> Option<Constant> {
+    let operands: Vec<Value> = operands.iter().map(|c| value(c)).collect();
+    match operator(symbol(op), &operands).ok()? {
+        Value::Int(i) => Some(Constant::Int(i)),
+        Value::Float(x) => Some(Constant::Float(x)),
+        Value::Bool(b) => Some(Constant::Bool(b)),
+        _ => None,
+    }
+}
+
+/// A runtime error at the instruction that just ran.
+fn error(frame: &Frame, message: String) -> Diagnostic {
+    let span: Span = frame.function.spans[frame.ip - 1];
+    Diagnostic::error(message, span)
+}
+
+/// An error for code that the loader's checks let through, but that no
+/// compiled program contains.
+fn damaged(frame: &Frame, what: &str) -> Diagnostic {
+    error(frame, format!("the program is damaged: {}", what))
+}
+
+/// The builtin an instruction applies.
+fn symbol(op: Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Subtract => "-",
+        Op::Multiply => "*",
+        Op::Divide => "/",
+        Op::Remainder => "%",
+        Op::Equal => "==",
+        Op::NotEqual => "!=",
+        Op::Less => "<",
+        Op::LessEqual => "<=",
+        Op::Greater => ">",
+        Op::GreaterEqual => ">=",
+        Op::Negate => "negate",
+        Op::Not => "not",
+        other => unreachable!("{:?} is not an operator", other),
+    }
+}
+
+/// The builtin operators, with the same semantics as in the interpreter.
+fn operator<'p>(name: &str, args: &[Value<'p>]) -> Result<Value<'p>, String> {
+    let int = |result: Option<i64>, a: i64, b: i64| {
+        result.map(Value::Int).ok_or_else(|| {
+            if b == 0 && matches!(name, "/" | "%") {
+                "division by zero".to_string()
+            } else {
+                format!("`{} {} {}` overflows an `int`", a, name, b)
+            }
+        })
+    };
+    match (name, args) {
+        ("not", [Value::Bool(b)]) => Ok(Value::Bool(!b)),
+        ("negate", [Value::Int(i)]) => i
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| format!("negating `{}` overflows an `int`", i)),
+        ("negate", [Value::Float(x)]) => Ok(Value::Float(-x)),
+        ("+", [Value::Int(a), Value::Int(b)]) => int(a.checked_add(*b), *a, *b),
+        ("-", [Value::Int(a), Value::Int(b)]) => int(a.checked_sub(*b), *a, *b),
+        ("*", [Value::Int(a), Value::Int(b)]) => int(a.checked_mul(*b), *a, *b),
+        ("/", [Value::Int(a), Value::Int(b)]) => int(a.checked_div(*b), *a, *b),
+        ("%", [Value::Int(a), Value::Int(b)]) => int(a.checked_rem(*b), *a, *b),
+        ("==", [a, b]) => Ok(Value::Bool(equal(a, b))),
+        ("!=", [a, b]) => Ok(Value::Bool(!equal(a, b))),
+        ("<" | "<=" | ">" | ">=", [a, b]) => match compare(a, b) {
+            Some(ordering) => Ok(Value::Bool(match name {
+                "<" => ordering.is_lt(),
+                "<=" => ordering.is_le(),
+                ">" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })),
+            None => Err(format!("cannot compare `{}` and `{}`", a, b)),
+        },
+        ("+" | "-" | "*" | "/" | "%", [a, b]) => match (float(a), float(b)) {
+            (Some(a), Some(b)) => Ok(Value::Float(match name {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                _ => a % b,
+            })),
+            _ => Err(format!("cannot apply `{}` to `{}` and `{}`", name, a, b)),
+        },
+        _ => Err(format!("`{}` cannot be applied to these arguments", name)),
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => float(a)?.partial_cmp(&float(b)?),
+    }
+}
+
+/// Structural equality; functions are never equal.
+fn equal<'p>(a: &Value<'p>, b: &Value<'p>) -> bool {
+    let all = |xs: &[Value<'p>], ys: &[Value<'p>]| {
+        xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| equal(x, y))
+    };
+    match (a, b) {
+        (Value::Null, Value::Null) | (Value::Unit, Value::Unit) => true,
+        (Value::Int(x), Value::Int(y)) => x == y,
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => float(a) == float(b),
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::String(x), Value::String(y)) => x == y,
+        (Value::List(x), Value::List(y)) => all(&x.borrow(), &y.borrow()),
+        (Value::Set(x), Value::Set(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len() && x.iter().all(|v| y.iter().any(|w| equal(v, w)))
+        }
+        (Value::Map(x), Value::Map(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len()
+                && x.iter().all(|(k, v)| {
+                    y.iter()
+                        .find(|(l, _)| equal(k, l))
+                        .is_some_and(|(_, w)| equal(v, w))
+                })
+        }
+        (Value::Tuple(x), Value::Tuple(y)) => all(x, y),
+        (Value::Struct(x), Value::Struct(y)) => {
+            Rc::ptr_eq(x, y)
+                || (std::ptr::eq(x.ty, y.ty) && all(&x.fields.borrow(), &y.fields.borrow()))
+        }
+        (Value::Variant(n, xs), Value::Variant(m, ys)) => n == m && all(xs, ys),
+        (Value::Type(x), Value::Type(y)) => x == y,
+        _ => false,
+    }
+}
+
+/// A hash of values that agrees with `equal`, as the interpreter's does.
+fn hash(values: &[Value]) -> u64 {
+    values.iter().fold(memo::SEED, |hash, v| memo::mix(hash, word(v)))
+}
+
+fn word(value: &Value) -> u64 {
+    let sum = |words: &mut dyn Iterator<Item = u64>| words.fold(0u64, u64::wrapping_add);
+    match value {
+        Value::Int(i) => *i as u64,
+        Value::Float(x) if x.fract() == 0.0 && x.abs() < i64::MAX as f64 => *x as i64 as u64,
+        Value::Float(x) => x.to_bits(),
+        Value::Bool(b) => *b as u64,
+        Value::String(s) => memo::string(s.as_bytes()),
+        Value::List(items) => hash(&items.borrow()),
+        Value::Set(items) => sum(&mut items.borrow().iter().map(word)),
+        Value::Map(entries) => sum(
+            &mut entries
+                .borrow()
+                .iter()
+                .map(|(k, v)| memo::mix(word(k), word(v))),
+        ),
+        Value::Tuple(items) => hash(items),
+        Value::Struct(instance) => memo::mix(
+            memo::string(instance.ty.name.as_bytes()),
+            hash(&instance.fields.borrow()),
+        ),
+        Value::Variant(name, items) => memo::mix(memo::string(name.as_bytes()), hash(items)),
+        _ => 0,
+    }
+}
+
+/// A copy of a value that shares nothing mutable with it, for a cache.
+fn snapshot<'p>(value: &Value<'p>) -> Value<'p> {
+    let all = |items: &[Value<'p>]| items.iter().map(snapshot).collect::<Vec<_>>();
+    match value {
+        Value::List(items) => Value::List(Rc::new(RefCell::new(all(&items.borrow())))),
+        Value::Set(items) => Value::Set(Rc::new(RefCell::new(all(&items.borrow())))),
+        Value::Map(entries) => Value::Map(Rc::new(RefCell::new(
+            entries
+                .borrow()
+                .iter()
+                .map(|(k, v)| (snapshot(k), snapshot(v)))
+                .collect(),
+        ))),
+        Value::Tuple(items) => Value::Tuple(all(items).into()),
+        Value::Struct(instance) => Value::Struct(Rc::new(Instance {
+            ty: instance.ty,
+            fields: RefCell::new(all(&instance.fields.borrow())),
+        })),
+        Value::Variant(name, items) => Value::Variant(name.clone(), all(items).into()),
+        other => other.clone(),
+    }
+}
+
+fn field<'p>(receiver: &Value<'p>, name: &str) -> Result<Value<'p>, String> {
+    match receiver {
+        Value::Struct(instance) => {
+            let index = field_index(instance, name)?;
+            Ok(instance.fields.borrow()[index].clone())
+        }
+        Value::Tuple(values) => name
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| values.get(i).cloned())
+            .ok_or_else(|| no_field(receiver, name)),
+        _ => Err(no_field(receiver, name)),
+    }
+}
+
+fn field_index(instance: &Instance, name: &str) -> Result<usize, String> {
+    let info = instance.ty;
+    info.fields
+        .iter()
+        .position(|f| f == name)
+        .ok_or_else(|| format!("`{}` has no field `{}`", info.name, name))
+}
+
+fn no_field(receiver: &Value, name: &str) -> String {
+    format!("`{}` has no field `{}`", receiver, name)
+}
+
+/// What a `for` loop with `count` names visits: single values, or tuples
+/// with one element per name.
+fn elements<'p>(iterable: &Value<'p>, count: usize) -> Result<Vec<Value<'p>>, String> {
+    let items: Vec<Value<'p>> = match iterable {
+        Value::List(items) | Value::Set(items) => items.borrow().clone(),
+        Value::Map(entries) if count == 2 => {
+            let entries = entries.borrow();
+            return Ok(entries
+                .iter()
+                .map(|(k, v)| Value::Tuple(Rc::new([k.clone(), v.clone()])))
+                .collect());
+        }
+        Value::Map(entries) => entries.borrow().iter().map(|(k, _)| k.clone()).collect(),
+        Value::String(s) => s
+            .chars()
+            .map(|c| Value::String(c.to_string().into()))
+            .collect(),
+        other => return Err(format!("cannot loop over `{}`", other)),
+    };
+    if count > 1 {
+        if let Some(item) = items
+            .iter()
+            .find(|item| !matches!(item, Value::Tuple(values) if values.len() == count))
+        {
+            return Err(format!(
+                "cannot split `{}` into {} loop variables",
+                item, count
+            ));
+        }
+    }
+    Ok(items)
+}
+
+/// Strings print as they are at the top level and quoted inside collections.
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl fmt::Debug for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, values: &[Value]) -> fmt::Result {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{:?}", value)?;
+            }
+            Ok(())
+        }
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Unit => write!(f, "()"),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::List(values) => {
+                write!(f, "[")?;
+                list(f, &values.borrow())?;
+                write!(f, "]")
+            }
+            Value::Set(values) => {
+                write!(f, "{{")?;
+                list(f, &values.borrow())?;
+                write!(f, "}}")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {:?}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                list(f, values)?;
+                write!(f, ")")
+            }
+            Value::Struct(instance) => {
+                write!(f, "{} {{ ", instance.ty.name)?;
+                let fields = instance.fields.borrow();
+                for (i, (name, value)) in instance.ty.fields.iter().zip(fields.iter()).enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {:?}", name, value)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Variant(name, values) if values.is_empty() => write!(f, "{}", name),
+            Value::Variant(name, values) => {
+                write!(f, "{}(", name)?;
+                list(f, values)?;
+                write!(f, ")")
+            }
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::Builtin(_) => write!(f, "<builtin>"),
+            Value::Type(_) => write!(f, "<type>"),
+            Value::Cell(cell) => write!(f, "{:?}", cell.borrow()),
+            Value::Iterator(_) => write!(f, "<iterator>"),
+        }
+    }
+}
@@ -0,0 +1,911 @@
+/// The magic number and version every WebAssembly binary starts with.
+const HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+/// The bytes of one page of linear memory.
+pub const PAGE: usize = 65536;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+/// A function the host provides.
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub ty: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub ty: u32,
+    /// Locals besides the parameters.
+    pub locals: Vec<ValType>,
+    /// The body, ending with `End`.
+    pub code: Vec<Instr>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Global {
+    pub ty: ValType,
+    pub mutable: bool,
+    pub init: Instr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportKind {
+    Function,
+    Memory,
+    Global,
+}
+
+#[derive(Clone, Debug)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExportKind,
+    pub index: u32,
+}
+
+/// Bytes copied into memory at `offset` when the module is instantiated.
+#[derive(Clone, Debug)]
+pub struct Data {
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// A WebAssembly module with the sections chop uses. Functions are indexed
+/// imports first.
+#[derive(Clone, Debug, Default)]
+pub struct Module {
+    pub types: Vec<FuncType>,
+    pub imports: Vec<Import>,
+    pub functions: Vec<Function>,
+    pub memory: Option<Limits>,
+    pub globals: Vec<Global>,
+    pub exports: Vec<Export>,
+    pub data: Vec<Data>,
+    /// Custom sections by name.
+    pub customs: Vec<(String, Vec<u8>)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockType {
+    Empty,
+    Value(ValType),
+}
+
+/// The offset and alignment of a load or store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemArg {
+    pub align: u32,
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Nop,
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    /// `return_call` from the tail call proposal: returns what the callee
+    /// returns, with the callee taking the place of the caller's frame.
+    ReturnCall(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// A load or store, by its opcode.
+    Memory(u8, MemArg),
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    /// An instruction without immediates, by its opcode.
+    Numeric(u8),
+}
+
+/// Opcodes of the loads and stores.
+pub mod memory {
+    pub const I32_LOAD: u8 = 0x28;
+    pub const I64_LOAD: u8 = 0x29;
+    pub const F64_LOAD: u8 = 0x2b;
+    pub const I32_LOAD8_U: u8 = 0x2d;
+    pub const I32_STORE: u8 = 0x36;
+    pub const I64_STORE: u8 = 0x37;
+    pub const F64_STORE: u8 = 0x39;
+    pub const I32_STORE8: u8 = 0x3a;
+}
+
+/// Opcodes of the numeric instructions.
+pub mod op {
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_EQ: u8 = 0x46;
+    pub const I32_NE: u8 = 0x47;
+    pub const I32_LT_S: u8 = 0x48;
+    pub const I32_LT_U: u8 = 0x49;
+    pub const I32_GT_S: u8 = 0x4a;
+    pub const I32_GT_U: u8 = 0x4b;
+    pub const I32_LE_S: u8 = 0x4c;
+    pub const I32_LE_U: u8 = 0x4d;
+    pub const I32_GE_S: u8 = 0x4e;
+    pub const I32_GE_U: u8 = 0x4f;
+    pub const I64_EQZ: u8 = 0x50;
+    pub const I64_EQ: u8 = 0x51;
+    pub const I64_NE: u8 = 0x52;
+    pub const I64_LT_S: u8 = 0x53;
+    pub const I64_LT_U: u8 = 0x54;
+    pub const I64_GT_S: u8 = 0x55;
+    pub const I64_GT_U: u8 = 0x56;
+    pub const I64_LE_S: u8 = 0x57;
+    pub const I64_LE_U: u8 = 0x58;
+    pub const I64_GE_S: u8 = 0x59;
+    pub const I64_GE_U: u8 = 0x5a;
+    pub const F64_EQ: u8 = 0x61;
+    pub const F64_NE: u8 = 0x62;
+    pub const F64_LT: u8 = 0x63;
+    pub const F64_GT: u8 = 0x64;
+    pub const F64_LE: u8 = 0x65;
+    pub const F64_GE: u8 = 0x66;
+    pub const I32_ADD: u8 = 0x6a;
+    pub const I32_SUB: u8 = 0x6b;
+    pub const I32_MUL: u8 = 0x6c;
+    pub const I32_DIV_S: u8 = 0x6d;
+    pub const I32_DIV_U: u8 = 0x6e;
+    pub const I32_REM_S: u8 = 0x6f;
+    pub const I32_REM_U: u8 = 0x70;
+    pub const I32_AND: u8 = 0x71;
+    pub const I32_OR: u8 = 0x72;
+    pub const I32_XOR: u8 = 0x73;
+    pub const I32_SHL: u8 = 0x74;
+    pub const I32_SHR_S: u8 = 0x75;
+    pub const I32_SHR_U: u8 = 0x76;
+    pub const I64_ADD: u8 = 0x7c;
+    pub const I64_SUB: u8 = 0x7d;
+    pub const I64_MUL: u8 = 0x7e;
+    pub const I64_DIV_S: u8 = 0x7f;
+    pub const I64_DIV_U: u8 = 0x80;
+    pub const I64_REM_S: u8 = 0x81;
+    pub const I64_REM_U: u8 = 0x82;
+    pub const I64_AND: u8 = 0x83;
+    pub const I64_OR: u8 = 0x84;
+    pub const I64_XOR: u8 = 0x85;
+    pub const I64_SHL: u8 = 0x86;
+    pub const I64_SHR_S: u8 = 0x87;
+    pub const I64_SHR_U: u8 = 0x88;
+    pub const F64_ABS: u8 = 0x99;
+    pub const F64_NEG: u8 = 0x9a;
+    pub const F64_SQRT: u8 = 0x9f;
+    pub const F64_ADD: u8 = 0xa0;
+    pub const F64_SUB: u8 = 0xa1;
+    pub const F64_MUL: u8 = 0xa2;
+    pub const F64_DIV: u8 = 0xa3;
+    pub const F64_MIN: u8 = 0xa4;
+    pub const F64_MAX: u8 = 0xa5;
+    pub const I32_WRAP_I64: u8 = 0xa7;
+    pub const I64_EXTEND_I32_S: u8 = 0xac;
+    pub const I64_EXTEND_I32_U: u8 = 0xad;
+    pub const F64_CONVERT_I32_S: u8 = 0xb7;
+    pub const F64_CONVERT_I64_S: u8 = 0xb9;
+    pub const I64_REINTERPRET_F64: u8 = 0xbd;
+    pub const F64_REINTERPRET_I64: u8 = 0xbf;
+}
+
+/// The operand and result types of a numeric instruction, or `None` for
+/// an opcode this crate does not implement.
+pub fn numeric_type(opcode: u8) -> Option<(&'static [ValType], Option<ValType>)> {
+    use ValType::{F64, I32, I64};
+    Some(match opcode {
+        op::I32_EQZ => (&[I32], Some(I32)),
+        op::I32_EQ..=op::I32_GE_U => (&[I32, I32], Some(I32)),
+        op::I64_EQZ => (&[I64], Some(I32)),
+        op::I64_EQ..=op::I64_GE_U => (&[I64, I64], Some(I32)),
+        op::F64_EQ..=op::F64_GE => (&[F64, F64], Some(I32)),
+        op::I32_ADD..=op::I32_SHR_U => (&[I32, I32], Some(I32)),
+        op::I64_ADD..=op::I64_SHR_U => (&[I64, I64], Some(I64)),
+        op::F64_ABS | op::F64_NEG | op::F64_SQRT => (&[F64], Some(F64)),
+        op::F64_ADD..=op::F64_MAX => (&[F64, F64], Some(F64)),
+        op::I32_WRAP_I64 => (&[I64], Some(I32)),
+        op::I64_EXTEND_I32_S | op::I64_EXTEND_I32_U => (&[I32], Some(I64)),
+        op::F64_CONVERT_I32_S => (&[I32], Some(F64)),
+        op::F64_CONVERT_I64_S => (&[I64], Some(F64)),
+        op::I64_REINTERPRET_F64 => (&[F64], Some(I64)),
+        op::F64_REINTERPRET_I64 => (&[I64], Some(F64)),
+        _ => return None,
+    })
+}
+
+/// The type a load produces or a store consumes, and how many bytes it
+/// touches.
+pub fn memory_type(opcode: u8) -> Option<(ValType, bool, u32)> {
+    use memory::*;
+    Some(match opcode {
+        I32_LOAD => (ValType::I32, false, 4),
+        I64_LOAD => (ValType::I64, false, 8),
+        F64_LOAD => (ValType::F64, false, 8),
+        I32_LOAD8_U => (ValType::I32, false, 1),
+        I32_STORE => (ValType::I32, true, 4),
+        I64_STORE => (ValType::I64, true, 8),
+        F64_STORE => (ValType::F64, true, 8),
+        I32_STORE8 => (ValType::I32, true, 1),
+        _ => return None,
+    })
+}
+
+impl Module {
+    /// The type of a function, counting imports first.
+    pub fn function_type(&self, index: u32) -> Option<&FuncType> {
+        let index = index as usize;
+        let ty = match self.imports.get(index) {
+            Some(import) => import.ty,
+            None => self.functions.get(index - self.imports.len())?.ty,
+        };
+        self.types.get(ty as usize)
+    }
+
+    pub fn custom(&self, name: &str) -> Option<&[u8]> {
+        self.customs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, bytes)| bytes.as_slice())
+    }
+
+    /// The binary format of the module.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = HEADER.to_vec();
+        section(&mut out, 1, self.types.len(), |s| {
+            for ty in &self.types {
+                s.push(0x60);
+                s.vec(&ty.params, |s, t| s.valtype(*t));
+                s.vec(&ty.results, |s, t| s.valtype(*t));
+            }
+        });
+        section(&mut out, 2, self.imports.len(), |s| {
+            for import in &self.imports {
+                s.name(&import.module);
+                s.name(&import.name);
+                s.push(0x00);
+                s.u32(import.ty);
+            }
+        });
+        section(&mut out, 3, self.functions.len(), |s| {
+            for function in &self.functions {
+                s.u32(function.ty);
+            }
+        });
+        if let Some(limits) = self.memory {
+            section(&mut out, 5, 1, |s| s.limits(limits));
+        }
+        section(&mut out, 6, self.globals.len(), |s| {
+            for global in &self.globals {
+                s.valtype(global.ty);
+                s.push(global.mutable as u8);
+                s.instr(&global.init);
+                s.instr(&Instr::End);
+            }
+        });
+        section(&mut out, 7, self.exports.len(), |s| {
+            for export in &self.exports {
+                s.name(&export.name);
+                s.push(match export.kind {
+                    ExportKind::Function => 0x00,
+                    ExportKind::Memory => 0x02,
+                    ExportKind::Global => 0x03,
+                });
+                s.u32(export.index);
+            }
+        });
+        section(&mut out, 10, self.functions.len(), |s| {
+            for function in &self.functions {
+                let mut body = Encoder(Vec::new());
+                let mut groups: Vec<(u32, ValType)> = Vec::new();
+                for local in &function.locals {
+                    match groups.last_mut() {
+                        Some((count, ty)) if ty == local => *count += 1,
+                        _ => groups.push((1, *local)),
+                    }
+                }
+                body.vec(&groups, |s, (count, ty)| {
+                    s.u32(*count);
+                    s.valtype(*ty);
+                });
+                for instr in &function.code {
+                    body.instr(instr);
+                }
+                s.u32(body.0.len() as u32);
+                s.0.extend(body.0);
+            }
+        });
+        section(&mut out, 11, self.data.len(), |s| {
+            for data in &self.data {
+                s.push(0x00);
+                s.instr(&Instr::I32Const(data.offset as i32));
+                s.instr(&Instr::End);
+                s.u32(data.bytes.len() as u32);
+                s.0.extend(&data.bytes);
+            }
+        });
+        for (name, bytes) in &self.customs {
+            let mut s = Encoder(Vec::new());
+            s.name(name);
+            s.0.extend(bytes);
+            out.push(0);
+            out.extend(length(s.0.len()));
+            out.extend(s.0);
+        }
+        out
+    }
+
+    /// Reads a module in the binary format. Sections and instructions this
+    /// crate does not implement are rejected.
+    pub fn decode(bytes: &[u8]) -> Result<Module, String> {
+        if bytes.len() < HEADER.len() || bytes[..4] != HEADER[..4] {
+            return Err("not a WebAssembly module".to_string());
+        }
+        if bytes[4..8] != HEADER[4..] {
+            return Err("unsupported WebAssembly version".to_string());
+        }
+        let mut reader = Decoder { bytes, at: 8 };
+        let mut module = Module::default();
+        let mut declared = Vec::new();
+        let mut last = 0;
+        while !reader.done() {
+            let id = reader.byte()?;
+            let size = reader.u32()? as usize;
+            let end = reader
+                .at
+                .checked_add(size)
+                .filter(|end| *end <= bytes.len());
+            let Some(end) = end else {
+                return Err("a section runs past the end of the module".to_string());
+            };
+            let mut s = Decoder {
+                bytes: &bytes[..end],
+                at: reader.at,
+            };
+            reader.at = end;
+            if id != 0 {
+                if id <= last {
+                    return Err(format!("section {} is out of order or repeated", id));
+                }
+                last = id;
+            }
+            match id {
+                0 => {
+                    let name = s.name()?;
+                    module.customs.push((name, s.bytes[s.at..].to_vec()));
+                    s.at = end;
+                }
+                1 => {
+                    module.types = s.vec(|s| {
+                        if s.byte()? != 0x60 {
+                            return Err("malformed function type".to_string());
+                        }
+                        Ok(FuncType {
+                            params: s.vec(Decoder::valtype)?,
+                            results: s.vec(Decoder::valtype)?,
+                        })
+                    })?
+                }
+                2 => {
+                    module.imports = s.vec(|s| {
+                        let module = s.name()?;
+                        let name = s.name()?;
+                        if s.byte()? != 0x00 {
+                            return Err(format!("import {}.{} is not a function", module, name));
+                        }
+                        let ty = s.u32()?;
+                        Ok(Import { module, name, ty })
+                    })?
+                }
+                3 => declared = s.vec(Decoder::u32)?,
+                5 => {
+                    let memories = s.vec(Decoder::limits)?;
+                    if memories.len() > 1 {
+                        return Err("a module has at most one memory".to_string());
+                    }
+                    module.memory = memories.first().copied();
+                }
+                6 => {
+                    module.globals = s.vec(|s| {
+                        let ty = s.valtype()?;
+                        let mutable = match s.byte()? {
+                            0 => false,
+                            1 => true,
+                            _ => return Err("malformed global mutability".to_string()),
+                        };
+                        let init = s.constant()?;
+                        Ok(Global { ty, mutable, init })
+                    })?
+                }
+                7 => {
+                    module.exports = s.vec(|s| {
+                        let name = s.name()?;
+                        let kind = match s.byte()? {
+                            0x00 => ExportKind::Function,
+                            0x02 => ExportKind::Memory,
+                            0x03 => ExportKind::Global,
+                            _ => return Err(format!("export `{}` has an unsupported kind", name)),
+                        };
+                        let index = s.u32()?;
+                        Ok(Export { name, kind, index })
+                    })?
+                }
+                10 => {
+                    let bodies = s.vec(|s| {
+                        let size = s.u32()? as usize;
+                        let end = s.at.checked_add(size).filter(|end| *end <= s.bytes.len());
+                        let Some(end) = end else {
+                            return Err("a function body runs past its section".to_string());
+                        };
+                        let mut body = Decoder {
+                            bytes: &s.bytes[..end],
+                            at: s.at,
+                        };
+                        s.at = end;
+                        let mut locals = Vec::new();
+                        for (count, ty) in body.vec(|s| Ok((s.u32()?, s.valtype()?)))? {
+                            if locals.len() + count as usize > 50_000 {
+                                return Err("a function has too many locals".to_string());
+                            }
+                            locals.extend(std::iter::repeat_n(ty, count as usize));
+                        }
+                        let code = body.expression()?;
+                        if !body.done() {
+                            return Err("a function body has bytes after its end".to_string());
+                        }
+                        Ok((locals, code))
+                    })?;
+                    if bodies.len() != declared.len() {
+                        return Err(format!(
+                            "{} functions are declared but {} have bodies",
+                            declared.len(),
+                            bodies.len()
+                        ));
+                    }
+                    module.functions = declared
+                        .iter()
+                        .zip(bodies)
+                        .map(|(ty, (locals, code))| Function {
+                            ty: *ty,
+                            locals,
+                            code,
+                        })
+                        .collect();
+                }
+                11 => {
+                    module.data = s.vec(|s| {
+                        if s.u32()? != 0 {
+                            return Err("only active data for memory 0 is supported".to_string());
+                        }
+                        let offset = match s.constant()? {
+                            Instr::I32Const(offset) => offset as u32,
+                            _ => return Err("data offsets must be constants".to_string()),
+                        };
+                        let len = s.u32()? as usize;
+                        let bytes = s.take(len)?.to_vec();
+                        Ok(Data { offset, bytes })
+                    })?
+                }
+                _ => return Err(format!("unsupported section {}", id)),
+            }
+            if !s.done() {
+                return Err(format!("section {} has bytes after its contents", id));
+            }
+        }
+        if module.functions.len() != declared.len() {
+            return Err("function bodies are missing".to_string());
+        }
+        Ok(module)
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, count: usize, contents: impl FnOnce(&mut Encoder)) {
+    if count == 0 {
+        return;
+    }
+    let mut s = Encoder(Vec::new());
+    s.u32(count as u32);
+    contents(&mut s);
+    out.push(id);
+    out.extend(length(s.0.len()));
+    out.extend(s.0);
+}
+
+/// The encoding of a section or body size.
+fn length(len: usize) -> Vec<u8> {
+    let mut s = Encoder(Vec::new());
+    s.u32(len as u32);
+    s.0
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn push(&mut self, byte: u8) {
+        self.0.push(byte);
+    }
+
+    fn u32(&mut self, mut value: u32) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.push(byte);
+                return;
+            }
+            self.push(byte | 0x80);
+        }
+    }
+
+    fn i64(&mut self, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            if done {
+                self.push(byte);
+                return;
+            }
+            self.push(byte | 0x80);
+        }
+    }
+
+    fn name(&mut self, name: &str) {
+        self.u32(name.len() as u32);
+        self.0.extend(name.as_bytes());
+    }
+
+    fn vec<T>(&mut self, items: &[T], mut each: impl FnMut(&mut Self, &T)) {
+        self.u32(items.len() as u32);
+        for item in items {
+            each(self, item);
+        }
+    }
+
+    fn valtype(&mut self, ty: ValType) {
+        self.push(match ty {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F64 => 0x7c,
+        });
+    }
+
+    fn limits(&mut self, limits: Limits) {
+        match limits.max {
+            None => {
+                self.push(0x00);
+                self.u32(limits.min);
+            }
+            Some(max) => {
+                self.push(0x01);
+                self.u32(limits.min);
+                self.u32(max);
+            }
+        }
+    }
+
+    fn block_type(&mut self, ty: BlockType) {
+        match ty {
+            BlockType::Empty => self.push(0x40),
+            BlockType::Value(ty) => self.valtype(ty),
+        }
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        match instr {
+            Instr::Unreachable => self.push(0x00),
+            Instr::Nop => self.push(0x01),
+            Instr::Block(ty) => {
+                self.push(0x02);
+                self.block_type(*ty);
+            }
+            Instr::Loop(ty) => {
+                self.push(0x03);
+                self.block_type(*ty);
+            }
+            Instr::If(ty) => {
+                self.push(0x04);
+                self.block_type(*ty);
+            }
+            Instr::Else => self.push(0x05),
+            Instr::End => self.push(0x0b),
+            Instr::Br(depth) => {
+                self.push(0x0c);
+                self.u32(*depth);
+            }
+            Instr::BrIf(depth) => {
+                self.push(0x0d);
+                self.u32(*depth);
+            }
+            Instr::BrTable(depths, default) => {
+                self.push(0x0e);
+                self.vec(depths, |s, d| s.u32(*d));
+                self.u32(*default);
+            }
+            Instr::Return => self.push(0x0f),
+            Instr::Call(index) => {
+                self.push(0x10);
+                self.u32(*index);
+            }
+            Instr::ReturnCall(index) => {
+                self.push(0x12);
+                self.u32(*index);
+            }
+            Instr::Drop => self.push(0x1a),
+            Instr::Select => self.push(0x1b),
+            Instr::LocalGet(index) => {
+                self.push(0x20);
+                self.u32(*index);
+            }
+            Instr::LocalSet(index) => {
+                self.push(0x21);
+                self.u32(*index);
+            }
+            Instr::LocalTee(index) => {
+                self.push(0x22);
+                self.u32(*index);
+            }
+            Instr::GlobalGet(index) => {
+                self.push(0x23);
+                self.u32(*index);
+            }
+            Instr::GlobalSet(index) => {
+                self.push(0x24);
+                self.u32(*index);
+            }
+            Instr::Memory(opcode, arg) => {
+                self.push(*opcode);
+                self.u32(arg.align);
+                self.u32(arg.offset);
+            }
+            Instr::MemorySize => {
+                self.push(0x3f);
+                self.push(0x00);
+            }
+            Instr::MemoryGrow => {
+                self.push(0x40);
+                self.push(0x00);
+            }
+            Instr::I32Const(value) => {
+                self.push(0x41);
+                self.i64(*value as i64);
+            }
+            Instr::I64Const(value) => {
+                self.push(0x42);
+                self.i64(*value);
+            }
+            Instr::F64Const(value) => {
+                self.push(0x44);
+                self.0.extend(value.to_le_bytes());
+            }
+            Instr::Numeric(opcode) => self.push(*opcode),
+        }
+    }
+}
+
+struct Decoder<'b> {
+    bytes: &'b [u8],
+    at: usize,
+}
+
+impl<'b> Decoder<'b> {
+    fn done(&self) -> bool {
+        self.at >= self.bytes.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'b [u8], String> {
+        let end = self
+            .at
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err("the module ends too early".to_string());
+        };
+        let bytes = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut value: u64 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return u32::try_from(value).map_err(|_| "an integer is too large".to_string());
+            }
+        }
+        Err("an integer is too long".to_string())
+    }
+
+    fn signed(&mut self, bits: u32) -> Result<i64, String> {
+        let mut value: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= bits.div_ceil(7) * 7 {
+                return Err("an integer is too long".to_string());
+            }
+            value |= ((byte & 0x7f) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                let (min, max) = if bits == 64 {
+                    (i64::MIN, i64::MAX)
+                } else {
+                    (i32::MIN as i64, i32::MAX as i64)
+                };
+                if value < min || value > max {
+                    return Err("an integer is too large".to_string());
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "a name is not UTF-8".to_string())
+    }
+
+    fn vec<T>(
+        &mut self,
+        mut each: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let count = self.u32()? as usize;
+        // Every item takes at least a byte, so a count larger than what
+        // is left is malformed and must not be allocated for.
+        if count > self.bytes.len() - self.at {
+            return Err("a vector is longer than its section".to_string());
+        }
+        let mut items = Vec::with_capacity(count);
+        for _ in 0..count {
+            items.push(each(self)?);
+        }
+        Ok(items)
+    }
+
+    fn valtype(&mut self) -> Result<ValType, String> {
+        match self.byte()? {
+            0x7f => Ok(ValType::I32),
+            0x7e => Ok(ValType::I64),
+            0x7c => Ok(ValType::F64),
+            other => Err(format!("unsupported value type 0x{:02x}", other)),
+        }
+    }
+
+    fn limits(&mut self) -> Result<Limits, String> {
+        match self.byte()? {
+            0x00 => Ok(Limits {
+                min: self.u32()?,
+                max: None,
+            }),
+            0x01 => Ok(Limits {
+                min: self.u32()?,
+                max: Some(self.u32()?),
+            }),
+            _ => Err("malformed limits".to_string()),
+        }
+    }
+
+    fn block_type(&mut self) -> Result<BlockType, String> {
+        if self.bytes.get(self.at) == Some(&0x40) {
+            self.at += 1;
+            return Ok(BlockType::Empty);
+        }
+        Ok(BlockType::Value(self.valtype()?))
+    }
+
+    /// A constant expression: one constant or `global.get`, then `end`.
+    fn constant(&mut self) -> Result<Instr, String> {
+        let instr = self.instr()?;
+        if !matches!(
+            instr,
+            Instr::I32Const(_) | Instr::I64Const(_) | Instr::F64Const(_) | Instr::GlobalGet(_)
+        ) || self.instr()? != Instr::End
+        {
+            return Err("malformed constant expression".to_string());
+        }
+        Ok(instr)
+    }
+
+    /// Instructions up to the `end` closing the body.
+    fn expression(&mut self) -> Result<Vec<Instr>, String> {
+        let mut code = Vec::new();
+        let mut open = 1;
+        while open > 0 {
+            let instr = self.instr()?;
+            match instr {
+                Instr::Block(_) | Instr::Loop(_) | Instr::If(_) => open += 1,
+                Instr::End => open -= 1,
+                _ => {}
+            }
+            code.push(instr);
+        }
+        Ok(code)
+    }
+
+    fn memarg(&mut self) -> Result<MemArg, String> {
+        Ok(MemArg {
+            align: self.u32()?,
+            offset: self.u32()?,
+        })
+    }
+
+    fn instr(&mut self) -> Result<Instr, String> {
+        let opcode = self.byte()?;
+        Ok(match opcode {
+            0x00 => Instr::Unreachable,
+            0x01 => Instr::Nop,
+            0x02 => Instr::Block(self.block_type()?),
+            0x03 => Instr::Loop(self.block_type()?),
+            0x04 => Instr::If(self.block_type()?),
+            0x05 => Instr::Else,
+            0x0b => Instr::End,
+            0x0c => Instr::Br(self.u32()?),
+            0x0d => Instr::BrIf(self.u32()?),
+            0x0e => Instr::BrTable(self.vec(Decoder::u32)?, self.u32()?),
+            0x0f => Instr::Return,
+            0x10 => Instr::Call(self.u32()?),
+            0x12 => Instr::ReturnCall(self.u32()?),
+            0x1a => Instr::Drop,
+            0x1b => Instr::Select,
+            0x20 => Instr::LocalGet(self.u32()?),
+            0x21 => Instr::LocalSet(self.u32()?),
+            0x22 => Instr::LocalTee(self.u32()?),
+            0x23 => Instr::GlobalGet(self.u32()?),
+            0x24 => Instr::GlobalSet(self.u32()?),
+            0x3f | 0x40 => {
+                if self.byte()? != 0 {
+                    return Err("memory instructions must name memory 0".to_string());
+                }
+                if opcode == 0x3f {
+                    Instr::MemorySize
+                } else {
+                    Instr::MemoryGrow
+                }
+            }
+            0x41 => Instr::I32Const(self.signed(32)? as i32),
+            0x42 => Instr::I64Const(self.signed(64)?),
+            0x44 => Instr::F64Const(f64::from_le_bytes(
+                self.take(8)?.try_into().expect("eight bytes"),
+            )),
+            opcode if memory_type(opcode).is_some() => Instr::Memory(opcode, self.memarg()?),
+            opcode if numeric_type(opcode).is_some() => Instr::Numeric(opcode),
+            other => return Err(format!("unsupported instruction 0x{:02x}", other)),
+        })
+    }
+}
@@ -0,0 +1,2691 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::bytecode::{Constant, Function, Op, Pattern, Program};
+use crate::c_backend::{depths, effect};
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::MAX_CALL_DEPTH;
+use crate::tokens::Span;
+use crate::wasm::memory::*;
+use crate::wasm::op::*;
+use crate::wasm::Instr::{self, *};
+use crate::wasm::{
+    BlockType, Data, Export, ExportKind, FuncType, Global, Import, Limits, MemArg, Module, ValType,
+    PAGE,
+};
+
+/// The tags in the first word of every value's box.
+const NULL: i32 = 0;
+const UNIT: i32 = 1;
+const INT: i32 = 2;
+const FLOAT: i32 = 3;
+const BOOL: i32 = 4;
+const STRING: i32 = 5;
+const LIST: i32 = 6;
+const STRUCT: i32 = 7;
+const TYPE: i32 = 8;
+const ITERATOR: i32 = 9;
+
+/// The names methods are looked up under for the tags up to `LIST`, which
+/// are also their type ids.
+const BUILTIN_TYPES: &[&str] = &["null", "()", "int", "float", "bool", "string", "list"];
+
+/// The operators of `arith` and `compare`, by the kind they take.
+const SYMBOLS: &[&str] = &["+", "-", "*", "/", "%", "<", "<=", ">", ">="];
+
+/// Scratch space for formatting ints.
+const SCRATCH: u32 = 16;
+/// The site of every call in progress, for errors that point at the caller.
+/// The first is that of `main`, which is only set by a tail call in it.
+const SITES: u32 = 48;
+/// Where the static data starts.
+const DATA: u32 = SITES + 4 * (MAX_CALL_DEPTH as u32 + 1);
+/// The most pages the heap grows to, a gigabyte.
+const MAX_PAGES: u32 = 16384;
+
+/// The host functions, imported from module `chop` in this order.
+const PRINT: u32 = 0;
+const FORMAT_FLOAT: u32 = 1;
+const FMOD: u32 = 2;
+const HOST_FAIL: u32 = 3;
+const IMPORTS: u32 = 4;
+
+/// The globals of the runtime, followed by one per chop global.
+const HEAP: u32 = 0;
+const BUF: u32 = 1;
+const BUF_LEN: u32 = 2;
+const BUF_CAP: u32 = 3;
+const DEPTH: u32 = 4;
+const GLOBALS: u32 = 5;
+
+/// Lowers a compiled program to a WebAssembly module that runs it like the
+/// virtual machine does, or explains which of its constructs the target
+/// does not support yet.
+///
+/// Every value is a pointer to a 16-byte box in linear memory: a tag, a
+/// word whose meaning depends on the tag (a length, a struct index), and
+/// the payload. Literals are boxed in the data segment and everything else
+/// on a heap that only grows. The depth of the operand stack is known at
+/// every instruction, so stack slots become locals, and jumps become a
+/// `br_table` over the function's basic blocks. Printing and runtime
+/// errors go through the host functions `chop.print` and `chop.fail`; the
+/// path of the source is kept in a `chop.source` custom section.
+pub fn generate(program: &Program, path: &str) -> Result<Module, Vec<Diagnostic>> {
+    let mut backend = Backend::new(program);
+    let main = backend.chop_functions();
+    let entry = backend.helper("main", &[], None, |_, f| {
+        f.extend([Call(main), Drop]);
+    });
+    if !backend.errors.is_empty() {
+        return Err(backend.errors);
+    }
+    Ok(backend.module(entry, path))
+}
+
+/// A function being assembled.
+struct Body {
+    params: Vec<ValType>,
+    locals: Vec<ValType>,
+    code: Vec<Instr>,
+}
+
+impl Body {
+    fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        (self.params.len() + self.locals.len() - 1) as u32
+    }
+
+    fn extend(&mut self, code: impl IntoIterator<Item = Instr>) {
+        self.code.extend(code);
+    }
+
+    fn push(&mut self, instr: Instr) {
+        self.code.push(instr);
+    }
+
+    /// Opens the dispatch loop: a block per basic block, the innermost
+    /// branching to the end of the block whose index is in `pc`.
+    fn push_loop(&mut self, blocks: u32, pc: u32) {
+        self.push(Loop(EMPTY));
+        for _ in 0..blocks {
+            self.push(Block(EMPTY));
+        }
+        self.extend([
+            LocalGet(pc),
+            BrTable((0..blocks).collect(), blocks - 1),
+            End,
+        ]);
+    }
+}
+
+/// A piece of a runtime error message.
+enum Part<'a> {
+    Text(&'a str),
+    /// The value in a local, as `print` shows it.
+    Value(u32),
+    /// The string box the instructions leave on the stack.
+    String(Vec<Instr>),
+    /// The `i64` in a local.
+    Int(u32),
+    /// The name of the type of the value in a local.
+    Type(u32),
+}
+
+struct Backend<'p> {
+    program: &'p Program,
+    /// The static data, placed at `DATA`.
+    data: Vec<u8>,
+    strings: HashMap<String, u32>,
+    sites: HashMap<Span, u32>,
+    types: Vec<FuncType>,
+    /// Functions by index after the imports; a helper's slot is reserved
+    /// before its body is built, so that it can call itself.
+    functions: Vec<Option<crate::wasm::Function>>,
+    helpers: HashMap<String, u32>,
+    /// The wasm function running each reachable chop function.
+    chop: BTreeMap<u32, u32>,
+    /// Ids of the names types are looked up by, in `CallMethod`.
+    type_ids: HashMap<String, u32>,
+    type_boxes: HashMap<String, u32>,
+    constants: Vec<u32>,
+    /// The table of `[name, field count, field names, type id]` of each
+    /// struct, and the names of the builtin types and operators.
+    structs: u32,
+    builtin_types: u32,
+    symbols: u32,
+    errors: Vec<Diagnostic>,
+}
+
+fn arg(offset: u32, align: u32) -> MemArg {
+    MemArg { align, offset }
+}
+
+fn load(offset: u32) -> Instr {
+    Memory(I32_LOAD, arg(offset, 2))
+}
+
+fn load64(offset: u32) -> Instr {
+    Memory(I64_LOAD, arg(offset, 3))
+}
+
+fn load_f64(offset: u32) -> Instr {
+    Memory(F64_LOAD, arg(offset, 3))
+}
+
+fn load8(offset: u32) -> Instr {
+    Memory(I32_LOAD8_U, arg(offset, 0))
+}
+
+fn store(offset: u32) -> Instr {
+    Memory(I32_STORE, arg(offset, 2))
+}
+
+fn store64(offset: u32) -> Instr {
+    Memory(I64_STORE, arg(offset, 3))
+}
+
+fn store_f64(offset: u32) -> Instr {
+    Memory(F64_STORE, arg(offset, 3))
+}
+
+fn store8(offset: u32) -> Instr {
+    Memory(I32_STORE8, arg(offset, 0))
+}
+
+fn n(opcode: u8) -> Instr {
+    Numeric(opcode)
+}
+
+const EMPTY: BlockType = BlockType::Empty;
+const I32: ValType = ValType::I32;
+const I64: ValType = ValType::I64;
+const F64: ValType = ValType::F64;
+
+/// Runs `body` for each `i` from zero up to the value of local `count`.
+/// Branches in `body` are two levels deeper than around the loop.
+fn each(i: u32, count: u32, body: Vec<Instr>) -> Vec<Instr> {
+    let mut code = vec![
+        I32Const(0),
+        LocalSet(i),
+        Block(EMPTY),
+        Loop(EMPTY),
+        LocalGet(i),
+        LocalGet(count),
+        n(I32_GE_U),
+        BrIf(1),
+    ];
+    code.extend(body);
+    code.extend([
+        LocalGet(i),
+        I32Const(1),
+        n(I32_ADD),
+        LocalSet(i),
+        Br(0),
+        End,
+        End,
+    ]);
+    code
+}
+
+/// The tag of the value in `local` equals `tag`.
+fn tag_is(local: u32, tag: i32) -> [Instr; 4] {
+    [LocalGet(local), load(0), I32Const(tag), n(I32_EQ)]
+}
+
+impl<'p> Backend<'p> {
+    fn new(program: &'p Program) -> Self {
+        let mut backend = Backend {
+            program,
+            data: Vec::new(),
+            strings: HashMap::new(),
+            sites: HashMap::new(),
+            types: Vec::new(),
+            functions: Vec::new(),
+            helpers: HashMap::new(),
+            chop: BTreeMap::new(),
+            type_ids: HashMap::new(),
+            type_boxes: HashMap::new(),
+            constants: Vec::new(),
+            structs: 0,
+            builtin_types: 0,
+            symbols: 0,
+            errors: Vec::new(),
+        };
+        for name in BUILTIN_TYPES {
+            backend.type_id(name);
+        }
+        let names: Vec<u32> = BUILTIN_TYPES.iter().map(|t| backend.string(t)).collect();
+        backend.builtin_types = backend.words(&names);
+        let symbols: Vec<u32> = SYMBOLS.iter().map(|s| backend.string(s)).collect();
+        backend.symbols = backend.words(&symbols);
+
+        let mut entries = Vec::new();
+        for info in &program.structs {
+            let fields: Vec<u32> = info.fields.iter().map(|f| backend.string(f)).collect();
+            let fields = backend.words(&fields);
+            let name = backend.string(&info.name);
+            let id = backend.type_id(&info.name);
+            entries.extend([name, info.fields.len() as u32, fields, id]);
+        }
+        backend.structs = backend.words(&entries);
+        backend.constants = program
+            .constants
+            .iter()
+            .map(|constant| match constant {
+                Constant::Null => backend.boxed(NULL, 0, 0),
+                Constant::Unit => backend.boxed(UNIT, 0, 0),
+                Constant::Int(i) => backend.boxed(INT, 0, *i as u64),
+                Constant::Float(x) => backend.boxed(FLOAT, 0, x.to_bits()),
+                Constant::Bool(b) => backend.boxed(BOOL, 0, *b as u64),
+                Constant::String(s) => backend.string(s),
+            })
+            .collect();
+        backend
+    }
+
+    /// The finished module, exporting `entry` as `main`.
+    fn module(self, entry: u32, path: &str) -> Module {
+        let heap = (DATA + self.data.len() as u32).next_multiple_of(8);
+        let mut globals = vec![
+            Global {
+                ty: I32,
+                mutable: true,
+                init: I32Const(heap as i32),
+            };
+            GLOBALS as usize + self.program.globals.len()
+        ];
+        for global in &mut globals[BUF as usize..] {
+            global.init = I32Const(0);
+        }
+        let mut module = Module {
+            types: self.types,
+            imports: Vec::new(),
+            functions: self
+                .functions
+                .into_iter()
+                .map(|f| f.expect("every reserved function is built"))
+                .collect(),
+            memory: Some(Limits {
+                min: heap.div_ceil(PAGE as u32),
+                max: Some(MAX_PAGES),
+            }),
+            globals,
+            exports: vec![
+                Export {
+                    name: "main".to_string(),
+                    kind: ExportKind::Function,
+                    index: entry,
+                },
+                Export {
+                    name: "memory".to_string(),
+                    kind: ExportKind::Memory,
+                    index: 0,
+                },
+            ],
+            data: vec![Data {
+                offset: DATA,
+                bytes: self.data,
+            }],
+            customs: vec![("chop.source".to_string(), path.as_bytes().to_vec())],
+        };
+        let hosts: [(&str, &[ValType], &[ValType]); 4] = [
+            ("print", &[I32, I32], &[]),
+            ("format_float", &[F64, I32], &[I32]),
+            ("fmod", &[F64, F64], &[F64]),
+            ("fail", &[I32, I32, I32, I32, I32, I32], &[]),
+        ];
+        for (name, params, results) in hosts {
+            let ty = FuncType {
+                params: params.to_vec(),
+                results: results.to_vec(),
+            };
+            let ty = match module.types.iter().position(|t| *t == ty) {
+                Some(index) => index,
+                None => {
+                    module.types.push(ty);
+                    module.types.len() - 1
+                }
+            };
+            module.imports.push(Import {
+                module: "chop".to_string(),
+                name: name.to_string(),
+                ty: ty as u32,
+            });
+        }
+        module
+    }
+
+    // Static data.
+
+    fn address(&self) -> u32 {
+        DATA + self.data.len() as u32
+    }
+
+    fn align(&mut self) {
+        while !self.data.len().is_multiple_of(8) {
+            self.data.push(0);
+        }
+    }
+
+    fn words(&mut self, words: &[u32]) -> u32 {
+        self.align();
+        let address = self.address();
+        for word in words {
+            self.data.extend(word.to_le_bytes());
+        }
+        address
+    }
+
+    fn boxed(&mut self, tag: i32, aux: u32, payload: u64) -> u32 {
+        self.align();
+        let address = self.address();
+        self.data.extend(tag.to_le_bytes());
+        self.data.extend(aux.to_le_bytes());
+        self.data.extend(payload.to_le_bytes());
+        address
+    }
+
+    /// A boxed string, followed by its bytes.
+    fn string(&mut self, text: &str) -> u32 {
+        if let Some(&address) = self.strings.get(text) {
+            return address;
+        }
+        let address = self.boxed(STRING, text.len() as u32, 0);
+        let bytes = address + 16;
+        self.data[(address + 8 - DATA) as usize..(address + 12 - DATA) as usize]
+            .copy_from_slice(&bytes.to_le_bytes());
+        self.data.extend(text.as_bytes());
+        self.strings.insert(text.to_string(), address);
+        address
+    }
+
+    /// The span of an instruction, as four words `fail` passes to the host.
+    fn site(&mut self, span: Span) -> i32 {
+        if let Some(&address) = self.sites.get(&span) {
+            return address as i32;
+        }
+        let address = self.words(&[
+            span.start.0,
+            span.start.1 as u32,
+            span.end.0,
+            span.end.1 as u32,
+        ]);
+        self.sites.insert(span, address);
+        address as i32
+    }
+
+    fn type_id(&mut self, name: &str) -> u32 {
+        let next = self.type_ids.len() as u32;
+        *self.type_ids.entry(name.to_string()).or_insert(next)
+    }
+
+    /// The value of `Type(name)`: its id, and its name for messages.
+    fn type_box(&mut self, name: &str) -> u32 {
+        if let Some(&address) = self.type_boxes.get(name) {
+            return address;
+        }
+        let id = self.type_id(name);
+        let text = self.string(name);
+        let address = self.boxed(TYPE, id, text as u64);
+        self.type_boxes.insert(name.to_string(), address);
+        address
+    }
+
+    // Functions.
+
+    fn type_index(&mut self, params: &[ValType], result: Option<ValType>) -> u32 {
+        let ty = FuncType {
+            params: params.to_vec(),
+            results: result.into_iter().collect(),
+        };
+        match self.types.iter().position(|t| *t == ty) {
+            Some(index) => index as u32,
+            None => {
+                self.types.push(ty);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+
+    fn reserve(&mut self) -> u32 {
+        self.functions.push(None);
+        IMPORTS + self.functions.len() as u32 - 1
+    }
+
+    fn define(&mut self, index: u32, result: Option<ValType>, mut body: Body) {
+        body.code.push(End);
+        let ty = self.type_index(&body.params, result);
+        self.functions[(index - IMPORTS) as usize] = Some(crate::wasm::Function {
+            ty,
+            locals: body.locals,
+            code: body.code,
+        });
+    }
+
+    /// The function `key` names, built by `build` the first time it is
+    /// needed.
+    fn helper(
+        &mut self,
+        key: &str,
+        params: &[ValType],
+        result: Option<ValType>,
+        build: impl FnOnce(&mut Self, &mut Body),
+    ) -> u32 {
+        if let Some(&index) = self.helpers.get(key) {
+            return index;
+        }
+        let index = self.reserve();
+        self.helpers.insert(key.to_string(), index);
+        let mut body = Body {
+            params: params.to_vec(),
+            locals: Vec::new(),
+            code: Vec::new(),
+        };
+        build(self, &mut body);
+        self.define(index, result, body);
+        index
+    }
+
+    /// Instructions appending a piece of text to the buffer.
+    fn text(&mut self, text: &str) -> [Instr; 2] {
+        let string = self.string(text);
+        [I32Const(string as i32), Call(self.buf_str())]
+    }
+
+    /// Instructions stopping the program with a message at the site the
+    /// instructions in `site` leave on the stack.
+    fn fail_with(&mut self, parts: Vec<Part>, site: Vec<Instr>) -> Vec<Instr> {
+        let mut code = vec![I32Const(0), GlobalSet(BUF_LEN)];
+        for part in parts {
+            match part {
+                Part::Text(text) => code.extend(self.text(text)),
+                Part::Value(local) => {
+                    code.extend([LocalGet(local), I32Const(0), Call(self.show())])
+                }
+                Part::String(string) => {
+                    code.extend(string);
+                    code.push(Call(self.buf_str()));
+                }
+                Part::Int(local) => code.extend([LocalGet(local), Call(self.buf_int())]),
+                Part::Type(local) => code.extend([LocalGet(local), Call(self.buf_type())]),
+            }
+        }
+        code.extend(site);
+        code.extend([Call(self.fail()), Unreachable]);
+        code
+    }
+
+    // The runtime.
+
+    /// `alloc(size)`: the address of `size` fresh bytes, aligned to eight.
+    fn alloc(&mut self) -> u32 {
+        self.helper("alloc", &[I32], Some(I32), |_, f| {
+            let (p, end) = (f.local(I32), f.local(I32));
+            f.extend([
+                GlobalGet(HEAP),
+                LocalTee(p),
+                LocalGet(0),
+                I32Const(7),
+                n(I32_ADD),
+                I32Const(-8),
+                n(I32_AND),
+                n(I32_ADD),
+                LocalTee(end),
+                MemorySize,
+                I32Const(16),
+                n(I32_SHL),
+                n(I32_GT_U),
+                If(EMPTY),
+                LocalGet(end),
+                I32Const(PAGE as i32 - 1),
+                n(I32_ADD),
+                I32Const(16),
+                n(I32_SHR_U),
+                MemorySize,
+                n(I32_SUB),
+                MemoryGrow,
+                I32Const(-1),
+                n(I32_EQ),
+                // Out of memory.
+                If(EMPTY),
+                Unreachable,
+                End,
+                End,
+                LocalGet(end),
+                GlobalSet(HEAP),
+                LocalGet(p),
+            ]);
+        })
+    }
+
+    fn box_int(&mut self) -> u32 {
+        self.helper("box_int", &[I64], Some(I32), |this, f| {
+            let p = f.local(I32);
+            f.extend([
+                I32Const(16),
+                Call(this.alloc()),
+                LocalTee(p),
+                I32Const(INT),
+                store(0),
+                LocalGet(p),
+                LocalGet(0),
+                store64(8),
+                LocalGet(p),
+            ]);
+        })
+    }
+
+    fn box_float(&mut self) -> u32 {
+        self.helper("box_float", &[F64], Some(I32), |this, f| {
+            let p = f.local(I32);
+            f.extend([
+                I32Const(16),
+                Call(this.alloc()),
+                LocalTee(p),
+                I32Const(FLOAT),
+                store(0),
+                LocalGet(p),
+                LocalGet(0),
+                store_f64(8),
+                LocalGet(p),
+            ]);
+        })
+    }
+
+    /// `bool(b)`: the boxed `true` or `false`.
+    fn bool(&mut self) -> u32 {
+        let yes = self.boxed(BOOL, 0, 1) as i32;
+        let no = self.boxed(BOOL, 0, 0) as i32;
+        self.helper("bool", &[I32], Some(I32), |_, f| {
+            f.extend([I32Const(yes), I32Const(no), LocalGet(0), Select]);
+        })
+    }
+
+    /// `new(tag, aux, count)`: a box whose payload points at `count` words.
+    fn new_box(&mut self) -> u32 {
+        self.helper("new", &[I32, I32, I32], Some(I32), |this, f| {
+            let p = f.local(I32);
+            let alloc = this.alloc();
+            f.extend([
+                I32Const(16),
+                Call(alloc),
+                LocalTee(p),
+                LocalGet(0),
+                store(0),
+                LocalGet(p),
+                LocalGet(1),
+                store(4),
+                LocalGet(p),
+                LocalGet(2),
+                I32Const(2),
+                n(I32_SHL),
+                Call(alloc),
+                store(8),
+                LocalGet(p),
+            ]);
+        })
+    }
+
+    /// `copy(to, from, len)`.
+    fn copy(&mut self) -> u32 {
+        self.helper("copy", &[I32, I32, I32], None, |_, f| {
+            let i = f.local(I32);
+            f.extend(each(
+                i,
+                2,
+                vec![
+                    LocalGet(0),
+                    LocalGet(i),
+                    n(I32_ADD),
+                    LocalGet(1),
+                    LocalGet(i),
+                    n(I32_ADD),
+                    load8(0),
+                    store8(0),
+                ],
+            ));
+        })
+    }
+
+    /// `reserve(len)`: makes room for `len` more bytes in the buffer that
+    /// output and error messages are built in.
+    fn reserve_buf(&mut self) -> u32 {
+        self.helper("reserve", &[I32], None, |this, f| {
+            let (cap, new) = (f.local(I32), f.local(I32));
+            f.extend([
+                GlobalGet(BUF_LEN),
+                LocalGet(0),
+                n(I32_ADD),
+                GlobalGet(BUF_CAP),
+                n(I32_GT_U),
+                If(EMPTY),
+                GlobalGet(BUF_CAP),
+                I32Const(1),
+                n(I32_SHL),
+                LocalTee(cap),
+                GlobalGet(BUF_LEN),
+                LocalGet(0),
+                n(I32_ADD),
+                I32Const(256),
+                n(I32_ADD),
+                LocalTee(new),
+                LocalGet(cap),
+                LocalGet(new),
+                n(I32_GT_U),
+                Select,
+                LocalTee(cap),
+                Call(this.alloc()),
+                LocalTee(new),
+                GlobalGet(BUF),
+                GlobalGet(BUF_LEN),
+                Call(this.copy()),
+                LocalGet(new),
+                GlobalSet(BUF),
+                LocalGet(cap),
+                GlobalSet(BUF_CAP),
+                End,
+            ]);
+        })
+    }
+
+    fn buf_byte(&mut self) -> u32 {
+        self.helper("buf_byte", &[I32], None, |this, f| {
+            f.extend([
+                I32Const(1),
+                Call(this.reserve_buf()),
+                GlobalGet(BUF),
+                GlobalGet(BUF_LEN),
+                n(I32_ADD),
+                LocalGet(0),
+                store8(0),
+                GlobalGet(BUF_LEN),
+                I32Const(1),
+                n(I32_ADD),
+                GlobalSet(BUF_LEN),
+            ]);
+        })
+    }
+
+    /// `buf_bytes(ptr, len)`.
+    fn buf_bytes(&mut self) -> u32 {
+        self.helper("buf_bytes", &[I32, I32], None, |this, f| {
+            f.extend([
+                LocalGet(1),
+                Call(this.reserve_buf()),
+                GlobalGet(BUF),
+                GlobalGet(BUF_LEN),
+                n(I32_ADD),
+                LocalGet(0),
+                LocalGet(1),
+                Call(this.copy()),
+                GlobalGet(BUF_LEN),
+                LocalGet(1),
+                n(I32_ADD),
+                GlobalSet(BUF_LEN),
+            ]);
+        })
+    }
+
+    /// `buf_str(string)`: appends the bytes of a string box.
+    fn buf_str(&mut self) -> u32 {
+        self.helper("buf_str", &[I32], None, |this, f| {
+            f.extend([
+                LocalGet(0),
+                load(8),
+                LocalGet(0),
+                load(4),
+                Call(this.buf_bytes()),
+            ]);
+        })
+    }
+
+    fn buf_int(&mut self) -> u32 {
+        self.helper("buf_int", &[I64], None, |this, f| {
+            let p = f.local(I32);
+            let end = SCRATCH as i32 + 24;
+            f.extend([
+                LocalGet(0),
+                I64Const(0),
+                n(I64_LT_S),
+                If(EMPTY),
+                I32Const(b'-' as i32),
+                Call(this.buf_byte()),
+                // The digits are those of the unsigned negation, which also
+                // holds for the most negative int.
+                I64Const(0),
+                LocalGet(0),
+                n(I64_SUB),
+                LocalSet(0),
+                End,
+                I32Const(end),
+                LocalSet(p),
+                Loop(EMPTY),
+                LocalGet(p),
+                I32Const(1),
+                n(I32_SUB),
+                LocalTee(p),
+                LocalGet(0),
+                I64Const(10),
+                n(I64_REM_U),
+                n(I32_WRAP_I64),
+                I32Const(b'0' as i32),
+                n(I32_ADD),
+                store8(0),
+                LocalGet(0),
+                I64Const(10),
+                n(I64_DIV_U),
+                LocalTee(0),
+                I64Const(0),
+                n(I64_NE),
+                BrIf(0),
+                End,
+                LocalGet(p),
+                I32Const(end),
+                LocalGet(p),
+                n(I32_SUB),
+                Call(this.buf_bytes()),
+            ]);
+        })
+    }
+
+    /// Floats are formatted by the host, which writes at most 32 bytes.
+    fn buf_float(&mut self) -> u32 {
+        self.helper("buf_float", &[F64], None, |this, f| {
+            f.extend([
+                I32Const(32),
+                Call(this.reserve_buf()),
+                LocalGet(0),
+                GlobalGet(BUF),
+                GlobalGet(BUF_LEN),
+                n(I32_ADD),
+                Call(FORMAT_FLOAT),
+                GlobalGet(BUF_LEN),
+                n(I32_ADD),
+                GlobalSet(BUF_LEN),
+            ]);
+        })
+    }
+
+    /// `buf_hex(byte)`: the byte in lowercase hex, without leading zeros.
+    fn buf_hex(&mut self) -> u32 {
+        self.helper("buf_hex", &[I32], None, |this, f| {
+            let digit = f.local(I32);
+            let buf_byte = this.buf_byte();
+            let emit = |f: &mut Body| {
+                f.extend([
+                    LocalGet(digit),
+                    I32Const(b'0' as i32),
+                    n(I32_ADD),
+                    LocalGet(digit),
+                    I32Const(b'a' as i32 - 10),
+                    n(I32_ADD),
+                    LocalGet(digit),
+                    I32Const(10),
+                    n(I32_LT_U),
+                    Select,
+                    Call(buf_byte),
+                ]);
+            };
+            f.extend([
+                LocalGet(0),
+                I32Const(16),
+                n(I32_GE_U),
+                If(EMPTY),
+                LocalGet(0),
+                I32Const(4),
+                n(I32_SHR_U),
+                LocalSet(digit),
+            ]);
+            emit(f);
+            f.extend([End, LocalGet(0), I32Const(15), n(I32_AND), LocalSet(digit)]);
+            emit(f);
+        })
+    }
+
+    /// `buf_quoted(ptr, len)`: a string quoted and escaped like Rust's
+    /// `{:?}` does for ASCII.
+    fn buf_quoted(&mut self) -> u32 {
+        self.helper("buf_quoted", &[I32, I32], None, |this, f| {
+            let (i, c) = (f.local(I32), f.local(I32));
+            let buf_byte = this.buf_byte();
+            let mut body = vec![
+                LocalGet(0),
+                LocalGet(i),
+                n(I32_ADD),
+                load8(0),
+                LocalSet(c),
+                Block(EMPTY),
+            ];
+            for (byte, letter) in [
+                (b'"', b'"'),
+                (b'\\', b'\\'),
+                (b'\n', b'n'),
+                (b'\r', b'r'),
+                (b'\t', b't'),
+                (0, b'0'),
+            ] {
+                body.extend([
+                    LocalGet(c),
+                    I32Const(byte as i32),
+                    n(I32_EQ),
+                    If(EMPTY),
+                    I32Const(b'\\' as i32),
+                    Call(buf_byte),
+                    I32Const(letter as i32),
+                    Call(buf_byte),
+                    Br(1),
+                    End,
+                ]);
+            }
+            body.extend([
+                LocalGet(c),
+                I32Const(32),
+                n(I32_LT_U),
+                LocalGet(c),
+                I32Const(127),
+                n(I32_EQ),
+                n(I32_OR),
+                If(EMPTY),
+            ]);
+            body.extend(this.text("\\u{"));
+            body.extend([
+                LocalGet(c),
+                Call(this.buf_hex()),
+                I32Const(b'}' as i32),
+                Call(buf_byte),
+                Br(1),
+                End,
+                LocalGet(c),
+                Call(buf_byte),
+                End,
+            ]);
+            f.extend([I32Const(b'"' as i32), Call(buf_byte)]);
+            f.extend(each(i, 1, body));
+            f.extend([I32Const(b'"' as i32), Call(buf_byte)]);
+        })
+    }
+
+    /// `buf_type(value)`: the name methods of the value are looked up by.
+    fn buf_type(&mut self) -> u32 {
+        let (structs, builtin) = (self.structs as i32, self.builtin_types as i32);
+        self.helper("buf_type", &[I32], None, |this, f| {
+            let buf_str = this.buf_str();
+            f.extend(tag_is(0, STRUCT));
+            f.extend([
+                If(EMPTY),
+                LocalGet(0),
+                load(4),
+                I32Const(4),
+                n(I32_SHL),
+                I32Const(structs),
+                n(I32_ADD),
+                load(0),
+                Call(buf_str),
+                Return,
+                End,
+            ]);
+            f.extend(tag_is(0, TYPE));
+            f.extend([
+                If(EMPTY),
+                LocalGet(0),
+                load(8),
+                Call(buf_str),
+                Return,
+                End,
+                LocalGet(0),
+                load(0),
+                I32Const(2),
+                n(I32_SHL),
+                I32Const(builtin),
+                n(I32_ADD),
+                load(0),
+                Call(buf_str),
+            ]);
+        })
+    }
+
+    /// `show(value, quoted)`: appends a value as `print` shows it, or as it
+    /// shows inside a collection when `quoted` is set.
+    fn show(&mut self) -> u32 {
+        let structs = self.structs as i32;
+        self.helper("show", &[I32, I32], None, |this, f| {
+            let show = this.show();
+            let buf_str = this.buf_str();
+            let (tag, i, count, items, entry) = (
+                f.local(I32),
+                f.local(I32),
+                f.local(I32),
+                f.local(I32),
+                f.local(I32),
+            );
+            let comma = this.string(", ") as i32;
+            let separator = [LocalGet(i), If(EMPTY), I32Const(comma), Call(buf_str), End];
+            let item = [
+                LocalGet(items),
+                LocalGet(i),
+                I32Const(2),
+                n(I32_SHL),
+                n(I32_ADD),
+                load(0),
+                I32Const(1),
+                Call(show),
+            ];
+
+            let mut list = this.text("[").to_vec();
+            list.extend([
+                LocalGet(0),
+                load(4),
+                LocalSet(count),
+                LocalGet(0),
+                load(8),
+                LocalSet(items),
+            ]);
+            list.extend(each(i, count, [&separator[..], &item[..]].concat()));
+            list.extend(this.text("]"));
+
+            let mut instance = vec![
+                LocalGet(0),
+                load(4),
+                I32Const(4),
+                n(I32_SHL),
+                I32Const(structs),
+                n(I32_ADD),
+                LocalTee(entry),
+                load(0),
+                Call(buf_str),
+            ];
+            instance.extend(this.text(" { "));
+            instance.extend([
+                LocalGet(entry),
+                load(4),
+                LocalSet(count),
+                LocalGet(0),
+                load(8),
+                LocalSet(items),
+            ]);
+            let mut field = separator.to_vec();
+            field.extend([
+                LocalGet(entry),
+                load(8),
+                LocalGet(i),
+                I32Const(2),
+                n(I32_SHL),
+                n(I32_ADD),
+                load(0),
+                Call(buf_str),
+            ]);
+            field.extend(this.text(": "));
+            field.extend(item);
+            instance.extend(each(i, count, field));
+            instance.extend(this.text(" }"));
+
+            let yes = this.string("true") as i32;
+            let no = this.string("false") as i32;
+            let cases: Vec<(i32, Vec<Instr>)> = vec![
+                (NULL, this.text("null").to_vec()),
+                (UNIT, this.text("()").to_vec()),
+                (INT, vec![LocalGet(0), load64(8), Call(this.buf_int())]),
+                (
+                    FLOAT,
+                    vec![LocalGet(0), load_f64(8), Call(this.buf_float())],
+                ),
+                (
+                    BOOL,
+                    vec![
+                        I32Const(yes),
+                        I32Const(no),
+                        LocalGet(0),
+                        load(8),
+                        Select,
+                        Call(buf_str),
+                    ],
+                ),
+                (
+                    STRING,
+                    vec![
+                        LocalGet(1),
+                        If(EMPTY),
+                        LocalGet(0),
+                        load(8),
+                        LocalGet(0),
+                        load(4),
+                        Call(this.buf_quoted()),
+                        Else,
+                        LocalGet(0),
+                        Call(buf_str),
+                        End,
+                    ],
+                ),
+                (LIST, list),
+                (STRUCT, instance),
+                (TYPE, this.text("<type>").to_vec()),
+            ];
+            f.extend([LocalGet(0), load(0), LocalSet(tag)]);
+            for (value, code) in cases {
+                f.extend([LocalGet(tag), I32Const(value), n(I32_EQ), If(EMPTY)]);
+                f.extend(code);
+                f.extend([Return, End]);
+            }
+            f.extend(this.text("<iterator>"));
+        })
+    }
+
+    /// `flush()`: prints the buffer and empties it.
+    fn flush(&mut self) -> u32 {
+        self.helper("flush", &[], None, |_, f| {
+            f.extend([
+                GlobalGet(BUF),
+                GlobalGet(BUF_LEN),
+                Call(PRINT),
+                I32Const(0),
+                GlobalSet(BUF_LEN),
+            ]);
+        })
+    }
+
+    /// `fail(site)`: stops the program with the message in the buffer.
+    fn fail(&mut self) -> u32 {
+        self.helper("fail", &[I32], None, |_, f| {
+            f.extend([GlobalGet(BUF), GlobalGet(BUF_LEN)]);
+            for offset in [0, 4, 8, 12] {
+                f.extend([LocalGet(0), load(offset)]);
+            }
+            f.extend([Call(HOST_FAIL), Unreachable]);
+        })
+    }
+
+    /// `fail_text(string, site)`.
+    fn fail_text(&mut self) -> u32 {
+        self.helper("fail_text", &[I32, I32], None, |this, f| {
+            let code = this.fail_with(vec![Part::String(vec![LocalGet(0)])], vec![LocalGet(1)]);
+            f.extend(code);
+        })
+    }
+
+    /// `enter(site, name)`: records a call that is about to start, or stops
+    /// the program if too many are in progress.
+    fn enter(&mut self) -> u32 {
+        self.helper("enter", &[I32, I32], None, |this, f| {
+            let limit = format!("` nest more than {} deep", MAX_CALL_DEPTH);
+            let fail = this.fail_with(
+                vec![
+                    Part::Text("calls to `"),
+                    Part::String(vec![LocalGet(1)]),
+                    Part::Text(&limit),
+                ],
+                vec![LocalGet(0)],
+            );
+            f.extend([
+                GlobalGet(DEPTH),
+                I32Const(MAX_CALL_DEPTH as i32),
+                n(I32_GE_U),
+                If(EMPTY),
+            ]);
+            f.extend(fail);
+            f.extend([
+                End,
+                GlobalGet(DEPTH),
+                I32Const(1),
+                n(I32_ADD),
+                GlobalSet(DEPTH),
+                GlobalGet(DEPTH),
+                I32Const(2),
+                n(I32_SHL),
+                LocalGet(0),
+                store(SITES),
+            ]);
+        })
+    }
+
+    fn leave(&mut self) -> u32 {
+        self.helper("leave", &[], None, |_, f| {
+            f.extend([GlobalGet(DEPTH), I32Const(1), n(I32_SUB), GlobalSet(DEPTH)]);
+        })
+    }
+
+    /// `no_clause(name, args, at_caller, site)`: stops the program because
+    /// no clause of a function accepts the arguments in a list. The error
+    /// points at the call of the running function when `at_caller` is set.
+    fn no_clause(&mut self) -> u32 {
+        self.helper("no_clause", &[I32, I32, I32, I32], None, |this, f| {
+            let (i, count, items) = (f.local(I32), f.local(I32), f.local(I32));
+            let call = f.local(I32);
+            let buf_str = this.buf_str();
+            let comma = this.string(", ") as i32;
+            f.extend([I32Const(0), GlobalSet(BUF_LEN)]);
+            f.extend(this.text("no clause of `"));
+            f.extend([LocalGet(0), Call(buf_str)]);
+            f.extend(this.text("` matches the arguments ("));
+            f.extend([
+                LocalGet(1),
+                load(4),
+                LocalSet(count),
+                LocalGet(1),
+                load(8),
+                LocalSet(items),
+            ]);
+            f.extend(each(
+                i,
+                count,
+                vec![
+                    LocalGet(i),
+                    If(EMPTY),
+                    I32Const(comma),
+                    Call(buf_str),
+                    End,
+                    LocalGet(items),
+                    LocalGet(i),
+                    I32Const(2),
+                    n(I32_SHL),
+                    n(I32_ADD),
+                    load(0),
+                    I32Const(0),
+                    Call(this.show()),
+                ],
+            ));
+            f.extend(this.text(")"));
+            f.extend([
+                LocalGet(2),
+                If(EMPTY),
+                GlobalGet(DEPTH),
+                I32Const(2),
+                n(I32_SHL),
+                load(SITES),
+                LocalTee(call),
+                If(EMPTY),
+                LocalGet(call),
+                LocalSet(3),
+                End,
+                End,
+                LocalGet(3),
+                Call(this.fail()),
+            ]);
+        })
+    }
+
+    fn is_number(&mut self) -> u32 {
+        self.helper("is_number", &[I32], Some(I32), |_, f| {
+            f.extend(tag_is(0, INT));
+            f.extend(tag_is(0, FLOAT));
+            f.push(n(I32_OR));
+        })
+    }
+
+    /// `number(value)`: an int or float as a float.
+    fn number(&mut self) -> u32 {
+        self.helper("number", &[I32], Some(F64), |_, f| {
+            f.extend(tag_is(0, INT));
+            f.extend([
+                If(BlockType::Value(F64)),
+                LocalGet(0),
+                load64(8),
+                n(F64_CONVERT_I64_S),
+                Else,
+                LocalGet(0),
+                load_f64(8),
+                End,
+            ]);
+        })
+    }
+
+    /// `bytes_equal(ptr, len, ptr, len)`.
+    fn bytes_equal(&mut self) -> u32 {
+        self.helper("bytes_equal", &[I32, I32, I32, I32], Some(I32), |_, f| {
+            let i = f.local(I32);
+            f.extend([
+                LocalGet(1),
+                LocalGet(3),
+                n(I32_NE),
+                If(EMPTY),
+                I32Const(0),
+                Return,
+                End,
+            ]);
+            f.extend(each(
+                i,
+                1,
+                vec![
+                    LocalGet(0),
+                    LocalGet(i),
+                    n(I32_ADD),
+                    load8(0),
+                    LocalGet(2),
+                    LocalGet(i),
+                    n(I32_ADD),
+                    load8(0),
+                    n(I32_NE),
+                    If(EMPTY),
+                    I32Const(0),
+                    Return,
+                    End,
+                ],
+            ));
+            f.push(I32Const(1));
+        })
+    }
+
+    /// `equal(a, b)`: structural equality, as `==` compares.
+    fn equal(&mut self) -> u32 {
+        let structs = self.structs as i32;
+        self.helper("equal", &[I32, I32], Some(I32), |this, f| {
+            let (ta, tb, i, count) = (f.local(I32), f.local(I32), f.local(I32), f.local(I32));
+            let (is_number, number) = (this.is_number(), this.number());
+            let equal = this.equal();
+            let returns = |value: i32| [If(EMPTY), I32Const(value), Return, End];
+            f.extend([
+                LocalGet(0),
+                load(0),
+                LocalSet(ta),
+                LocalGet(1),
+                load(0),
+                LocalSet(tb),
+            ]);
+            f.extend([
+                LocalGet(ta),
+                I32Const(INT),
+                n(I32_EQ),
+                LocalGet(tb),
+                I32Const(INT),
+                n(I32_EQ),
+                n(I32_AND),
+                If(EMPTY),
+                LocalGet(0),
+                load64(8),
+                LocalGet(1),
+                load64(8),
+                n(I64_EQ),
+                Return,
+                End,
+                LocalGet(0),
+                Call(is_number),
+                LocalGet(1),
+                Call(is_number),
+                n(I32_AND),
+                If(EMPTY),
+                LocalGet(0),
+                Call(number),
+                LocalGet(1),
+                Call(number),
+                n(F64_EQ),
+                Return,
+                End,
+                LocalGet(ta),
+                LocalGet(tb),
+                n(I32_NE),
+            ]);
+            f.extend(returns(0));
+            f.extend([LocalGet(ta), I32Const(UNIT), n(I32_LE_U)]);
+            f.extend(returns(1));
+            for (tag, offset) in [(BOOL, 8), (TYPE, 4)] {
+                f.extend([
+                    LocalGet(ta),
+                    I32Const(tag),
+                    n(I32_EQ),
+                    If(EMPTY),
+                    LocalGet(0),
+                    load(offset),
+                    LocalGet(1),
+                    load(offset),
+                    n(I32_EQ),
+                    Return,
+                    End,
+                ]);
+            }
+            f.extend([
+                LocalGet(ta),
+                I32Const(STRING),
+                n(I32_EQ),
+                If(EMPTY),
+                LocalGet(0),
+                load(8),
+                LocalGet(0),
+                load(4),
+                LocalGet(1),
+                load(8),
+                LocalGet(1),
+                load(4),
+                Call(this.bytes_equal()),
+                Return,
+                End,
+                LocalGet(ta),
+                I32Const(STRUCT),
+                n(I32_EQ),
+                LocalGet(0),
+                LocalGet(1),
+                n(I32_EQ),
+                n(I32_AND),
+            ]);
+            f.extend(returns(1));
+            // Lists of the same length, or instances of the same struct,
+            // are equal when all their items are.
+            f.extend([
+                LocalGet(ta),
+                I32Const(LIST),
+                n(I32_EQ),
+                LocalGet(ta),
+                I32Const(STRUCT),
+                n(I32_EQ),
+                n(I32_OR),
+                If(EMPTY),
+                LocalGet(0),
+                load(4),
+                LocalGet(1),
+                load(4),
+                n(I32_NE),
+            ]);
+            f.extend(returns(0));
+            f.extend([
+                LocalGet(0),
+                load(4),
+                LocalSet(count),
+                LocalGet(ta),
+                I32Const(STRUCT),
+                n(I32_EQ),
+                If(EMPTY),
+                LocalGet(count),
+                I32Const(4),
+                n(I32_SHL),
+                I32Const(structs),
+                n(I32_ADD),
+                load(4),
+                LocalSet(count),
+                End,
+            ]);
+            f.extend(each(
+                i,
+                count,
+                vec![
+                    LocalGet(0),
+                    load(8),
+                    LocalGet(i),
+                    I32Const(2),
+                    n(I32_SHL),
+                    n(I32_ADD),
+                    load(0),
+                    LocalGet(1),
+                    load(8),
+                    LocalGet(i),
+                    I32Const(2),
+                    n(I32_SHL),
+                    n(I32_ADD),
+                    load(0),
+                    Call(equal),
+                    n(I32_EQZ),
+                    If(EMPTY),
+                    I32Const(0),
+                    Return,
+                    End,
+                ],
+            ));
+            f.extend([I32Const(1), Return, End, I32Const(0)]);
+        })
+    }
+
+    /// `order(a, b)`: -1, 0 or 1 as `a` is less than, equal to or greater
+    /// than `b`, or 2 when they cannot be compared.
+    fn order(&mut self) -> u32 {
+        self.helper("order", &[I32, I32], Some(I32), |this, f| {
+            let (x, y) = (f.local(F64), f.local(F64));
+            let (i, len, c, d) = (f.local(I32), f.local(I32), f.local(I32), f.local(I32));
+            let sign = |greater: u8, less: u8, a: [Instr; 2], b: [Instr; 2]| {
+                let mut code = Vec::new();
+                code.extend(a.clone());
+                code.extend(b.clone());
+                code.push(n(greater));
+                code.extend(a);
+                code.extend(b);
+                code.extend([n(less), n(I32_SUB)]);
+                code
+            };
+            f.extend(tag_is(0, INT));
+            f.extend(tag_is(1, INT));
+            f.extend([n(I32_AND), If(EMPTY)]);
+            f.extend(sign(
+                I64_GT_S,
+                I64_LT_S,
+                [LocalGet(0), load64(8)],
+                [LocalGet(1), load64(8)],
+            ));
+            f.extend([Return, End]);
+
+            f.extend(tag_is(0, STRING));
+            f.extend(tag_is(1, STRING));
+            f.extend([
+                n(I32_AND),
+                If(EMPTY),
+                LocalGet(0),
+                load(4),
+                LocalGet(1),
+                load(4),
+                LocalGet(0),
+                load(4),
+                LocalGet(1),
+                load(4),
+                n(I32_LT_U),
+                Select,
+                LocalSet(len),
+            ]);
+            f.extend(each(
+                i,
+                len,
+                vec![
+                    LocalGet(0),
+                    load(8),
+                    LocalGet(i),
+                    n(I32_ADD),
+                    load8(0),
+                    LocalSet(c),
+                    LocalGet(1),
+                    load(8),
+                    LocalGet(i),
+                    n(I32_ADD),
+                    load8(0),
+                    LocalSet(d),
+                    LocalGet(c),
+                    LocalGet(d),
+                    n(I32_NE),
+                    If(EMPTY),
+                    LocalGet(c),
+                    LocalGet(d),
+                    n(I32_GT_U),
+                    LocalGet(c),
+                    LocalGet(d),
+                    n(I32_LT_U),
+                    n(I32_SUB),
+                    Return,
+                    End,
+                ],
+            ));
+            f.extend(sign(
+                I32_GT_U,
+                I32_LT_U,
+                [LocalGet(0), load(4)],
+                [LocalGet(1), load(4)],
+            ));
+            f.extend([Return, End]);
+
+            let (is_number, number) = (this.is_number(), this.number());
+            f.extend([
+                LocalGet(0),
+                Call(is_number),
+                LocalGet(1),
+                Call(is_number),
+                n(I32_AND),
+                If(EMPTY),
+                LocalGet(0),
+                Call(number),
+                LocalSet(x),
+                LocalGet(1),
+                Call(number),
+                LocalSet(y),
+            ]);
+            for (test, result) in [(F64_LT, -1), (F64_GT, 1), (F64_EQ, 0)] {
+                f.extend([
+                    LocalGet(x),
+                    LocalGet(y),
+                    n(test),
+                    If(EMPTY),
+                    I32Const(result),
+                    Return,
+                    End,
+                ]);
+            }
+            f.extend([End, I32Const(2)]);
+        })
+    }
+
+    /// `compare(kind, a, b, site)`: one of `<`, `<=`, `>` and `>=`.
+    fn compare(&mut self) -> u32 {
+        self.helper("compare", &[I32, I32, I32, I32], Some(I32), |this, f| {
+            let order = f.local(I32);
+            let fail = this.fail_with(
+                vec![
+                    Part::Text("cannot compare `"),
+                    Part::Value(1),
+                    Part::Text("` and `"),
+                    Part::Value(2),
+                    Part::Text("`"),
+                ],
+                vec![LocalGet(3)],
+            );
+            let bool = this.bool();
+            f.extend([
+                LocalGet(1),
+                LocalGet(2),
+                Call(this.order()),
+                LocalTee(order),
+                I32Const(2),
+                n(I32_EQ),
+                If(EMPTY),
+            ]);
+            f.extend(fail);
+            f.push(End);
+            for (kind, test) in [(5, I32_LT_S), (6, I32_LE_S), (7, I32_GT_S)] {
+                f.extend([
+                    LocalGet(0),
+                    I32Const(kind),
+                    n(I32_EQ),
+                    If(EMPTY),
+                    LocalGet(order),
+                    I32Const(0),
+                    n(test),
+                    Call(bool),
+                    Return,
+                    End,
+                ]);
+            }
+            f.extend([LocalGet(order), I32Const(0), n(I32_GE_S), Call(bool)]);
+        })
+    }
+
+    /// `arith(kind, a, b, site)`: one of `+`, `-`, `*`, `/` and `%`. Ints
+    /// stay ints and fail on overflow; any float makes the result a float.
+    fn arith(&mut self) -> u32 {
+        let symbols = self.symbols as i32;
+        self.helper("arith", &[I32, I32, I32, I32], Some(I32), |this, f| {
+            let (x, y, r) = (f.local(I64), f.local(I64), f.local(I64));
+            let (fx, fy) = (f.local(F64), f.local(F64));
+            let symbol = vec![
+                LocalGet(0),
+                I32Const(2),
+                n(I32_SHL),
+                I32Const(symbols),
+                n(I32_ADD),
+                load(0),
+            ];
+            let overflow = this.fail_with(
+                vec![
+                    Part::Text("`"),
+                    Part::Int(x),
+                    Part::Text(" "),
+                    Part::String(symbol.clone()),
+                    Part::Text(" "),
+                    Part::Int(y),
+                    Part::Text("` overflows an `int`"),
+                ],
+                vec![LocalGet(3)],
+            );
+            let by_zero = this.fail_with(vec![Part::Text("division by zero")], vec![LocalGet(3)]);
+            let box_int = this.box_int();
+            let kind = |k: i32| [LocalGet(0), I32Const(k), n(I32_EQ), If(EMPTY)];
+            let checked = |test: Vec<Instr>| {
+                let mut code = test;
+                code.push(If(EMPTY));
+                code.extend(overflow.clone());
+                code.extend([End, LocalGet(r), Call(box_int), Return, End]);
+                code
+            };
+
+            f.extend(tag_is(1, INT));
+            f.extend(tag_is(2, INT));
+            f.extend([
+                n(I32_AND),
+                If(EMPTY),
+                LocalGet(1),
+                load64(8),
+                LocalSet(x),
+                LocalGet(2),
+                load64(8),
+                LocalSet(y),
+            ]);
+            // Addition and subtraction overflow when the sign of the result
+            // is wrong for the signs of the operands.
+            f.extend(kind(0));
+            f.extend(checked(vec![
+                LocalGet(x),
+                LocalGet(y),
+                n(I64_ADD),
+                LocalTee(r),
+                LocalGet(x),
+                n(I64_XOR),
+                LocalGet(r),
+                LocalGet(y),
+                n(I64_XOR),
+                n(I64_AND),
+                I64Const(0),
+                n(I64_LT_S),
+            ]));
+            f.extend(kind(1));
+            f.extend(checked(vec![
+                LocalGet(x),
+                LocalGet(y),
+                n(I64_SUB),
+                LocalSet(r),
+                LocalGet(x),
+                LocalGet(y),
+                n(I64_XOR),
+                LocalGet(x),
+                LocalGet(r),
+                n(I64_XOR),
+                n(I64_AND),
+                I64Const(0),
+                n(I64_LT_S),
+            ]));
+            // A product overflows when dividing it by one factor does not
+            // give the other; -1 times the most negative int is tested
+            // first because that division would trap.
+            f.extend(kind(2));
+            f.extend(checked(vec![
+                LocalGet(x),
+                LocalGet(y),
+                n(I64_MUL),
+                LocalSet(r),
+                LocalGet(x),
+                I64Const(-1),
+                n(I64_EQ),
+                If(BlockType::Value(I32)),
+                LocalGet(y),
+                I64Const(i64::MIN),
+                n(I64_EQ),
+                Else,
+                LocalGet(x),
+                n(I64_EQZ),
+                If(BlockType::Value(I32)),
+                I32Const(0),
+                Else,
+                LocalGet(r),
+                LocalGet(x),
+                n(I64_DIV_S),
+                LocalGet(y),
+                n(I64_NE),
+                End,
+                End,
+            ]));
+            for (k, op) in [(3, I64_DIV_S), (4, I64_REM_S)] {
+                f.extend(kind(k));
+                f.extend([LocalGet(y), n(I64_EQZ), If(EMPTY)]);
+                f.extend(by_zero.clone());
+                f.push(End);
+                f.extend(checked(vec![
+                    LocalGet(x),
+                    I64Const(i64::MIN),
+                    n(I64_EQ),
+                    LocalGet(y),
+                    I64Const(-1),
+                    n(I64_EQ),
+                    n(I32_AND),
+                    If(BlockType::Value(I32)),
+                    I32Const(1),
+                    Else,
+                    LocalGet(x),
+                    LocalGet(y),
+                    n(op),
+                    LocalSet(r),
+                    I32Const(0),
+                    End,
+                ]));
+            }
+            f.push(End);
+
+            let (is_number, number) = (this.is_number(), this.number());
+            let box_float = this.box_float();
+            f.extend([
+                LocalGet(1),
+                Call(is_number),
+                LocalGet(2),
+                Call(is_number),
+                n(I32_AND),
+                If(EMPTY),
+                LocalGet(1),
+                Call(number),
+                LocalSet(fx),
+                LocalGet(2),
+                Call(number),
+                LocalSet(fy),
+            ]);
+            for (k, op) in [(0, F64_ADD), (1, F64_SUB), (2, F64_MUL), (3, F64_DIV)] {
+                f.extend(kind(k));
+                f.extend([
+                    LocalGet(fx),
+                    LocalGet(fy),
+                    n(op),
+                    Call(box_float),
+                    Return,
+                    End,
+                ]);
+            }
+            f.extend([
+                LocalGet(fx),
+                LocalGet(fy),
+                Call(FMOD),
+                Call(box_float),
+                Return,
+                End,
+            ]);
+            let fail = this.fail_with(
+                vec![
+                    Part::Text("cannot apply `"),
+                    Part::String(symbol),
+                    Part::Text("` to `"),
+                    Part::Value(1),
+                    Part::Text("` and `"),
+                    Part::Value(2),
+                    Part::Text("`"),
+                ],
+                vec![LocalGet(3)],
+            );
+            f.extend(fail);
+        })
+    }
+
+    fn negate(&mut self) -> u32 {
+        self.helper("negate", &[I32, I32], Some(I32), |this, f| {
+            let x = f.local(I64);
+            let overflow = this.fail_with(
+                vec![
+                    Part::Text("negating `"),
+                    Part::Int(x),
+                    Part::Text("` overflows an `int`"),
+                ],
+                vec![LocalGet(1)],
+            );
+            let fail = this.fail_with(
+                vec![Part::Text("`negate` cannot be applied to these arguments")],
+                vec![LocalGet(1)],
+            );
+            f.extend(tag_is(0, INT));
+            f.extend([
+                If(EMPTY),
+                LocalGet(0),
+                load64(8),
+                LocalTee(x),
+                I64Const(i64::MIN),
+                n(I64_EQ),
+                If(EMPTY),
+            ]);
+            f.extend(overflow);
+            f.extend([
+                End,
+                I64Const(0),
+                LocalGet(x),
+                n(I64_SUB),
+                Call(this.box_int()),
+                Return,
+                End,
+            ]);
+            f.extend(tag_is(0, FLOAT));
+            f.extend([
+                If(EMPTY),
+                LocalGet(0),
+                load_f64(8),
+                n(F64_NEG),
+                Call(this.box_float()),
+                Return,
+                End,
+            ]);
+            f.extend(fail);
+        })
+    }
+
+    fn not(&mut self) -> u32 {
+        self.helper("not", &[I32, I32], Some(I32), |this, f| {
+            let fail = this.fail_with(
+                vec![Part::Text("`not` cannot be applied to these arguments")],
+                vec![LocalGet(1)],
+            );
+            f.extend(tag_is(0, BOOL));
+            f.extend([
+                If(EMPTY),
+                LocalGet(0),
+                load(8),
+                n(I32_EQZ),
+                Call(this.bool()),
+                Return,
+                End,
+            ]);
+            f.extend(fail);
+        })
+    }
+
+    /// `truth(value, site)`: whether a condition holds.
+    fn truth(&mut self) -> u32 {
+        self.helper("truth", &[I32, I32], Some(I32), |this, f| {
+            let fail = this.fail_with(
+                vec![
+                    Part::Text("expected `true` or `false`, found `"),
+                    Part::Value(0),
+                    Part::Text("`"),
+                ],
+                vec![LocalGet(1)],
+            );
+            f.extend(tag_is(0, BOOL));
+            f.extend([If(EMPTY), LocalGet(0), load(8), Return, End]);
+            f.extend(fail);
+        })
+    }
+
+    /// `iterate(value, site)`: an iterator over a list or the characters of
+    /// a string.
+    fn iterate(&mut self) -> u32 {
+        self.helper("iterate", &[I32, I32], Some(I32), |this, f| {
+            let p = f.local(I32);
+            let fail = this.fail_with(
+                vec![
+                    Part::Text("cannot loop over `"),
+                    Part::Value(0),
+                    Part::Text("`"),
+                ],
+                vec![LocalGet(1)],
+            );
+            f.extend(tag_is(0, LIST));
+            f.extend(tag_is(0, STRING));
+            f.extend([
+                n(I32_OR),
+                If(EMPTY),
+                I32Const(16),
+                Call(this.alloc()),
+                LocalTee(p),
+                I32Const(ITERATOR),
+                store(0),
+                LocalGet(p),
+                I32Const(0),
+                store(4),
+                LocalGet(p),
+                LocalGet(0),
+                store(8),
+                LocalGet(p),
+                Return,
+                End,
+            ]);
+            f.extend(fail);
+        })
+    }
+
+    /// `next(iterator)`: the next value, or zero when there is none.
+    fn next(&mut self) -> u32 {
+        self.helper("next", &[I32], Some(I32), |this, f| {
+            let (value, at, len, p) = (f.local(I32), f.local(I32), f.local(I32), f.local(I32));
+            f.extend([
+                LocalGet(0),
+                load(8),
+                LocalSet(value),
+                LocalGet(0),
+                load(4),
+                LocalTee(at),
+                LocalGet(value),
+                load(4),
+                n(I32_GE_U),
+                If(EMPTY),
+                I32Const(0),
+                Return,
+                End,
+            ]);
+            f.extend(tag_is(value, LIST));
+            f.extend([
+                If(EMPTY),
+                LocalGet(0),
+                LocalGet(at),
+                I32Const(1),
+                n(I32_ADD),
+                store(4),
+                LocalGet(value),
+                load(8),
+                LocalGet(at),
+                I32Const(2),
+                n(I32_SHL),
+                n(I32_ADD),
+                load(0),
+                Return,
+                End,
+                // A character of a string is as long as its first byte says.
+                LocalGet(value),
+                load(8),
+                LocalGet(at),
+                n(I32_ADD),
+                load8(0),
+                LocalTee(len),
+                I32Const(0xc0),
+                n(I32_GE_U),
+                LocalGet(len),
+                I32Const(0xe0),
+                n(I32_GE_U),
+                n(I32_ADD),
+                LocalGet(len),
+                I32Const(0xf0),
+                n(I32_GE_U),
+                n(I32_ADD),
+                I32Const(1),
+                n(I32_ADD),
+                LocalSet(len),
+                I32Const(16),
+                Call(this.alloc()),
+                LocalTee(p),
+                I32Const(STRING),
+                store(0),
+                LocalGet(p),
+                LocalGet(len),
+                store(4),
+                LocalGet(p),
+                LocalGet(value),
+                load(8),
+                LocalGet(at),
+                n(I32_ADD),
+                store(8),
+                LocalGet(0),
+                LocalGet(at),
+                LocalGet(len),
+                n(I32_ADD),
+                store(4),
+                LocalGet(p),
+            ]);
+        })
+    }
+
+    /// `type_of(value)`: the id of the type methods of the value are
+    /// looked up in.
+    fn type_of(&mut self) -> u32 {
+        let structs = self.structs as i32;
+        self.helper("type_of", &[I32], Some(I32), |_, f| {
+            f.extend(tag_is(0, STRUCT));
+            f.extend([
+                If(EMPTY),
+                LocalGet(0),
+                load(4),
+                I32Const(4),
+                n(I32_SHL),
+                I32Const(structs),
+                n(I32_ADD),
+                load(12),
+                Return,
+                End,
+                LocalGet(0),
+                load(0),
+            ]);
+        })
+    }
+
+    /// `field_<name>(value, site)` and `set_field_<name>(value, new, site)`.
+    fn field(&mut self, name: &str, set: bool) -> u32 {
+        let key = format!("{}field_{}", if set { "set_" } else { "" }, name);
+        let params: &[ValType] = if set { &[I32, I32, I32] } else { &[I32, I32] };
+        let result = if set { None } else { Some(I32) };
+        let owners: Vec<(usize, usize)> = self
+            .program
+            .structs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, info)| Some((index, info.fields.iter().position(|f| f == name)?)))
+            .collect();
+        let site = params.len() as u32 - 1;
+        self.helper(&key, params, result, |this, f| {
+            let no_field = format!("` has no field `{}`", name);
+            let in_struct = this.fail_with(
+                vec![Part::Text("`"), Part::Type(0), Part::Text(&no_field)],
+                vec![LocalGet(site)],
+            );
+            let anything = this.fail_with(
+                vec![Part::Text("`"), Part::Value(0), Part::Text(&no_field)],
+                vec![LocalGet(site)],
+            );
+            f.extend(tag_is(0, STRUCT));
+            f.push(If(EMPTY));
+            for (index, position) in owners {
+                f.extend([
+                    LocalGet(0),
+                    load(4),
+                    I32Const(index as i32),
+                    n(I32_EQ),
+                    If(EMPTY),
+                    LocalGet(0),
+                    load(8),
+                ]);
+                if set {
+                    f.extend([LocalGet(1), store(4 * position as u32)]);
+                } else {
+                    f.push(load(4 * position as u32));
+                }
+                f.extend([Return, End]);
+            }
+            f.extend(in_struct);
+            f.push(End);
+            f.extend(anything);
+        })
+    }
+
+    /// `global_<index>(site)`: the value of a global, computed by calling
+    /// its initializer the first time. Its wasm global holds zero until
+    /// then and -1 while the initializer runs.
+    fn global(&mut self, index: u32) -> u32 {
+        let global = &self.program.globals[index as usize];
+        let init = self.chop[&global.init];
+        let name = global.name.clone();
+        let init_name = self.program.functions[global.init as usize].name.clone();
+        self.helper(
+            &format!("global_{}", index),
+            &[I32],
+            Some(I32),
+            |this, f| {
+                let value = f.local(I32);
+                let message = format!("`{}` is used while it is being initialized", name);
+                let fail = this.fail_with(vec![Part::Text(&message)], vec![LocalGet(0)]);
+                let init_name = this.string(&init_name) as i32;
+                let g = GLOBALS + index;
+                f.extend([
+                    GlobalGet(g),
+                    LocalTee(value),
+                    n(I32_EQZ),
+                    If(EMPTY),
+                    I32Const(-1),
+                    GlobalSet(g),
+                    LocalGet(0),
+                    I32Const(init_name),
+                    Call(this.enter()),
+                    Call(init),
+                    LocalTee(value),
+                    GlobalSet(g),
+                    Call(this.leave()),
+                    LocalGet(value),
+                    Return,
+                    End,
+                    LocalGet(value),
+                    I32Const(-1),
+                    n(I32_EQ),
+                    If(EMPTY),
+                ]);
+                f.extend(fail);
+                f.extend([End, LocalGet(value)]);
+            },
+        )
+    }
+
+    /// Instructions boxing the values of `locals` into a new list.
+    fn list_of(&mut self, locals: impl Iterator<Item = u32>, scratch: u32) -> Vec<Instr> {
+        let locals: Vec<u32> = locals.collect();
+        let mut code = vec![
+            I32Const(LIST),
+            I32Const(locals.len() as i32),
+            I32Const(locals.len() as i32),
+            Call(self.new_box()),
+            LocalSet(scratch),
+        ];
+        for (k, local) in locals.into_iter().enumerate() {
+            code.extend([
+                LocalGet(scratch),
+                load(8),
+                LocalGet(local),
+                store(4 * k as u32),
+            ]);
+        }
+        code.push(LocalGet(scratch));
+        code
+    }
+
+    /// Instructions calling a chop function with the arguments in `args`,
+    /// recording the call for errors and the depth limit.
+    fn call_chop(&mut self, function: u32, args: &[u32], site: Vec<Instr>) -> Vec<Instr> {
+        let name = &self.program.functions[function as usize].name;
+        let name = self.string(&name.clone()) as i32;
+        let mut code = site;
+        code.extend([I32Const(name), Call(self.enter())]);
+        code.extend(args.iter().map(|a| LocalGet(*a)));
+        code.extend([Call(self.chop[&function]), Call(self.leave())]);
+        code
+    }
+
+    /// A call in tail position: the callee takes the place of the running
+    /// call, both its frame and its site.
+    fn tail_call_chop(&mut self, function: u32, args: &[u32], site: Vec<Instr>) -> Vec<Instr> {
+        let mut code = vec![GlobalGet(DEPTH), I32Const(2), n(I32_SHL)];
+        code.extend(site);
+        code.push(store(SITES));
+        code.extend(args.iter().map(|a| LocalGet(*a)));
+        code.push(ReturnCall(self.chop[&function]));
+        code
+    }
+
+    /// `method_<name>_<count>(receiver, args.., site)`: `receiver.name(args)`
+    /// as the virtual machine dispatches it. The `tail_method_` variant is
+    /// for calls in tail position, and makes its call as a tail call too.
+    fn method(&mut self, name: &str, count: u8, tail: bool) -> u32 {
+        let program = self.program;
+        // Later definitions of a method replace earlier ones.
+        let mut owners: Vec<(&str, u32)> = Vec::new();
+        for method in program.methods.iter().filter(|m| m.name == name) {
+            owners.retain(|(owner, _)| *owner != method.owner);
+            owners.push((&method.owner, method.function));
+        }
+        let mut params = vec![I32; count as usize];
+        params.push(I32);
+        let site = count as u32;
+        let prefix = if tail { "tail_method" } else { "method" };
+        self.helper(
+            &format!("{}_{}_{}", prefix, name, count),
+            &params,
+            Some(I32),
+            |this, f| {
+                let (id, scratch) = (f.local(I32), f.local(I32));
+                let no_clause = this.no_clause();
+                let calls = |this: &mut Self, f: &mut Body, args: Vec<u32>| {
+                    for (owner, function) in &owners {
+                        let owner_id = this.type_id(owner) as i32;
+                        f.extend([LocalGet(id), I32Const(owner_id), n(I32_EQ), If(EMPTY)]);
+                        let callee = &program.functions[*function as usize];
+                        if callee.arity as usize == args.len() && tail {
+                            let call = this.tail_call_chop(*function, &args, vec![LocalGet(site)]);
+                            f.extend(call);
+                        } else if callee.arity as usize == args.len() {
+                            let call = this.call_chop(*function, &args, vec![LocalGet(site)]);
+                            f.extend(call);
+                            f.push(Return);
+                        } else {
+                            let list = this.list_of(args.iter().copied(), scratch);
+                            let callee = this.string(&callee.name) as i32;
+                            f.push(I32Const(callee));
+                            f.extend(list);
+                            f.extend([I32Const(0), LocalGet(site), Call(no_clause), Unreachable]);
+                        }
+                        f.push(End);
+                    }
+                };
+
+                f.extend(tag_is(0, TYPE));
+                f.extend([If(EMPTY), LocalGet(0), load(4), LocalSet(id)]);
+                if name == "init" {
+                    let mut seen = Vec::new();
+                    for (index, info) in program.structs.iter().enumerate() {
+                        if seen.contains(&&info.name) {
+                            continue;
+                        }
+                        seen.push(&info.name);
+                        let struct_id = this.type_id(&info.name) as i32;
+                        f.extend([LocalGet(id), I32Const(struct_id), n(I32_EQ), If(EMPTY)]);
+                        let given = count as usize - 1;
+                        if given != info.fields.len() {
+                            let message = format!(
+                                "`{}.init` takes {} fields, but {} were given",
+                                info.name,
+                                info.fields.len(),
+                                given
+                            );
+                            let fail =
+                                this.fail_with(vec![Part::Text(&message)], vec![LocalGet(site)]);
+                            f.extend(fail);
+                        } else {
+                            f.extend([
+                                I32Const(STRUCT),
+                                I32Const(index as i32),
+                                I32Const(given as i32),
+                                Call(this.new_box()),
+                                LocalSet(scratch),
+                            ]);
+                            for k in 0..given {
+                                f.extend([
+                                    LocalGet(scratch),
+                                    load(8),
+                                    LocalGet(k as u32 + 1),
+                                    store(4 * k as u32),
+                                ]);
+                            }
+                            f.extend([LocalGet(scratch), Return]);
+                        }
+                        f.push(End);
+                    }
+                }
+                calls(this, f, (1..count as u32).collect());
+                let no_function = format!("` has no function `{}`", name);
+                let fail = this.fail_with(
+                    vec![
+                        Part::Text("`"),
+                        Part::String(vec![LocalGet(0), load(8)]),
+                        Part::Text(&no_function),
+                    ],
+                    vec![LocalGet(site)],
+                );
+                f.extend(fail);
+                f.extend([End, LocalGet(0), Call(this.type_of()), LocalSet(id)]);
+                calls(this, f, (0..count as u32).collect());
+                let no_method = format!("` has no method `{}`", name);
+                let fail = this.fail_with(
+                    vec![Part::Text("`"), Part::Type(0), Part::Text(&no_method)],
+                    vec![LocalGet(site)],
+                );
+                f.extend(fail);
+            },
+        )
+    }
+
+    // Chop functions.
+
+    /// Builds every function `main` can reach, returning the wasm function
+    /// running `main`.
+    fn chop_functions(&mut self) -> u32 {
+        let program = self.program;
+        let mut work = vec![program.main];
+        while let Some(index) = work.pop() {
+            if self.chop.contains_key(&index) {
+                continue;
+            }
+            let wasm = self.reserve();
+            self.chop.insert(index, wasm);
+            for op in &program.functions[index as usize].code {
+                match *op {
+                    Op::CallFunction(callee, _) => work.push(callee),
+                    Op::Global(global) | Op::SetGlobal(global) => {
+                        work.push(program.globals[global as usize].init)
+                    }
+                    Op::CallMethod(name, _) => {
+                        let name = program.string(name);
+                        work.extend(
+                            program
+                                .methods
+                                .iter()
+                                .filter(|m| m.name == name)
+                                .map(|m| m.function),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let reachable: Vec<(u32, u32)> = self.chop.iter().map(|(c, w)| (*c, *w)).collect();
+        for (index, wasm) in reachable {
+            let function = &program.functions[index as usize];
+            let body = self.function(function);
+            self.define(wasm, Some(I32), body);
+        }
+        self.chop[&program.main]
+    }
+
+    fn unsupported(&mut self, what: &str, span: Span) {
+        let message = format!("the wasm target does not support {} yet", what);
+        if !self
+            .errors
+            .iter()
+            .any(|e| e.message == message && e.span == span)
+        {
+            self.errors.push(Diagnostic::error(message, span));
+        }
+    }
+
+    /// The body of a chop function: its arguments are the first locals,
+    /// then its other slots, then one local per stack slot. Each basic
+    /// block is a case of a `br_table` in a loop, and a jump sets `pc` to
+    /// its target's block and branches back to the loop.
+    fn function(&mut self, function: &Function) -> Body {
+        if let Some(memo) = function.memo {
+            self.unsupported("`@memo` caches", memo.span);
+        }
+        let depths = depths(function);
+        let peak = depths
+            .iter()
+            .zip(&function.code)
+            .filter_map(|(depth, op)| depth.map(|d| d + effect(*op).1))
+            .max()
+            .unwrap_or(0);
+        let mut body = Body {
+            params: vec![I32; function.arity as usize],
+            locals: Vec::new(),
+            code: Vec::new(),
+        };
+        for _ in function.arity as u16..function.slots {
+            body.local(I32);
+        }
+        let stack = function.slots as u32;
+        for _ in 0..peak {
+            body.local(I32);
+        }
+        let pc = body.local(I32);
+        let scratch = body.local(I32);
+
+        let mut starts: Vec<usize> = function
+            .code
+            .iter()
+            .zip(&depths)
+            .filter(|(_, depth)| depth.is_some())
+            .filter_map(|(op, _)| match *op {
+                Op::Jump(t) | Op::JumpIfFalse(t) | Op::Next(_, t) | Op::Match(_, t) => {
+                    Some(t as usize)
+                }
+                _ => None,
+            })
+            .collect();
+        starts.push(0);
+        starts.sort_unstable();
+        starts.dedup();
+        let blocks = starts.len() as u32;
+
+        let unit = self.constant_box(Constant::Unit) as i32;
+        for slot in function.arity as u32..function.slots as u32 {
+            body.extend([I32Const(unit), LocalSet(slot)]);
+        }
+        body.push_loop(blocks, pc);
+        let mut block = 0;
+        let mut emitter = Emitter {
+            stack,
+            pc,
+            scratch,
+            starts: &starts,
+            blocks,
+            block: 0,
+        };
+        for (ip, op) in function.code.iter().enumerate() {
+            let Some(depth) = depths[ip] else {
+                continue;
+            };
+            if ip > 0 && starts.binary_search(&ip).is_ok() {
+                body.push(End);
+                block += 1;
+                emitter.block = block;
+            }
+            let code = self.instruction(function, ip, *op, depth as u32, &emitter);
+            body.extend(code);
+        }
+        body.extend([End, Unreachable]);
+        body
+    }
+
+    fn constant_box(&mut self, constant: Constant) -> u32 {
+        match self.program.constants.iter().position(|c| *c == constant) {
+            Some(index) => self.constants[index],
+            None => match constant {
+                Constant::Unit => self.boxed(UNIT, 0, 0),
+                _ => unreachable!("only unit is boxed on demand"),
+            },
+        }
+    }
+
+    /// The instructions running one instruction of a chop function, with
+    /// `d` values on its stack before it.
+    fn instruction(
+        &mut self,
+        function: &Function,
+        ip: usize,
+        op: Op,
+        d: u32,
+        at: &Emitter,
+    ) -> Vec<Instr> {
+        let program = self.program;
+        let span = function.spans[ip];
+        let site = self.site(span);
+        let tail = function.returns_at(ip + 1);
+        let s = |k: u32| at.stack + k;
+        let top = if d > 0 { s(d - 1) } else { u32::MAX };
+        match op {
+            Op::Constant(index) => vec![
+                I32Const(self.constants[index as usize] as i32),
+                LocalSet(s(d)),
+            ],
+            Op::Pop => Vec::new(),
+            Op::Dup => vec![LocalGet(top), LocalSet(s(d))],
+            Op::Local(slot) => vec![LocalGet(slot as u32), LocalSet(s(d))],
+            Op::SetLocal(slot) => vec![LocalGet(top), LocalSet(slot as u32)],
+            Op::Global(index) => vec![I32Const(site), Call(self.global(index)), LocalSet(s(d))],
+            Op::SetGlobal(index) => vec![LocalGet(top), GlobalSet(GLOBALS + index)],
+            Op::Type(name) => {
+                let ty = self.type_box(program.string(name)) as i32;
+                vec![I32Const(ty), LocalSet(s(d))]
+            }
+            Op::CallFunction(index, count) => {
+                let callee = &program.functions[index as usize];
+                let args: Vec<u32> = (d - count as u32..d).map(s).collect();
+                if callee.arity == count && tail {
+                    return self.tail_call_chop(index, &args, vec![I32Const(site)]);
+                }
+                let mut code = if callee.arity == count {
+                    self.call_chop(index, &args, vec![I32Const(site)])
+                } else {
+                    let name = self.string(&callee.name) as i32;
+                    let mut code = vec![I32Const(name)];
+                    code.extend(self.list_of(args.into_iter(), at.scratch));
+                    code.extend([
+                        I32Const(0),
+                        I32Const(site),
+                        Call(self.no_clause()),
+                        I32Const(0),
+                    ]);
+                    code
+                };
+                code.push(LocalSet(s(d - count as u32)));
+                code
+            }
+            Op::CallBuiltin(name, count) => {
+                let name = program.string(name);
+                let base = d - count as u32;
+                let mut code = Vec::new();
+                if matches!(name, "print" | "println") {
+                    let show = self.show();
+                    for k in base..d {
+                        code.extend([LocalGet(s(k)), I32Const(0), Call(show)]);
+                    }
+                    if name == "println" {
+                        code.extend([I32Const(b'\n' as i32), Call(self.buf_byte())]);
+                    }
+                    let unit = self.constant_box(Constant::Unit) as i32;
+                    code.extend([Call(self.flush()), I32Const(unit), LocalSet(s(base))]);
+                } else {
+                    let message = format!("`{}` cannot be applied to these arguments", name);
+                    code = self.fail_with(vec![Part::Text(&message)], vec![I32Const(site)]);
+                }
+                code
+            }
+            Op::CallMethod(name, count) => {
+                let method = self.method(program.string(name), count, tail);
+                let base = d - count as u32;
+                let mut code: Vec<Instr> = (base..d).map(|k| LocalGet(s(k))).collect();
+                if tail {
+                    code.extend([I32Const(site), ReturnCall(method)]);
+                } else {
+                    code.extend([I32Const(site), Call(method), LocalSet(s(base))]);
+                }
+                code
+            }
+            Op::Return => vec![LocalGet(top), Return],
+            Op::Jump(target) => at.jump(target, 0),
+            Op::JumpIfFalse(target) => {
+                let mut code = vec![
+                    LocalGet(top),
+                    I32Const(site),
+                    Call(self.truth()),
+                    n(I32_EQZ),
+                    If(EMPTY),
+                ];
+                code.extend(at.jump(target, 1));
+                code.push(End);
+                code
+            }
+            Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder => {
+                let kind = match op {
+                    Op::Add => 0,
+                    Op::Subtract => 1,
+                    Op::Multiply => 2,
+                    Op::Divide => 3,
+                    _ => 4,
+                };
+                vec![
+                    I32Const(kind),
+                    LocalGet(s(d - 2)),
+                    LocalGet(top),
+                    I32Const(site),
+                    Call(self.arith()),
+                    LocalSet(s(d - 2)),
+                ]
+            }
+            Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => {
+                let kind = match op {
+                    Op::Less => 5,
+                    Op::LessEqual => 6,
+                    Op::Greater => 7,
+                    _ => 8,
+                };
+                vec![
+                    I32Const(kind),
+                    LocalGet(s(d - 2)),
+                    LocalGet(top),
+                    I32Const(site),
+                    Call(self.compare()),
+                    LocalSet(s(d - 2)),
+                ]
+            }
+            Op::Equal | Op::NotEqual => {
+                let mut code = vec![LocalGet(s(d - 2)), LocalGet(top), Call(self.equal())];
+                if op == Op::NotEqual {
+                    code.push(n(I32_EQZ));
+                }
+                code.extend([Call(self.bool()), LocalSet(s(d - 2))]);
+                code
+            }
+            Op::Negate => vec![
+                LocalGet(top),
+                I32Const(site),
+                Call(self.negate()),
+                LocalSet(top),
+            ],
+            Op::Not => vec![
+                LocalGet(top),
+                I32Const(site),
+                Call(self.not()),
+                LocalSet(top),
+            ],
+            Op::List(count) => {
+                let base = d - count;
+                let mut code = self.list_of((base..d).map(s), at.scratch);
+                code.push(LocalSet(s(base)));
+                code
+            }
+            Op::Struct(index, count) => {
+                let base = d - count;
+                let mut code = vec![
+                    I32Const(STRUCT),
+                    I32Const(index as i32),
+                    I32Const(count as i32),
+                    Call(self.new_box()),
+                    LocalSet(at.scratch),
+                ];
+                for k in 0..count {
+                    code.extend([
+                        LocalGet(at.scratch),
+                        load(8),
+                        LocalGet(s(base + k)),
+                        store(4 * k),
+                    ]);
+                }
+                code.extend([LocalGet(at.scratch), LocalSet(s(base))]);
+                code
+            }
+            Op::Field(name) => {
+                let field = self.field(program.string(name), false);
+                vec![LocalGet(top), I32Const(site), Call(field), LocalSet(top)]
+            }
+            Op::SetField(name) => {
+                let field = self.field(program.string(name), true);
+                vec![
+                    LocalGet(s(d - 2)),
+                    LocalGet(top),
+                    I32Const(site),
+                    Call(field),
+                ]
+            }
+            Op::Iterate(1) => vec![
+                LocalGet(top),
+                I32Const(site),
+                Call(self.iterate()),
+                LocalSet(top),
+            ],
+            Op::Next(slot, target) => {
+                let mut code = vec![
+                    LocalGet(slot as u32),
+                    Call(self.next()),
+                    LocalTee(at.scratch),
+                    n(I32_EQZ),
+                    If(EMPTY),
+                ];
+                code.extend(at.jump(target, 1));
+                code.extend([End, LocalGet(at.scratch), LocalSet(s(d))]);
+                code
+            }
+            Op::Match(pattern, target) => match &function.patterns[pattern as usize] {
+                Pattern::Wildcard => Vec::new(),
+                Pattern::Bind(slot) => vec![LocalGet(top), LocalSet(*slot as u32)],
+                Pattern::Constant(index) => {
+                    let constant = self.constants[*index as usize] as i32;
+                    let mut code = vec![
+                        LocalGet(top),
+                        I32Const(constant),
+                        Call(self.equal()),
+                        n(I32_EQZ),
+                        If(EMPTY),
+                    ];
+                    code.extend(at.jump(target, 1));
+                    code.push(End);
+                    code
+                }
+                Pattern::Cell(_) => self.unsupported_op("closures", span),
+                Pattern::Tuple(_) => self.unsupported_op("tuples", span),
+                Pattern::Variant(..) => self.unsupported_op("enums", span),
+            },
+            Op::NoClause => {
+                let name = self.string(&function.name) as i32;
+                let mut code = vec![I32Const(name)];
+                code.extend(self.list_of(0..function.arity as u32, at.scratch));
+                code.extend([
+                    I32Const(1),
+                    I32Const(site),
+                    Call(self.no_clause()),
+                    Unreachable,
+                ]);
+                code
+            }
+            Op::NoArm(slot) => self.fail_with(
+                vec![
+                    Part::Text("no arm of this `match` matches `"),
+                    Part::Value(slot as u32),
+                    Part::Text("`"),
+                ],
+                vec![I32Const(site)],
+            ),
+            Op::Fail(message) => {
+                let message = self.string(program.string(message)) as i32;
+                vec![
+                    I32Const(message),
+                    I32Const(site),
+                    Call(self.fail_text()),
+                    Unreachable,
+                ]
+            }
+            Op::Cell(_)
+            | Op::SetCell(_)
+            | Op::NewCell(_)
+            | Op::Capture(_)
+            | Op::SetCapture(_)
+            | Op::Closure(_) => self.unsupported_op("closures", span),
+            Op::Function(_) | Op::Builtin(_) | Op::Call(_) => {
+                self.unsupported_op("functions as values", span)
+            }
+            Op::Variant(..) => self.unsupported_op("enums", span),
+            Op::Set(_) => self.unsupported_op("sets", span),
+            Op::Map(_) => self.unsupported_op("maps", span),
+            Op::Tuple(_) | Op::Iterate(_) | Op::Unpack(_) => self.unsupported_op("tuples", span),
+        }
+    }
+
+    fn unsupported_op(&mut self, what: &str, span: Span) -> Vec<Instr> {
+        self.unsupported(what, span);
+        vec![Unreachable]
+    }
+}
+
+/// Where a chop function's stack slots are, and how to reach its blocks.
+struct Emitter<'s> {
+    stack: u32,
+    pc: u32,
+    scratch: u32,
+    starts: &'s [usize],
+    blocks: u32,
+    /// The block being emitted.
+    block: u32,
+}
+
+impl Emitter<'_> {
+    /// Instructions jumping to `target` from inside `nesting` structured
+    /// instructions of the current block.
+    fn jump(&self, target: u32, nesting: u32) -> Vec<Instr> {
+        let block = self
+            .starts
+            .binary_search(&(target as usize))
+            .expect("jump targets start blocks");
+        vec![
+            I32Const(block as i32),
+            LocalSet(self.pc),
+            Br(self.blocks - 1 - self.block + nesting),
+        ]
+    }
+}
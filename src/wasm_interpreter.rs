@@ -0,0 +1,646 @@
+use std::io::Write;
+
+use crate::diagnostics::Diagnostic;
+use crate::tokens::{Position, Span};
+use crate::wasm::{memory, op, BlockType, ExportKind, FuncType, Instr, Module, ValType, PAGE};
+use crate::wasm_validate::Targets;
+
+/// How many calls may be in progress at once before the interpreter traps.
+const MAX_FRAMES: usize = 1_000_000;
+
+/// Why a module stopped running: a trap, or a runtime error the program
+/// reported through the host's `fail`.
+#[derive(Debug)]
+pub enum Stop {
+    Trap(String),
+    Failed(Diagnostic),
+}
+
+/// The functions the host provides to chop modules, in module `chop`.
+const HOST: &[(&str, &[ValType], &[ValType])] = &[
+    ("print", &[ValType::I32, ValType::I32], &[]),
+    (
+        "format_float",
+        &[ValType::F64, ValType::I32],
+        &[ValType::I32],
+    ),
+    ("fmod", &[ValType::F64, ValType::F64], &[ValType::F64]),
+    (
+        "fail",
+        &[
+            ValType::I32,
+            ValType::I32,
+            ValType::I32,
+            ValType::I32,
+            ValType::I32,
+            ValType::I32,
+        ],
+        &[],
+    ),
+];
+
+/// The functions a module imports from the host.
+#[derive(Clone, Copy)]
+enum Host {
+    /// Writes the bytes from `ptr` to `ptr + len` to the output.
+    Print,
+    /// Writes a float as chop prints it at `out`, returning the length.
+    FormatFloat,
+    /// The floating point remainder, which WebAssembly has no instruction for.
+    Fmod,
+    /// Stops the program with the message from `ptr` to `ptr + len` at the
+    /// span given by the other four arguments.
+    Fail,
+}
+
+/// Instantiates a validated module and runs its exported `main`, writing
+/// what it prints to `out`.
+pub fn run(module: &Module, targets: &[Targets], out: &mut impl Write) -> Result<(), Stop> {
+    let hosts = module
+        .imports
+        .iter()
+        .map(|import| {
+            let ty = &module.types[import.ty as usize];
+            let found = HOST.iter().position(|(name, params, results)| {
+                import.module == "chop"
+                    && import.name == *name
+                    && ty.params == *params
+                    && ty.results == *results
+            });
+            match found {
+                Some(0) => Ok(Host::Print),
+                Some(1) => Ok(Host::FormatFloat),
+                Some(2) => Ok(Host::Fmod),
+                Some(_) => Ok(Host::Fail),
+                None => Err(Stop::Trap(format!(
+                    "unknown import {}.{}",
+                    import.module, import.name
+                ))),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let main = module
+        .exports
+        .iter()
+        .find(|e| e.name == "main" && e.kind == ExportKind::Function)
+        .ok_or_else(|| Stop::Trap("the module exports no `main` function".to_string()))?;
+    let ty = module.function_type(main.index).expect("validated");
+    if !ty.params.is_empty() || (main.index as usize) < hosts.len() {
+        return Err(Stop::Trap(
+            "`main` must be a function without parameters".to_string(),
+        ));
+    }
+    let (pages, max) = match module.memory {
+        Some(limits) => (limits.min, limits.max.unwrap_or(65536)),
+        None => (0, 0),
+    };
+    let mut memory = vec![0; pages as usize * PAGE];
+    for data in &module.data {
+        let at = data.offset as usize;
+        memory[at..at + data.bytes.len()].copy_from_slice(&data.bytes);
+    }
+    let globals = module
+        .globals
+        .iter()
+        .map(|global| match global.init {
+            Instr::I32Const(value) => value as u32 as u64,
+            Instr::I64Const(value) => value as u64,
+            Instr::F64Const(value) => value.to_bits(),
+            _ => unreachable!("validated"),
+        })
+        .collect();
+    let mut machine = Machine {
+        module,
+        targets,
+        hosts,
+        memory,
+        max_pages: max,
+        globals,
+        stack: Vec::new(),
+        locals: Vec::new(),
+        labels: Vec::new(),
+        frames: Vec::new(),
+        out,
+    };
+    let result = machine.call(main.index as usize - machine.hosts.len());
+    let _ = machine.out.flush();
+    result
+}
+
+/// A function call in progress: where its locals and labels start, and the
+/// height of the operand stack below its own operands.
+struct Frame {
+    function: usize,
+    pc: usize,
+    locals: usize,
+    labels: usize,
+    height: usize,
+}
+
+/// A block a branch can target.
+struct Label {
+    height: usize,
+    arity: usize,
+    /// Where a branch continues: after the `end`, or at the top of a loop.
+    target: usize,
+    is_loop: bool,
+}
+
+struct Machine<'m, W: Write> {
+    module: &'m Module,
+    targets: &'m [Targets],
+    hosts: Vec<Host>,
+    memory: Vec<u8>,
+    max_pages: u32,
+    /// Globals, locals and operands are kept as raw bits; validation
+    /// guarantees every instruction reads them at the type they were
+    /// written with.
+    globals: Vec<u64>,
+    stack: Vec<u64>,
+    locals: Vec<u64>,
+    labels: Vec<Label>,
+    frames: Vec<Frame>,
+    out: &'m mut W,
+}
+
+type Exec<T> = Result<T, Stop>;
+
+fn trap<T>(message: &str) -> Exec<T> {
+    Err(Stop::Trap(message.to_string()))
+}
+
+fn arity(ty: BlockType) -> usize {
+    match ty {
+        BlockType::Empty => 0,
+        BlockType::Value(_) => 1,
+    }
+}
+
+impl<W: Write> Machine<'_, W> {
+    fn pop(&mut self) -> u64 {
+        self.stack.pop().expect("validated")
+    }
+
+    fn pop_i32(&mut self) -> i32 {
+        self.pop() as u32 as i32
+    }
+
+    fn pop_i64(&mut self) -> i64 {
+        self.pop() as i64
+    }
+
+    fn pop_f64(&mut self) -> f64 {
+        f64::from_bits(self.pop())
+    }
+
+    fn push_i32(&mut self, value: i32) {
+        self.stack.push(value as u32 as u64);
+    }
+
+    fn push_i64(&mut self, value: i64) {
+        self.stack.push(value as u64);
+    }
+
+    fn push_f64(&mut self, value: f64) {
+        self.stack.push(value.to_bits());
+    }
+
+    fn push_bool(&mut self, value: bool) {
+        self.stack.push(value as u64);
+    }
+
+    /// The bytes from `address + offset` that an access of `width` touches.
+    fn range(&self, address: u32, offset: u32, width: usize) -> Exec<std::ops::Range<usize>> {
+        let start = address as usize + offset as usize;
+        if start + width > self.memory.len() {
+            return trap("out of bounds memory access");
+        }
+        Ok(start..start + width)
+    }
+
+    fn bytes(&self, ptr: u32, len: u32) -> Exec<&[u8]> {
+        let range = self.range(ptr, 0, len as usize)?;
+        Ok(&self.memory[range])
+    }
+
+    /// Enters a function defined by the module, with its arguments on the
+    /// stack, and runs until it returns.
+    fn call(&mut self, function: usize) -> Exec<()> {
+        self.enter(function)?;
+        let bottom = self.frames.len() - 1;
+        while self.frames.len() > bottom {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, function: usize) -> Exec<()> {
+        if self.frames.len() >= MAX_FRAMES {
+            return trap("call stack exhausted");
+        }
+        let definition = &self.module.functions[function];
+        let ty = &self.module.types[definition.ty as usize];
+        let locals = self.locals.len();
+        let height = self.stack.len() - ty.params.len();
+        self.locals.extend(self.stack.drain(height..));
+        self.locals
+            .resize(self.locals.len() + definition.locals.len(), 0);
+        self.frames.push(Frame {
+            function,
+            pc: 0,
+            locals,
+            labels: self.labels.len(),
+            height,
+        });
+        Ok(())
+    }
+
+    fn function_type(&self) -> &'_ FuncType {
+        let frame = self.frames.last().expect("a running function");
+        let ty = self.module.functions[frame.function].ty;
+        &self.module.types[ty as usize]
+    }
+
+    fn leave(&mut self) {
+        let arity = self.function_type().results.len();
+        let frame = self.frames.pop().expect("a running function");
+        let results = self.stack.len() - arity;
+        self.stack.drain(frame.height..results);
+        self.locals.truncate(frame.locals);
+        self.labels.truncate(frame.labels);
+    }
+
+    fn branch(&mut self, depth: u32) {
+        let base = self.frames.last().expect("a running function").labels;
+        if depth as usize == self.labels.len() - base {
+            return self.leave();
+        }
+        let index = self.labels.len() - 1 - depth as usize;
+        let label = &self.labels[index];
+        let (target, keep) = (label.target, if label.is_loop { index + 1 } else { index });
+        let results = self.stack.len() - label.arity;
+        self.stack.drain(label.height..results);
+        self.jump(target);
+        self.labels.truncate(keep);
+    }
+
+    fn jump(&mut self, pc: usize) {
+        self.frames.last_mut().expect("a running function").pc = pc;
+    }
+
+    fn host(&mut self, host: Host) -> Exec<()> {
+        match host {
+            Host::Print => {
+                let len = self.pop_i32() as u32;
+                let ptr = self.pop_i32() as u32;
+                let range = self.range(ptr, 0, len as usize)?;
+                let _ = self.out.write_all(&self.memory[range]);
+            }
+            Host::FormatFloat => {
+                let out = self.pop_i32() as u32;
+                let value = self.pop_f64();
+                let text = format!("{:?}", value);
+                let range = self.range(out, 0, text.len())?;
+                self.memory[range].copy_from_slice(text.as_bytes());
+                self.push_i32(text.len() as i32);
+            }
+            Host::Fmod => {
+                let b = self.pop_f64();
+                let a = self.pop_f64();
+                self.push_f64(a % b);
+            }
+            Host::Fail => {
+                let end_column = self.pop_i32() as u16;
+                let end_line = self.pop_i32() as u32;
+                let column = self.pop_i32() as u16;
+                let line = self.pop_i32() as u32;
+                let len = self.pop_i32() as u32;
+                let ptr = self.pop_i32() as u32;
+                let message = String::from_utf8_lossy(self.bytes(ptr, len)?).into_owned();
+                let span = Span::new(Position(line, column), Position(end_line, end_column));
+                return Err(Stop::Failed(Diagnostic::error(message, span)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs one instruction of the innermost frame.
+    fn step(&mut self) -> Exec<()> {
+        let (module, all) = (self.module, self.targets);
+        let frame = self.frames.last_mut().expect("a running function");
+        let at = frame.pc;
+        frame.pc += 1;
+        let (function, locals, labels) = (frame.function, frame.locals, frame.labels);
+        let targets = &all[function];
+        match &module.functions[function].code[at] {
+            Instr::Unreachable => return trap("unreachable executed"),
+            Instr::Nop => {}
+            Instr::Block(ty) => self.labels.push(Label {
+                height: self.stack.len(),
+                arity: arity(*ty),
+                target: targets.ends[at] as usize + 1,
+                is_loop: false,
+            }),
+            Instr::Loop(_) => self.labels.push(Label {
+                height: self.stack.len(),
+                arity: 0,
+                target: at + 1,
+                is_loop: true,
+            }),
+            Instr::If(ty) => {
+                let (end, other) = (targets.ends[at] as usize, targets.elses[at]);
+                let condition = self.pop_i32();
+                if condition == 0 && other == u32::MAX {
+                    self.jump(end + 1);
+                } else {
+                    if condition == 0 {
+                        self.jump(other as usize + 1);
+                    }
+                    self.labels.push(Label {
+                        height: self.stack.len(),
+                        arity: arity(*ty),
+                        target: end + 1,
+                        is_loop: false,
+                    });
+                }
+            }
+            Instr::Else => {
+                self.jump(targets.ends[at] as usize + 1);
+                self.labels.pop();
+            }
+            Instr::End => {
+                if self.labels.len() > labels {
+                    self.labels.pop();
+                } else {
+                    self.leave();
+                }
+            }
+            Instr::Br(depth) => self.branch(*depth),
+            Instr::BrIf(depth) => {
+                if self.pop_i32() != 0 {
+                    self.branch(*depth);
+                }
+            }
+            Instr::BrTable(depths, default) => {
+                let index = self.pop_i32() as u32 as usize;
+                let depth = depths.get(index).unwrap_or(default);
+                self.branch(*depth);
+            }
+            Instr::Return => self.leave(),
+            Instr::Call(index) => {
+                let index = *index as usize;
+                match self.hosts.get(index) {
+                    Some(host) => self.host(*host)?,
+                    None => self.enter(index - self.hosts.len())?,
+                }
+            }
+            // The caller's frame goes before the callee's comes, so tail
+            // recursion runs in constant space.
+            Instr::ReturnCall(index) => {
+                let ty = self.module.function_type(*index).expect("validated");
+                let params = ty.params.len();
+                let index = *index as usize;
+                let args = self.stack.split_off(self.stack.len() - params);
+                let frame = self.frames.pop().expect("a running function");
+                self.stack.truncate(frame.height);
+                self.locals.truncate(frame.locals);
+                self.labels.truncate(frame.labels);
+                self.stack.extend(args);
+                match self.hosts.get(index) {
+                    Some(host) => self.host(*host)?,
+                    None => self.enter(index - self.hosts.len())?,
+                }
+            }
+            Instr::Drop => {
+                self.pop();
+            }
+            Instr::Select => {
+                let condition = self.pop_i32();
+                let b = self.pop();
+                let a = self.pop();
+                self.stack.push(if condition != 0 { a } else { b });
+            }
+            Instr::LocalGet(index) => self.stack.push(self.locals[locals + *index as usize]),
+            Instr::LocalSet(index) => {
+                let value = self.pop();
+                self.locals[locals + *index as usize] = value;
+            }
+            Instr::LocalTee(index) => {
+                let value = *self.stack.last().expect("validated");
+                self.locals[locals + *index as usize] = value;
+            }
+            Instr::GlobalGet(index) => self.stack.push(self.globals[*index as usize]),
+            Instr::GlobalSet(index) => {
+                let value = self.pop();
+                self.globals[*index as usize] = value;
+            }
+            Instr::Memory(opcode, arg) => self.access(*opcode, arg.offset)?,
+            Instr::MemorySize => self.push_i32((self.memory.len() / PAGE) as i32),
+            Instr::MemoryGrow => {
+                let pages = self.memory.len() / PAGE;
+                let more = self.pop_i32() as u32 as usize;
+                if pages + more > self.max_pages as usize {
+                    self.push_i32(-1);
+                } else {
+                    self.memory.resize((pages + more) * PAGE, 0);
+                    self.push_i32(pages as i32);
+                }
+            }
+            Instr::I32Const(value) => self.push_i32(*value),
+            Instr::I64Const(value) => self.push_i64(*value),
+            Instr::F64Const(value) => self.push_f64(*value),
+            Instr::Numeric(opcode) => self.numeric(*opcode)?,
+        }
+        Ok(())
+    }
+
+    fn access(&mut self, opcode: u8, offset: u32) -> Exec<()> {
+        match opcode {
+            memory::I32_STORE | memory::I64_STORE | memory::F64_STORE | memory::I32_STORE8 => {
+                let value = self.pop();
+                let address = self.pop_i32() as u32;
+                let width = match opcode {
+                    memory::I32_STORE => 4,
+                    memory::I32_STORE8 => 1,
+                    _ => 8,
+                };
+                let range = self.range(address, offset, width)?;
+                self.memory[range].copy_from_slice(&value.to_le_bytes()[..width]);
+            }
+            _ => {
+                let address = self.pop_i32() as u32;
+                let width = match opcode {
+                    memory::I32_LOAD => 4,
+                    memory::I32_LOAD8_U => 1,
+                    _ => 8,
+                };
+                let range = self.range(address, offset, width)?;
+                let mut bytes = [0; 8];
+                bytes[..width].copy_from_slice(&self.memory[range]);
+                self.stack.push(u64::from_le_bytes(bytes));
+            }
+        }
+        Ok(())
+    }
+
+    fn numeric(&mut self, opcode: u8) -> Exec<()> {
+        match opcode {
+            op::I32_EQZ => {
+                let a = self.pop_i32();
+                self.push_bool(a == 0);
+            }
+            op::I64_EQZ => {
+                let a = self.pop_i64();
+                self.push_bool(a == 0);
+            }
+            op::I32_EQ..=op::I32_GE_U => {
+                let b = self.pop_i32();
+                let a = self.pop_i32();
+                let (ua, ub) = (a as u32, b as u32);
+                self.push_bool(match opcode {
+                    op::I32_EQ => a == b,
+                    op::I32_NE => a != b,
+                    op::I32_LT_S => a < b,
+                    op::I32_LT_U => ua < ub,
+                    op::I32_GT_S => a > b,
+                    op::I32_GT_U => ua > ub,
+                    op::I32_LE_S => a <= b,
+                    op::I32_LE_U => ua <= ub,
+                    op::I32_GE_S => a >= b,
+                    _ => ua >= ub,
+                });
+            }
+            op::I64_EQ..=op::I64_GE_U => {
+                let b = self.pop_i64();
+                let a = self.pop_i64();
+                let (ua, ub) = (a as u64, b as u64);
+                self.push_bool(match opcode {
+                    op::I64_EQ => a == b,
+                    op::I64_NE => a != b,
+                    op::I64_LT_S => a < b,
+                    op::I64_LT_U => ua < ub,
+                    op::I64_GT_S => a > b,
+                    op::I64_GT_U => ua > ub,
+                    op::I64_LE_S => a <= b,
+                    op::I64_LE_U => ua <= ub,
+                    op::I64_GE_S => a >= b,
+                    _ => ua >= ub,
+                });
+            }
+            op::F64_EQ..=op::F64_GE => {
+                let b = self.pop_f64();
+                let a = self.pop_f64();
+                self.push_bool(match opcode {
+                    op::F64_EQ => a == b,
+                    op::F64_NE => a != b,
+                    op::F64_LT => a < b,
+                    op::F64_GT => a > b,
+                    op::F64_LE => a <= b,
+                    _ => a >= b,
+                });
+            }
+            op::I32_ADD..=op::I32_SHR_U => {
+                let b = self.pop_i32();
+                let a = self.pop_i32();
+                let (ua, ub) = (a as u32, b as u32);
+                let result = match opcode {
+                    op::I32_ADD => a.wrapping_add(b),
+                    op::I32_SUB => a.wrapping_sub(b),
+                    op::I32_MUL => a.wrapping_mul(b),
+                    op::I32_DIV_S | op::I32_REM_S if b == 0 => {
+                        return trap("integer divide by zero")
+                    }
+                    op::I32_DIV_S if a == i32::MIN && b == -1 => return trap("integer overflow"),
+                    op::I32_DIV_S => a / b,
+                    op::I32_REM_S => a.wrapping_rem(b),
+                    op::I32_DIV_U | op::I32_REM_U if b == 0 => {
+                        return trap("integer divide by zero")
+                    }
+                    op::I32_DIV_U => (ua / ub) as i32,
+                    op::I32_REM_U => (ua % ub) as i32,
+                    op::I32_AND => a & b,
+                    op::I32_OR => a | b,
+                    op::I32_XOR => a ^ b,
+                    op::I32_SHL => a.wrapping_shl(ub),
+                    op::I32_SHR_S => a.wrapping_shr(ub),
+                    _ => ua.wrapping_shr(ub) as i32,
+                };
+                self.push_i32(result);
+            }
+            op::I64_ADD..=op::I64_SHR_U => {
+                let b = self.pop_i64();
+                let a = self.pop_i64();
+                let (ua, ub) = (a as u64, b as u64);
+                let result = match opcode {
+                    op::I64_ADD => a.wrapping_add(b),
+                    op::I64_SUB => a.wrapping_sub(b),
+                    op::I64_MUL => a.wrapping_mul(b),
+                    op::I64_DIV_S | op::I64_REM_S if b == 0 => {
+                        return trap("integer divide by zero")
+                    }
+                    op::I64_DIV_S if a == i64::MIN && b == -1 => return trap("integer overflow"),
+                    op::I64_DIV_S => a / b,
+                    op::I64_REM_S => a.wrapping_rem(b),
+                    op::I64_DIV_U | op::I64_REM_U if b == 0 => {
+                        return trap("integer divide by zero")
+                    }
+                    op::I64_DIV_U => (ua / ub) as i64,
+                    op::I64_REM_U => (ua % ub) as i64,
+                    op::I64_AND => a & b,
+                    op::I64_OR => a | b,
+                    op::I64_XOR => a ^ b,
+                    op::I64_SHL => a.wrapping_shl(ub as u32),
+                    op::I64_SHR_S => a.wrapping_shr(ub as u32),
+                    _ => ua.wrapping_shr(ub as u32) as i64,
+                };
+                self.push_i64(result);
+            }
+            op::F64_ABS | op::F64_NEG | op::F64_SQRT => {
+                let a = self.pop_f64();
+                self.push_f64(match opcode {
+                    op::F64_ABS => a.abs(),
+                    op::F64_NEG => -a,
+                    _ => a.sqrt(),
+                });
+            }
+            op::F64_ADD..=op::F64_MAX => {
+                let b = self.pop_f64();
+                let a = self.pop_f64();
+                self.push_f64(match opcode {
+                    op::F64_ADD => a + b,
+                    op::F64_SUB => a - b,
+                    op::F64_MUL => a * b,
+                    op::F64_DIV => a / b,
+                    // Unlike Rust's, WebAssembly's min and max propagate NaN.
+                    _ if a.is_nan() || b.is_nan() => f64::NAN,
+                    op::F64_MIN => a.min(b),
+                    _ => a.max(b),
+                });
+            }
+            op::I32_WRAP_I64 => {
+                let a = self.pop_i64();
+                self.push_i32(a as i32);
+            }
+            op::I64_EXTEND_I32_S => {
+                let a = self.pop_i32();
+                self.push_i64(a as i64);
+            }
+            op::I64_EXTEND_I32_U => {
+                let a = self.pop_i32();
+                self.push_i64(a as u32 as i64);
+            }
+            op::F64_CONVERT_I32_S => {
+                let a = self.pop_i32();
+                self.push_f64(a as f64);
+            }
+            op::F64_CONVERT_I64_S => {
+                let a = self.pop_i64();
+                self.push_f64(a as f64);
+            }
+            // Reinterpretations keep the bits as they are.
+            op::I64_REINTERPRET_F64 | op::F64_REINTERPRET_I64 => {}
+            other => unreachable!("0x{:02x} passed validation", other),
+        }
+        Ok(())
+    }
+}
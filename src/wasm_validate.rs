@@ -0,0 +1,434 @@
+use std::collections::HashSet;
+
+use crate::wasm::{
+    memory_type, numeric_type, BlockType, ExportKind, FuncType, Instr, Module, ValType, PAGE,
+};
+
+/// Where control goes for each structured instruction of a function: the
+/// `end` of every `block`, `loop` and `if`, and the `else` of every `if`
+/// that has one, by instruction index.
+#[derive(Debug, Default)]
+pub struct Targets {
+    pub ends: Vec<u32>,
+    pub elses: Vec<u32>,
+}
+
+/// The most pages a memory may have.
+const MAX_PAGES: u32 = 65536;
+
+/// Checks that a module is well formed and well typed, following the
+/// validation algorithm of the WebAssembly specification, and returns the
+/// targets of each function's structured instructions.
+pub fn validate(module: &Module) -> Result<Vec<Targets>, String> {
+    for import in &module.imports {
+        if module.types.get(import.ty as usize).is_none() {
+            return Err(format!(
+                "import {}.{} has an unknown type",
+                import.module, import.name
+            ));
+        }
+    }
+    for (index, function) in module.functions.iter().enumerate() {
+        if module.types.get(function.ty as usize).is_none() {
+            return Err(format!("function {} has an unknown type", index));
+        }
+    }
+    if let Some(limits) = module.memory {
+        if limits.min > MAX_PAGES
+            || limits
+                .max
+                .is_some_and(|max| max > MAX_PAGES || max < limits.min)
+        {
+            return Err("the memory limits are invalid".to_string());
+        }
+    }
+    for (index, global) in module.globals.iter().enumerate() {
+        let ty = match global.init {
+            Instr::I32Const(_) => ValType::I32,
+            Instr::I64Const(_) => ValType::I64,
+            Instr::F64Const(_) => ValType::F64,
+            // Only imported globals may initialize others, and there are none.
+            _ => return Err(format!("global {} has an invalid initializer", index)),
+        };
+        if ty != global.ty {
+            return Err(format!(
+                "global {} is initialized with the wrong type",
+                index
+            ));
+        }
+    }
+    let functions = module.imports.len() + module.functions.len();
+    let mut names = HashSet::new();
+    for export in &module.exports {
+        if !names.insert(export.name.as_str()) {
+            return Err(format!("`{}` is exported twice", export.name));
+        }
+        let exists = match export.kind {
+            ExportKind::Function => (export.index as usize) < functions,
+            ExportKind::Memory => export.index == 0 && module.memory.is_some(),
+            ExportKind::Global => (export.index as usize) < module.globals.len(),
+        };
+        if !exists {
+            return Err(format!("export `{}` refers to nothing", export.name));
+        }
+    }
+    for data in &module.data {
+        let Some(limits) = module.memory else {
+            return Err("a data segment needs a memory".to_string());
+        };
+        let end = data.offset as u64 + data.bytes.len() as u64;
+        if end > limits.min as u64 * PAGE as u64 {
+            return Err("a data segment does not fit in the memory".to_string());
+        }
+    }
+    module
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| {
+            let ty = &module.types[function.ty as usize];
+            let mut locals = ty.params.clone();
+            locals.extend(&function.locals);
+            let mut checker = Checker {
+                module,
+                locals,
+                operands: Vec::new(),
+                frames: Vec::new(),
+                targets: Targets {
+                    ends: vec![u32::MAX; function.code.len()],
+                    elses: vec![u32::MAX; function.code.len()],
+                },
+            };
+            checker
+                .function(ty, &function.code)
+                .map_err(|e| format!("function {}: {}", index + module.imports.len(), e))?;
+            Ok(checker.targets)
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Block,
+    Loop,
+    If,
+    Else,
+    Function,
+}
+
+/// An open block, as in the specification's control stack.
+struct Frame {
+    kind: Kind,
+    result: Option<ValType>,
+    /// The height of the operand stack when the block was entered.
+    height: usize,
+    /// Whether the rest of the block is unreachable, so that its operands
+    /// may be of any type.
+    unreachable: bool,
+    /// The index of the instruction that opened the block.
+    start: usize,
+}
+
+struct Checker<'m> {
+    module: &'m Module,
+    locals: Vec<ValType>,
+    /// Operand types; `None` is a value of unknown type in unreachable code.
+    operands: Vec<Option<ValType>>,
+    frames: Vec<Frame>,
+    targets: Targets,
+}
+
+impl Checker<'_> {
+    fn function(&mut self, ty: &FuncType, code: &[Instr]) -> Result<(), String> {
+        if ty.results.len() > 1 {
+            return Err("functions return at most one value".to_string());
+        }
+        self.frames.push(Frame {
+            kind: Kind::Function,
+            result: ty.results.first().copied(),
+            height: 0,
+            unreachable: false,
+            start: 0,
+        });
+        for (at, instr) in code.iter().enumerate() {
+            if self.frames.is_empty() {
+                return Err("instructions follow the end of the function".to_string());
+            }
+            self.instr(at, instr)
+                .map_err(|e| format!("instruction {}: {}", at, e))?;
+        }
+        if !self.frames.is_empty() {
+            return Err("the function does not end".to_string());
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, ty: ValType) {
+        self.operands.push(Some(ty));
+    }
+
+    fn pop(&mut self) -> Result<Option<ValType>, String> {
+        let frame = self.frames.last().expect("an open block");
+        if self.operands.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return Err("an instruction is missing an operand".to_string());
+        }
+        Ok(self.operands.pop().expect("an operand"))
+    }
+
+    fn expect(&mut self, expected: ValType) -> Result<(), String> {
+        match self.pop()? {
+            Some(actual) if actual != expected => Err(format!(
+                "expected an operand of type {:?}, found {:?}",
+                expected, actual
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks the rest of the block as unreachable.
+    fn unreachable(&mut self) {
+        let frame = self.frames.last_mut().expect("an open block");
+        self.operands.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn open(&mut self, kind: Kind, ty: BlockType, start: usize) {
+        self.frames.push(Frame {
+            kind,
+            result: match ty {
+                BlockType::Empty => None,
+                BlockType::Value(ty) => Some(ty),
+            },
+            height: self.operands.len(),
+            unreachable: false,
+            start,
+        });
+    }
+
+    /// Checks that the block left exactly its result, and closes it.
+    fn close(&mut self) -> Result<Frame, String> {
+        let result = self.frames.last().expect("an open block").result;
+        if let Some(ty) = result {
+            self.expect(ty)?;
+        }
+        let frame = self.frames.pop().expect("an open block");
+        if self.operands.len() != frame.height {
+            return Err("a block leaves extra operands".to_string());
+        }
+        Ok(frame)
+    }
+
+    /// The types a branch to the block `depth` levels out carries.
+    fn label(&self, depth: u32) -> Result<Option<ValType>, String> {
+        let index = self
+            .frames
+            .len()
+            .checked_sub(depth as usize + 1)
+            .ok_or_else(|| format!("there is no block {} levels out", depth))?;
+        let frame = &self.frames[index];
+        Ok(match frame.kind {
+            Kind::Loop => None,
+            _ => frame.result,
+        })
+    }
+
+    fn local(&self, index: u32) -> Result<ValType, String> {
+        self.locals
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("there is no local {}", index))
+    }
+
+    fn global(&self, index: u32) -> Result<(ValType, bool), String> {
+        self.module
+            .globals
+            .get(index as usize)
+            .map(|g| (g.ty, g.mutable))
+            .ok_or_else(|| format!("there is no global {}", index))
+    }
+
+    fn memory(&self) -> Result<(), String> {
+        match self.module.memory {
+            Some(_) => Ok(()),
+            None => Err("the module has no memory".to_string()),
+        }
+    }
+
+    fn instr(&mut self, at: usize, instr: &Instr) -> Result<(), String> {
+        match instr {
+            Instr::Unreachable => self.unreachable(),
+            Instr::Nop => {}
+            Instr::Block(ty) => self.open(Kind::Block, *ty, at),
+            Instr::Loop(ty) => self.open(Kind::Loop, *ty, at),
+            Instr::If(ty) => {
+                self.expect(ValType::I32)?;
+                self.open(Kind::If, *ty, at);
+            }
+            Instr::Else => {
+                if self.frames.last().map(|f| f.kind) != Some(Kind::If) {
+                    return Err("`else` outside of an `if`".to_string());
+                }
+                let frame = self.close()?;
+                self.targets.elses[frame.start] = at as u32;
+                self.frames.push(Frame {
+                    kind: Kind::Else,
+                    height: self.operands.len(),
+                    unreachable: false,
+                    ..frame
+                });
+            }
+            Instr::End => {
+                let frame = self.close()?;
+                if frame.kind == Kind::If && frame.result.is_some() {
+                    return Err("an `if` with a result needs an `else`".to_string());
+                }
+                if frame.kind == Kind::Else {
+                    let start = frame.start;
+                    self.targets.ends[self.targets.elses[start] as usize] = at as u32;
+                }
+                self.targets.ends[frame.start] = at as u32;
+                if let Some(ty) = frame.result {
+                    self.push(ty);
+                }
+            }
+            Instr::Br(depth) => {
+                if let Some(ty) = self.label(*depth)? {
+                    self.expect(ty)?;
+                }
+                self.unreachable();
+            }
+            Instr::BrIf(depth) => {
+                self.expect(ValType::I32)?;
+                if let Some(ty) = self.label(*depth)? {
+                    self.expect(ty)?;
+                    self.push(ty);
+                }
+            }
+            Instr::BrTable(depths, default) => {
+                self.expect(ValType::I32)?;
+                let ty = self.label(*default)?;
+                for depth in depths {
+                    if self.label(*depth)? != ty {
+                        return Err("the targets of a `br_table` disagree".to_string());
+                    }
+                }
+                if let Some(ty) = ty {
+                    self.expect(ty)?;
+                }
+                self.unreachable();
+            }
+            Instr::Return => {
+                if let Some(ty) = self.frames[0].result {
+                    self.expect(ty)?;
+                }
+                self.unreachable();
+            }
+            Instr::Call(index) => {
+                let ty = self
+                    .module
+                    .function_type(*index)
+                    .ok_or_else(|| format!("there is no function {}", index))?;
+                if ty.results.len() > 1 {
+                    return Err("functions return at most one value".to_string());
+                }
+                for param in ty.params.iter().rev() {
+                    self.expect(*param)?;
+                }
+                if let Some(result) = ty.results.first() {
+                    self.push(*result);
+                }
+            }
+            Instr::ReturnCall(index) => {
+                let ty = self
+                    .module
+                    .function_type(*index)
+                    .ok_or_else(|| format!("there is no function {}", index))?;
+                if ty.results.first().copied() != self.frames[0].result || ty.results.len() > 1 {
+                    return Err("a tail call must return what its caller returns".to_string());
+                }
+                for param in ty.params.iter().rev() {
+                    self.expect(*param)?;
+                }
+                self.unreachable();
+            }
+            Instr::Drop => {
+                self.pop()?;
+            }
+            Instr::Select => {
+                self.expect(ValType::I32)?;
+                let b = self.pop()?;
+                let a = self.pop()?;
+                match (a, b) {
+                    (Some(a), Some(b)) if a != b => {
+                        return Err("the operands of `select` differ".to_string())
+                    }
+                    (Some(ty), _) | (_, Some(ty)) => self.push(ty),
+                    (None, None) => self.operands.push(None),
+                }
+            }
+            Instr::LocalGet(index) => {
+                let ty = self.local(*index)?;
+                self.push(ty);
+            }
+            Instr::LocalSet(index) => {
+                let ty = self.local(*index)?;
+                self.expect(ty)?;
+            }
+            Instr::LocalTee(index) => {
+                let ty = self.local(*index)?;
+                self.expect(ty)?;
+                self.push(ty);
+            }
+            Instr::GlobalGet(index) => {
+                let (ty, _) = self.global(*index)?;
+                self.push(ty);
+            }
+            Instr::GlobalSet(index) => {
+                let (ty, mutable) = self.global(*index)?;
+                if !mutable {
+                    return Err(format!("global {} is immutable", index));
+                }
+                self.expect(ty)?;
+            }
+            Instr::Memory(opcode, arg) => {
+                self.memory()?;
+                let (ty, store, width) = memory_type(*opcode).expect("decoded opcodes are known");
+                if 1u64 << arg.align.min(32) > width as u64 {
+                    return Err("an alignment is larger than its access".to_string());
+                }
+                if store {
+                    self.expect(ty)?;
+                    self.expect(ValType::I32)?;
+                } else {
+                    self.expect(ValType::I32)?;
+                    self.push(ty);
+                }
+            }
+            Instr::MemorySize => {
+                self.memory()?;
+                self.push(ValType::I32);
+            }
+            Instr::MemoryGrow => {
+                self.memory()?;
+                self.expect(ValType::I32)?;
+                self.push(ValType::I32);
+            }
+            Instr::I32Const(_) => self.push(ValType::I32),
+            Instr::I64Const(_) => self.push(ValType::I64),
+            Instr::F64Const(_) => self.push(ValType::F64),
+            Instr::Numeric(opcode) => {
+                let (params, result) = numeric_type(*opcode).expect("decoded opcodes are known");
+                for param in params.iter().rev() {
+                    self.expect(*param)?;
+                }
+                if let Some(result) = result {
+                    self.push(result);
+                }
+            }
+        }
+        Ok(())
+    }
+}
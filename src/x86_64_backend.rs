@@ -0,0 +1,1806 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::bytecode::{Constant, Function, Op, Pattern, Program};
+use crate::c_backend::effect;
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::MAX_CALL_DEPTH;
+use crate::tokens::Span;
+
+const RUNTIME: &str = include_str!("x86_64_runtime.s");
+
+/// The callee-saved registers values are allocated to, in the order they
+/// are handed out.
+const REGISTERS: &[&str] = &["%rbx", "%r12", "%r13", "%r14", "%r15"];
+/// The registers the first arguments of a call are passed in.
+const ARGUMENTS: &[&str] = &["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+/// The most versions of functions a program is specialised into.
+const MAX_INSTANCES: usize = 10_000;
+/// Stack for the runtime and the C library on top of the chop frames.
+const STACK_MARGIN: usize = 1 << 22;
+
+/// Appends an instruction to the code being generated.
+macro_rules! emit {
+    ($backend:expr, $($arg:tt)*) => {{
+        let _ = writeln!($backend.text, "        {}", format_args!($($arg)*));
+    }};
+}
+
+/// Lowers a compiled program to x86-64 assembly for the GNU assembler,
+/// following the System V calling convention, or explains which of its
+/// constructs the target does not support yet.
+///
+/// Values are not tagged: every function is specialised to the kinds of
+/// its arguments, and the kind of every slot and stack position is found
+/// by running the bytecode over kinds until nothing changes. Ints, floats
+/// and bools are held in a register; strings, lists, structs and iterators
+/// are pointers to memory that is never freed. The slots and stack
+/// positions of a function are given callee-saved registers by a linear
+/// scan over the instructions where they are live, and the rest are
+/// spilled to its frame. Printing goes through the C library, so the
+/// output is linked with `cc`.
+pub fn generate(program: &Program, path: &str, source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut backend = Backend::new(program, path, source);
+    let span = program.functions[program.main as usize].spans[0];
+    let main = backend
+        .instance(program.main, Vec::new(), span)
+        .expect("the first instance is always created");
+    backend.infer();
+    if !backend.errors.is_empty() {
+        return Err(backend.errors);
+    }
+    Ok(backend.assembly(main))
+}
+
+/// What the values in a slot or on the stack can be. Where a kind is
+/// optional, `None` means no value ever gets there.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    Null,
+    Unit,
+    Int,
+    Float,
+    Bool,
+    Str,
+    List(Box<Kind>),
+    Struct(u32),
+    Type(String),
+    /// An iterator over a list with items of the kind.
+    Iterator(Box<Kind>),
+    /// An iterator over the characters of a string.
+    Chars,
+    /// The items of a list that is always empty.
+    Empty,
+}
+
+/// The kind of values that are one kind or the other, if there is one.
+fn join(a: &Kind, b: &Kind) -> Option<Kind> {
+    match (a, b) {
+        _ if a == b => Some(a.clone()),
+        (Kind::Empty, other) | (other, Kind::Empty) => Some(other.clone()),
+        (Kind::List(x), Kind::List(y)) => Some(Kind::List(Box::new(join(x, y)?))),
+        (Kind::Iterator(x), Kind::Iterator(y)) => Some(Kind::Iterator(Box::new(join(x, y)?))),
+        _ => None,
+    }
+}
+
+fn constant_kind(constant: &Constant) -> Kind {
+    match constant {
+        Constant::Null => Kind::Null,
+        Constant::Unit => Kind::Unit,
+        Constant::Int(_) => Kind::Int,
+        Constant::Float(_) => Kind::Float,
+        Constant::Bool(_) => Kind::Bool,
+        Constant::String(_) => Kind::Str,
+    }
+}
+
+/// The kinds before an instruction.
+#[derive(Clone, Debug, PartialEq)]
+struct State {
+    slots: Vec<Option<Kind>>,
+    stack: Vec<Option<Kind>>,
+}
+
+/// A function specialised to the kinds of its arguments.
+struct Instance {
+    function: u32,
+    args: Vec<Kind>,
+    result: Option<Kind>,
+    states: Vec<Option<State>>,
+}
+
+/// How a method call is dispatched for the kind of its receiver.
+enum Dispatch {
+    /// `T.init(...)` building the struct with the index.
+    Init(u32),
+    /// A call of the function with the arguments from the one at the
+    /// offset, which skips a type receiver.
+    Call(u32, usize),
+    Missing(String),
+}
+
+/// A helper generated once for the kinds it handles.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Routine {
+    /// Prints a list or struct.
+    Show(Kind),
+    /// Compares two lists or structs.
+    Equal(Kind, Kind),
+}
+
+/// Part of the message of a runtime error: text, or a value as it displays.
+enum Part {
+    Text(String),
+    Value(String, Kind),
+}
+
+struct Backend<'p> {
+    program: &'p Program,
+    path: &'p str,
+    source: &'p str,
+    instances: Vec<Instance>,
+    by_args: HashMap<(u32, Vec<Kind>), usize>,
+    /// The kinds of the fields of each struct.
+    fields: Vec<Vec<Option<Kind>>>,
+    globals: Vec<Option<Kind>>,
+    liveness: HashMap<u32, Rc<Vec<Vec<bool>>>>,
+    /// Whether the last pass over the instances learned anything.
+    changed: bool,
+    /// Whether conflicts are reported, which only happens once the kinds
+    /// are final.
+    report: bool,
+    errors: Vec<Diagnostic>,
+    text: String,
+    data: String,
+    strings: HashMap<(String, bool), String>,
+    sites: HashMap<Span, String>,
+    routines: HashMap<Routine, String>,
+    pending: Vec<(Routine, String)>,
+    labels: usize,
+    /// The operand of each slot and stack position of the function being
+    /// generated.
+    operands: Vec<String>,
+    /// How many of `REGISTERS` the function being generated saves.
+    saved: usize,
+    /// The most stack one call of any function uses.
+    frame: usize,
+    /// How many slots every call passes on the stack, enough for the
+    /// function taking the most arguments, so a tail call can always pass
+    /// its arguments where those of the running call came in.
+    area: usize,
+}
+
+impl<'p> Backend<'p> {
+    fn new(program: &'p Program, path: &'p str, source: &'p str) -> Self {
+        Backend {
+            program,
+            path,
+            source,
+            instances: Vec::new(),
+            by_args: HashMap::new(),
+            fields: program
+                .structs
+                .iter()
+                .map(|info| vec![None; info.fields.len()])
+                .collect(),
+            globals: vec![None; program.globals.len()],
+            liveness: HashMap::new(),
+            changed: false,
+            report: false,
+            errors: Vec::new(),
+            text: String::new(),
+            data: String::new(),
+            strings: HashMap::new(),
+            sites: HashMap::new(),
+            routines: HashMap::new(),
+            pending: Vec::new(),
+            labels: 0,
+            operands: Vec::new(),
+            saved: 0,
+            frame: 0,
+            area: program
+                .functions
+                .iter()
+                .map(|function| (function.arity as usize).saturating_sub(ARGUMENTS.len()))
+                .max()
+                .unwrap_or(0)
+                .next_multiple_of(2),
+        }
+    }
+
+    fn unsupported(&mut self, what: &str, span: Span) {
+        if !self.report {
+            return;
+        }
+        let message = format!("the x86-64 target does not support {} yet", what);
+        let diagnostic = Diagnostic::error(message, span);
+        if !self.errors.contains(&diagnostic) {
+            self.errors.push(diagnostic);
+        }
+    }
+
+    /// The kinds of every instance, once analysing them again would not
+    /// change any of them.
+    fn infer(&mut self) {
+        loop {
+            self.changed = false;
+            let mut index = 0;
+            while index < self.instances.len() {
+                self.analyse(index);
+                index += 1;
+            }
+            if !self.changed {
+                break;
+            }
+        }
+        self.report = true;
+        for index in 0..self.instances.len() {
+            self.analyse(index);
+        }
+    }
+
+    /// The instance of a function for arguments of these kinds.
+    fn instance(&mut self, function: u32, args: Vec<Kind>, span: Span) -> Option<usize> {
+        if let Some(memo) = self.program.functions[function as usize].memo {
+            self.unsupported("`@memo` caches", memo.span);
+        }
+        let key = (function, args);
+        if let Some(&index) = self.by_args.get(&key) {
+            return Some(index);
+        }
+        if self.instances.len() >= MAX_INSTANCES {
+            let what = format!("more than {} versions of its functions", MAX_INSTANCES);
+            self.unsupported(&what, span);
+            return None;
+        }
+        let index = self.instances.len();
+        self.instances.push(Instance {
+            function,
+            args: key.1.clone(),
+            result: None,
+            states: Vec::new(),
+        });
+        self.by_args.insert(key, index);
+        self.changed = true;
+        Some(index)
+    }
+
+    fn live(&mut self, function: u32) -> Rc<Vec<Vec<bool>>> {
+        let program = self.program;
+        self.liveness
+            .entry(function)
+            .or_insert_with(|| Rc::new(liveness(&program.functions[function as usize])))
+            .clone()
+    }
+
+    /// Runs an instance over kinds, from its arguments to every instruction
+    /// it reaches.
+    fn analyse(&mut self, index: usize) {
+        let program = self.program;
+        let function = &program.functions[self.instances[index].function as usize];
+        let live = self.live(self.instances[index].function);
+        let mut entry = State {
+            slots: vec![Some(Kind::Unit); function.slots as usize],
+            stack: Vec::new(),
+        };
+        for (slot, kind) in self.instances[index].args.iter().enumerate() {
+            entry.slots[slot] = Some(kind.clone());
+        }
+        let mut states: Vec<Option<State>> = vec![None; function.code.len()];
+        let mut work = vec![(0, entry)];
+        while let Some((ip, mut state)) = work.pop() {
+            // Slots nobody reads again are forgotten, so that a variable
+            // scoped to a loop can hold other kinds elsewhere.
+            for (slot, live) in state.slots.iter_mut().zip(&live[ip]) {
+                if !live {
+                    *slot = None;
+                }
+            }
+            let state = match &states[ip] {
+                None => state,
+                Some(known) => {
+                    let known = known.clone();
+                    let joined = self.join_states(&known, &state, function.spans[ip]);
+                    if joined == known {
+                        continue;
+                    }
+                    joined
+                }
+            };
+            states[ip] = Some(state.clone());
+            work.extend(self.transfer(index, function, ip, state));
+        }
+        self.instances[index].states = states;
+    }
+
+    fn join_states(&mut self, known: &State, new: &State, span: Span) -> State {
+        let slots = known.slots.iter().zip(&new.slots);
+        let stack = known.stack.iter().zip(&new.stack);
+        State {
+            slots: slots.map(|(a, b)| self.merge(a, b, span)).collect(),
+            stack: stack.map(|(a, b)| self.merge(a, b, span)).collect(),
+        }
+    }
+
+    /// The kind of values of either kind. Kinds that do not join keep the
+    /// known one, and are reported.
+    fn merge(&mut self, known: &Option<Kind>, new: &Option<Kind>, span: Span) -> Option<Kind> {
+        match (known, new) {
+            (None, kind) | (kind, None) => kind.clone(),
+            (Some(a), Some(b)) => match join(a, b) {
+                Some(kind) => Some(kind),
+                None => {
+                    let what = format!(
+                        "values that can be both `{}` and `{}`",
+                        self.describe(a),
+                        self.describe(b)
+                    );
+                    self.unsupported(&what, span);
+                    Some(a.clone())
+                }
+            },
+        }
+    }
+
+    fn describe(&self, kind: &Kind) -> String {
+        match kind {
+            Kind::List(item) if **item != Kind::Empty => format!("list of {}", self.describe(item)),
+            Kind::Iterator(_) | Kind::Chars => "iterator".to_string(),
+            other => self.type_name(other),
+        }
+    }
+
+    /// The name methods are looked up under for values of a kind.
+    fn type_name(&self, kind: &Kind) -> String {
+        match kind {
+            Kind::Null => "null".to_string(),
+            Kind::Unit => "()".to_string(),
+            Kind::Int => "int".to_string(),
+            Kind::Float => "float".to_string(),
+            Kind::Bool => "bool".to_string(),
+            Kind::Str => "string".to_string(),
+            Kind::List(_) | Kind::Empty => "list".to_string(),
+            Kind::Struct(index) => self.program.structs[*index as usize].name.clone(),
+            Kind::Type(name) => name.clone(),
+            Kind::Iterator(_) | Kind::Chars => unreachable!("the program never sees these"),
+        }
+    }
+
+    /// The states an instruction leads to, with the instructions they are
+    /// at.
+    fn transfer(
+        &mut self,
+        index: usize,
+        function: &'p Function,
+        ip: usize,
+        mut state: State,
+    ) -> Vec<(usize, State)> {
+        let program = self.program;
+        let op = function.code[ip];
+        let span = function.spans[ip];
+        let (pops, pushes) = effect(op);
+        let inputs = state.stack.split_off(state.stack.len() - pops);
+        let pushed = match op {
+            Op::Constant(c) => Some(constant_kind(&program.constants[c as usize])),
+            Op::Pop => return vec![(ip + 1, state)],
+            Op::Dup => state.stack.last().cloned().expect("a value to copy"),
+            Op::Local(slot) => state.slots[slot as usize].clone(),
+            Op::SetLocal(slot) => {
+                state.slots[slot as usize] = inputs[0].clone();
+                return vec![(ip + 1, state)];
+            }
+            Op::Global(global) => self.global_kind(global, span),
+            Op::SetGlobal(global) => {
+                self.merge_global(global, &inputs[0], span);
+                return vec![(ip + 1, state)];
+            }
+            Op::Type(name) => Some(Kind::Type(program.string(name).to_string())),
+            Op::CallFunction(callee, _) => self.call_kind(callee, &inputs, span),
+            Op::CallBuiltin(name, _) => match program.string(name) {
+                "print" | "println" if inputs.iter().all(Option::is_some) => Some(Kind::Unit),
+                _ => None,
+            },
+            Op::CallMethod(name, _) => self.method_kind(program.string(name), &inputs, span),
+            Op::Return => {
+                if let Some(kind) = &inputs[0] {
+                    let known = self.instances[index].result.clone();
+                    let result = self.merge(&known, &Some(kind.clone()), span);
+                    if result != known {
+                        self.instances[index].result = result;
+                        self.changed = true;
+                    }
+                }
+                return Vec::new();
+            }
+            Op::Jump(target) => return vec![(target as usize, state)],
+            Op::JumpIfFalse(target) => {
+                if inputs[0] != Some(Kind::Bool) {
+                    return Vec::new();
+                }
+                return vec![(target as usize, state.clone()), (ip + 1, state)];
+            }
+            Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder => {
+                match (&inputs[0], &inputs[1]) {
+                    (Some(Kind::Int), Some(Kind::Int)) => Some(Kind::Int),
+                    (Some(Kind::Int | Kind::Float), Some(Kind::Int | Kind::Float)) => {
+                        Some(Kind::Float)
+                    }
+                    _ => None,
+                }
+            }
+            Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => {
+                match (&inputs[0], &inputs[1]) {
+                    (Some(Kind::Str), Some(Kind::Str))
+                    | (Some(Kind::Int | Kind::Float), Some(Kind::Int | Kind::Float)) => {
+                        Some(Kind::Bool)
+                    }
+                    _ => None,
+                }
+            }
+            Op::Equal | Op::NotEqual => inputs.iter().all(Option::is_some).then_some(Kind::Bool),
+            Op::Negate => inputs[0]
+                .clone()
+                .filter(|kind| matches!(kind, Kind::Int | Kind::Float)),
+            Op::Not => inputs[0].clone().filter(|kind| *kind == Kind::Bool),
+            Op::List(_) => {
+                let mut item = Some(Kind::Empty);
+                for input in &inputs {
+                    if input.is_none() {
+                        item = None;
+                        break;
+                    }
+                    item = self.merge(&item, input, span);
+                }
+                item.map(|item| Kind::List(Box::new(item)))
+            }
+            Op::Struct(info, _) => self.build_struct(info, &inputs, span),
+            Op::Field(name) => match &inputs[0] {
+                Some(Kind::Struct(info)) => {
+                    let name = program.string(name);
+                    let fields = &program.structs[*info as usize].fields;
+                    match fields.iter().position(|field| field == name) {
+                        Some(field) => self.fields[*info as usize][field].clone(),
+                        None => None,
+                    }
+                }
+                _ => None,
+            },
+            Op::SetField(name) => {
+                if let (Some(Kind::Struct(info)), Some(value)) = (&inputs[0], &inputs[1]) {
+                    let name = program.string(name);
+                    let fields = &program.structs[*info as usize].fields;
+                    if let Some(field) = fields.iter().position(|field| field == name) {
+                        self.merge_field(*info, field, value, span);
+                    }
+                }
+                return vec![(ip + 1, state)];
+            }
+            Op::Iterate(1) => match &inputs[0] {
+                Some(Kind::List(item)) => Some(Kind::Iterator(item.clone())),
+                Some(Kind::Str) => Some(Kind::Chars),
+                _ => None,
+            },
+            Op::Next(slot, target) => {
+                let item = match &state.slots[slot as usize] {
+                    Some(Kind::Iterator(item)) if **item != Kind::Empty => Some((**item).clone()),
+                    Some(Kind::Chars) => Some(Kind::Str),
+                    _ => None,
+                };
+                let mut next = state.clone();
+                next.stack.push(item);
+                return vec![(target as usize, state), (ip + 1, next)];
+            }
+            Op::Match(pattern, target) => {
+                let Some(value) = &inputs[0] else {
+                    return Vec::new();
+                };
+                return match &function.patterns[pattern as usize] {
+                    Pattern::Wildcard => vec![(ip + 1, state)],
+                    Pattern::Bind(slot) => {
+                        state.slots[*slot as usize] = Some(value.clone());
+                        vec![(ip + 1, state)]
+                    }
+                    Pattern::Constant(_) => vec![(target as usize, state.clone()), (ip + 1, state)],
+                    Pattern::Cell(_) => {
+                        self.unsupported("closures", span);
+                        Vec::new()
+                    }
+                    Pattern::Tuple(_) => {
+                        self.unsupported("tuples", span);
+                        Vec::new()
+                    }
+                    Pattern::Variant(..) => {
+                        self.unsupported("enums", span);
+                        Vec::new()
+                    }
+                };
+            }
+            Op::NoClause | Op::NoArm(_) | Op::Fail(_) => return Vec::new(),
+            Op::Cell(_)
+            | Op::SetCell(_)
+            | Op::NewCell(_)
+            | Op::Capture(_)
+            | Op::SetCapture(_)
+            | Op::Closure(_) => self.unsupported_op("closures", span),
+            Op::Function(_) | Op::Builtin(_) | Op::Call(_) => {
+                self.unsupported_op("functions as values", span)
+            }
+            Op::Variant(..) => self.unsupported_op("enums", span),
+            Op::Set(_) => self.unsupported_op("sets", span),
+            Op::Map(_) => self.unsupported_op("maps", span),
+            Op::Tuple(_) | Op::Iterate(_) | Op::Unpack(_) => self.unsupported_op("tuples", span),
+        };
+        if pushes > 0 {
+            state.stack.push(pushed);
+            // `Unpack` pushes more than one value, all of them unknown.
+            state.stack.resize(state.stack.len() + pushes - 1, None);
+        }
+        vec![(ip + 1, state)]
+    }
+
+    fn unsupported_op(&mut self, what: &str, span: Span) -> Option<Kind> {
+        self.unsupported(what, span);
+        None
+    }
+
+    fn call_kind(&mut self, function: u32, inputs: &[Option<Kind>], span: Span) -> Option<Kind> {
+        if inputs.len() != self.program.functions[function as usize].arity as usize {
+            return None;
+        }
+        let args: Vec<Kind> = inputs.iter().cloned().collect::<Option<_>>()?;
+        let instance = self.instance(function, args, span)?;
+        self.instances[instance].result.clone()
+    }
+
+    fn method_kind(&mut self, name: &str, inputs: &[Option<Kind>], span: Span) -> Option<Kind> {
+        let receiver = inputs[0].as_ref()?;
+        match self.dispatch(receiver, name) {
+            Dispatch::Init(info) => self.build_struct(info, &inputs[1..], span),
+            Dispatch::Call(function, skip) => self.call_kind(function, &inputs[skip..], span),
+            Dispatch::Missing(_) => None,
+        }
+    }
+
+    fn dispatch(&self, receiver: &Kind, name: &str) -> Dispatch {
+        let program = self.program;
+        if let Kind::Type(ty) = receiver {
+            if name == "init" {
+                if let Some(info) = program.structs.iter().position(|info| info.name == *ty) {
+                    return Dispatch::Init(info as u32);
+                }
+            }
+            return match self.method(ty, name) {
+                Some(function) => Dispatch::Call(function, 1),
+                None => Dispatch::Missing(format!("`{}` has no function `{}`", ty, name)),
+            };
+        }
+        let owner = self.type_name(receiver);
+        match self.method(&owner, name) {
+            Some(function) => Dispatch::Call(function, 0),
+            None => Dispatch::Missing(format!("`{}` has no method `{}`", owner, name)),
+        }
+    }
+
+    /// The function of a method; a later definition wins, as in the VM.
+    fn method(&self, owner: &str, name: &str) -> Option<u32> {
+        let mut methods = self.program.methods.iter().rev();
+        methods
+            .find(|method| method.owner == owner && method.name == name)
+            .map(|method| method.function)
+    }
+
+    fn build_struct(&mut self, info: u32, inputs: &[Option<Kind>], span: Span) -> Option<Kind> {
+        if inputs.len() != self.fields[info as usize].len() {
+            return None;
+        }
+        let values: Vec<Kind> = inputs.iter().cloned().collect::<Option<_>>()?;
+        for (field, value) in values.iter().enumerate() {
+            self.merge_field(info, field, value, span);
+        }
+        Some(Kind::Struct(info))
+    }
+
+    fn merge_field(&mut self, info: u32, field: usize, value: &Kind, span: Span) {
+        let known = self.fields[info as usize][field].clone();
+        let merged = self.merge(&known, &Some(value.clone()), span);
+        if merged != known {
+            self.fields[info as usize][field] = merged;
+            self.changed = true;
+        }
+    }
+
+    fn global_kind(&mut self, global: u32, span: Span) -> Option<Kind> {
+        let init = self.program.globals[global as usize].init;
+        if let Some(instance) = self.instance(init, Vec::new(), span) {
+            let result = self.instances[instance].result.clone();
+            self.merge_global(global, &result, span);
+        }
+        self.globals[global as usize].clone()
+    }
+
+    fn merge_global(&mut self, global: u32, value: &Option<Kind>, span: Span) {
+        let known = self.globals[global as usize].clone();
+        let merged = self.merge(&known, value, span);
+        if merged != known {
+            self.globals[global as usize] = merged;
+            self.changed = true;
+        }
+    }
+
+    /// The whole program: the runtime, the code of every instance and of
+    /// the routines it needs, and the data they refer to.
+    fn assembly(&mut self, main: usize) -> String {
+        for index in 0..self.instances.len() {
+            self.function(index);
+        }
+        while let Some((routine, label)) = self.pending.pop() {
+            match routine {
+                Routine::Show(kind) => self.show_routine(&label, &kind),
+                Routine::Equal(a, b) => self.equal_routine(&label, &a, &b),
+            }
+        }
+        for global in 0..self.program.globals.len() {
+            let _ = writeln!(self.data, ".Lglobal{}:\n        .quad   0, 0", global);
+        }
+        let stack = self.frame * (MAX_CALL_DEPTH + 1) + STACK_MARGIN;
+        let mut out = format!(
+            "# Generated by `chop build --target=x86-64` from {}.\n",
+            self.path
+        );
+        let _ = writeln!(out, "        .set    CHOP_MAX_DEPTH, {}\n", MAX_CALL_DEPTH);
+        out.push_str(RUNTIME);
+        out.push_str("\n        .text\n");
+        let _ = writeln!(
+            out,
+            "chop_main:\n        subq    ${1}, %rsp\n        call    {0}\n        addq    ${1}, %rsp\n        ret",
+            self.symbol(main),
+            8 + 8 * self.area
+        );
+        out.push_str(&self.text);
+        out.push_str("\n        .data\n        .balign 8\n");
+        let _ = writeln!(out, "chop_stack_size:\n        .quad   {}", stack);
+        out.push_str(&self.data);
+        out
+    }
+
+    /// The symbol of an instance, named after its function for debuggers.
+    fn symbol(&self, index: usize) -> String {
+        let function = &self.program.functions[self.instances[index].function as usize];
+        format!("chop_fn_{}_{}", ident(&function.name), index)
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".Ll{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        let _ = writeln!(self.text, "{}:", label);
+    }
+
+    /// The label of a string in the data, with a quoted form when it is a
+    /// value of the program.
+    fn string(&mut self, text: &str, quoted: bool) -> String {
+        let key = (text.to_string(), quoted);
+        if let Some(label) = self.strings.get(&key) {
+            return label.clone();
+        }
+        let quoted_label = if quoted {
+            self.string(&format!("{:?}", text), false)
+        } else {
+            "0".to_string()
+        };
+        let label = format!(".Lstring{}", self.strings.len());
+        let _ = writeln!(
+            self.data,
+            "        .balign 8\n{}:\n        .quad   {}, {}\n        .ascii  {}",
+            label,
+            text.len(),
+            quoted_label,
+            gas_string(text)
+        );
+        self.strings.insert(key, label.clone());
+        label
+    }
+
+    /// The label of a site: the diagnostic for an error at a span, split
+    /// around its message.
+    fn site(&mut self, span: Span) -> String {
+        if let Some(label) = self.sites.get(&span) {
+            return label.clone();
+        }
+        let rendered = Diagnostic::error("\0".to_string(), span).render(self.path, self.source);
+        let (before, after) = rendered.split_once('\0').expect("the message is rendered");
+        let after = format!(
+            "{}{}: the program stopped with an error\n",
+            after, self.path
+        );
+        let before = self.string(before, false);
+        let after = self.string(&after, false);
+        let label = format!(".Lsite{}", self.sites.len());
+        let _ = writeln!(
+            self.data,
+            "        .balign 8\n{}:\n        .quad   {}, {}",
+            label, before, after
+        );
+        self.sites.insert(span, label.clone());
+        label
+    }
+
+    fn routine(&mut self, routine: Routine) -> String {
+        if let Some(label) = self.routines.get(&routine) {
+            return label.clone();
+        }
+        let label = format!(".Lroutine{}", self.routines.len());
+        self.routines.insert(routine.clone(), label.clone());
+        self.pending.push((routine, label.clone()));
+        label
+    }
+
+    /// The code of an instance, with a frame from the register allocator.
+    fn function(&mut self, index: usize) {
+        let program = self.program;
+        let function = &program.functions[self.instances[index].function as usize];
+        let states = std::mem::take(&mut self.instances[index].states);
+        let live = self.live(self.instances[index].function);
+        let frame = allocate(function, &states, &live);
+        self.operands = frame.operands;
+        self.saved = frame.saved;
+        let symbol = self.symbol(index);
+        let _ = writeln!(self.text, "\n# {}", function.name);
+        self.place(&symbol);
+        emit!(self, "pushq   %rbp");
+        emit!(self, "movq    %rsp, %rbp");
+        for register in &REGISTERS[..frame.saved] {
+            emit!(self, "pushq   {}", register);
+        }
+        if frame.reserved > 0 {
+            emit!(self, "subq    ${}, %rsp", frame.reserved);
+        }
+        for param in 0..function.arity as usize {
+            let operand = self.operands[param].clone();
+            match ARGUMENTS.get(param) {
+                Some(register) => self.copy(register, &operand),
+                None => {
+                    let passed = format!("{}(%rbp)", 16 + 8 * (param - ARGUMENTS.len()));
+                    self.copy(&passed, &operand);
+                }
+            }
+        }
+        let targets = targets(function);
+        let mut stacked = 0;
+        for (ip, state) in states.iter().enumerate() {
+            if targets[ip] {
+                self.place(&format!(".L{}_{}", index, ip));
+            }
+            if let Some(state) = state {
+                stacked = stacked.max(self.op(index, ip, state));
+            }
+        }
+        self.place(&format!(".L{}_return", index));
+        emit!(self, "leaq    -{}(%rbp), %rsp", 8 * frame.saved);
+        for register in REGISTERS[..frame.saved].iter().rev() {
+            emit!(self, "popq    {}", register);
+        }
+        emit!(self, "popq    %rbp");
+        emit!(self, "ret");
+        let size = 16 + 8 * frame.saved + frame.reserved + 8 * (stacked + 1);
+        self.frame = self.frame.max(size);
+        self.instances[index].states = states;
+    }
+
+    /// Moves a value between operands, at most one of which is in memory
+    /// unless it goes through `%rax`.
+    fn copy(&mut self, from: &str, to: &str) {
+        if from == to {
+            return;
+        }
+        if from.starts_with('%') || to.starts_with('%') {
+            emit!(self, "movq    {}, {}", from, to);
+        } else {
+            emit!(self, "movq    {}, %rax", from);
+            emit!(self, "movq    %rax, {}", to);
+        }
+    }
+
+    /// The code of one instruction, returning how many arguments it passes
+    /// on the stack.
+    fn op(&mut self, index: usize, ip: usize, state: &State) -> usize {
+        let program = self.program;
+        let function = &program.functions[self.instances[index].function as usize];
+        let op = function.code[ip];
+        let span = function.spans[ip];
+        let (pops, pushes) = effect(op);
+        let depth = state.stack.len();
+        let base = function.slots as usize + depth - pops;
+        // An instruction taking a value that never exists never runs.
+        let Some(kinds) = state.stack[depth - pops..]
+            .iter()
+            .cloned()
+            .collect::<Option<Vec<Kind>>>()
+        else {
+            emit!(self, "ud2");
+            return 0;
+        };
+        let inputs: Vec<String> = (0..pops).map(|k| self.operands[base + k].clone()).collect();
+        let out = if pushes > 0 {
+            self.operands[base].clone()
+        } else {
+            String::new()
+        };
+        let jump = |target: u32| format!(".L{}_{}", index, target);
+        let tail = function.returns_at(ip + 1);
+        match op {
+            Op::Constant(c) => {
+                self.constant(c, "%rax");
+                emit!(self, "movq    %rax, {}", out);
+            }
+            Op::Pop => {}
+            Op::Dup => {
+                let top = self.operands[base - 1].clone();
+                self.copy(&top, &out);
+            }
+            Op::Local(slot) => {
+                let slot = self.operands[slot as usize].clone();
+                self.copy(&slot, &out);
+            }
+            Op::SetLocal(slot) => {
+                let slot = self.operands[slot as usize].clone();
+                self.copy(&inputs[0], &slot);
+            }
+            Op::Global(global) => return self.global(global, span, &out),
+            Op::SetGlobal(global) => {
+                emit!(self, "movq    {}, %rax", inputs[0]);
+                emit!(self, "movq    %rax, .Lglobal{}(%rip)", global);
+                emit!(self, "movq    $2, .Lglobal{}+8(%rip)", global);
+            }
+            Op::Type(_) => {}
+            Op::CallFunction(callee, _) => {
+                return self.call(callee, &kinds, &inputs, span, &out, tail)
+            }
+            Op::CallBuiltin(name, _) => match program.string(name) {
+                name @ ("print" | "println") => {
+                    for (input, kind) in inputs.iter().zip(&kinds) {
+                        self.show(input, kind, false);
+                    }
+                    if name == "println" {
+                        emit!(self, "movl    $10, %edi");
+                        emit!(self, "call    chop_print_char");
+                    }
+                }
+                name => {
+                    let message = format!("`{}` cannot be applied to these arguments", name);
+                    self.fail(span, false, vec![Part::Text(message)]);
+                }
+            },
+            Op::CallMethod(name, _) => match self.dispatch(&kinds[0], program.string(name)) {
+                Dispatch::Init(info) => {
+                    let info = &program.structs[info as usize];
+                    if info.fields.len() != pops - 1 {
+                        let message = format!(
+                            "`{}.init` takes {} fields, but {} were given",
+                            info.name,
+                            info.fields.len(),
+                            pops - 1
+                        );
+                        self.fail(span, false, vec![Part::Text(message)]);
+                    } else {
+                        self.allocate_items(&inputs[1..], 0, &out);
+                    }
+                }
+                Dispatch::Call(callee, skip) => {
+                    return self.call(callee, &kinds[skip..], &inputs[skip..], span, &out, tail)
+                }
+                Dispatch::Missing(message) => self.fail(span, false, vec![Part::Text(message)]),
+            },
+            Op::Return => {
+                emit!(self, "movq    {}, %rax", inputs[0]);
+                emit!(self, "jmp     .L{}_return", index);
+            }
+            Op::Jump(target) => emit!(self, "jmp     {}", jump(target)),
+            Op::JumpIfFalse(target) => {
+                if kinds[0] == Kind::Bool {
+                    emit!(self, "movq    {}, %rax", inputs[0]);
+                    emit!(self, "testq   %rax, %rax");
+                    emit!(self, "jz      {}", jump(target));
+                } else {
+                    let parts = vec![
+                        Part::Text("expected `true` or `false`, found `".to_string()),
+                        Part::Value(inputs[0].clone(), kinds[0].clone()),
+                        Part::Text("`".to_string()),
+                    ];
+                    self.fail(span, false, parts);
+                }
+            }
+            Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder => {
+                self.arithmetic(op, &inputs, &kinds, span, &out)
+            }
+            Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => {
+                self.compare(op, &inputs, &kinds, span, &out)
+            }
+            Op::Equal | Op::NotEqual => {
+                self.equal(&inputs[0], &kinds[0], &inputs[1], &kinds[1]);
+                if op == Op::NotEqual {
+                    emit!(self, "xorl    $1, %eax");
+                }
+                emit!(self, "movq    %rax, {}", out);
+            }
+            Op::Negate => match kinds[0] {
+                Kind::Int => {
+                    let fine = self.label();
+                    emit!(self, "movq    {}, %rax", inputs[0]);
+                    emit!(self, "movabsq ${}, %rcx", i64::MIN);
+                    emit!(self, "cmpq    %rcx, %rax");
+                    emit!(self, "jne     {}", fine);
+                    let parts = vec![
+                        Part::Text("negating `".to_string()),
+                        Part::Value(inputs[0].clone(), Kind::Int),
+                        Part::Text("` overflows an `int`".to_string()),
+                    ];
+                    self.fail(span, false, parts);
+                    self.place(&fine);
+                    emit!(self, "negq    %rax");
+                    emit!(self, "movq    %rax, {}", out);
+                }
+                Kind::Float => {
+                    emit!(self, "movq    {}, %rax", inputs[0]);
+                    emit!(self, "btcq    $63, %rax");
+                    emit!(self, "movq    %rax, {}", out);
+                }
+                _ => self.fail_operator("negate", span),
+            },
+            Op::Not => match kinds[0] {
+                Kind::Bool => {
+                    emit!(self, "movq    {}, %rax", inputs[0]);
+                    emit!(self, "xorq    $1, %rax");
+                    emit!(self, "movq    %rax, {}", out);
+                }
+                _ => self.fail_operator("not", span),
+            },
+            Op::List(_) => self.allocate_items(&inputs, 1, &out),
+            Op::Struct(..) => self.allocate_items(&inputs, 0, &out),
+            Op::Field(name) => {
+                let name = program.string(name);
+                if let Some(offset) = self.field(&kinds[0], &inputs[0], name, span) {
+                    emit!(self, "movq    {}, %rax", inputs[0]);
+                    emit!(self, "movq    {}(%rax), %rax", offset);
+                    emit!(self, "movq    %rax, {}", out);
+                }
+            }
+            Op::SetField(name) => {
+                let name = program.string(name);
+                if let Some(offset) = self.field(&kinds[0], &inputs[0], name, span) {
+                    emit!(self, "movq    {}, %rax", inputs[0]);
+                    emit!(self, "movq    {}, %rcx", inputs[1]);
+                    emit!(self, "movq    %rcx, {}(%rax)", offset);
+                }
+            }
+            Op::Iterate(1) if matches!(kinds[0], Kind::List(_) | Kind::Str) => {
+                emit!(self, "movl    $16, %edi");
+                emit!(self, "call    chop_alloc");
+                emit!(self, "movq    {}, %rcx", inputs[0]);
+                emit!(self, "movq    %rcx, (%rax)");
+                emit!(self, "movq    $0, 8(%rax)");
+                emit!(self, "movq    %rax, {}", out);
+            }
+            Op::Iterate(1) => {
+                let parts = vec![
+                    Part::Text("cannot loop over `".to_string()),
+                    Part::Value(inputs[0].clone(), kinds[0].clone()),
+                    Part::Text("`".to_string()),
+                ];
+                self.fail(span, false, parts);
+            }
+            Op::Next(slot, target) => {
+                let iterator = self.operands[slot as usize].clone();
+                match &state.slots[slot as usize] {
+                    Some(Kind::Iterator(_)) => {
+                        emit!(self, "movq    {}, %rax", iterator);
+                        emit!(self, "movq    8(%rax), %rcx");
+                        emit!(self, "movq    (%rax), %rdx");
+                        emit!(self, "cmpq    (%rdx), %rcx");
+                        emit!(self, "jge     {}", jump(target));
+                        emit!(self, "movq    8(%rdx,%rcx,8), %rdx");
+                        emit!(self, "incq    %rcx");
+                        emit!(self, "movq    %rcx, 8(%rax)");
+                        emit!(self, "movq    %rdx, {}", out);
+                    }
+                    Some(Kind::Chars) => {
+                        emit!(self, "movq    {}, %rdi", iterator);
+                        emit!(self, "call    chop_next_char");
+                        emit!(self, "testq   %rax, %rax");
+                        emit!(self, "jz      {}", jump(target));
+                        emit!(self, "movq    %rax, {}", out);
+                    }
+                    _ => emit!(self, "ud2"),
+                }
+            }
+            Op::Match(pattern, target) => match &function.patterns[pattern as usize] {
+                Pattern::Wildcard => {}
+                Pattern::Bind(slot) => {
+                    let slot = self.operands[*slot as usize].clone();
+                    self.copy(&inputs[0], &slot);
+                }
+                Pattern::Constant(c) => {
+                    let kind = constant_kind(&program.constants[*c as usize]);
+                    self.constant(*c, "%rdx");
+                    self.equal(&inputs[0], &kinds[0], "%rdx", &kind);
+                    emit!(self, "testq   %rax, %rax");
+                    emit!(self, "jz      {}", jump(target));
+                }
+                Pattern::Cell(_) | Pattern::Tuple(_) | Pattern::Variant(..) => emit!(self, "ud2"),
+            },
+            Op::NoClause => {
+                let mut parts = vec![Part::Text(format!(
+                    "no clause of `{}` matches the arguments (",
+                    function.name
+                ))];
+                for param in 0..function.arity as usize {
+                    if param > 0 {
+                        parts.push(Part::Text(", ".to_string()));
+                    }
+                    if let Some(kind) = &state.slots[param] {
+                        parts.push(Part::Value(self.operands[param].clone(), kind.clone()));
+                    }
+                }
+                parts.push(Part::Text(")".to_string()));
+                self.fail(span, true, parts);
+            }
+            Op::NoArm(slot) => {
+                let mut parts = vec![Part::Text("no arm of this `match` matches `".to_string())];
+                if let Some(kind) = &state.slots[slot as usize] {
+                    parts.push(Part::Value(
+                        self.operands[slot as usize].clone(),
+                        kind.clone(),
+                    ));
+                }
+                parts.push(Part::Text("`".to_string()));
+                self.fail(span, false, parts);
+            }
+            Op::Fail(message) => {
+                let message = program.string(message).to_string();
+                self.fail(span, false, vec![Part::Text(message)]);
+            }
+            Op::Cell(_)
+            | Op::SetCell(_)
+            | Op::NewCell(_)
+            | Op::Capture(_)
+            | Op::SetCapture(_)
+            | Op::Closure(_)
+            | Op::Function(_)
+            | Op::Builtin(_)
+            | Op::Call(_)
+            | Op::Variant(..)
+            | Op::Set(_)
+            | Op::Map(_)
+            | Op::Tuple(_)
+            | Op::Iterate(_)
+            | Op::Unpack(_) => emit!(self, "ud2"),
+        }
+        0
+    }
+
+    /// Loads a constant into a register.
+    fn constant(&mut self, index: u32, register: &str) {
+        match &self.program.constants[index as usize] {
+            Constant::Null | Constant::Unit => emit!(self, "movq    $0, {}", register),
+            Constant::Int(i) => emit!(self, "movabsq ${}, {}", i, register),
+            Constant::Float(x) => emit!(self, "movabsq ${}, {}", x.to_bits() as i64, register),
+            Constant::Bool(b) => emit!(self, "movq    ${}, {}", *b as u8, register),
+            Constant::String(s) => {
+                let label = self.string(s, true);
+                emit!(self, "leaq    {}(%rip), {}", label, register);
+            }
+        }
+    }
+
+    /// Reads a global, running its initializer the first time. The word
+    /// after a global's value is 0 before it is initialized, 1 while it is
+    /// and 2 after.
+    fn global(&mut self, global: u32, span: Span, out: &str) -> usize {
+        let program = self.program;
+        let info = &program.globals[global as usize];
+        let Some(&instance) = self.by_args.get(&(info.init, Vec::new())) else {
+            emit!(self, "ud2");
+            return 0;
+        };
+        let ready = self.label();
+        let start = self.label();
+        emit!(self, "cmpq    $2, .Lglobal{}+8(%rip)", global);
+        emit!(self, "je      {}", ready);
+        emit!(self, "cmpq    $1, .Lglobal{}+8(%rip)", global);
+        emit!(self, "jne     {}", start);
+        let message = format!("`{}` is used while it is being initialized", info.name);
+        self.fail(span, false, vec![Part::Text(message)]);
+        self.place(&start);
+        emit!(self, "movq    $1, .Lglobal{}+8(%rip)", global);
+        let name = &program.functions[info.init as usize].name;
+        self.enter(span, name);
+        emit!(self, "call    {}", self.symbol(instance));
+        emit!(self, "decq    chop_depth(%rip)");
+        emit!(self, "movq    %rax, .Lglobal{}(%rip)", global);
+        emit!(self, "movq    $2, .Lglobal{}+8(%rip)", global);
+        self.place(&ready);
+        emit!(self, "movq    .Lglobal{}(%rip), %rax", global);
+        emit!(self, "movq    %rax, {}", out);
+        0
+    }
+
+    /// Records a call about to start at a span, as the VM counts its frames.
+    fn enter(&mut self, span: Span, name: &str) {
+        let site = self.site(span);
+        let name = self.string(name, false);
+        emit!(self, "leaq    {}(%rip), %rdi", site);
+        emit!(self, "leaq    {}(%rip), %rsi", name);
+        emit!(self, "call    chop_enter");
+    }
+
+    /// Calls the instance of a function for the kinds of the arguments,
+    /// returning how many slots it passes on the stack. A call in tail
+    /// position jumps to the callee in place of the caller, passing the
+    /// arguments on the stack where the caller's came in.
+    fn call(
+        &mut self,
+        function: u32,
+        kinds: &[Kind],
+        args: &[String],
+        span: Span,
+        out: &str,
+        tail: bool,
+    ) -> usize {
+        let callee = &self.program.functions[function as usize];
+        if callee.arity as usize != args.len() {
+            let mut parts = vec![Part::Text(format!(
+                "no clause of `{}` matches the arguments (",
+                callee.name
+            ))];
+            for (k, (arg, kind)) in args.iter().zip(kinds).enumerate() {
+                if k > 0 {
+                    parts.push(Part::Text(", ".to_string()));
+                }
+                parts.push(Part::Value(arg.clone(), kind.clone()));
+            }
+            parts.push(Part::Text(")".to_string()));
+            self.fail(span, false, parts);
+            return 0;
+        }
+        let Some(&instance) = self.by_args.get(&(function, kinds.to_vec())) else {
+            emit!(self, "ud2");
+            return 0;
+        };
+        if tail {
+            self.tail_call(instance, args, span);
+            return 0;
+        }
+        self.enter(span, &callee.name);
+        let padding = self.area - args.len().saturating_sub(ARGUMENTS.len());
+        if padding > 0 {
+            emit!(self, "subq    ${}, %rsp", 8 * padding);
+        }
+        for arg in args.iter().skip(ARGUMENTS.len()).rev() {
+            emit!(self, "pushq   {}", arg);
+        }
+        for (arg, register) in args.iter().zip(ARGUMENTS) {
+            emit!(self, "movq    {}, {}", arg, register);
+        }
+        emit!(self, "call    {}", self.symbol(instance));
+        if self.area > 0 {
+            emit!(self, "addq    ${}, %rsp", 8 * self.area);
+        }
+        emit!(self, "decq    chop_depth(%rip)");
+        emit!(self, "movq    %rax, {}", out);
+        self.area
+    }
+
+    /// Jumps to an instance with the arguments where it takes them, having
+    /// restored what the running function saved on entry.
+    fn tail_call(&mut self, instance: usize, args: &[String], span: Span) {
+        let site = self.site(span);
+        emit!(self, "leaq    {}(%rip), %rdi", site);
+        emit!(self, "call    chop_replace_call");
+        for (k, arg) in args.iter().enumerate().skip(ARGUMENTS.len()) {
+            let passed = format!("{}(%rbp)", 16 + 8 * (k - ARGUMENTS.len()));
+            self.copy(arg, &passed);
+        }
+        for (arg, register) in args.iter().zip(ARGUMENTS) {
+            emit!(self, "movq    {}, {}", arg, register);
+        }
+        emit!(self, "leaq    -{}(%rbp), %rsp", 8 * self.saved);
+        for register in REGISTERS[..self.saved].iter().rev() {
+            emit!(self, "popq    {}", register);
+        }
+        emit!(self, "popq    %rbp");
+        emit!(self, "jmp     {}", self.symbol(instance));
+    }
+
+    /// A new list (with its length first) or struct holding the inputs.
+    fn allocate_items(&mut self, items: &[String], header: usize, out: &str) {
+        let size = 8 * (header + items.len()).max(1);
+        emit!(self, "movl    ${}, %edi", size);
+        emit!(self, "call    chop_alloc");
+        if header > 0 {
+            emit!(self, "movq    ${}, (%rax)", items.len());
+        }
+        for (k, item) in items.iter().enumerate() {
+            emit!(self, "movq    {}, %rcx", item);
+            emit!(self, "movq    %rcx, {}(%rax)", 8 * (header + k));
+        }
+        emit!(self, "movq    %rax, {}", out);
+    }
+
+    /// The offset of a field in a struct of this kind, or the code of the
+    /// error when there is no such field.
+    fn field(&mut self, kind: &Kind, receiver: &str, name: &str, span: Span) -> Option<usize> {
+        let parts = match kind {
+            Kind::Struct(info) => {
+                let info = &self.program.structs[*info as usize];
+                if let Some(field) = info.fields.iter().position(|field| field == name) {
+                    return Some(8 * field);
+                }
+                vec![Part::Text(format!(
+                    "`{}` has no field `{}`",
+                    info.name, name
+                ))]
+            }
+            other => vec![
+                Part::Text("`".to_string()),
+                Part::Value(receiver.to_string(), other.clone()),
+                Part::Text(format!("` has no field `{}`", name)),
+            ],
+        };
+        self.fail(span, false, parts);
+        None
+    }
+
+    fn arithmetic(&mut self, op: Op, inputs: &[String], kinds: &[Kind], span: Span, out: &str) {
+        let symbol = symbol(op);
+        match (&kinds[0], &kinds[1]) {
+            (Kind::Int, Kind::Int) => {
+                let overflow = vec![
+                    Part::Text("`".to_string()),
+                    Part::Value(inputs[0].clone(), Kind::Int),
+                    Part::Text(format!(" {} ", symbol)),
+                    Part::Value(inputs[1].clone(), Kind::Int),
+                    Part::Text("` overflows an `int`".to_string()),
+                ];
+                let fine = self.label();
+                emit!(self, "movq    {}, %rax", inputs[0]);
+                emit!(self, "movq    {}, %rcx", inputs[1]);
+                match op {
+                    Op::Add => emit!(self, "addq    %rcx, %rax"),
+                    Op::Subtract => emit!(self, "subq    %rcx, %rax"),
+                    Op::Multiply => emit!(self, "imulq   %rcx, %rax"),
+                    _ => {
+                        let nonzero = self.label();
+                        emit!(self, "testq   %rcx, %rcx");
+                        emit!(self, "jnz     {}", nonzero);
+                        self.fail(
+                            span,
+                            false,
+                            vec![Part::Text("division by zero".to_string())],
+                        );
+                        self.place(&nonzero);
+                        let divide = self.label();
+                        emit!(self, "cmpq    $-1, %rcx");
+                        emit!(self, "jne     {}", divide);
+                        emit!(self, "movabsq ${}, %rdx", i64::MIN);
+                        emit!(self, "cmpq    %rdx, %rax");
+                        emit!(self, "jne     {}", divide);
+                        self.fail(span, false, overflow);
+                        self.place(&divide);
+                        emit!(self, "cqto");
+                        emit!(self, "idivq   %rcx");
+                        if op == Op::Remainder {
+                            emit!(self, "movq    %rdx, %rax");
+                        }
+                        emit!(self, "movq    %rax, {}", out);
+                        return;
+                    }
+                }
+                emit!(self, "jno     {}", fine);
+                self.fail(span, false, overflow);
+                self.place(&fine);
+                emit!(self, "movq    %rax, {}", out);
+            }
+            (Kind::Int | Kind::Float, Kind::Int | Kind::Float) => {
+                self.load_float(&inputs[0], &kinds[0], "%xmm0");
+                self.load_float(&inputs[1], &kinds[1], "%xmm1");
+                match op {
+                    Op::Add => emit!(self, "addsd   %xmm1, %xmm0"),
+                    Op::Subtract => emit!(self, "subsd   %xmm1, %xmm0"),
+                    Op::Multiply => emit!(self, "mulsd   %xmm1, %xmm0"),
+                    Op::Divide => emit!(self, "divsd   %xmm1, %xmm0"),
+                    _ => emit!(self, "call    fmod@PLT"),
+                }
+                emit!(self, "movq    %xmm0, %rax");
+                emit!(self, "movq    %rax, {}", out);
+            }
+            _ => {
+                let parts = vec![
+                    Part::Text(format!("cannot apply `{}` to `", symbol)),
+                    Part::Value(inputs[0].clone(), kinds[0].clone()),
+                    Part::Text("` and `".to_string()),
+                    Part::Value(inputs[1].clone(), kinds[1].clone()),
+                    Part::Text("`".to_string()),
+                ];
+                self.fail(span, false, parts);
+            }
+        }
+    }
+
+    fn compare(&mut self, op: Op, inputs: &[String], kinds: &[Kind], span: Span, out: &str) {
+        let incomparable = vec![
+            Part::Text("cannot compare `".to_string()),
+            Part::Value(inputs[0].clone(), kinds[0].clone()),
+            Part::Text("` and `".to_string()),
+            Part::Value(inputs[1].clone(), kinds[1].clone()),
+            Part::Text("`".to_string()),
+        ];
+        // The condition codes of a signed and of an unsigned comparison.
+        let (signed, unsigned) = match op {
+            Op::Less => ("l", "b"),
+            Op::LessEqual => ("le", "be"),
+            Op::Greater => ("g", "a"),
+            _ => ("ge", "ae"),
+        };
+        match (&kinds[0], &kinds[1]) {
+            (Kind::Int, Kind::Int) => {
+                emit!(self, "movq    {}, %rax", inputs[0]);
+                emit!(self, "movq    {}, %rcx", inputs[1]);
+                emit!(self, "cmpq    %rcx, %rax");
+                emit!(self, "{:<8}%al", format!("set{}", signed));
+            }
+            (Kind::Str, Kind::Str) => {
+                emit!(self, "movq    {}, %rdi", inputs[0]);
+                emit!(self, "movq    {}, %rsi", inputs[1]);
+                emit!(self, "call    chop_string_order");
+                emit!(self, "cmpq    $0, %rax");
+                emit!(self, "{:<8}%al", format!("set{}", signed));
+            }
+            (Kind::Int | Kind::Float, Kind::Int | Kind::Float) => {
+                self.load_float(&inputs[0], &kinds[0], "%xmm0");
+                self.load_float(&inputs[1], &kinds[1], "%xmm1");
+                let ordered = self.label();
+                emit!(self, "ucomisd %xmm1, %xmm0");
+                emit!(self, "jnp     {}", ordered);
+                self.fail(span, false, incomparable);
+                self.place(&ordered);
+                emit!(self, "{:<8}%al", format!("set{}", unsigned));
+            }
+            _ => return self.fail(span, false, incomparable),
+        }
+        emit!(self, "movzbl  %al, %eax");
+        emit!(self, "movq    %rax, {}", out);
+    }
+
+    fn load_float(&mut self, operand: &str, kind: &Kind, register: &str) {
+        match kind {
+            Kind::Int => emit!(self, "cvtsi2sdq {}, {}", operand, register),
+            _ => emit!(self, "movq    {}, {}", operand, register),
+        }
+    }
+
+    /// Leaves 1 in `%rax` if the values are equal and 0 if not, clobbering
+    /// only registers a call may clobber.
+    fn equal(&mut self, a: &str, a_kind: &Kind, b: &str, b_kind: &Kind) {
+        match (a_kind, b_kind) {
+            (Kind::Int, Kind::Int) | (Kind::Bool, Kind::Bool) => {
+                emit!(self, "movq    {}, %rax", a);
+                emit!(self, "movq    {}, %rcx", b);
+                emit!(self, "cmpq    %rcx, %rax");
+                emit!(self, "sete    %al");
+                emit!(self, "movzbl  %al, %eax");
+            }
+            (Kind::Int | Kind::Float, Kind::Int | Kind::Float) => {
+                self.load_float(a, a_kind, "%xmm0");
+                self.load_float(b, b_kind, "%xmm1");
+                emit!(self, "ucomisd %xmm1, %xmm0");
+                emit!(self, "sete    %al");
+                emit!(self, "setnp   %cl");
+                emit!(self, "andb    %cl, %al");
+                emit!(self, "movzbl  %al, %eax");
+            }
+            (Kind::Str, Kind::Str) => {
+                emit!(self, "movq    {}, %rdi", a);
+                emit!(self, "movq    {}, %rsi", b);
+                emit!(self, "call    chop_string_equal");
+            }
+            (Kind::Null, Kind::Null) | (Kind::Unit, Kind::Unit) => emit!(self, "movl    $1, %eax"),
+            (Kind::Type(x), Kind::Type(y)) => emit!(self, "movl    ${}, %eax", (x == y) as u8),
+            (Kind::List(_), Kind::List(_)) | (Kind::Struct(_), Kind::Struct(_))
+                if matches!(a_kind, Kind::List(_)) || a_kind == b_kind =>
+            {
+                let routine = self.routine(Routine::Equal(a_kind.clone(), b_kind.clone()));
+                emit!(self, "movq    {}, %rdi", a);
+                emit!(self, "movq    {}, %rsi", b);
+                emit!(self, "call    {}", routine);
+            }
+            _ => emit!(self, "xorl    %eax, %eax"),
+        }
+    }
+
+    /// Prints a value as `println` shows it, or quoted the way it shows
+    /// inside a list or struct.
+    fn show(&mut self, operand: &str, kind: &Kind, quoted: bool) {
+        match kind {
+            Kind::Int => {
+                emit!(self, "movq    {}, %rdi", operand);
+                emit!(self, "call    chop_print_int");
+            }
+            Kind::Float => {
+                emit!(self, "movq    {}, %xmm0", operand);
+                emit!(self, "call    chop_print_float");
+            }
+            Kind::Bool => {
+                emit!(self, "movq    {}, %rdi", operand);
+                emit!(self, "call    chop_print_bool");
+            }
+            Kind::Str => {
+                emit!(self, "movq    {}, %rdi", operand);
+                if quoted {
+                    emit!(self, "call    chop_print_quoted");
+                } else {
+                    emit!(self, "call    chop_print_str");
+                }
+            }
+            Kind::Null => self.print_text("null"),
+            Kind::Unit => self.print_text("()"),
+            Kind::Type(_) => self.print_text("<type>"),
+            Kind::Iterator(_) | Kind::Chars => self.print_text("<iterator>"),
+            Kind::Empty => {}
+            Kind::List(_) | Kind::Struct(_) => {
+                let routine = self.routine(Routine::Show(kind.clone()));
+                emit!(self, "movq    {}, %rdi", operand);
+                emit!(self, "call    {}", routine);
+            }
+        }
+    }
+
+    fn print_text(&mut self, text: &str) {
+        let label = self.string(text, false);
+        emit!(self, "leaq    {}(%rip), %rdi", label);
+        emit!(self, "call    chop_print_str");
+    }
+
+    /// Stops the program with an error at a span, or at the call of the
+    /// running function.
+    fn fail(&mut self, span: Span, caller: bool, parts: Vec<Part>) {
+        let site = self.site(span);
+        emit!(self, "leaq    {}(%rip), %rdi", site);
+        if caller {
+            emit!(self, "call    chop_fail_begin_caller");
+        } else {
+            emit!(self, "call    chop_fail_begin");
+        }
+        for part in parts {
+            match part {
+                Part::Text(text) => self.print_text(&text),
+                Part::Value(operand, kind) => self.show(&operand, &kind, false),
+            }
+        }
+        emit!(self, "call    chop_fail_end");
+    }
+
+    fn fail_operator(&mut self, name: &str, span: Span) {
+        let message = format!("`{}` cannot be applied to these arguments", name);
+        self.fail(span, false, vec![Part::Text(message)]);
+    }
+
+    /// Prints the list or struct `%rdi` points to.
+    fn show_routine(&mut self, label: &str, kind: &Kind) {
+        self.text.push('\n');
+        self.place(label);
+        emit!(self, "pushq   %rbx");
+        emit!(self, "pushq   %r12");
+        emit!(self, "subq    $8, %rsp");
+        emit!(self, "movq    %rdi, %rbx");
+        match kind {
+            Kind::List(item) => {
+                let (next, first, end) = (self.label(), self.label(), self.label());
+                self.print_text("[");
+                emit!(self, "xorl    %r12d, %r12d");
+                self.place(&next);
+                emit!(self, "cmpq    (%rbx), %r12");
+                emit!(self, "jge     {}", end);
+                emit!(self, "testq   %r12, %r12");
+                emit!(self, "jz      {}", first);
+                self.print_text(", ");
+                self.place(&first);
+                self.show("8(%rbx,%r12,8)", item, true);
+                emit!(self, "incq    %r12");
+                emit!(self, "jmp     {}", next);
+                self.place(&end);
+                self.print_text("]");
+            }
+            Kind::Struct(info) => {
+                let program = self.program;
+                let info_index = *info as usize;
+                let info = &program.structs[info_index];
+                let mut text = format!("{} {{ ", info.name);
+                for (field, name) in info.fields.iter().enumerate() {
+                    if field > 0 {
+                        text.push_str(", ");
+                    }
+                    let _ = write!(text, "{}: ", name);
+                    if let Some(kind) = self.fields[info_index][field].clone() {
+                        self.print_text(&text);
+                        text.clear();
+                        self.show(&format!("{}(%rbx)", 8 * field), &kind, true);
+                    }
+                }
+                text.push_str(" }");
+                self.print_text(&text);
+            }
+            other => unreachable!("{:?} is shown inline", other),
+        }
+        emit!(self, "addq    $8, %rsp");
+        emit!(self, "popq    %r12");
+        emit!(self, "popq    %rbx");
+        emit!(self, "ret");
+    }
+
+    /// Compares the lists or structs `%rdi` and `%rsi` point to, leaving
+    /// the result in `%rax`.
+    fn equal_routine(&mut self, label: &str, a: &Kind, b: &Kind) {
+        let (equal, unequal, done) = (self.label(), self.label(), self.label());
+        self.text.push('\n');
+        self.place(label);
+        emit!(self, "pushq   %rbx");
+        emit!(self, "pushq   %r12");
+        emit!(self, "pushq   %r13");
+        emit!(self, "movq    %rdi, %rbx");
+        emit!(self, "movq    %rsi, %r12");
+        match (a, b) {
+            (Kind::List(x), Kind::List(y)) => {
+                let next = self.label();
+                emit!(self, "movq    (%rbx), %rax");
+                emit!(self, "cmpq    (%r12), %rax");
+                emit!(self, "jne     {}", unequal);
+                emit!(self, "xorl    %r13d, %r13d");
+                self.place(&next);
+                emit!(self, "cmpq    (%rbx), %r13");
+                emit!(self, "jge     {}", equal);
+                self.equal("8(%rbx,%r13,8)", x, "8(%r12,%r13,8)", y);
+                emit!(self, "testq   %rax, %rax");
+                emit!(self, "jz      {}", unequal);
+                emit!(self, "incq    %r13");
+                emit!(self, "jmp     {}", next);
+            }
+            (Kind::Struct(info), _) => {
+                emit!(self, "cmpq    %rbx, %r12");
+                emit!(self, "je      {}", equal);
+                for (field, kind) in self.fields[*info as usize].clone().iter().enumerate() {
+                    if let Some(kind) = kind {
+                        let operand = |base: &str| format!("{}({})", 8 * field, base);
+                        self.equal(&operand("%rbx"), kind, &operand("%r12"), kind);
+                        emit!(self, "testq   %rax, %rax");
+                        emit!(self, "jz      {}", unequal);
+                    }
+                }
+            }
+            other => unreachable!("{:?} are compared inline", other),
+        }
+        self.place(&equal);
+        emit!(self, "movl    $1, %eax");
+        emit!(self, "jmp     {}", done);
+        self.place(&unequal);
+        emit!(self, "xorl    %eax, %eax");
+        self.place(&done);
+        emit!(self, "popq    %r13");
+        emit!(self, "popq    %r12");
+        emit!(self, "popq    %rbx");
+        emit!(self, "ret");
+    }
+}
+
+/// Where the slots and stack positions of a function live, from a linear
+/// scan register allocation.
+struct Frame {
+    /// The operand of every slot, then of every stack position.
+    operands: Vec<String>,
+    /// How many of `REGISTERS` the function uses, and saves on entry.
+    saved: usize,
+    /// The bytes below them for spilled values.
+    reserved: usize,
+}
+
+/// Gives the slots and stack positions of a function registers while they
+/// are live. Each one's interval runs from the first to the last
+/// instruction it is live or written at; the intervals are taken in order
+/// of their start, and when no register is free the one that ends last is
+/// spilled.
+fn allocate(function: &Function, states: &[Option<State>], live: &[Vec<bool>]) -> Frame {
+    let slots = function.slots as usize;
+    let mut bounds: Vec<Option<(usize, usize)>> = vec![None; slots];
+    fn extend(bounds: &mut Vec<Option<(usize, usize)>>, value: usize, ip: usize) {
+        if value >= bounds.len() {
+            bounds.resize(value + 1, None);
+        }
+        bounds[value] = Some(match bounds[value] {
+            None => (ip, ip),
+            Some((start, end)) => (start.min(ip), end.max(ip)),
+        });
+    }
+    for param in 0..function.arity as usize {
+        extend(&mut bounds, param, 0);
+    }
+    for (ip, state) in states.iter().enumerate() {
+        let Some(state) = state else { continue };
+        for slot in (0..slots).filter(|&slot| live[ip][slot]) {
+            extend(&mut bounds, slot, ip);
+        }
+        let op = function.code[ip];
+        match op {
+            Op::SetLocal(slot) => extend(&mut bounds, slot as usize, ip),
+            Op::Match(pattern, _) => {
+                if let Pattern::Bind(slot) = function.patterns[pattern as usize] {
+                    extend(&mut bounds, slot as usize, ip);
+                }
+            }
+            _ => {}
+        }
+        let (pops, pushes) = effect(op);
+        let depth = state.stack.len();
+        for position in 0..depth.max(depth - pops + pushes) {
+            extend(&mut bounds, slots + position, ip);
+        }
+    }
+    let mut order: Vec<usize> = (0..bounds.len()).filter(|&v| bounds[v].is_some()).collect();
+    order.sort_by_key(|&v| bounds[v].map(|(start, _)| start));
+    let end = |v: usize| bounds[v].map_or(0, |(_, end)| end);
+    let mut registers: Vec<Option<usize>> = vec![None; bounds.len()];
+    let mut spilled: Vec<Option<usize>> = vec![None; bounds.len()];
+    let mut spills = 0;
+    let mut active: Vec<usize> = Vec::new();
+    let mut free: Vec<usize> = (0..REGISTERS.len()).rev().collect();
+    for value in order {
+        let start = bounds[value].map_or(0, |(start, _)| start);
+        active.retain(|&other| {
+            let expired = end(other) < start;
+            if expired {
+                free.push(registers[other].expect("active values have registers"));
+            }
+            !expired
+        });
+        free.sort_unstable_by(|a, b| b.cmp(a));
+        if let Some(register) = free.pop() {
+            registers[value] = Some(register);
+            active.push(value);
+            continue;
+        }
+        let (at, &furthest) = active
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, &other)| end(other))
+            .expect("every register is taken");
+        if end(furthest) > end(value) {
+            registers[value] = registers[furthest].take();
+            spilled[furthest] = Some(spills);
+            active[at] = value;
+        } else {
+            spilled[value] = Some(spills);
+        }
+        spills += 1;
+    }
+    let saved = registers.iter().flatten().map(|r| r + 1).max().unwrap_or(0);
+    let operands = (0..bounds.len())
+        .map(|value| match (registers[value], spilled[value]) {
+            (Some(register), _) => REGISTERS[register].to_string(),
+            (None, Some(spill)) => format!("-{}(%rbp)", 8 * (saved + spill + 1)),
+            // Never read or written by code that runs.
+            (None, None) => "%rax".to_string(),
+        })
+        .collect();
+    let reserved = 8 * (spills + (saved + spills) % 2);
+    Frame {
+        operands,
+        saved,
+        reserved,
+    }
+}
+
+/// The slots each instruction of a function may read before they are
+/// written again, found backwards from their reads.
+fn liveness(function: &Function) -> Vec<Vec<bool>> {
+    let slots = function.slots as usize;
+    let mut live = vec![vec![false; slots]; function.code.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for ip in (0..function.code.len()).rev() {
+            let mut set = vec![false; slots];
+            for (next, written) in successors(function, ip) {
+                for slot in 0..slots {
+                    if live[next][slot] && written != Some(slot) {
+                        set[slot] = true;
+                    }
+                }
+            }
+            match function.code[ip] {
+                Op::Local(slot) | Op::NoArm(slot) | Op::Next(slot, _) => set[slot as usize] = true,
+                Op::NoClause => set[..function.arity as usize].fill(true),
+                _ => {}
+            }
+            if set != live[ip] {
+                live[ip] = set;
+                changed = true;
+            }
+        }
+    }
+    live
+}
+
+/// The instructions that can run after one, each with the slot the edge
+/// to it writes.
+fn successors(function: &Function, ip: usize) -> Vec<(usize, Option<usize>)> {
+    match function.code[ip] {
+        Op::Jump(target) => vec![(target as usize, None)],
+        Op::JumpIfFalse(target) | Op::Next(_, target) => {
+            vec![(target as usize, None), (ip + 1, None)]
+        }
+        Op::Match(pattern, target) => {
+            let bound = match function.patterns[pattern as usize] {
+                Pattern::Bind(slot) => Some(slot as usize),
+                _ => None,
+            };
+            vec![(target as usize, None), (ip + 1, bound)]
+        }
+        Op::SetLocal(slot) => vec![(ip + 1, Some(slot as usize))],
+        Op::Return | Op::NoClause | Op::NoArm(_) | Op::Fail(_) => Vec::new(),
+        _ => vec![(ip + 1, None)],
+    }
+}
+
+/// Which instructions of a function are jumped to, and need a label.
+fn targets(function: &Function) -> Vec<bool> {
+    let mut targets = vec![false; function.code.len()];
+    for op in &function.code {
+        if let Op::Jump(target)
+        | Op::JumpIfFalse(target)
+        | Op::Next(_, target)
+        | Op::Match(_, target) = *op
+        {
+            targets[target as usize] = true;
+        }
+    }
+    targets
+}
+
+/// The operator an arithmetic instruction applies.
+fn symbol(op: Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Subtract => "-",
+        Op::Multiply => "*",
+        Op::Divide => "/",
+        Op::Remainder => "%",
+        other => unreachable!("{:?} is not arithmetic", other),
+    }
+}
+
+fn ident(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// A string for the `.ascii` directive with the bytes of `text`.
+fn gas_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            _ => {
+                let _ = write!(literal, "\\{:03o}", byte);
+            }
+        }
+    }
+    literal.push('"');
+    literal
+}
@@ -0,0 +1,527 @@
+# The runtime of programs built with `chop build --target=x86-64`. The code
+# generated after it defines `chop_main`, `chop_stack_size` and
+# `CHOP_MAX_DEPTH`.
+#
+# A string is a pointer to its length, a pointer to the string of its quoted
+# form (or zero when it has none), then its bytes. A site is a pointer to
+# the strings a rendered diagnostic has before and after its message.
+
+        .text
+
+# int main(void): runs `chop_main` on a stack deep enough for
+# CHOP_MAX_DEPTH nested calls.
+        .globl  main
+main:
+        pushq   %rbp
+        movq    %rsp, %rbp
+        pushq   %rbx
+        subq    $8, %rsp
+        movq    stdout@GOTPCREL(%rip), %rax
+        movq    (%rax), %rax
+        movq    %rax, chop_out(%rip)
+        movq    chop_stack_size(%rip), %rdi
+        call    chop_alloc
+        addq    chop_stack_size(%rip), %rax
+        andq    $-16, %rax
+        movq    %rsp, %rbx
+        movq    %rax, %rsp
+        call    chop_main
+        movq    %rbx, %rsp
+        xorl    %eax, %eax
+        movq    -8(%rbp), %rbx
+        leave
+        ret
+
+# chop_alloc(size): fresh memory, or the end of the program when there is
+# none left.
+chop_alloc:
+        subq    $8, %rsp
+        call    malloc@PLT
+        testq   %rax, %rax
+        jz      1f
+        addq    $8, %rsp
+        ret
+1:      leaq    .Lout_of_memory(%rip), %rdi
+        movq    stderr@GOTPCREL(%rip), %rax
+        movq    (%rax), %rsi
+        call    fputs@PLT
+        movl    $1, %edi
+        call    exit@PLT
+
+# chop_print_bytes(bytes, length)
+chop_print_bytes:
+        subq    $8, %rsp
+        movq    %rsi, %rdx
+        movl    $1, %esi
+        movq    chop_out(%rip), %rcx
+        call    fwrite@PLT
+        addq    $8, %rsp
+        ret
+
+# chop_print_str(string)
+chop_print_str:
+        movq    (%rdi), %rsi
+        leaq    16(%rdi), %rdi
+        jmp     chop_print_bytes
+
+# chop_print_quoted(string): a string as it shows inside a list or struct.
+# Strings made while the program runs have no quoted form, and are escaped
+# here byte by byte.
+chop_print_quoted:
+        movq    8(%rdi), %rax
+        testq   %rax, %rax
+        jz      1f
+        movq    %rax, %rdi
+        jmp     chop_print_str
+1:      pushq   %rbx
+        pushq   %r12
+        pushq   %r13
+        movq    %rdi, %rbx
+        xorl    %r12d, %r12d
+        movl    $34, %edi
+        call    chop_print_char
+2:      cmpq    (%rbx), %r12
+        jae     6f
+        movzbl  16(%rbx,%r12), %r13d
+        incq    %r12
+        movl    %r13d, %eax
+        cmpl    $34, %r13d
+        je      4f
+        cmpl    $92, %r13d
+        je      4f
+        movl    $116, %eax
+        cmpl    $9, %r13d
+        je      4f
+        movl    $110, %eax
+        cmpl    $10, %r13d
+        je      4f
+        movl    $114, %eax
+        cmpl    $13, %r13d
+        je      4f
+        movl    $48, %eax
+        testl   %r13d, %r13d
+        jz      4f
+        cmpl    $32, %r13d
+        jb      5f
+        cmpl    $127, %r13d
+        je      5f
+        movl    %r13d, %edi
+        call    chop_print_char
+        jmp     2b
+4:      movl    %eax, %r13d
+        movl    $92, %edi
+        call    chop_print_char
+        movl    %r13d, %edi
+        call    chop_print_char
+        jmp     2b
+5:      movq    chop_out(%rip), %rdi
+        leaq    .Lunicode_format(%rip), %rsi
+        movl    %r13d, %edx
+        xorl    %eax, %eax
+        call    fprintf@PLT
+        jmp     2b
+6:      movl    $34, %edi
+        call    chop_print_char
+        popq    %r13
+        popq    %r12
+        popq    %rbx
+        ret
+
+# chop_print_char(byte)
+chop_print_char:
+        subq    $8, %rsp
+        movq    chop_out(%rip), %rsi
+        call    fputc@PLT
+        addq    $8, %rsp
+        ret
+
+# chop_print_int(int)
+chop_print_int:
+        subq    $8, %rsp
+        movq    %rdi, %rdx
+        movq    chop_out(%rip), %rdi
+        leaq    .Lint_format(%rip), %rsi
+        xorl    %eax, %eax
+        call    fprintf@PLT
+        addq    $8, %rsp
+        ret
+
+# chop_print_bool(bool)
+chop_print_bool:
+        leaq    chop_true(%rip), %rax
+        leaq    chop_false(%rip), %rcx
+        testq   %rdi, %rdi
+        cmovzq  %rcx, %rax
+        movq    %rax, %rdi
+        jmp     chop_print_str
+
+# chop_print_float(%xmm0): a float as Rust's `{:?}` shows it, with the
+# fewest digits that read back as the same float. They are found by asking
+# `snprintf` for more and more of them.
+chop_print_float:
+        pushq   %rbp
+        movq    %rsp, %rbp
+        pushq   %rbx
+        pushq   %r12
+        pushq   %r13
+        pushq   %r14
+        subq    $64, %rsp
+        ucomisd %xmm0, %xmm0
+        jp      .Lnan
+        movq    %xmm0, %rax
+        btrq    $63, %rax
+        movq    %rax, 48(%rsp)
+        jnc     1f
+        movl    $45, %edi
+        call    chop_print_char
+1:      movq    48(%rsp), %rax
+        testq   %rax, %rax
+        jz      .Lzero
+        movabsq $0x7ff0000000000000, %rcx
+        cmpq    %rcx, %rax
+        je      .Linfinity
+        xorl    %ebx, %ebx
+2:      movq    %rsp, %rdi
+        movl    $48, %esi
+        leaq    .Lscientific_format(%rip), %rdx
+        movl    %ebx, %ecx
+        movsd   48(%rsp), %xmm0
+        movl    $1, %eax
+        call    snprintf@PLT
+        movq    %rsp, %rdi
+        xorl    %esi, %esi
+        call    strtod@PLT
+        ucomisd 48(%rsp), %xmm0
+        je      3f
+        incl    %ebx
+        cmpl    $17, %ebx
+        jb      2b
+        decl    %ebx
+# The buffer holds `d.ddde±x`: r13 points at the digits once the point is
+# dropped, r12 counts them and r14 is the exponent.
+3:      leal    1(%rbx), %r12d
+        movq    %rsp, %r13
+        testl   %ebx, %ebx
+        jz      4f
+        movb    (%rsp), %al
+        movb    %al, 1(%rsp)
+        leaq    1(%rsp), %r13
+4:      leaq    1(%r13,%r12), %rdi
+        xorl    %esi, %esi
+        movl    $10, %edx
+        call    strtol@PLT
+        movq    %rax, %r14
+        movsd   48(%rsp), %xmm0
+        ucomisd .Lsmallest_decimal(%rip), %xmm0
+        jb      .Lscientific
+        ucomisd .Llargest_decimal(%rip), %xmm0
+        jae     .Lscientific
+        testq   %r14, %r14
+        js      .Lfraction
+        leaq    1(%r14), %rbx
+        cmpq    %rbx, %r12
+        jg      6f
+        movq    %r13, %rdi
+        movq    %r12, %rsi
+        call    chop_print_bytes
+        subq    %r12, %rbx
+5:      testq   %rbx, %rbx
+        jz      .Lpoint_zero
+        movl    $48, %edi
+        call    chop_print_char
+        decq    %rbx
+        jmp     5b
+6:      movq    %r13, %rdi
+        movq    %rbx, %rsi
+        call    chop_print_bytes
+        movl    $46, %edi
+        call    chop_print_char
+        leaq    (%r13,%rbx), %rdi
+        movq    %r12, %rsi
+        subq    %rbx, %rsi
+        call    chop_print_bytes
+        jmp     .Lprinted
+.Lfraction:
+        leaq    chop_zero_point(%rip), %rdi
+        call    chop_print_str
+        movq    %r14, %rbx
+        notq    %rbx
+7:      testq   %rbx, %rbx
+        jz      8f
+        movl    $48, %edi
+        call    chop_print_char
+        decq    %rbx
+        jmp     7b
+8:      movq    %r13, %rdi
+        movq    %r12, %rsi
+        call    chop_print_bytes
+        jmp     .Lprinted
+.Lscientific:
+        movq    %r13, %rdi
+        movl    $1, %esi
+        call    chop_print_bytes
+        cmpq    $1, %r12
+        je      9f
+        movl    $46, %edi
+        call    chop_print_char
+        leaq    1(%r13), %rdi
+        leaq    -1(%r12), %rsi
+        call    chop_print_bytes
+9:      movl    $101, %edi
+        call    chop_print_char
+        movq    %r14, %rdi
+        call    chop_print_int
+        jmp     .Lprinted
+.Lpoint_zero:
+        leaq    chop_point_zero(%rip), %rdi
+        call    chop_print_str
+        jmp     .Lprinted
+.Lnan:
+        leaq    chop_nan(%rip), %rdi
+        call    chop_print_str
+        jmp     .Lprinted
+.Linfinity:
+        leaq    chop_infinity(%rip), %rdi
+        call    chop_print_str
+        jmp     .Lprinted
+.Lzero:
+        leaq    chop_zero(%rip), %rdi
+        call    chop_print_str
+.Lprinted:
+        leaq    -32(%rbp), %rsp
+        popq    %r14
+        popq    %r13
+        popq    %r12
+        popq    %rbx
+        popq    %rbp
+        ret
+
+# chop_string_order(a, b): -1, 0 or 1 as string a sorts before, with or
+# after string b.
+chop_string_order:
+        pushq   %rbx
+        pushq   %r12
+        subq    $8, %rsp
+        movq    %rdi, %rbx
+        movq    %rsi, %r12
+        movq    (%rdi), %rdx
+        cmpq    (%rsi), %rdx
+        cmovaq  (%rsi), %rdx
+        addq    $16, %rdi
+        addq    $16, %rsi
+        call    memcmp@PLT
+        movslq  %eax, %rax
+        testq   %rax, %rax
+        jnz     1f
+        movq    (%rbx), %rax
+        subq    (%r12), %rax
+1:      testq   %rax, %rax
+        setg    %al
+        setl    %cl
+        movzbq  %al, %rax
+        movzbq  %cl, %rcx
+        subq    %rcx, %rax
+        addq    $8, %rsp
+        popq    %r12
+        popq    %rbx
+        ret
+
+# chop_string_equal(a, b)
+chop_string_equal:
+        movq    (%rdi), %rdx
+        cmpq    (%rsi), %rdx
+        jne     1f
+        subq    $8, %rsp
+        addq    $16, %rdi
+        addq    $16, %rsi
+        call    memcmp@PLT
+        addq    $8, %rsp
+        testl   %eax, %eax
+        sete    %al
+        movzbl  %al, %eax
+        ret
+1:      xorl    %eax, %eax
+        ret
+
+# chop_next_char(iterator): the next character of the string an iterator
+# walks, as a string of its own, or zero when there are no more.
+chop_next_char:
+        pushq   %rbx
+        pushq   %r12
+        pushq   %r13
+        movq    %rdi, %rbx
+        movq    (%rdi), %r12
+        movq    8(%rdi), %rcx
+        cmpq    (%r12), %rcx
+        jae     2f
+        movzbl  16(%r12,%rcx), %eax
+        movl    $1, %r13d
+        cmpl    $0xc0, %eax
+        jb      1f
+        incl    %r13d
+        cmpl    $0xe0, %eax
+        jb      1f
+        incl    %r13d
+        cmpl    $0xf0, %eax
+        jb      1f
+        incl    %r13d
+1:      leaq    16(%r13), %rdi
+        call    chop_alloc
+        movq    %r13, (%rax)
+        movq    $0, 8(%rax)
+        movq    8(%rbx), %rcx
+        leaq    16(%r12,%rcx), %rsi
+        addq    %r13, %rcx
+        movq    %rcx, 8(%rbx)
+        leaq    16(%rax), %rdi
+        movq    %r13, %rdx
+        movq    %rax, %r12
+        call    memcpy@PLT
+        movq    %r12, %rax
+        jmp     3f
+2:      xorl    %eax, %eax
+3:      popq    %r13
+        popq    %r12
+        popq    %rbx
+        ret
+
+# chop_enter(site, name): records a call of the function with the name
+# that is about to start at a site, or stops the program if too many are
+# in progress.
+chop_enter:
+        movq    chop_depth(%rip), %rax
+        cmpq    $CHOP_MAX_DEPTH, %rax
+        jae     1f
+        incq    %rax
+        movq    %rax, chop_depth(%rip)
+        leaq    chop_sites(%rip), %rcx
+        movq    %rdi, (%rcx,%rax,8)
+        ret
+1:      pushq   %rsi
+        call    chop_fail_begin
+        leaq    chop_calls_to(%rip), %rdi
+        call    chop_print_str
+        movq    (%rsp), %rdi
+        call    chop_print_str
+        leaq    chop_nest_more_than(%rip), %rdi
+        call    chop_print_str
+        movq    $CHOP_MAX_DEPTH, %rdi
+        call    chop_print_int
+        leaq    chop_deep(%rip), %rdi
+        call    chop_print_str
+        call    chop_fail_end
+
+# chop_replace_call(site): a tail call at a site takes the place of the
+# running call, so errors about its arguments point at it.
+chop_replace_call:
+        movq    chop_depth(%rip), %rax
+        leaq    chop_sites(%rip), %rcx
+        movq    %rdi, (%rcx,%rax,8)
+        ret
+
+# chop_fail_begin(site): starts a runtime error at a site, writing the
+# diagnostic up to its message to standard error. The code that follows
+# writes the message and calls `chop_fail_end`.
+chop_fail_begin:
+        pushq   %rdi
+        movq    stdout@GOTPCREL(%rip), %rax
+        movq    (%rax), %rdi
+        call    fflush@PLT
+        movq    stderr@GOTPCREL(%rip), %rax
+        movq    (%rax), %rax
+        movq    %rax, chop_out(%rip)
+        popq    %rdi
+        movq    %rdi, chop_fail_site(%rip)
+        movq    (%rdi), %rdi
+        jmp     chop_print_str
+
+# chop_fail_begin_caller(site): the same at the call of the running
+# function, or at the site when `main` is running and made no tail call.
+chop_fail_begin_caller:
+        movq    chop_depth(%rip), %rax
+        leaq    chop_sites(%rip), %rcx
+        movq    (%rcx,%rax,8), %rax
+        testq   %rax, %rax
+        jz      chop_fail_begin
+        movq    %rax, %rdi
+        jmp     chop_fail_begin
+
+# chop_fail_end(): the rest of the diagnostic, then the end of the program.
+chop_fail_end:
+        subq    $8, %rsp
+        movq    chop_fail_site(%rip), %rdi
+        movq    8(%rdi), %rdi
+        call    chop_print_str
+        movl    $1, %edi
+        call    exit@PLT
+
+        .section .rodata
+.Lint_format:
+        .asciz  "%lld"
+.Lscientific_format:
+        .asciz  "%.*e"
+.Lunicode_format:
+        .asciz  "\\u{%x}"
+.Lout_of_memory:
+        .asciz  "out of memory\n"
+        .balign 8
+.Lsmallest_decimal:
+        .double 1e-4
+.Llargest_decimal:
+        .double 1e16
+
+        .data
+        .balign 8
+chop_out:
+        .quad   0
+chop_depth:
+        .quad   0
+chop_fail_site:
+        .quad   0
+chop_true:
+        .quad   4, 0
+        .ascii  "true"
+        .balign 8
+chop_false:
+        .quad   5, 0
+        .ascii  "false"
+        .balign 8
+chop_nan:
+        .quad   3, 0
+        .ascii  "NaN"
+        .balign 8
+chop_infinity:
+        .quad   3, 0
+        .ascii  "inf"
+        .balign 8
+chop_zero:
+        .quad   3, 0
+        .ascii  "0.0"
+        .balign 8
+chop_point_zero:
+        .quad   2, 0
+        .ascii  ".0"
+        .balign 8
+chop_zero_point:
+        .quad   2, 0
+        .ascii  "0."
+        .balign 8
+chop_calls_to:
+        .quad   10, 0
+        .ascii  "calls to `"
+        .balign 8
+chop_nest_more_than:
+        .quad   17, 0
+        .ascii  "` nest more than "
+        .balign 8
+chop_deep:
+        .quad   5, 0
+        .ascii  " deep"
+
+        .bss
+        .balign 8
+# The site of every call in progress; the first is that of `main`.
+chop_sites:
+        .zero   8 * (CHOP_MAX_DEPTH + 1)
+
+        .section .note.GNU-stack,"",@progbits
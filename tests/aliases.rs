@@ -0,0 +1,125 @@
+mod common;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// A transparent alias, a generic one, and a distinct type that is made
+/// with its name and taken apart by matching on it.
+const SOURCE: &str = "type Meters = float
+
+type Pair<T> = (T, T)
+
+type UserId = distinct int
+
+fn area = (w: Meters, h: Meters) -> w * h
+
+fn swap = (p: Pair<int>) -> match p {
+    (a, b) -> (b, a)
+}
+
+fn raw = (id: UserId) -> match id {
+    UserId(n) -> n
+}
+
+proc main = () {
+    const w: Meters = 2.0
+    println(area(w, 3.5))
+    println(swap((1, 2)))
+    const id = UserId(7)
+    println(raw(id) + 1)
+    println(id)
+}
+";
+
+const EXPECTED: &str = "7.0\n(2, 1)\n8\nUserId(7)\n";
+
+#[test]
+fn every_engine_runs_aliases_and_distinct_types() {
+    let file = scratch("aliases-run").join("aliases.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    assert_eq!(chop_ok(&["run", "--engine=ast", path(&file)], ""), EXPECTED);
+    for level in ["-O0", "-O2"] {
+        assert_eq!(
+            chop_ok(&["run", level, path(&file)], ""),
+            EXPECTED,
+            "{}",
+            level
+        );
+    }
+}
+
+#[test]
+fn inferred_types_keep_the_alias_names() {
+    let types = chop_ok(&["check", "--emit=types", "-"], SOURCE);
+    for expected in [
+        "fn area: (Meters, Meters) -> Meters",
+        "fn swap: (Pair<int>) -> (int, int)",
+        "fn raw: (UserId) -> int",
+    ] {
+        assert!(types.contains(expected), "{}", types);
+    }
+}
+
+#[test]
+fn distinct_types_do_not_mix_with_their_target() {
+    let source = "type Meters = float
+
+type UserId = distinct int
+
+type Pair<T> = (T, T)
+
+fn area = (w: Meters, h: Meters) -> w * h
+
+fn lookup = (id: UserId) -> 0
+
+proc main = () {
+    const m: Meters = \"far\"
+    const p: Pair<int> = (1, \"x\")
+    const q: Pair<int, int> = (1, 2)
+    println(area(2.0, \"x\"))
+    println(lookup(3))
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":12:23: error: mismatched types: expected `Meters`, found `string`",
+        ":13:26: error: mismatched types: expected `Pair<int>`, found `(int, string)`",
+        ":14:5: error: `Pair` takes 1 type arguments, but 2 were given",
+        ":15:23: error: mismatched types: expected `Meters`, found `string`",
+        ":16:20: error: no instance of `@num` for `UserId`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn cyclic_aliases_are_reported() {
+    let source = "type A = [B]
+
+type B = (int, A)
+
+type Loop = Loop
+
+type Tree = distinct (int, [Tree])
+
+proc main = () {}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":1:1: error: the type alias `A` refers to itself through `B`",
+        ":3:1: `B` refers back to `A` here",
+        ":5:1: error: the type alias `Loop` refers to itself",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 2, "{}", stderr);
+}
+
+#[test]
+fn aliases_are_formatted() {
+    let source = "type Pair<A, B> = (A, B)\n\ntype UserId = distinct int\n";
+    assert_eq!(chop_ok(&["fmt", "-"], source), source);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{chop, chop_ok, path, sources};
+
+#[test]
+fn examples_survive_a_json_round_trip() {
+    for file in sources() {
+        let json = chop_ok(&["parse", path(&file)], "");
+        assert_eq!(
+            chop_ok(&["parse", "--from=ast-json", "-"], &json),
+            json,
+            "{} changes when read back from its dump",
+            file.display()
+        );
+        assert_eq!(
+            chop_ok(&["parse", "--from=ast-json", "--emit=ast-sexp", "-"], &json),
+            chop_ok(&["parse", "--emit=ast-sexp", path(&file)], ""),
+            "{} reads back as a different tree",
+            file.display()
+        );
+    }
+}
+
+#[test]
+fn non_ascii_text_survives_a_json_round_trip() {
+    let source = "proc main = () {\n    println(\"naïve café, 日本\")\n}\n";
+    let json = chop_ok(&["parse", "-"], source);
+    assert!(json.contains("naïve café, 日本"), "{}", json);
+    assert_eq!(chop_ok(&["parse", "--from=ast-json", "-"], &json), json);
+}
+
+/// The stderr of reading `json` back, which must fail.
+fn unreadable(json: &str) -> String {
+    let output = chop(&["parse", "--from=ast-json", "-"], json);
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn dumps_of_other_format_versions_are_rejected() {
+    let json = chop_ok(&["parse", "examples/hello_world.chop"], "");
+    let version = json
+        .lines()
+        .find(|line| line.trim_start().starts_with("\"version\""))
+        .expect("version field");
+    let stderr = unreadable(&json.replace(version, "  \"version\": 999,"));
+    assert!(
+        stderr.contains("unsupported AST format version 999"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn dumps_without_a_format_are_rejected() {
+    let json = chop_ok(&["parse", "examples/hello_world.chop"], "");
+    let stderr = unreadable(&json.replace("  \"format\": \"chop-ast\",\n", ""));
+    assert!(stderr.contains("missing field 'format'"), "{}", stderr);
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{build_and_run, chop, chop_ok, path, scratch, PROGRAMS};
+
+#[test]
+fn executables_print_what_the_interpreter_prints() {
+    let dir = scratch("build-programs");
+    for (i, program) in PROGRAMS.iter().enumerate() {
+        let output = build_and_run("c", program, &dir, &format!("program{}", i));
+        assert!(output.status.success(), "{} failed once built", program);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            chop_ok(&["run", "--engine=ast", program], ""),
+            "{} prints something else once built",
+            program
+        );
+    }
+}
+
+#[test]
+fn executables_report_runtime_errors_like_chop_run() {
+    let dir = scratch("build-errors");
+    let source = dir.join("divide.chop");
+    let text = "fn divide = (a, b) -> a / b\n\nproc main = () {\n    println(divide(1, 0))\n}\n";
+    std::fs::write(&source, text).expect("source");
+    let output = build_and_run("c", path(&source), &dir, "divide");
+    let run = chop(&["run", path(&source)], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        String::from_utf8_lossy(&run.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("division by zero"));
+}
+
+#[test]
+fn deep_recursion_is_stopped() {
+    let dir = scratch("build-recursion");
+    let source = dir.join("down.chop");
+    let text = "fn down = (n) -> 1 + down(n + 1)\n\nproc main = () {\n    println(down(0))\n}\n";
+    std::fs::write(&source, text).expect("source");
+    let output = build_and_run("c", path(&source), &dir, "down");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("calls to `down` nest more than"));
+}
+
+#[test]
+fn generated_c_points_back_at_the_source() {
+    let c = chop_ok(&["build", "--emit=c", "tests/fixtures/runtime.chop"], "");
+    assert!(c.contains("#line 18 \"tests/fixtures/runtime.chop\""));
+    assert!(c.contains("enum chop_enum_shape {"));
+    assert!(c.contains("static chop_value f0_bump(chop_closure *self, chop_value *args) {"));
+}
+
+#[test]
+fn structs_and_enums_get_c_types_of_their_own() {
+    let dir = scratch("build-layouts");
+    let source = dir.join("shapes.chop");
+    let text = "struct Point = {
+    var x: int
+    var y: int
+}
+
+enum Shape = {
+    Circle(int)
+    Rect(Point, Point)
+    Empty
+}
+
+fn area = (s) -> match s {
+    Circle(r) -> r * r * 3
+    Rect(a, b) -> (b.x - a.x) * (b.y - a.y)
+    Empty -> 0
+}
+
+proc main = () {
+    var p = Point { x: 1, y: 2 }
+    p.y = p.y + 40
+    const box = Rect(Point.init(0, 0), p)
+    println(box)
+    println([area(box), area(Circle(2)), area(Empty), area(box)])
+}
+";
+    std::fs::write(&source, text).expect("source");
+    let c = chop_ok(&["build", "--emit=c", path(&source)], "");
+    for expected in [
+        "typedef struct {\n    struct chop_instance header;\n    chop_value f_x;\n    chop_value f_y;\n} struct0_Point;",
+        "        struct {\n            chop_value _0;\n            chop_value _1;\n        } v_Rect;",
+        "} enum0_Shape;",
+        "s[0] = struct0_Point_init(&s[0]);",
+        "((enum0_Shape *)s[0].as.variant)->as.v_Rect._1",
+    ] {
+        assert!(c.contains(expected), "{}", expected);
+    }
+    let output = build_and_run("c", path(&source), &dir, "shapes");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        chop_ok(&["run", path(&source)], "")
+    );
+}
+
+#[test]
+fn unknown_targets_are_rejected() {
+    let output = chop(&["build", "--target=jvm", "examples/hello_world.chop"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown target 'jvm'"));
+}
@@ -0,0 +1,259 @@
+mod common;
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use common::{chop, chop_ok, path, scratch, PROGRAMS};
+
+/// Where the header ends and the checksummed body starts.
+const BODY: usize = 16;
+
+fn compile(source: &str, output: &Path) -> Vec<u8> {
+    chop_ok(
+        &["compile", &format!("--output={}", path(output)), source],
+        "",
+    );
+    std::fs::read(output).expect("compiled program")
+}
+
+/// The stderr of a command that must fail.
+fn fails(args: &[&str]) -> String {
+    let output = chop(args, "");
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "chop {:?} did not fail cleanly",
+        args
+    );
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn reseal(bytes: &mut [u8]) {
+    let sum = checksum(&bytes[BODY..]);
+    bytes[8..BODY].copy_from_slice(&sum.to_le_bytes());
+}
+
+/// xorshift64, so that every run fuzzes the same files.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+
+    /// Flips, overwrites, inserts or removes a few bytes of the body.
+    fn mutate(&mut self, original: &[u8]) -> Vec<u8> {
+        let mut bytes = original.to_vec();
+        for _ in 0..1 + self.below(4) {
+            let at = BODY + self.below(bytes.len() - BODY);
+            match self.below(4) {
+                0 => bytes[at] ^= 1 << self.below(8),
+                1 => bytes[at] = self.below(256) as u8,
+                2 => bytes.insert(at, self.below(256) as u8),
+                _ if bytes.len() > BODY + 1 => {
+                    bytes.remove(at);
+                }
+                _ => {}
+            }
+        }
+        bytes
+    }
+}
+
+#[test]
+fn compiled_programs_run_like_their_source() {
+    let dir = scratch("chopc-run");
+    for (i, program) in PROGRAMS.iter().enumerate() {
+        let compiled = dir.join(format!("{}.chopc", i));
+        compile(program, &compiled);
+        assert_eq!(
+            chop_ok(&["run", path(&compiled)], ""),
+            chop_ok(&["run", program], ""),
+            "{} prints something else once compiled",
+            program
+        );
+        assert_eq!(
+            chop_ok(&["disasm", path(&compiled)], ""),
+            chop_ok(&["disasm", program], ""),
+            "{} disassembles differently once compiled",
+            program
+        );
+    }
+}
+
+#[test]
+fn compile_writes_next_to_the_source() {
+    let dir = scratch("chopc-next");
+    let source = dir.join("hello.chop");
+    std::fs::copy("examples/hello_world.chop", &source).expect("copy");
+    chop_ok(&["compile", path(&source)], "");
+    let output = chop_ok(&["run", path(&dir.join("hello.chopc"))], "");
+    assert_eq!(output, chop_ok(&["run", "examples/hello_world.chop"], ""));
+
+    let stdin = chop(&["compile", "-"], "proc main = () {}\n");
+    assert!(String::from_utf8_lossy(&stdin.stderr).contains("--output is required"));
+}
+
+#[test]
+fn disassembly_quotes_source_lines() {
+    let listing = chop_ok(&["disasm", "examples/fibonacci.chop"], "");
+    assert!(listing.contains("fn #0 fibonacci: 1 params, 1 slots"));
+    assert!(listing.contains("     1 | fn fibonacci = (0) -> 0"));
+    assert!(listing.contains("CallFunction  0, 1      fibonacci"));
+    assert!(listing.contains("CallBuiltin   4, 1      println"));
+}
+
+#[test]
+fn runtime_errors_point_into_the_source() {
+    let dir = scratch("chopc-errors");
+    let source = dir.join("half.chop");
+    let text = "fn half = (0) -> 0\n\nproc main = () {\n    println(half(1))\n}\n";
+    std::fs::write(&source, text).expect("source");
+    let compiled = dir.join("half.chopc");
+    compile(path(&source), &compiled);
+
+    let stderr = fails(&["run", path(&compiled)]);
+    assert!(stderr.contains("no clause of `half` matches"), "{}", stderr);
+    assert!(stderr.contains("println(half(1))"), "{}", stderr);
+    assert!(fails(&["run", "--engine=ast", path(&compiled)]).contains("only run on the vm"));
+}
+
+#[test]
+fn other_versions_are_rejected() {
+    let dir = scratch("chopc-version");
+    let compiled = dir.join("fib.chopc");
+    let mut bytes = compile("examples/fibonacci.chop", &compiled);
+    bytes[6..8].copy_from_slice(&7u16.to_le_bytes());
+    std::fs::write(&compiled, &bytes).expect("write");
+    assert!(fails(&["run", path(&compiled)])
+        .contains("compiled for bytecode format 7, but this chop reads format 4"));
+}
+
+#[test]
+fn other_files_are_rejected() {
+    let dir = scratch("chopc-magic");
+    let compiled = dir.join("source.chopc");
+    std::fs::copy("examples/fibonacci.chop", &compiled).expect("copy");
+    assert!(fails(&["run", path(&compiled)]).contains("not a compiled chop program"));
+    std::fs::write(&compiled, b"").expect("write");
+    assert!(fails(&["disasm", path(&compiled)]).contains("not a compiled chop program"));
+}
+
+#[test]
+fn truncated_files_are_rejected() {
+    let dir = scratch("chopc-truncated");
+    let compiled = dir.join("instances.chopc");
+    let bytes = compile("tests/fixtures/instances.chop", &compiled);
+    for len in [BODY - 1, BODY, BODY + 3, bytes.len() / 2, bytes.len() - 1] {
+        std::fs::write(&compiled, &bytes[..len]).expect("write");
+        let stderr = fails(&["run", path(&compiled)]);
+        assert!(
+            stderr.contains("not a compiled chop program") || stderr.contains("checksum"),
+            "{} bytes: {}",
+            len,
+            stderr
+        );
+
+        let mut resealed = bytes[..len.max(BODY)].to_vec();
+        reseal(&mut resealed);
+        std::fs::write(&compiled, &resealed).expect("write");
+        assert!(fails(&["disasm", path(&compiled)]).contains("the file is corrupted"));
+    }
+}
+
+#[test]
+fn fuzzed_files_fail_the_checksum() {
+    let dir = scratch("chopc-fuzz-checksum");
+    let compiled = dir.join("runtime.chopc");
+    let original = compile("tests/fixtures/runtime.chop", &compiled);
+    let mut random = Random(0x5eed_c0de);
+    for _ in 0..50 {
+        let bytes = random.mutate(&original);
+        if bytes == original {
+            continue;
+        }
+        std::fs::write(&compiled, &bytes).expect("write");
+        assert!(fails(&["run", path(&compiled)]).contains("checksum does not match"));
+    }
+}
+
+/// Mutations that keep a valid checksum reach the loader's own checks,
+/// which must turn them into errors rather than crashes.
+#[test]
+fn fuzzed_files_never_crash_the_loader() {
+    let dir = scratch("chopc-fuzz-loader");
+    let compiled = dir.join("runtime.chopc");
+    let original = compile("tests/fixtures/runtime.chop", &compiled);
+    let mut random = Random(0xc4a1_1e96);
+    for round in 0..300 {
+        let mut bytes = random.mutate(&original);
+        reseal(&mut bytes);
+        std::fs::write(&compiled, &bytes).expect("write");
+        let output = chop(&["disasm", path(&compiled)], "");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match output.status.code() {
+            Some(0) => {}
+            Some(1) => assert!(
+                stderr.contains("the file is corrupted"),
+                "round {}: {}",
+                round,
+                stderr
+            ),
+            _ => panic!("round {}: the loader crashed:\n{}", round, stderr),
+        }
+    }
+}
+
+/// Files that load are run as well: the loader's checks must leave nothing
+/// the virtual machine trips over. A mutation may well loop forever, so a
+/// run that outlasts its time is stopped and counts as surviving.
+#[test]
+fn fuzzed_files_never_crash_the_virtual_machine() {
+    let dir = scratch("chopc-fuzz-run");
+    let compiled = dir.join("runtime.chopc");
+    let original = compile("tests/fixtures/runtime.chop", &compiled);
+    let mut random = Random(0xfeed_face);
+    for round in 0..300 {
+        let mut bytes = random.mutate(&original);
+        reseal(&mut bytes);
+        std::fs::write(&compiled, &bytes).expect("write");
+        let mut child = Command::new(env!("CARGO_BIN_EXE_chop"))
+            .args(["run", path(&compiled)])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to start chop");
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().expect("wait") {
+                break Some(status);
+            }
+            if started.elapsed() > Duration::from_secs(5) {
+                child.kill().expect("kill");
+                child.wait().expect("wait");
+                break None;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        let output = child.wait_with_output().expect("stderr");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if let Some(status) = status {
+            assert!(
+                matches!(status.code(), Some(0 | 1)),
+                "round {}: the virtual machine crashed:\n{}",
+                round,
+                stderr
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::chop;
+
+const SHAPE: &str = "enum shape = {
+    circle(float)
+    rectangle(float)
+    empty
+}
+";
+
+/// The warnings `chop check` prints for `source`, which must check.
+fn warnings(source: &str) -> String {
+    let output = chop(&["check", "-"], &format!("{}{}", SHAPE, source));
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    assert!(output.status.success(), "{}", stderr);
+    stderr
+}
+
+#[test]
+fn covering_clauses_are_accepted_quietly() {
+    let stderr = warnings(
+        "
+fn fact = (0) -> 1
+fn fact = (n) -> n * fact(n - 1)
+
+fn kind = (circle(_)) -> 1
+fn kind = (rectangle(_)) -> 2
+fn kind = (empty) -> 3
+
+fn positive = (n where n > 0) -> true
+fn positive = (n) -> false
+
+proc main = () {
+    println([fact(3), kind(empty)])
+    println(positive(1))
+}
+",
+    );
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn uncovered_arguments_are_named() {
+    let stderr = warnings(
+        "
+fn area = (circle(r)) -> r
+fn area = (empty) -> 0.0
+
+fn zero = (0) -> true
+
+proc main = () {
+    println(area(empty))
+    println(zero(0))
+}
+",
+    );
+    for expected in [
+        ":7:1: warning: the clauses of `area` do not cover every argument",
+        "help: add a clause for `area(rectangle(_))`",
+        ":10:1: warning: the clauses of `zero` do not cover every argument",
+        "help: add a clause for `zero(1)`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("warning:").count(), 2, "{}", stderr);
+}
+
+#[test]
+fn guarded_clauses_do_not_count_towards_coverage() {
+    let stderr = warnings(
+        "
+fn sign = (n where n < 0) -> -1
+fn sign = (0) -> 0
+
+proc main = () {
+    println(sign(0))
+}
+",
+    );
+    for expected in [
+        ":7:1: warning: the clauses of `sign` do not cover every argument",
+        ":7:1: a clause with a `where` guard is not counted, as its guard may be false",
+        "help: add a clause for `sign(1)`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn clauses_after_covering_ones_are_redundant() {
+    let stderr = warnings(
+        "
+fn first = (x) -> x
+fn first = (1) -> 2
+
+fn kind = (empty) -> 0
+fn kind = (_) -> 1
+fn kind = (empty) -> 2
+
+proc main = () {
+    println([first(1), kind(empty)])
+}
+",
+    );
+    for expected in [
+        ":8:1: warning: this clause of `first` can never match",
+        "help: the clauses before it already match all of its arguments",
+        ":12:1: warning: this clause of `kind` can never match",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("warning:").count(), 2, "{}", stderr);
+}
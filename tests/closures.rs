@@ -0,0 +1,238 @@
+mod common;
+
+use std::process::Command;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// Closures passed to and returned from higher-order `fn`s, capturing
+/// consts, loop variables and a `var` that is assigned after they are made.
+const SOURCE: &str = "fn apply = (f, x) -> f(x)
+
+fn twice = (f, x) -> f(f(x))
+
+fn compose = (f, g) -> (x) -> f(g(x))
+
+fn adder = (n) -> (x) -> x + n
+
+fn sign = (n) -> match n {
+    m where (m > 0) -> 1
+    _ -> 0
+}
+
+proc main = () {
+    const k = 10
+    println(apply((x) -> x * 2, 21))
+    println(twice((x) -> x + k, 1))
+    const both = compose(adder(1), (x: int) -> x * 2)
+    println(both(5))
+    var count = 0
+    proc bump = () {
+        count += 1
+    }
+    const seen = () -> count
+    bump()
+    bump()
+    println(seen())
+    for x in [1, 2, 3] {
+        println(apply((y) -> y * x, 10))
+    }
+    println(sign(3))
+}
+";
+
+const EXPECTED: &str = "42\n21\n11\n2\n10\n20\n30\n1\n";
+
+fn source(name: &str) -> String {
+    let file = scratch(name).join("closures.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    path(&file).to_string()
+}
+
+#[test]
+fn the_interpreter_and_the_virtual_machine_run_closures_alike() {
+    let source = source("closures-run");
+    assert_eq!(chop_ok(&["run", "--engine=ast", &source], ""), EXPECTED);
+    for level in ["-O0", "-O1", "-O2"] {
+        assert_eq!(chop_ok(&["run", level, &source], ""), EXPECTED, "{}", level);
+    }
+}
+
+#[test]
+fn closures_copy_what_they_do_not_share() {
+    let source = source("closures-disasm");
+    let listing = chop_ok(&["disasm", &source], "");
+    assert!(listing.contains("closure over copy of slot"), "{}", listing);
+    assert!(listing.contains("bump over slot"), "{}", listing);
+    let compiled = scratch("closures-disasm").join("closures.chopc");
+    chop_ok(
+        &["compile", &format!("--output={}", path(&compiled)), &source],
+        "",
+    );
+    assert_eq!(chop_ok(&["run", path(&compiled)], ""), EXPECTED);
+}
+
+#[test]
+fn c_executables_run_closures() {
+    let source = source("closures-c");
+    let executable = scratch("closures-c").join("closures");
+    chop_ok(
+        &[
+            "build",
+            "--target=c",
+            &format!("--output={}", path(&executable)),
+            &source,
+        ],
+        "",
+    );
+    let output = Command::new(&executable)
+        .output()
+        .expect("the built program runs");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), EXPECTED);
+}
+
+#[test]
+fn closures_may_not_outlive_a_var_they_share() {
+    let source = "struct Holder = {
+    var f: (int) -> int
+}
+
+var global = (x: int) -> x
+
+proc leak = (holder: Holder) -> (int) -> int {
+    var n = 1
+    n += 1
+    const m = n
+    const f = (x) -> x + n
+    holder.f = f
+    global = (x) -> x + m
+    global = f
+    return f
+}
+
+proc keep = () -> (int) -> int {
+    var n = 1
+    n += 1
+    var g = (x) -> x + n
+    println(g(1))
+    g = (x) -> x
+    return (x) -> x + 1
+}
+
+proc main = () {
+    println(1)
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":12:16: error: this closure outlives `n`, which it captures by reference",
+        ":14:14: error: this closure outlives `n`, which it captures by reference",
+        ":15:12: error: this closure outlives `n`, which it captures by reference",
+        ":8:5: `n` is declared here",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 3, "{}", stderr);
+}
+
+#[test]
+fn closures_may_not_outlive_a_var_through_a_closure_they_capture() {
+    let source = "proc leak = () -> () -> int {
+    var n = 1
+    n += 1
+    const f = () -> n
+    const g = () -> f()
+    return g
+}
+
+proc main = () {
+    const h = leak()
+    println(h())
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("-:6:12: error: this closure outlives `n`, which it captures by reference"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error:").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn closures_may_not_outlive_a_var_through_a_call_that_returns_them() {
+    let source = "struct Box = {
+    var value: int
+
+    fn with = (self, f) -> f
+}
+
+fn id = (x) -> x
+
+fn left = (pair) -> match pair {
+    (l, _) -> l
+}
+
+fn apply = (f) -> f()
+
+fn twice = (f) -> () -> f() + f()
+
+proc leak = () -> () -> int {
+    var x = 1
+    x += 1
+    return id(() -> x)
+}
+
+proc leak_pair = () -> () -> int {
+    var x = 1
+    x += 1
+    return left((() -> x, 0))
+}
+
+proc leak_wrapped = () -> () -> int {
+    var x = 1
+    x += 1
+    return twice(() -> x)
+}
+
+proc leak_method = (b: Box) -> () -> int {
+    var x = 1
+    x += 1
+    return b.with(() -> x)
+}
+
+proc keep = () -> int {
+    var x = 1
+    x += 1
+    return apply(() -> x)
+}
+
+proc main = () {
+    println(keep())
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in [20, 26, 32, 38] {
+        let expected = format!(
+            "-:{}:12: error: this closure outlives `x`, which it captures by reference",
+            line
+        );
+        assert!(stderr.contains(&expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 4, "{}", stderr);
+}
+
+#[test]
+fn closures_are_formatted_like_fn_clauses() {
+    let source = "proc main = () {\n    println(apply(( x )->x+1, 2))\n}\n";
+    assert_eq!(
+        chop_ok(&["fmt", "-"], source),
+        "proc main = () {\n    println(apply((x) -> x + 1, 2))\n}\n"
+    );
+}
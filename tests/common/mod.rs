@@ -0,0 +1,107 @@
+// Each test binary uses its own subset of these helpers.
+#![allow(dead_code)]
+
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Programs whose output must not depend on the engine or backend running them.
+pub const PROGRAMS: &[&str] = &[
+    "examples/hello_world.chop",
+    "examples/fibonacci.chop",
+    "tests/fixtures/instances.chop",
+    "tests/fixtures/runtime.chop",
+];
+
+/// Runs the `chop` binary with `stdin` piped in.
+pub fn chop(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chop"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start chop");
+    // A command that fails before reading its input closes the pipe early.
+    let written = child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(stdin.as_bytes());
+    if let Err(error) = written {
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe, "failed to write stdin");
+    }
+    child.wait_with_output().expect("chop did not finish")
+}
+
+/// Runs `chop` and returns its stdout, failing the test if it exits with an error.
+pub fn chop_ok(args: &[&str], stdin: &str) -> String {
+    let output = chop(args, stdin);
+    assert!(
+        output.status.success(),
+        "chop {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("utf-8 output")
+}
+
+/// The stderr of `chop check` on a program it must reject.
+pub fn rejected(source: &str) -> String {
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// The `.chop` files in `examples/` and `benches/` plus the test fixtures.
+pub fn sources() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut files = Vec::new();
+    for dir in ["examples", "benches", "tests/fixtures"] {
+        for entry in std::fs::read_dir(root.join(dir)).expect("source directory") {
+            let path = entry.expect("directory entry").path();
+            if path.extension().is_some_and(|ext| ext == "chop") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// A fresh directory for one test's files.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chop-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).expect("scratch directory");
+    dir
+}
+
+pub fn path(path: &Path) -> &str {
+    path.to_str().expect("utf-8 path")
+}
+
+/// Builds a program for `target` into `dir` and runs what was built: a wasm
+/// module with `chop run`, an executable directly.
+pub fn build_and_run(target: &str, source: &str, dir: &Path, name: &str) -> Output {
+    let built = match target {
+        "wasm" => dir.join(format!("{}.wasm", name)),
+        _ => dir.join(name),
+    };
+    chop_ok(
+        &[
+            "build",
+            &format!("--target={}", target),
+            &format!("--output={}", path(&built)),
+            source,
+        ],
+        "",
+    );
+    if target == "wasm" {
+        return chop(&["run", path(&built)], "");
+    }
+    Command::new(&built)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("the built program runs")
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{chop_ok, rejected};
+
+#[test]
+fn consts_are_evaluated_up_to_the_edge_of_an_int() {
+    let source = "fn fib = (n: int) -> match n {
+    0 -> 0
+    1 -> 1
+    _ -> fib(n - 1) + fib(n - 2)
+}
+
+const ten = fib(10)
+const limit = 9223372036854775806 + 1
+const pair = (ten, -limit)
+
+proc main = () {
+    println(pair)
+}
+";
+    assert_eq!(
+        chop_ok(&["check", "--emit=consts", "-"], source),
+        "const ten = 55\nconst limit = 9223372036854775807\nconst pair = (55, -9223372036854775807)\n"
+    );
+}
+
+#[test]
+fn overflow_and_division_by_zero_are_compile_errors() {
+    let stderr = rejected(
+        "const big = 9223372036854775807 + 1
+const half = 10 / (5 - 5)
+const neg = -(0 - 9223372036854775807 - 1)
+
+proc main = () {
+    println([big, half, neg])
+}
+",
+    );
+    for expected in [
+        "-:1:13: error: this `+` overflows: `9223372036854775807 + 1` does not fit in an `int`",
+        "-:2:14: error: division by zero",
+        "-:3:13: error: negating `-9223372036854775808` overflows an `int`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn evaluation_is_limited_in_steps_and_depth() {
+    let stderr = rejected(
+        "fn fib = (n: int) -> match n {
+    0 -> 0
+    1 -> 1
+    _ -> fib(n - 1) + fib(n - 2)
+}
+
+fn deep = (n: int) -> match n {
+    0 -> 0
+    _ -> 1 + deep(n - 1)
+}
+
+const slow = fib(40)
+const depth = deep(1000)
+
+proc main = () {
+    println([slow, depth])
+}
+",
+    );
+    for expected in [
+        "-:4:10: error: evaluating `slow` takes more than 100000 steps",
+        "help: a `const` may only call functions that finish quickly",
+        "-:9:14: error: calls to `deep` nest more than 256 deep at compile time",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn a_const_that_depends_on_itself_is_a_cycle() {
+    let stderr = rejected(
+        "const a = b + 1
+const b = a + 1
+
+proc main = () {
+    println(a)
+}
+",
+    );
+    for expected in [
+        "-:1:1: error: the value of `a` depends on itself",
+        "note: -:1:11: `a` uses `b` here",
+        "note: -:2:11: `b` uses `a` here",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("depends on itself").count(), 1, "{}", stderr);
+}
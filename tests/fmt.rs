@@ -0,0 +1,88 @@
+mod common;
+
+use common::{chop, chop_ok, sources};
+
+/// The AST dump without spans, which legitimately move when code is reformatted.
+fn structure(source: &str) -> String {
+    chop_ok(&["parse", "-"], source)
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("\"span\""))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    for path in sources() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let once = chop_ok(&["fmt", "-"], &source);
+        let twice = chop_ok(&["fmt", "-"], &once);
+        assert_eq!(once, twice, "{} changes when formatted twice", path.display());
+    }
+}
+
+#[test]
+fn formatting_preserves_the_parse() {
+    for path in sources() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let formatted = chop_ok(&["fmt", "-"], &source);
+        assert_eq!(
+            structure(&source),
+            structure(&formatted),
+            "formatting changed the syntax tree of {}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn examples_are_formatted() {
+    chop_ok(&["fmt", "--check", "examples"], "");
+}
+
+#[test]
+fn check_fails_on_unformatted_files() {
+    let output = chop(&["fmt", "--check", "tests/fixtures/unformatted.chop"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not formatted"));
+
+    let source = std::fs::read_to_string("tests/fixtures/unformatted.chop").unwrap();
+    assert!(!chop(&["fmt", "--check", "-"], &source).status.success());
+}
+
+#[test]
+fn comments_are_kept() {
+    let formatted = chop_ok(
+        &["fmt", "-"],
+        "// about main\nproc main = () {\n  println(1)   // one\n  // done\n}\n",
+    );
+    assert_eq!(
+        formatted,
+        "// about main\nproc main = () {\n    println(1) // one\n    // done\n}\n"
+    );
+}
+
+#[test]
+fn struct_fields_and_enum_entries_are_aligned() {
+    let formatted = chop_ok(
+        &["fmt", "-"],
+        "struct S = {\n var a: int // a\n var long_name: [int] // b\n}\n\nenum E = {\n x // x\n yy(int) // y\n}\n",
+    );
+    assert_eq!(
+        formatted,
+        "struct S = {\n    var a:         int   // a\n    var long_name: [int] // b\n}\n\n\
+         enum E = {\n    x       // x\n    yy(int) // y\n}\n"
+    );
+}
+
+#[test]
+fn long_argument_lists_are_wrapped() {
+    let args: Vec<String> = (0..12).map(|i| format!("argument_{}", i)).collect();
+    let source = format!("proc main = () {{\n    println({})\n}}\n", args.join(", "));
+    let formatted = chop_ok(&["fmt", "-"], &source);
+    let expected: String = args.iter().map(|a| format!("        {},\n", a)).collect();
+    assert_eq!(
+        formatted,
+        format!("proc main = () {{\n    println(\n{}    )\n}}\n", expected)
+    );
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// A bounded generic `fn`, a generic enum, a struct whose type parameter
+/// has a bound, and one generic `fn` calling another with its own
+/// parameters.
+const SOURCE: &str = "fn max = (type T: @ord, a: T, b: T) -> match a < b {
+    true -> b
+    _ -> a
+}
+
+fn twice = (type T, x: T) -> [x, x]
+
+fn both = (type A, type B, a: A, b: B) -> (twice(a), twice(b))
+
+enum option = {
+    type T
+    some(T)
+    none
+}
+
+fn unwrap = (type T, o: option<T>, default: T) -> match o {
+    none -> default
+    some(x) -> x
+}
+
+struct Labelled = {
+    type T: @show
+    var label: string
+    var value: T
+}
+
+proc main = () {
+    println(max(1, 2))
+    println(max(\"b\", \"a\"))
+    println(unwrap(some(2.5), 0.0))
+    println(unwrap(none, max(3, 4)))
+    println(both(1, true))
+    const l = Labelled { label: \"x\", value: 3 }
+    println(l.value)
+}
+";
+
+const EXPECTED: &str = "2\nb\n2.5\n4\n([1, 1], [true, true])\n3\n";
+
+#[test]
+fn every_engine_runs_the_instances() {
+    let file = scratch("generics-run").join("generics.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    assert_eq!(chop_ok(&["run", "--engine=ast", path(&file)], ""), EXPECTED);
+    for level in ["-O0", "-O2"] {
+        assert_eq!(
+            chop_ok(&["run", level, path(&file)], ""),
+            EXPECTED,
+            "{}",
+            level
+        );
+    }
+    let disasm = chop_ok(&["disasm", path(&file)], "");
+    assert!(disasm.contains("CallFunction"), "{}", disasm);
+    assert!(disasm.contains("max<string>"), "{}", disasm);
+}
+
+#[test]
+fn emit_mono_lists_each_instance_once() {
+    assert_eq!(
+        chop_ok(&["check", "--emit=mono", "-"], SOURCE),
+        "fn max<int>: (int, int) -> int\n\
+         fn max<string>: (string, string) -> string\n\
+         fn unwrap<float>: (option<float>, float) -> float\n\
+         fn unwrap<int>: (option<int>, int) -> int\n\
+         fn both<int, bool>: (int, bool) -> ([int], [bool])\n\
+         fn twice<int>: (int) -> [int]\n\
+         fn twice<bool>: (bool) -> [bool]\n"
+    );
+}
+
+#[test]
+fn type_parameters_must_stay_generic() {
+    let source = "fn add = (type T, a: T) -> a + 1
+
+fn negated = (type T, a: T) -> not(a)
+
+fn pair = (type A, type B, a: A, b: B) -> [a, b]
+
+fn ignored = (type T, a: int) -> a
+
+fn wrong = (type T: int, a: T) -> a
+
+struct Sorted = {
+    type T: @ord
+    var item: T
+}
+
+fn max = (type T: @ord, a: T, b: T) -> a
+
+proc main = () {
+    println(max(true, \"b\"))
+    const s = Sorted { item: (x) -> x }
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":1:11: error: `add` needs `@num` of its type parameter `T`, which its bounds do not provide",
+        "help: add the bound: `type T: @num`",
+        ":3:15: error: `negated` only works when its type parameter `T` is `bool`",
+        ":5:20: error: `pair` only works when its type parameters `A` and `B` are the same type",
+        ":7:15: error: the type parameter `T` of `ignored` is in none of its parameters or its result",
+        ":9:13: error: the bound of the type parameter `T` is not a typeclass",
+        ":19:23: error: the type parameter `T` of `max` cannot be both `bool` and `string`",
+        ":19:17: `T` is `bool` because of this",
+        ":20:15: error: no instance of `@ord` for `(a) -> a`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn a_parameter_no_use_decides_is_reported() {
+    let source = "fn size = (type T, xs: [T]) -> 0
+
+fn count = (xs) -> size(xs)
+
+proc main = () {
+    println(count([1]))
+    println(size([]))
+}
+";
+    let output = chop(&["run", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(":7:13: error: the type parameter `T` of `size` cannot be inferred here"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error:").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn bounds_and_enum_parameters_are_formatted() {
+    let source = "enum option = {\n    type T: (@eq, @show)\n    some(T)\n    none\n}\n\n\
+                  fn id = (type T: @ord, x: T) -> x\n";
+    assert_eq!(chop_ok(&["fmt", "-"], source), source);
+}
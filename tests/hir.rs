@@ -0,0 +1,157 @@
+mod common;
+
+use common::{chop_ok, path, sources};
+
+fn hir(source: &str) -> String {
+    chop_ok(&["parse", "--emit=hir", "-"], source)
+}
+
+#[test]
+fn every_source_desugars_without_surface_loops() {
+    for file in sources() {
+        let output = chop_ok(&["parse", "--emit=hir", path(&file)], "");
+        for line in output.lines() {
+            let line = line.trim_start();
+            assert!(
+                !line.starts_with("while ") && !line.starts_with("for ") && !line.contains("+="),
+                "{} keeps surface syntax in its HIR: {}",
+                file.display(),
+                line
+            );
+        }
+    }
+}
+
+#[test]
+fn clauses_and_guards_become_one_match() {
+    let output = hir("fn fib = (n where n < 1) -> 1
+fn fib = (1) -> 1
+fn fib = (n) -> fib(n - 1) + fib(n - 2)
+
+fn pick = (0, y) -> y
+fn pick = (x, _) -> x
+");
+    assert_eq!(
+        output,
+        "fn fib($0) = match clauses $0 {
+    n where <(n, 1) -> 1
+    1 -> 1
+    n -> +(fib(-(n, 1)), fib(-(n, 2)))
+}
+fn pick($0, $1) = match clauses tuple($0, $1) {
+    (0, y) -> y
+    (x, _) -> x
+}
+"
+    );
+}
+
+#[test]
+fn statements_become_loops_and_plain_assignments() {
+    let output = hir("proc main = () {
+    var i = 0
+    while i < 3 {
+        i += 1
+    } else {
+        println(i)
+    }
+    for k, v in {\"a\": 1} {
+        println(-v)
+    }
+    println(!{1, 2}, (1, 2))
+}
+");
+    assert_eq!(
+        output,
+        "proc main() = {
+    var i = 0
+    {
+        var $ended0 = false
+        loop {
+            if <(i, 3) {
+                i = +(i, 1)
+            } else {
+                $ended0 = true
+                break
+            }
+        }
+        if $ended0 {
+            println(i)
+        }
+    }
+    {
+        const $iter1 = $iterate(map(tuple(\"a\", 1)))
+        loop {
+            match for $next($iter1) {
+                $done() -> break
+                (k, v) -> {
+                    println(negate(v))
+                }
+            }
+        }
+    }
+    println(not(set(1, 2)), tuple(1, 2))
+}
+"
+    );
+}
+
+#[test]
+fn methods_are_named_after_their_type() {
+    let output = hir("struct Counter = {
+    var count: int
+
+    proc bump = (self) {
+        self.count += 1
+    }
+}
+
+proc main = () {
+    var c = Counter.init(0)
+    const u = c.bump()
+}
+");
+    assert!(output.contains("struct Counter { count }\n"), "{}", output);
+    assert!(
+        output.contains("proc Counter.bump(self) = {\n    self.count = +(self.count, 1)\n}\n"),
+        "{}",
+        output
+    );
+    assert!(output.contains("    const u = c.bump()\n"), "{}", output);
+}
+
+#[test]
+fn both_engines_run_the_desugared_loops() {
+    let source = "proc main = () {
+    var n = 0
+    while n < 10 {
+        var i = 0
+        while i < n {
+            i += 1
+            if i == 2 {
+                break
+            }
+        } else {
+            println(n)
+            n += 1
+            continue
+        }
+        println(0 - n)
+        n += 5
+    }
+    var total = 0
+    for k, v in {\"a\": 1, \"b\": 2} {
+        total += v
+    }
+    println(total)
+}
+";
+    for engine in ["--engine=ast", "--engine=vm"] {
+        assert_eq!(
+            chop_ok(&["run", engine, "-"], source),
+            "0\n1\n-2\n-7\n3\n",
+            "{}",
+            engine
+        );
+    }
+}
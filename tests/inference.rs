@@ -0,0 +1,79 @@
+mod common;
+
+use common::{chop_ok, rejected};
+
+#[test]
+fn unannotated_definitions_get_their_principal_types() {
+    let source = "fn is_even = (a) -> a % 2 == 0
+
+fn fibonacci = (0) -> 0
+fn fibonacci = (1) -> 1
+fn fibonacci = (n) -> fibonacci(n - 1) + fibonacci(n - 2)
+
+fn identity = (x) -> x
+
+fn first = (a, b) -> a
+
+fn apply = (f, x) -> f(x)
+
+const pi = 3.141592
+
+const names = [\"a\", \"b\"]
+
+proc main = () {
+    println(is_even(fibonacci(5)))
+    println(first(identity(pi), identity(names)))
+}
+";
+    let types = chop_ok(&["check", "--emit=types", "-"], source);
+    for expected in [
+        "fn is_even: (a) -> bool where @eq a, @num a\n",
+        "fn fibonacci: (a) -> b where @num a, @eq a, @num b\n",
+        "fn identity: (a) -> a\n",
+        "fn first: (a, b) -> a\n",
+        "fn apply: ((a) -> b, a) -> b\n",
+        "const pi: float\n",
+        "const names: [string]\n",
+    ] {
+        assert!(types.contains(expected), "{}", types);
+    }
+}
+
+#[test]
+fn mismatches_point_at_both_sides() {
+    let stderr = rejected(
+        "fn describe = (0) -> \"zero\"
+fn describe = (n) -> n == 1
+
+const count: int = \"three\"
+
+proc main = () {
+    println(describe(2))
+}
+",
+    );
+    for expected in [
+        "-:2:22: error: mismatched types: expected `string`, found `bool`",
+        "note: -:1:22: expected `string` because of this",
+        "-:4:20: error: mismatched types: expected `int`, found `string`",
+        "note: -:4:1: expected `int` because of this",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 2, "{}", stderr);
+}
+
+#[test]
+fn infinite_types_fail_the_occurs_check() {
+    let stderr = rejected(
+        "fn self_apply = (f) -> f(f)
+
+proc main = () {}
+",
+    );
+    assert!(
+        stderr.contains("-:1:24: error: `a` would have to contain itself as `(a) -> b`"),
+        "{}",
+        stderr
+    );
+}
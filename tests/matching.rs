@@ -0,0 +1,91 @@
+mod common;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// Arms naming variants without fields, placed before the arms that
+/// would otherwise catch them.
+const SOURCE: &str = "enum shape = {
+    circle(float)
+    rectangle(float)
+    empty
+}
+
+enum option = {
+    type T
+    some(T)
+    none
+}
+
+fn size = (s: shape) -> match s {
+    empty -> 0.0
+    circle(r) -> r
+    _ -> 1.0
+}
+
+fn unwrap = (type T, o: option<T>, default: T) -> match o {
+    none -> default
+    some(x) -> x
+}
+
+fn area = (empty) -> 0.0
+fn area = (circle(r)) -> 3.0 * r * r
+fn area = (rectangle(w)) -> w * w
+
+proc main = () {
+    println([size(rectangle(2.0)), size(empty), size(circle(4.0))])
+    println([unwrap(some(2), 0), unwrap(none, 5)])
+    println([area(rectangle(2.0)), area(empty), area(circle(1.0))])
+}
+";
+
+const EXPECTED: &str = "[1.0, 0.0, 4.0]\n[2, 5]\n[4.0, 0.0, 3.0]\n";
+
+#[test]
+fn variants_without_fields_only_match_themselves() {
+    let file = scratch("matching-run").join("matching.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    assert_eq!(chop_ok(&["run", "--engine=ast", path(&file)], ""), EXPECTED);
+    for level in ["-O0", "-O1", "-O2"] {
+        assert_eq!(
+            chop_ok(&["run", level, path(&file)], ""),
+            EXPECTED,
+            "{}",
+            level
+        );
+    }
+}
+
+#[test]
+fn a_variant_arm_leaves_the_other_variants_uncovered() {
+    let source = "enum shape = {
+    circle(float)
+    rectangle(float)
+    empty
+}
+
+fn size = (s: shape) -> match s {
+    circle(r) -> r
+    empty -> 0.0
+}
+
+fn other = (s: shape) -> match s {
+    x -> 1.0
+    empty -> 0.0
+}
+
+proc main = () {
+    println(size(empty))
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":7:25: warning: this `match` does not cover every value",
+        "help: add an arm for `rectangle(_)`",
+        ":14:5: warning: this arm can never match",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("warning:").count(), 2, "{}", stderr);
+}
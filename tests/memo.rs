@@ -0,0 +1,160 @@
+mod common;
+
+use std::process::Command;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// A recursion that takes exponential time without its cache, a cache of
+/// two results whose evictions `impure` shows, and a struct that changes
+/// after a call on it was cached.
+const SOURCE: &str = "@memo fn fib = (0) -> 0
+fn fib = (1) -> 1
+fn fib = (n) -> fib(n - 1) + fib(n - 2)
+
+@memo(2) fn square = (n) -> match impure println(n) {
+    _ -> n * n
+}
+
+struct Counter = {
+    var count: int
+
+    proc bump = (self) {
+        self.count += 1
+    }
+
+    @memo fn tens = (self) -> match impure println(self.count) {
+        _ -> self.count * 10
+    }
+
+    typeclass @eq = {}
+    typeclass @hash = {}
+}
+
+proc main = () {
+    println(fib(90))
+    println([square(3), square(3), square(4), square(5), square(3), square(4)])
+    var c = Counter.init(2)
+    const a = c.tens()
+    const u = c.bump()
+    println([a, c.tens(), Counter.init(2).tens()])
+}
+";
+
+/// `square(3)` is cached, `square(4)` misses, `square(5)` takes the slot of
+/// `square(3)`, which misses again and takes its slot back. The counter is
+/// looked up by the count it had when it was cached.
+const EXPECTED: &str = "2880067194370816120\n3\n4\n5\n3\n[9, 9, 16, 25, 9, 16]\n\
+                        2\n3\n[20, 30, 20]\n";
+
+fn source(name: &str) -> String {
+    let file = scratch(name).join("memo.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    path(&file).to_string()
+}
+
+#[test]
+fn the_interpreter_and_the_virtual_machine_cache_alike() {
+    let source = source("memo-run");
+    assert_eq!(chop_ok(&["run", "--engine=ast", &source], ""), EXPECTED);
+    for level in ["-O0", "-O1", "-O2"] {
+        assert_eq!(chop_ok(&["run", level, &source], ""), EXPECTED, "{}", level);
+    }
+}
+
+#[test]
+fn compiled_bytecode_keeps_its_caches() {
+    let source = source("memo-chopc");
+    let compiled = scratch("memo-chopc").join("memo.chopc");
+    chop_ok(
+        &["compile", &format!("--output={}", path(&compiled)), &source],
+        "",
+    );
+    assert!(chop_ok(&["disasm", path(&compiled)], "").contains("caches 2 results"));
+    assert_eq!(chop_ok(&["run", path(&compiled)], ""), EXPECTED);
+}
+
+#[test]
+fn c_executables_cache_alike() {
+    let source = source("memo-c");
+    let executable = scratch("memo-c").join("memo");
+    chop_ok(
+        &[
+            "build",
+            "--target=c",
+            &format!("--output={}", path(&executable)),
+            &source,
+        ],
+        "",
+    );
+    let output = Command::new(&executable)
+        .output()
+        .expect("the built program runs");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), EXPECTED);
+}
+
+#[test]
+fn memo_only_applies_to_module_level_fns() {
+    let source = "fn twice = (x) -> x * 2
+@memo fn twice = (x) -> x * 3
+
+@memo(0) fn zero = (x) -> x
+
+@memo proc say = () {
+    println(1)
+}
+
+@memo fn half = (x: float) -> x / 2.0
+
+proc main = () {
+    @memo fn local = (n) -> n
+    println(twice(zero(local(1))))
+    say()
+    println(half(1.0))
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":2:1: error: `@memo` goes on the first clause of `twice`",
+        ":4:1: error: the cache of `zero` cannot hold 0 results",
+        ":6:1: error: `@memo` only applies to `fn`s",
+        ":10:1: error: no instance of `@hash` for `float`",
+        ":13:5: error: `@memo` only applies to module level and member `fn`s",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 5, "{}", stderr);
+}
+
+#[test]
+fn targets_without_caches_report_each_memo_once() {
+    let source = source("memo-unsupported");
+    let output = scratch("memo-unsupported").join("memo.out");
+    for target in ["wasm", "x86-64"] {
+        let output = chop(
+            &[
+                "build",
+                &format!("--target={}", target),
+                &format!("--output={}", path(&output)),
+                &source,
+            ],
+            "",
+        );
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = format!(
+            "error: the {} target does not support `@memo` caches yet",
+            target
+        );
+        for position in [":1:1: ", ":5:1: ", ":16:5: "] {
+            assert!(
+                stderr.contains(&format!("{}{}", position, message)),
+                "{}",
+                stderr
+            );
+        }
+        assert_eq!(stderr.matches(&message).count(), 3, "{}", stderr);
+    }
+}
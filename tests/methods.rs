@@ -0,0 +1,193 @@
+mod common;
+
+use std::process::Command;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// Member functions with and without `self`, a typeclass method called on
+/// a struct, `init`, and members called through their type.
+const SOURCE: &str = "typeclass @size = {
+    fn size: Self -> int
+}
+
+struct Stack = {
+    var count: int
+    var top: int
+
+    fn peek = (self) -> self.top
+
+    fn push = (self, n: int) -> Stack.init(self.count + 1, n)
+
+    fn empty = () -> Stack.init(0, 0)
+
+    typeclass @size = {
+        fn size = (self) -> self.count
+    }
+}
+
+fn depth = (s: Stack) -> s.size() + 1
+
+proc main = () {
+    const s = Stack.empty().push(3).push(7)
+    println(s.peek())
+    println(s.size())
+    println(Stack.peek(Stack.push(s, 9)))
+    println(depth(s))
+}
+";
+
+const EXPECTED: &str = "7\n2\n9\n3\n";
+
+#[test]
+fn every_engine_calls_methods_alike() {
+    let file = scratch("methods-run").join("methods.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    assert_eq!(chop_ok(&["run", "--engine=ast", path(&file)], ""), EXPECTED);
+    for level in ["-O0", "-O2"] {
+        assert_eq!(
+            chop_ok(&["run", level, path(&file)], ""),
+            EXPECTED,
+            "{}",
+            level
+        );
+    }
+}
+
+#[test]
+fn method_calls_are_checked_against_the_receiver_type() {
+    let source = "typeclass @size = {
+    fn size: Self -> int
+}
+
+typeclass @count = {
+    fn size: Self -> int
+}
+
+struct Box = {
+    var value: int
+    var f: (int) -> int
+
+    fn make = (n: int) -> Box.init(n, (x) -> x)
+
+    fn get = (self) -> self.value
+
+    typeclass @size = {
+        fn size = (self) -> 1
+    }
+
+    typeclass @count = {
+        fn size = (self) -> 1
+    }
+}
+
+fn first = (b) -> b.get()
+
+proc main = () {
+    const b = Box.make(1)
+    println(b.size())
+    println(b.make(2))
+    println(b.missing())
+    println(b.f(2))
+    println(Box.init(1))
+    println(Box.peek(b))
+    println(b.get(1))
+    println(b.get() + \"a\")
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":26:19: error: the type of this value must be known to call its method `get`",
+        ":30:13: error: `Box` has more than one method `size`",
+        ":18:9: `@size` defines one here",
+        ":22:9: `@count` defines one here",
+        ":31:13: error: `make` takes no `self`, so it is not a method",
+        "help: call it as `Box.make(..)`",
+        ":32:13: error: a value of type `Box` has no method `missing`",
+        "help: `f` is a field; bind it to a name to call the function it holds",
+        ":34:13: error: `Box.init` takes 2 fields, but 1 were given",
+        ":35:13: error: `Box` has no function `peek`",
+        ":36:13: error: `get` takes 1 arguments, but 2 were given",
+        ":37:23: error: mismatched types: expected `int`, found `string`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 9, "{}", stderr);
+}
+
+#[test]
+fn proc_methods_are_held_to_purity_and_mutability() {
+    let source = "struct Counter = {
+    var x: int
+
+    proc bump = (self) {
+        println(self.x)
+        self.x += 1
+    }
+}
+
+fn sneaky = (c: Counter) -> c.bump()
+
+proc main = () {
+    const q = Counter.init(1)
+    const u = q.bump()
+    const w = Counter.bump(q)
+    var v = Counter.init(1)
+    const t = v.bump()
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":10:29: error: the `fn` `sneaky` cannot call the `proc` `bump`",
+        ":14:15: error: cannot call the `proc` `bump` on `q`, which is a const",
+        ":15:15: error: cannot call the `proc` `bump` on `q`, which is a const",
+        ":10:29: error: cannot call the `proc` `bump` on `c`, which is a parameter",
+        "help: only a `proc` may change the fields of its parameters",
+        ":13:5: `q` is declared here",
+        "help: declare it with `var` to allow changing its fields",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 4, "{}", stderr);
+}
+
+#[test]
+fn a_method_call_is_a_statement() {
+    let source = "struct Counter = {
+    var x: int
+
+    proc bump = (self) {
+        self.x += 1
+    }
+}
+
+proc main = () {
+    var c = Counter.init(1)
+    c.bump()
+    Counter.bump(c)
+    println(c.x)
+}
+";
+    let dir = scratch("methods-statement");
+    let file = dir.join("counter.chop");
+    std::fs::write(&file, source).expect("source");
+    assert_eq!(chop_ok(&["run", "--engine=ast", path(&file)], ""), "3\n");
+    assert_eq!(chop_ok(&["run", path(&file)], ""), "3\n");
+    let executable = dir.join("counter");
+    chop_ok(
+        &[
+            "build",
+            &format!("--output={}", path(&executable)),
+            path(&file),
+        ],
+        "",
+    );
+    let output = Command::new(&executable)
+        .output()
+        .expect("the built program runs");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+    assert_eq!(chop_ok(&["fmt", "-"], source), source);
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::{chop_ok, rejected};
+
+#[test]
+fn a_const_cannot_be_assigned() {
+    let stderr = rejected(
+        "proc main = () {
+    const limit = 3
+    limit = 4
+    for i in [1, 2] {
+        i = 3
+    }
+    println(limit)
+}
+",
+    );
+    for expected in [
+        "-:3:5: error: cannot assign to `limit`, which is a const",
+        "note: -:2:5: `limit` is declared here",
+        "help: declare it with `var` to allow assigning to it",
+        "-:5:9: error: cannot assign to `i`, which is a loop variable",
+        "help: copy it into a `var` first",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn the_fields_of_a_const_cannot_be_assigned() {
+    let stderr = rejected(
+        "struct Point = {
+    var x: int
+    var y: int
+}
+
+struct Line = {
+    var from: Point
+    var to: Point
+}
+
+const origin = Point.init(0, 0)
+
+proc main = () {
+    const q = Point.init(1, 2)
+    q.y = 6
+    origin.x = 1
+    const line = Line.init(q, origin)
+    line.to.x = 3
+    println(q.y)
+}
+",
+    );
+    for expected in [
+        "-:15:5: error: cannot assign to a field of `q`, which is a const",
+        "note: -:14:5: `q` is declared here",
+        "help: declare it with `var` to allow changing its fields",
+        "-:16:5: error: cannot assign to a field of `origin`, which is a const",
+        "note: -:11:1: `origin` is declared here",
+        "-:18:5: error: cannot assign to a field of `line`, which is a const",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn a_var_and_the_fields_of_a_proc_parameter_can_be_assigned() {
+    let source = "struct Point = {
+    var x: int
+    var y: int
+}
+
+proc shift = (p: Point) {
+    p.x = p.x + 10
+}
+
+proc main = () {
+    var count = 1
+    count = count + 1
+    var p = Point.init(1, 2)
+    p.y = 5
+    shift(p)
+    println([count, p.x, p.y])
+}
+";
+    assert_eq!(chop_ok(&["run", "-"], source), "[2, 11, 5]\n");
+}
+
+#[test]
+fn a_const_and_a_var_cannot_share_a_struct() {
+    let stderr = rejected(
+        "struct P = {
+    var x: int
+}
+
+struct Box = {
+    var p: P
+}
+
+proc main = () {
+    var c = P.init(1)
+    const a = c
+    var b = Box.init(P.init(2))
+    const q = b.p
+    const l = [c]
+    var d = P.init(3)
+    d = a
+    const e = P { ..c }
+    const n = b.p.x
+    c.x = 5
+    println([a.x, q.x, e.x, n])
+    println(l)
+}
+",
+    );
+    for expected in [
+        "-:11:15: error: the const `a` cannot share a struct with the var `c`",
+        "note: -:10:5: `c` is declared here",
+        "help: copy it instead, as in `P { ..c }`",
+        "-:13:15: error: the const `q` cannot share a struct with the var `b`",
+        "help: copy it instead, as in `P { ..b.p }`",
+        "-:14:16: error: the const `l` cannot share a struct with the var `c`",
+        "-:16:9: error: the var `d` cannot share a struct with the const `a`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 4, "{}", stderr);
+}
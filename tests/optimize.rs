@@ -0,0 +1,137 @@
+mod common;
+
+use common::{chop, chop_ok, path, scratch, PROGRAMS};
+
+const SOURCE: &str = "const limit = 3
+
+fn square = (x) -> x * x
+
+fn zero = (x) -> x / 0
+
+proc main = () {
+    var a = 1 + 2
+    println(square(a) + limit)
+    var i = 0
+    while i < limit {
+        println(i)
+        i += 1
+    }
+    println(zero(4))
+}
+";
+
+/// Writes `SOURCE` into a fresh directory and returns its path.
+fn source(name: &str) -> String {
+    let file = scratch(name).join("optimize.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    path(&file).to_string()
+}
+
+#[test]
+fn optimized_programs_print_what_the_interpreter_prints() {
+    for program in PROGRAMS.iter().chain(["tests/fixtures/wasm.chop"].iter()) {
+        let expected = chop_ok(&["run", "--engine=ast", program], "");
+        for level in ["-O1", "-O2"] {
+            assert_eq!(
+                chop_ok(&["run", level, program], ""),
+                expected,
+                "{} prints something else at {}",
+                program,
+                level
+            );
+        }
+    }
+}
+
+#[test]
+fn runtime_errors_are_not_folded_away() {
+    let source = source("optimize-errors");
+    let unoptimized = chop(&["run", &source], "");
+    for level in ["-O1", "-O2"] {
+        let output = chop(&["run", level, &source], "");
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, unoptimized.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(":5:18: error: division by zero"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn emitted_ir_has_constants_and_consts_folded() {
+    let source = source("optimize-ir");
+    let ir = chop_ok(&["build", "--emit=ir", "-O1", &source], "");
+    let main = &ir[ir.find("fn #3 main()").expect("main is printed")..];
+    assert!(
+        main.contains(concat!(
+            "b0:\n",
+            "    %5: int = Constant(0)  ; 3\n",
+            "    %6: any = CallFunction(1, 1) %5  ; square\n",
+            "    %7: int = Constant(0)  ; 3\n",
+            "    %8: any = Add %6, %7\n",
+        )),
+        "{}",
+        main
+    );
+    assert!(
+        main.contains("%1: int = phi [b0: %10], [b2: %19]"),
+        "{}",
+        main
+    );
+    assert!(!main.contains("Global"), "{}", main);
+    let unoptimized = chop_ok(&["build", "--emit=ir", &source], "");
+    assert!(unoptimized.contains("Global"), "{}", unoptimized);
+}
+
+#[test]
+fn small_functions_are_inlined_at_o2() {
+    let source = source("optimize-inline");
+    let calls = |level: &str| {
+        chop_ok(&["disasm", level, &source], "")
+            .lines()
+            .filter(|line| line.contains("CallFunction"))
+            .count()
+    };
+    assert_eq!(calls("-O1"), 2);
+    assert_eq!(calls("-O2"), 0);
+}
+
+#[test]
+fn optimized_bytecode_survives_chopc_files_and_backends() {
+    let dir = scratch("optimize-backends");
+    let program = "examples/fibonacci.chop";
+    let expected = chop_ok(&["run", program], "");
+    let chopc = dir.join("fibonacci.chopc");
+    chop_ok(
+        &[
+            "compile",
+            "-O2",
+            &format!("--output={}", path(&chopc)),
+            program,
+        ],
+        "",
+    );
+    assert_eq!(chop_ok(&["run", path(&chopc)], ""), expected);
+    let wasm = dir.join("fibonacci.wasm");
+    chop_ok(
+        &[
+            "build",
+            "-O2",
+            "--target=wasm",
+            &format!("--output={}", path(&wasm)),
+            program,
+        ],
+        "",
+    );
+    assert_eq!(chop_ok(&["run", path(&wasm)], ""), expected);
+}
+
+#[test]
+fn unknown_optimization_levels_are_rejected() {
+    let output = chop(&["run", "-O7", "examples/hello_world.chop"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown optimization level '7'"));
+}
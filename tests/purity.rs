@@ -0,0 +1,152 @@
+mod common;
+
+use common::{chop_ok, rejected};
+
+#[test]
+fn a_fn_cannot_call_a_proc() {
+    let stderr = rejected(
+        "proc shout = (x) {
+    println(x)
+}
+
+fn loud = (x) -> shout(x)
+
+fn hidden = (x) -> [1, 2].map((y) -> println(y))
+
+proc main = () {
+    println(loud(1))
+}
+",
+    );
+    for expected in [
+        "-:5:18: error: the `fn` `loud` cannot call the `proc` `shout`",
+        "help: make `loud` a `proc`, or write `impure shout(..)` to allow this one call",
+        "-:7:38: error: the `fn` `hidden` cannot call the `proc` `println`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn diagnostics_point_at_the_call_and_the_fn() {
+    let stderr = rejected(
+        "fn loud = (x) -> println(x)
+
+proc main = () {
+    println(loud(1))
+}
+",
+    );
+    assert!(
+        stderr.contains("-:1:18: error: the `fn` `loud` cannot call the `proc` `println`"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("note: -:1:1: `loud` is a `fn`, so it cannot have side effects"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("1 | fn loud = (x) -> println(x)\n  |                  ^^^^^^^^^^"));
+    assert!(stderr.contains("1 | fn loud = (x) -> println(x)\n  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^"));
+}
+
+#[test]
+fn a_fn_cannot_use_a_proc_as_a_value() {
+    let stderr = rejected(
+        "fn apply = (f, x) -> f(x)
+
+fn sneaky = (x) -> apply(println, x)
+
+proc main = () {
+    println(sneaky(1))
+}
+",
+    );
+    for expected in [
+        "-:3:26: error: the `fn` `sneaky` cannot use the `proc` `println` as a value",
+        "note: -:3:1: `sneaky` is a `fn`, so it cannot have side effects",
+        "help: make `sneaky` a `proc`, or write `impure println` to allow this one use",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+/// A `fn` has no statements, so the only way it could assign to a `var`
+/// is by calling a `proc` that does.
+#[test]
+fn a_fn_cannot_assign_to_a_var() {
+    let stderr = rejected("var total = 0\n\nfn tally = (x) -> total = x\n");
+    assert!(
+        stderr.contains("-:3:25: Expected a declaration, found '='"),
+        "{}",
+        stderr
+    );
+
+    let stderr = rejected(
+        "var total = 0
+
+proc record = (x) {
+    total = x
+}
+
+fn tally = (x) -> record(x)
+
+proc main = () {
+    println(tally(1))
+}
+",
+    );
+    assert!(
+        stderr.contains("-:7:19: error: the `fn` `tally` cannot call the `proc` `record`"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn a_fn_cannot_be_passed_a_proc() {
+    let stderr = rejected(
+        "fn apply = (f, x) -> f(x)
+
+fn double = (x) -> x * 2
+
+proc main = () {
+    apply(println, 3)
+    const p = println
+    apply(p, 4)
+    apply((x) -> println(x), 5)
+    println(apply(double, 6))
+    println(apply((x) -> x + 1, 7))
+    apply(impure println, 8)
+}
+",
+    );
+    for expected in [
+        "-:6:11: error: the `fn` `apply` cannot be passed the `proc` `println`",
+        "note: -:1:1: `apply` is a `fn`, so it cannot have side effects",
+        "help: make `apply` a `proc`, or write `impure println` to allow this one use",
+        "-:8:11: error: the `fn` `apply` cannot be passed `p`, which holds a `proc`",
+        "-:9:11: error: the `fn` `apply` cannot be passed a closure with side effects",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 3, "{}", stderr);
+}
+
+#[test]
+fn impure_allows_one_call_or_use() {
+    let source = "fn apply = (f, x) -> f(x)
+
+fn shown = (x) -> impure println(x)
+
+fn passed = (x) -> apply(impure println, x)
+
+proc main = () {
+    shown(1)
+    passed(2)
+}
+";
+    assert_eq!(chop_ok(&["check", "-"], source), "");
+    assert_eq!(chop_ok(&["run", "-"], source), "1\n2\n");
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{chop_ok, rejected};
+
+#[test]
+fn undefined_names_suggest_a_close_one() {
+    let stderr = rejected(
+        "fn fibonacci = (n) -> n
+
+struct Point = {
+    var x: int
+}
+
+proc main = () {
+    println(fibonaci(3))
+    const p: Piont = Point.init(1)
+    printn(p)
+    println(nothing_like_it)
+}
+",
+    );
+    for expected in [
+        "-:8:13: error: undefined name `fibonaci`\n",
+        "help: did you mean `fibonacci`?",
+        "-:9:5: error: undefined type `Piont`\n",
+        "help: did you mean `Point`?",
+        "-:10:5: error: undefined name `printn`\n",
+        "help: did you mean `print`?",
+        "-:11:13: error: undefined name `nothing_like_it`\n",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("help:").count(), 3, "{}", stderr);
+}
+
+#[test]
+fn duplicates_are_reported_once_with_the_first_definition() {
+    let stderr = rejected(
+        "fn pair = (a, a) -> a
+
+struct Point = {
+    var x: int
+}
+
+enum Point = {
+    origin
+}
+
+proc main = () {
+    const x = 1
+    const x = 2
+}
+",
+    );
+    for expected in [
+        "-:1:15: error: `a` is defined more than once",
+        "note: -:1:12: `a` was first defined here",
+        "-:7:1: error: `Point` is defined more than once",
+        "note: -:3:1: `Point` was first defined here",
+        "-:13:5: error: `x` is defined more than once",
+        "note: -:12:5: `x` was first defined here",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 3, "{}", stderr);
+}
+
+#[test]
+fn locals_cannot_shadow_locals_of_the_same_function() {
+    let stderr = rejected(
+        "fn outer = (x) -> [1].map((x) -> x)
+
+proc main = () {
+    const y = 1
+    for i in [1, 2] {
+        const y = i
+    }
+}
+",
+    );
+    for expected in [
+        "-:1:28: error: `x` shadows the parameter of the same name",
+        "note: -:1:13: `x` is defined here",
+        "-:6:9: error: `y` shadows the const of the same name",
+        "note: -:4:5: `y` is defined here",
+        "help: rename one of the two",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+/// Module level names may be hidden by parameters and locals, and clauses
+/// of one function are not duplicates.
+#[test]
+fn locals_may_shadow_module_level_names() {
+    let source = "const n = 1
+
+fn twice = (0) -> 0
+fn twice = (n) -> n * 2
+
+proc main = () {
+    const twice_n = twice(n)
+    println(twice_n)
+}
+";
+    assert_eq!(chop_ok(&["check", "-"], source), "");
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::{chop, chop_ok};
+
+const ENGINES: &[&str] = &["--engine=ast", "--engine=vm"];
+
+#[test]
+fn runs_hello_world() {
+    for engine in ENGINES {
+        assert_eq!(
+            chop_ok(&["run", engine, "examples/hello_world.chop"], ""),
+            "Hello World\n",
+            "{}",
+            engine
+        );
+    }
+}
+
+#[test]
+fn runs_fibonacci() {
+    for engine in ENGINES {
+        assert_eq!(
+            chop_ok(&["run", engine, "examples/fibonacci.chop"], ""),
+            "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n",
+            "{}",
+            engine
+        );
+    }
+}
+
+#[test]
+fn runtime_errors_stop_the_program() {
+    let source = "proc main = () {\n    println(1)\n    println(1 / 0)\n    println(2)\n}\n";
+    for engine in ENGINES {
+        let output = chop(&["run", engine, "-"], source);
+        assert!(!output.status.success(), "{}", engine);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n", "{}", engine);
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("division by zero"),
+            "{}",
+            engine
+        );
+    }
+}
+
+#[test]
+fn non_ascii_strings_are_printed_as_written() {
+    let source = "proc main = () {\n    println([\"日本\", x])\n}\n";
+    let output = chop(&["run", "-"], source);
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("-:2:20: error: undefined name `x`\n  |\n2 |     println([\"日本\", x])\n  |                    ^\n"));
+    let source = "proc main = () {\n    // ça va\n    println(\"é ünï 日本\")\n}\n";
+    for engine in ENGINES {
+        assert_eq!(chop_ok(&["run", engine, "-"], source), "é ünï 日本\n");
+    }
+    assert_eq!(chop_ok(&["fmt", "-"], source), source);
+}
+
+#[test]
+fn columns_past_the_last_one_a_position_holds_are_reported_at_it() {
+    let source = format!(
+        "proc main = () {{\n    println(\"{}\" $ 1)\n}}\n",
+        "a".repeat(70000)
+    );
+    let output = chop(&["run", "-"], &source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("-:2:65535: Unexpected atom: $"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use std::process::Command;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// Fields given out of order and by shorthand, a copy with one field
+/// changed, and a struct literal next to the block of an `if`.
+const SOURCE: &str = "struct ArrayList = {
+    var arr: [int]
+    var len: int
+    var cap: int
+
+    fn grown = (self) -> ArrayList { ..self, cap: self.cap * 2 }
+}
+
+proc main = () {
+    const len = 2
+    const a = ArrayList { arr: [1, 2], len, cap: 4 }
+    const b = ArrayList { cap: 8, len: 0, arr: [] }
+    const c = a.grown()
+    const d = ArrayList { ..c, len: match impure println(len) {
+        _ -> 5
+    } }
+    println([a.len, a.cap, b.cap, b.len, c.cap, c.len, d.len])
+    println(d.arr)
+    if a.len == len {
+        println(b.arr)
+    }
+}
+";
+
+const EXPECTED: &str = "2\n[2, 4, 8, 0, 8, 2, 5]\n[1, 2]\n[]\n";
+
+fn source(name: &str) -> String {
+    let file = scratch(name).join("structs.chop");
+    std::fs::write(&file, SOURCE).expect("source");
+    path(&file).to_string()
+}
+
+#[test]
+fn every_engine_builds_structs_from_named_fields() {
+    let source = source("structs-run");
+    assert_eq!(chop_ok(&["run", "--engine=ast", &source], ""), EXPECTED);
+    for level in ["-O0", "-O1", "-O2"] {
+        assert_eq!(chop_ok(&["run", level, &source], ""), EXPECTED, "{}", level);
+    }
+}
+
+#[test]
+fn c_executables_build_structs_from_named_fields() {
+    let source = source("structs-c");
+    let executable = scratch("structs-c").join("structs");
+    chop_ok(
+        &[
+            "build",
+            "--target=c",
+            &format!("--output={}", path(&executable)),
+            &source,
+        ],
+        "",
+    );
+    let output = Command::new(&executable)
+        .output()
+        .expect("the built program runs");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), EXPECTED);
+}
+
+#[test]
+fn struct_literals_give_every_field_once() {
+    let source = "struct Point = {
+    var x: int
+    var y: int
+}
+
+enum Shape = {
+    Dot
+}
+
+proc main = () {
+    const p = Point { x: 1 }
+    const q = Point {}
+    const r = Point { x: 1, y: 2, z: 3 }
+    const s = Point { x: 1, x: 2, y: 3 }
+    const t = Point { ..p, x: \"a\" }
+    const u = Shape { x: 1 }
+    const v = Point { ..p }
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        ":11:15: error: this `Point` is missing its field `y`",
+        ":12:15: error: this `Point` is missing its fields `x`, `y`",
+        ":13:35: error: `Point` has no field `z`",
+        ":14:29: error: the field `x` is given more than once",
+        ":14:23: it is first given here",
+        ":15:31: error: mismatched types: expected `int`, found `string`",
+        ":16:15: error: `Shape` is not a struct",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 6, "{}", stderr);
+}
+
+#[test]
+fn the_base_of_a_struct_literal_comes_first() {
+    let output = chop(
+        &["check", "-"],
+        "proc main = () {\n    const p = P { x: 1, ..q }\n}\n",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(":2:25: A struct literal's `..base` goes before its fields"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn struct_literals_are_formatted_with_shorthand_fields() {
+    let source = "proc main = () {\n    const p = Point{ ..q,x:x,y : 1 }\n}\n";
+    assert_eq!(
+        chop_ok(&["fmt", "-"], source),
+        "proc main = () {\n    const p = Point { ..q, x, y: 1 }\n}\n"
+    );
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use std::process::Command;
+
+use common::{chop, chop_ok, path, scratch};
+
+/// Self, mutual and method tail recursion `DEPTH` calls deep, which is
+/// more than calls may nest.
+const SOURCE: &str = "fn count = (0, acc) -> acc
+fn count = (n, acc) -> @tail count(n - 1, acc + 1)
+
+fn is_even = (0) -> true
+fn is_even = (n) -> is_odd(n - 1)
+
+fn is_odd = (0) -> false
+fn is_odd = (n) -> match n {
+    _ -> @tail is_even(n - 1)
+}
+
+struct Countdown = {
+    var from: int
+
+    fn run = (self, 0) -> self.from
+    fn run = (self, n) -> self.run(n - 1)
+}
+
+proc main = () {
+    println(count(DEPTH, 0))
+    println(is_even(DEPTH + 1))
+    const c = Countdown.init(7)
+    println(c.run(DEPTH))
+}
+";
+
+/// Writes `SOURCE` recursing `depth` deep into a fresh directory.
+fn source(name: &str, depth: u32) -> String {
+    let file = scratch(name).join("tailcalls.chop");
+    let text = SOURCE.replace("DEPTH", &depth.to_string());
+    std::fs::write(&file, text).expect("source");
+    path(&file).to_string()
+}
+
+fn expected(depth: u32) -> String {
+    format!("{}\n{}\n7\n", depth, depth % 2 == 1)
+}
+
+#[test]
+fn the_virtual_machine_recurses_a_million_times_in_tail_position() {
+    let source = source("tailcalls-vm", 1_000_000);
+    for level in ["-O0", "-O1", "-O2"] {
+        assert_eq!(
+            chop_ok(&["run", level, &source], ""),
+            expected(1_000_000),
+            "{}",
+            level
+        );
+    }
+}
+
+/// The tree-walking interpreter is slow without optimizations, so this
+/// only goes twice as deep as calls may nest.
+#[test]
+fn the_interpreter_recurses_in_tail_position() {
+    let source = source("tailcalls-ast", 200_000);
+    assert_eq!(
+        chop_ok(&["run", "--engine=ast", &source], ""),
+        expected(200_000)
+    );
+}
+
+#[test]
+fn native_executables_recurse_a_million_times_in_tail_position() {
+    let source = source("tailcalls-native", 1_000_000);
+    let dir = scratch("tailcalls-native");
+    for target in ["c", "x86-64"] {
+        let executable = dir.join(target);
+        chop_ok(
+            &[
+                "build",
+                &format!("--target={}", target),
+                &format!("--output={}", path(&executable)),
+                &source,
+            ],
+            "",
+        );
+        let output = Command::new(&executable)
+            .output()
+            .expect("the built program runs");
+        assert!(output.status.success(), "{} failed", target);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            expected(1_000_000),
+            "{}",
+            target
+        );
+    }
+}
+
+/// The in-tree WebAssembly interpreter is slow without optimizations, so
+/// this only goes twice as deep as calls may nest.
+#[test]
+fn webassembly_tail_calls_become_return_calls() {
+    let source = source("tailcalls-wasm", 200_000);
+    let module = scratch("tailcalls-wasm").join("tailcalls.wasm");
+    chop_ok(
+        &[
+            "build",
+            "--target=wasm",
+            &format!("--output={}", path(&module)),
+            &source,
+        ],
+        "",
+    );
+    assert_eq!(chop_ok(&["run", path(&module)], ""), expected(200_000));
+}
+
+#[test]
+fn errors_in_tail_calls_point_at_the_last_call() {
+    let source = "fn half = (0) -> 0\nfn half = (n where n > 1) -> half(n - 2)\n\n\
+                  proc main = () {\n    println(half(5))\n}\n";
+    let ast = chop(&["run", "--engine=ast", "-"], source);
+    let vm = chop(&["run", "-"], source);
+    assert!(!vm.status.success());
+    let stderr = String::from_utf8_lossy(&vm.stderr);
+    assert_eq!(stderr, String::from_utf8_lossy(&ast.stderr));
+    assert!(
+        stderr.contains(":2:30: error: no clause of `half` matches the arguments (1)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn calls_marked_tail_must_be_in_tail_position() {
+    let source = "fn count = (0) -> 0
+fn count = (n) -> 1 + @tail count(n - 1)
+
+fn same = (n) -> @tail n
+
+proc main = () {
+    println(@tail count(3))
+    return @tail count(2)
+}
+";
+    let output = chop(&["check", "-"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(":2:23: error: this call to `count` is not in tail position"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(":4:18: error: `@tail` only applies to calls"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(":7:13: error: this call to `count` is not in tail position"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error:").count(), 3, "{}", stderr);
+}
@@ -0,0 +1,272 @@
+mod common;
+
+use std::process::Command;
+
+use common::{chop_ok, path, rejected, scratch};
+
+#[test]
+fn a_missing_instance_is_reported_where_it_is_needed() {
+    let stderr = rejected(
+        "typeclass @describe = {
+    fn name: Self -> string
+}
+
+fn label = (x: @describe) -> name(x)
+
+proc main = () {
+    println(label(\"cat\"))
+    println([1, 2] + [\"a\"])
+}
+",
+    );
+    for expected in [
+        "-:8:13: error: no instance of `@describe` for `string`",
+        "-:9:14: error: no instance of `@num` for `string`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn a_missing_instance_is_reported_once_for_an_operator_and_its_literals() {
+    let stderr = rejected(
+        "type UserId = distinct int
+
+proc main = () {
+    println(\"a\" + 1)
+    const u = UserId(3)
+    println(u + 1)
+}
+",
+    );
+    for expected in [
+        "-:4:13: error: no instance of `@num` for `string`",
+        "-:6:13: error: no instance of `@num` for `UserId`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error:").count(), 2, "{}", stderr);
+}
+
+#[test]
+fn overlapping_instances_are_rejected() {
+    let stderr = rejected(
+        "typeclass @describe = {
+    fn name: Self -> string
+}
+
+typeclass @describe: [int] = {
+    fn name = (self) -> \"ints\"
+}
+
+typeclass @describe: [a] = {
+    type a
+    fn name = (self) -> \"list\"
+}
+
+proc main = () {
+    println(name([1]))
+}
+",
+    );
+    assert!(
+        stderr.contains("-:9:1: error: overlapping instances of `@describe` for `[a]` and `[int]`"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("note: -:5:1: the other instance is here"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn an_instance_must_implement_every_method_without_a_default() {
+    let stderr = rejected(
+        "typeclass @describe = {
+    fn name: Self -> string
+    fn describe = (self) -> [self.name()]
+}
+
+struct Cat = {
+    var lives: int
+
+    typeclass @describe = {
+        fn nmae = (self) -> \"cat\"
+    }
+}
+
+proc main = () {
+    println(Cat.init(9).lives)
+}
+",
+    );
+    for expected in [
+        "-:9:5: error: the instance of `@describe` for `Cat` does not implement `name`",
+        "note: -:2:5: `name` is declared here",
+        "-:10:9: error: `nmae` is not a method of `@describe`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert!(!stderr.contains("`describe`"), "{}", stderr);
+}
+
+#[test]
+fn default_methods_run_unless_an_instance_overrides_them() {
+    let source = "typeclass @describe = {
+    fn name: Self -> string
+    fn describe = (self) -> [self.name(), \"!\"]
+}
+
+struct Cat = {
+    var lives: int
+
+    typeclass @describe = {
+        fn name = (self) -> \"cat\"
+    }
+}
+
+struct Dog = {
+    var age: int
+
+    typeclass @describe = {
+        fn name = (self) -> \"dog\"
+        fn describe = (self) -> [\"the dog\"]
+    }
+}
+
+typeclass @describe: int = {
+    fn name = (self) -> \"int\"
+}
+
+proc main = () {
+    println(Cat.init(9).describe())
+    println(Dog.init(3).describe())
+    const n: int = 7
+    println(n.describe())
+}
+";
+    let expected = "[\"cat\", \"!\"]\n[\"the dog\"]\n[\"int\", \"!\"]\n";
+    let dir = scratch("typeclasses-defaults");
+    let file = dir.join("defaults.chop");
+    std::fs::write(&file, source).expect("source");
+    assert_eq!(chop_ok(&["run", "--engine=ast", path(&file)], ""), expected);
+    assert_eq!(chop_ok(&["run", path(&file)], ""), expected);
+    let executable = dir.join("defaults");
+    chop_ok(
+        &[
+            "build",
+            &format!("--output={}", path(&executable)),
+            path(&file),
+        ],
+        "",
+    );
+    let output = Command::new(&executable)
+        .output()
+        .expect("the built program runs");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn methods_called_by_name_run_the_instance_of_their_first_argument() {
+    let source = "typeclass @describe = {
+    fn size: Self -> int
+    fn twice = (self) -> size(self) * 2
+}
+
+struct Box = {
+    var width: int
+
+    typeclass @describe = {
+        fn size = (self) -> self.width
+    }
+}
+
+struct Crate = {
+    var depth: int
+
+    typeclass @describe = {
+        fn size = (self) -> self.depth
+        fn twice = (self) -> 0
+    }
+}
+
+typeclass @describe: int = {
+    fn size = (self) -> self * 10
+}
+
+fn describe = (type T: @describe, v: T) -> size(v) + 1
+
+proc main = () {
+    const n: int = 3
+    println([describe(Box.init(4)), describe(n), size(n)])
+    println([twice(Box.init(5)), twice(Crate.init(5)), twice(n)])
+}
+";
+    let expected = "[5, 31, 30]\n[10, 0, 60]\n";
+    let dir = scratch("typeclasses-by-name");
+    let file = dir.join("by_name.chop");
+    std::fs::write(&file, source).expect("source");
+    assert_eq!(chop_ok(&["run", "--engine=ast", path(&file)], ""), expected);
+    assert_eq!(chop_ok(&["run", path(&file)], ""), expected);
+    for target in ["c", "x86-64", "wasm"] {
+        let output = match target {
+            "wasm" => dir.join("by_name.wasm"),
+            _ => dir.join(target),
+        };
+        chop_ok(
+            &[
+                "build",
+                &format!("--target={}", target),
+                &format!("--output={}", path(&output)),
+                path(&file),
+            ],
+            "",
+        );
+        let stdout = if target == "wasm" {
+            chop_ok(&["run", path(&output)], "")
+        } else {
+            let output = Command::new(&output)
+                .output()
+                .expect("the built program runs");
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        };
+        assert_eq!(stdout, expected, "{}", target);
+    }
+}
+
+#[test]
+fn methods_called_by_name_must_find_their_instance_from_the_first_argument() {
+    let stderr = rejected(
+        "typeclass @describe = {
+    fn size: Self -> int
+}
+
+typeclass @describe: [int] = {
+    fn size = (self) -> 7
+}
+
+typeclass @make = {
+    fn make: int -> Self
+}
+
+typeclass @make: int = {
+    fn make = (n) -> n + 1
+}
+
+proc main = () {
+    println(size([1]))
+    const m: int = make(3)
+    println(m)
+}
+",
+    );
+    for expected in [
+        "-:18:13: error: the instance of `@describe` for `[int]` cannot be found when the program runs",
+        "note: -:5:1: the instance is here",
+        "-:19:20: error: `make` cannot be called by name, as its first parameter is not `Self`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
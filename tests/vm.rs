@@ -0,0 +1,71 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{chop, chop_ok, PROGRAMS};
+
+#[test]
+fn engines_agree() {
+    for path in PROGRAMS {
+        assert_eq!(
+            chop_ok(&["run", path], ""),
+            chop_ok(&["run", "--engine=ast", path], ""),
+            "{} prints something else on the virtual machine",
+            path
+        );
+    }
+}
+
+#[test]
+fn engines_report_the_same_runtime_errors() {
+    let source = "fn half = (0) -> 0\n\nproc main = () {\n    println(half(1))\n}\n";
+    let vm = chop(&["run", "-"], source);
+    let ast = chop(&["run", "--engine=ast", "-"], source);
+    assert!(!vm.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&vm.stderr),
+        String::from_utf8_lossy(&ast.stderr)
+    );
+    assert!(String::from_utf8_lossy(&vm.stderr).contains("no clause of `half` matches"));
+}
+
+#[test]
+fn deep_recursion_is_stopped() {
+    let source = "fn down = (n) -> 1 + down(n + 1)\n\nproc main = () {\n    println(down(0))\n}\n";
+    let output = chop(&["run", "-"], source);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("calls to `down` nest more than"));
+}
+
+#[test]
+fn deep_recursion_is_stopped_in_the_interpreter() {
+    let source = "fn down = (n) -> 1 + down(n + 1)\n\nproc main = () {\n    println(down(0))\n}\n";
+    let output = chop(&["run", "--engine=ast", "-"], source);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("-:1:22: error: calls to `down` nest more than 100000 deep"));
+}
+
+fn timed(args: &[&str]) -> Duration {
+    let start = Instant::now();
+    chop_ok(args, "");
+    start.elapsed()
+}
+
+/// Timing is only meaningful in an optimized build, so this runs with
+/// `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn vm_is_five_times_faster_than_the_interpreter() {
+    for path in ["benches/fibonacci.chop", "benches/loops.chop"] {
+        let vm = timed(&["run", path]);
+        let ast = timed(&["run", "--engine=ast", path]);
+        assert!(
+            vm * 5 <= ast,
+            "{}: {:?} on the virtual machine, {:?} interpreted",
+            path,
+            vm,
+            ast
+        );
+    }
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use common::{build_and_run, chop, chop_ok, path, scratch, PROGRAMS};
+
+#[test]
+fn modules_print_what_the_vm_prints() {
+    let dir = scratch("wasm-programs");
+    let programs = PROGRAMS
+        .iter()
+        .filter(|program| !program.ends_with("runtime.chop"))
+        .chain(["tests/fixtures/wasm.chop"].iter());
+    for (i, program) in programs.enumerate() {
+        let output = build_and_run("wasm", program, &dir, &format!("program{}", i));
+        assert!(
+            output.status.success(),
+            "{} failed as wasm:\n{}",
+            program,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            chop_ok(&["run", program], ""),
+            "{} prints something else as wasm",
+            program
+        );
+    }
+}
+
+#[test]
+fn modules_iterate_over_characters() {
+    let dir = scratch("wasm-utf8");
+    let source = dir.join("chars.chop");
+    let text = "proc main = () {\n    for ch in \"h\u{e9} \u{2713} \u{1d11e}\" {\n        println(ch)\n    }\n    println([\"\u{e9}\", \"a\\tb\"])\n}\n";
+    std::fs::write(&source, text).expect("source");
+    let output = build_and_run("wasm", path(&source), &dir, "chars");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        chop_ok(&["run", path(&source)], "")
+    );
+}
+
+#[test]
+fn modules_report_runtime_errors_like_chop_run() {
+    let dir = scratch("wasm-errors");
+    let cases = [
+        (
+            "divide",
+            "fn divide = (a, b) -> a / b\n\nproc main = () {\n    println(divide(1, 0))\n}\n",
+        ),
+        (
+            "overflow",
+            "fn big = (n) -> n * 9223372036854775807\n\nproc main = () {\n    println(big(2))\n}\n",
+        ),
+        (
+            "down",
+            "fn down = (n) -> 1 + down(n + 1)\n\nproc main = () {\n    println(down(0))\n}\n",
+        ),
+        (
+            "clause",
+            "fn one = (1) -> 1\n\nproc main = () {\n    println(one(2))\n}\n",
+        ),
+    ];
+    for (name, text) in cases {
+        let source = dir.join(format!("{}.chop", name));
+        std::fs::write(&source, text).expect("source");
+        let output = build_and_run("wasm", path(&source), &dir, name);
+        let run = chop(&["run", path(&source)], "");
+        assert_eq!(output.status.code(), Some(1), "{} did not fail", name);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let expected = String::from_utf8_lossy(&run.stderr);
+        // Warnings are printed when the module is built rather than run,
+        // and the last line names the file that was run.
+        let error = expected.find("error:").expect("an error");
+        let error = &expected[expected[..error].rfind('\n').map_or(0, |i| i + 1)..];
+        let (message, _) = error.rsplit_once(path(&source)).expect("the file is named");
+        assert!(stderr.starts_with(message), "{} reports:\n{}", name, stderr);
+        assert!(stderr.ends_with(&format!(
+            "{}.wasm: the program stopped with an error\n",
+            name
+        )));
+    }
+}
+
+#[test]
+fn modules_are_valid_webassembly() {
+    let dir = scratch("wasm-header");
+    let module = dir.join("hello.wasm");
+    chop_ok(
+        &[
+            "build",
+            "--target=wasm",
+            &format!("--output={}", path(&module)),
+            "examples/hello_world.chop",
+        ],
+        "",
+    );
+    let bytes = std::fs::read(&module).expect("module");
+    assert_eq!(&bytes[..8], b"\0asm\x01\0\0\0");
+    let contents = String::from_utf8_lossy(&bytes);
+    for import in ["print", "format_float", "fmod", "fail", "chop.source"] {
+        assert!(
+            contents.contains(import),
+            "the module does not mention {}",
+            import
+        );
+    }
+}
+
+#[test]
+fn invalid_modules_are_rejected() {
+    let dir = scratch("wasm-invalid");
+    let module = dir.join("broken.wasm");
+    chop_ok(
+        &[
+            "build",
+            "--target=wasm",
+            &format!("--output={}", path(&module)),
+            "examples/fibonacci.chop",
+        ],
+        "",
+    );
+    let mut bytes = std::fs::read(&module).expect("module");
+    bytes.truncate(bytes.len() / 2);
+    std::fs::write(&module, &bytes).expect("module");
+    let output = chop(&["run", path(&module)], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("broken.wasm: "));
+
+    std::fs::write(&module, b"\0asm\x02\0\0\0").expect("module");
+    let output = chop(&["run", path(&module)], "");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn unsupported_constructs_are_reported_where_they_are_used() {
+    let output = chop(
+        &[
+            "build",
+            "--target=wasm",
+            "--output=-",
+            "tests/fixtures/runtime.chop",
+        ],
+        "",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for construct in [
+        "closures",
+        "functions as values",
+        "enums",
+        "tuples",
+        "sets",
+        "maps",
+    ] {
+        let message = format!("error: the wasm target does not support {} yet", construct);
+        assert!(
+            stderr.contains(&message),
+            "{} are not reported:\n{}",
+            construct,
+            stderr
+        );
+    }
+    assert!(stderr.contains("could not build due to previous errors"));
+}
+
+#[test]
+fn emit_is_only_for_the_c_target() {
+    let output = chop(
+        &[
+            "build",
+            "--target=wasm",
+            "--emit=c",
+            "examples/hello_world.chop",
+        ],
+        "",
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown emit format 'c'"));
+}
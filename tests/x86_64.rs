@@ -0,0 +1,186 @@
+mod common;
+
+use common::{build_and_run, chop, chop_ok, path, scratch, PROGRAMS};
+
+#[test]
+fn assembled_executables_print_what_the_interpreter_prints() {
+    let dir = scratch("x86-64-programs");
+    let programs = PROGRAMS
+        .iter()
+        .filter(|program| !program.ends_with("runtime.chop"))
+        .chain(["tests/fixtures/wasm.chop"].iter());
+    for (i, program) in programs.enumerate() {
+        let output = build_and_run("x86-64", program, &dir, &format!("program{}", i));
+        assert!(
+            output.status.success(),
+            "{} failed as x86-64:\n{}",
+            program,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            chop_ok(&["run", "--engine=ast", program], ""),
+            "{} prints something else as x86-64",
+            program
+        );
+    }
+}
+
+#[test]
+fn executables_print_floats_and_spilled_values() {
+    let dir = scratch("x86-64-values");
+    let source = dir.join("values.chop");
+    let text = "fn many = (a, b, c, d, e, f, g, h, i) -> a + b + c + d + e + f + g + h + i
+
+fn deep = (a, b) -> a + (b + (a + (b + (a + (b + (a + (b + (a + b))))))))
+
+proc main = () {
+    println(many(1, 2, 3, 4, 5, 6, 7, 8, 9))
+    println(deep(1, 2))
+    println([10000000000000000.0, 9999999999999998.0, 0.0001, 0.00001, 0.000015])
+    println([0.0000001, 100.0, 1.0 / 3.0, 123456789.125, 1000000000000000000000.0])
+    println([0.0 / 0.0, -1.0 / 0.0, -0.0, 1 + 0.5, 12345678901234567000.0])
+    for ch in \"ab\\t\" {
+        println([ch])
+    }
+    println([1] == [1.0])
+    println(0.0 / 0.0 == 0.0 / 0.0)
+}
+";
+    std::fs::write(&source, text).expect("source");
+    let output = build_and_run("x86-64", path(&source), &dir, "values");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        chop_ok(&["run", "--engine=ast", path(&source)], "")
+    );
+}
+
+/// Tail calls passing more arguments on the stack than the caller was
+/// given still take its place, so mutual recursion runs a million deep.
+#[test]
+fn tail_calls_pass_more_stacked_arguments_than_they_were_given() {
+    let dir = scratch("x86-64-tail-calls");
+    let source = dir.join("tail.chop");
+    let text = "fn ping = (0, a, b, c, d, e) -> [a, b, c, d, e]
+fn ping = (n, a, b, c, d, e) -> pong(n - 1, e, d, c, b, a, n % 3, n % 5)
+
+fn pong = (0, a, b, c, d, e, f, g) -> [a, b, c, d, e, f, g]
+fn pong = (n, a, b, c, d, e, f, g) -> ping(n - 1, b + f, a, d, c + g, e)
+
+proc main = () {
+    println(ping(1000000, 1, 2, 3, 4, 5))
+    println(ping(1000001, 1, 2, 3, 4, 5))
+}
+";
+    std::fs::write(&source, text).expect("source");
+    let output = build_and_run("x86-64", path(&source), &dir, "tail");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        chop_ok(&["run", path(&source)], "")
+    );
+}
+
+#[test]
+fn assembled_executables_report_runtime_errors_like_chop_run() {
+    let dir = scratch("x86-64-errors");
+    let cases = [
+        (
+            "divide",
+            "fn divide = (a, b) -> a / b\n\nproc main = () {\n    println(divide(1, 0))\n}\n",
+        ),
+        (
+            "overflow",
+            "fn big = (n) -> n * 9223372036854775807\n\nproc main = () {\n    println(big(2))\n}\n",
+        ),
+        (
+            "down",
+            "fn down = (n) -> 1 + down(n + 1)\n\nproc main = () {\n    println(down(0))\n}\n",
+        ),
+        (
+            "clause",
+            "fn one = (1) -> 1\n\nproc main = () {\n    println(one(2))\n}\n",
+        ),
+    ];
+    for (name, text) in cases {
+        let source = dir.join(format!("{}.chop", name));
+        std::fs::write(&source, text).expect("source");
+        let output = build_and_run("x86-64", path(&source), &dir, name);
+        let run = chop(&["run", path(&source)], "");
+        assert_eq!(output.status.code(), Some(1), "{} did not fail", name);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Warnings are printed when the program is built rather than run.
+        assert!(
+            stderr.starts_with(path(&source)),
+            "{} reports:\n{}",
+            name,
+            stderr
+        );
+        assert!(
+            String::from_utf8_lossy(&run.stderr).ends_with(&*stderr),
+            "{} reports:\n{}",
+            name,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn unsupported_constructs_are_reported_where_they_are_used() {
+    let output = chop(
+        &[
+            "build",
+            "--target=x86-64",
+            "--emit=asm",
+            "tests/fixtures/runtime.chop",
+        ],
+        "",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for construct in [
+        "closures",
+        "functions as values",
+        "enums",
+        "tuples",
+        "sets",
+        "maps",
+    ] {
+        let message = format!(
+            "error: the x86-64 target does not support {} yet",
+            construct
+        );
+        assert!(
+            stderr.contains(&message),
+            "{} are not reported:\n{}",
+            construct,
+            stderr
+        );
+    }
+    assert!(stderr.contains("could not build due to previous errors"));
+}
+
+#[test]
+fn emit_asm_prints_the_assembly() {
+    let assembly = chop_ok(
+        &[
+            "build",
+            "--target=x86-64",
+            "--emit=asm",
+            "examples/fibonacci.chop",
+        ],
+        "",
+    );
+    assert!(assembly.contains(".globl  main"));
+    assert!(assembly.contains("chop_main:"));
+    assert!(assembly.contains("chop_fn_fibonacci_"));
+
+    let output = chop(&["build", "--emit=asm", "examples/fibonacci.chop"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown emit format 'asm'"));
+}